@@ -0,0 +1,10 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.10" tiledversion="1.10.2" name="terrain" tilewidth="16" tileheight="16" tilecount="8" columns="4">
+ <image source="terrain.png" width="64" height="32"/>
+ <tile id="2">
+  <animation>
+   <frame tileid="2" duration="500"/>
+   <frame tileid="3" duration="500"/>
+  </animation>
+ </tile>
+</tileset>
@@ -0,0 +1,54 @@
+use std::fmt::Write;
+
+// Adapter report
+//======================
+// `--list-adapters` prints what wgpu finds on the machine, without opening a window, for bug
+// reports: every adapter with its backend, features, limits and downlevel capabilities, and which
+// of the usual swapchain formats it can render to.
+//
+// Surfaces belong to a window, so without one wgpu 0.12 can't tell which formats and present
+// modes a surface really supports; the formats below are what the adapter itself supports, and
+// `Fifo` is the only present mode every surface has. The log (RUST_LOG=info) shows the format
+// picked for the actual window.
+
+// The formats a surface usually offers.
+const SURFACE_FORMATS: &[wgpu::TextureFormat] = &[
+    wgpu::TextureFormat::Bgra8UnormSrgb,
+    wgpu::TextureFormat::Bgra8Unorm,
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+    wgpu::TextureFormat::Rgba8Unorm,
+    wgpu::TextureFormat::Rgb10a2Unorm,
+    wgpu::TextureFormat::Rgba16Float,
+];
+
+pub fn report(backends: wgpu::Backends) -> String {
+    let instance = wgpu::Instance::new(backends);
+    let mut report = String::new();
+    let adapters: Vec<wgpu::Adapter> = instance.enumerate_adapters(backends).collect();
+    if adapters.is_empty() {
+        let _ = writeln!(report, "No adapters found for backends {:?}", backends);
+        return report;
+    }
+    for (index, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        let _ = writeln!(report, "Adapter {}: {}", index, info.name);
+        let _ = writeln!(report, "  Backend: {:?}", info.backend);
+        let _ = writeln!(report, "  Type: {:?}", info.device_type);
+        let _ = writeln!(report, "  Vendor: {:#06x}, device: {:#06x}", info.vendor, info.device);
+        let _ = writeln!(report, "  Features: {:?}", adapter.features());
+        let _ = writeln!(report, "  Limits: {:#?}", adapter.limits());
+        let _ = writeln!(report, "  Downlevel: {:?}", adapter.get_downlevel_properties());
+        let _ = writeln!(report, "  Swapchain formats:");
+        for &format in SURFACE_FORMATS {
+            let features = adapter.get_texture_format_features(format);
+            let renderable = features.allowed_usages.contains(wgpu::TextureUsages::RENDER_ATTACHMENT);
+            let _ = writeln!(report, "    {:?}: {}", format, if renderable { "yes" } else { "no" });
+        }
+        let _ = writeln!(report, "  Present modes: Fifo (others depend on the window)");
+    }
+    let _ = writeln!(
+        report,
+        "\nNote: --adapter counts only the adapters that can present to the window, in this order."
+    );
+    report
+}
@@ -1,68 +1,774 @@
-use raw_window_handle::HasRawWindowHandle;
-use raw_window_handle::RawWindowHandle::Win32;
-use windows::Win32::UI::WindowsAndMessaging::{
-    DispatchMessageW, GetMessageW, PeekMessageW, TranslateMessage, MSG, PM_REMOVE, WM_QUIT, PostQuitMessage,
-};
-pub type Result<T> = core::result::Result<T, Win32Error>;
-use crate::{error::Win32Error, window::Window};
-
-pub struct App {
-    pub window: Window,
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "settings_ui")]
+use windows::Win32::UI::Input::KeyboardAndMouse::VK_F2;
+#[cfg(feature = "renderdoc")]
+use windows::Win32::UI::Input::KeyboardAndMouse::VK_F11;
+use windows::Win32::UI::Input::KeyboardAndMouse::{VK_F3, VK_F4, VK_F6};
+
+use crate::crash;
+use crate::error::{Context, Error, ErrorCategory, Result};
+use crate::game::{Event, Frame, Game, Input};
+use crate::gfx::GfxOptions;
+use crate::keyboard::KeyboardState;
+use crate::latency::LatencyTracker;
+use crate::limiter::FrameLimiter;
+use crate::logging;
+use crate::metrics::{FrameMetrics, MetricsSink};
+use crate::mouse::MouseDelta;
+use crate::platform::{NativeWindow, Platform};
+use crate::power::PowerMonitor;
+use crate::replay::{Player, Recorder, Replay, ReplayFrame, ReplayHeader};
+use crate::rng::Rng;
+#[cfg(feature = "settings_ui")]
+use crate::settings_ui::SettingsPanel;
+use crate::stats::FrameStats;
+use crate::time::Time;
+use crate::scene::{Scene, SceneInstance};
+use crate::window::WindowBuilder;
+#[cfg(feature = "winit")]
+use crate::winit_window::WinitWindow;
+
+// Game loop
+//======================
+// `update` runs at a fixed rate, which keeps physics and gameplay deterministic
+// and independent of the frame rate. Rendering runs as often as the loop comes around:
+// real time accumulates, and whole ticks are consumed from it before every frame.
+// The leftover fraction of a tick is passed to `render` as `Frame::alpha`, to interpolate
+// between the previous and the current state. After a long stall (a breakpoint, dragging the window)
+// the accumulated time is clamped, so the game slows down instead of trying to catch up forever.
+
+/// Longest stretch of real time simulated in one frame, in seconds.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// Number of recent log lines shown under the stats overlay.
+const LOG_LINES: usize = 8;
+
+// Throttling
+//======================
+// Nobody sees the frames of a minimized window, so the loop blocks waiting for messages until it is restored.
+// Other windows render at a reduced frame rate, sleeping in between while still handling messages right away:
+//  - `background_fps` while the window doesn't have the focus; it stays visible, but usually isn't watched closely,
+//  - `occluded_fps` while it can't be seen at all, covered by other windows or on another virtual desktop
+//    (`Platform::is_occluded`),
+//  - `battery_fps` while the machine runs on battery (power.rs).
+// The lowest of those that apply wins. The game hears of the changes through
+// `Event::OcclusionChanged` and `Event::PowerChanged`, e.g. to pause a simulation nobody watches.
+// They are recorded into replays like input, and replays play them back instead of checking the machine
+// they run on.
+
+// Redrawing
+//======================
+// Games render continuously, as often as the loop comes around. Editors and tools that show the
+// same picture until the user does something can set `App::redraw_mode` to `OnDemand` instead:
+// the loop then blocks waiting for messages (MsgWaitForMultipleObjects on Win32) and only runs a
+// frame when
+//  - the OS asks for the window to be repainted, e.g. after it was uncovered, or it was resized,
+//  - input arrived,
+//  - `App::request_redraw` was called, or `Game::needs_redraw` returns true.
+// Every frame still runs the `update` ticks that fit in the time since the last one, up to
+// `MAX_FRAME_TIME`, so game time doesn't jump ahead by how long the app was idle.
+
+// Errors
+//======================
+// A frame that fails doesn't have to end the app. What happens depends on the error's category
+// (error.rs):
+//  - transient errors skip the frame and the loop goes on, until `MAX_FAILED_FRAMES` fail in a row,
+//  - when the GPU was removed or reset, the graphics state is created again on a new device and
+//    `Game::init` runs again, as everything the game created on the old one is gone. This is tried
+//    `MAX_DEVICE_RECREATIONS` times per run,
+//  - anything else shuts down and `run` returns the error.
+
+/// Consecutive frames that may fail with transient errors before giving up.
+const MAX_FAILED_FRAMES: u32 = 30;
+
+/// Times the graphics device may be lost and recreated in one run.
+const MAX_DEVICE_RECREATIONS: u32 = 3;
+
+/// When the loop renders frames, see above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedrawMode {
+    Continuous,
+    OnDemand,
+}
+
+// Runs a `Game` in a window of platform `P`, see platform.rs.
+pub struct App<P: Platform = NativeWindow> {
+    pub window: P,
+    gfx_options: GfxOptions,
+    /// Seconds per `update` tick.
+    pub fixed_dt: f32,
+    time: Time,
+    stats: FrameStats,
+    show_stats: bool,
+    /// Virtual-key code that toggles the stats overlay.
+    pub stats_key: u16,
+    /// Virtual-key code that switches between the window and borderless fullscreen.
+    pub fullscreen_key: u16,
+    windowed_size: (i32, i32), // To return to from fullscreen.
+    /// Frame rate while the window does not have the focus, `None` to keep rendering at full rate.
+    pub background_fps: Option<f32>,
+    /// Frame rate while the window is covered, see "Throttling" above.
+    pub occluded_fps: Option<f32>,
+    /// Frame rate while running on battery.
+    pub battery_fps: Option<f32>,
+    power: PowerMonitor,
+    occluded: bool, // As last reported to the game.
+    pub redraw_mode: RedrawMode,
+    redraw_requested: bool, // Renders a frame with `RedrawMode::OnDemand`.
+    /// Frame rate cap, independent of vsync. Games can change it through `Frame::limiter`.
+    pub limiter: FrameLimiter,
+    exit_requested: bool,
+    failed_frames: u32, // In a row, see "Errors" above.
+    device_recreations: u32,
+    /// Exit after this many frames, for benchmarks.
+    pub max_frames: Option<u64>,
+    /// Seeded from the clock, unless a replay sets the recorded seed.
+    pub rng: Rng,
+    /// Record the session into this file, see replay.rs.
+    pub record_path: Option<PathBuf>,
+    replay: Replay,
+    // The keys held down at the last `update`, for `Input::previous_keys`.
+    previous_keys: KeyboardState,
+    // Mouse movement no `update` got yet, from frames too short for a tick.
+    mouse_delta: MouseDelta,
+    /// Per-frame metrics for performance dashboards, see metrics.rs.
+    pub metrics: Option<MetricsSink>,
+    /// Virtual-key code that writes the metrics recorded so far.
+    pub metrics_key: u16,
+    /// Input to GPU latency of every frame, logged on exit, see latency.rs.
+    pub latency: Option<LatencyTracker>,
+    #[cfg(feature = "settings_ui")]
+    pub settings: SettingsPanel,
+    /// Virtual-key code that opens and closes the settings panel, see settings_ui.rs.
+    #[cfg(feature = "settings_ui")]
+    pub settings_key: u16,
+    /// Virtual-key code that captures the frame with RenderDoc, see renderdoc.rs.
+    #[cfg(feature = "renderdoc")]
+    pub capture_key: u16,
+    /// Loaded into the GFX before `Game::init`, see `AppBuilder::scene`.
+    pub scene_path: Option<PathBuf>,
+    scene: Option<SceneInstance>,
 }
 
 impl App {
-    pub fn new() -> App {
+    // Configures an app before creating it, see `AppBuilder`.
+    pub fn builder() -> AppBuilder {
+        AppBuilder::new()
+    }
+}
+
+// App builder
+//======================
+// Everything an app is set up with before `run`, in one chain:
+//
+//     let mut app = App::builder()
+//         .title("Demo")
+//         .size(1280, 720)
+//         .tick_rate(120.0)
+//         .log_level(tracing::Level::DEBUG)
+//         .scene("scene.ron")
+//         .build();
+//     app.run(&mut game)?;
+//
+// `build` creates the native window of the platform, the Win32 one or the X11 one on Linux, and
+// `build_winit` the same app on winit, see platform.rs.
+
+pub struct AppBuilder {
+    window: WindowBuilder,
+    gfx_options: GfxOptions,
+    fixed_dt: f32,
+    log_level: Option<tracing::Level>, // `None` leaves logging to the caller, see logging.rs.
+    log_file: Option<PathBuf>,
+    scene: Option<PathBuf>,
+}
+
+impl AppBuilder {
+    pub fn new() -> AppBuilder {
+        AppBuilder {
+            window: WindowBuilder::new(),
+            gfx_options: GfxOptions::default(),
+            fixed_dt: 1.0 / 60.0,
+            log_level: None,
+            log_file: None,
+            scene: None,
+        }
+    }
+
+    // Replaces the window settings chosen so far, e.g. with `Config::window_builder`.
+    pub fn window(mut self, window: WindowBuilder) -> AppBuilder {
+        self.window = window;
+        self
+    }
+
+    // Size of the client area, ignored in fullscreen.
+    pub fn size(mut self, width: i32, height: i32) -> AppBuilder {
+        self.window = self.window.size(width, height);
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> AppBuilder {
+        self.window = self.window.title(title);
+        self
+    }
+
+    pub fn fullscreen(mut self, fullscreen: bool) -> AppBuilder {
+        self.window = self.window.fullscreen(fullscreen);
+        self
+    }
+
+    pub fn gfx_options(mut self, gfx_options: GfxOptions) -> AppBuilder {
+        self.gfx_options = gfx_options;
+        self
+    }
+
+    // `update` ticks per second, see `App::fixed_dt`.
+    pub fn tick_rate(mut self, ticks_per_second: f32) -> AppBuilder {
+        self.fixed_dt = 1.0 / ticks_per_second.max(1.0);
+        self
+    }
+
+    // Installs logging when the app is built, see logging.rs. Apps that log before that install
+    // it themselves with `logging::init` instead.
+    pub fn log_level(mut self, level: tracing::Level) -> AppBuilder {
+        self.log_level = Some(level);
+        self
+    }
+
+    // Also writes the log into `path`, with `log_level`.
+    pub fn log_file(mut self, path: impl Into<PathBuf>) -> AppBuilder {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    // A scene file loaded into the GFX before `Game::init`, see scene.rs and `App::scene`.
+    pub fn scene(mut self, path: impl Into<PathBuf>) -> AppBuilder {
+        self.scene = Some(path.into());
+        self
+    }
+
+    pub fn build(self) -> App {
+        let window = self.window.clone().build();
+        self.build_with(window)
+    }
+
+    #[cfg(feature = "winit")]
+    pub fn build_winit(self) -> App<WinitWindow> {
+        let window = self.window.clone().build_winit();
+        self.build_with(window)
+    }
+
+    // The app on a window created elsewhere; the window settings of the builder are not used.
+    pub fn build_with<P: Platform>(self, window: P) -> App<P> {
+        if let Some(level) = self.log_level {
+            logging::init_with_level(self.log_file.as_deref(), level);
+        }
+        let mut app = App::with_options(window, self.gfx_options);
+        app.fixed_dt = self.fixed_dt;
+        app.scene_path = self.scene;
+        app
+    }
+}
+
+impl Default for AppBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Platform> App<P> {
+    pub fn with_options(window: P, gfx_options: GfxOptions) -> App<P> {
+        let windowed_size = window.size();
         App {
-            window: Window::new(800, 600, "-"),
+            window,
+            gfx_options,
+            fixed_dt: 1.0 / 60.0,
+            time: Time::new(),
+            stats: FrameStats::new(),
+            show_stats: false,
+            stats_key: VK_F3,
+            fullscreen_key: VK_F4,
+            windowed_size,
+            background_fps: Some(10.0),
+            occluded_fps: Some(5.0),
+            battery_fps: Some(30.0),
+            power: PowerMonitor::new(),
+            occluded: false,
+            redraw_mode: RedrawMode::Continuous,
+            redraw_requested: true,
+            limiter: FrameLimiter::new(None),
+            exit_requested: false,
+            failed_frames: 0,
+            device_recreations: 0,
+            max_frames: None,
+            rng: Rng::from_time(),
+            record_path: None,
+            replay: Replay::Off,
+            previous_keys: KeyboardState::default(),
+            mouse_delta: MouseDelta::default(),
+            metrics: None,
+            metrics_key: VK_F6,
+            latency: None,
+            #[cfg(feature = "settings_ui")]
+            settings: SettingsPanel::new(),
+            #[cfg(feature = "settings_ui")]
+            settings_key: VK_F2,
+            #[cfg(feature = "renderdoc")]
+            capture_key: VK_F11,
+            scene_path: None,
+            scene: None,
+        }
+    }
+
+    // Switches to borderless fullscreen, or back to the window's size before it.
+    fn toggle_fullscreen(&mut self) {
+        if self.window.is_fullscreen() {
+            let (width, height) = self.windowed_size;
+            self.window.set_window_mode(width, height, false);
+        } else {
+            self.windowed_size = self.window.size();
+            let (width, height) = self.windowed_size;
+            self.window.set_window_mode(width, height, true);
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        self.window.initialize()?;
-        let win_handle = self.window.raw_window_handle();
-        match win_handle {
-            Win32(Win32Handle) => println!("Window handle: {:?} - Instance: {:?}", Win32Handle.hwnd, Win32Handle.hinstance),
-            _ => {}
+    // Plays a recording instead of taking input from the user. `run` ends with the recording.
+    pub fn replay(&mut self, player: Player) {
+        let header = player.header();
+        let (width, height) = self.window.size();
+        if (header.width, header.height) != (width, height) {
+            tracing::warn!(
+                "Replaying a {}x{} recording in a {}x{} window, mouse input may differ",
+                header.width,
+                header.height,
+                width,
+                height
+            );
         }
+        self.rng = Rng::new(header.seed);
+        self.fixed_dt = header.fixed_dt;
+        self.window.set_accept_os_input(false);
+        self.replay = Replay::Playing(player);
+    }
+
+    // Ends `run` after the current frame. Games request this through `Game::should_exit`.
+    pub fn exit(&mut self) {
+        self.exit_requested = true;
+    }
+
+    // What the scene of `scene_path` added to the GFX, once `run` loaded it.
+    pub fn scene(&self) -> Option<&SceneInstance> {
+        self.scene.as_ref()
+    }
+
+    // Renders one more frame with `RedrawMode::OnDemand`, e.g. after changing the scene from
+    // outside the game.
+    pub fn request_redraw(&mut self) {
+        self.redraw_requested = true;
+    }
+
+    // Whether the next frame has to be rendered, see "Redrawing" above.
+    fn needs_redraw(&mut self, game: &impl Game) -> bool {
+        // Replays feed every recorded frame, with or without input.
+        if self.redraw_mode == RedrawMode::Continuous || matches!(self.replay, Replay::Playing(_)) {
+            return true;
+        }
+        let invalidated = self.window.take_invalidated();
+        std::mem::take(&mut self.redraw_requested) || invalidated || game.needs_redraw()
+    }
+
+    // Raises the events for changes of the occlusion and power source, see "Throttling" above.
+    fn check_throttling(&mut self) {
+        if matches!(self.replay, Replay::Playing(_)) {
+            return;
+        }
+        let occluded = self.window.is_occluded();
+        if occluded != self.occluded {
+            self.occluded = occluded;
+            tracing::debug!("Window {}", if occluded { "occluded" } else { "uncovered" });
+            self.window.inject_event(Event::OcclusionChanged { occluded });
+        }
+        if let Some(on_battery) = self.power.poll() {
+            self.window.inject_event(Event::PowerChanged { on_battery });
+        }
+    }
+
+    // The reduced frame rate that applies now, if any, see "Throttling" above.
+    fn throttled_fps(&self) -> Option<f32> {
+        [
+            self.background_fps.filter(|_| !self.window.is_focused()),
+            self.occluded_fps.filter(|_| self.occluded),
+            self.battery_fps.filter(|_| self.power.on_battery()),
+        ]
+        .into_iter()
+        .flatten()
+        .reduce(f32::min)
+    }
+
+    pub fn run(&mut self, game: &mut impl Game) -> Result<()> {
+        self.window.initialize(&self.gfx_options).context("cannot create the window")?;
+        tracing::debug!("Window handle: {:?}", self.window.raw_window_handle());
 
-        let mut message = MSG::default();
-        loop {
-            unsafe {
-                // Initially the window is not visible
-                if self.window.visible {
-                    while PeekMessageW(&mut message, None, 0, 0, PM_REMOVE).into() {
-                        if message.message == WM_QUIT {
-                            return Ok(());
+        if let Some(path) = &self.record_path {
+            let (width, height) = self.window.size();
+            let header = ReplayHeader::new(self.rng.seed(), self.fixed_dt, width, height);
+            match Recorder::create(path, &header) {
+                Ok(recorder) => self.replay = Replay::Recording(recorder),
+                Err(e) => tracing::error!("Failed to record to {}: {}", path.display(), e),
+            }
+        }
+
+        self.init_game(game);
+        self.time = Time::new();
+        let mut accumulator = 0.0;
+
+        while !self.exit_requested {
+            // Initially the window is not visible
+            if self.window.is_visible() {
+                let frame_start = Instant::now();
+                if !self.window.pump_messages() {
+                    break;
+                }
+                self.check_throttling();
+                if !self.needs_redraw(game) {
+                    // Nothing to show, sleep until the next message and check again.
+                    if !self.window.wait_messages(None) {
+                        break;
+                    }
+                    continue;
+                }
+
+                let replayed = match &mut self.replay {
+                    Replay::Playing(player) => match player.next_frame() {
+                        Some(frame) => Some(frame),
+                        None => {
+                            tracing::info!("Replay finished after {} frames", self.time.frame());
+                            break;
+                        }
+                    },
+                    _ => None,
+                };
+                accumulator += match &replayed {
+                    Some(frame) => self.time.begin_frame_with(frame.dt),
+                    None => self.time.begin_frame(MAX_FRAME_TIME),
+                };
+                let span = tracing::info_span!("frame", number = self.time.frame()).entered();
+                let frame = self.time.frame();
+                let result = self.frame(game, &mut accumulator, replayed);
+                drop(span);
+                match result.with_context(|| format!("frame {} failed", frame)) {
+                    Ok(()) => self.failed_frames = 0,
+                    Err(e) => {
+                        if let Err(e) = self.recover(game, e) {
+                            // Release the GPU state in order, the error is reported by the caller.
+                            self.shutdown();
+                            return Err(e);
                         }
-                        TranslateMessage(&message);
-                        DispatchMessageW(&message);
                     }
-                    //self.render()?;
-                } else {
-                    GetMessageW(&mut message, None, 0, 0);
+                }
+                let frames_done = self.max_frames.is_some_and(|max| self.time.frame() >= max);
+                if game.should_exit() || frames_done {
+                    self.exit();
+                }
+                self.limiter.wait();
 
-                    if message.message == WM_QUIT {
-                        return Ok(());
+                if let Some(fps) = self.throttled_fps() {
+                    let deadline = frame_start + Duration::from_secs_f32(1.0 / fps.max(0.1));
+                    if !self.window.wait_messages(Some(deadline)) {
+                        break;
                     }
-                    TranslateMessage(&message);
-                    DispatchMessageW(&message);
                 }
+            } else if !self.window.wait_messages(None) {
+                break;
+            }
+        }
+
+        self.shutdown();
+        Ok(())
+    }
+
+    // Loads the scene and lets the game create its resources, on the GFX as it is now.
+    fn init_game(&mut self, game: &mut impl Game) {
+        if let Some(path) = &self.scene_path {
+            match Scene::load(path).and_then(|scene| scene.instantiate(self.window.gfx_mut().unwrap())) {
+                Ok(scene) => self.scene = Some(scene),
+                Err(e) => tracing::error!("Failed to load scene {}: {}", path.display(), e),
+            }
+        }
+        game.init(self.window.gfx_mut().unwrap());
+    }
+
+    // Gets the loop going again after a failed frame, or returns the error if it can't, see "Errors" above.
+    fn recover(&mut self, game: &mut impl Game, error: Error) -> Result<()> {
+        match error.category() {
+            _ if error.is_retryable() && self.failed_frames < MAX_FAILED_FRAMES => {
+                self.failed_frames += 1;
+                tracing::warn!("{}, trying the next frame", error);
+                Ok(())
+            }
+            ErrorCategory::DeviceRemoved if self.device_recreations < MAX_DEVICE_RECREATIONS => {
+                self.device_recreations += 1;
+                tracing::error!("{}, recreating the graphics device", error);
+                // A shared context is on the device that was lost.
+                let options = GfxOptions {
+                    shared_context: None,
+                    ..self.gfx_options.clone()
+                };
+                self.window.recreate_gfx(&options).context("cannot recover from losing the device")?;
+                if let Some(latency) = &mut self.latency {
+                    latency.drop_in_flight();
+                }
+                self.scene = None;
+                self.init_game(game);
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+
+    // Lets the GPU finish the submitted work before its resources go away, then releases the
+    // graphics state while the window it renders to still exists. The window itself is destroyed on drop.
+    fn shutdown(&mut self) {
+        if let Some(gfx) = self.window.gfx_mut() {
+            gfx.wait_idle();
+            if let Some(latency) = &mut self.latency {
+                latency.poll(gfx.context().device());
+                tracing::info!("{}", latency.report(gfx.present_mode()));
+            }
+        }
+        self.window.release_gfx();
+        self.flush_metrics();
+    }
+
+    // Writes the metrics recorded so far, if any are being recorded.
+    pub fn flush_metrics(&self) {
+        if let Some(metrics) = &self.metrics {
+            match metrics.flush() {
+                Ok(()) => tracing::info!("Wrote {} frames of metrics to {}", metrics.samples().len(), metrics.path().display()),
+                Err(e) => tracing::error!("Failed to write metrics to {}: {}", metrics.path().display(), e),
             }
         }
     }
 
-    // fn render(&mut self) -> Result<()> {
-    //     println!("APP_RENDER");
-    //     let gfx = self.window.gfx.as_mut().unwrap();
-    //     match gfx.render() {
-    //         Ok(_) => {}
-    //         // Reconfigure the surface if lost
-    //         Err(wgpu::SurfaceError::Lost) => gfx.resize(self.window.width as u32, self.window.height as u32),
-    //         // The system is out of memory, we should probably quit
-    //         Err(wgpu::SurfaceError::OutOfMemory) => unsafe {PostQuitMessage(0)},
-    //         // All other errors (Outdated, Timeout) should be resolved by the next frame
-    //         Err(e) => eprintln!("{:?}", e),
-    //     }
-    //     Ok(())
-    // }
+    // Hands the queued window events to the game, runs the ticks that fit in `accumulator`
+    // and renders one frame. A `replayed` frame brings its own events.
+    fn frame(&mut self, game: &mut impl Game, accumulator: &mut f32, replayed: Option<ReplayFrame>) -> Result<()> {
+        let cpu_start = Instant::now();
+        self.window.gfx_mut().unwrap().update_assets();
+        if let Some(frame) = replayed {
+            for event in frame.events {
+                self.window.inject_event(event);
+            }
+        }
+        let mut events = Vec::new();
+        while let Some(event) = self.window.next_event() {
+            if event == Event::KeyPressed(self.stats_key) {
+                self.show_stats = !self.show_stats;
+            }
+            if event == Event::KeyPressed(self.metrics_key) {
+                self.flush_metrics();
+            }
+            if event == Event::KeyPressed(self.fullscreen_key) {
+                self.toggle_fullscreen();
+            }
+            #[cfg(feature = "renderdoc")]
+            if event == Event::KeyPressed(self.capture_key) {
+                self.window.gfx_mut().unwrap().trigger_capture();
+            }
+            #[cfg(feature = "settings_ui")]
+            {
+                if event == Event::KeyPressed(self.settings_key) {
+                    self.settings.toggle();
+                }
+                self.settings.on_event(&event);
+            }
+            game.on_event(&event);
+            events.push(event);
+        }
+        if let Replay::Recording(recorder) = &mut self.replay {
+            recorder.record(&ReplayFrame {
+                dt: self.time.real_delta(),
+                events,
+            });
+        }
+        let input_time = self.window.take_input_time();
+        if let Some(latency) = &mut self.latency {
+            latency.poll(self.window.gfx_mut().unwrap().context().device());
+            latency.begin_frame(input_time);
+        }
+        if let Some((width, height)) = self.window.take_resize() {
+            self.window.gfx_mut().unwrap().resize(width, height);
+            game.on_resize(width, height);
+        }
+
+        self.mouse_delta += self.window.take_mouse_delta();
+        let input = self.window.input(&self.rng);
+        run_updates(
+            game,
+            &mut self.time,
+            input,
+            accumulator,
+            self.fixed_dt,
+            &mut self.previous_keys,
+            &mut self.mouse_delta,
+        );
+        let alpha = *accumulator / self.fixed_dt;
+
+        #[cfg(feature = "settings_ui")]
+        self.settings.run(&mut self.window);
+
+        let _span = tracing::info_span!("render").entered();
+        let (width, height) = self.window.size();
+        let (width, height) = (width as u32, height as u32);
+        let gfx = self.window.gfx_mut().unwrap();
+        gfx.update_globals(&self.time);
+        game.render(&mut Frame {
+            gfx,
+            time: &self.time,
+            stats: &self.stats,
+            limiter: &mut self.limiter,
+            alpha,
+        });
+        if self.show_stats {
+            gfx.draw_text(8.0, 8.0, 2.0, [1.0, 1.0, 0.0, 1.0], &self.stats.summary());
+            let log = logging::recent(LOG_LINES).join("\n");
+            gfx.draw_text(8.0, height as f32 - 8.0 - 12.0 * LOG_LINES as f32, 2.0, [0.8, 0.8, 0.8, 1.0], &log);
+        }
+        match gfx.render() {
+            Ok(_) => {
+                if let Some(latency) = &mut self.latency {
+                    latency.end_frame(gfx.context().queue());
+                }
+            }
+            // Reconfigure the surface if lost
+            Err(wgpu::SurfaceError::Lost) => gfx.resize(width, height),
+            // The loop decides what to do about the others: Outdated and Timeout should be
+            // resolved by the next frame, OutOfMemory ends the app, see "Errors" above.
+            Err(e) => return Err(e.into()),
+        }
+        // A validation error means a bug in the rendering code, and the frames after it are likely
+        // wrong. A lost device is created again by the loop, see "Errors" above.
+        if let Some(e) = gfx.take_device_error() {
+            return Err(e);
+        }
+        self.stats.record(self.time.real_delta(), gfx.render_counters());
+        crash::record_frame(self.time.frame(), self.time.real_delta(), gfx.render_counters());
+        if let Some(metrics) = &mut self.metrics {
+            let cpu_ms = cpu_start.elapsed().as_secs_f32() * 1000.0;
+            let mut sample = FrameMetrics::new(self.time.frame(), cpu_ms, gfx.render_counters());
+            sample.gpu_ms = gfx.gpu_pass_times().iter().copied().collect();
+            (sample.textures, sample.meshes, sample.shaders) = gfx.assets().counts();
+            metrics.record(sample);
+        }
+        Ok(())
+    }
+}
+
+// Runs the updates that fit in `accumulator`, one tick of `fixed_dt` each, see "Game loop" above.
+// `input` holds the state after this frame's events. The first update takes the pending
+// `mouse_delta`; without an update it stays pending, and the next frame adds to it.
+pub(crate) fn run_updates(
+    game: &mut impl Game,
+    time: &mut Time,
+    mut input: Input,
+    accumulator: &mut f32,
+    fixed_dt: f32,
+    previous_keys: &mut KeyboardState,
+    mouse_delta: &mut MouseDelta,
+) {
+    while *accumulator >= fixed_dt {
+        let _span = tracing::info_span!("update").entered();
+        time.tick(fixed_dt);
+        input.previous_keys = *previous_keys;
+        *previous_keys = input.keys;
+        input.mouse_delta = std::mem::take(mouse_delta);
+        game.update(time, &input);
+        *accumulator -= fixed_dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::Keyboard;
+    use crate::mouse::Mouse;
+
+    const FIXED_DT: f32 = 1.0 / 60.0;
+
+    // Keeps the mouse movement of every update.
+    #[derive(Default)]
+    struct Looker {
+        deltas: Vec<MouseDelta>,
+    }
+
+    impl Game for Looker {
+        fn update(&mut self, _time: &mut Time, input: &Input) {
+            self.deltas.push(input.mouse_delta);
+        }
+    }
+
+    #[test]
+    fn the_builder_sets_up_the_app() {
+        let log = std::env::temp_dir().join(format!("app-builder-test-{}.log", std::process::id()));
+        let app = App::builder()
+            .title("Built")
+            .size(640, 360)
+            .fullscreen(true)
+            .tick_rate(120.0)
+            .log_level(tracing::Level::DEBUG)
+            .log_file(&log)
+            .scene("scene.ron")
+            .build();
+        assert_eq!(app.window.size(), (640, 360));
+        assert!(app.window.is_fullscreen());
+        assert_eq!(app.fixed_dt, 1.0 / 120.0);
+        assert_eq!(app.scene_path, Some(PathBuf::from("scene.ron")));
+        assert!(app.scene().is_none());
+
+        tracing::debug!("written at the builder's level");
+        let written = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_file(&log).unwrap();
+        assert!(written.contains("written at the builder's level"));
+    }
+
+    #[test]
+    fn a_frame_without_updates_passes_its_movement_on() {
+        let (keyboard, mut mouse, rng) = (Keyboard::new(), Mouse::new(), Rng::new(1));
+        let (mut game, mut time) = (Looker::default(), Time::new());
+        let (mut accumulator, mut previous_keys, mut pending) = (0.0, KeyboardState::default(), MouseDelta::default());
+        let mut frame = |mouse: &mut Mouse, game: &mut Looker| {
+            accumulator += 0.6 * FIXED_DT;
+            pending += mouse.take_frame_delta();
+            let input = Input {
+                keyboard: &keyboard,
+                keys: keyboard.state(),
+                previous_keys: KeyboardState::default(),
+                mouse,
+                mouse_delta: MouseDelta::default(),
+                rng: &rng,
+            };
+            run_updates(game, &mut time, input, &mut accumulator, FIXED_DT, &mut previous_keys, &mut pending);
+        };
+
+        // Too short for a tick.
+        mouse.on_raw_move(3, -2);
+        mouse.on_scroll_lines(0.0, 1.0);
+        frame(&mut mouse, &mut game);
+        assert!(game.deltas.is_empty());
+
+        // One tick, which gets the movement of both frames.
+        mouse.on_raw_move(4, 1);
+        mouse.on_scroll_lines(0.0, 0.5);
+        frame(&mut mouse, &mut game);
+        assert_eq!(game.deltas.len(), 1);
+        assert_eq!((game.deltas[0].raw_x, game.deltas[0].raw_y), (7, -1));
+        assert_eq!(game.deltas[0].scroll_lines, (0.0, 1.5));
+
+        // Used up: the next tick gets none.
+        frame(&mut mouse, &mut game);
+        frame(&mut mouse, &mut game);
+        assert_eq!(game.deltas.len(), 2);
+        assert_eq!(game.deltas[1], MouseDelta::default());
+    }
 }
@@ -0,0 +1,550 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::device_cache::DeviceCache;
+use crate::loader::{Job, Loaded, Loader};
+use crate::material::ShaderId;
+use crate::mesh::Mesh;
+use crate::reflection::ReflectError;
+use crate::texture::Texture;
+use crate::tiled::TiledError;
+use crate::Vertex;
+
+// Assets
+//======================
+// `Assets` owns the textures, meshes and shaders of the scene. Everything else refers to them through
+// typed handles, which count references: cloning a handle adds one, dropping it removes one.
+// Loading the same file twice returns the same asset. At the end of every frame `collect_garbage`
+// unloads the assets that no handle refers to anymore.
+//
+// Textures are handed out as `Rc<Texture>` for materials to bind, so the GPU memory of an unloaded
+// texture is only freed once the last material lets go of it. Shaders stay compiled in `Materials`,
+// which refers to them by `ShaderId`; unloading a shader only forgets its file.
+//
+// The `_async` loads return a handle right away, to a placeholder (a checker texture, a unit cube)
+// that is swapped for the real asset once a worker thread has decoded it, see loader.rs.
+// A file that fails to load keeps the placeholder, which makes it easy to spot.
+
+/// A counted reference to an asset of type `T` in `Assets`.
+pub struct Handle<T> {
+    index: usize,
+    refs: Rc<()>,
+    _asset: PhantomData<fn() -> T>,
+}
+
+pub type TextureHandle = Handle<Texture>;
+pub type MeshHandle = Handle<Mesh>;
+pub type ShaderHandle = Handle<ShaderId>;
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle {
+            index: self.index,
+            refs: self.refs.clone(),
+            _asset: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.index)
+    }
+}
+
+struct Slot<T> {
+    asset: Rc<T>,
+    path: Option<PathBuf>,
+    loaded: bool, // False while the asset is a placeholder.
+    // Alive as long as a handle is.
+    refs: Weak<()>,
+}
+
+// The assets of one type. Freed slots are reused.
+struct Storage<T> {
+    slots: Vec<Option<Slot<T>>>,
+    free: Vec<usize>,
+    by_path: HashMap<u64, usize>,
+}
+
+impl<T> Storage<T> {
+    fn new() -> Storage<T> {
+        Storage {
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_path: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, asset: T, path: Option<&Path>) -> Handle<T> {
+        self.insert_shared(Rc::new(asset), path, true)
+    }
+
+    fn insert_shared(&mut self, asset: Rc<T>, path: Option<&Path>, loaded: bool) -> Handle<T> {
+        let refs = Rc::new(());
+        let slot = Slot {
+            asset,
+            path: path.map(Path::to_path_buf),
+            loaded,
+            refs: Rc::downgrade(&refs),
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = Some(slot);
+                index
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        if let Some(path) = path {
+            self.by_path.insert(path_key(path), index);
+        }
+        Handle {
+            index,
+            refs,
+            _asset: PhantomData,
+        }
+    }
+
+    // A new handle to the asset loaded from `path`, if it is still loaded.
+    fn find(&self, path: &Path) -> Option<Handle<T>> {
+        let index = *self.by_path.get(&path_key(path))?;
+        let refs = self.slots[index].as_ref()?.refs.upgrade()?;
+        Some(Handle {
+            index,
+            refs,
+            _asset: PhantomData,
+        })
+    }
+
+    fn get(&self, handle: &Handle<T>) -> &Rc<T> {
+        // A live handle keeps its slot from being collected.
+        &self.slots[handle.index].as_ref().unwrap().asset
+    }
+
+    // Swaps in a new version of the asset loaded from `path`. Returns the old version.
+    fn replace(&mut self, path: &Path, asset: T) -> Option<Rc<T>> {
+        let index = *self.by_path.get(&path_key(path))?;
+        let slot = self.slots[index].as_mut()?;
+        slot.loaded = true;
+        Some(std::mem::replace(&mut slot.asset, Rc::new(asset)))
+    }
+
+    // Swaps in a new version of the asset behind `handle`.
+    fn set(&mut self, handle: &Handle<T>, asset: T) {
+        self.slots[handle.index].as_mut().unwrap().asset = Rc::new(asset);
+    }
+
+    fn is_loaded(&self, handle: &Handle<T>) -> bool {
+        self.slots[handle.index].as_ref().unwrap().loaded
+    }
+
+    // The file the asset was loaded from, if any.
+    fn path(&self, handle: &Handle<T>) -> Option<&Path> {
+        self.slots[handle.index].as_ref().unwrap().path.as_deref()
+    }
+
+    fn contains_path(&self, path: &Path) -> bool {
+        self.by_path.contains_key(&path_key(path))
+    }
+
+    // Frees the slots without handles, returning the paths they were loaded from.
+    fn collect_garbage(&mut self) -> Vec<PathBuf> {
+        let mut unloaded = Vec::new();
+        for (index, entry) in self.slots.iter_mut().enumerate() {
+            if entry.as_ref().is_some_and(|slot| slot.refs.strong_count() == 0) {
+                let slot = entry.take().unwrap();
+                if let Some(path) = slot.path {
+                    self.by_path.remove(&path_key(&path));
+                    unloaded.push(path);
+                }
+                self.free.push(index);
+            }
+        }
+        unloaded
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+}
+
+// Paths are compared by the hash of their canonical form, so `a/../b.png` and `b.png` are one asset.
+fn path_key(path: &Path) -> u64 {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut hasher = DefaultHasher::new();
+    canonical.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The registry of all loaded assets, see above.
+pub struct Assets {
+    textures: Storage<Texture>,
+    meshes: Storage<Mesh>,
+    shaders: Storage<ShaderId>,
+    loader: Loader,
+    placeholder_texture: Rc<Texture>,
+    placeholder_mesh: Rc<Mesh>,
+    compress_vertices: bool,
+    cache: Rc<DeviceCache>, // For the samplers of the textures.
+}
+
+impl Assets {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, cache: Rc<DeviceCache>) -> Assets {
+        Assets {
+            textures: Storage::new(),
+            meshes: Storage::new(),
+            shaders: Storage::new(),
+            loader: Loader::new(),
+            placeholder_texture: Rc::new(checker_texture(device, queue, &cache)),
+            placeholder_mesh: Rc::new(unit_cube(device)),
+            compress_vertices: false,
+            cache,
+        }
+    }
+
+    // Packs the vertices of meshes loaded from files, see packing.rs.
+    pub fn with_compressed_vertices(mut self, compress: bool) -> Assets {
+        self.compress_vertices = compress;
+        self
+    }
+
+    pub fn compresses_vertices(&self) -> bool {
+        self.compress_vertices
+    }
+
+    pub fn add_texture(&mut self, texture: Texture) -> TextureHandle {
+        self.textures.insert(texture, None)
+    }
+
+    // Loads a PNG or JPEG image, or returns the texture already loaded from `path`.
+    pub fn load_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<TextureHandle, LoadError> {
+        if let Some(handle) = self.textures.find(path) {
+            return Ok(handle);
+        }
+        let texture = Texture::load(device, queue, &self.cache, path)?;
+        Ok(self.textures.insert(texture, Some(path)))
+    }
+
+    // Starts loading an image in the background. The handle refers to a checker texture until it is loaded.
+    pub fn load_texture_async(&mut self, path: &Path) -> TextureHandle {
+        if let Some(handle) = self.textures.find(path) {
+            return handle;
+        }
+        self.loader.queue(Job::Texture(path.to_path_buf()));
+        self.textures.insert_shared(self.placeholder_texture.clone(), Some(path), false)
+    }
+
+    pub fn is_texture_loaded(&self, handle: &TextureHandle) -> bool {
+        self.textures.is_loaded(handle)
+    }
+
+    pub fn texture(&self, handle: &TextureHandle) -> &Rc<Texture> {
+        self.textures.get(handle)
+    }
+
+    // Swaps in a new version of the texture loaded from `path`, returning the old one.
+    pub fn replace_texture(&mut self, path: &Path, texture: Texture) -> Option<Rc<Texture>> {
+        self.textures.replace(path, texture)
+    }
+
+    pub fn texture_path(&self, handle: &TextureHandle) -> Option<&Path> {
+        self.textures.path(handle)
+    }
+
+    pub fn is_texture_file(&self, path: &Path) -> bool {
+        self.textures.contains_path(path)
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshHandle {
+        self.meshes.insert(mesh, None)
+    }
+
+    // Starts loading an OBJ model in the background. The handle refers to a unit cube until it is loaded.
+    pub fn load_mesh_async(&mut self, path: &Path) -> MeshHandle {
+        if let Some(handle) = self.meshes.find(path) {
+            return handle;
+        }
+        self.loader.queue(Job::Mesh(path.to_path_buf()));
+        self.meshes.insert_shared(self.placeholder_mesh.clone(), Some(path), false)
+    }
+
+    // A handle to the unit cube that also stands in for meshes that are still loading.
+    pub fn unit_cube(&mut self) -> MeshHandle {
+        self.meshes.insert_shared(self.placeholder_mesh.clone(), None, true)
+    }
+
+    pub fn is_mesh_loaded(&self, handle: &MeshHandle) -> bool {
+        self.meshes.is_loaded(handle)
+    }
+
+    pub fn mesh_path(&self, handle: &MeshHandle) -> Option<&Path> {
+        self.meshes.path(handle)
+    }
+
+    // True for handles from `unit_cube`, but not for loading meshes that show it in their place.
+    pub fn is_unit_cube(&self, handle: &MeshHandle) -> bool {
+        self.meshes.path(handle).is_none() && Rc::ptr_eq(self.meshes.get(handle), &self.placeholder_mesh)
+    }
+
+    pub fn is_mesh_file(&self, path: &Path) -> bool {
+        self.meshes.contains_path(path)
+    }
+
+    // Loads a model again after it changed on disk. The old version stays until the new one arrives.
+    pub fn reload_mesh(&mut self, path: &Path) {
+        self.loader.queue(Job::Mesh(path.to_path_buf()));
+    }
+
+    // Swaps in a new version of a mesh made at runtime, see dynamic_mesh.rs.
+    pub fn set_mesh(&mut self, handle: &MeshHandle, mesh: Mesh) {
+        self.meshes.set(handle, mesh)
+    }
+
+    pub fn mesh(&self, handle: &MeshHandle) -> &Mesh {
+        self.meshes.get(handle)
+    }
+
+    // Registers a shader compiled from `path`, so loading the file again finds it.
+    pub fn add_shader(&mut self, id: ShaderId, path: &Path) -> ShaderHandle {
+        self.shaders.insert(id, Some(path))
+    }
+
+    pub fn find_shader(&self, path: &Path) -> Option<ShaderHandle> {
+        self.shaders.find(path)
+    }
+
+    pub fn shader(&self, handle: &ShaderHandle) -> ShaderId {
+        **self.shaders.get(handle)
+    }
+
+    // The file a shader was compiled from, if it was loaded from one.
+    pub fn shader_path(&self, id: ShaderId) -> Option<&Path> {
+        self.shaders.slots.iter().flatten().find(|slot| *slot.asset == id).and_then(|slot| slot.path.as_deref())
+    }
+
+    // Uploads the assets finished by the loader threads, swapping out their placeholders.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        for finished in self.loader.finished() {
+            let path = &finished.path;
+            let label = path.to_string_lossy();
+            match finished.result {
+                Ok(Loaded::Texture { width, height, pixels }) => {
+                    let texture = Texture::from_rgba8(device, queue, &self.cache, &label, width, height, &pixels);
+                    self.textures.replace(path, texture);
+                }
+                Ok(Loaded::Mesh { vertices, indices }) => {
+                    let mesh = if self.compress_vertices {
+                        Mesh::new_packed(device, &label, &vertices, &indices)
+                    } else {
+                        Mesh::new(device, &label, &vertices, &indices)
+                    };
+                    self.meshes.replace(path, mesh);
+                }
+                Err(e) => tracing::error!("Failed to load {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    // Number of background loads that have not finished yet, e.g. for a loading screen.
+    pub fn pending_loads(&self) -> usize {
+        self.loader.pending()
+    }
+
+    // Unloads the assets without handles. Returns the files they were loaded from.
+    pub fn collect_garbage(&mut self) -> Vec<PathBuf> {
+        let mut unloaded = self.textures.collect_garbage();
+        unloaded.extend(self.meshes.collect_garbage());
+        unloaded.extend(self.shaders.collect_garbage());
+        unloaded
+    }
+
+    // Number of loaded (textures, meshes, shaders).
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (self.textures.len(), self.meshes.len(), self.shaders.len())
+    }
+}
+
+// Magenta and black squares, hard to mistake for a real texture.
+fn checker_texture(device: &wgpu::Device, queue: &wgpu::Queue, cache: &DeviceCache) -> Texture {
+    const SIZE: u32 = 8;
+    let pixels: Vec<u8> = (0..SIZE * SIZE)
+        .flat_map(|i| {
+            let (x, y) = (i % SIZE, i / SIZE);
+            if (x + y) % 2 == 0 {
+                [255, 0, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        })
+        .collect();
+    Texture::from_rgba8(device, queue, cache, "Placeholder Texture", SIZE, SIZE, &pixels)
+}
+
+// A cube of size 1 around the origin, colored by position.
+fn unit_cube(device: &wgpu::Device) -> Mesh {
+    // Corner `i` is at x = bit 0, y = bit 1, z = bit 2.
+    let vertices: Vec<Vertex> = (0..8)
+        .map(|i| {
+            let corner = [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32];
+            Vertex {
+                position: corner.map(|c| c - 0.5),
+                color: corner,
+            }
+        })
+        .collect();
+    // Counter-clockwise seen from outside.
+    let indices: [u16; 36] = [
+        0, 6, 2, 0, 4, 6, // -x
+        1, 3, 7, 1, 7, 5, // +x
+        0, 1, 5, 0, 5, 4, // -y
+        2, 7, 3, 2, 6, 7, // +y
+        0, 3, 1, 0, 2, 3, // -z
+        4, 5, 7, 4, 7, 6, // +z
+    ];
+    Mesh::new(device, "Placeholder Cube", &vertices, &indices)
+}
+
+// Hot reloading
+//======================
+// Assets loaded from files are watched, so editing a shader or texture shows up in the running app.
+// A background thread polls the modification times of the watched files; `GFX::update_assets`
+// picks up the changes at the start of a frame and swaps the new versions in, so a frame is always
+// drawn with either the old or the new asset, never a mix.
+// A file that fails to load (a shader with a syntax error, a half-written image) keeps the old version.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Why an asset could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Shader(ReflectError),
+    Tilemap(TiledError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Image(e) => write!(f, "{}", e),
+            LoadError::Shader(e) => write!(f, "{}", e),
+            LoadError::Tilemap(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for LoadError {
+    fn from(e: image::ImageError) -> Self {
+        LoadError::Image(e)
+    }
+}
+
+impl From<ReflectError> for LoadError {
+    fn from(e: ReflectError) -> Self {
+        LoadError::Shader(e)
+    }
+}
+
+impl From<TiledError> for LoadError {
+    fn from(e: TiledError) -> Self {
+        LoadError::Tilemap(e)
+    }
+}
+
+/// Reports files that changed on disk since they were watched.
+pub struct FileWatcher {
+    // Last seen modification time of every watched file, shared with the polling thread.
+    files: Arc<Mutex<HashMap<PathBuf, Option<SystemTime>>>>,
+    changed: Receiver<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        let files: Arc<Mutex<HashMap<PathBuf, Option<SystemTime>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let (changed_tx, changed_rx) = channel::<PathBuf>();
+
+        // The thread only holds a weak reference, and stops once the watcher is dropped.
+        let weak = Arc::downgrade(&files);
+        thread::Builder::new()
+            .name("file watcher".into())
+            .spawn(move || {
+                while let Some(files) = weak.upgrade() {
+                    let mut files = files.lock().unwrap();
+                    for (path, last) in files.iter_mut() {
+                        let modified = modified_time(path);
+                        if modified != *last {
+                            *last = modified;
+                            // A deleted file is not a new version, editors briefly remove files while saving.
+                            if modified.is_some() && changed_tx.send(path.clone()).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    drop(files);
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })
+            .expect("failed to spawn file watcher thread");
+
+        FileWatcher {
+            files,
+            changed: changed_rx,
+        }
+    }
+
+    pub fn watch(&self, path: &Path) {
+        let modified = modified_time(path);
+        self.files.lock().unwrap().entry(path.to_path_buf()).or_insert(modified);
+    }
+
+    pub fn unwatch(&self, path: &Path) {
+        self.files.lock().unwrap().remove(path);
+    }
+
+    // The files that changed since the last call, each listed once.
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self.changed.try_iter().collect();
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,171 @@
+use crate::compute::ComputeKernel;
+use crate::compute_kernels::{self, DownsampleNode, GaussianBlurNode, IMAGE_WORKGROUP, STORAGE_FORMAT};
+use crate::exposure::{ExposureSettings, HDR_TARGET, LUMINANCE};
+use crate::render_graph::{NodeContext, NodeParameter, RenderGraph, RenderNode};
+use crate::uniform::UniformLayout;
+
+// Bloom
+//======================
+// Light bleeding around the brightest parts of the HDR scene, like glare in a lens, so emissive
+// materials (GFX::add_emissive_material) and lights read as glowing. Part of the exposure nodes
+// when `ExposureSettings::bloom` is set:
+//
+//     let exposure = ExposureSettings { bloom: Some(BloomSettings::default()), ..Default::default() };
+//
+// The bright parts are picked at half resolution, halved once more, blurred, and added to the
+// scene by the tonemap node before it is exposed:
+//
+//     scene.hdr -> Bloom Threshold -> Downsample -> Gaussian Blur -> Tonemap
+//
+// The threshold is in exposed units, where 1 is white in the frame, so what blooms does not
+// change as the eyes adapt.
+
+/// The render graph texture holding the blurred bloom, a quarter of the scene's size.
+pub const BLOOM_TARGET: &str = "bloom.blurred";
+
+const BRIGHT: &str = "bloom.bright";
+const QUARTER: &str = "bloom.quarter";
+
+/// How much of the scene blooms, and how far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+    pub threshold: f32, // Exposed brightness from where on the scene blooms.
+    pub knee: f32, // Fraction of the threshold below it where the bloom fades in.
+    pub intensity: f32, // Of the bloom added to the scene.
+    // Of the blur, in pixels of the quarter resolution texture.
+    pub radius: i32,
+    pub sigma: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        BloomSettings {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.3,
+            radius: 12,
+            sigma: 5.0,
+        }
+    }
+}
+
+// Adds the nodes writing `bloom.blurred` from `scene.hdr`, see above.
+pub fn add_nodes(graph: &mut RenderGraph, exposure: ExposureSettings, settings: BloomSettings) {
+    graph.add_node(Box::new(BloomThresholdNode::new(exposure, settings)));
+    graph.add_node(Box::new(DownsampleNode::new(BRIGHT, QUARTER)));
+    graph.add_node(Box::new(GaussianBlurNode::new(QUARTER, BLOOM_TARGET, settings.radius, settings.sigma)));
+}
+
+uniform_struct! {
+    struct ThresholdParams {
+        threshold: f32,
+        knee: f32,
+        min_ev: f32,
+        max_ev: f32,
+        compensation: f32,
+    }
+}
+assert_uniform_size!(ThresholdParams, 20);
+
+/// Writes the parts of `scene.hdr` brighter than the threshold into `bloom.bright`, at half size.
+pub struct BloomThresholdNode {
+    exposure: ExposureSettings,
+    settings: BloomSettings,
+    kernel: Option<ComputeKernel>,
+    params: Option<wgpu::Buffer>,
+}
+
+impl BloomThresholdNode {
+    pub fn new(exposure: ExposureSettings, settings: BloomSettings) -> BloomThresholdNode {
+        BloomThresholdNode {
+            exposure,
+            settings,
+            kernel: None,
+            params: None,
+        }
+    }
+}
+
+impl RenderNode for BloomThresholdNode {
+    fn name(&self) -> &str {
+        "Bloom Threshold"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![HDR_TARGET, LUMINANCE]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![BRIGHT]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (width, height) = match ctx.resources.texture(HDR_TARGET) {
+            Some(scene) => ((scene.width / 2).max(1), (scene.height / 2).max(1)),
+            None => return,
+        };
+        if ctx.resources.buffer(LUMINANCE).is_none() {
+            return;
+        }
+        ctx.resources.ensure_texture(ctx.device, BRIGHT, width, height, STORAGE_FORMAT);
+
+        let params = ThresholdParams {
+            threshold: self.settings.threshold.max(0.0),
+            knee: self.settings.knee.clamp(0.0, 1.0),
+            min_ev: self.exposure.min_ev,
+            max_ev: self.exposure.max_ev.max(self.exposure.min_ev),
+            compensation: self.exposure.compensation,
+        };
+        match &self.params {
+            Some(buffer) => ctx.queue.write_buffer(buffer, 0, &params.to_uniform_bytes()),
+            None => self.params = Some(compute_kernels::create_uniform_buffer(ctx.device, "Bloom Threshold Params", &params)),
+        }
+
+        let kernel = compute_kernels::kernel(&mut self.kernel, ctx.device, "Bloom Threshold", include_str!("bloom_threshold.wgsl"));
+        let scene = ctx.resources.texture(HDR_TARGET).unwrap();
+        let luminance = ctx.resources.buffer(LUMINANCE).unwrap();
+        let output = ctx.resources.texture(BRIGHT).unwrap();
+
+        let bind_group = kernel.bind_group(
+            ctx.device,
+            0,
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_ref().unwrap().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&scene.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: luminance.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&output.view),
+                },
+            ],
+        );
+        match bind_group {
+            Ok(bind_group) => kernel.dispatch_2d(encoder, &[&bind_group], IMAGE_WORKGROUP, width, height),
+            Err(e) => tracing::error!("Bloom Threshold: {}", e),
+        }
+    }
+
+    fn parameters(&self) -> Vec<NodeParameter> {
+        vec![
+            NodeParameter::new("threshold", self.settings.threshold, 0.0..=8.0),
+            NodeParameter::new("knee", self.settings.knee, 0.0..=1.0),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "threshold" => self.settings.threshold = value,
+            "knee" => self.settings.knee = value,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// The parts of the HDR scene bright enough to bloom, at half its resolution, see bloom.rs. The
+// threshold is in exposed units, so it follows the automatic exposure: 1 is white in the frame.
+
+struct ThresholdParams {
+    threshold: f32;
+    knee: f32; // Width of the soft transition below the threshold, relative to it.
+    min_ev: f32;
+    max_ev: f32;
+    compensation: f32;
+};
+
+struct Average {
+    luminance: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: ThresholdParams;
+[[group(0), binding(1)]]
+var input: texture_2d<f32>;
+[[group(0), binding(2)]]
+var<storage, read> average: Average;
+[[group(0), binding(3)]]
+var output: texture_storage_2d<rgba16float, write>;
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    // A 2x2 box, like downsample.wgsl.
+    let max_pos = textureDimensions(input) - vec2<i32>(1, 1);
+    let src = pos * 2;
+    let color = (textureLoad(input, src, 0).rgb
+        + textureLoad(input, min(src + vec2<i32>(1, 0), max_pos), 0).rgb
+        + textureLoad(input, min(src + vec2<i32>(0, 1), max_pos), 0).rgb
+        + textureLoad(input, min(src + vec2<i32>(1, 1), max_pos), 0).rgb) * 0.25;
+
+    // The exposure of tonemap.wgsl.
+    let ev = clamp(log2(max(average.luminance, 1e-6) * 100.0 / 12.5), params.min_ev, params.max_ev);
+    let exposure = 0.18 / (exp2(ev) * 0.125) * exp2(params.compensation);
+
+    // The brightest channel past the threshold, with a quadratic knee below it so the bloom fades
+    // in instead of popping.
+    let brightness = max(color.r, max(color.g, color.b)) * exposure;
+    let knee = params.threshold * params.knee + 1e-5;
+    let soft = clamp(brightness - params.threshold + knee, 0.0, 2.0 * knee);
+    let contribution = max(soft * soft / (4.0 * knee), brightness - params.threshold) / max(brightness, 1e-5);
+    // Kept in scene units, the tonemap exposes it with the scene.
+    textureStore(output, pos, vec4<f32>(color * contribution, 1.0));
+}
@@ -0,0 +1,33 @@
+// Separable Gaussian blur. Run once horizontally and once vertically.
+
+struct BlurParams {
+    direction: vec2<i32>; // (1, 0) for the horizontal pass, (0, 1) for the vertical pass.
+    radius: i32;
+    sigma: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: BlurParams;
+[[group(0), binding(1)]]
+var input: texture_2d<f32>;
+[[group(0), binding(2)]]
+var output: texture_storage_2d<rgba16float, write>;
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    var sum = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    var weight_sum = 0.0;
+    for (var i = -params.radius; i <= params.radius; i = i + 1) {
+        let p = clamp(pos + params.direction * i, vec2<i32>(0, 0), size - vec2<i32>(1, 1));
+        let weight = exp(-f32(i * i) / (2.0 * params.sigma * params.sigma));
+        sum = sum + textureLoad(input, p, 0) * weight;
+        weight_sum = weight_sum + weight;
+    }
+    textureStore(output, pos, sum / weight_sum);
+}
@@ -0,0 +1,382 @@
+use cgmath::Vector2;
+use wgpu::util::DeviceExt;
+
+use crate::compute::ComputeKernel;
+use crate::gfx::GFX;
+use crate::reflection::ShaderReflection;
+use crate::render_graph::{NodeContext, RenderNode, Stage};
+use crate::rng::Rng;
+use crate::uniform::UniformLayout;
+
+// Boids
+//======================
+// A flock of agents following three local rules, simulated and drawn on the GPU:
+//
+//     learn-wgpu --boids 20000
+//
+// Each boid steers away from the ones too close to it (separation), towards the average heading
+// of its neighbors (alignment) and towards their center (cohesion). Looking at every other boid
+// would cost count² reads, so the neighbors are found through a grid of cells the size of the
+// largest rule distance, rebuilt on the GPU every step (boids.wgsl):
+//
+//     clear_grid   zeroes the count of every cell
+//     insert       every boid adds its index to its cell, with an atomic counter
+//     update       every boid applies the rules to the boids in the 3 x 3 cells around it,
+//                  writing its new state into "boids.next", which is then copied back
+//
+// Cells hold `cell_capacity` boids. Boids past that in a very dense cell are missed as
+// neighbors, which is hardly visible: the separation rule keeps cells from filling up.
+//
+// The boids are drawn after the scene as instanced triangles turned to their velocity, from the
+// same buffer the simulation writes. The world is the square from -1 to 1 and wraps around.
+// Like the N-body demo (nbody.rs), the simulation advances by `dt` per rendered frame.
+
+pub const BOIDS: &str = "boids.boids";
+pub const NEXT: &str = "boids.next";
+pub const CELL_COUNTS: &str = "boids.cell_counts";
+pub const CELL_BOIDS: &str = "boids.cell_boids";
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// See above. Distances and speeds are in world units, the world being 2 wide.
+#[derive(Clone, Copy, Debug)]
+pub struct BoidsSettings {
+    pub count: u32,
+    pub dt: f32, // Simulated time per frame.
+    pub separation_distance: f32,
+    pub alignment_distance: f32,
+    pub cohesion_distance: f32,
+    pub separation: f32, // How strongly each rule steers.
+    pub alignment: f32,
+    pub cohesion: f32,
+    pub max_speed: f32,
+    pub cell_capacity: u32,
+    pub size: f32, // Drawn length of a boid.
+    pub seed: u64,
+}
+
+impl Default for BoidsSettings {
+    fn default() -> Self {
+        BoidsSettings {
+            count: 10_000,
+            dt: 0.04,
+            separation_distance: 0.025,
+            alignment_distance: 0.025,
+            cohesion_distance: 0.1,
+            separation: 0.05,
+            alignment: 0.005,
+            cohesion: 0.02,
+            max_speed: 0.1,
+            cell_capacity: 64,
+            size: 0.015,
+            seed: 1,
+        }
+    }
+}
+
+impl BoidsSettings {
+    fn cell_size(&self) -> f32 {
+        self.separation_distance.max(self.alignment_distance).max(self.cohesion_distance)
+    }
+
+    // Cells along each side of the world.
+    fn grid_size(&self) -> u32 {
+        ((2.0 / self.cell_size()).ceil() as u32).max(1)
+    }
+}
+
+// Adds the simulation and the drawing of the flock to the render graph, see above.
+pub fn add_nodes(gfx: &mut GFX, settings: BoidsSettings) {
+    tracing::info!("Boids: {} in a {}x{} grid", settings.count, settings.grid_size(), settings.grid_size());
+    gfx.add_render_node(Box::new(BoidsSimulation::new(settings)));
+    gfx.add_render_node(Box::new(BoidsDraw::new(settings)));
+}
+
+uniform_struct! {
+    struct SimulationParams {
+        count: u32,
+        grid_size: u32,
+        cell_capacity: u32,
+        dt: f32,
+        cell_size: f32,
+        separation_distance: f32,
+        alignment_distance: f32,
+        cohesion_distance: f32,
+        separation: f32,
+        alignment: f32,
+        cohesion: f32,
+        max_speed: f32,
+    }
+}
+assert_uniform_size!(SimulationParams, 48);
+
+uniform_struct! {
+    struct DrawParams {
+        scale: Vector2<f32>,
+        size: f32,
+    }
+}
+assert_uniform_size!(DrawParams, 16);
+
+// Spread over the world, flying in random directions.
+fn initial_boids(settings: &BoidsSettings) -> Vec<[f32; 4]> {
+    let rng = Rng::new(settings.seed);
+    (0..settings.count)
+        .map(|_| {
+            let angle = rng.range(0.0, std::f32::consts::TAU);
+            let speed = rng.range(0.2, 1.0) * settings.max_speed;
+            [rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), angle.cos() * speed, angle.sin() * speed]
+        })
+        .collect()
+}
+
+// The kernels, sharing one bind group layout, created on the first run.
+struct Kernels {
+    clear_grid: ComputeKernel,
+    insert: ComputeKernel,
+    update: ComputeKernel,
+    bind_groups: [wgpu::BindGroup; 3], // For each kernel, in the order above.
+    _params: wgpu::Buffer,
+}
+
+/// Moves the flock one step, before the scene, see above.
+pub struct BoidsSimulation {
+    settings: BoidsSettings,
+    kernels: Option<Kernels>,
+}
+
+impl BoidsSimulation {
+    pub fn new(settings: BoidsSettings) -> BoidsSimulation {
+        BoidsSimulation { settings, kernels: None }
+    }
+
+    fn create_kernels(&self, ctx: &mut NodeContext) -> Kernels {
+        let settings = &self.settings;
+        let grid_size = settings.grid_size();
+        let cells = (grid_size * grid_size) as wgpu::BufferAddress;
+        let size = settings.count as wgpu::BufferAddress * 16;
+        let storage = wgpu::BufferUsages::STORAGE;
+        let resources = &mut *ctx.resources;
+        resources.ensure_buffer(ctx.device, BOIDS, size, storage | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST);
+        resources.ensure_buffer(ctx.device, NEXT, size, storage | wgpu::BufferUsages::COPY_SRC);
+        resources.ensure_buffer(ctx.device, CELL_COUNTS, cells * 4, storage);
+        resources.ensure_buffer(ctx.device, CELL_BOIDS, cells * settings.cell_capacity as wgpu::BufferAddress * 4, storage);
+        ctx.queue.write_buffer(resources.buffer(BOIDS).unwrap(), 0, bytemuck::cast_slice(&initial_boids(settings)));
+
+        let params = SimulationParams {
+            count: settings.count,
+            grid_size,
+            cell_capacity: settings.cell_capacity,
+            dt: settings.dt,
+            cell_size: settings.cell_size(),
+            separation_distance: settings.separation_distance,
+            alignment_distance: settings.alignment_distance,
+            cohesion_distance: settings.cohesion_distance,
+            separation: settings.separation,
+            alignment: settings.alignment,
+            cohesion: settings.cohesion,
+            max_speed: settings.max_speed,
+        };
+        let params = ctx.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Boids Params"),
+            contents: &params.to_uniform_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // The shaders are part of the crate, so failing to build them is a bug.
+        let kernel = |entry_point: &str| {
+            let label = format!("Boids {}", entry_point);
+            ComputeKernel::new(ctx.device, &label, include_str!("boids.wgsl"), entry_point).expect("built-in kernel is valid")
+        };
+        let (clear_grid, insert, update) = (kernel("clear_grid"), kernel("insert"), kernel("update"));
+        let buffer = |binding: u32, name: &str| wgpu::BindGroupEntry {
+            binding,
+            resource: resources.buffer(name).unwrap().as_entire_binding(),
+        };
+        let entries = [
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            },
+            buffer(1, BOIDS),
+            buffer(2, NEXT),
+            buffer(3, CELL_COUNTS),
+            buffer(4, CELL_BOIDS),
+        ];
+        let bind_group = |kernel: &ComputeKernel| kernel.bind_group(ctx.device, 0, &entries).expect("boids buffers match the kernel");
+        let bind_groups = [bind_group(&clear_grid), bind_group(&insert), bind_group(&update)];
+        Kernels {
+            clear_grid,
+            insert,
+            update,
+            bind_groups,
+            _params: params,
+        }
+    }
+}
+
+impl RenderNode for BoidsSimulation {
+    fn name(&self) -> &str {
+        "Boids Simulation"
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::BeforeScene
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![BOIDS, NEXT, CELL_COUNTS, CELL_BOIDS]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        if self.settings.count == 0 {
+            return;
+        }
+        if self.kernels.is_none() {
+            self.kernels = Some(self.create_kernels(ctx));
+        }
+        let kernels = self.kernels.as_ref().unwrap();
+        let (count, cells) = (self.settings.count, self.settings.grid_size().pow(2));
+        let workgroup = (WORKGROUP_SIZE, 1);
+        kernels.clear_grid.dispatch_2d(encoder, &[&kernels.bind_groups[0]], workgroup, cells, 1);
+        kernels.insert.dispatch_2d(encoder, &[&kernels.bind_groups[1]], workgroup, count, 1);
+        kernels.update.dispatch_2d(encoder, &[&kernels.bind_groups[2]], workgroup, count, 1);
+        let (boids, next) = (ctx.resources.buffer(BOIDS).unwrap(), ctx.resources.buffer(NEXT).unwrap());
+        encoder.copy_buffer_to_buffer(next, 0, boids, 0, count as wgpu::BufferAddress * 16);
+    }
+}
+
+// The pipeline for the frame's format.
+struct DrawState {
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    params: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Draws the flock on top of the scene, see above.
+pub struct BoidsDraw {
+    settings: BoidsSettings,
+    state: Option<DrawState>,
+}
+
+impl BoidsDraw {
+    pub fn new(settings: BoidsSettings) -> BoidsDraw {
+        BoidsDraw { settings, state: None }
+    }
+
+    fn create_state(device: &wgpu::Device, format: wgpu::TextureFormat) -> DrawState {
+        let wgsl = include_str!("boids_draw.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("Boids Draw"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Boids Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Boids Draw Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Boids Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                // A triangle per boid, read from the simulation's buffer.
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: 16,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            // Turning a boid may flip its winding.
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Boids Draw Params"),
+            size: DrawParams::SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Boids Draw Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            }],
+        });
+        DrawState {
+            pipeline,
+            format,
+            params,
+            bind_group,
+        }
+    }
+}
+
+impl RenderNode for BoidsDraw {
+    fn name(&self) -> &str {
+        "Boids"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![BOIDS]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (view, boids) = match (ctx.frame.view, ctx.resources.buffer(BOIDS)) {
+            (Some(view), Some(boids)) => (view, boids),
+            _ => return,
+        };
+        let format = ctx.frame.format;
+        if self.state.as_ref().is_none_or(|state| state.format != format) {
+            self.state = Some(Self::create_state(ctx.device, format));
+        }
+        let state = self.state.as_ref().unwrap();
+
+        // The whole world in the shorter side of the frame.
+        let (width, height) = (ctx.frame.size.0.max(1) as f32, ctx.frame.size.1.max(1) as f32);
+        let scale = if width > height {
+            Vector2::new(height / width, 1.0)
+        } else {
+            Vector2::new(1.0, width / height)
+        };
+        let params = DrawParams {
+            scale,
+            size: self.settings.size,
+        };
+        ctx.queue.write_buffer(&state.params, 0, &params.to_uniform_bytes());
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Boids Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&state.pipeline);
+        render_pass.set_bind_group(0, &state.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, boids.slice(..));
+        render_pass.draw(0..3, 0..self.settings.count);
+    }
+}
@@ -0,0 +1,143 @@
+// Flocking over a spatial hash grid, see boids.rs. Every step runs `clear_grid` over the cells,
+// `insert` and `update` over the boids, each in a pass of its own.
+
+struct Params {
+    count: u32;
+    grid_size: u32; // Cells along each side of the world.
+    cell_capacity: u32; // Boids a cell holds, the ones after them are missed as neighbors.
+    dt: f32;
+    cell_size: f32;
+    separation_distance: f32;
+    alignment_distance: f32;
+    cohesion_distance: f32;
+    separation: f32;
+    alignment: f32;
+    cohesion: f32;
+    max_speed: f32;
+};
+
+// xy is the position, zw the velocity. The world wraps around at -1 and 1.
+struct Boids {
+    data: array<vec4<f32>>;
+};
+
+struct CellCounts {
+    data: array<atomic<u32>>;
+};
+
+// `cell_capacity` boid indices per cell.
+struct CellBoids {
+    data: array<u32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var<storage, read_write> boids: Boids;
+[[group(0), binding(2)]]
+var<storage, read_write> next: Boids;
+[[group(0), binding(3)]]
+var<storage, read_write> cell_counts: CellCounts;
+[[group(0), binding(4)]]
+var<storage, read_write> cell_boids: CellBoids;
+
+fn cell_of(position: vec2<f32>) -> vec2<i32> {
+    let cell = vec2<i32>(floor((position + vec2<f32>(1.0)) / params.cell_size));
+    return clamp(cell, vec2<i32>(0), vec2<i32>(i32(params.grid_size) - 1));
+}
+
+fn cell_index(cell: vec2<i32>) -> u32 {
+    let size = i32(params.grid_size);
+    // Wrapped like the world.
+    let wrapped = (cell + vec2<i32>(size)) % vec2<i32>(size);
+    return u32(wrapped.y * size + wrapped.x);
+}
+
+// From `a` to `b` the short way around the wrapping world.
+fn wrapped_delta(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let d = b - a;
+    return d - 2.0 * round(d * 0.5);
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn clear_grid([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    if (id.x < params.grid_size * params.grid_size) {
+        atomicStore(&cell_counts.data[id.x], 0u);
+    }
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn insert([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.count) {
+        return;
+    }
+    let cell = cell_index(cell_of(boids.data[index].xy));
+    let slot = atomicAdd(&cell_counts.data[cell], 1u);
+    if (slot < params.cell_capacity) {
+        cell_boids.data[cell * params.cell_capacity + slot] = index;
+    }
+}
+
+// The three rules over the boids in the cells around this one: steer away from those too close
+// (separation), towards the heading (alignment) and the center (cohesion) of the others nearby.
+[[stage(compute), workgroup_size(64)]]
+fn update([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.count) {
+        return;
+    }
+    let boid = boids.data[index];
+    let position = boid.xy;
+    var velocity = boid.zw;
+
+    var separation = vec2<f32>(0.0);
+    var heading = vec2<f32>(0.0);
+    var center = vec2<f32>(0.0);
+    var aligned = 0u;
+    var grouped = 0u;
+    // The cell size is the largest rule distance, so the neighbors are in the 3 x 3 cells around.
+    let cell = cell_of(position);
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let neighbor_cell = cell_index(cell + vec2<i32>(x, y));
+            let count = min(atomicLoad(&cell_counts.data[neighbor_cell]), params.cell_capacity);
+            for (var i = 0u; i < count; i = i + 1u) {
+                let other = cell_boids.data[neighbor_cell * params.cell_capacity + i];
+                if (other == index) {
+                    continue;
+                }
+                let neighbor = boids.data[other];
+                let d = wrapped_delta(position, neighbor.xy);
+                let distance = length(d);
+                if (distance < params.separation_distance) {
+                    separation = separation - d;
+                }
+                if (distance < params.alignment_distance) {
+                    heading = heading + neighbor.zw;
+                    aligned = aligned + 1u;
+                }
+                if (distance < params.cohesion_distance) {
+                    center = center + d;
+                    grouped = grouped + 1u;
+                }
+            }
+        }
+    }
+
+    velocity = velocity + separation * params.separation;
+    if (aligned > 0u) {
+        velocity = velocity + heading / f32(aligned) * params.alignment;
+    }
+    if (grouped > 0u) {
+        velocity = velocity + center / f32(grouped) * params.cohesion;
+    }
+    let speed = length(velocity);
+    if (speed > params.max_speed) {
+        velocity = velocity * (params.max_speed / speed);
+    }
+
+    var moved = position + velocity * params.dt;
+    moved = moved - 2.0 * floor((moved + vec2<f32>(1.0)) * 0.5);
+    next.data[index] = vec4<f32>(moved, velocity);
+}
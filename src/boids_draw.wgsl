@@ -0,0 +1,41 @@
+// Draws the boids as triangles pointing where they fly, see boids.rs.
+
+struct DrawParams {
+    scale: vec2<f32>; // Fits the square world into the frame.
+    size: f32; // Length of a boid, in world units.
+};
+[[group(0), binding(0)]]
+var<uniform> params: DrawParams;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] vertex_index: u32,
+    [[location(0)]] boid: vec4<f32>, // Position and velocity, per instance.
+) -> VertexOutput {
+    // Pointing along +y, turned to the velocity.
+    var shape = array<vec2<f32>, 3>(
+        vec2<f32>(-0.3, -0.5),
+        vec2<f32>(0.3, -0.5),
+        vec2<f32>(0.0, 0.5),
+    );
+    let forward = normalize(boid.zw + vec2<f32>(0.0, 1e-6));
+    let right = vec2<f32>(forward.y, -forward.x);
+    let local = shape[vertex_index] * params.size;
+    let position = boid.xy + right * local.x + forward * local.y;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(position * params.scale, 0.0, 1.0);
+    // Colored by heading.
+    out.color = 0.5 + 0.5 * vec3<f32>(forward.x, forward.y, -forward.x);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
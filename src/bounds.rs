@@ -0,0 +1,166 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Transform, Vector3, Vector4};
+
+// Bounds
+//======================
+// Simple shapes for questions about the scene that don't need the triangles: what is in front of
+// a camera, what does a ray hit, what is near a point. See bvh.rs for answering them quickly.
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Aabb {
+        Aabb { min, max }
+    }
+
+    // The smallest box around `points`, `None` without points.
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Option<Aabb> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Aabb::new(first, first), |bounds, point| bounds.union(&Aabb::new(point, point))))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        Point3::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        )
+    }
+
+    pub fn extents(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    // Half the surface area, which is all the BVH needs to compare boxes.
+    pub fn half_area(&self) -> f32 {
+        let e = self.extents();
+        e.x * e.y + e.y * e.z + e.z * e.x
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        (self.min.x..=self.max.x).contains(&point.x)
+            && (self.min.y..=self.max.y).contains(&point.y)
+            && (self.min.z..=self.max.z).contains(&point.z)
+    }
+
+    // The box around this box after `matrix` moved it, e.g. from model space into world space.
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Aabb {
+        // The extents grow by the absolute value of each axis of the matrix (Arvo's method).
+        let center = matrix.transform_point(self.center());
+        let half = self.extents() * 0.5;
+        let extent = matrix.x.truncate().map(f32::abs) * half.x
+            + matrix.y.truncate().map(f32::abs) * half.y
+            + matrix.z.truncate().map(f32::abs) * half.z;
+        Aabb::new(center - extent, center + extent)
+    }
+}
+
+/// A half-line from `origin` along `direction`.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>, // Normalized.
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    // The ray through a point of the screen, in normalized device coordinates (x and y in [-1, 1],
+    // y up), for the camera with the view projection matrix `view_proj`.
+    pub fn from_screen(view_proj: &Matrix4<f32>, x: f32, y: f32) -> Option<Ray> {
+        let inverse = cgmath::SquareMatrix::invert(view_proj)?;
+        let unproject = |z: f32| {
+            let point = inverse * Vector4::new(x, y, z, 1.0);
+            Point3::from_homogeneous(point)
+        };
+        // wgpu's depth goes from 0 at the near plane to 1 at the far plane.
+        let (near, far) = (unproject(0.0), unproject(1.0));
+        Some(Ray::new(near, far - near))
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // The distance along the ray to where it enters the box, 0 if it starts inside.
+    pub fn intersect_aabb(&self, bounds: &Aabb) -> Option<f32> {
+        // Slab test: the ray is inside the box where it is between all three pairs of planes.
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let t0 = (bounds.min[axis] - self.origin[axis]) * inverse;
+            let t1 = (bounds.max[axis] - self.origin[axis]) * inverse;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+/// The six planes around what a camera sees. Points inside are on the positive side of all planes.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6], // (normal, distance), not normalized.
+}
+
+impl Frustum {
+    // The frustum of a view projection matrix, with wgpu's depth range of [0, 1].
+    pub fn from_matrix(view_proj: &Matrix4<f32>) -> Frustum {
+        // The planes are sums of the rows of the matrix (Gribb and Hartmann).
+        let row = |i: usize| Vector4::new(view_proj.x[i], view_proj.y[i], view_proj.z[i], view_proj.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Frustum {
+            planes: [w + x, w - x, w + y, w - y, z, w - z],
+        }
+    }
+
+    pub fn intersects_aabb(&self, bounds: &Aabb) -> bool {
+        // A box is outside if its corner furthest along the normal of a plane is behind it.
+        self.planes.iter().all(|plane| {
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { bounds.max.x } else { bounds.min.x },
+                if plane.y >= 0.0 { bounds.max.y } else { bounds.min.y },
+                if plane.z >= 0.0 { bounds.max.z } else { bounds.min.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn contains_aabb(&self, bounds: &Aabb) -> bool {
+        // Inside if even the corner least along each normal is in front of the plane.
+        self.planes.iter().all(|plane| {
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { bounds.min.x } else { bounds.max.x },
+                if plane.y >= 0.0 { bounds.min.y } else { bounds.max.y },
+                if plane.z >= 0.0 { bounds.min.z } else { bounds.max.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
@@ -0,0 +1,349 @@
+use std::cmp::Ordering;
+
+use crate::bounds::{Aabb, Frustum, Ray};
+
+// Bounding volume hierarchy
+//======================
+// A binary tree of boxes over the bounds of numbered items (GFX uses the renderable indices), so
+// culling, picking and overlap queries skip whole groups of items far away from what they look
+// for instead of testing every item.
+//
+// Items that move keep their leaf: `set` updates the leaf and grows or shrinks the boxes above it
+// ("refitting"), which is cheap but loosens the tree over time. `update` builds the tree from
+// scratch when items were added or removed, or when refitting made the boxes twice as large in
+// total as right after the last build.
+
+const NONE: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Leaf(usize),         // The item.
+    Inner(usize, usize), // The children.
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    parent: usize, // `NONE` at the root.
+    kind: Kind,
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>, // The root is the first.
+    items: Vec<Option<Aabb>>,
+    leaves: Vec<usize>, // The leaf of each item, `NONE` for items not in the tree.
+    // Items were added or removed since the last build.
+    stale: bool,
+    // Total half area of the inner nodes, now and right after the last build, see above.
+    inner_area: f32,
+    built_area: f32,
+}
+
+impl Bvh {
+    pub fn new() -> Bvh {
+        Bvh {
+            nodes: Vec::new(),
+            items: Vec::new(),
+            leaves: Vec::new(),
+            stale: false,
+            inner_area: 0.0,
+            built_area: 0.0,
+        }
+    }
+
+    // Sets the bounds of `item`, `None` to take it out of the tree.
+    pub fn set(&mut self, item: usize, bounds: Option<Aabb>) {
+        if item >= self.items.len() {
+            self.items.resize(item + 1, None);
+            self.leaves.resize(item + 1, NONE);
+        }
+        if self.items[item] == bounds {
+            return;
+        }
+        self.items[item] = bounds;
+        match (self.leaves[item], bounds) {
+            (leaf, Some(bounds)) if leaf != NONE => {
+                self.nodes[leaf].bounds = bounds;
+                self.refit(self.nodes[leaf].parent);
+            }
+            _ => self.stale = true,
+        }
+    }
+
+    // Rebuilds the tree if needed, see above. Queries see the items as of the last update.
+    pub fn update(&mut self) {
+        if self.stale || self.inner_area > self.built_area * 2.0 {
+            self.rebuild();
+        }
+    }
+
+    pub fn rebuild(&mut self) {
+        self.nodes.clear();
+        self.leaves.fill(NONE);
+        self.inner_area = 0.0;
+        let mut items: Vec<(usize, Aabb)> =
+            self.items.iter().enumerate().filter_map(|(item, bounds)| Some((item, (*bounds)?))).collect();
+        if !items.is_empty() {
+            self.build(&mut items, NONE);
+        }
+        self.built_area = self.inner_area;
+        self.stale = false;
+    }
+
+    // Splits the items in half along the axis their centers spread furthest on, top-down.
+    // Returns the node.
+    fn build(&mut self, items: &mut [(usize, Aabb)], parent: usize) -> usize {
+        let index = self.nodes.len();
+        if let [(item, bounds)] = *items {
+            self.nodes.push(Node {
+                bounds,
+                parent,
+                kind: Kind::Leaf(item),
+            });
+            self.leaves[item] = index;
+            return index;
+        }
+
+        let bounds = items.iter().skip(1).fold(items[0].1, |bounds, (_, b)| bounds.union(b));
+        self.nodes.push(Node {
+            bounds,
+            parent,
+            kind: Kind::Inner(NONE, NONE),
+        });
+        self.inner_area += bounds.half_area();
+
+        let centers = Aabb::from_points(items.iter().map(|(_, b)| b.center())).unwrap();
+        let spread = centers.extents();
+        let axis = if spread.x >= spread.y && spread.x >= spread.z {
+            0
+        } else if spread.y >= spread.z {
+            1
+        } else {
+            2
+        };
+        let middle = items.len() / 2;
+        items.select_nth_unstable_by(middle, |(_, a), (_, b)| {
+            a.center()[axis].partial_cmp(&b.center()[axis]).unwrap_or(Ordering::Equal)
+        });
+        let (left, right) = items.split_at_mut(middle);
+        let left = self.build(left, index);
+        let right = self.build(right, index);
+        self.nodes[index].kind = Kind::Inner(left, right);
+        index
+    }
+
+    // Fits the boxes from `node` up to the root around their children again.
+    fn refit(&mut self, mut node: usize) {
+        while node != NONE {
+            let bounds = match self.nodes[node].kind {
+                Kind::Inner(left, right) => self.nodes[left].bounds.union(&self.nodes[right].bounds),
+                Kind::Leaf(_) => unreachable!("leaves have no children"),
+            };
+            let old = self.nodes[node].bounds;
+            if bounds == old {
+                // Nothing above changes either.
+                return;
+            }
+            self.inner_area += bounds.half_area() - old.half_area();
+            self.nodes[node].bounds = bounds;
+            node = self.nodes[node].parent;
+        }
+    }
+
+    // The bounds of `item` as last set.
+    pub fn bounds(&self, item: usize) -> Option<Aabb> {
+        self.items.get(item).copied().flatten()
+    }
+
+    // Calls `found` for every item whose bounds may be inside the frustum.
+    pub fn query_frustum(&self, frustum: &Frustum, mut found: impl FnMut(usize)) {
+        let mut stack = self.root().into_iter().collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if !frustum.intersects_aabb(&node.bounds) {
+                continue;
+            }
+            match node.kind {
+                Kind::Leaf(item) => found(item),
+                // Everything below a node inside the frustum is inside, no need to test it.
+                Kind::Inner(..) if frustum.contains_aabb(&node.bounds) => self.leaves_below(node, &mut found),
+                Kind::Inner(left, right) => stack.extend([left, right]),
+            }
+        }
+    }
+
+    // Calls `found` for every item whose bounds intersect `bounds`.
+    pub fn query_aabb(&self, bounds: &Aabb, mut found: impl FnMut(usize)) {
+        let mut stack = self.root().into_iter().collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if !node.bounds.intersects(bounds) {
+                continue;
+            }
+            match node.kind {
+                Kind::Leaf(item) => found(item),
+                Kind::Inner(left, right) => stack.extend([left, right]),
+            }
+        }
+    }
+
+    // The nearest item accepted by `filter` whose bounds the ray hits, and the distance to them.
+    pub fn raycast(&self, ray: &Ray, filter: impl Fn(usize) -> bool) -> Option<(usize, f32)> {
+        let mut nearest: Option<(usize, f32)> = None;
+        let mut stack = self.root().into_iter().collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let distance = match ray.intersect_aabb(&node.bounds) {
+                Some(distance) if nearest.is_none_or(|(_, nearest)| distance < nearest) => distance,
+                _ => continue,
+            };
+            match node.kind {
+                Kind::Leaf(item) if filter(item) => nearest = Some((item, distance)),
+                Kind::Leaf(_) => {}
+                Kind::Inner(left, right) => stack.extend([left, right]),
+            }
+        }
+        nearest
+    }
+
+    // Calls `visit` with the box, depth and leaf flag of every node down to `max_depth`, for
+    // drawing the tree.
+    pub fn visit_nodes(&self, max_depth: usize, mut visit: impl FnMut(&Aabb, usize, bool)) {
+        let mut stack: Vec<(usize, usize)> = self.root().map(|root| (root, 0)).into_iter().collect();
+        while let Some((node, depth)) = stack.pop() {
+            let node = &self.nodes[node];
+            visit(&node.bounds, depth, matches!(node.kind, Kind::Leaf(_)));
+            if let Kind::Inner(left, right) = node.kind {
+                if depth < max_depth {
+                    stack.extend([(left, depth + 1), (right, depth + 1)]);
+                }
+            }
+        }
+    }
+
+    fn root(&self) -> Option<usize> {
+        (!self.nodes.is_empty()).then_some(0)
+    }
+
+    fn leaves_below(&self, node: &Node, found: &mut impl FnMut(usize)) {
+        match node.kind {
+            Kind::Leaf(item) => found(item),
+            Kind::Inner(left, right) => {
+                self.leaves_below(&self.nodes[left], found);
+                self.leaves_below(&self.nodes[right], found);
+            }
+        }
+    }
+}
+
+impl Default for Bvh {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::rng::Rng;
+    use cgmath::{Point3, Vector3};
+
+    fn random_box(rng: &Rng) -> Aabb {
+        let min = Point3::new(rng.range(-50.0, 50.0), rng.range(-50.0, 50.0), rng.range(-50.0, 50.0));
+        let size = Point3::new(rng.range(0.0, 4.0), rng.range(0.0, 4.0), rng.range(0.0, 4.0));
+        Aabb::new(min, Point3::new(min.x + size.x, min.y + size.y, min.z + size.z))
+    }
+
+    // A camera in the middle of the boxes, so some are in front, some behind, some cut by planes.
+    fn frustum() -> Frustum {
+        let mut camera = Camera::new(16.0 / 9.0);
+        camera.eye = Point3::new(-5.0, 3.0, 10.0);
+        camera.target = Point3::new(10.0, -2.0, -20.0);
+        camera.zfar = 60.0;
+        Frustum::from_matrix(&camera.build_view_projection_matrix())
+    }
+
+    fn culled(bvh: &Bvh, frustum: &Frustum) -> Vec<usize> {
+        let mut found = Vec::new();
+        bvh.query_frustum(frustum, |item| found.push(item));
+        found.sort_unstable();
+        found
+    }
+
+    fn brute_force(boxes: &[Option<Aabb>], frustum: &Frustum) -> Vec<usize> {
+        (0..boxes.len()).filter(|&i| boxes[i].is_some_and(|bounds| frustum.intersects_aabb(&bounds))).collect()
+    }
+
+    #[test]
+    fn culling_matches_testing_every_box() {
+        let (rng, frustum) = (Rng::new(7), frustum());
+        let boxes: Vec<Option<Aabb>> = (0..500).map(|_| Some(random_box(&rng))).collect();
+        let mut bvh = Bvh::new();
+        for (item, bounds) in boxes.iter().enumerate() {
+            bvh.set(item, *bounds);
+        }
+        bvh.update();
+
+        let expected = brute_force(&boxes, &frustum);
+        assert!(!expected.is_empty() && expected.len() < boxes.len());
+        assert_eq!(culled(&bvh, &frustum), expected);
+    }
+
+    #[test]
+    fn refitting_keeps_culling_exact() {
+        let (rng, frustum) = (Rng::new(11), frustum());
+        let mut boxes: Vec<Option<Aabb>> = (0..300).map(|_| Some(random_box(&rng))).collect();
+        let mut bvh = Bvh::new();
+        for (item, bounds) in boxes.iter().enumerate() {
+            bvh.set(item, *bounds);
+        }
+        bvh.rebuild();
+
+        // Small moves refit the leaves in place, without a rebuild.
+        for _ in 0..5 {
+            for (item, bounds) in boxes.iter_mut().enumerate() {
+                if rng.chance(0.3) {
+                    let moved = bounds.unwrap();
+                    let offset = Vector3::new(rng.range(-2.0, 2.0), rng.range(-2.0, 2.0), rng.range(-2.0, 2.0));
+                    *bounds = Some(Aabb::new(moved.min + offset, moved.max + offset));
+                    bvh.set(item, *bounds);
+                }
+            }
+            assert!(!bvh.stale);
+            assert_eq!(culled(&bvh, &frustum), brute_force(&boxes, &frustum));
+        }
+
+        // Moving an item across the scene refits all the boxes up to the root.
+        let far = Aabb::new(Point3::new(500.0, 500.0, 500.0), Point3::new(501.0, 501.0, 501.0));
+        let seen = brute_force(&boxes, &frustum)[0];
+        boxes[seen] = Some(far);
+        bvh.set(seen, boxes[seen]);
+        assert!(!culled(&bvh, &frustum).contains(&seen));
+        assert_eq!(culled(&bvh, &frustum), brute_force(&boxes, &frustum));
+        assert_eq!(bvh.nodes[0].bounds.max, far.max);
+
+        // That loosened the tree past the limit, so it is built again, with the same answer.
+        assert!(bvh.inner_area > bvh.built_area * 2.0);
+        bvh.update();
+        assert_eq!(bvh.inner_area, bvh.built_area);
+        assert_eq!(culled(&bvh, &frustum), brute_force(&boxes, &frustum));
+    }
+
+    #[test]
+    fn removed_items_are_not_found() {
+        let (rng, frustum) = (Rng::new(3), frustum());
+        let mut boxes: Vec<Option<Aabb>> = (0..200).map(|_| Some(random_box(&rng))).collect();
+        let mut bvh = Bvh::new();
+        for (item, bounds) in boxes.iter().enumerate() {
+            bvh.set(item, *bounds);
+        }
+        bvh.update();
+        for item in (0..boxes.len()).step_by(3) {
+            boxes[item] = None;
+            bvh.set(item, None);
+        }
+        bvh.update();
+        assert_eq!(culled(&bvh, &frustum), brute_force(&boxes, &frustum));
+    }
+}
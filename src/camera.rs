@@ -0,0 +1,131 @@
+use cgmath::{Matrix4, Point3, Vector3};
+use crate::layers::RenderLayers;
+
+// wgpu's normalized device coordinates have z in [0, 1], while cgmath builds
+// OpenGL style projection matrices with z in [-1, 1].
+// This matrix scales and translates z from the OpenGL range into the wgpu range.
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// A perspective camera looking from `eye` towards `target`.
+pub struct Camera {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub aspect: f32,
+    pub fovy: f32, // vertical field of view, in degrees.
+    pub znear: f32,
+    pub zfar: f32,
+    pub layers: RenderLayers, // Only renderables on one of these layers are drawn.
+    // Keep `aspect` matching the camera's viewport when the window or the viewport is resized.
+    // Turn off to stretch the image, or to manage the aspect ratio yourself.
+    pub auto_aspect: bool,
+}
+
+impl Camera {
+    pub fn new(aspect: f32) -> Camera {
+        Camera {
+            // Position the camera two units back.
+            // +z is out of the screen.
+            eye: (0.0, 0.0, 2.0).into(),
+            // Have it look at the origin.
+            target: (0.0, 0.0, 0.0).into(),
+            // Which way is "up".
+            up: Vector3::unit_y(),
+            aspect,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            layers: RenderLayers::ALL,
+            auto_aspect: true,
+        }
+    }
+
+    pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
+        // Moves the world to be at the position and rotation of the camera.
+        let view = Matrix4::look_at_rh(self.eye, self.target, self.up);
+        // Warps the scene to give the effect of depth.
+        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+}
+
+// The camera matrix as it is laid out in the uniform buffer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+    eye: [f32; 4], // w is unused.
+}
+
+impl CameraUniform {
+    pub fn new() -> Self {
+        use cgmath::SquareMatrix;
+        Self {
+            view_proj: Matrix4::identity().into(),
+            eye: [0.0; 4],
+        }
+    }
+
+    pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_proj = camera.build_view_projection_matrix().into();
+        self.eye = camera.eye.to_homogeneous().into();
+    }
+
+    // For views that are not a plain `Camera`, like the mirrored ones of mirrors.rs.
+    pub fn set_view_proj(&mut self, view_proj: Matrix4<f32>, eye: Point3<f32>) {
+        self.view_proj = view_proj.into();
+        self.eye = eye.to_homogeneous().into();
+    }
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A rectangle in normalized surface coordinates.
+/// (0, 0) is the top-left corner of the surface and (1, 1) the bottom-right corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    /// The whole surface.
+    pub const FULL: Rect = Rect {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    // Converts the rectangle to pixels of a `surface_width` x `surface_height` surface,
+    // clamped so that it never extends outside of the surface.
+    // Returns (x, y, width, height).
+    pub fn to_pixels(self, surface_width: u32, surface_height: u32) -> (u32, u32, u32, u32) {
+        let (w, h) = (surface_width as f32, surface_height as f32);
+        let left = (self.x * w).round().clamp(0.0, w);
+        let top = (self.y * h).round().clamp(0.0, h);
+        let right = ((self.x + self.width) * w).round().clamp(left, w);
+        let bottom = ((self.y + self.height) * h).round().clamp(top, h);
+        (left as u32, top as u32, (right - left) as u32, (bottom - top) as u32)
+    }
+}
+
+/// Identifies a camera registered with `GFX::add_camera`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CameraId(pub(crate) usize);
@@ -0,0 +1,360 @@
+use cgmath::Point3;
+use windows::Win32::UI::Input::KeyboardAndMouse::{VK_C, VK_END, VK_HOME, VK_LEFT, VK_RIGHT, VK_SPACE};
+
+use crate::camera::Camera;
+use crate::game::Event;
+use crate::gfx::GFX;
+use crate::layers::RenderLayers;
+use crate::spline::Spline;
+
+// Camera paths
+//======================
+// Fly-throughs: the camera moves along a spline (spline.rs) while looking at keyframed targets,
+// with a keyframed field of view and speed:
+//
+//     let path = CameraPath::new(Spline::catmull_rom(&points, false))
+//         .with_target(0.0, statue)
+//         .with_target(0.6, gate)
+//         .with_speed(0.0, 2.0)
+//         .with_speed(0.5, 6.0)
+//         .with_fov(1.0, 30.0);
+//     let mut rig = CameraRig::new(path);
+//     ...
+//     rig.update(dt, gfx.camera_mut(camera));
+//
+// Keys are placed along the path, from 0 at its start to 1 at its end, and interpolated linearly
+// in between. Without target keys the camera looks along the path. Speeds are in world units per
+// second; the time it takes to get from one point to the next follows from them, and is tabulated
+// when the path is built, so the rig can jump to any time and knows how long the path takes.
+//
+// For benchmarks, advance the rig by a fixed step instead of the frame time, so every run renders
+// the same frames whatever the frame rate.
+//
+// The rig's keys, while it is fed events: Space plays and pauses, Left and Right scrub a second
+// back and forth, Home and End jump to the start and the end, C toggles cinematic mode, which
+// hides the gizmos (`RenderLayers::GIZMOS`) from the camera while the rig plays.
+
+// Steps of the table from distances to times.
+const TIME_SAMPLES: usize = 256;
+// How far Left and Right scrub, in seconds.
+const SCRUB_STEP: f32 = 1.0;
+// Slower speeds would make the path take forever.
+const MIN_SPEED: f32 = 0.01;
+
+/// Where the camera is, what it looks at and how wide, at a time of a path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPose {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub fovy: f32, // In degrees, like `Camera::fovy`.
+}
+
+/// A spline with keyframes for the camera following it, see above.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    spline: Spline,
+    targets: Vec<(f32, Point3<f32>)>, // Sorted by where along the path, from 0 to 1.
+    fovs: Vec<(f32, f32)>,
+    speeds: Vec<(f32, f32)>,
+    times: Vec<f32>, // The time at `TIME_SAMPLES + 1` evenly spaced distances along the path.
+}
+
+impl CameraPath {
+    // At 45 degrees and 1 unit per second, looking along the path, until keys are added.
+    pub fn new(spline: Spline) -> CameraPath {
+        let mut path = CameraPath {
+            spline,
+            targets: Vec::new(),
+            fovs: Vec::new(),
+            speeds: Vec::new(),
+            times: Vec::new(),
+        };
+        path.build_times();
+        path
+    }
+
+    pub fn with_target(mut self, at: f32, target: Point3<f32>) -> CameraPath {
+        insert_key(&mut self.targets, at, target);
+        self
+    }
+
+    pub fn with_fov(mut self, at: f32, fovy: f32) -> CameraPath {
+        insert_key(&mut self.fovs, at, fovy);
+        self
+    }
+
+    pub fn with_speed(mut self, at: f32, speed: f32) -> CameraPath {
+        insert_key(&mut self.speeds, at, speed.max(MIN_SPEED));
+        self.build_times();
+        self
+    }
+
+    pub fn spline(&self) -> &Spline {
+        &self.spline
+    }
+
+    // In seconds, from the start to the end of the path.
+    pub fn duration(&self) -> f32 {
+        *self.times.last().expect("built with the path")
+    }
+
+    // Where along the path the camera is at `time`, from 0 to 1.
+    pub fn progress_at(&self, time: f32) -> f32 {
+        let time = time.clamp(0.0, self.duration());
+        let i = self.times.partition_point(|&t| t < time).clamp(1, TIME_SAMPLES);
+        let (a, b) = (self.times[i - 1], self.times[i]);
+        let fraction = if b > a { (time - a) / (b - a) } else { 0.0 };
+        ((i - 1) as f32 + fraction) / TIME_SAMPLES as f32
+    }
+
+    pub fn pose(&self, time: f32) -> CameraPose {
+        let progress = self.progress_at(time);
+        let t = self.spline.t_at_distance(progress * self.spline.length());
+        let eye = self.spline.position(t);
+        let target = match sample(&self.targets, progress, |a, b, t| a + (b - a) * t) {
+            Some(target) => target,
+            None => eye + self.spline.tangent(t),
+        };
+        CameraPose {
+            eye,
+            target,
+            fovy: sample(&self.fovs, progress, lerp).unwrap_or(45.0),
+        }
+    }
+
+    // Integrates the time to cross every step of the path at the speed keyed there.
+    fn build_times(&mut self) {
+        let step = self.spline.length() / TIME_SAMPLES as f32;
+        let speed = |progress: f32| sample(&self.speeds, progress, lerp).unwrap_or(1.0).max(MIN_SPEED);
+        let mut times = Vec::with_capacity(TIME_SAMPLES + 1);
+        let mut time = 0.0;
+        times.push(time);
+        for i in 0..TIME_SAMPLES {
+            // At the speed in the middle of the step.
+            time += step / speed((i as f32 + 0.5) / TIME_SAMPLES as f32);
+            times.push(time);
+        }
+        self.times = times;
+    }
+}
+
+fn insert_key<T>(keys: &mut Vec<(f32, T)>, at: f32, value: T) {
+    let at = at.clamp(0.0, 1.0);
+    let i = keys.partition_point(|(key, _)| *key <= at);
+    keys.insert(i, (at, value));
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// The keys interpolated at `at`, held before the first and after the last. `None` without keys.
+fn sample<T: Copy>(keys: &[(f32, T)], at: f32, lerp: impl Fn(T, T, f32) -> T) -> Option<T> {
+    let next = keys.partition_point(|(key, _)| *key <= at);
+    Some(match next {
+        0 => keys.first()?.1,
+        n if n == keys.len() => keys[n - 1].1,
+        n => {
+            let ((a_at, a), (b_at, b)) = (keys[n - 1], keys[n]);
+            lerp(a, b, (at - a_at) / (b_at - a_at).max(f32::EPSILON))
+        }
+    })
+}
+
+/// Moves a camera along a path, see above.
+pub struct CameraRig {
+    path: CameraPath,
+    time: f32,
+    pub playing: bool,
+    pub looping: bool,
+    pub cinematic: bool,
+    hidden: Option<RenderLayers>, // The camera's layers before cinematic mode hid the gizmos.
+}
+
+impl CameraRig {
+    pub fn new(path: CameraPath) -> CameraRig {
+        CameraRig {
+            path,
+            time: 0.0,
+            playing: true,
+            looping: false,
+            cinematic: true,
+            hidden: None,
+        }
+    }
+
+    pub fn path(&self) -> &CameraPath {
+        &self.path
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    // Whether a path that doesn't loop got to its end, e.g. to end a benchmark.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.path.duration()
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        let duration = self.path.duration();
+        self.time = if self.looping && duration > 0.0 { time.rem_euclid(duration) } else { time.clamp(0.0, duration) };
+    }
+
+    // Moves along the path while playing, and puts the camera where the path is now.
+    pub fn update(&mut self, dt: f32, camera: &mut Camera) {
+        if self.playing {
+            self.seek(self.time + dt);
+        }
+        let pose = self.path.pose(self.time);
+        camera.eye = pose.eye;
+        camera.target = pose.target;
+        camera.fovy = pose.fovy;
+        // Hide the gizmos while playing in cinematic mode, and give them back otherwise.
+        match (self.cinematic && self.playing, self.hidden) {
+            (true, None) => {
+                self.hidden = Some(camera.layers);
+                camera.layers = camera.layers.without(RenderLayers::GIZMOS);
+            }
+            (false, Some(layers)) => {
+                camera.layers = layers;
+                self.hidden = None;
+            }
+            _ => {}
+        }
+    }
+
+    // The scrubbing keys, see above. Returns true if the event was one of them.
+    pub fn on_event(&mut self, event: &Event) -> bool {
+        let key = match *event {
+            Event::KeyPressed(key) => key,
+            _ => return false,
+        };
+        match key {
+            VK_SPACE => self.playing = !self.playing,
+            VK_LEFT => self.seek(self.time - SCRUB_STEP),
+            VK_RIGHT => self.seek(self.time + SCRUB_STEP),
+            VK_HOME => self.time = 0.0,
+            VK_END => self.time = self.path.duration(),
+            VK_C => self.cinematic = !self.cinematic,
+            _ => return false,
+        }
+        true
+    }
+}
+
+
+// Fly-through demo
+//======================
+
+// Once around the pentagon and up over it, looking at it, then into a close-up: the path slows down
+// and narrows the view at the end. The rig moves by the update ticks, so runs with the same
+// `--seed` and `--replay` render the same frames, and the demo quits at the end of the path, e.g.
+// for `--metrics`. While paused, the path is drawn with the debug lines.
+pub struct FlyThrough {
+    rig: CameraRig,
+    pending: f32, // Game time since the last frame moved the camera.
+}
+
+impl FlyThrough {
+    pub fn new() -> FlyThrough {
+        let points: Vec<Point3<f32>> = (0..7)
+            .map(|i| {
+                let angle = i as f32 / 6.0 * std::f32::consts::TAU;
+                let radius = 3.0 - 1.8 * i as f32 / 6.0;
+                Point3::new(angle.sin() * radius, 0.2 + 0.4 * i as f32 / 6.0, angle.cos() * radius)
+            })
+            .collect();
+        let pentagon = Point3::new(0.0, 0.0, 0.0);
+        let path = CameraPath::new(Spline::catmull_rom(&points, false))
+            .with_target(0.0, pentagon)
+            .with_speed(0.0, 2.0)
+            .with_speed(0.7, 2.0)
+            .with_speed(1.0, 0.5)
+            .with_fov(0.7, 45.0)
+            .with_fov(1.0, 25.0);
+        tracing::info!("Fly-through: {:.1}s", path.duration());
+        FlyThrough {
+            rig: CameraRig::new(path),
+            pending: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.pending += dt;
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        let camera = gfx.main_camera();
+        self.rig.update(std::mem::take(&mut self.pending), gfx.camera_mut(camera));
+        if !self.rig.playing {
+            self.rig.path().spline().debug_draw(gfx.debug_draw(), [0.3, 0.8, 1.0, 1.0], false);
+        }
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        if self.rig.on_event(event) && !self.rig.playing {
+            tracing::info!("Fly-through at {:.1}s", self.rig.time());
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.rig.is_finished()
+    }
+}
+
+impl Default for FlyThrough {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn straight(length: f32) -> Spline {
+        Spline::catmull_rom(&[Point3::new(0.0, 0.0, 0.0), Point3::new(length, 0.0, 0.0)], false)
+    }
+
+    #[test]
+    fn the_time_follows_from_the_speeds() {
+        let path = CameraPath::new(straight(4.0)).with_speed(0.0, 2.0);
+        assert!((path.duration() - 2.0).abs() < 1e-3);
+        assert!((path.progress_at(1.0) - 0.5).abs() < 1e-3);
+        // Twice as slow over the second half.
+        let path = CameraPath::new(straight(4.0)).with_speed(0.5, 2.0).with_speed(0.5, 1.0);
+        assert!((path.duration() - 3.0).abs() < 0.05);
+        assert!((path.progress_at(1.0) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn poses_interpolate_the_keys() {
+        let path = CameraPath::new(straight(4.0)).with_fov(0.0, 60.0).with_fov(1.0, 30.0);
+        let pose = path.pose(path.duration() / 2.0);
+        assert!((pose.fovy - 45.0).abs() < 0.1);
+        assert!((pose.eye.x - 2.0).abs() < 1e-2);
+        // Without targets, along the path.
+        assert!(pose.target.x > pose.eye.x);
+        let target = Point3::new(0.0, 5.0, 0.0);
+        assert_eq!(path.with_target(0.3, target).pose(0.0).target, target);
+    }
+
+    #[test]
+    fn the_rig_moves_the_camera_and_hides_the_gizmos_while_playing() {
+        let mut rig = CameraRig::new(CameraPath::new(straight(4.0)));
+        let mut camera = Camera::new(1.0);
+        let layers = camera.layers;
+        rig.update(1.0, &mut camera);
+        assert!((camera.eye.x - 1.0).abs() < 1e-2);
+        assert!(!camera.layers.intersects(RenderLayers::GIZMOS));
+        assert!(rig.on_event(&Event::KeyPressed(VK_SPACE)));
+        rig.update(1.0, &mut camera);
+        assert_eq!(camera.layers, layers);
+        assert!(rig.on_event(&Event::KeyPressed(VK_END)));
+        assert!(rig.is_finished());
+        rig.looping = true;
+        rig.seek(5.0);
+        assert!((rig.time() - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,107 @@
+use std::num::NonZeroU8;
+
+use crate::gfx::GfxOptions;
+
+// Capabilities
+//======================
+// Not every adapter supports everything the settings ask for: GL and older drivers lack timestamp
+// queries and anisotropic filtering, have smaller limits, and wgpu only guarantees 1 and 4 samples
+// per pixel. Rather than failing when the device or a pipeline is created, `GFX::new` checks the
+// settings against the adapter once, turns off (or lowers) what doesn't fit with a warning in the
+// log, and keeps the result for the game to read, see `GFX::capabilities`.
+
+/// What the graphics actually use, after checking the settings against the adapter.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    // Samples per pixel, 1 or 4.
+    pub msaa_samples: u32,
+    // Anisotropic filtering of streamed textures, `None` when off.
+    pub anisotropy: Option<NonZeroU8>,
+    // GPU pass times, see gpu_timer.rs.
+    pub timestamp_queries: bool,
+    // Counting the visible fragments of renderables and boxes, see occlusion_queries.rs.
+    pub occlusion_queries: bool,
+    // Width and height of the largest 2D texture; larger images are scaled down when loaded,
+    // see texture.rs and streaming.rs.
+    pub max_texture_size: u32,
+    pub compute_shaders: bool,
+    // Hiding what last frame's depth covers, see occlusion.rs. Needs compute shaders and indirect
+    // draws that start at any instance.
+    pub occlusion_culling: bool,
+    // The limits the device was requested with.
+    pub limits: wgpu::Limits,
+}
+
+impl Capabilities {
+    pub fn new(adapter: &wgpu::Adapter, options: &GfxOptions) -> Capabilities {
+        let downlevel = adapter.get_downlevel_properties();
+        // Ask for the WebGPU limits where the adapter is compliant, otherwise for the WebGL 2 ones,
+        // and for textures as large as the adapter allows either way.
+        let limits = if downlevel.is_webgpu_compliant() {
+            wgpu::Limits::default()
+        } else {
+            tracing::warn!("The adapter is not WebGPU compliant ({:?}), using lower limits", downlevel.flags);
+            wgpu::Limits::downlevel_webgl2_defaults()
+        }
+        .using_resolution(adapter.limits());
+
+        let msaa_samples = match options.msaa_samples.max(1) {
+            1 => 1,
+            4 => 4,
+            samples => {
+                tracing::warn!("{} samples per pixel are not portable, using 4", samples);
+                4
+            }
+        };
+
+        let anisotropy = match options.anisotropy {
+            0 | 1 => None,
+            _ if !downlevel.flags.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING) => {
+                tracing::warn!("The adapter has no anisotropic filtering, turning it off");
+                None
+            }
+            // Powers of two up to 16 are valid.
+            requested => NonZeroU8::new(requested.min(16).next_power_of_two().min(16)),
+        };
+
+        let timestamp_queries = adapter.features().contains(wgpu::Features::TIMESTAMP_QUERY);
+        if !timestamp_queries {
+            tracing::info!("The adapter has no timestamp queries, GPU pass times are unavailable");
+        }
+
+        let occlusion_queries = adapter.features().contains(wgpu::Features::PIPELINE_STATISTICS_QUERY);
+        if !occlusion_queries {
+            tracing::info!("The adapter has no pipeline statistics queries, occlusion queries are unavailable");
+        }
+
+        let compute_shaders = downlevel.flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        if !compute_shaders {
+            tracing::warn!("The adapter has no compute shaders");
+        }
+
+        let occlusion_culling = options.occlusion_culling
+            && compute_shaders
+            && adapter.features().contains(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+        if options.occlusion_culling && !occlusion_culling {
+            tracing::warn!("The adapter can't cull occluded objects on the GPU, turning occlusion culling off");
+        }
+
+        Capabilities {
+            msaa_samples,
+            anisotropy,
+            timestamp_queries,
+            occlusion_queries,
+            max_texture_size: limits.max_texture_dimension_2d,
+            compute_shaders,
+            occlusion_culling,
+            limits,
+        }
+    }
+
+    // The features to request the device with: all that any settings may use and the adapter has,
+    // as the device may be shared by several GFX with different settings, see gfx_context.rs.
+    pub fn device_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+        adapter.features()
+            & (wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::INDIRECT_FIRST_INSTANCE | wgpu::Features::PIPELINE_STATISTICS_QUERY)
+    }
+}
@@ -0,0 +1,140 @@
+use crate::texture::Texture;
+
+// Clearing
+//======================
+// Every pass decides how its targets start out: cleared to a value, or with what earlier passes
+// drew. GFX clears the surface to its clear color once per frame (`GFX::set_clear_color`), then
+// each camera applies its own `ClearSettings` (`GFX::set_camera_clear`) to its region only:
+//
+//     gfx.set_camera_clear(minimap, ClearSettings::color(wgpu::Color::BLACK));  // A black box.
+//     gfx.set_camera_clear(overlay, ClearSettings::KEEP);  // Drawn into the main camera's depth.
+//
+// Load operations always cover the whole attachment, so a camera with a viewport smaller than the
+// surface has its color cleared by drawing a triangle over the viewport instead (`ClearQuad`).
+// Depth is cleared for the whole attachment: the cameras drawn earlier are done with it.
+//
+// Render graph nodes get the settings configured for them with `RenderGraph::set_clear` in
+// `NodeContext::clear`, and use `color_ops` and `depth_ops` when they begin their passes.
+
+/// How a pass starts out with its color and depth targets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClearSettings {
+    pub color: Option<wgpu::Color>, // `None` keeps the color.
+    pub depth: Option<f32>, // `None` keeps the depth.
+}
+
+impl ClearSettings {
+    // Keeps color and depth, e.g. to draw on top of another camera.
+    pub const KEEP: ClearSettings = ClearSettings {
+        color: None,
+        depth: None,
+    };
+
+    // Clears the color to `color` and the depth to the far plane.
+    pub fn color(color: wgpu::Color) -> ClearSettings {
+        ClearSettings {
+            color: Some(color),
+            depth: Some(1.0),
+        }
+    }
+
+    pub fn with_depth(mut self, depth: Option<f32>) -> ClearSettings {
+        self.depth = depth;
+        self
+    }
+
+    pub fn color_ops(&self) -> wgpu::Operations<wgpu::Color> {
+        wgpu::Operations {
+            load: self.color.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear),
+            store: true,
+        }
+    }
+
+    pub fn depth_ops(&self) -> wgpu::Operations<f32> {
+        wgpu::Operations {
+            load: self.depth.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear),
+            store: true,
+        }
+    }
+}
+
+// Cameras draw over what is there, on fresh depth.
+impl Default for ClearSettings {
+    fn default() -> Self {
+        ClearSettings {
+            color: None,
+            depth: Some(1.0),
+        }
+    }
+}
+
+/// Clears the color of the current viewport and scissor rect of a scene pass.
+pub struct ClearQuad {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ClearQuad {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) -> ClearQuad {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Clear Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("clear.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Clear Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Clear Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    // The shader outputs 1, so this writes the blend constant: the clear color,
+                    // without a uniform buffer per camera.
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Constant,
+                            dst_factor: wgpu::BlendFactor::Zero,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Constant,
+                            dst_factor: wgpu::BlendFactor::Zero,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // The scene pass has a depth target, which the clear leaves alone.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: samples,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+        ClearQuad { pipeline }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, color: wgpu::Color) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_blend_constant(color);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,14 @@
+// Covers the viewport with the blend constant, see clear.rs.
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    // A triangle over the whole viewport: (-1, -1), (3, -1) and (-1, 3).
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_main() -> [[location(0)]] vec4<f32> {
+    // Multiplied by the blend constant.
+    return vec4<f32>(1.0);
+}
@@ -0,0 +1,299 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::life::LifeRule;
+use crate::reaction_diffusion::ReactionDiffusionSettings;
+
+// Command line
+//======================
+// Lets benchmarks and bug reports vary the configuration without code changes:
+//
+//     learn-wgpu --size 1280x720 --backend dx12 --adapter 1 --no-vsync --frames 500
+//
+// Options that are given override the configuration file, see config.rs.
+
+pub const USAGE: &str = "\
+Usage: learn-wgpu [options]
+
+Options:
+    --size <W>x<H>       client area size, e.g. 1280x720
+    --fullscreen         borderless fullscreen on the primary monitor
+    --winit              create the window through winit (needs the winit feature)
+    --backend <name>     vulkan, dx12, dx11, metal, gl or all
+    --adapter <index>    adapter to use, in the order wgpu lists them
+    --list-adapters      print the adapters with their features and limits, then exit
+    --no-vsync           present frames immediately
+    --hdr                render in HDR with automatic exposure, see exposure.rs
+    --bloom              with HDR, add a glow around the brightest parts, see bloom.rs
+    --frames <count>     exit after rendering this many frames
+    --log-file <path>    also write the log to this file (levels come from RUST_LOG)
+    --metrics <path>     record per-frame metrics into a .csv or .json file, see metrics.rs
+    --latency            measure the input to GPU latency of every frame and log it on exit,
+                         see latency.rs
+    --record <path>      record the session for --replay, see replay.rs
+    --replay <path>      play a recorded session instead of taking input
+    --seed <number>      seed of the random number generator (default: from the clock)
+    --scene <path>       load a .ron or .json scene, see scene.rs
+    --script <path>      run a rhai script, see scripting.rs
+    --nbody <count>      simulate and draw this many bodies pulling on each other, see nbody.rs
+    --nbody-untiled      with --nbody, compute the forces without workgroup memory, to compare
+    --boids <count>      simulate and draw a flock of this many boids, see boids.rs
+    --life <W>x<H>       run a cellular automaton on a grid of this many cells, see life.rs
+    --life-rule <rule>   conway, highlife, seeds, daynight or e.g. B36/S23 (default: conway)
+    --life-rate <steps>  generations per second (default: 10)
+    --reaction-diffusion <preset>
+                         run a Gray-Scott simulation: coral, mitosis, maze, spots or waves,
+                         see reaction_diffusion.rs
+    --fluid              run a fluid simulation, stirred by dragging the mouse, see fluid.rs
+    --metaballs          add a few metaballs meshed with marching cubes, see marching_cubes.rs
+    --light-shafts       add a spot light behind the pentagon shining through a window and a fog;
+                         L switches it off and on, O takes the window out, see volumetrics.rs
+    --skinning           add an arm bent by two joints on the GPU, with its skeleton drawn over it;
+                         Space, Left, Right, Home, Comma and Period scrub its animation, J
+                         highlights a joint, I logs the pose, see skinning.rs
+    --particles          add a fire, smoke and sparks around the pentagon, B sets off an
+                         explosion, see particles.rs
+    --trail              add a ribbon trailing a point around the pentagon, see dynamic_mesh.rs
+    --gizmo              select renderables with the mouse and move (W), rotate (E) or scale (R)
+                         them with a gizmo, see gizmo.rs and selection.rs
+    --track              add a cube running along a spline around the pentagon, K switches between
+                         Catmull-Rom, Hermite and Bezier, see spline.rs
+    --camera-path        fly the camera around the pentagon and quit at the end; Space, Left,
+                         Right, Home and End scrub it, C shows the gizmos, see camera_path.rs
+    --terrain            add tiles of Perlin, simplex and Worley noise terrain, see noise.rs
+    --monitors           add two monitors beside the pentagon showing texture cameras; M freezes,
+                         P pixelates and X unplugs them, see render_targets.rs
+    --tilemap <path>     draw a .tmx or .json map made with Tiled behind the scene, e.g.
+                         res/maps/demo.tmx, see tilemap.rs
+    --ui                 show a menu of nine-slice panels setting the UI scale, see ui.rs
+    --day-length <secs>  light the scene with a sky through a day and night of this many seconds,
+                         see world_time.rs
+    --trace <dir>        record a wgpu API trace into this directory (needs wgpu's trace feature)
+    --golden <dir>       render the golden image scenes and compare them with the PNGs in <dir>,
+                         then exit, see golden.rs
+    --update-golden      with --golden, write the rendered images as the new references
+    --help               show this message";
+
+/// The settings given on the command line, `None` (or `false`) where not given.
+#[derive(Debug, Default)]
+pub struct CliOptions {
+    pub size: Option<(i32, i32)>,
+    pub fullscreen: bool,
+    pub winit: bool,
+    pub backend: Option<String>,
+    pub adapter: Option<usize>,
+    pub list_adapters: bool,
+    pub no_vsync: bool,
+    pub hdr: bool,
+    pub bloom: bool,
+    pub frames: Option<u64>,
+    pub log_file: Option<PathBuf>,
+    pub metrics: Option<PathBuf>,
+    pub latency: bool,
+    pub trace: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+    pub scene: Option<PathBuf>,
+    pub nbody: Option<u32>,
+    pub nbody_untiled: bool,
+    pub boids: Option<u32>,
+    pub life: Option<(u32, u32)>,
+    pub life_rule: Option<LifeRule>,
+    pub life_rate: Option<f32>,
+    pub reaction_diffusion: Option<ReactionDiffusionSettings>,
+    pub fluid: bool,
+    pub metaballs: bool,
+    pub light_shafts: bool,
+    pub skinning: bool,
+    pub particles: bool,
+    pub trail: bool,
+    pub gizmo: bool,
+    pub track: bool,
+    pub camera_path: bool,
+    pub terrain: bool,
+    pub monitors: bool,
+    pub tilemap: Option<PathBuf>,
+    pub ui: bool,
+    pub day_length: Option<f32>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub golden: Option<PathBuf>,
+    pub update_golden: bool,
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::Invalid(message) => write!(f, "{}\n\n{}", message, USAGE),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+// Parses the arguments, without the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<CliOptions, CliError> {
+    let mut options = CliOptions::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| CliError::Invalid(format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "--size" => {
+                let size = value("--size")?;
+                let size = parse_size(&size)
+                    .ok_or_else(|| CliError::Invalid(format!("invalid size '{}', expected e.g. 1280x720", size)))?;
+                options.size = Some(size);
+            }
+            "--fullscreen" => options.fullscreen = true,
+            "--winit" => options.winit = true,
+            "--backend" => {
+                let backend = value("--backend")?;
+                if parse_backend(&backend).is_none() {
+                    return Err(CliError::Invalid(format!("unknown backend '{}'", backend)));
+                }
+                options.backend = Some(backend);
+            }
+            "--adapter" => options.adapter = Some(parse_number(&value("--adapter")?)?),
+            "--list-adapters" => options.list_adapters = true,
+            "--no-vsync" => options.no_vsync = true,
+            "--hdr" => options.hdr = true,
+            "--bloom" => options.bloom = true,
+            "--frames" => options.frames = Some(parse_number(&value("--frames")?)?),
+            "--log-file" => options.log_file = Some(PathBuf::from(value("--log-file")?)),
+            "--metrics" => options.metrics = Some(PathBuf::from(value("--metrics")?)),
+            "--latency" => options.latency = true,
+            "--record" => options.record = Some(PathBuf::from(value("--record")?)),
+            "--replay" => options.replay = Some(PathBuf::from(value("--replay")?)),
+            "--seed" => options.seed = Some(parse_number(&value("--seed")?)?),
+            "--scene" => options.scene = Some(PathBuf::from(value("--scene")?)),
+            "--script" => options.script = Some(PathBuf::from(value("--script")?)),
+            "--nbody" => options.nbody = Some(parse_number(&value("--nbody")?)?),
+            "--nbody-untiled" => options.nbody_untiled = true,
+            "--boids" => options.boids = Some(parse_number(&value("--boids")?)?),
+            "--life" => {
+                let size = value("--life")?;
+                let (width, height) = parse_size(&size)
+                    .ok_or_else(|| CliError::Invalid(format!("invalid grid size '{}', expected e.g. 320x180", size)))?;
+                options.life = Some((width as u32, height as u32));
+            }
+            "--life-rule" => {
+                let rule = value("--life-rule")?;
+                options.life_rule =
+                    Some(LifeRule::parse(&rule).ok_or_else(|| CliError::Invalid(format!("unknown rule '{}'", rule)))?);
+            }
+            "--life-rate" => options.life_rate = Some(parse_number(&value("--life-rate")?)?),
+            "--fluid" => options.fluid = true,
+            "--metaballs" => options.metaballs = true,
+            "--light-shafts" => options.light_shafts = true,
+            "--skinning" => options.skinning = true,
+            "--particles" => options.particles = true,
+            "--trail" => options.trail = true,
+            "--gizmo" => options.gizmo = true,
+            "--track" => options.track = true,
+            "--camera-path" => options.camera_path = true,
+            "--terrain" => options.terrain = true,
+            "--monitors" => options.monitors = true,
+            "--tilemap" => options.tilemap = Some(PathBuf::from(value("--tilemap")?)),
+            "--ui" => options.ui = true,
+            "--day-length" => options.day_length = Some(parse_number(&value("--day-length")?)?),
+            "--reaction-diffusion" => {
+                let preset = value("--reaction-diffusion")?;
+                options.reaction_diffusion = Some(
+                    ReactionDiffusionSettings::preset(&preset)
+                        .ok_or_else(|| CliError::Invalid(format!("unknown reaction-diffusion preset '{}'", preset)))?,
+                );
+            }
+            "--trace" => options.trace = Some(PathBuf::from(value("--trace")?)),
+            "--golden" => options.golden = Some(PathBuf::from(value("--golden")?)),
+            "--update-golden" => options.update_golden = true,
+            "--help" | "-h" => return Err(CliError::Help),
+            _ => return Err(CliError::Invalid(format!("unknown option '{}'", arg))),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_size(size: &str) -> Option<(i32, i32)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    if width > 0 && height > 0 {
+        Some((width, height))
+    } else {
+        None
+    }
+}
+
+pub fn parse_backend(name: &str) -> Option<wgpu::Backends> {
+    match name.to_ascii_lowercase().as_str() {
+        "vulkan" => Some(wgpu::Backends::VULKAN),
+        "dx12" => Some(wgpu::Backends::DX12),
+        "dx11" => Some(wgpu::Backends::DX11),
+        "metal" => Some(wgpu::Backends::METAL),
+        "gl" => Some(wgpu::Backends::GL),
+        "all" => Some(wgpu::Backends::all()),
+        _ => None,
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, CliError> {
+    value
+        .parse()
+        .map_err(|_| CliError::Invalid(format!("'{}' is not a valid number", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<CliOptions, CliError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn invalid_message(result: Result<CliOptions, CliError>) -> String {
+        match result {
+            Err(CliError::Invalid(message)) => message,
+            other => panic!("expected an invalid option, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_args(&["--size", "1280x720"]).unwrap().size, Some((1280, 720)));
+        assert!(invalid_message(parse_args(&["--size", "0x5"])).contains("invalid size '0x5'"));
+        assert!(invalid_message(parse_args(&["--size", "axb"])).contains("invalid size 'axb'"));
+        assert!(invalid_message(parse_args(&["--size", "1280"])).contains("invalid size"));
+    }
+
+    #[test]
+    fn reports_a_missing_value() {
+        assert_eq!(invalid_message(parse_args(&["--frames"])), "--frames needs a value");
+        assert_eq!(invalid_message(parse_args(&["--no-vsync", "--size"])), "--size needs a value");
+    }
+
+    #[test]
+    fn reports_unknown_options_and_values() {
+        assert_eq!(invalid_message(parse_args(&["--frobnicate"])), "unknown option '--frobnicate'");
+        assert_eq!(invalid_message(parse_args(&["--backend", "glide"])), "unknown backend 'glide'");
+        assert_eq!(invalid_message(parse_args(&["--frames", "many"])), "'many' is not a valid number");
+        assert!(matches!(parse_args(&["--hdr", "--help"]), Err(CliError::Help)));
+    }
+
+    #[test]
+    fn parses_flags_and_values() {
+        let options = parse_args(&["--no-vsync", "--frames", "500", "--backend", "dx12", "--bloom"]).unwrap();
+        assert!(options.no_vsync && options.bloom && !options.hdr);
+        assert_eq!(options.frames, Some(500));
+        assert_eq!(options.backend.as_deref(), Some("dx12"));
+    }
+}
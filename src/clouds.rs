@@ -0,0 +1,593 @@
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::compute::ComputeKernel;
+use crate::reflection::ShaderReflection;
+use crate::uniform::UniformLayout;
+
+// Volumetric clouds
+//======================
+// A layer of clouds between two heights, marched through per pixel rather than drawn as geometry
+// (clouds.wgsl):
+//
+//     gfx.set_clouds(Some(CloudSettings::default().with_coverage(0.6)));
+//
+// The shapes come from a tiling volume of noise, generated once by a compute kernel
+// (clouds_noise.wgsl): Perlin-Worley noise for the billowing shapes, and Worley noise of rising
+// frequencies that erodes their edges. `coverage` decides how much of the noise becomes cloud and
+// `density` how opaque the clouds are. The wind moves the shapes, and the detail twice as fast.
+//
+// At every step of the march that finds cloud, a few more steps towards the sun find how much of
+// its light gets there (Beer-Lambert), which is scattered towards the camera by a Henyey-Greenstein
+// phase function: clouds glow around the sun, and their undersides are dark where they are thick.
+// The ambient color lights them from everywhere, more so at their tops.
+//
+// Marching is expensive, so the clouds are rendered at 1/`resolution` of the scene's size along
+// each axis, and every pixel starts its march a little further along the ray every frame. The
+// result is blended with last frame's, found where the clouds along the ray were on screen then
+// (temporal reprojection), which averages out the noise. `history_weight` is how much of last
+// frame is kept; 0 turns this off, and fast moving cameras smear less with lower weights.
+//
+// The march stops at the scene's depth, so buildings poke into low clouds. The clouds are blended
+// over the scene after its renderables and before the selection outline and lens flares, with HDR
+// into the scene target before the tonemapping. They don't write the depth. Upsampling doesn't
+// look at the depth, so the edges of objects in front of the clouds get a halo up to `resolution`
+// pixels wide.
+
+/// The format of the low resolution cloud targets: the scattered light, and the transmittance.
+pub const CLOUD_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const NOISE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const NOISE_SIZE: u32 = 64;
+
+/// The layer of clouds, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CloudSettings {
+    pub bottom: f32, // Height of the slab of clouds, along y.
+    pub top: f32,
+    pub coverage: f32, // From 0, a clear sky, to 1, overcast.
+    pub density: f32, // Extinction per world unit, inside the clouds.
+    pub shape_scale: f32, // World units the noise spans, for the shapes.
+    pub detail_scale: f32, // And for the eroding detail.
+    pub wind: Vector3<f32>, // In world units per second.
+    pub sun_direction: Vector3<f32>, // Towards the sun.
+    pub sun_color: [f32; 3],
+    pub ambient_color: [f32; 3],
+    pub steps: u32, // Of the march through the slab.
+    pub light_steps: u32, // Towards the sun, at every step in a cloud.
+    pub resolution: u32, // Scene pixels per cloud pixel, along each axis.
+    pub history_weight: f32, // How much of last frame is kept, see above.
+}
+
+impl Default for CloudSettings {
+    fn default() -> Self {
+        CloudSettings {
+            bottom: 1500.0,
+            top: 3500.0,
+            coverage: 0.5,
+            density: 0.02,
+            shape_scale: 6000.0,
+            detail_scale: 800.0,
+            wind: Vector3::new(10.0, 0.0, 3.0),
+            sun_direction: Vector3::new(0.4, 0.6, -0.5),
+            sun_color: [1.0, 0.96, 0.9],
+            ambient_color: [0.45, 0.5, 0.6],
+            steps: 48,
+            light_steps: 6,
+            resolution: 2,
+            history_weight: 0.9,
+        }
+    }
+}
+
+impl CloudSettings {
+    pub fn with_layer(mut self, bottom: f32, top: f32) -> CloudSettings {
+        self.bottom = bottom;
+        self.top = top;
+        self
+    }
+
+    pub fn with_coverage(mut self, coverage: f32) -> CloudSettings {
+        self.coverage = coverage;
+        self
+    }
+
+    pub fn with_wind(mut self, wind: Vector3<f32>) -> CloudSettings {
+        self.wind = wind;
+        self
+    }
+
+    pub fn with_sun(mut self, direction: Vector3<f32>, color: [f32; 3]) -> CloudSettings {
+        self.sun_direction = direction;
+        self.sun_color = color;
+        self
+    }
+}
+
+/// A camera the clouds are drawn for this frame.
+#[derive(Clone, Copy, Debug)]
+pub struct CloudCamera {
+    pub view_proj: Matrix4<f32>,
+    pub eye: Point3<f32>,
+    pub viewport: (u32, u32, u32, u32), // x, y, width and height in pixels of the scene target.
+}
+
+uniform_struct! {
+    struct CloudParams {
+        inverse_view_proj: Matrix4<f32>,
+        previous_view_proj: Matrix4<f32>,
+        eye: Vector4<f32>,
+        sun_direction: Vector4<f32>,
+        sun_color: Vector4<f32>,
+        ambient_color: Vector4<f32>,
+        wind_offset: Vector4<f32>,
+        viewport: Vector4<f32>,
+        shape_scale: f32,
+        detail_scale: f32,
+        steps: f32,
+        light_steps: f32,
+        history_weight: f32,
+        resolution: f32,
+    }
+}
+assert_uniform_size!(CloudParams, 256);
+
+uniform_struct! {
+    struct CompositeParams {
+        resolution: f32,
+    }
+}
+
+uniform_struct! {
+    struct VolumeParams {
+        seed: u32,
+    }
+}
+
+/// Renders the clouds for every camera, see above.
+pub struct Clouds {
+    march_layout: wgpu::BindGroupLayout, // Depends on the samples of the depth.
+    march_pipeline: wgpu::RenderPipeline,
+    composite_layout: wgpu::BindGroupLayout,
+    composite_pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    params_stride: u32,
+    params_capacity: usize, // In cameras.
+    composite_buffer: wgpu::Buffer,
+    noise: Option<wgpu::TextureView>, // Generated by the first `prepare`.
+    noise_sampler: wgpu::Sampler,
+    target_sampler: wgpu::Sampler,
+    // This frame's clouds and last frame's, swapped every frame.
+    targets: Vec<(wgpu::Texture, wgpu::TextureView)>,
+    target_size: (u32, u32),
+    current: usize,
+    march_bind_group: Option<wgpu::BindGroup>, // Made by `prepare`.
+    composite_bind_group: Option<wgpu::BindGroup>,
+    viewports: Vec<(f32, f32, f32, f32)>, // In the cloud targets, by camera.
+    previous_view_projs: Vec<Matrix4<f32>>, // Empty when there is no history to blend.
+    time: f32,
+    frame: u32,
+}
+
+impl Clouds {
+    // `format` and `samples` are those of the scene target, and of the depth.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) -> Clouds {
+        let (march_layout, march_pipeline) = create_march_pipeline(device, samples);
+        let (composite_layout, composite_pipeline) = create_composite_pipeline(device, format, samples);
+        let params_stride = device.limits().min_uniform_buffer_offset_alignment.max(CloudParams::SIZE as u32);
+        let params_capacity = 4;
+        let composite_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cloud Composite Params"),
+            size: CompositeParams::SIZE as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // The noise repeats across the sky.
+        let noise_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Cloud Noise Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let target_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Cloud Target Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Clouds {
+            march_layout,
+            march_pipeline,
+            composite_layout,
+            composite_pipeline,
+            params_buffer: create_params_buffer(device, params_stride, params_capacity),
+            params_stride,
+            params_capacity,
+            composite_buffer,
+            noise: None,
+            noise_sampler,
+            target_sampler,
+            targets: Vec::new(),
+            target_size: (0, 0),
+            current: 0,
+            march_bind_group: None,
+            composite_bind_group: None,
+            viewports: Vec::new(),
+            previous_view_projs: Vec::new(),
+            time: 0.0,
+            frame: 0,
+        }
+    }
+
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        (self.march_layout, self.march_pipeline) = create_march_pipeline(device, samples);
+        (self.composite_layout, self.composite_pipeline) = create_composite_pipeline(device, format, samples);
+        self.march_bind_group = None;
+        self.composite_bind_group = None;
+    }
+
+    // Game time in seconds, which moves the clouds with the wind.
+    pub fn set_time(&mut self, elapsed: f64) {
+        // Wrapped like the globals' time, to keep f32 precision in long sessions.
+        self.time = (elapsed % 3600.0) as f32;
+    }
+
+    // Uploads the parameters of every camera and makes this frame's bind groups, to be drawn with
+    // `draw` after the camera's scene pass into `depth`. `size` is that of the scene target.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &CloudSettings,
+        cameras: &[CloudCamera],
+        depth: &wgpu::TextureView,
+        size: (u32, u32),
+    ) {
+        if self.noise.is_none() {
+            self.noise = Some(generate_noise(device, queue));
+        }
+        let resolution = settings.resolution.max(1);
+        let target_size = (size.0.div_ceil(resolution).max(1), size.1.div_ceil(resolution).max(1));
+        if target_size != self.target_size || self.targets.is_empty() {
+            self.targets = (0..2).map(|_| create_target(device, target_size)).collect();
+            self.target_size = target_size;
+            // The old history is gone, and the new one is empty.
+            self.previous_view_projs.clear();
+        }
+        self.current = 1 - self.current;
+        if cameras.len() > self.params_capacity {
+            self.params_capacity = cameras.len().next_power_of_two();
+            self.params_buffer = create_params_buffer(device, self.params_stride, self.params_capacity);
+        }
+
+        let sun_direction = if settings.sun_direction.magnitude2() > 0.0 {
+            settings.sun_direction.normalize()
+        } else {
+            Vector3::unit_y()
+        };
+        let wind_offset = -settings.wind * self.time;
+        let top = settings.top.max(settings.bottom + 1.0);
+        // The golden ratio spreads the ray offsets of consecutive frames evenly.
+        let jitter = (self.frame as f32 * 0.618034).fract();
+        self.frame = self.frame.wrapping_add(1);
+        self.viewports.clear();
+        for (index, camera) in cameras.iter().enumerate() {
+            let (x, y, w, h) = camera.viewport;
+            let scale = resolution as f32;
+            let viewport = (x as f32 / scale, y as f32 / scale, (w as f32 / scale).ceil(), (h as f32 / scale).ceil());
+            self.viewports.push(viewport);
+            // A new camera, or the first frame, has no history to blend.
+            let previous = self.previous_view_projs.get(index).copied();
+            let history_weight = if previous.is_some() { settings.history_weight.clamp(0.0, 0.98) } else { 0.0 };
+            let params = CloudParams {
+                inverse_view_proj: camera.view_proj.invert().unwrap_or_else(Matrix4::identity),
+                previous_view_proj: previous.unwrap_or(camera.view_proj),
+                eye: Vector4::new(camera.eye.x, camera.eye.y, camera.eye.z, jitter),
+                sun_direction: sun_direction.extend(settings.coverage.clamp(0.01, 1.0)),
+                sun_color: Vector3::from(settings.sun_color).extend(settings.density.max(0.0)),
+                ambient_color: Vector3::from(settings.ambient_color).extend(settings.bottom),
+                wind_offset: wind_offset.extend(top),
+                viewport: Vector4::new(viewport.0, viewport.1, viewport.2, viewport.3),
+                shape_scale: settings.shape_scale.max(f32::EPSILON),
+                detail_scale: settings.detail_scale.max(f32::EPSILON),
+                steps: settings.steps.max(1) as f32,
+                light_steps: settings.light_steps.max(1) as f32,
+                history_weight,
+                resolution: scale,
+            };
+            let offset = index as u64 * self.params_stride as u64;
+            queue.write_buffer(&self.params_buffer, offset, &params.to_uniform_bytes());
+        }
+        self.previous_view_projs = cameras.iter().map(|camera| camera.view_proj).collect();
+        let composite = CompositeParams {
+            resolution: resolution as f32,
+        };
+        queue.write_buffer(&self.composite_buffer, 0, &composite.to_uniform_bytes());
+
+        // The depth view changes with the surface size, so these bind groups are made every frame.
+        let noise = self.noise.as_ref().expect("generated above");
+        let history = &self.targets[1 - self.current].1;
+        self.march_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cloud March Bind Group"),
+            layout: &self.march_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.params_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(CloudParams::SIZE as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(noise),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.noise_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(history),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&self.target_sampler),
+                },
+            ],
+        }));
+        self.composite_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cloud Composite Bind Group"),
+            layout: &self.composite_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.targets[self.current].1),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.target_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.composite_buffer.as_entire_binding(),
+                },
+            ],
+        }));
+    }
+
+    // Records the passes marching the clouds of camera `index` and blending them over its part of
+    // the scene, within its viewport and scissor rect (x, y, width, height in pixels).
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        index: usize,
+        viewport: (u32, u32, u32, u32),
+        scissor: (u32, u32, u32, u32),
+    ) {
+        let (march_bind_group, composite_bind_group, cloud_viewport) =
+            match (&self.march_bind_group, &self.composite_bind_group, self.viewports.get(index)) {
+                (Some(march), Some(composite), Some(viewport)) => (march, composite, *viewport),
+                _ => return,
+            };
+        {
+            // Other cameras' regions of the target are their history, so they are kept.
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Cloud March Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &self.targets[self.current].1,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            let (x, y, w, h) = cloud_viewport;
+            let (max_w, max_h) = (self.target_size.0 as f32, self.target_size.1 as f32);
+            render_pass.set_viewport(x, y, w.min(max_w - x), h.min(max_h - y), 0.0, 1.0);
+            render_pass.set_pipeline(&self.march_pipeline);
+            render_pass.set_bind_group(0, march_bind_group, &[index as u32 * self.params_stride]);
+            render_pass.draw(0..3, 0..1);
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Cloud Composite Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        let (x, y, w, h) = viewport;
+        let (sx, sy, sw, sh) = scissor;
+        render_pass.set_viewport(x as f32, y as f32, w as f32, h as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(sx, sy, sw, sh);
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// The tiling noise volume the clouds are shaped from.
+fn generate_noise(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureView {
+    let kernel = ComputeKernel::new(device, "Cloud Noise", include_str!("clouds_noise.wgsl"), "main").expect("built-in kernel is valid");
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Cloud Noise"),
+        size: wgpu::Extent3d {
+            width: NOISE_SIZE,
+            height: NOISE_SIZE,
+            depth_or_array_layers: NOISE_SIZE,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: NOISE_FORMAT,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Cloud Noise Params"),
+        contents: &VolumeParams { seed: 0 }.to_uniform_bytes(),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let bind_group = kernel
+        .bind_group(
+            device,
+            0,
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        )
+        .expect("bindings match the built-in kernel");
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Cloud Noise Encoder"),
+    });
+    kernel.dispatch_3d(&mut encoder, &[&bind_group], (4, 4, 4), (NOISE_SIZE, NOISE_SIZE, NOISE_SIZE));
+    queue.submit(std::iter::once(encoder.finish()));
+    view
+}
+
+fn create_target(device: &wgpu::Device, size: (u32, u32)) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Cloud Target"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: CLOUD_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
+
+// The depth is multisampled with MSAA; the shader reads its first sample either way.
+fn create_march_pipeline(device: &wgpu::Device, samples: u32) -> (wgpu::BindGroupLayout, wgpu::RenderPipeline) {
+    let wgsl = include_str!("clouds.wgsl");
+    // The same shader reads multisampled depth and plain depth, like lens_flare.rs.
+    let wgsl = if samples > 1 {
+        wgsl.to_string()
+    } else {
+        wgsl.replace("texture_depth_multisampled_2d", "texture_depth_2d")
+    };
+    let reflection = ShaderReflection::from_wgsl(&wgsl).expect("built-in shader is valid");
+    // Every camera has its own part of the parameters' buffer.
+    let mut entries = reflection.layout_entries(0);
+    for entry in &mut entries {
+        if let wgpu::BindingType::Buffer { has_dynamic_offset, .. } = &mut entry.ty {
+            *has_dynamic_offset = true;
+        }
+    }
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Cloud March Bind Group Layout"),
+        entries: &entries,
+    });
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Cloud March Shader"),
+        source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+    });
+    let pipeline = create_pipeline(device, "Cloud March", &layout, &shader, CLOUD_FORMAT, None, 1);
+    (layout, pipeline)
+}
+
+fn create_composite_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) -> (wgpu::BindGroupLayout, wgpu::RenderPipeline) {
+    let wgsl = include_str!("clouds_composite.wgsl");
+    let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+    let layout = reflection.create_bind_group_layout(device, Some("Cloud Composite Bind Group Layout"), 0);
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Cloud Composite Shader"),
+        source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+    });
+    // The scattered light adds up, the scene shows through by the transmittance, and the alpha of
+    // the scene is left alone.
+    let blend = wgpu::BlendState {
+        color: wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::SrcAlpha,
+            operation: wgpu::BlendOperation::Add,
+        },
+        alpha: wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        },
+    };
+    let pipeline = create_pipeline(device, "Cloud Composite", &layout, &shader, format, Some(blend), samples);
+    (layout, pipeline)
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format!("{} Pipeline Layout", label)),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{} Pipeline", label)),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+fn create_params_buffer(device: &wgpu::Device, stride: u32, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Cloud Params"),
+        size: stride as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
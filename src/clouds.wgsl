@@ -0,0 +1,173 @@
+// The cloud layer, see clouds.rs. A triangle covers the camera's viewport of the low resolution
+// cloud target, and every pixel marches its ray through the slab of clouds, towards the sun at
+// every step it finds cloud, then blends the result with last frame's.
+
+struct CloudParams {
+    inverse_view_proj: mat4x4<f32>;
+    previous_view_proj: mat4x4<f32>; // Last frame's, to find where this pixel was.
+    eye: vec4<f32>; // w is this frame's offset of the ray starts, in steps.
+    sun_direction: vec4<f32>; // Towards the sun; w is the coverage.
+    sun_color: vec4<f32>; // w is the density.
+    ambient_color: vec4<f32>; // w is the bottom of the slab.
+    wind_offset: vec4<f32>; // w is the top of the slab.
+    viewport: vec4<f32>; // x, y, width and height in pixels of the cloud target.
+    shape_scale: f32; // World units the noise volume spans.
+    detail_scale: f32;
+    steps: f32;
+    light_steps: f32;
+    history_weight: f32; // 0 ignores the history.
+    resolution: f32; // Scene pixels per cloud pixel, along each axis.
+};
+[[group(0), binding(0)]]
+var<uniform> params: CloudParams;
+[[group(0), binding(1)]]
+var noise: texture_3d<f32>;
+[[group(0), binding(2)]]
+var noise_sampler: sampler;
+[[group(0), binding(3)]]
+var depth: texture_depth_multisampled_2d;
+[[group(0), binding(4)]]
+var history: texture_2d<f32>;
+[[group(0), binding(5)]]
+var history_sampler: sampler;
+
+let PI: f32 = 3.14159265;
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = params.inverse_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+fn remap(value: f32, low: f32, high: f32) -> f32 {
+    return clamp((value - low) / (high - low), 0.0, 1.0);
+}
+
+// The extinction of the clouds at `p`, per world unit.
+fn density_at(p: vec3<f32>) -> f32 {
+    let bottom = params.ambient_color.w;
+    let top = params.wind_offset.w;
+    let height = clamp((p.y - bottom) / (top - bottom), 0.0, 1.0);
+    // Rounded off at the bottom of the slab, and thinning out towards its top.
+    let profile = smoothStep(0.0, 0.1, height) * (1.0 - smoothStep(0.5, 1.0, height));
+    let shape = textureSampleLevel(noise, noise_sampler, (p + params.wind_offset.xyz) / params.shape_scale, 0.0);
+    let worley = shape.g * 0.625 + shape.b * 0.25 + shape.a * 0.125;
+    let base = remap(shape.r, worley - 1.0, 1.0) * profile;
+    let covered = remap(base, 1.0 - params.sun_direction.w, 1.0);
+    if (covered <= 0.0) {
+        return 0.0;
+    }
+    // Fine noise, drifting faster than the shapes, wears away their edges.
+    let detail = textureSampleLevel(noise, noise_sampler, (p + params.wind_offset.xyz * 2.0) / params.detail_scale, 0.0).g;
+    return remap(covered, detail * 0.35, 1.0) * params.sun_color.w;
+}
+
+// How much sunlight reaches `p` through the clouds above it.
+fn sun_transmittance(p: vec3<f32>) -> f32 {
+    let step = (params.wind_offset.w - params.ambient_color.w) * 0.5 / params.light_steps;
+    var optical_depth = 0.0;
+    for (var i = 0.0; i < params.light_steps; i = i + 1.0) {
+        optical_depth = optical_depth + density_at(p + params.sun_direction.xyz * step * (i + 0.5));
+    }
+    optical_depth = optical_depth * step;
+    // Beer-Lambert, with the "powder" darkening of the edges facing the sun (Schneider, "The
+    // real-time volumetric cloudscapes of Horizon Zero Dawn").
+    return exp(-optical_depth) * (1.0 - exp(-optical_depth * 2.0) * 0.5);
+}
+
+// Henyey-Greenstein: how much light scatters at an angle with cosine `cos_theta` to where it came from.
+fn phase(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let viewport = params.viewport;
+    let local = (position.xy - viewport.xy) / viewport.zw;
+    let ndc = vec2<f32>(local.x * 2.0 - 1.0, 1.0 - local.y * 2.0);
+    let origin = params.eye.xyz;
+    let direction = normalize(unproject(ndc, 1.0) - unproject(ndc, 0.0));
+
+    // The scene in front of the clouds ends the ray.
+    let size = textureDimensions(depth);
+    let pixel = min(vec2<i32>(position.xy * params.resolution), size - 1);
+    let scene_depth = textureLoad(depth, pixel, 0);
+    var max_distance = 1e30;
+    if (scene_depth < 1.0) {
+        max_distance = length(unproject(ndc, scene_depth) - origin);
+    }
+
+    // Where the ray is within the slab.
+    let bottom = params.ambient_color.w;
+    let top = params.wind_offset.w;
+    var start = 0.0;
+    var end = -1.0;
+    if (abs(direction.y) > 0.000001) {
+        let to_bottom = (bottom - origin.y) / direction.y;
+        let to_top = (top - origin.y) / direction.y;
+        start = max(min(to_bottom, to_top), 0.0);
+        end = max(to_bottom, to_top);
+    } else if (origin.y > bottom && origin.y < top) {
+        end = 1e30;
+    }
+    // Near the horizon the ray runs through the slab for miles; the clouds there are a haze anyway.
+    end = min(min(end, max_distance), start + (top - bottom) * 8.0);
+
+    var scattered = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    if (end > start) {
+        let step = (end - start) / params.steps;
+        // Interleaved gradient noise (Jimenez) offsets the start of every pixel's march, and a
+        // different offset every frame turns the banding into noise the history averages away.
+        let noise_offset = fract(52.9829189 * fract(dot(position.xy, vec2<f32>(0.06711056, 0.00583715))));
+        var t = start + step * fract(noise_offset + params.eye.w);
+        let cos_theta = dot(direction, params.sun_direction.xyz);
+        // Bright towards the sun, and a little light scattered back from it.
+        let sun_phase = mix(phase(cos_theta, 0.6), phase(cos_theta, -0.3), 0.3);
+        for (var i = 0.0; i < params.steps; i = i + 1.0) {
+            let p = origin + direction * t;
+            let density = density_at(p);
+            if (density > 0.0) {
+                let height = clamp((p.y - bottom) / (top - bottom), 0.0, 1.0);
+                let light = params.sun_color.xyz * sun_transmittance(p) * sun_phase * 4.0 * PI
+                    + params.ambient_color.xyz * mix(0.5, 1.0, height);
+                // Integrated over the step, rather than sampled at its start (Hillaire, "Physically
+                // based sky, atmosphere and cloud rendering in Frostbite").
+                let step_transmittance = exp(-density * step);
+                scattered = scattered + light * transmittance * (1.0 - step_transmittance);
+                transmittance = transmittance * step_transmittance;
+                if (transmittance < 0.01) {
+                    break;
+                }
+            }
+            t = t + step;
+        }
+    }
+
+    var result = vec4<f32>(scattered, transmittance);
+    if (params.history_weight > 0.0) {
+        // Where the clouds along this ray were on screen last frame: the start of the slab, or far
+        // away when the ray misses it.
+        var reference = origin + direction * 100000.0;
+        if (end > start) {
+            reference = origin + direction * start;
+        }
+        let previous = params.previous_view_proj * vec4<f32>(reference, 1.0);
+        if (previous.w > 0.0) {
+            let previous_ndc = previous.xy / previous.w;
+            if (abs(previous_ndc.x) <= 1.0 && abs(previous_ndc.y) <= 1.0) {
+                let previous_pixel = viewport.xy + vec2<f32>(previous_ndc.x * 0.5 + 0.5, 0.5 - previous_ndc.y * 0.5) * viewport.zw;
+                let uv = previous_pixel / vec2<f32>(textureDimensions(history));
+                let old = textureSampleLevel(history, history_sampler, uv, 0.0);
+                result = mix(result, old, params.history_weight);
+            }
+        }
+    }
+    return result;
+}
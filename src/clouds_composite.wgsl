@@ -0,0 +1,27 @@
+// Blends the low resolution clouds over the scene, see clouds.rs, and the light shafts, see
+// volumetrics.rs. The cloud target holds the light the clouds scatter towards the camera in rgb,
+// and how much of the scene shows through in alpha, so the blend state computes
+// `clouds.rgb + scene.rgb * clouds.a`.
+
+[[group(0), binding(0)]]
+var clouds: texture_2d<f32>;
+[[group(0), binding(1)]]
+var clouds_sampler: sampler;
+
+struct CompositeParams {
+    resolution: f32; // Scene pixels per cloud pixel, along each axis.
+};
+[[group(0), binding(2)]]
+var<uniform> params: CompositeParams;
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let uv = position.xy / (params.resolution * vec2<f32>(textureDimensions(clouds)));
+    return textureSampleLevel(clouds, clouds_sampler, uv, 0.0);
+}
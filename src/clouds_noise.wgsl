@@ -0,0 +1,119 @@
+// The 3D noise the clouds are shaped from, see clouds.rs. Every channel tiles across the volume,
+// since the clouds repeat it across the sky: red is Perlin-Worley noise, the base shapes; green,
+// blue and alpha are Worley noise of rising frequencies, which erode them.
+
+struct VolumeParams {
+    seed: u32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: VolumeParams;
+[[group(0), binding(1)]]
+var output: texture_storage_3d<rgba8unorm, write>;
+
+// PCG hash, like noise.wgsl.
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Cells wrap around every `period` cells, which makes the noise tile.
+fn hash_cell(cell: vec3<i32>, period: i32) -> u32 {
+    let c = vec3<u32>(((cell % period) + period) % period);
+    return hash(c.x ^ hash(c.y ^ hash(c.z ^ hash(params.seed))));
+}
+
+fn to_unit(value: u32) -> f32 {
+    return f32(value >> 8u) / 16777216.0;
+}
+
+// Dot product of `f` with one of 12 gradients along the edges of a cube (Perlin, "Improving noise").
+fn grad(h: u32, f: vec3<f32>) -> f32 {
+    let h = h & 15u;
+    var u = f.y;
+    if (h < 8u) {
+        u = f.x;
+    }
+    var v = f.z;
+    if (h < 4u) {
+        v = f.y;
+    } else if (h == 12u || h == 14u) {
+        v = f.x;
+    }
+    if ((h & 1u) != 0u) {
+        u = -u;
+    }
+    if ((h & 2u) != 0u) {
+        v = -v;
+    }
+    return u + v;
+}
+
+// In about [-1, 1].
+fn perlin(p: vec3<f32>, period: i32) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    let f = p - floor(p);
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    var corners: array<f32, 8>;
+    for (var i = 0; i < 8; i = i + 1) {
+        let offset = vec3<i32>(i & 1, (i >> 1u) & 1, (i >> 2u) & 1);
+        corners[i] = grad(hash_cell(cell + offset, period), f - vec3<f32>(offset));
+    }
+    let x0 = mix(corners[0], corners[1], u.x);
+    let x1 = mix(corners[2], corners[3], u.x);
+    let x2 = mix(corners[4], corners[5], u.x);
+    let x3 = mix(corners[6], corners[7], u.x);
+    return mix(mix(x0, x1, u.y), mix(x2, x3, u.y), u.z);
+}
+
+// 1 at the random points, one per cell, falling to 0 a cell away from them.
+fn worley(p: vec3<f32>, period: i32) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    var nearest = 1.0;
+    for (var z = -1; z <= 1; z = z + 1) {
+        for (var y = -1; y <= 1; y = y + 1) {
+            for (var x = -1; x <= 1; x = x + 1) {
+                let neighbour = cell + vec3<i32>(x, y, z);
+                let h = hash_cell(neighbour, period);
+                let point = vec3<f32>(neighbour) + vec3<f32>(to_unit(h), to_unit(hash(h)), to_unit(hash(h + 1u)));
+                nearest = min(nearest, length(point - p));
+            }
+        }
+    }
+    return 1.0 - nearest;
+}
+
+// Three octaves of Worley noise from `frequency` cells across the volume.
+fn worley_fbm(p: vec3<f32>, frequency: i32) -> f32 {
+    let f = f32(frequency);
+    return worley(p * f, frequency) * 0.625 + worley(p * f * 2.0, frequency * 2) * 0.25 + worley(p * f * 4.0, frequency * 4) * 0.125;
+}
+
+fn remap(value: f32, low: f32, high: f32) -> f32 {
+    return clamp((value - low) / (high - low), 0.0, 1.0);
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pos = vec3<i32>(id);
+    if (pos.x >= size.x || pos.y >= size.y || pos.z >= size.z) {
+        return;
+    }
+    let p = (vec3<f32>(pos) + 0.5) / vec3<f32>(size);
+    var perlin_fbm = 0.0;
+    var amplitude = 1.0;
+    var frequency = 4;
+    for (var octave = 0; octave < 4; octave = octave + 1) {
+        perlin_fbm = perlin_fbm + perlin(p * f32(frequency), frequency) * amplitude;
+        amplitude = amplitude * 0.5;
+        frequency = frequency * 2;
+    }
+    perlin_fbm = clamp(perlin_fbm / 1.875 * 0.5 + 0.5, 0.0, 1.0);
+    // Perlin noise with Worley billows: the Perlin values lifted where the Worley ones are high.
+    let base = worley_fbm(p, 4);
+    let perlin_worley = remap(perlin_fbm, base - 1.0, 1.0);
+    let value = vec4<f32>(perlin_worley, worley_fbm(p, 4), worley_fbm(p, 8), worley_fbm(p, 16));
+    textureStore(output, pos, value);
+}
@@ -0,0 +1,119 @@
+use crate::overrides::{self, PipelineConstants};
+use crate::reflection::{ReflectError, ShaderReflection};
+
+/// A compute pipeline built from WGSL, with its bind group layouts reflected from the source.
+pub struct ComputeKernel {
+    pipeline: wgpu::ComputePipeline,
+    reflection: ShaderReflection,
+    layouts: Vec<wgpu::BindGroupLayout>, // One per bind group, starting at group 0.
+    label: String,
+}
+
+impl ComputeKernel {
+    pub fn new(device: &wgpu::Device, label: &str, wgsl: &str, entry_point: &str) -> Result<ComputeKernel, ReflectError> {
+        Self::with_constants(device, label, wgsl, entry_point, &PipelineConstants::new())
+    }
+
+    // Creates the kernel with constants of the shader and its workgroup size overridden, see
+    // overrides.rs. Dispatches must use the overridden workgroup size.
+    pub fn with_constants(
+        device: &wgpu::Device,
+        label: &str,
+        wgsl: &str,
+        entry_point: &str,
+        constants: &PipelineConstants,
+    ) -> Result<ComputeKernel, ReflectError> {
+        let wgsl = overrides::specialize(wgsl, constants)?;
+        let reflection = ShaderReflection::from_wgsl(&wgsl)?;
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+
+        let group_count = reflection.group_indices().max().map_or(0, |max| max + 1);
+        let layouts: Vec<wgpu::BindGroupLayout> = (0..group_count)
+            .map(|group| reflection.create_bind_group_layout(device, Some(label), group))
+            .collect();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &layouts.iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            module: &module,
+            entry_point,
+        });
+
+        Ok(ComputeKernel {
+            pipeline,
+            reflection,
+            layouts,
+            label: label.to_string(),
+        })
+    }
+
+    pub fn pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.pipeline
+    }
+
+    // Creates a bind group for `group`, after checking the resources against the shader.
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        group: u32,
+        entries: &[wgpu::BindGroupEntry],
+    ) -> Result<wgpu::BindGroup, ReflectError> {
+        self.reflection.validate_entries(group, entries)?;
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{} Bind Group {}", self.label, group)),
+            layout: &self.layouts[group as usize],
+            entries,
+        }))
+    }
+
+    // Records a dispatch of enough `workgroup_size` sized workgroups to cover `width` x `height` invocations.
+    pub fn dispatch_2d(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: &[&wgpu::BindGroup],
+        workgroup_size: (u32, u32),
+        width: u32,
+        height: u32,
+    ) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&self.label),
+        });
+        pass.set_pipeline(&self.pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        pass.dispatch(width.div_ceil(workgroup_size.0), height.div_ceil(workgroup_size.1), 1);
+    }
+
+    // Like `dispatch_2d`, for `width` x `height` x `depth` invocations, e.g. the texels of a volume.
+    pub fn dispatch_3d(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: &[&wgpu::BindGroup],
+        workgroup_size: (u32, u32, u32),
+        size: (u32, u32, u32),
+    ) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&self.label),
+        });
+        pass.set_pipeline(&self.pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        let (width, height, depth) = size;
+        pass.dispatch(
+            width.div_ceil(workgroup_size.0),
+            height.div_ceil(workgroup_size.1),
+            depth.div_ceil(workgroup_size.2),
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use cgmath::Vector2;
+use wgpu::util::DeviceExt;
+
+use crate::compute::ComputeKernel;
+use crate::render_graph::{NodeContext, NodeParameter, RenderNode};
+use crate::uniform::UniformLayout;
+
+// Post-processing kernels
+//======================
+// Reusable compute kernels for post effects, each wrapped in a render graph node.
+// Image kernels read any float texture and write `rgba16float` storage textures,
+// so they can be chained: downsample -> blur -> composite, histogram -> average luminance, ...
+// Every node names its inputs and outputs; outputs are (re)created in the graph resources as needed.
+
+/// Format of the textures written by the image kernels.
+pub const STORAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Number of bins in a luminance histogram buffer.
+pub const HISTOGRAM_BINS: u64 = 256;
+
+pub(crate) const IMAGE_WORKGROUP: (u32, u32) = (8, 8);
+const HISTOGRAM_WORKGROUP: (u32, u32) = (16, 16);
+
+uniform_struct! {
+    struct BlurParams {
+        direction: Vector2<i32>,
+        radius: i32,
+        sigma: f32,
+    }
+}
+assert_uniform_size!(BlurParams, 16);
+
+uniform_struct! {
+    struct HistogramParams {
+        min_log_lum: f32,
+        inv_log_lum_range: f32,
+    }
+}
+assert_uniform_size!(HistogramParams, 8);
+
+uniform_struct! {
+    struct AverageParams {
+        min_log_lum: f32,
+        log_lum_range: f32,
+        pixel_count: f32,
+        adaptation: f32,
+    }
+}
+assert_uniform_size!(AverageParams, 16);
+
+pub(crate) fn create_uniform_buffer(device: &wgpu::Device, label: &str, params: &impl UniformLayout) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: &params.to_uniform_bytes(),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+// Creates the kernel on first use. The shaders are part of the crate, so failing to build them is a bug.
+pub(crate) fn kernel<'a>(kernel: &'a mut Option<ComputeKernel>, device: &wgpu::Device, label: &str, wgsl: &str) -> &'a ComputeKernel {
+    kernel.get_or_insert_with(|| ComputeKernel::new(device, label, wgsl, "main").expect("built-in kernel is valid"))
+}
+
+/// Separable Gaussian blur of `input` into `output`, at the size of the input.
+pub struct GaussianBlurNode {
+    pub input: String,
+    pub output: String,
+    pub radius: i32,
+    pub sigma: f32,
+    kernel: Option<ComputeKernel>,
+    // One params buffer per pass: both writes land before the passes execute.
+    params: Option<[wgpu::Buffer; 2]>,
+}
+
+impl GaussianBlurNode {
+    pub fn new(input: &str, output: &str, radius: i32, sigma: f32) -> GaussianBlurNode {
+        GaussianBlurNode {
+            input: input.to_string(),
+            output: output.to_string(),
+            radius,
+            sigma,
+            kernel: None,
+            params: None,
+        }
+    }
+
+    fn temp_name(&self) -> String {
+        format!("{}.blur_temp", self.output)
+    }
+}
+
+impl RenderNode for GaussianBlurNode {
+    fn name(&self) -> &str {
+        "Gaussian Blur"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![&self.input]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![&self.output]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (width, height) = match ctx.resources.texture(&self.input) {
+            Some(input) => (input.width, input.height),
+            None => return,
+        };
+        let temp_name = self.temp_name();
+        ctx.resources.ensure_texture(ctx.device, &temp_name, width, height, STORAGE_FORMAT);
+        ctx.resources.ensure_texture(ctx.device, &self.output, width, height, STORAGE_FORMAT);
+
+        let passes = [Vector2::new(1, 0), Vector2::new(0, 1)].map(|direction| BlurParams {
+            direction,
+            radius: self.radius,
+            sigma: self.sigma.max(0.01),
+        });
+        match &self.params {
+            Some(buffers) => {
+                for (buffer, params) in buffers.iter().zip(&passes) {
+                    ctx.queue.write_buffer(buffer, 0, &params.to_uniform_bytes());
+                }
+            }
+            None => {
+                self.params = Some([
+                    create_uniform_buffer(ctx.device, "Horizontal Blur Params", &passes[0]),
+                    create_uniform_buffer(ctx.device, "Vertical Blur Params", &passes[1]),
+                ]);
+            }
+        }
+
+        let kernel = kernel(&mut self.kernel, ctx.device, "Gaussian Blur", include_str!("blur.wgsl"));
+        let params = self.params.as_ref().unwrap();
+        let input = ctx.resources.texture(&self.input).unwrap();
+        let temp = ctx.resources.texture(&temp_name).unwrap();
+        let output = ctx.resources.texture(&self.output).unwrap();
+
+        // Horizontal pass into the temporary texture, then vertical pass into the output.
+        for (params, (from, to)) in params.iter().zip([(input, temp), (temp, output)]) {
+            let bind_group = kernel.bind_group(
+                ctx.device,
+                0,
+                &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&from.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&to.view),
+                    },
+                ],
+            );
+            match bind_group {
+                Ok(bind_group) => kernel.dispatch_2d(encoder, &[&bind_group], IMAGE_WORKGROUP, width, height),
+                Err(e) => {
+                    tracing::error!("Gaussian Blur: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Halves the resolution of `input` into `output` with a box filter.
+pub struct DownsampleNode {
+    pub input: String,
+    pub output: String,
+    kernel: Option<ComputeKernel>,
+}
+
+impl DownsampleNode {
+    pub fn new(input: &str, output: &str) -> DownsampleNode {
+        DownsampleNode {
+            input: input.to_string(),
+            output: output.to_string(),
+            kernel: None,
+        }
+    }
+}
+
+impl RenderNode for DownsampleNode {
+    fn name(&self) -> &str {
+        "Downsample"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![&self.input]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![&self.output]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (width, height) = match ctx.resources.texture(&self.input) {
+            Some(input) => ((input.width / 2).max(1), (input.height / 2).max(1)),
+            None => return,
+        };
+        ctx.resources.ensure_texture(ctx.device, &self.output, width, height, STORAGE_FORMAT);
+
+        let kernel = kernel(&mut self.kernel, ctx.device, "Downsample", include_str!("downsample.wgsl"));
+        let input = ctx.resources.texture(&self.input).unwrap();
+        let output = ctx.resources.texture(&self.output).unwrap();
+
+        let bind_group = kernel.bind_group(
+            ctx.device,
+            0,
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&output.view),
+                },
+            ],
+        );
+        match bind_group {
+            Ok(bind_group) => kernel.dispatch_2d(encoder, &[&bind_group], IMAGE_WORKGROUP, width, height),
+            Err(e) => tracing::error!("Downsample: {}", e),
+        }
+    }
+}
+
+/// Accumulates a log2 luminance histogram of `input` into the buffer `output`.
+/// The histogram is cleared again by `AverageLuminanceNode`.
+pub struct HistogramNode {
+    pub input: String,
+    pub output: String,
+    // Range of log2 luminance covered by the histogram, e.g. -8.0 and 12.0 for 1/256 to 4096.
+    pub min_log_lum: f32,
+    pub log_lum_range: f32,
+    kernel: Option<ComputeKernel>,
+    params: Option<wgpu::Buffer>,
+}
+
+impl HistogramNode {
+    pub fn new(input: &str, output: &str, min_log_lum: f32, log_lum_range: f32) -> HistogramNode {
+        HistogramNode {
+            input: input.to_string(),
+            output: output.to_string(),
+            min_log_lum,
+            log_lum_range,
+            kernel: None,
+            params: None,
+        }
+    }
+}
+
+impl RenderNode for HistogramNode {
+    fn name(&self) -> &str {
+        "Luminance Histogram"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![&self.input]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![&self.output]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (width, height) = match ctx.resources.texture(&self.input) {
+            Some(input) => (input.width, input.height),
+            None => return,
+        };
+        ctx.resources.ensure_buffer(
+            ctx.device,
+            &self.output,
+            HISTOGRAM_BINS * 4,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+
+        let params = HistogramParams {
+            min_log_lum: self.min_log_lum,
+            inv_log_lum_range: 1.0 / self.log_lum_range,
+        };
+        match &self.params {
+            Some(buffer) => ctx.queue.write_buffer(buffer, 0, &params.to_uniform_bytes()),
+            None => self.params = Some(create_uniform_buffer(ctx.device, "Histogram Params", &params)),
+        }
+
+        let kernel = kernel(&mut self.kernel, ctx.device, "Luminance Histogram", include_str!("histogram.wgsl"));
+        let input = ctx.resources.texture(&self.input).unwrap();
+        let histogram = ctx.resources.buffer(&self.output).unwrap();
+
+        let bind_group = kernel.bind_group(
+            ctx.device,
+            0,
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_ref().unwrap().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: histogram.as_entire_binding(),
+                },
+            ],
+        );
+        match bind_group {
+            Ok(bind_group) => kernel.dispatch_2d(encoder, &[&bind_group], HISTOGRAM_WORKGROUP, width, height),
+            Err(e) => tracing::error!("Luminance Histogram: {}", e),
+        }
+    }
+}
+
+/// Reduces the histogram built by a `HistogramNode` to the average scene luminance,
+/// which is written as a single f32 into the buffer `output` and eased in over time.
+/// Auto-exposure reads that buffer, or copies it out with `GFX::read_buffer`.
+pub struct AverageLuminanceNode {
+    pub histogram: String,
+    // The texture the histogram was built from, to know the pixel count.
+    pub source: String,
+    pub output: String,
+    pub min_log_lum: f32,
+    pub log_lum_range: f32,
+    // Fraction of the way to move from the previous average to this frame's, in [0, 1].
+    pub adaptation: f32,
+    kernel: Option<ComputeKernel>,
+    params: Option<wgpu::Buffer>,
+}
+
+impl AverageLuminanceNode {
+    // Use the same luminance range as the `HistogramNode` that fills `histogram`.
+    pub fn new(histogram: &str, source: &str, output: &str, min_log_lum: f32, log_lum_range: f32) -> AverageLuminanceNode {
+        AverageLuminanceNode {
+            histogram: histogram.to_string(),
+            source: source.to_string(),
+            output: output.to_string(),
+            min_log_lum,
+            log_lum_range,
+            adaptation: 0.05,
+            kernel: None,
+            params: None,
+        }
+    }
+}
+
+impl RenderNode for AverageLuminanceNode {
+    fn name(&self) -> &str {
+        "Average Luminance"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![&self.histogram, &self.source]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![&self.output]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let pixel_count = match ctx.resources.texture(&self.source) {
+            Some(source) => (source.width * source.height) as f32,
+            None => return,
+        };
+        if ctx.resources.buffer(&self.histogram).is_none() {
+            return;
+        }
+        ctx.resources.ensure_buffer(
+            ctx.device,
+            &self.output,
+            4,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+
+        let params = AverageParams {
+            min_log_lum: self.min_log_lum,
+            log_lum_range: self.log_lum_range,
+            pixel_count,
+            adaptation: self.adaptation.clamp(0.0, 1.0),
+        };
+        match &self.params {
+            Some(buffer) => ctx.queue.write_buffer(buffer, 0, &params.to_uniform_bytes()),
+            None => self.params = Some(create_uniform_buffer(ctx.device, "Average Luminance Params", &params)),
+        }
+
+        let kernel = kernel(&mut self.kernel, ctx.device, "Average Luminance", include_str!("luminance.wgsl"));
+        let histogram = ctx.resources.buffer(&self.histogram).unwrap();
+        let output = ctx.resources.buffer(&self.output).unwrap();
+
+        let bind_group = kernel.bind_group(
+            ctx.device,
+            0,
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_ref().unwrap().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        );
+        match bind_group {
+            // A single workgroup of 256 invocations, one per bin.
+            Ok(bind_group) => kernel.dispatch_2d(encoder, &[&bind_group], (1, 1), 1, 1),
+            Err(e) => tracing::error!("Average Luminance: {}", e),
+        }
+    }
+
+    fn parameters(&self) -> Vec<NodeParameter> {
+        vec![NodeParameter::new("adaptation", self.adaptation, 0.0..=1.0)]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        if name == "adaptation" {
+            self.adaptation = value;
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bloom::BloomSettings;
+use crate::cli::{self, CliOptions};
+use crate::exposure::ExposureSettings;
+use crate::gfx::GfxOptions;
+use crate::letterbox::VirtualResolution;
+use crate::render_scale::{DynamicResolution, RenderScaleSettings};
+use crate::window::WindowBuilder;
+
+// Configuration file
+//======================
+// Settings live in `config.toml` next to the executable. Missing settings (or a missing file)
+// fall back to the defaults below, and command line options override the file:
+//
+//     [window]
+//     width = 1280
+//     height = 720
+//     fullscreen = false
+//     redraw_on_demand = false    # true renders only on input or repaints, for tools, see app.rs
+//     background_fps = 10         # frame rate without the focus, 0 for no limit
+//     occluded_fps = 5            # while covered by other windows
+//     battery_fps = 30            # while running on battery
+//
+//     [graphics]
+//     backend = "dx12"            # vulkan, dx12, dx11, metal, gl or all
+//     power_preference = "high-performance"
+//     adapter = 1                 # leave out to let wgpu choose
+//     vsync = true
+//     msaa = 4                    # 1 or 4
+//     anisotropy = 16             # 1 to turn anisotropic filtering off
+//     compress_vertices = true    # half-float positions for loaded meshes
+//     occlusion_culling = true    # skip objects hidden behind others, on the GPU
+//     frames_in_flight = 2        # 1 to 3
+//     virtual_resolution = [320, 180]  # leave out to render at the window size, see letterbox.rs
+//     scale_filter = "nearest"    # or "linear", scaling the virtual resolution to the window
+//     integer_scale = true        # scale the virtual resolution by whole numbers only
+//     hdr = true                  # render in HDR with automatic exposure, see exposure.rs
+//     exposure_range = [-4, 12]   # the darkest and brightest exposure, in EV100
+//     bloom = true                # glow around the brightest parts with HDR, see bloom.rs
+//     render_scale = 0.75         # render the scene smaller and scale it up, see render_scale.rs
+//     dynamic_resolution_fps = 60 # adjust the render scale to hold this frame rate, 0 to keep it
+//
+//     [keys]
+//     quit = "Escape"
+//     toggle_stats = "F3"
+//     toggle_fullscreen = "F4"
+//     save_scene = "F5"
+//     flush_metrics = "F6"
+//     toggle_settings = "F2"      # with the settings_ui feature
+//     capture_frame = "F11"       # with the renderdoc feature, see renderdoc.rs
+
+const FILE_NAME: &str = "config.toml";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub graphics: GraphicsConfig,
+    pub keys: KeyBindings,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: i32,
+    pub height: i32,
+    pub fullscreen: bool,
+    pub redraw_on_demand: bool,
+    // Reduced frame rates, see "Throttling" in app.rs. 0 doesn't reduce it.
+    pub background_fps: f32,
+    pub occluded_fps: f32,
+    pub battery_fps: f32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            fullscreen: false,
+            redraw_on_demand: false,
+            background_fps: 10.0,
+            occluded_fps: 5.0,
+            battery_fps: 30.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsConfig {
+    pub backend: String,
+    pub power_preference: String, // "default", "low-power" or "high-performance"
+    pub adapter: Option<usize>,
+    pub vsync: bool,
+    pub msaa: u32,
+    pub anisotropy: u8,
+    pub compress_vertices: bool,
+    pub occlusion_culling: bool,
+    pub frames_in_flight: usize,
+    pub virtual_resolution: Option<[u32; 2]>,
+    pub scale_filter: String, // "nearest" or "linear"
+    pub integer_scale: bool,
+    pub hdr: bool,
+    pub exposure_range: [f32; 2],
+    pub bloom: bool,
+    pub render_scale: f32,
+    pub dynamic_resolution_fps: f32,
+    pub shadow_atlas_size: u32, // Of the local lights' shadow maps, see shadow_atlas.rs.
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        Self {
+            backend: "all".into(),
+            power_preference: "default".into(),
+            adapter: None,
+            vsync: true,
+            msaa: 1,
+            anisotropy: 1,
+            compress_vertices: false,
+            occlusion_culling: false,
+            frames_in_flight: 2,
+            virtual_resolution: None,
+            scale_filter: "nearest".into(),
+            integer_scale: false,
+            hdr: false,
+            exposure_range: [-4.0, 12.0],
+            bloom: false,
+            render_scale: 1.0,
+            dynamic_resolution_fps: 0.0,
+            shadow_atlas_size: 2048,
+        }
+    }
+}
+
+/// Maps action names to key names, e.g. "quit" to "Escape".
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct KeyBindings(pub BTreeMap<String, String>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let bindings = [
+            ("quit", "Escape"),
+            ("toggle_stats", "F3"),
+            ("toggle_fullscreen", "F4"),
+            ("save_scene", "F5"),
+            ("flush_metrics", "F6"),
+            ("toggle_settings", "F2"),
+            ("capture_frame", "F11"),
+        ];
+        KeyBindings(bindings.iter().map(|(a, k)| (a.to_string(), k.to_string())).collect())
+    }
+}
+
+impl KeyBindings {
+    // The virtual-key code bound to `action`, if it is bound to a known key.
+    pub fn key(&self, action: &str) -> Option<u16> {
+        self.0.get(action).and_then(|name| key_code(name))
+    }
+}
+
+impl Config {
+    // `config.toml` next to the executable.
+    pub fn default_path() -> PathBuf {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(FILE_NAME)))
+            .unwrap_or_else(|| PathBuf::from(FILE_NAME))
+    }
+
+    // Loads the configuration, using the defaults for everything that is missing or unreadable.
+    pub fn load(path: &Path) -> Config {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Config::default(),
+        };
+        toml::from_str(&text).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring {}: {}", path.display(), e);
+            Config::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let text = toml::to_string_pretty(self).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, text)
+    }
+
+    // Applies the options given on the command line on top of the file.
+    pub fn apply(&mut self, cli: &CliOptions) {
+        if let Some((width, height)) = cli.size {
+            self.window.width = width;
+            self.window.height = height;
+        }
+        if cli.fullscreen {
+            self.window.fullscreen = true;
+        }
+        if let Some(backend) = &cli.backend {
+            self.graphics.backend = backend.clone();
+        }
+        if cli.adapter.is_some() {
+            self.graphics.adapter = cli.adapter;
+        }
+        if cli.no_vsync {
+            self.graphics.vsync = false;
+        }
+        if cli.hdr {
+            self.graphics.hdr = true;
+        }
+        if cli.bloom {
+            self.graphics.hdr = true;
+            self.graphics.bloom = true;
+        }
+    }
+
+    pub fn window_builder(&self) -> WindowBuilder {
+        WindowBuilder::new()
+            .size(self.window.width, self.window.height)
+            .fullscreen(self.window.fullscreen)
+    }
+
+    pub fn gfx_options(&self) -> GfxOptions {
+        let backends = cli::parse_backend(&self.graphics.backend).unwrap_or_else(|| {
+            tracing::warn!("Unknown backend '{}', using all backends", self.graphics.backend);
+            wgpu::Backends::all()
+        });
+        let power_preference = match self.graphics.power_preference.as_str() {
+            "low-power" => wgpu::PowerPreference::LowPower,
+            "high-performance" => wgpu::PowerPreference::HighPerformance,
+            _ => wgpu::PowerPreference::default(),
+        };
+        let scale_filter = match self.graphics.scale_filter.as_str() {
+            "linear" => wgpu::FilterMode::Linear,
+            "nearest" => wgpu::FilterMode::Nearest,
+            other => {
+                tracing::warn!("Unknown scale filter '{}', using nearest", other);
+                wgpu::FilterMode::Nearest
+            }
+        };
+        let virtual_resolution = self.graphics.virtual_resolution.map(|[width, height]| {
+            VirtualResolution::new(width, height)
+                .with_filter(scale_filter)
+                .with_integer_scale(self.graphics.integer_scale)
+        });
+        GfxOptions {
+            backends,
+            adapter: self.graphics.adapter,
+            power_preference,
+            vsync: self.graphics.vsync,
+            msaa_samples: self.graphics.msaa.max(1),
+            anisotropy: self.graphics.anisotropy,
+            compress_vertices: self.graphics.compress_vertices,
+            occlusion_culling: self.graphics.occlusion_culling,
+            frames_in_flight: self.graphics.frames_in_flight.clamp(1, 3),
+            trace_path: None,
+            virtual_resolution,
+            shared_context: None,
+            exposure: self.graphics.hdr.then(|| ExposureSettings {
+                min_ev: self.graphics.exposure_range[0],
+                max_ev: self.graphics.exposure_range[1],
+                bloom: self.graphics.bloom.then(BloomSettings::default),
+                ..Default::default()
+            }),
+            render_scale: self.render_scale(),
+            shadow_atlas_size: self.graphics.shadow_atlas_size,
+        }
+    }
+
+    // Dynamic with a target frame rate, starting out at `render_scale`.
+    fn render_scale(&self) -> Option<RenderScaleSettings> {
+        let scale = self.graphics.render_scale;
+        if self.graphics.dynamic_resolution_fps > 0.0 {
+            let dynamic = DynamicResolution::new(self.graphics.dynamic_resolution_fps);
+            Some(RenderScaleSettings { scale, dynamic: Some(dynamic) })
+        } else if scale < 1.0 {
+            Some(RenderScaleSettings::fixed(scale))
+        } else {
+            None
+        }
+    }
+}
+
+// Virtual-key code of a key name: letters, digits, F1-F12 and a few named keys.
+fn key_code(name: &str) -> Option<u16> {
+    let upper = name.to_ascii_uppercase();
+    let mut chars = upper.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // Letters and digits have their ASCII code.
+        return c.is_ascii_alphanumeric().then_some(c as u16);
+    }
+    if let Some(n) = upper.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+        return (1..=12).contains(&n).then(|| 0x70 + n - 1);
+    }
+    let code = match upper.as_str() {
+        "BACKSPACE" => 0x08,
+        "TAB" => 0x09,
+        "ENTER" => 0x0D,
+        "SHIFT" => 0x10,
+        "CONTROL" => 0x11,
+        "ALT" => 0x12,
+        "ESCAPE" => 0x1B,
+        "SPACE" => 0x20,
+        "LEFT" => 0x25,
+        "UP" => 0x26,
+        "RIGHT" => 0x27,
+        "DOWN" => 0x28,
+        _ => return None,
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_implies_hdr() {
+        let mut config = Config::default();
+        config.apply(&CliOptions {
+            bloom: true,
+            ..Default::default()
+        });
+        assert!(config.graphics.hdr && config.graphics.bloom);
+
+        let mut config = Config::default();
+        config.apply(&CliOptions {
+            hdr: true,
+            ..Default::default()
+        });
+        assert!(config.graphics.hdr && !config.graphics.bloom);
+    }
+
+    #[test]
+    fn options_override_the_file() {
+        let mut config = Config::default();
+        config.apply(&CliOptions {
+            size: Some((640, 480)),
+            no_vsync: true,
+            ..Default::default()
+        });
+        assert_eq!((config.window.width, config.window.height), (640, 480));
+        assert!(!config.graphics.vsync);
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::logging;
+use crate::stats::RenderCounters;
+
+// Crash reports
+//======================
+// Graphics drivers tend to crash inside their own code, where a panic message says little.
+// When the process goes down, because of a panic or an exception nobody handled (e.g. an access
+// violation in the driver), the following is written next to the executable:
+//  - `crash-<seconds>.dmp`, a minidump of the process for WinDbg or Visual Studio (Windows only),
+//  - an entry in `crash.log` (see panic.rs) with the adapter in use, the last frames and the
+//    last log lines.
+// Users attach both to their bug report.
+
+const RECENT_FRAMES: usize = 60;
+const LOG_LINES: usize = 20;
+
+struct FrameRecord {
+    frame: u64,
+    dt: f32,
+    counters: RenderCounters,
+}
+
+struct CrashContext {
+    adapter: Option<String>,
+    frames: VecDeque<FrameRecord>, // Oldest first.
+}
+
+static CONTEXT: Mutex<CrashContext> = Mutex::new(CrashContext {
+    adapter: None,
+    frames: VecDeque::new(),
+});
+
+// Called by `GFX::new` with the adapter it picked.
+pub fn set_adapter(info: &wgpu::AdapterInfo) {
+    let adapter = format!(
+        "{} ({:?}, {:?}, vendor {:#06x}, device {:#06x})",
+        info.name, info.backend, info.device_type, info.vendor, info.device
+    );
+    if let Ok(mut context) = CONTEXT.lock() {
+        context.adapter = Some(adapter);
+    }
+}
+
+// Called by `App` after every frame.
+pub fn record_frame(frame: u64, dt: f32, counters: RenderCounters) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        if context.frames.len() == RECENT_FRAMES {
+            context.frames.pop_front();
+        }
+        context.frames.push_back(FrameRecord { frame, dt, counters });
+    }
+}
+
+// The adapter, recent frames and log lines, for crash.log.
+pub fn context_report() -> String {
+    let mut report = String::new();
+    // The crash may have happened while the context was locked, don't wait for it.
+    match CONTEXT.try_lock() {
+        Ok(context) => {
+            let adapter = context.adapter.as_deref().unwrap_or("none yet");
+            let _ = writeln!(report, "Adapter: {}", adapter);
+            let _ = writeln!(report, "Last {} frames:", context.frames.len());
+            for record in &context.frames {
+                let _ = writeln!(
+                    report,
+                    "  frame {}: {:.2} ms, {} draw calls, {} triangles",
+                    record.frame,
+                    record.dt * 1000.0,
+                    record.counters.draw_calls,
+                    record.counters.triangles
+                );
+            }
+        }
+        Err(_) => report.push_str("Adapter and frames unavailable (locked while crashing)\n"),
+    }
+    report.push_str("Last log lines:\n");
+    for line in logging::recent(LOG_LINES) {
+        let _ = writeln!(report, "  {}", line);
+    }
+    report
+}
+
+// `crash-<seconds>.dmp` next to crash.log.
+#[cfg(windows)]
+fn dump_path() -> PathBuf {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    crate::panic::log_path().with_file_name(format!("crash-{}.dmp", seconds))
+}
+
+#[cfg(windows)]
+pub use self::windows_dump::{install_exception_filter, write_minidump};
+
+#[cfg(not(windows))]
+pub fn install_exception_filter() {}
+
+// Minidumps are Windows only; elsewhere the system's core dumps serve the same purpose.
+#[cfg(not(windows))]
+pub fn write_minidump() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(windows)]
+mod windows_dump {
+    use std::fs::File;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::PathBuf;
+
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::Diagnostics::Debug::{
+        MiniDumpWithDataSegs, MiniDumpWithIndirectlyReferencedMemory, MiniDumpWithThreadInfo, MiniDumpWriteDump,
+        SetUnhandledExceptionFilter, EXCEPTION_POINTERS, MINIDUMP_EXCEPTION_INFORMATION,
+    };
+    use windows::Win32::System::Threading::{GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId};
+
+    use super::{context_report, dump_path};
+
+    const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+
+    // Catches the exceptions nobody handled, e.g. access violations in the graphics driver.
+    pub fn install_exception_filter() {
+        unsafe {
+            SetUnhandledExceptionFilter(Some(on_unhandled_exception));
+        }
+    }
+
+    unsafe extern "system" fn on_unhandled_exception(info: *const EXCEPTION_POINTERS) -> i32 {
+        let record = &*(*info).ExceptionRecord;
+        let dump = write_dump(info);
+        let mut report = format!(
+            "=== unhandled exception {:#010X} at {:?} ===\n",
+            record.ExceptionCode.0 as u32, record.ExceptionAddress
+        );
+        if let Some(dump) = dump {
+            report.push_str(&format!("Minidump: {}\n", dump.display()));
+        }
+        report.push_str(&context_report());
+        let _ = crate::panic::write_log(&crate::panic::log_path(), &report);
+        // Let Windows end the process (and Windows Error Reporting see the crash).
+        EXCEPTION_CONTINUE_SEARCH
+    }
+
+    // A minidump of the process as it is now, e.g. from the panic hook.
+    pub fn write_minidump() -> Option<PathBuf> {
+        write_dump(std::ptr::null())
+    }
+
+    fn write_dump(exception: *const EXCEPTION_POINTERS) -> Option<PathBuf> {
+        let path = dump_path();
+        let file = File::create(&path).ok()?;
+        let exception_info = MINIDUMP_EXCEPTION_INFORMATION {
+            ThreadId: unsafe { GetCurrentThreadId() },
+            ExceptionPointers: exception as *mut _,
+            ClientPointers: false.into(),
+        };
+        let written = unsafe {
+            MiniDumpWriteDump(
+                GetCurrentProcess(),
+                GetCurrentProcessId(),
+                HANDLE(file.as_raw_handle() as isize),
+                // The stacks, globals and the memory they point to: enough to inspect the crash,
+                // while staying in the megabytes.
+                MiniDumpWithDataSegs | MiniDumpWithThreadInfo | MiniDumpWithIndirectlyReferencedMemory,
+                if exception.is_null() {
+                    std::ptr::null()
+                } else {
+                    &exception_info
+                },
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        if written.as_bool() {
+            Some(path)
+        } else {
+            drop(file);
+            let _ = std::fs::remove_file(&path);
+            None
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform as _, Vector3};
+
+use crate::texture::Texture;
+
+// Debug drawing
+//======================
+// World-space lines for visualizing what is otherwise invisible: colliders, bounds, paths.
+// Like text, lines are queued for a single frame. They are drawn after the renderables,
+// by every camera that sees `RenderLayers::GIZMOS`.
+//
+// The GFX also draws some of its own state every frame when asked to with `GFX::set_debug_views`,
+// each category on its own, to see what the culling and the shadows work with:
+//
+//     gfx.set_debug_views(DebugViews { camera_frusta: true, aabbs: true, ..Default::default() });
+
+/// The categories of engine state `GFX` draws as wireframes, see above.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DebugViews {
+    pub camera_frusta: bool, // Yellow, as seen by the other cameras.
+    // Queued with `GFX::draw_light_frustum`, e.g. by shadow passes, one color per cascade.
+    pub light_frusta: bool,
+    pub aabbs: bool, // World bounds of the renderables: green if a camera draws them, red if culled.
+    pub bounding_spheres: bool, // Around the same bounds, colored the same way.
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl LineVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// Segments per circle of `sphere` and `circle`.
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// Queues debug lines every frame and draws them on top of the scene.
+pub struct DebugDraw {
+    pipeline: wgpu::RenderPipeline,
+    vertices: Vec<LineVertex>,
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    vertex_count: u32,
+}
+
+impl DebugDraw {
+    // `camera_layout` is the layout of the camera bind group, which the lines are drawn with.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        samples: u32,
+    ) -> DebugDraw {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug_draw.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList, // Every two vertices make a line.
+                ..Default::default()
+            },
+            // Drawn on top of the scene, without hiding anything from it.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: samples,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let vertex_capacity = 1024;
+        DebugDraw {
+            pipeline,
+            vertices: Vec::new(),
+            vertex_buffer: create_vertex_buffer(device, vertex_capacity),
+            vertex_capacity,
+            vertex_count: 0,
+        }
+    }
+
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 4]) {
+        self.vertices.push(LineVertex {
+            position: a.into(),
+            color,
+        });
+        self.vertices.push(LineVertex {
+            position: b.into(),
+            color,
+        });
+    }
+
+    // An axis-aligned box.
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 4]) {
+        let center = min + (max - min) / 2.0;
+        let transform = Matrix4::from_translation(center.to_vec());
+        self.oriented_box(&transform, (max - min) / 2.0, color);
+    }
+
+    // A box of the given half extents around the origin, placed by `transform`.
+    pub fn oriented_box(&mut self, transform: &Matrix4<f32>, half_extents: Vector3<f32>, color: [f32; 4]) {
+        // Corner `i` is at x = bit 0, y = bit 1, z = bit 2, as in the unit cube of assets.rs.
+        let corners: Vec<Point3<f32>> = (0..8)
+            .map(|i| {
+                let sign = |bit: usize| if i & (1 << bit) != 0 { 1.0 } else { -1.0 };
+                let local = Point3::new(
+                    sign(0) * half_extents.x,
+                    sign(1) * half_extents.y,
+                    sign(2) * half_extents.z,
+                );
+                transform.transform_point(local)
+            })
+            .collect();
+        // The 12 edges connect corners that differ in a single bit.
+        for i in 0..8 {
+            for bit in 0..3 {
+                let j = i | (1 << bit);
+                if j != i {
+                    self.line(corners[i], corners[j], color);
+                }
+            }
+        }
+    }
+
+    // The volume a view projection matrix maps to clip space, e.g. of a camera or a light.
+    // Nothing for matrices that can't be inverted.
+    pub fn frustum(&mut self, view_proj: &Matrix4<f32>, color: [f32; 4]) {
+        let inverse = match view_proj.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        // Corners of wgpu's clip volume, x and y in [-1, 1], z in [0, 1], numbered as in `oriented_box`.
+        let corners: Vec<Point3<f32>> = (0..8)
+            .map(|i| {
+                let bit = |bit: usize| if i & (1 << bit) != 0 { 1.0 } else { 0.0 };
+                inverse.transform_point(Point3::new(bit(0) * 2.0 - 1.0, bit(1) * 2.0 - 1.0, bit(2)))
+            })
+            .collect();
+        for i in 0..8 {
+            for bit in 0..3 {
+                let j = i | (1 << bit);
+                if j != i {
+                    self.line(corners[i], corners[j], color);
+                }
+            }
+        }
+    }
+
+    // Three circles, one around each axis.
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 4]) {
+        let point = |axis: usize, angle: f32| {
+            let (sin, cos) = (angle.sin() * radius, angle.cos() * radius);
+            center
+                + match axis {
+                    0 => Vector3::new(0.0, cos, sin),
+                    1 => Vector3::new(cos, 0.0, sin),
+                    _ => Vector3::new(cos, sin, 0.0),
+                }
+        };
+        let step = std::f32::consts::TAU / CIRCLE_SEGMENTS as f32;
+        for axis in 0..3 {
+            for i in 0..CIRCLE_SEGMENTS {
+                self.line(point(axis, i as f32 * step), point(axis, (i + 1) as f32 * step), color);
+            }
+        }
+    }
+
+    // A circle around `normal`, e.g. the handles of a rotation gizmo.
+    pub fn circle(&mut self, center: Point3<f32>, normal: Vector3<f32>, radius: f32, color: [f32; 4]) {
+        let normal = normal.normalize();
+        // Any two directions perpendicular to the normal and to each other span the circle.
+        let other = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+        let u = normal.cross(other).normalize() * radius;
+        let v = normal.cross(u);
+        let point = |angle: f32| center + u * angle.cos() + v * angle.sin();
+        let step = std::f32::consts::TAU / CIRCLE_SEGMENTS as f32;
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i as f32 * step), point((i + 1) as f32 * step), color);
+        }
+    }
+
+    // Uploads the queued lines, to be drawn by `draw` this frame.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = self.vertices.len().next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        self.vertex_count = self.vertices.len() as u32;
+        self.vertices.clear();
+    }
+
+    // Draws the lines with the camera bind group already set at group 0.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Line Buffer"),
+        size: (capacity * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::num::NonZeroU8;
+use std::rc::Rc;
+
+// Device object caches
+//======================
+// Every shader variant reflects its own material bind group layout and every texture comes with a
+// sampler, but nearly all of them are identical: a few layouts and two or three samplers cover a
+// whole scene. Some backends run out of descriptor pools or sampler heap slots long before they run
+// out of memory, so identical objects are created once and shared:
+//
+//     let layout = cache.bind_group_layout(device, Some("Material"), &reflection.layout_entries(1));
+//     let sampler = cache.sampler(device, &wgpu::SamplerDescriptor { .. });
+//
+// Objects are looked up by their descriptor without the label; a shared object keeps the label it
+// was created with. The GFX owns one cache per device and lends it to the materials, the asset
+// registry and the texture streamer. `collect_garbage` drops the objects nothing uses anymore.
+
+// The parts of a `wgpu::SamplerDescriptor` that make samplers different. The level of detail
+// clamps are compared bit for bit, as floats aren't `Hash`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SamplerKey {
+    address_modes: [wgpu::AddressMode; 3],
+    filters: [wgpu::FilterMode; 3], // Mag, min and mipmap.
+    lod_clamp: [u32; 2],
+    compare: Option<wgpu::CompareFunction>,
+    anisotropy_clamp: Option<NonZeroU8>,
+    border_color: Option<wgpu::SamplerBorderColor>,
+}
+
+impl SamplerKey {
+    fn new(desc: &wgpu::SamplerDescriptor) -> SamplerKey {
+        SamplerKey {
+            address_modes: [desc.address_mode_u, desc.address_mode_v, desc.address_mode_w],
+            filters: [desc.mag_filter, desc.min_filter, desc.mipmap_filter],
+            lod_clamp: [desc.lod_min_clamp.to_bits(), desc.lod_max_clamp.to_bits()],
+            compare: desc.compare,
+            anisotropy_clamp: desc.anisotropy_clamp,
+            border_color: desc.border_color,
+        }
+    }
+}
+
+/// Shares identical bind group layouts and samplers, see above.
+pub struct DeviceCache {
+    layouts: RefCell<HashMap<Vec<wgpu::BindGroupLayoutEntry>, Rc<wgpu::BindGroupLayout>>>,
+    samplers: RefCell<HashMap<SamplerKey, Rc<wgpu::Sampler>>>,
+}
+
+impl DeviceCache {
+    pub fn new() -> DeviceCache {
+        DeviceCache {
+            layouts: RefCell::new(HashMap::new()),
+            samplers: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // A layout with `entries`, created the first time they are asked for. Entries are matched in
+    // binding order, so the order they are listed in doesn't matter.
+    pub fn bind_group_layout(
+        &self,
+        device: &wgpu::Device,
+        label: Option<&str>,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> Rc<wgpu::BindGroupLayout> {
+        let mut key = entries.to_vec();
+        key.sort_by_key(|entry| entry.binding);
+        self.layouts
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with_key(|entries| {
+                Rc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label, entries }))
+            })
+            .clone()
+    }
+
+    // A sampler like `desc`, created the first time it is asked for.
+    pub fn sampler(&self, device: &wgpu::Device, desc: &wgpu::SamplerDescriptor) -> Rc<wgpu::Sampler> {
+        self.samplers
+            .borrow_mut()
+            .entry(SamplerKey::new(desc))
+            .or_insert_with(|| Rc::new(device.create_sampler(desc)))
+            .clone()
+    }
+
+    // Drops the layouts and samplers only the cache still refers to. Returns how many.
+    pub fn collect_garbage(&self) -> usize {
+        let mut layouts = self.layouts.borrow_mut();
+        let mut samplers = self.samplers.borrow_mut();
+        let before = layouts.len() + samplers.len();
+        layouts.retain(|_, layout| Rc::strong_count(layout) > 1);
+        samplers.retain(|_, sampler| Rc::strong_count(sampler) > 1);
+        before - layouts.len() - samplers.len()
+    }
+
+    // Number of cached (bind group layouts, samplers).
+    pub fn counts(&self) -> (usize, usize) {
+        (self.layouts.borrow().len(), self.samplers.borrow().len())
+    }
+}
+
+impl Default for DeviceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
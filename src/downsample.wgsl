@@ -0,0 +1,23 @@
+// Halves the resolution with a 2x2 box filter.
+
+[[group(0), binding(0)]]
+var input: texture_2d<f32>;
+[[group(0), binding(1)]]
+var output: texture_storage_2d<rgba16float, write>;
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    let max_pos = textureDimensions(input) - vec2<i32>(1, 1);
+    let src = pos * 2;
+    let color = textureLoad(input, src, 0)
+        + textureLoad(input, min(src + vec2<i32>(1, 0), max_pos), 0)
+        + textureLoad(input, min(src + vec2<i32>(0, 1), max_pos), 0)
+        + textureLoad(input, min(src + vec2<i32>(1, 1), max_pos), 0);
+    textureStore(output, pos, color * 0.25);
+}
@@ -0,0 +1,390 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::rc::Rc;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::assets::MeshHandle;
+use crate::game::Event;
+use crate::gfx::GFX;
+use crate::mesh::{Mesh, VertexLayout};
+use crate::layers::RenderLayers;
+use crate::Vertex;
+
+// Dynamic meshes
+//======================
+// Geometry that changes every frame, like trails, ropes or the quads of CPU particles, is edited
+// on the CPU and uploaded before the frame is rendered:
+//
+//     let mut trail = DynamicMesh::<Vertex>::new(gfx, "Trail", VertexLayout::Full);
+//     gfx.add_renderable(trail.handle().clone(), material, RenderLayers::DEFAULT);
+//     ...
+//     trail.clear();
+//     let first = trail.push_vertices(&points);
+//     trail.push_indices(&[first, first + 1, first + 2]);
+//     trail.upload(gfx);
+//
+// An upload writes only what changed since the last one, with one `write_buffer` per buffer that
+// covers the modified range. When the vertices or indices no longer fit, the buffer is replaced
+// by one of the next power of two in size, so a mesh that grows a little every frame is rarely
+// reallocated, and everything is written. Buffers never shrink.
+//
+// Indices are 16-bit like those of the other meshes, so a dynamic mesh has at most 65536 vertices.
+// Its shape changes from frame to frame, so it has no bounds and is never culled.
+
+/// Vertices and indices room is made for at first.
+const MIN_CAPACITY: usize = 64;
+
+/// A mesh edited on the CPU and uploaded in parts, see above.
+pub struct DynamicMesh<V: bytemuck::Pod = Vertex> {
+    label: String,
+    layout: VertexLayout,
+    geometry: Geometry<V>,
+    vertex_buffer: Rc<wgpu::Buffer>,
+    index_buffer: Rc<wgpu::Buffer>,
+    vertex_capacity: usize, // Of the buffers, in vertices and indices.
+    index_capacity: usize,
+    uploaded_indices: usize, // Drawn by the mesh in the assets.
+    handle: MeshHandle,
+}
+
+impl<V: bytemuck::Pod> DynamicMesh<V> {
+    // An empty mesh, registered with the GFX. `layout` describes `V`.
+    pub fn new(gfx: &mut GFX, label: &str, layout: VertexLayout) -> DynamicMesh<V> {
+        // `write_buffer` copies multiples of 4 bytes, which wgpu requires of vertex strides anyway.
+        assert!(std::mem::size_of::<V>().is_multiple_of(4), "vertex size must be a multiple of 4 bytes");
+        let vertex_buffer = Rc::new(create_buffer::<V>(gfx.device(), label, MIN_CAPACITY, wgpu::BufferUsages::VERTEX));
+        let index_buffer = Rc::new(create_buffer::<u16>(gfx.device(), label, MIN_CAPACITY, wgpu::BufferUsages::INDEX));
+        let handle = gfx.add_mesh(Mesh {
+            vertex_buffer: vertex_buffer.clone(),
+            index_buffer: index_buffer.clone(),
+            num_indices: 0,
+            layout,
+            bounds: None,
+        });
+        DynamicMesh {
+            label: label.to_string(),
+            layout,
+            geometry: Geometry::new(label),
+            vertex_buffer,
+            index_buffer,
+            vertex_capacity: MIN_CAPACITY,
+            index_capacity: MIN_CAPACITY,
+            uploaded_indices: 0,
+            handle,
+        }
+    }
+
+    // For renderables that draw the mesh.
+    pub fn handle(&self) -> &MeshHandle {
+        &self.handle
+    }
+
+    pub fn vertices(&self) -> &[V] {
+        &self.geometry.vertices
+    }
+
+    pub fn indices(&self) -> &[u16] {
+        &self.geometry.indices
+    }
+
+    // Removes everything, keeping the buffers for the next frame's geometry.
+    pub fn clear(&mut self) {
+        self.geometry.clear();
+    }
+
+    // Keeps the first `vertices` and `indices`, e.g. to shorten a trail from its end.
+    pub fn truncate(&mut self, vertices: usize, indices: usize) {
+        self.geometry.truncate(vertices, indices);
+    }
+
+    // Appends vertices, returning the index of the first.
+    pub fn push_vertices(&mut self, vertices: &[V]) -> u16 {
+        self.geometry.push_vertices(vertices)
+    }
+
+    pub fn push_indices(&mut self, indices: &[u16]) {
+        self.geometry.push_indices(indices);
+    }
+
+    // The vertices in `range`, to change in place, e.g. to move the points of a rope.
+    pub fn vertices_mut(&mut self, range: Range<usize>) -> &mut [V] {
+        self.geometry.vertices_mut(range)
+    }
+
+    pub fn indices_mut(&mut self, range: Range<usize>) -> &mut [u16] {
+        self.geometry.indices_mut(range)
+    }
+
+    // Writes the changes to the GPU, growing the buffers if needed, see above.
+    pub fn upload(&mut self, gfx: &mut GFX) {
+        let geometry = &mut self.geometry;
+        let mut replaced = geometry.indices.len() != self.uploaded_indices;
+        let (device, queue) = (gfx.device(), gfx.queue());
+
+        if geometry.vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = geometry.vertices.len().next_power_of_two();
+            let buffer = create_buffer::<V>(device, &self.label, self.vertex_capacity, wgpu::BufferUsages::VERTEX);
+            self.vertex_buffer = Rc::new(buffer);
+            geometry.dirty_vertices = Some(0..geometry.vertices.len());
+            replaced = true;
+        }
+        if geometry.indices.len() > self.index_capacity {
+            self.index_capacity = geometry.indices.len().next_power_of_two();
+            let buffer = create_buffer::<u16>(device, &self.label, self.index_capacity, wgpu::BufferUsages::INDEX);
+            self.index_buffer = Rc::new(buffer);
+            geometry.dirty_indices = Some(0..geometry.indices.len());
+            replaced = true;
+        }
+
+        let (vertices, indices) = geometry.take_writes();
+        if let Some(range) = vertices {
+            let offset = (range.start * std::mem::size_of::<V>()) as wgpu::BufferAddress;
+            queue.write_buffer(&self.vertex_buffer, offset, bytemuck::cast_slice(&geometry.vertices[range]));
+        }
+        if let Some(range) = indices {
+            // An odd index at the end is padded with a 0; the capacity is a power of two, which
+            // leaves room for it.
+            let mut padded = geometry.indices[range.clone()].to_vec();
+            if !padded.len().is_multiple_of(2) {
+                padded.push(0);
+            }
+            let offset = (range.start * std::mem::size_of::<u16>()) as wgpu::BufferAddress;
+            queue.write_buffer(&self.index_buffer, offset, bytemuck::cast_slice(&padded));
+        }
+
+        // The mesh in the assets is swapped for one with the new buffers or index count.
+        if replaced {
+            self.uploaded_indices = geometry.indices.len();
+            let mesh = Mesh {
+                vertex_buffer: self.vertex_buffer.clone(),
+                index_buffer: self.index_buffer.clone(),
+                num_indices: geometry.indices.len() as u32,
+                layout: self.layout,
+                bounds: None,
+            };
+            gfx.set_mesh(&self.handle, mesh);
+        }
+    }
+}
+
+// The vertices and indices of a `DynamicMesh` on the CPU, and what changed since the last upload.
+struct Geometry<V> {
+    label: String,
+    vertices: Vec<V>,
+    indices: Vec<u16>,
+    dirty_vertices: Option<Range<usize>>, // Modified since the last upload.
+    dirty_indices: Option<Range<usize>>,
+}
+
+impl<V: Copy> Geometry<V> {
+    fn new(label: &str) -> Geometry<V> {
+        Geometry {
+            label: label.to_string(),
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            dirty_vertices: None,
+            dirty_indices: None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+        self.dirty_vertices = None;
+        self.dirty_indices = None;
+    }
+
+    // The changes marked past the new ends are left to `take_writes` to cut off.
+    fn truncate(&mut self, vertices: usize, indices: usize) {
+        self.vertices.truncate(vertices);
+        self.indices.truncate(indices);
+    }
+
+    fn push_vertices(&mut self, vertices: &[V]) -> u16 {
+        let first = self.vertices.len();
+        assert!(first + vertices.len() <= u16::MAX as usize + 1, "{}: too many vertices for 16-bit indices", self.label);
+        self.vertices.extend_from_slice(vertices);
+        mark(&mut self.dirty_vertices, first..self.vertices.len());
+        first as u16
+    }
+
+    fn push_indices(&mut self, indices: &[u16]) {
+        let first = self.indices.len();
+        self.indices.extend_from_slice(indices);
+        mark(&mut self.dirty_indices, first..self.indices.len());
+    }
+
+    fn vertices_mut(&mut self, range: Range<usize>) -> &mut [V] {
+        mark(&mut self.dirty_vertices, range.clone());
+        &mut self.vertices[range]
+    }
+
+    fn indices_mut(&mut self, range: Range<usize>) -> &mut [u16] {
+        mark(&mut self.dirty_indices, range.clone());
+        &mut self.indices[range]
+    }
+
+    // The vertices and indices an upload writes, `None` for nothing, and forgets the changes.
+    // Ranges may reach past the end after `truncate`, the writes end at it. Writes are in
+    // multiples of 4 bytes, so the indices are widened to whole pairs, but for an odd one at the end.
+    fn take_writes(&mut self) -> (Option<Range<usize>>, Option<Range<usize>>) {
+        let (vertices, indices) = (self.vertices.len(), self.indices.len());
+        let vertices = self.dirty_vertices.take().map(|range| range.start.min(vertices)..range.end.min(vertices));
+        let indices = self
+            .dirty_indices
+            .take()
+            .map(|range| (range.start & !1).min(indices)..((range.end + 1) & !1).min(indices));
+        (vertices.filter(|range| !range.is_empty()), indices.filter(|range| !range.is_empty()))
+    }
+}
+
+// Trail demo
+//======================
+
+const TRAIL_POINTS: usize = 64;
+const TRAIL_WIDTH: f32 = 0.08;
+
+// A ribbon trailing a point that loops around the pentagon. The ribbon grows by a segment a frame
+// until it is `TRAIL_POINTS` long; after that its indices stay as they are and only the vertices,
+// which all move along, are written again. C clears it.
+pub struct TrailDemo {
+    mesh: DynamicMesh<Vertex>,
+    points: VecDeque<Point3<f32>>, // The newest first.
+    segments: usize, // Indexed so far.
+    time: f32,
+    clear_requested: bool,
+}
+
+impl TrailDemo {
+    pub fn new(gfx: &mut GFX) -> TrailDemo {
+        let mesh = DynamicMesh::new(gfx, "Trail", VertexLayout::Full);
+        let material = gfx.default_material();
+        gfx.add_renderable(mesh.handle().clone(), material, RenderLayers::DEFAULT);
+        TrailDemo {
+            mesh,
+            points: VecDeque::with_capacity(TRAIL_POINTS + 1),
+            segments: 0,
+            time: 0.0,
+            clear_requested: false,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+        let t = self.time;
+        self.points.push_front(Point3::new(0.8 * (t * 1.3).sin(), 0.6 * (t * 2.1).sin(), 0.2 * (t * 0.7).cos()));
+        self.points.truncate(TRAIL_POINTS);
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        if *event == Event::KeyPressed(b'C' as u16) {
+            self.clear_requested = true;
+        }
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        if std::mem::take(&mut self.clear_requested) {
+            self.points.clear();
+            self.mesh.clear();
+            self.segments = 0;
+        }
+        // Two vertices per point, across the direction of the trail, narrowing and darkening
+        // towards its end.
+        let mut vertices = Vec::with_capacity(self.points.len() * 2);
+        for (i, &point) in self.points.iter().enumerate() {
+            let previous = self.points.get(i.saturating_sub(1)).copied().unwrap_or(point);
+            let next = self.points.get(i + 1).copied().unwrap_or(point);
+            let along = previous - next;
+            let across = if along.magnitude2() > 0.0 {
+                Vector3::new(-along.y, along.x, 0.0).normalize()
+            } else {
+                Vector3::unit_y()
+            };
+            let fade = 1.0 - i as f32 / TRAIL_POINTS as f32;
+            let side = across * TRAIL_WIDTH * fade;
+            let color = [fade, 0.6 * fade, 0.2 * fade];
+            vertices.push(Vertex {
+                position: (point + side).into(),
+                color,
+            });
+            vertices.push(Vertex {
+                position: (point - side).into(),
+                color,
+            });
+        }
+        let old = self.mesh.vertices().len();
+        self.mesh.vertices_mut(0..old).copy_from_slice(&vertices[..old]);
+        self.mesh.push_vertices(&vertices[old..]);
+        // Segment k joins points k and k + 1, drawn from both sides as the ribbon twists.
+        while self.segments + 1 < self.points.len() {
+            let v = (self.segments * 2) as u16;
+            self.mesh.push_indices(&[v, v + 1, v + 2, v + 2, v + 1, v + 3, v, v + 2, v + 1, v + 2, v + 3, v + 1]);
+            self.segments += 1;
+        }
+        self.mesh.upload(gfx);
+    }
+}
+
+// Grows `dirty` to cover `range` too.
+fn mark(dirty: &mut Option<Range<usize>>, range: Range<usize>) {
+    *dirty = Some(match dirty.take() {
+        Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+        None => range,
+    });
+}
+
+fn create_buffer<T>(device: &wgpu::Device, label: &str, capacity: usize, usage: wgpu::BufferUsages) -> wgpu::Buffer {
+    let kind = if usage.contains(wgpu::BufferUsages::INDEX) { "Index" } else { "Vertex" };
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("{} Dynamic {} Buffer", label, kind)),
+        size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_grow_to_cover_every_change() {
+        let mut dirty = None;
+        mark(&mut dirty, 4..6);
+        assert_eq!(dirty, Some(4..6));
+        mark(&mut dirty, 10..12);
+        assert_eq!(dirty, Some(4..12));
+        mark(&mut dirty, 0..1);
+        assert_eq!(dirty, Some(0..12));
+        mark(&mut dirty, 5..7);
+        assert_eq!(dirty, Some(0..12));
+    }
+
+    #[test]
+    fn writes_stay_inside_the_geometry_after_a_truncate() {
+        let mut geometry = Geometry::<[f32; 4]>::new("Test");
+        geometry.push_vertices(&[[0.0; 4]; 8]);
+        geometry.push_indices(&[0, 1, 2, 2, 1, 3, 4, 5, 6]);
+        assert_eq!(geometry.take_writes(), (Some(0..8), Some(0..9)));
+
+        // Changes past the new ends go with them.
+        geometry.vertices_mut(5..8)[0] = [1.0; 4];
+        geometry.indices_mut(6..9)[0] = 7;
+        geometry.truncate(6, 5);
+        geometry.vertices_mut(1..3)[1] = [2.0; 4];
+        geometry.indices_mut(3..4)[0] = 0;
+        let (vertices, indices) = geometry.take_writes();
+        assert_eq!(vertices, Some(1..6));
+        assert_eq!(indices, Some(2..5));
+
+        // Only the changes past the ends, nothing to write.
+        geometry.push_vertices(&[[3.0; 4]; 2]);
+        geometry.take_writes();
+        geometry.vertices_mut(6..8)[0] = [4.0; 4];
+        geometry.truncate(6, 5);
+        assert_eq!(geometry.take_writes(), (None, None));
+        assert_eq!(geometry.vertices.len(), 6);
+        assert_eq!(geometry.indices, [0, 1, 2, 0, 1]);
+    }
+}
@@ -0,0 +1,55 @@
+// egui meshes: triangles in points, with premultiplied sRGB vertex colors.
+
+struct Screen {
+    size: vec2<f32>;     // In points.
+    linear_output: f32;  // 1 when the target is an sRGB format, which expects linear colors.
+};
+
+[[group(0), binding(0)]]
+var<uniform> screen: Screen;
+[[group(1), binding(0)]]
+var t_texture: texture_2d<f32>;
+[[group(1), binding(1)]]
+var s_texture: sampler;
+
+struct VertexInput {
+    [[location(0)]] position: vec2<f32>;
+    [[location(1)]] uv: vec2<f32>;
+    [[location(2)]] color: vec4<f32>; // sRGB, premultiplied.
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+fn linear_from_srgb(srgb: vec3<f32>) -> vec3<f32> {
+    let cutoff = srgb < vec3<f32>(0.04045);
+    let lower = srgb / vec3<f32>(12.92);
+    let higher = pow((srgb + vec3<f32>(0.055)) / vec3<f32>(1.055), vec3<f32>(2.4));
+    return select(higher, lower, cutoff);
+}
+
+[[stage(vertex)]]
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    // Points have y pointing down, clip space has y pointing up.
+    out.clip_position = vec4<f32>(
+        vertex.position.x / screen.size.x * 2.0 - 1.0,
+        1.0 - vertex.position.y / screen.size.y * 2.0,
+        0.0,
+        1.0,
+    );
+    out.uv = vertex.uv;
+    out.color = vertex.color;
+    if (screen.linear_output > 0.5) {
+        out.color = vec4<f32>(linear_from_srgb(vertex.color.rgb), vertex.color.a);
+    }
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return in.color * textureSample(t_texture, s_texture, in.uv);
+}
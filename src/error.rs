@@ -1,35 +1,464 @@
+use std::panic::Location;
 use std::{fmt, error};
-use windows::core::Error;
+use windows::core::{Error as WindowsError, HRESULT};
+#[cfg(windows)]
+use windows::Win32::Foundation::PWSTR;
+#[cfg(windows)]
+use windows::Win32::System::Diagnostics::Debug::{
+    FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS,
+};
+
+use crate::assets::LoadError;
+use crate::reflection::ReflectError;
+
+// Errors
+//======================
+// Everything that can end `App::run` early is an `Error`. Lower layers keep their own error types
+// (`Win32Error`, `LoadError`, `ReflectError`, the wgpu errors) and convert with `?`.
+// `context` says what was being done when it failed, so a failure reads e.g.
+//
+//     cannot create the window: os error 0x80070578 at src/window.rs:188: Invalid window handle.
+//
+// The wgpu validation errors are collected by `GFX` instead of panicking, and reported by `App`.
+// wgpu 0.12 has no callback for a lost device: the device's next use fails with an error caused by
+// wgpu-core's `DeviceError::Lost`, which `Error::from_wgpu` turns into `DeviceLost`.
+//
+// `category` tells what can be done about an error, for `App` to decide between trying the next
+// frame, creating the graphics device again, and giving up:
+//
+//     Transient      goes away by itself, e.g. a surface that timed out or a GPU still busy
+//     DeviceRemoved  the GPU was reset, removed, or its driver updated or crashed
+//     User           bad input, e.g. a missing asset or a shader that doesn't compile
+//     Fatal          everything else, e.g. out of memory or a bug caught by validation
+//
+// Only transient errors are retryable as is: the other categories fail the same way on the next try.
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Os(#[from] Win32Error),
+    #[error("no graphics adapter can render to the window")]
+    NoAdapter,
+    #[error("cannot create the graphics device: {0}")]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+    #[error("wgpu validation error: {0}")]
+    Validation(String),
+    #[error("the graphics device was lost: {0}")]
+    DeviceLost(String),
+    #[error("surface error: {0}")]
+    Surface(#[from] wgpu::SurfaceError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Asset(#[from] LoadError),
+    #[error(transparent)]
+    Shader(#[from] ReflectError),
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+/// What can be done about an error, see above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Transient,
+    DeviceRemoved,
+    User,
+    Fatal,
+}
+
+impl Error {
+    // An error wgpu reports for a device: `DeviceLost` if wgpu-core's `DeviceError::Lost` is among
+    // its causes, a validation error otherwise. wgpu builds the chain from wgpu-core's types, so
+    // their versions must match, see Cargo.toml.
+    pub fn from_wgpu(error: &wgpu::Error) -> Error {
+        let mut source: Option<&(dyn error::Error + 'static)> = Some(error);
+        while let Some(cause) = source {
+            if is_device_lost(cause) {
+                return Error::DeviceLost(error.to_string());
+            }
+            source = cause.source();
+        }
+        Error::Validation(error.to_string())
+    }
+
+    // The category of the innermost error, as context doesn't change what went wrong.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Error::Os(e) => e.category(),
+            // No adapter now is no adapter on the next try either.
+            Error::NoAdapter => ErrorCategory::Fatal,
+            Error::RequestDevice(_) => ErrorCategory::Fatal,
+            Error::Validation(_) => ErrorCategory::Fatal,
+            Error::DeviceLost(_) => ErrorCategory::DeviceRemoved,
+            Error::Surface(e) => match e {
+                // `Lost` is fixed by configuring the surface again, which `App` does on resize.
+                wgpu::SurfaceError::Timeout | wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost => {
+                    ErrorCategory::Transient
+                }
+                wgpu::SurfaceError::OutOfMemory => ErrorCategory::Fatal,
+            },
+            Error::Io(e) => match e.kind() {
+                std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                    ErrorCategory::Transient
+                }
+                std::io::ErrorKind::NotFound
+                | std::io::ErrorKind::PermissionDenied
+                | std::io::ErrorKind::InvalidInput
+                | std::io::ErrorKind::InvalidData => ErrorCategory::User,
+                _ => ErrorCategory::Fatal,
+            },
+            Error::Asset(_) | Error::Shader(_) => ErrorCategory::User,
+            Error::Context { source, .. } => source.category(),
+        }
+    }
+
+    // Whether doing the same again may succeed, see above.
+    pub fn is_retryable(&self) -> bool {
+        self.category() == ErrorCategory::Transient
+    }
+}
+
+// wgpu-core's errors of the calls on a device hold `DeviceError` transparently, which hides it
+// from the chain of sources, so the ones wgpu reports are looked into as well.
+fn is_device_lost(cause: &(dyn error::Error + 'static)) -> bool {
+    use wgpu_core::device::DeviceError::{self, Lost};
+    use wgpu_core::{binding_model as b, pipeline as p, resource as r};
+    if let Some(Lost) = cause.downcast_ref::<DeviceError>() {
+        return true;
+    }
+    macro_rules! lost_in {
+        ($($ty:ty => $variant:path),* $(,)?) => {
+            $(if let Some($variant(Lost)) = cause.downcast_ref::<$ty>() {
+                return true;
+            })*
+        };
+    }
+    lost_in! {
+        r::CreateBufferError => r::CreateBufferError::Device,
+        r::CreateTextureError => r::CreateTextureError::Device,
+        r::CreateSamplerError => r::CreateSamplerError::Device,
+        r::CreateQuerySetError => r::CreateQuerySetError::Device,
+        r::BufferAccessError => r::BufferAccessError::Device,
+        b::CreateBindGroupLayoutError => b::CreateBindGroupLayoutError::Device,
+        b::CreateBindGroupError => b::CreateBindGroupError::Device,
+        b::CreatePipelineLayoutError => b::CreatePipelineLayoutError::Device,
+        p::CreateShaderModuleError => p::CreateShaderModuleError::Device,
+        p::CreateComputePipelineError => p::CreateComputePipelineError::Device,
+        p::CreateRenderPipelineError => p::CreateRenderPipelineError::Device,
+    }
+    false
+}
+
+/// Attaches what was being done to an error, see above.
+pub trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T>;
+
+    // For context that is expensive to format, built only on failure.
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for core::result::Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|e| Error::Context {
+            context: context.into(),
+            source: Box::new(e.into()),
+        })
+    }
+
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|e| Error::Context {
+            context: context().into(),
+            source: Box::new(e.into()),
+        })
+    }
+}
 
 /// The error type for when the OS cannot perform the requested operation.
+// Captures the HRESULT and the system's description of it right away, while the thread's error
+// state still belongs to this failure, and the place in our code that reported it.
 #[derive(Debug)]
 pub struct Win32Error {
-    line: u32,
-    file: &'static str,
-    error: Error,
+    location: &'static Location<'static>,
+    error: Cause,
+}
+
+#[derive(Debug)]
+enum Cause {
+    Os {
+        hresult: HRESULT,
+        description: String, // From FormatMessageW.
+    },
+    // From the other platforms, where `WindowsError` cannot be built (it allocates through Win32).
+    Message(String),
 }
 
 impl Win32Error {
-    #[allow(dead_code)]
-    pub(crate) fn new(line: u32, file: &'static str, error: Error) -> Win32Error {
-        Win32Error { line, file, error }
+    // The location is the caller's, or where `win_error!` was used.
+    #[track_caller]
+    pub(crate) fn new(error: WindowsError) -> Win32Error {
+        let hresult = error.code();
+        let description = describe(hresult).unwrap_or_else(|| error.message().to_string());
+        Win32Error {
+            location: Location::caller(),
+            error: Cause::Os { hresult, description },
+        }
+    }
+
+    // From `GetLastError`, for the functions that report failure that way.
+    #[track_caller]
+    pub fn last() -> Win32Error {
+        Win32Error::new(WindowsError::from_win32())
+    }
+
+    #[track_caller]
+    pub fn message(message: String) -> Win32Error {
+        Win32Error {
+            location: Location::caller(),
+            error: Cause::Message(message),
+        }
+    }
+
+    // `None` for errors from other platforms.
+    pub fn hresult(&self) -> Option<HRESULT> {
+        match &self.error {
+            Cause::Os { hresult, .. } => Some(*hresult),
+            Cause::Message(_) => None,
+        }
+    }
+
+    // Errors from other platforms carry no code to tell, and count as fatal.
+    pub fn category(&self) -> ErrorCategory {
+        match self.hresult() {
+            Some(hresult) => categorize_hresult(hresult),
+            None => ErrorCategory::Fatal,
+        }
+    }
+
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+// So that `?` records where the error was propagated from.
+impl From<WindowsError> for Win32Error {
+    #[track_caller]
+    fn from(error: WindowsError) -> Self {
+        Win32Error::new(error)
     }
 }
 
 #[allow(unused_macros)]
 macro_rules! win_error {
     ($error:expr) => {{
-            crate::error::Win32Error::new(line!(), file!(), $error)
+            crate::error::Win32Error::new($error)
+        }};
+}
+
+#[allow(unused_macros)]
+macro_rules! platform_error {
+    ($message:expr) => {{
+            crate::error::Win32Error::message($message.to_string())
         }};
 }
 
 impl fmt::Display for Win32Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        f.pad(&format!(
-            "os error at {}:{}: {}",
-            self.file, self.line, self.error
-        ))
+        let (file, line) = (self.location.file(), self.location.line());
+        match &self.error {
+            Cause::Os { hresult, description, .. } => f.pad(&format!(
+                "os error {:#010X} at {}:{}: {}",
+                hresult.0 as u32, file, line, description
+            )),
+            Cause::Message(message) => f.pad(&format!("os error at {}:{}: {}", file, line, message)),
+        }
     }
 }
 
 impl error::Error for Win32Error {}
+
+// HRESULT codes
+//======================
+// The ones `categorize_hresult` knows, spelled out so they don't need the DXGI bindings.
+
+const DXGI_ERROR_DEVICE_REMOVED: u32 = 0x887A_0005;
+const DXGI_ERROR_DEVICE_HUNG: u32 = 0x887A_0006;
+const DXGI_ERROR_DEVICE_RESET: u32 = 0x887A_0007;
+const DXGI_ERROR_WAS_STILL_DRAWING: u32 = 0x887A_000A;
+const DXGI_ERROR_DRIVER_INTERNAL_ERROR: u32 = 0x887A_0020;
+const DXGI_ERROR_NOT_CURRENTLY_AVAILABLE: u32 = 0x887A_0022;
+const D3DDDIERR_DEVICEREMOVED: u32 = 0x8876_0870;
+const E_PENDING: u32 = 0x8000_000A;
+const E_ACCESSDENIED: u32 = 0x8007_0005;
+const E_INVALIDARG: u32 = 0x8007_0057;
+const HRESULT_FILE_NOT_FOUND: u32 = 0x8007_0002; // ERROR_FILE_NOT_FOUND
+const HRESULT_PATH_NOT_FOUND: u32 = 0x8007_0003; // ERROR_PATH_NOT_FOUND
+const HRESULT_BUSY: u32 = 0x8007_00AA; // ERROR_BUSY
+const HRESULT_TIMEOUT: u32 = 0x8007_05B4; // ERROR_TIMEOUT
+
+// What can be done about a failed Win32 or DXGI call, see above. Codes it doesn't know are fatal.
+pub fn categorize_hresult(hresult: HRESULT) -> ErrorCategory {
+    match hresult.0 as u32 {
+        DXGI_ERROR_DEVICE_REMOVED
+        | DXGI_ERROR_DEVICE_HUNG
+        | DXGI_ERROR_DEVICE_RESET
+        | DXGI_ERROR_DRIVER_INTERNAL_ERROR
+        | D3DDDIERR_DEVICEREMOVED => ErrorCategory::DeviceRemoved,
+        DXGI_ERROR_WAS_STILL_DRAWING
+        | DXGI_ERROR_NOT_CURRENTLY_AVAILABLE
+        | E_PENDING
+        | HRESULT_BUSY
+        | HRESULT_TIMEOUT => ErrorCategory::Transient,
+        E_ACCESSDENIED | E_INVALIDARG | HRESULT_FILE_NOT_FOUND | HRESULT_PATH_NOT_FOUND => ErrorCategory::User,
+        _ => ErrorCategory::Fatal,
+    }
+}
+
+// The system's text for `hresult`, without the trailing line break. `None` for codes it doesn't know.
+#[cfg(windows)]
+fn describe(hresult: HRESULT) -> Option<String> {
+    let mut buffer = [0u16; 512];
+    let length = unsafe {
+        FormatMessageW(
+            FORMAT_MESSAGE_FROM_SYSTEM | FORMAT_MESSAGE_IGNORE_INSERTS,
+            std::ptr::null(),
+            hresult.0 as u32,
+            0,
+            PWSTR(buffer.as_mut_ptr()),
+            buffer.len() as u32,
+            std::ptr::null(),
+        )
+    };
+    if length == 0 {
+        return None;
+    }
+    Some(String::from_utf16_lossy(&buffer[..length as usize]).trim_end().to_string())
+}
+
+#[cfg(not(windows))]
+fn describe(_hresult: HRESULT) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_errors_have_no_code_and_are_fatal() {
+        let line = line!() + 1;
+        let error = platform_error!("cannot open the X display");
+        assert_eq!((error.location().file(), error.location().line()), (file!(), line));
+        assert_eq!(error.hresult(), None);
+        assert_eq!(error.category(), ErrorCategory::Fatal);
+        assert!(error.to_string().ends_with(": cannot open the X display"), "{}", error);
+    }
+
+    // As wgpu reports the failed use of a device: the cause wrapped in what was being done.
+    fn wgpu_error(cause: impl error::Error + Send + Sync + 'static) -> wgpu::Error {
+        wgpu::Error::Validation {
+            source: Box::new(wgpu_core::error::ContextError {
+                string: "Queue::submit",
+                cause: Box::new(cause),
+                label_key: "label",
+                label: String::new(),
+            }),
+            description: "Validation Error".to_string(),
+        }
+    }
+
+    #[test]
+    fn a_lost_device_is_told_by_its_cause() {
+        let lost = Error::from_wgpu(&wgpu_error(wgpu_core::device::DeviceError::Lost));
+        assert!(matches!(lost, Error::DeviceLost(_)));
+        assert_eq!(lost.category(), ErrorCategory::DeviceRemoved);
+        let invalid = Error::from_wgpu(&wgpu_error(wgpu_core::device::DeviceError::Invalid));
+        assert!(matches!(invalid, Error::Validation(_)));
+        assert_eq!(invalid.category(), ErrorCategory::Fatal);
+        // Not by its message.
+        assert_eq!(Error::Validation("buffer lost its mapping".into()).category(), ErrorCategory::Fatal);
+    }
+
+    #[test]
+    fn a_lost_device_is_told_through_the_errors_of_calls() {
+        use wgpu_core::device::DeviceError;
+        use wgpu_core::resource::CreateBufferError;
+        // wgpu's error for a buffer created on a lost device: `DeviceError` is not among the sources.
+        let error = wgpu_error(CreateBufferError::Device(DeviceError::Lost));
+        assert!(matches!(Error::from_wgpu(&error), Error::DeviceLost(_)));
+        let error = wgpu_error(CreateBufferError::Device(DeviceError::OutOfMemory));
+        assert!(matches!(Error::from_wgpu(&error), Error::Validation(_)));
+    }
+
+    // A validation error of a real device, which wgpu builds from wgpu-core's errors: it only
+    // reaches them if wgpu uses the same wgpu-core, see Cargo.toml. Ignored as it needs an adapter.
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn wgpu_reports_the_errors_of_its_wgpu_core() {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default())).expect("no adapter");
+        let (device, _queue) = pollster::block_on(adapter.request_device(&Default::default(), None)).unwrap();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        // Buffers cannot be mapped both for reading and writing.
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 4,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::MAP_WRITE,
+            mapped_at_creation: false,
+        });
+        let error = pollster::block_on(device.pop_error_scope()).expect("no validation error");
+        let mut source: Option<&(dyn error::Error + 'static)> = Some(&error);
+        let mut causes = Vec::new();
+        while let Some(cause) = source {
+            causes.push(cause);
+            source = cause.source();
+        }
+        assert!(causes.iter().any(|cause| cause.is::<wgpu_core::resource::CreateBufferError>()));
+        assert!(matches!(Error::from_wgpu(&error), Error::Validation(_)));
+    }
+
+    #[test]
+    fn surface_errors_other_than_out_of_memory_are_retryable() {
+        for e in [wgpu::SurfaceError::Timeout, wgpu::SurfaceError::Outdated, wgpu::SurfaceError::Lost] {
+            assert!(Error::Surface(e).is_retryable());
+        }
+        assert_eq!(Error::Surface(wgpu::SurfaceError::OutOfMemory).category(), ErrorCategory::Fatal);
+    }
+
+    #[test]
+    fn io_and_asset_errors_are_the_user_s_or_transient() {
+        let io = |kind| Error::Io(std::io::Error::from(kind)).category();
+        assert_eq!(io(std::io::ErrorKind::NotFound), ErrorCategory::User);
+        assert_eq!(io(std::io::ErrorKind::InvalidData), ErrorCategory::User);
+        assert_eq!(io(std::io::ErrorKind::TimedOut), ErrorCategory::Transient);
+        assert_eq!(io(std::io::ErrorKind::OutOfMemory), ErrorCategory::Fatal);
+        assert_eq!(Error::Shader(ReflectError::Parse("expected ';'".into())).category(), ErrorCategory::User);
+        assert_eq!(Error::NoAdapter.category(), ErrorCategory::Fatal);
+    }
+
+    #[test]
+    fn context_keeps_the_category_of_its_source() {
+        let result: Result<()> = Err(Error::Surface(wgpu::SurfaceError::Timeout));
+        let e = result.context("cannot render").context("frame 12").unwrap_err();
+        assert_eq!(e.category(), ErrorCategory::Transient);
+        assert_eq!(e.to_string(), "frame 12: cannot render: surface error: A timeout was encountered while trying to acquire the next frame");
+        let lost: Result<()> = Err(Error::DeviceLost("parent device is lost".into()));
+        assert_eq!(lost.context("cannot submit").unwrap_err().category(), ErrorCategory::DeviceRemoved);
+    }
+
+    #[test]
+    fn hresults_map_to_their_categories() {
+        assert_eq!(categorize_hresult(HRESULT(DXGI_ERROR_DEVICE_REMOVED as i32)), ErrorCategory::DeviceRemoved);
+        assert_eq!(categorize_hresult(HRESULT(DXGI_ERROR_DEVICE_RESET as i32)), ErrorCategory::DeviceRemoved);
+        assert_eq!(categorize_hresult(HRESULT(DXGI_ERROR_WAS_STILL_DRAWING as i32)), ErrorCategory::Transient);
+        assert_eq!(categorize_hresult(HRESULT(HRESULT_FILE_NOT_FOUND as i32)), ErrorCategory::User);
+        assert_eq!(categorize_hresult(HRESULT(0x8000_4005u32 as i32)), ErrorCategory::Fatal); // E_FAIL
+        // Errors without a code, from other platforms.
+        assert_eq!(Error::Os(Win32Error::message("no display".into())).category(), ErrorCategory::Fatal);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::bloom::{self, BloomSettings, BLOOM_TARGET};
+use crate::compute_kernels::{AverageLuminanceNode, HistogramNode};
+use crate::reflection::ShaderReflection;
+use crate::render_graph::{NodeContext, NodeParameter, RenderGraph, RenderNode};
+use crate::uniform::UniformLayout;
+
+// HDR and automatic exposure
+//======================
+// With `GfxOptions::exposure`, the scene is rendered into the float texture `scene.hdr` of the
+// render graph instead of the frame, so lights and emissive surfaces can be brighter than white.
+// Three nodes, added first to the graph, turn it into the frame:
+//
+//     scene.hdr -> Luminance Histogram -> Average Luminance -> Tonemap -> frame
+//
+// The histogram and the average are the compute kernels of compute_kernels.rs; the average moves a
+// little towards the current frame's every frame, like eyes adapting to the dark. The tonemap node
+// exposes the scene so its average lands on middle gray, within the EV range of the settings, and
+// compresses it into the frame with a filmic curve. A fixed exposure is a range of a single EV.
+// With `bloom`, the bloom nodes of bloom.rs run before the tonemap, which adds their glow.
+//
+// Nodes added by the game run after the tonemap node unless they read its inputs, so post effects
+// work on the tonemapped frame as without HDR, and nodes reading or writing `scene.hdr` see the
+// HDR scene. The text overlay is drawn after all of them, and not exposed.
+
+/// The render graph texture the scene is rendered into with HDR.
+pub const HDR_TARGET: &str = "scene.hdr";
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const HISTOGRAM: &str = "exposure.histogram";
+pub(crate) const LUMINANCE: &str = "exposure.luminance";
+
+/// How the HDR scene is exposed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExposureSettings {
+    // The range of exposures, in EV100: the darkest scenes are exposed like `min_ev`, the
+    // brightest like `max_ev`.
+    pub min_ev: f32,
+    pub max_ev: f32,
+    pub compensation: f32, // In EV, added on top, brighter when positive.
+    // Fraction of the way from the previous average luminance to the current frame's, every frame.
+    pub adaptation: f32,
+    pub bloom: Option<BloomSettings>, // None for no bloom.
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        ExposureSettings {
+            min_ev: -4.0,
+            max_ev: 12.0,
+            compensation: 0.0,
+            adaptation: 0.05,
+            bloom: None,
+        }
+    }
+}
+
+impl ExposureSettings {
+    // Always exposed like `ev`.
+    pub fn fixed(ev: f32) -> ExposureSettings {
+        ExposureSettings {
+            min_ev: ev,
+            max_ev: ev,
+            ..Default::default()
+        }
+    }
+}
+
+// Adds the nodes turning `scene.hdr` into the frame, see above.
+pub fn add_nodes(graph: &mut RenderGraph, settings: ExposureSettings) {
+    // The histogram covers the luminances of the EV range: EV100 0 is a luminance of 0.125.
+    let min_log_lum = settings.min_ev - 3.0;
+    let log_lum_range = (settings.max_ev - settings.min_ev).max(1.0);
+    graph.add_node(Box::new(HistogramNode::new(HDR_TARGET, HISTOGRAM, min_log_lum, log_lum_range)));
+    let mut average = AverageLuminanceNode::new(HISTOGRAM, HDR_TARGET, LUMINANCE, min_log_lum, log_lum_range);
+    average.adaptation = settings.adaptation;
+    graph.add_node(Box::new(average));
+    if let Some(bloom) = settings.bloom {
+        bloom::add_nodes(graph, settings, bloom);
+    }
+    graph.add_node(Box::new(TonemapNode::new(settings)));
+}
+
+uniform_struct! {
+    struct TonemapParams {
+        min_ev: f32,
+        max_ev: f32,
+        compensation: f32,
+        bloom_intensity: f32,
+    }
+}
+assert_uniform_size!(TonemapParams, 16);
+
+// The pipeline for one frame format.
+struct TonemapState {
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    no_bloom: wgpu::TextureView, // Black, bound without bloom.
+}
+
+/// Exposes `scene.hdr` by the average luminance and tonemaps it into the frame.
+pub struct TonemapNode {
+    settings: ExposureSettings,
+    state: Option<TonemapState>,
+}
+
+impl TonemapNode {
+    pub fn new(settings: ExposureSettings) -> TonemapNode {
+        TonemapNode { settings, state: None }
+    }
+
+    fn create_state(device: &wgpu::Device, format: wgpu::TextureFormat) -> TonemapState {
+        let wgsl = include_str!("tonemap.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("Tonemap"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemap Params"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Tonemap Bloom Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let no_bloom = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("No Bloom"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        TonemapState {
+            pipeline,
+            format,
+            bind_group_layout,
+            params,
+            sampler,
+            no_bloom,
+        }
+    }
+}
+
+impl RenderNode for TonemapNode {
+    fn name(&self) -> &str {
+        "Tonemap"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        match self.settings.bloom {
+            Some(_) => vec![HDR_TARGET, LUMINANCE, BLOOM_TARGET],
+            None => vec![HDR_TARGET, LUMINANCE],
+        }
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (view, scene, luminance) = match (ctx.frame.view, ctx.resources.texture(HDR_TARGET), ctx.resources.buffer(LUMINANCE)) {
+            (Some(view), Some(scene), Some(luminance)) => (view, scene, luminance),
+            _ => return,
+        };
+        let format = ctx.frame.format;
+        if self.state.as_ref().is_none_or(|state| state.format != format) {
+            self.state = Some(Self::create_state(ctx.device, format));
+        }
+        let state = self.state.as_ref().unwrap();
+        let params = TonemapParams {
+            min_ev: self.settings.min_ev,
+            max_ev: self.settings.max_ev.max(self.settings.min_ev),
+            compensation: self.settings.compensation,
+            bloom_intensity: self.settings.bloom.map_or(0.0, |bloom| bloom.intensity),
+        };
+        ctx.queue.write_buffer(&state.params, 0, &params.to_uniform_bytes());
+        let bloom = match (self.settings.bloom, ctx.resources.texture(BLOOM_TARGET)) {
+            (Some(_), Some(bloom)) => &bloom.view,
+            _ => &state.no_bloom,
+        };
+        // The scene texture is recreated when the frame is resized.
+        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap Bind Group"),
+            layout: &state.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: state.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&scene.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: luminance.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(bloom),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&state.sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&state.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn parameters(&self) -> Vec<NodeParameter> {
+        let mut parameters = vec![
+            NodeParameter::new("min_ev", self.settings.min_ev, -8.0..=16.0),
+            NodeParameter::new("max_ev", self.settings.max_ev, -8.0..=16.0),
+            NodeParameter::new("compensation", self.settings.compensation, -4.0..=4.0),
+        ];
+        if let Some(bloom) = self.settings.bloom {
+            parameters.push(NodeParameter::new("bloom_intensity", bloom.intensity, 0.0..=2.0));
+        }
+        parameters
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "min_ev" => self.settings.min_ev = value,
+            "max_ev" => self.settings.max_ev = value,
+            "compensation" => self.settings.compensation = value,
+            "bloom_intensity" => {
+                if let Some(bloom) = &mut self.settings.bloom {
+                    bloom.intensity = value;
+                }
+            }
+            _ => {}
+        }
+    }
+}
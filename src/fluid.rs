@@ -0,0 +1,535 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::compute::ComputeKernel;
+use crate::game::{Event, MouseButton};
+use crate::gfx::GFX;
+use crate::reflection::ShaderReflection;
+use crate::render_graph::{NodeContext, RenderNode, Stage};
+use crate::uniform::UniformLayout;
+
+// Fluid simulation
+//======================
+// Smoke-like dye carried by an incompressible 2D fluid, after Jos Stam's "Stable Fluids":
+//
+//     learn-wgpu --fluid
+//
+// Dragging with the left mouse button pushes the fluid along and injects dye of changing colors.
+// The fields live in `rgba16float` textures of the render graph, a texel for every `scale` x
+// `scale` pixels of the frame. Each frame, before the scene, `FluidSimulation` advances the
+// fluid by `dt` with the passes of fluid.wgsl:
+//
+//     splat              adds the forces and dye of the mouse drags since the last frame
+//     advect             carries the velocity along itself
+//     diffuse            spreads the velocity by the viscosity, with Jacobi iterations
+//     divergence         measures how much flows out of every cell
+//     pressure           solves for the pressure that cancels it, with Jacobi iterations
+//     subtract_gradient  removes the pressure gradient, leaving a divergence-free velocity
+//     advect             carries the dye along the velocity
+//
+// Every pass reads one texture and writes another, so fields have two that take turns.
+// `FluidDraw` then shows the dye over the whole frame. The fields start over, empty, when the
+// frame changes size.
+
+pub const VELOCITY: [&str; 2] = ["fluid.velocity.0", "fluid.velocity.1"];
+pub const DYE: [&str; 2] = ["fluid.dye.0", "fluid.dye.1"];
+pub const PRESSURE: [&str; 2] = ["fluid.pressure.0", "fluid.pressure.1"];
+pub const DIVERGENCE: &str = "fluid.divergence";
+// The velocity before diffusion, the right-hand side of its Jacobi iterations.
+const DIFFUSION_INPUT: &str = "fluid.diffusion_input";
+const SPLATS: &str = "fluid.splats";
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+
+/// Splats past this many in a frame are dropped.
+const MAX_SPLATS: usize = 32;
+
+/// See above. Distances are in cells, times in seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct FluidSettings {
+    pub scale: u32, // Frame pixels per cell, along each side.
+    pub dt: f32, // Simulated time per frame.
+    pub viscosity: f32, // 0 skips the diffusion.
+    pub velocity_dissipation: f32, // How fast motion dies down, per second.
+    pub dye_dissipation: f32,
+    pub diffusion_iterations: u32,
+    pub pressure_iterations: u32, // More make the fluid more incompressible, at a cost.
+    pub splat_radius: f32,
+}
+
+impl Default for FluidSettings {
+    fn default() -> Self {
+        FluidSettings {
+            scale: 4,
+            dt: 1.0 / 60.0,
+            viscosity: 0.5,
+            velocity_dissipation: 0.2,
+            dye_dissipation: 0.3,
+            diffusion_iterations: 10,
+            pressure_iterations: 40,
+            splat_radius: 4.0,
+        }
+    }
+}
+
+/// A push on the fluid, as in fluid.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Splat {
+    pub position: [f32; 2], // From 0 to 1 across the frame.
+    pub force: [f32; 2], // Added velocity, in frame widths and heights per second.
+    pub color: [f32; 4], // Added dye.
+}
+
+// What `Fluid` passes to the nodes.
+#[derive(Default)]
+struct Shared {
+    splats: Vec<Splat>,
+    dye: usize, // Index in `DYE` of the current dye.
+}
+
+// Fluid API
+//======================
+
+/// The fluid, as seen from the game, see above.
+pub struct Fluid {
+    settings: FluidSettings,
+    shared: Rc<RefCell<Shared>>,
+    cursor: Option<(f32, f32)>, // In window pixels.
+    dragging: bool,
+    last: Option<(f32, f32)>, // Where the drag was at the last frame, from 0 to 1 across the frame.
+    hue: f32, // Of the next dye, in turns.
+}
+
+impl Fluid {
+    pub fn new(gfx: &mut GFX, settings: FluidSettings) -> Fluid {
+        tracing::info!("Fluid simulation with {} pressure iterations", settings.pressure_iterations);
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        gfx.add_render_node(Box::new(FluidSimulation::new(settings, shared.clone())));
+        gfx.add_render_node(Box::new(FluidDraw::new(shared.clone())));
+        Fluid {
+            settings,
+            shared,
+            cursor: None,
+            dragging: false,
+            last: None,
+            hue: 0.0,
+        }
+    }
+
+    // Applied at the next frame.
+    pub fn splat(&mut self, splat: Splat) {
+        self.shared.borrow_mut().splats.push(splat);
+    }
+
+    // Call from `Game::on_event`, for the mouse drags.
+    pub fn on_event(&mut self, event: &Event) {
+        match *event {
+            Event::MouseMoved { x, y } => self.cursor = Some((x as f32, y as f32)),
+            Event::MousePressed(MouseButton::Left) => self.dragging = true,
+            Event::MouseReleased(MouseButton::Left) | Event::FocusLost => {
+                self.dragging = false;
+                self.last = None;
+            }
+            _ => {}
+        }
+    }
+
+    // Call from `Game::render`: splats along the drag since the last frame.
+    pub fn render(&mut self, gfx: &GFX) {
+        if !self.dragging {
+            return;
+        }
+        let (width, height) = gfx.render_size();
+        let position = match self.cursor.and_then(|(x, y)| gfx.window_to_virtual(x, y)) {
+            Some((x, y)) => (x / width.max(1) as f32, y / height.max(1) as f32),
+            None => return,
+        };
+        let last = self.last.replace(position).unwrap_or(position);
+        let force = [(position.0 - last.0) / self.settings.dt, (position.1 - last.1) / self.settings.dt];
+        self.hue = (self.hue + 0.002).fract();
+        let [r, g, b] = hue_to_rgb(self.hue);
+        self.splat(Splat {
+            position: [position.0, position.1],
+            force,
+            color: [r * 0.3, g * 0.3, b * 0.3, 1.0],
+        });
+    }
+}
+
+// A fully saturated color.
+fn hue_to_rgb(hue: f32) -> [f32; 3] {
+    let channel = |offset: f32| (((hue + offset).fract() * 6.0 - 3.0).abs() - 1.0).clamp(0.0, 1.0);
+    [channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0)]
+}
+
+// Simulation
+//======================
+
+uniform_struct! {
+    struct PassParams {
+        dt: f32,
+        dissipation: f32,
+        alpha: f32,
+        r_beta: f32,
+        splat_count: u32,
+        splat_radius: f32,
+    }
+}
+assert_uniform_size!(PassParams, 24);
+
+// Which of `Kernels::params` a pass uses. Queue writes all land before the frame runs, so passes
+// that need different values need different buffers.
+#[derive(Clone, Copy)]
+enum Params {
+    AdvectVelocity,
+    AdvectDye,
+    Diffuse,
+    Pressure,
+    Other,
+}
+
+// The kernels, one per entry point of fluid.wgsl, created on the first run.
+struct Kernels {
+    advect: ComputeKernel,
+    splat_velocity: ComputeKernel,
+    splat_dye: ComputeKernel,
+    jacobi: ComputeKernel,
+    divergence: ComputeKernel,
+    subtract_gradient: ComputeKernel,
+    sampler: wgpu::Sampler,
+    params: [wgpu::Buffer; 5], // Indexed by `Params`.
+}
+
+/// Advances the fluid one frame, before the scene, see above.
+pub struct FluidSimulation {
+    settings: FluidSettings,
+    shared: Rc<RefCell<Shared>>,
+    kernels: Option<Kernels>,
+    size: (u32, u32),
+    velocity: usize, // Index in `VELOCITY` of the current velocity.
+    pressure: usize,
+}
+
+impl FluidSimulation {
+    fn new(settings: FluidSettings, shared: Rc<RefCell<Shared>>) -> FluidSimulation {
+        FluidSimulation {
+            settings,
+            shared,
+            kernels: None,
+            size: (0, 0),
+            velocity: 0,
+            pressure: 0,
+        }
+    }
+
+    fn create_kernels(device: &wgpu::Device) -> Kernels {
+        // The shader is part of the crate, so failing to build it is a bug.
+        let kernel = |entry_point: &str| {
+            let label = format!("Fluid {}", entry_point);
+            ComputeKernel::new(device, &label, include_str!("fluid.wgsl"), entry_point).expect("built-in kernel is valid")
+        };
+        let params = |label: &str| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: PassParams::SIZE as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        Kernels {
+            advect: kernel("advect"),
+            splat_velocity: kernel("splat_velocity"),
+            splat_dye: kernel("splat_dye"),
+            jacobi: kernel("jacobi"),
+            divergence: kernel("divergence"),
+            subtract_gradient: kernel("subtract_gradient"),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Fluid Sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            params: [
+                params("Fluid Advect Velocity Params"),
+                params("Fluid Advect Dye Params"),
+                params("Fluid Diffuse Params"),
+                params("Fluid Pressure Params"),
+                params("Fluid Params"),
+            ],
+        }
+    }
+
+    // The parameters of each of `Params`.
+    fn pass_params(&self, splat_count: usize) -> [PassParams; 5] {
+        let settings = &self.settings;
+        let params = |dissipation: f32, alpha: f32, r_beta: f32| PassParams {
+            dt: settings.dt,
+            dissipation,
+            alpha,
+            r_beta,
+            splat_count: splat_count as u32,
+            splat_radius: settings.splat_radius,
+        };
+        let alpha = 1.0 / (settings.viscosity * settings.dt).max(1e-6);
+        [
+            params(settings.velocity_dissipation, 0.0, 0.0),
+            params(settings.dye_dissipation, 0.0, 0.0),
+            params(0.0, alpha, 1.0 / (4.0 + alpha)),
+            params(0.0, -1.0, 0.25),
+            params(0.0, 0.0, 0.0),
+        ]
+    }
+}
+
+// Records one pass of a kernel, from `source` (and `aux`) into `output`.
+#[allow(clippy::too_many_arguments)]
+fn dispatch(
+    ctx: &NodeContext,
+    encoder: &mut wgpu::CommandEncoder,
+    kernels: &Kernels,
+    kernel: &ComputeKernel,
+    params: Params,
+    source: &str,
+    aux: &str,
+    output: &str,
+) {
+    let view = |name: &str| wgpu::BindingResource::TextureView(&ctx.resources.texture(name).unwrap().view);
+    let entries = [
+        wgpu::BindGroupEntry {
+            binding: 0,
+            resource: kernels.params[params as usize].as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&kernels.sampler),
+        },
+        wgpu::BindGroupEntry { binding: 2, resource: view(source) },
+        wgpu::BindGroupEntry { binding: 3, resource: view(aux) },
+        wgpu::BindGroupEntry { binding: 4, resource: view(output) },
+        wgpu::BindGroupEntry {
+            binding: 5,
+            resource: ctx.resources.buffer(SPLATS).unwrap().as_entire_binding(),
+        },
+    ];
+    let bind_group = kernel.bind_group(ctx.device, 0, &entries).expect("fluid textures match the kernel");
+    let size = ctx.resources.texture(output).unwrap();
+    kernel.dispatch_2d(encoder, &[&bind_group], WORKGROUP_SIZE, size.width, size.height);
+}
+
+impl RenderNode for FluidSimulation {
+    fn name(&self) -> &str {
+        "Fluid Simulation"
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::BeforeScene
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        let mut writes = [VELOCITY, DYE, PRESSURE].concat();
+        writes.extend([DIVERGENCE, DIFFUSION_INPUT]);
+        writes
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let scale = self.settings.scale.max(1);
+        let size = ((ctx.frame.size.0 / scale).max(1), (ctx.frame.size.1 / scale).max(1));
+        if size != self.size {
+            // New textures start out zeroed: no motion, no dye.
+            for name in [VELOCITY, DYE, PRESSURE].concat().into_iter().chain([DIVERGENCE, DIFFUSION_INPUT]) {
+                ctx.resources.ensure_texture(ctx.device, name, size.0, size.1, FORMAT);
+            }
+            self.size = size;
+        }
+        let splat_size = (MAX_SPLATS * std::mem::size_of::<Splat>()) as wgpu::BufferAddress;
+        ctx.resources.ensure_buffer(ctx.device, SPLATS, splat_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        if self.kernels.is_none() {
+            self.kernels = Some(Self::create_kernels(ctx.device));
+        }
+
+        let shared = self.shared.clone();
+        let mut shared = shared.borrow_mut();
+        let mut splats = std::mem::take(&mut shared.splats);
+        splats.truncate(MAX_SPLATS);
+        if !splats.is_empty() {
+            ctx.queue.write_buffer(ctx.resources.buffer(SPLATS).unwrap(), 0, bytemuck::cast_slice(&splats));
+        }
+        let kernels = self.kernels.as_ref().unwrap();
+        for (buffer, params) in kernels.params.iter().zip(self.pass_params(splats.len())) {
+            ctx.queue.write_buffer(buffer, 0, &params.to_uniform_bytes());
+        }
+
+        let (mut velocity, mut dye, mut pressure) = (self.velocity, shared.dye, self.pressure);
+        let ctx = &*ctx;
+        if !splats.is_empty() {
+            dispatch(ctx, encoder, kernels, &kernels.splat_velocity, Params::Other, VELOCITY[velocity], VELOCITY[velocity], VELOCITY[1 - velocity]);
+            velocity = 1 - velocity;
+            dispatch(ctx, encoder, kernels, &kernels.splat_dye, Params::Other, DYE[dye], DYE[dye], DYE[1 - dye]);
+            dye = 1 - dye;
+        }
+
+        dispatch(ctx, encoder, kernels, &kernels.advect, Params::AdvectVelocity, VELOCITY[velocity], VELOCITY[velocity], VELOCITY[1 - velocity]);
+        velocity = 1 - velocity;
+
+        if self.settings.viscosity > 0.0 {
+            let input = &ctx.resources.texture(DIFFUSION_INPUT).unwrap().texture;
+            encoder.copy_texture_to_texture(
+                ctx.resources.texture(VELOCITY[velocity]).unwrap().texture.as_image_copy(),
+                input.as_image_copy(),
+                wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+            );
+            for _ in 0..self.settings.diffusion_iterations {
+                dispatch(ctx, encoder, kernels, &kernels.jacobi, Params::Diffuse, VELOCITY[velocity], DIFFUSION_INPUT, VELOCITY[1 - velocity]);
+                velocity = 1 - velocity;
+            }
+        }
+
+        // The pressure of the last frame is a good first guess.
+        dispatch(ctx, encoder, kernels, &kernels.divergence, Params::Other, VELOCITY[velocity], VELOCITY[velocity], DIVERGENCE);
+        for _ in 0..self.settings.pressure_iterations {
+            dispatch(ctx, encoder, kernels, &kernels.jacobi, Params::Pressure, PRESSURE[pressure], DIVERGENCE, PRESSURE[1 - pressure]);
+            pressure = 1 - pressure;
+        }
+        dispatch(ctx, encoder, kernels, &kernels.subtract_gradient, Params::Other, VELOCITY[velocity], PRESSURE[pressure], VELOCITY[1 - velocity]);
+        velocity = 1 - velocity;
+
+        dispatch(ctx, encoder, kernels, &kernels.advect, Params::AdvectDye, DYE[dye], VELOCITY[velocity], DYE[1 - dye]);
+        dye = 1 - dye;
+
+        self.velocity = velocity;
+        self.pressure = pressure;
+        shared.dye = dye;
+    }
+}
+
+// Drawing
+//======================
+
+// The pipeline for the frame's format.
+struct DrawState {
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+/// Draws the dye over the frame, see above.
+pub struct FluidDraw {
+    shared: Rc<RefCell<Shared>>,
+    state: Option<DrawState>,
+}
+
+impl FluidDraw {
+    fn new(shared: Rc<RefCell<Shared>>) -> FluidDraw {
+        FluidDraw { shared, state: None }
+    }
+
+    fn create_state(device: &wgpu::Device, format: wgpu::TextureFormat) -> DrawState {
+        let wgsl = include_str!("fluid_draw.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("Fluid Draw"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Fluid Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fluid Draw Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Fluid Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Fluid Draw Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        DrawState {
+            pipeline,
+            format,
+            bind_group_layout,
+            sampler,
+        }
+    }
+}
+
+impl RenderNode for FluidDraw {
+    fn name(&self) -> &str {
+        "Fluid"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        DYE.to_vec()
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let dye = DYE[self.shared.borrow().dye];
+        let (view, dye) = match (ctx.frame.view, ctx.resources.texture(dye)) {
+            (Some(view), Some(dye)) => (view, dye),
+            _ => return,
+        };
+        let format = ctx.frame.format;
+        if self.state.as_ref().is_none_or(|state| state.format != format) {
+            self.state = Some(Self::create_state(ctx.device, format));
+        }
+        let state = self.state.as_ref().unwrap();
+        // The current dye texture changes every frame.
+        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fluid Draw Bind Group"),
+            layout: &state.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&dye.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&state.sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Fluid Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&state.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
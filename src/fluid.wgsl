@@ -0,0 +1,143 @@
+// The passes of the stable fluids solver, see fluid.rs. All fields are rgba16float textures with
+// a texel per grid cell: the velocity in xy (in cells per second), the dye in rgb, the pressure and
+// the divergence in x. Every pass reads `source` (and some `aux`) and writes `output`; reads past
+// the edges repeat the edge.
+
+struct Params {
+    dt: f32;
+    dissipation: f32; // Of advected values, per second.
+    alpha: f32; // Of the Jacobi iterations, see `jacobi`.
+    r_beta: f32;
+    splat_count: u32;
+    splat_radius: f32; // In cells.
+};
+struct Splat {
+    position: vec2<f32>; // From 0 to 1 across the grid.
+    force: vec2<f32>; // Added velocity, in grids per second.
+    color: vec4<f32>; // Added dye.
+};
+struct Splats {
+    splats: array<Splat>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var s_linear: sampler;
+[[group(0), binding(2)]]
+var source: texture_2d<f32>;
+[[group(0), binding(3)]]
+var aux: texture_2d<f32>;
+[[group(0), binding(4)]]
+var output: texture_storage_2d<rgba16float, write>;
+[[group(0), binding(5)]]
+var<storage, read> splats: Splats;
+
+fn load_source(pos: vec2<i32>) -> vec4<f32> {
+    return textureLoad(source, clamp(pos, vec2<i32>(0, 0), textureDimensions(source) - vec2<i32>(1, 1)), 0);
+}
+
+fn load_aux(pos: vec2<i32>) -> vec4<f32> {
+    return textureLoad(aux, clamp(pos, vec2<i32>(0, 0), textureDimensions(aux) - vec2<i32>(1, 1)), 0);
+}
+
+fn outside(pos: vec2<i32>) -> bool {
+    let size = textureDimensions(output);
+    return pos.x >= size.x || pos.y >= size.y;
+}
+
+// Moves `source` along the velocity in `aux`: every cell takes the value found a time step back
+// along its velocity, interpolated between cells.
+[[stage(compute), workgroup_size(8, 8)]]
+fn advect([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let pos = vec2<i32>(id.xy);
+    if (outside(pos)) {
+        return;
+    }
+    let size = vec2<f32>(textureDimensions(source));
+    let back = vec2<f32>(pos) + 0.5 - params.dt * load_aux(pos).xy;
+    let value = textureSampleLevel(source, s_linear, back / size, 0.0);
+    textureStore(output, pos, value / (1.0 + params.dissipation * params.dt));
+}
+
+// Adds the force of every splat to the velocity in `source`, falling off with the distance.
+[[stage(compute), workgroup_size(8, 8)]]
+fn splat_velocity([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let pos = vec2<i32>(id.xy);
+    if (outside(pos)) {
+        return;
+    }
+    let size = vec2<f32>(textureDimensions(source));
+    var value = load_source(pos);
+    for (var i = 0u; i < params.splat_count; i = i + 1u) {
+        let splat = splats.splats[i];
+        let offset = vec2<f32>(pos) + 0.5 - splat.position * size;
+        let weight = exp(-dot(offset, offset) / (params.splat_radius * params.splat_radius));
+        value = value + vec4<f32>(splat.force * size * weight, 0.0, 0.0);
+    }
+    textureStore(output, pos, value);
+}
+
+// Adds the color of every splat to the dye in `source`.
+[[stage(compute), workgroup_size(8, 8)]]
+fn splat_dye([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let pos = vec2<i32>(id.xy);
+    if (outside(pos)) {
+        return;
+    }
+    let size = vec2<f32>(textureDimensions(source));
+    var value = load_source(pos);
+    for (var i = 0u; i < params.splat_count; i = i + 1u) {
+        let splat = splats.splats[i];
+        let offset = vec2<f32>(pos) + 0.5 - splat.position * size;
+        let weight = exp(-dot(offset, offset) / (params.splat_radius * params.splat_radius));
+        value = value + splat.color * weight;
+    }
+    textureStore(output, pos, min(value, vec4<f32>(4.0)));
+}
+
+// One Jacobi iteration towards the solution x of a Poisson equation, from the current guess in
+// `source` and the right-hand side b in `aux`:
+//
+//     x = (xL + xR + xB + xT + alpha b) r_beta
+//
+// Used for viscous diffusion (b is the velocity before it, alpha = 1 / (viscosity dt),
+// r_beta = 1 / (4 + alpha)) and for the pressure (b is the divergence, alpha = -1, r_beta = 1/4).
+[[stage(compute), workgroup_size(8, 8)]]
+fn jacobi([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let pos = vec2<i32>(id.xy);
+    if (outside(pos)) {
+        return;
+    }
+    let neighbors = load_source(pos + vec2<i32>(-1, 0)) + load_source(pos + vec2<i32>(1, 0))
+        + load_source(pos + vec2<i32>(0, -1)) + load_source(pos + vec2<i32>(0, 1));
+    textureStore(output, pos, (neighbors + params.alpha * load_aux(pos)) * params.r_beta);
+}
+
+// How much the velocity in `source` flows out of every cell.
+[[stage(compute), workgroup_size(8, 8)]]
+fn divergence([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let pos = vec2<i32>(id.xy);
+    if (outside(pos)) {
+        return;
+    }
+    let dx = load_source(pos + vec2<i32>(1, 0)).x - load_source(pos + vec2<i32>(-1, 0)).x;
+    let dy = load_source(pos + vec2<i32>(0, 1)).y - load_source(pos + vec2<i32>(0, -1)).y;
+    textureStore(output, pos, vec4<f32>(0.5 * (dx + dy), 0.0, 0.0, 0.0));
+}
+
+// Makes the velocity in `source` divergence-free by subtracting the gradient of the pressure
+// in `aux`.
+[[stage(compute), workgroup_size(8, 8)]]
+fn subtract_gradient([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let pos = vec2<i32>(id.xy);
+    if (outside(pos)) {
+        return;
+    }
+    let gradient = vec2<f32>(
+        load_aux(pos + vec2<i32>(1, 0)).x - load_aux(pos + vec2<i32>(-1, 0)).x,
+        load_aux(pos + vec2<i32>(0, 1)).x - load_aux(pos + vec2<i32>(0, -1)).x,
+    );
+    let velocity = load_source(pos).xy - 0.5 * gradient;
+    textureStore(output, pos, vec4<f32>(velocity, 0.0, 0.0));
+}
@@ -0,0 +1,29 @@
+// Shows the dye of the fluid over the whole frame, see fluid.rs.
+
+[[group(0), binding(0)]]
+var t_dye: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_dye: sampler;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    // A triangle over the whole viewport: (-1, -1), (3, -1) and (-1, 3).
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // Texture coordinates have y pointing down.
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let dye = textureSample(t_dye, s_dye, in.uv).rgb;
+    // Dye piles up where it's injected, so it's toned down rather than clipped.
+    return vec4<f32>(dye / (1.0 + dye), 1.0);
+}
@@ -0,0 +1,153 @@
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+// Frames in flight
+//======================
+// Uploading this frame's uniforms into the buffers the GPU is still reading for the previous frame
+// would make one wait for the other. Instead, every per-frame resource exists once per frame in flight,
+// and frame `n` uses copy `n % count`. Before a copy is reused, the CPU waits for the fence of the frame
+// that last used it, so it runs at most `count` frames ahead of the GPU.
+//
+// Per-frame uniforms are written through a staging belt of the frame: the data goes into mapped staging
+// memory and is copied into the uniform buffers at the start of the frame's command buffer.
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// Signals once the GPU finished all work submitted before it was created.
+// wgpu 0.12 has no submission indices; its `on_submitted_work_done` future is the closest thing.
+pub struct Fence {
+    future: Option<BoxFuture>,
+}
+
+impl Fence {
+    pub fn new(queue: &wgpu::Queue) -> Fence {
+        Fence {
+            future: Some(Box::pin(queue.on_submitted_work_done())),
+        }
+    }
+
+    // The future completes from `Device::poll`; this only checks whether it did.
+    pub fn is_signaled(&mut self) -> bool {
+        if let Some(future) = &mut self.future {
+            if poll_once(future) {
+                self.future = None;
+            }
+        }
+        self.future.is_none()
+    }
+}
+
+// Resources used by one frame in flight.
+pub struct FrameSlot {
+    pub globals_buffer: wgpu::Buffer,
+    belt: wgpu::util::StagingBelt,
+    // Maps the belt's staging buffers again once the GPU is done with them.
+    recall: Option<BoxFuture>,
+    fence: Option<Fence>,
+}
+
+impl FrameSlot {
+    // Queues a copy of `data` into `target`, executed at the start of `encoder`.
+    pub fn write_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        data: &[u8],
+    ) {
+        let size = NonZeroU64::new(data.len() as u64).expect("empty upload");
+        self.belt
+            .write_buffer(encoder, target, 0, size, device)
+            .copy_from_slice(data);
+    }
+
+    pub fn write_globals(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, data: &[u8]) {
+        let size = NonZeroU64::new(data.len() as u64).expect("empty upload");
+        self.belt
+            .write_buffer(encoder, &self.globals_buffer, 0, size, device)
+            .copy_from_slice(data);
+    }
+}
+
+pub struct FramesInFlight {
+    slots: Vec<FrameSlot>,
+    current: usize,
+}
+
+impl FramesInFlight {
+    pub fn new(device: &wgpu::Device, count: usize, globals_size: u64) -> FramesInFlight {
+        let slots = (0..count.max(1))
+            .map(|i| FrameSlot {
+                globals_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&format!("Globals Buffer {}", i)),
+                    size: globals_size,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                belt: wgpu::util::StagingBelt::new(4 * 1024),
+                recall: None,
+                fence: None,
+            })
+            .collect();
+        FramesInFlight { slots, current: 0 }
+    }
+
+    pub fn count(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Index of the slot used by the current frame.
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    pub fn slots(&self) -> &[FrameSlot] {
+        &self.slots
+    }
+
+    pub fn current_mut(&mut self) -> &mut FrameSlot {
+        &mut self.slots[self.current]
+    }
+
+    // Blocks until the GPU finished the frame that used the current slot before.
+    pub fn wait_for_current(&mut self, device: &wgpu::Device) {
+        let slot = &mut self.slots[self.current];
+        if let Some(fence) = &mut slot.fence {
+            loop {
+                device.poll(wgpu::Maintain::Poll);
+                if fence.is_signaled() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_micros(100));
+            }
+            slot.fence = None;
+        }
+        if let Some(recall) = &mut slot.recall {
+            if poll_once(recall) {
+                slot.recall = None;
+            }
+        }
+    }
+
+    // Call after encoding the frame, before submitting it.
+    pub fn finish_encoding(&mut self) {
+        self.slots[self.current].belt.finish();
+    }
+
+    // Call after submitting the frame: fences the slot and moves on to the next one.
+    pub fn end_frame(&mut self, queue: &wgpu::Queue) {
+        let slot = &mut self.slots[self.current];
+        slot.fence = Some(Fence::new(queue));
+        slot.recall = Some(Box::pin(slot.belt.recall()));
+        self.current = (self.current + 1) % self.slots.len();
+    }
+}
+
+// Polls without a waker to notify; wgpu completes its futures from `Device::poll`.
+fn poll_once(future: &mut BoxFuture) -> bool {
+    let mut context = Context::from_waker(Waker::noop());
+    matches!(future.as_mut().poll(&mut context), Poll::Ready(()))
+}
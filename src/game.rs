@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+use crate::gfx::GFX;
+use crate::keyboard::{Keyboard, KeyboardState};
+use crate::limiter::FrameLimiter;
+use crate::mouse::{Mouse, MouseDelta};
+use crate::rng::Rng;
+use crate::stats::FrameStats;
+use crate::time::Time;
+
+// Game
+//======================
+// The behavior of an application lives in a type implementing `Game`, which `App::run` drives:
+//
+//     init       once, after the window and graphics are created
+//     on_event   for every input event, as the window receives them
+//     on_resize  when the client area changes size (not while minimized)
+//     update     at a fixed rate (`App::fixed_dt`), zero or more times per frame,
+//                with the game time that can also be paused or scaled from here
+//     render     once per frame, right before the scene is drawn
+//     should_exit after every frame; returning true ends `App::run` cleanly
+//     needs_redraw with `RedrawMode::OnDemand`, before waiting for input; true renders a frame
+//
+// All callbacks have empty defaults, so a game only implements what it needs.
+
+pub trait Game {
+    fn init(&mut self, _gfx: &mut GFX) {}
+
+    fn update(&mut self, _time: &mut Time, _input: &Input) {}
+
+    // Adjust cameras, materials and renderables for this frame; `App` draws the scene afterwards.
+    fn render(&mut self, _frame: &mut Frame) {}
+
+    fn on_resize(&mut self, _width: u32, _height: u32) {}
+
+    fn on_event(&mut self, _event: &Event) {}
+
+    fn should_exit(&self) -> bool {
+        false
+    }
+
+    // Only asked with `RedrawMode::OnDemand`, see app.rs: true renders another frame without
+    // waiting for input, e.g. while an animation plays or a file loads.
+    fn needs_redraw(&self) -> bool {
+        false
+    }
+}
+
+/// The input state at the time of `Game::update`.
+pub struct Input<'a> {
+    pub keyboard: &'a Keyboard,
+    // The keys held down at this update and at the previous one, e.g.
+    // `keys.pressed_since(&previous_keys)` are the keys pressed in between.
+    pub keys: KeyboardState,
+    pub previous_keys: KeyboardState,
+    pub mouse: &'a Mouse,
+    // How far the mouse moved since the previous update. Only the first update of a frame gets
+    // it, the others get zero, so summing over updates never counts movement twice. Frames
+    // without an update pass their movement on to the next one.
+    pub mouse_delta: MouseDelta,
+    // The only source of randomness for gameplay, see rng.rs.
+    pub rng: &'a Rng,
+}
+
+/// Everything a game may change while rendering a frame.
+pub struct Frame<'a> {
+    pub gfx: &'a mut GFX,
+    pub time: &'a Time,
+    pub stats: &'a FrameStats,
+    pub limiter: &'a mut FrameLimiter,
+    /// How far real time is past the last `update`, as a fraction of a tick in [0, 1).
+    /// Draw `previous.lerp(current, alpha)` for motion that is smooth at any frame rate.
+    pub alpha: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MouseButton {
+    Left,
+    Right,
+}
+
+/// How far a wheel or touchpad scrolled, positive is up and right, see `MouseDelta`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScrollDelta {
+    // From wheels, one per notch, with fractions from precision touchpads.
+    Lines { x: f32, y: f32 },
+    // From touchpads that report pixels (winit on macOS and Wayland).
+    Pixels { x: f32, y: f32 },
+}
+
+/// An input event, in the order the window received them, or a change of the app's state.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    // Virtual-key codes, as in `Keyboard`.
+    KeyPressed(u16),
+    KeyReleased(u16),
+    // A UTF-16 code unit of typed text.
+    Char(u16),
+    // Client area coordinates, in pixels.
+    MouseMoved { x: i32, y: i32 },
+    MousePressed(MouseButton),
+    MouseReleased(MouseButton),
+    // Relative motion reported by the mouse itself, in device units, see `MouseDelta`.
+    MouseRawMoved { dx: i32, dy: i32 },
+    MouseScrolled(ScrollDelta),
+    FocusLost,
+    // Raised by `App` rather than the window, see "Throttling" in app.rs.
+    OcclusionChanged { occluded: bool },
+    PowerChanged { on_battery: bool },
+}
+
+impl Event {
+    // Keyboard and mouse events, as opposed to focus and app state changes.
+    pub fn is_input(&self) -> bool {
+        !matches!(self, Event::FocusLost | Event::OcclusionChanged { .. } | Event::PowerChanged { .. })
+    }
+}
@@ -1,56 +1,350 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use raw_window_handle::HasRawWindowHandle;
 use wgpu::util::DeviceExt;
-use crate::{window::Window, Vertex, INDICES, VERTICES};
+use crate::assets::{Assets, FileWatcher, LoadError, MeshHandle, ShaderHandle, TextureHandle};
+use crate::bounds::{Aabb, Frustum, Ray};
+use crate::bvh::Bvh;
+use crate::camera::{Camera, CameraId, CameraUniform, Rect};
+use crate::capabilities::Capabilities;
+use crate::clear::{ClearQuad, ClearSettings};
+use crate::clouds::{CloudCamera, CloudSettings, Clouds};
+use crate::debug_draw::{DebugDraw, DebugViews};
+use crate::error::{Error, Result};
+use crate::exposure::{self, ExposureSettings, HDR_FORMAT, HDR_TARGET};
+use crate::jobs;
+use crate::frames::FramesInFlight;
+use crate::gfx_context::GfxContext;
+use crate::gizmo::{Gizmo, GizmoView};
+use crate::id_buffer::{IdBuffer, IdDraw};
+use crate::gpu_particles::{DepthCamera, GpuParticleSettings, GpuParticles, GpuParticlesId};
+use crate::gpu_timer::GpuTimer;
+use crate::grid::{Grid, GridSettings};
+use crate::layers::RenderLayers;
+use crate::lens_flare::{FlareCamera, LensFlare, LensFlareId, LensFlares};
+use crate::light::DirectionalLight;
+use crate::light_cookies::CookieId;
+use crate::letterbox::{Letterbox, VirtualResolution};
+use crate::local_lights::{LocalLight, LocalLightId, LocalLights};
+use crate::material::{ColorParams, EmissiveParams, Material, MaterialId, Materials, PipelineKey, ReflectiveParams, SceneLayouts, ShaderId};
+use crate::mesh::{Mesh, VertexLayout};
+use crate::mirrors::{Mirror, MirrorId, Mirrors};
+use crate::noise::{NoiseGenerator, NoiseSettings};
+use crate::nine_slice::{NineSlice, NineSliceRenderer, UiAtlasId};
+use crate::occlusion::{Occlusion, OcclusionDraw};
+use crate::occlusion_queries::{OcclusionQueries, OcclusionQueryId, OcclusionTarget};
+use crate::overrides::PipelineConstants;
+use crate::particles::{ParticleEmitter, ParticleSprites};
+use crate::readback::Readback;
+use crate::reflection::ReflectError;
+use crate::reflection_probes::{ReflectionProbe, ReflectionProbeId, ReflectionProbes, MAX_PROBES, PROBE_FORMAT};
+use crate::render_graph::{FrameTarget, RenderGraph, RenderNode, Stage};
+use crate::render_scale::{RenderScale, RenderScaleSettings, SCALED_TARGET};
+use crate::render_targets::{RenderTarget, RenderTargets, TextureCameraId};
+use crate::selection::{OutlineSettings, Selection, SelectionArea, SelectionOutline, SelectionTool};
+use crate::shadow_atlas::ShadowView;
+#[cfg(feature = "renderdoc")]
+use crate::renderdoc::RenderDoc;
+use crate::shader_import;
+use crate::skeleton::{Pose, Skeleton};
+use crate::skinning::{SkinId, SkinnedVertex, Skinning};
+use crate::sky::{Sky, SkySettings};
+use crate::stats::RenderCounters;
+use crate::streaming::{StreamedTextureId, TextureStreamer};
+use crate::text::TextOverlay;
+use crate::texture::Texture;
+use crate::tilemap::{Camera2d, Tilemap, TilemapId, Tilemaps};
+use crate::time::{GlobalsUniform, Time};
+use crate::transform::{ModelInstance, Transform, Transforms};
+use crate::variants::ShaderDefines;
+use crate::volumetrics::{VolumetricCamera, VolumetricSettings, Volumetrics};
+
+/// Graphics settings chosen before startup, e.g. from the command line.
+#[derive(Clone, Debug)]
+pub struct GfxOptions {
+    pub backends: wgpu::Backends,
+    // Index into the adapters that can present to the window, in the order wgpu lists them.
+    // `None` lets wgpu pick one.
+    pub adapter: Option<usize>,
+    // Which adapter wgpu picks when `adapter` is `None`.
+    pub power_preference: wgpu::PowerPreference,
+    pub vsync: bool,
+    // Samples per pixel for multisample anti-aliasing, 1 to turn it off.
+    pub msaa_samples: u32,
+    // Anisotropic filtering of streamed textures, up to 16; 1 to turn it off.
+    pub anisotropy: u8,
+    // Store loaded meshes with half-float positions and 8-bit colors, see packing.rs.
+    pub compress_vertices: bool,
+    // Skip objects hidden behind others, tested on the GPU against the last frame, see occlusion.rs.
+    pub occlusion_culling: bool,
+    // How many frames the CPU may prepare before the GPU has finished the first of them.
+    // More hides stalls better, fewer lowers the input latency.
+    pub frames_in_flight: usize,
+    // Directory to record a wgpu API trace into, for replaying bugs with wgpu's player.
+    // wgpu only records it when built with its `trace` feature, otherwise it logs that tracing is unavailable.
+    pub trace_path: Option<PathBuf>,
+    // Render at a fixed size and scale it to the window, see letterbox.rs. `None` renders at the
+    // size of the window.
+    pub virtual_resolution: Option<VirtualResolution>,
+    // Render with the device of another GFX instead of creating one, see gfx_context.rs.
+    pub shared_context: Option<Rc<GfxContext>>,
+    // Render the scene in HDR and expose it automatically, see exposure.rs. `None` renders
+    // straight into the frame.
+    pub exposure: Option<ExposureSettings>,
+    // Render the scene smaller than the frame and scale it up, see render_scale.rs. `None` renders
+    // at the size of the frame, and can't be changed later.
+    pub render_scale: Option<RenderScaleSettings>,
+    // Width and height of the texture holding the shadow maps of the local lights, see
+    // shadow_atlas.rs.
+    pub shadow_atlas_size: u32,
+}
+
+impl Default for GfxOptions {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+            adapter: None,
+            power_preference: wgpu::PowerPreference::default(),
+            vsync: true,
+            msaa_samples: 1,
+            anisotropy: 1,
+            compress_vertices: false,
+            occlusion_culling: false,
+            frames_in_flight: 2,
+            trace_path: None,
+            virtual_resolution: None,
+            shared_context: None,
+            exposure: None,
+            render_scale: None,
+            shadow_atlas_size: 2048,
+        }
+    }
+}
 
-pub(crate) struct GFX {
-    surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+pub struct GFX {
+    surface: Option<wgpu::Surface>, // `None` when headless, see `GFX::headless`.
     config: wgpu::SurfaceConfiguration,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    globals: GlobalsUniform,
+    frames: FramesInFlight, // Holds the globals buffers, bound next to every camera, see time.rs.
+    cameras: Vec<CameraView>,
+    renderables: Vec<Renderable>,
+    transforms: Transforms, // Of the renderables, by index, see transform.rs.
+    // The model matrices of the renderables, in the same order, see transform.rs.
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    // Over the world bounds of the renderables, by index, see bvh.rs.
+    bvh: Bvh,
+    unbounded: Vec<usize>, // Renderables whose meshes have no bounds, never culled.
+    materials: Materials,
+    default_material: MaterialId,
+    graph: RenderGraph, // Passes added by the game, run before and after the scene pass.
+    skinning: Skinning, // Posed by a compute pass before the graph runs.
+    streamer: TextureStreamer,
+    // Material texture slots that follow a streamed texture: (material, texture index, streamed texture).
+    streamed_bindings: Vec<(MaterialId, usize, StreamedTextureId)>,
+    watcher: FileWatcher,
+    shader_files: Vec<(PathBuf, ShaderId)>,
+    // Material texture slots that follow a texture asset: (material, texture index, texture).
+    texture_bindings: Vec<(MaterialId, usize, TextureHandle)>,
+    assets: Assets,
+    changed_files: Vec<PathBuf>,
+    text: TextOverlay,
+    panels: NineSliceRenderer, // Drawn below the text, see nine_slice.rs.
+    debug_draw: DebugDraw,
+    debug_views: DebugViews,
+    light_frusta: Vec<Matrix4<f32>>, // Queued for this frame, see `draw_light_frustum`.
+    clear_color: Option<wgpu::Color>, // Of the whole surface, before the cameras draw.
+    clear_quad: ClearQuad,
+    tilemaps: Tilemaps, // Drawn behind the scene, see tilemap.rs.
+    letterbox: Option<Letterbox>, // `None` renders into the surface, see letterbox.rs.
+    msaa_samples: u32,
+    hdr: bool, // Whether the scene is rendered into `scene.hdr`, see exposure.rs.
+    render_scale: Option<RenderScale>, // `None` renders the scene at the size of the frame.
+    // The multisampled color target, resolved into the frame texture. `None` without MSAA.
+    msaa_view: Option<wgpu::TextureView>,
+    depth_view: wgpu::TextureView, // Shared by the cameras, with as many samples as the color target.
+    occlusion: Option<Occlusion>,  // `None` without occlusion culling.
+    occlusion_queries: Option<OcclusionQueries>, // `None` without pipeline statistics queries.
+    probes: ReflectionProbes,
+    probe_views: Vec<CameraView>, // A camera per cubemap face, created with the first probe.
+    lens_flares: LensFlares,
+    mirrors: Mirrors,
+    mirror_views: Vec<CameraView>, // By mirror id, created with the mirror.
+    render_targets: RenderTargets,
+    target_views: Vec<CameraView>, // By texture camera id, created with the camera.
+    gpu_particles: GpuParticles,
+    particle_sprites: ParticleSprites, // Queued for a single frame, like the debug lines.
+    selection: Selection,
+    outline: SelectionOutline,
+    outline_settings: Option<OutlineSettings>, // `None` doesn't outline the selection.
+    id_buffer: IdBuffer,
+    grid: Grid,
+    noise: NoiseGenerator,
+    grid_settings: Option<GridSettings>, // `None` doesn't draw the grid.
+    clouds: Clouds,
+    cloud_settings: Option<CloudSettings>, // `None` doesn't draw the clouds.
+    volumetrics: Volumetrics,
+    volumetric_settings: Option<VolumetricSettings>, // `None` doesn't draw the light shafts.
+    light: DirectionalLight, // Unless the sky decides it.
+    local_lights: LocalLights,
+    sky: Sky,
+    sky_settings: Option<SkySettings>, // `None` doesn't draw the sky.
+    counters: RenderCounters, // Of the last frame.
+    gpu_timer: Option<GpuTimer>, // `None` without timestamp queries.
+    capabilities: Capabilities,
+    context: Rc<GfxContext>, // The device and queue, possibly shared with other windows, see gfx_context.rs.
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc>, // `None` unless running under RenderDoc.
+}
+
+/// Identifies a renderable registered with `GFX::add_renderable`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RenderableId(usize);
+
+// A mesh drawn every frame with its material,
+// by the cameras whose layers intersect its layers.
+pub struct Renderable {
+    pub mesh: MeshHandle,
+    pub material: MaterialId,
+    pub layers: RenderLayers,
+}
+
+// A camera, the region of the surface it renders into,
+// and the GPU resources needed to render through it.
+struct CameraView {
+    camera: Camera,
+    viewport: Rect,
+    scissor: Option<Rect>, // Defaults to the viewport when `None`.
+    clear: ClearSettings,  // For its region, see clear.rs.
+    uniform: CameraUniform,
+    // One uniform buffer and bind group per frame in flight, see frames.rs.
+    buffers: Vec<wgpu::Buffer>,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl CameraView {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, frames: &FramesInFlight, lights: &LocalLights, camera: Camera) -> Self {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera);
+
+        let buffers: Vec<wgpu::Buffer> = (0..frames.count())
+            .map(|i| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Camera Buffer {}", i)),
+                    contents: bytemuck::cast_slice(&[uniform]),
+                    // COPY_DST so we can update the matrix every frame.
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+
+        let bind_groups = buffers
+            .iter()
+            .zip(frames.slots())
+            .enumerate()
+            .map(|(i, (buffer, frame))| {
+                let [lights, atlas, sampler, cookies, cookie_sampler] = lights.bind_group_entries();
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(&format!("Camera Bind Group {}", i)),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: frame.globals_buffer.as_entire_binding(),
+                        },
+                        lights,
+                        atlas,
+                        sampler,
+                        cookies,
+                        cookie_sampler,
+                    ],
+                })
+            })
+            .collect();
+
+        Self {
+            camera,
+            viewport: Rect::FULL,
+            scissor: None,
+            clear: ClearSettings::default(),
+            uniform,
+            buffers,
+            bind_groups,
+        }
+    }
+
+    // Uploads the uniform into the buffer of the current frame, after the camera moved.
+    fn write_uniform(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, frames: &mut FramesInFlight) {
+        let frame = frames.index();
+        frames.current_mut().write_buffer(device, encoder, &self.buffers[frame], bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+// What a camera rendering into a texture draws, for `GFX::draw_view`.
+struct ViewCamera<'a> {
+    view_proj: Matrix4<f32>,
+    layers: RenderLayers,
+    // E.g. the renderables sampling the texture being rendered into.
+    skip: &'a dyn Fn(&Renderable) -> bool,
+}
+
+// Where `GFX::draw_view` renders, with the probes bound for the materials sampling them.
+struct ViewTarget<'a> {
+    pass: &'static str,
+    label: String, // Of its debug group.
+    color: &'a wgpu::TextureView,
+    depth: &'a wgpu::TextureView,
+    clear: wgpu::Color,
+    probes: &'a wgpu::BindGroup,
 }
 
 impl GFX {
-    pub async fn new(window: &Window) -> Self {
-        // Instance of wgpu. Its primary use is to create `Adapter`s and `Surface`s.
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
-
-        // A `Surface` represents a platform-specific surface (e.g. a window)
-        // onto which rendered images may be presented.
-        // It's the part of the window that we draw to.
-        // Created from raw window handle.
-        let surface = unsafe { instance.create_surface(window) };
-
-        // Handle to a physical graphics and/or compute device.
-        // Adapters can be used to open a connection to the corresponding `Device`
-        //on the host system
-        let adapter = {
-            let options = wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            };
+    // `width` and `height` are the size of the window's client area.
+    pub async fn new(window: &impl HasRawWindowHandle, width: u32, height: u32, options: &GfxOptions) -> Result<Self> {
+        Self::create(Some(window), width, height, options).await
+    }
 
-            // Retrieves an `Adapter` which matches the given `RequestAdapterOptions`.
-            // If wgpu can't find an adapter with the required permissions,
-            // request_adapter will return None
-            instance.request_adapter(&options).await.unwrap() // Hard panic for now.
+    // Renders into a `width` x `height` texture instead of a window, e.g. for the golden image
+    // tests, see golden.rs. Frames are read back with `read_frame`.
+    pub async fn headless(width: u32, height: u32, options: &GfxOptions) -> Result<Self> {
+        // The virtual frame is all there is to render into.
+        let options = GfxOptions {
+            virtual_resolution: Some(options.virtual_resolution.unwrap_or_else(|| VirtualResolution::new(width, height))),
+            ..options.clone()
         };
+        Self::create(None, width, height, &options).await
+    }
 
-        // Open connection to a graphics and/or compute device
-        // and get handle to a command queue on a device.
-        let (device, queue) = {
-            let desc = wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
-                limits: wgpu::Limits::default(),
-                label: None,
-            };
+    async fn create(window: Option<&dyn HasRawWindowHandle>, width: u32, height: u32, options: &GfxOptions) -> Result<Self> {
+        // RenderDoc hooks into the graphics API when the device is created, so it goes first.
+        #[cfg(feature = "renderdoc")]
+        let renderdoc = RenderDoc::load();
 
-            // Requests a connection to a physical device, creating a logical device.
-            // Returns the Device together with a Queue that executes command buffers.
-            adapter.request_device(&desc, None).await.unwrap()
+        // The device, shared with another GFX or of our own, see gfx_context.rs.
+        let (context, surface, capabilities) = match &options.shared_context {
+            Some(context) => {
+                let surface = window.map(|window| context.create_surface(window)).transpose()?;
+                let capabilities = Capabilities::new(context.adapter(), options);
+                (context.clone(), surface, capabilities)
+            }
+            None => {
+                let (context, surface) = GfxContext::new(window, options).await?;
+                let capabilities = context.capabilities().clone();
+                (context, surface, capabilities)
+            }
         };
+        let adapter = context.adapter();
+        let device = context.device();
+        let queue = context.queue();
+        let cache = context.cache();
 
         // Configures a `Surface` for presentation.
         let surface_config = wgpu::SurfaceConfiguration {
@@ -59,174 +353,2543 @@ impl GFX {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
 
             // The texture format of the swap chain.
-            format: surface.get_preferred_format(&adapter).unwrap(),
+            // Headless, frames are read back in the byte order of PNG files.
+            format: match &surface {
+                Some(surface) => surface.get_preferred_format(adapter).ok_or(Error::NoAdapter)?,
+                None => wgpu::TextureFormat::Rgba8UnormSrgb,
+            },
 
             // Width and height of the swap chain.
             // Must be the same size as the surface.
-            width: window.width as u32,
-            height: window.height as u32,
+            width,
+            height,
 
             // Presentation mode of the swap chain.
             // FIFO is the only guaranteed to be supported.
             // FIFO will cap the display rate at the displays framerate.
             // This is essentially VSync. This is also the most optimal mode on mobile.
-            present_mode: wgpu::PresentMode::Fifo,
+            // Without vsync, frames are presented right away, which may tear.
+            present_mode: if options.vsync {
+                wgpu::PresentMode::Fifo
+            } else {
+                wgpu::PresentMode::Immediate
+            },
         };
 
         // Initializes `Surface` for presentation.
-        surface.configure(&device, &surface_config);
-
-        // Create shader module from WGSL source code.
-        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
+        if let Some(surface) = &surface {
+            surface.configure(device, &surface_config);
+        }
 
-        // Handle to pipeline layout.
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
+        // Describes the camera uniform that is bound in group 0. Fragment shaders read the eye
+        // position, e.g. for reflections.
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Camera Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // The globals (time, frame index), shared by all cameras.
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // The local lights, their shadow atlas and their cookies, see local_lights.rs.
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
             });
+        let local_lights = LocalLights::new(device, options.shadow_atlas_size);
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()], // type of vertices we want to pass to the vertex shader.
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                // The targets field tells wgpu what color outputs it should set up.
-                // Currently, we only need one for the surface.
-                targets: &[wgpu::ColorTargetState {
-                    format: surface_config.format,          // Surface's format.
-                    blend: Some(wgpu::BlendState::REPLACE), // Replace old with new.
-                    write_mask: wgpu::ColorWrites::ALL, // write to all colors: red, blue, green, and alpha.
-                }],
-            }),
-            // The primitive field describes how to interpret our vertices when converting them into triangles.
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // Each three vertices will correspond to one triangle.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // a triangle is facing forward if the vertices are arranged in a counter-clockwise direction.
-                cull_mode: Some(wgpu::Face::Back), // Not front-facing triangles are excluded from render (culled).
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1, // No multisampling.
-                mask: !0, // Use all samples.
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let mut globals = GlobalsUniform::new();
+        let frames = FramesInFlight::new(
+            device,
+            options.frames_in_flight,
+            std::mem::size_of::<GlobalsUniform>() as u64,
+        );
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: _as_bytes(VERTICES), // bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+        // The default material draws vertex colors, multiplied by the `color` parameter.
+        let mut materials = Materials::new(cache.clone());
+        let shader = materials
+            .add_shader(device, "Shader", include_str!("shader.wgsl"), ShaderDefines::new())
+            .expect("built-in shader is valid");
+        let default_material = materials
+            .add_material(device, Material::new(shader).with_uniform(&ColorParams {
+                color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            }))
+            .expect("built-in material matches its shader");
+
+        // The scene is rendered at the virtual resolution if there is one, at the window size otherwise.
+        let letterbox = options
+            .virtual_resolution
+            .map(|resolution| Letterbox::new(device, cache, surface_config.format, resolution));
+        let size = letterbox.as_ref().map_or((width, height), |l| (l.resolution().width, l.resolution().height));
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: _as_bytes(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
+        // Start out with a single camera covering the whole surface.
+        globals.set_resolution(size.0, size.1);
+        let aspect = size.0 as f32 / size.1 as f32;
+        let cameras = vec![CameraView::new(device, &camera_bind_group_layout, &frames, &local_lights, Camera::new(aspect))];
+
+        let msaa_samples = capabilities.msaa_samples;
+        // With HDR, the scene has a format of its own, and the overlay is drawn into the frame
+        // after tonemapping, without multisampling. Likewise after scaling up the scene.
+        let hdr = options.exposure.is_some();
+        let scene_format = if hdr { HDR_FORMAT } else { surface_config.format };
+        let overlay_samples = if hdr || options.render_scale.is_some() { 1 } else { msaa_samples };
+        let render_scale = options.render_scale.map(|settings| RenderScale::new(device, scene_format, settings));
+        let target_size = render_scale.as_ref().map_or(size, |scale| scale.allocated_size(size.0, size.1));
+        let mut graph = RenderGraph::new();
+        if let Some(settings) = options.exposure {
+            exposure::add_nodes(&mut graph, settings);
+        }
+        let msaa_view = create_msaa_view(device, scene_format, target_size, msaa_samples);
+        let depth_view = create_depth_view(device, target_size, msaa_samples);
+        let occlusion = capabilities.occlusion_culling.then(|| Occlusion::new(device));
+        let text = TextOverlay::new(device, queue, surface_config.format, overlay_samples);
+        let debug_draw = DebugDraw::new(device, &camera_bind_group_layout, scene_format, msaa_samples);
+        let outline = SelectionOutline::new(device, &camera_bind_group_layout, scene_format, msaa_samples);
+        let grid = Grid::new(device, &camera_bind_group_layout, scene_format, msaa_samples);
+        let sky = Sky::new(device, &camera_bind_group_layout, scene_format, msaa_samples);
+        let clear_quad = ClearQuad::new(device, scene_format, msaa_samples);
+        let tilemaps = Tilemaps::new(device, scene_format, msaa_samples);
+        let panels = NineSliceRenderer::new(device, queue, cache, surface_config.format, overlay_samples);
+
+        let assets = Assets::new(device, queue, cache.clone()).with_compressed_vertices(options.compress_vertices);
+        let streamer = TextureStreamer::new(256 * 1024 * 1024, cache.clone())
+            .with_anisotropy(capabilities.anisotropy)
+            .with_max_texture_size(capabilities.max_texture_size);
+        let instance_buffer = create_instance_buffer(device, 1);
+        let gpu_timer = capabilities
+            .timestamp_queries
+            .then(|| GpuTimer::new(device, queue, options.frames_in_flight));
+        let occlusion_queries = capabilities.occlusion_queries.then(|| {
+            OcclusionQueries::new(device, &camera_bind_group_layout, scene_format, msaa_samples, options.frames_in_flight)
         });
+        let gpu_particles = GpuParticles::new(device, &camera_bind_group_layout, scene_format, msaa_samples);
+        let particle_sprites = ParticleSprites::new(device, &camera_bind_group_layout, scene_format, msaa_samples);
+        let volumetrics = Volumetrics::new(device, &camera_bind_group_layout, scene_format, msaa_samples);
 
-        Self {
+        Ok(Self {
             surface,
-            device,
-            queue,
             config: surface_config,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
+            camera_bind_group_layout,
+            globals,
+            frames,
+            cameras,
+            renderables: Vec::new(), // Added by the game, see `Game::init`.
+            transforms: Transforms::new(),
+            instance_buffer,
+            instance_capacity: 1,
+            bvh: Bvh::new(),
+            unbounded: Vec::new(),
+            materials,
+            default_material,
+            graph,
+            skinning: Skinning::new(capabilities.compute_shaders),
+            streamer,
+            streamed_bindings: Vec::new(),
+            watcher: FileWatcher::new(),
+            shader_files: Vec::new(),
+            texture_bindings: Vec::new(),
+            assets,
+            changed_files: Vec::new(),
+            text,
+            panels,
+            debug_draw,
+            debug_views: DebugViews::default(),
+            light_frusta: Vec::new(),
+            clear_color: Some(wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            }),
+            clear_quad,
+            tilemaps,
+            letterbox,
+            msaa_samples,
+            hdr,
+            render_scale,
+            msaa_view,
+            depth_view,
+            occlusion,
+            occlusion_queries,
+            probes: ReflectionProbes::new(device),
+            probe_views: Vec::new(),
+            lens_flares: LensFlares::new(device, scene_format, msaa_samples),
+            mirrors: Mirrors::new(scene_format),
+            mirror_views: Vec::new(),
+            render_targets: RenderTargets::new(scene_format),
+            target_views: Vec::new(),
+            gpu_particles,
+            particle_sprites,
+            selection: Selection::new(),
+            outline,
+            outline_settings: Some(OutlineSettings::default()),
+            id_buffer: IdBuffer::new(device),
+            grid,
+            noise: NoiseGenerator::new(),
+            grid_settings: None,
+            clouds: Clouds::new(device, scene_format, msaa_samples),
+            cloud_settings: None,
+            volumetrics,
+            volumetric_settings: None,
+            light: DirectionalLight::default(),
+            local_lights,
+            sky,
+            sky_settings: None,
+            counters: RenderCounters::default(),
+            gpu_timer,
+            capabilities,
+            context,
+            #[cfg(feature = "renderdoc")]
+            renderdoc,
+        })
+    }
+
+    // Camera / viewport API
+    //======================
+    // Every camera renders the scene into its own region of the surface in the same frame,
+    // which allows split-screen rendering and editor style multi-views.
+
+    // The camera created together with the GFX, covering the whole surface.
+    pub fn main_camera(&self) -> CameraId {
+        CameraId(0)
+    }
+
+    // Registers an additional camera. It covers the whole surface until `set_viewport` is called.
+    pub fn add_camera(&mut self, camera: Camera) -> CameraId {
+        let view = CameraView::new(self.context.device(), &self.camera_bind_group_layout, &self.frames, &self.local_lights, camera);
+        self.cameras.push(view);
+        self.fit_cameras();
+        CameraId(self.cameras.len() - 1)
+    }
+
+    pub fn camera(&self, id: CameraId) -> &Camera {
+        &self.cameras[id.0].camera
+    }
+
+    pub fn camera_mut(&mut self, id: CameraId) -> &mut Camera {
+        &mut self.cameras[id.0].camera
+    }
+
+    // Sets the region of the surface the camera renders into, in normalized coordinates.
+    pub fn set_viewport(&mut self, id: CameraId, viewport: Rect) {
+        self.cameras[id.0].viewport = viewport;
+        self.fit_cameras();
+    }
+
+    // Matches the aspect ratio of the cameras to their viewports at the current frame size, for
+    // the cameras with `auto_aspect`, and tells the shaders the frame size. The matrices are
+    // uploaded with the next `render`, so the frame after a resize is never stretched.
+    fn fit_cameras(&mut self) {
+        let (width, height) = self.render_size();
+        self.globals.set_resolution(width, height);
+        for view in self.cameras.iter_mut().filter(|view| view.camera.auto_aspect) {
+            let (_, _, w, h) = view.viewport.to_pixels(width, height);
+            if w > 0 && h > 0 {
+                view.camera.aspect = w as f32 / h as f32;
+            }
         }
     }
 
-    // Support window resizing
-    pub fn resize(&mut self, new_width: u32, new_height: u32) {
-        if new_width > 0 && new_height > 0 {
-            self.config.width = new_width;
-            self.config.height = new_height;
-            self.surface.configure(&self.device, &self.config);
+    // Restricts drawing of the camera to `scissor`, in normalized coordinates.
+    // `None` clips to the camera's viewport.
+    pub fn set_scissor(&mut self, id: CameraId, scissor: Option<Rect>) {
+        self.cameras[id.0].scissor = scissor;
+    }
+
+    // How the camera's region starts out before it draws, see clear.rs. By default the camera
+    // draws over what is there, with fresh depth.
+    pub fn set_camera_clear(&mut self, id: CameraId, clear: ClearSettings) {
+        self.cameras[id.0].clear = clear;
+    }
+
+    pub fn camera_clear(&self, id: CameraId) -> ClearSettings {
+        self.cameras[id.0].clear
+    }
+
+    // The color the whole surface is cleared to every frame, before the cameras draw. `None` keeps
+    // the surface as it is, which is only safe if the cameras cover every pixel.
+    pub fn set_clear_color(&mut self, color: Option<wgpu::Color>) {
+        self.clear_color = color;
+    }
+
+    pub fn clear_color(&self) -> Option<wgpu::Color> {
+        self.clear_color
+    }
+
+    // Renderable API
+    //======================
+
+    pub fn add_renderable(&mut self, mesh: MeshHandle, material: MaterialId, layers: RenderLayers) -> RenderableId {
+        self.renderables.push(Renderable {
+            mesh,
+            material,
+            layers,
+        });
+        self.transforms.push(Transform::IDENTITY);
+        RenderableId(self.renderables.len() - 1)
+    }
+
+    pub fn renderables(&self) -> &[Renderable] {
+        &self.renderables
+    }
+
+    pub fn set_layers(&mut self, id: RenderableId, layers: RenderLayers) {
+        self.renderables[id.0].layers = layers;
+    }
+
+    // Relative to the parent of the renderable, if it has one.
+    pub fn transform(&self, id: RenderableId) -> &Transform {
+        self.transforms.local(id.0)
+    }
+
+    pub fn set_transform(&mut self, id: RenderableId, transform: Transform) {
+        self.transforms.set(id.0, transform);
+    }
+
+    // Makes the transform of the renderable relative to `parent`, so it follows the parent around,
+    // or relative to the world again with `None`. Renderables can't be their own ancestors.
+    pub fn set_parent(&mut self, id: RenderableId, parent: Option<RenderableId>) {
+        if !self.transforms.set_parent(id.0, parent.map(|parent| parent.0)) {
+            tracing::warn!("{:?} can't be a child of {:?}, which is its descendant", parent, id);
         }
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        // Returns the next texture to be presented by the swapchain for drawing.
-        let output = self.surface.get_current_texture()?;
+    pub fn parent(&self, id: RenderableId) -> Option<RenderableId> {
+        self.transforms.parent(id.0).map(RenderableId)
+    }
 
-        // Creates a view of this texture.
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    // The matrix placing the renderable in the world, with the transforms of its ancestors.
+    pub fn world_matrix(&mut self, id: RenderableId) -> Matrix4<f32> {
+        self.transforms.update();
+        *self.transforms.world(id.0)
+    }
 
-        // Encodes a series of GPU operations.
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+    pub fn transforms(&self) -> &Transforms {
+        &self.transforms
+    }
 
-        {
-            // Begins recording of a render pass.
+    pub fn set_material(&mut self, id: RenderableId, material: MaterialId) {
+        self.renderables[id.0].material = material;
+    }
 
-            let color_attachments = &[wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None, // same as view unless multisampling is used.
-                // What operations will be performed on this color attachment.
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
-                    store: true,
-                },
-            }];
+    // Material API
+    //======================
+    // Bind groups and pipelines of materials are created on first use and cached.
+
+    // Compiles a material shader. Its material bind group layout is reflected from the source.
+    pub fn add_shader(&mut self, label: &str, wgsl: &str) -> Result<ShaderId, ReflectError> {
+        self.materials.add_shader(self.context.device(), label, wgsl, ShaderDefines::new())
+    }
+
+    // Compiles a material shader with preprocessor lines, see variants.rs. `defaults` apply unless
+    // the global defines, the material or the mesh say otherwise.
+    pub fn add_shader_with_defines(&mut self, label: &str, wgsl: &str, defaults: ShaderDefines) -> Result<ShaderId, ReflectError> {
+        self.materials.add_shader(self.context.device(), label, wgsl, defaults)
+    }
+
+    // Fails if the material does not provide the parameters and textures its shader expects.
+    pub fn add_material(&mut self, material: Material) -> Result<MaterialId, ReflectError> {
+        self.materials.add_material(self.context.device(), material)
+    }
+
+    // Overrides constants of the shader for its pipelines, e.g. quality knobs, see overrides.rs.
+    // On error the shader keeps its previous constants.
+    pub fn set_shader_constants(&mut self, id: ShaderId, constants: PipelineConstants) -> Result<(), ReflectError> {
+        self.materials.set_constants(self.context.device(), id, constants)
+    }
+
+    // The defines of every material shader, e.g. `SHADOWS` from the quality settings.
+    pub fn shader_defines(&self) -> &ShaderDefines {
+        self.materials.defines()
+    }
+
+    // Changes the defines of every material shader. The variants are compiled when next drawn.
+    pub fn set_shader_defines(&mut self, defines: ShaderDefines) {
+        self.materials.set_defines(defines);
+    }
+
+    pub fn material(&self, id: MaterialId) -> &Material {
+        self.materials.get(id)
+    }
+
+    pub fn material_mut(&mut self, id: MaterialId) -> &mut Material {
+        self.materials.get_mut(id)
+    }
+
+    // Vertex colors, tinted by `ColorParams`.
+    pub fn default_material(&self) -> MaterialId {
+        self.default_material
+    }
+
+    // A material of the default shader with `EMISSIVE`, glowing with `emissive` times `intensity`
+    // on top of `color`, whether lit or not. `texture` multiplies the glow by the mesh's uvs. With
+    // HDR, intensities above 1 are brighter than white and bloom with `ExposureSettings::bloom`.
+    pub fn add_emissive_material(
+        &mut self,
+        color: Vector4<f32>,
+        emissive: Vector3<f32>,
+        intensity: f32,
+        texture: Option<Rc<Texture>>,
+    ) -> Result<MaterialId, ReflectError> {
+        let shader = self.materials.get(self.default_material).shader();
+        let defines = ShaderDefines::new().with_flag("EMISSIVE", true).with_flag("EMISSIVE_TEXTURE", texture.is_some());
+        let mut material = Material::new(shader).with_defines(defines).with_uniform(&EmissiveParams {
+            color,
+            emissive: emissive.extend(intensity),
+        });
+        if let Some(texture) = texture {
+            material.add_texture(texture);
+        }
+        self.add_material(material)
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        self.context.device()
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        self.context.queue()
+    }
+
+    // Blocks until the GPU has finished all submitted work.
+    pub fn wait_idle(&self) {
+        self.context.device().poll(wgpu::Maintain::Wait);
+    }
+
+    // Readback API
+    //======================
+    // The returned futures resolve to the copied bytes once the GPU is done,
+    // e.g. `pollster::block_on(gfx.read_buffer(&buffer, 0..64))`.
+
+    // Reads `range` of a buffer created with `BufferUsages::COPY_SRC`.
+    pub fn read_buffer(&self, buffer: &wgpu::Buffer, range: Range<wgpu::BufferAddress>) -> Readback {
+        Readback::from_buffer(self.context.device().clone(), self.context.queue(), buffer, range)
+    }
+
+    // Reads a region of a texture created with `TextureUsages::COPY_SRC`,
+    // as tightly packed rows of `format` texels.
+    pub fn read_texture(
+        &self,
+        source: wgpu::ImageCopyTexture,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+    ) -> Readback {
+        Readback::from_texture(self.context.device().clone(), self.context.queue(), source, format, size)
+    }
+
+    // Reads the last frame rendered at the virtual resolution, before it was scaled to the window,
+    // as tightly packed rows of `frame_format` texels. `None` without a virtual resolution, as the
+    // surface textures can't be copied from.
+    pub fn read_frame(&self) -> Option<Readback> {
+        let letterbox = self.letterbox.as_ref()?;
+        let resolution = letterbox.resolution();
+        let size = wgpu::Extent3d {
+            width: resolution.width,
+            height: resolution.height,
+            depth_or_array_layers: 1,
+        };
+        Some(self.read_texture(letterbox.texture().as_image_copy(), self.config.format, size))
+    }
+
+    // The format of the frame: Rgba8UnormSrgb when headless, what suits the window otherwise.
+    pub fn frame_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    // Render graph API
+    //======================
+    // Nodes run before or after the scene pass, depending on their stage, in the same command
+    // buffer as the scene, see render_graph.rs. Their inputs and outputs live in
+    // `render_graph_mut().resources`.
+
+    pub fn add_render_node(&mut self, node: Box<dyn RenderNode>) {
+        self.graph.add_node(node);
+    }
+
+    pub fn render_graph_mut(&mut self) -> &mut RenderGraph {
+        &mut self.graph
+    }
+
+    // Texture streaming API
+    //======================
+    // Streamed textures arrive over several frames, see streaming.rs.
+    // A material is created with a placeholder texture, which is swapped for the streamed one
+    // as soon as its first mip levels are on the GPU, and again as sharper levels arrive.
+
+    pub fn stream_texture(&mut self, path: impl AsRef<Path>) -> StreamedTextureId {
+        self.watcher.watch(path.as_ref());
+        self.streamer.request(path)
+    }
+
+    // Makes texture `index` of `material` follow the streamed texture `texture`.
+    pub fn bind_streamed_texture(&mut self, material: MaterialId, index: usize, texture: StreamedTextureId) {
+        self.streamed_bindings.push((material, index, texture));
+    }
+
+    pub fn texture_streamer_mut(&mut self) -> &mut TextureStreamer {
+        &mut self.streamer
+    }
 
-            let desc = {
-                wgpu::RenderPassDescriptor {
-                    label: Some("Render Pass"),
-                    color_attachments: color_attachments,
-                    depth_stencil_attachment: None,
+    // Uploads streamed mip levels and points materials at the latest views.
+    fn update_streamed_textures(&mut self) {
+        self.streamer.update(self.context.device(), self.context.queue());
+        for &(material, index, id) in &self.streamed_bindings {
+            // Only textures of materials that are drawn count as used, the others may be evicted.
+            if !self.renderables.iter().any(|r| r.material == material) {
+                continue;
+            }
+            if let Some(texture) = self.streamer.texture(id) {
+                let material = self.materials.get_mut(material);
+                if !material.texture(index).is_some_and(|current| Rc::ptr_eq(current, &texture)) {
+                    material.set_texture(index, texture);
                 }
-            };
+            }
+        }
+    }
 
-            let mut render_pass = encoder.begin_render_pass(&desc);
+    // Asset API
+    //======================
+    // Textures, meshes and shaders are owned by the asset registry and referred to by handles,
+    // see assets.rs. Assets loaded from files are reloaded when the files change.
+    // Other files (models, level data) can be watched too; the game reloads those itself
+    // when they show up in `changed_files`.
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            // Draw something with 3 vertices and 1 instance.
-            // Used in [[builtin(vertex_index)]] in the shader source.
-            render_pass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshHandle {
+        self.assets.add_mesh(mesh)
+    }
+
+    // Swaps in a new version of a mesh, for the renderables that use it. See dynamic_mesh.rs.
+    pub fn set_mesh(&mut self, handle: &MeshHandle, mesh: Mesh) {
+        self.assets.set_mesh(handle, mesh)
+    }
+
+    // Compiles the shader file at `path`, or returns the shader already compiled from it. SPIR-V and
+    // GLSL shaders are translated to WGSL first, see shader_import.rs.
+    pub fn load_shader(&mut self, path: impl AsRef<Path>) -> Result<ShaderHandle, LoadError> {
+        let path = path.as_ref();
+        if let Some(handle) = self.assets.find_shader(path) {
+            return Ok(handle);
         }
+        let shader = shader_import::load_material_shader(path)?;
+        let fragment = shader.fragment.as_ref().map(|(wgsl, _)| wgsl.as_str());
+        let label = path.to_string_lossy();
+        let id = self.materials.add_shader_stages(self.context.device(), &label, &shader.wgsl, fragment, ShaderDefines::new())?;
+        for file in std::iter::once(path).chain(shader.fragment.as_ref().map(|(_, file)| file.as_path())) {
+            self.watcher.watch(file);
+            self.shader_files.push((file.to_path_buf(), id));
+        }
+        Ok(self.assets.add_shader(id, path))
+    }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    pub fn unit_cube(&mut self) -> MeshHandle {
+        self.assets.unit_cube()
+    }
+
+    // Loads a model in the background, see assets.rs.
+    pub fn load_mesh_async(&mut self, path: impl AsRef<Path>) -> MeshHandle {
+        let path = path.as_ref();
+        self.watcher.watch(path);
+        self.assets.load_mesh_async(path)
+    }
+
+    pub fn load_texture_async(&mut self, path: impl AsRef<Path>) -> TextureHandle {
+        let path = path.as_ref();
+        self.watcher.watch(path);
+        self.assets.load_texture_async(path)
+    }
+
+    pub fn load_texture(&mut self, path: impl AsRef<Path>) -> Result<TextureHandle, LoadError> {
+        let path = path.as_ref();
+        let handle = self.assets.load_texture(self.context.device(), self.context.queue(), path)?;
+        self.watcher.watch(path);
+        Ok(handle)
+    }
 
+    pub fn texture(&self, handle: &TextureHandle) -> &Rc<Texture> {
+        self.assets.texture(handle)
+    }
+
+    // A texture of procedural noise, made by a compute kernel, see noise.rs.
+    pub fn generate_noise(&mut self, label: &str, settings: &NoiseSettings, width: u32, height: u32) -> TextureHandle {
+        let (device, queue, cache) = (self.context.device(), self.context.queue(), self.context.cache());
+        let texture = self.noise.generate(device, queue, cache, label, settings, width, height);
+        self.assets.add_texture(texture)
+    }
+
+    // Makes texture `index` of `material` follow `texture`, also across reloads.
+    // The binding keeps the texture loaded.
+    pub fn bind_texture(&mut self, material: MaterialId, index: usize, texture: TextureHandle) {
+        let current = self.assets.texture(&texture).clone();
+        self.materials.get_mut(material).set_texture(index, current);
+        self.texture_bindings.retain(|(m, i, _)| (*m, *i) != (material, index));
+        self.texture_bindings.push((material, index, texture));
+    }
+
+    // Loads the image at `path` into texture `index` of `material`, and again whenever it changes.
+    pub fn bind_texture_file(&mut self, material: MaterialId, index: usize, path: impl AsRef<Path>) -> Result<(), LoadError> {
+        let texture = self.load_texture(path)?;
+        self.bind_texture(material, index, texture);
         Ok(())
     }
+
+    // The texture asset bound to texture `index` of `material` with `bind_texture`, if any.
+    pub fn bound_texture(&self, material: MaterialId, index: usize) -> Option<&TextureHandle> {
+        self.texture_bindings
+            .iter()
+            .find(|(m, i, _)| (*m, *i) == (material, index))
+            .map(|(_, _, texture)| texture)
+    }
+
+    pub fn watch_file(&mut self, path: impl AsRef<Path>) {
+        self.watcher.watch(path.as_ref());
+    }
+
+    // The watched files that changed since the previous frame.
+    pub fn changed_files(&self) -> &[PathBuf] {
+        &self.changed_files
+    }
+
+    // Uploads the assets finished loading in the background, and swaps in new versions of the changed
+    // shaders, textures and models. `App` calls this between frames.
+    pub fn update_assets(&mut self) {
+        self.assets.update(self.context.device(), self.context.queue());
+        self.changed_files = self.watcher.changed();
+        for path in &self.changed_files {
+            tracing::info!("Reloading {}", path.display());
+            for (_, id) in self.shader_files.iter().filter(|(file, _)| file == path) {
+                // The shader is loaded from its first file, also when another one changed.
+                let first = self.assets.shader_path(*id).unwrap_or(path);
+                let result = shader_import::load_material_shader(first).and_then(|shader| {
+                    let fragment = shader.fragment.as_ref().map(|(wgsl, _)| wgsl.as_str());
+                    Ok(self.materials.reload_shader(self.context.device(), *id, &shader.wgsl, fragment)?)
+                });
+                if let Err(e) = result {
+                    tracing::error!("Failed to reload shader {}: {}", path.display(), e);
+                }
+            }
+            if self.assets.is_texture_file(path) {
+                match Texture::load(self.context.device(), self.context.queue(), self.context.cache(), path) {
+                    Ok(texture) => {
+                        self.assets.replace_texture(path, texture);
+                    }
+                    Err(e) => tracing::error!("Failed to reload texture {}: {}", path.display(), e),
+                }
+            }
+            if self.assets.is_mesh_file(path) {
+                self.assets.reload_mesh(path);
+            }
+            self.streamer.reload(path);
+        }
+
+        for (material, index, texture) in &self.texture_bindings {
+            let texture = self.assets.texture(texture);
+            let material = self.materials.get_mut(*material);
+            if !material.texture(*index).is_some_and(|current| Rc::ptr_eq(current, texture)) {
+                material.set_texture(*index, texture.clone());
+            }
+        }
+    }
+
+    // Unloads the assets that are no longer referred to, and stops watching their files.
+    // Layouts and samplers only the cache still holds are dropped with them.
+    fn collect_garbage(&mut self) {
+        for path in self.assets.collect_garbage() {
+            self.watcher.unwatch(&path);
+            // Together with the other files of the shader, e.g. the fragment stage of GLSL shaders.
+            if let Some(&(_, id)) = self.shader_files.iter().find(|(file, _)| *file == path) {
+                for (file, _) in self.shader_files.iter().filter(|(file, shader)| *shader == id && *file != path) {
+                    self.watcher.unwatch(file);
+                }
+                self.shader_files.retain(|(_, shader)| *shader != id);
+            }
+        }
+        let dropped = self.context.cache().collect_garbage();
+        if dropped > 0 {
+            let (layouts, samplers) = self.context.cache().counts();
+            tracing::debug!("Dropped {} unused layouts and samplers, {} layouts and {} samplers left", dropped, layouts, samplers);
+        }
+    }
+
+    // Skinning API
+    //======================
+    // Skinned meshes are posed on the GPU and then drawn like any other mesh, see skinning.rs.
+
+    // Registers a mesh deformed by `joint_count` joints. Add it as a renderable like a static mesh.
+    pub fn add_skinned_mesh(
+        &mut self,
+        label: &str,
+        vertices: &[SkinnedVertex],
+        indices: &[u16],
+        joint_count: usize,
+    ) -> (SkinId, MeshHandle) {
+        let (skin, mesh) = self.skinning.add(self.context.device(), label, vertices, indices, joint_count);
+        (skin, self.assets.add_mesh(mesh))
+    }
+
+    pub fn set_joint_matrices(&mut self, skin: SkinId, matrices: &[Matrix4<f32>]) {
+        self.skinning.set_joint_matrices(self.context.queue(), skin, matrices);
+    }
+
+    // Queues the bones and joints of the skinned renderable in `pose` with the debug lines, and
+    // with a `camera`, the names of the joints next to them as text, see skeleton.rs.
+    pub fn draw_skeleton(&mut self, id: RenderableId, skeleton: &Skeleton, pose: &Pose, highlight: Option<usize>, camera: Option<CameraId>) {
+        let model = self.world_matrix(id);
+        skeleton.debug_draw(&mut self.debug_draw, pose, &model, highlight);
+        let camera = match camera {
+            Some(camera) => camera,
+            None => return,
+        };
+        let (width, height) = self.render_size();
+        let view = &self.cameras[camera.0];
+        let (vx, vy, w, h) = view.viewport.to_pixels(width, height);
+        let view_proj = view.camera.build_view_projection_matrix() * model;
+        for (i, matrix) in skeleton.model_matrices(pose).iter().enumerate() {
+            let clip = view_proj * matrix.w;
+            if clip.w <= 0.0 {
+                continue;
+            }
+            let (ndc_x, ndc_y) = (clip.x / clip.w, clip.y / clip.w);
+            if ndc_x.abs() > 1.0 || ndc_y.abs() > 1.0 {
+                continue;
+            }
+            let x = vx as f32 + (ndc_x + 1.0) * 0.5 * w as f32;
+            let y = vy as f32 + (1.0 - ndc_y) * 0.5 * h as f32;
+            let color = if highlight == Some(i) { [1.0, 0.3, 0.9, 1.0] } else { [1.0, 1.0, 1.0, 1.0] };
+            self.text.queue_text(x + 4.0, y, 1.0, color, &skeleton.joints()[i].name);
+        }
+    }
+
+    // Lens flare API
+    //======================
+    // Glare and ghosts of bright lights, faded out where the scene hides them, see lens_flare.rs.
+
+    pub fn add_lens_flare(&mut self, flare: LensFlare) -> LensFlareId {
+        self.lens_flares.add(flare)
+    }
+
+    pub fn lens_flare(&self, id: LensFlareId) -> &LensFlare {
+        self.lens_flares.get(id)
+    }
+
+    pub fn set_lens_flare(&mut self, id: LensFlareId, flare: LensFlare) {
+        self.lens_flares.set(id, flare);
+    }
+
+    pub fn remove_lens_flare(&mut self, id: LensFlareId) {
+        self.lens_flares.remove(id);
+    }
+
+    // Mirror API
+    //======================
+    // Planes reflecting the scene as seen by one of the cameras, on the surfaces drawn with their
+    // mirror material, see mirrors.rs.
+
+    pub fn add_mirror(&mut self, mirror: Mirror) -> MirrorId {
+        let id = self.mirrors.add(self.context.device(), self.context.cache(), mirror);
+        if id.0 == self.mirror_views.len() {
+            let view = CameraView::new(
+                self.context.device(),
+                &self.camera_bind_group_layout,
+                &self.frames,
+                &self.local_lights,
+                Camera::new(1.0),
+            );
+            self.mirror_views.push(view);
+        }
+        id
+    }
+
+    pub fn mirror(&self, id: MirrorId) -> &Mirror {
+        self.mirrors.get(id)
+    }
+
+    pub fn set_mirror(&mut self, id: MirrorId, mirror: Mirror) {
+        if self.mirrors.set(self.context.device(), self.context.cache(), id, mirror) {
+            let texture = self.mirrors.texture(id).clone();
+            for &material in self.mirrors.materials(id) {
+                self.materials.get_mut(material).set_texture(0, texture.clone());
+            }
+        }
+    }
+
+    // Its materials keep showing the last reflection.
+    pub fn remove_mirror(&mut self, id: MirrorId) {
+        self.mirrors.remove(id);
+    }
+
+    // A material of the default shader with `PLANAR_MIRROR`, showing the mirror's reflection
+    // blended over `color` by `reflectivity`. For surfaces in the mirror's plane only.
+    pub fn add_mirror_material(&mut self, id: MirrorId, color: Vector4<f32>, reflectivity: f32) -> Result<MaterialId, ReflectError> {
+        let shader = self.materials.get(self.default_material).shader();
+        let material = Material::new(shader)
+            .with_defines(ShaderDefines::new().with_flag("PLANAR_MIRROR", true))
+            .with_uniform(&ReflectiveParams { color, reflectivity })
+            .with_texture(self.mirrors.texture(id).clone());
+        let material = self.add_material(material)?;
+        self.mirrors.add_material(id, material);
+        Ok(material)
+    }
+
+    // Renders every mirror through its mirrored camera, like the probes without multisampling.
+    fn render_mirrors(&mut self, encoder: &mut wgpu::CommandEncoder, counters: &mut RenderCounters) {
+        let keys = self.view_pipeline_keys(self.scene_format());
+        let clear = self.clear_color.unwrap_or(wgpu::Color::BLACK);
+
+        for id in self.mirrors.ids() {
+            let mirror = *self.mirrors.get(id);
+            let (view_proj, eye) = match self.cameras.get(mirror.camera).and_then(|view| mirror.mirrored_view_proj(&view.camera)) {
+                Some(mirrored) => mirrored,
+                None => continue,
+            };
+            let view = &mut self.mirror_views[id.0];
+            view.uniform.set_view_proj(view_proj, eye);
+            view.write_uniform(self.context.device(), encoder, &mut self.frames);
+
+            let camera = ViewCamera {
+                view_proj,
+                layers: mirror.layers,
+                skip: &|renderable: &Renderable| self.mirrors.is_mirror_material(renderable.material),
+            };
+            let target = ViewTarget {
+                pass: "Mirror Pass",
+                label: format!("Mirror {}", id.0),
+                color: &self.mirrors.texture(id).view,
+                depth: self.mirrors.depth_view(id),
+                clear,
+                probes: self.probes.bind_group(),
+            };
+            self.draw_view(encoder, &self.mirror_views[id.0], &camera, &target, &keys, counters);
+        }
+    }
+
+    // Render target API
+    //======================
+    // Cameras rendering the scene into textures for materials to show, in a set order every
+    // frame, see render_targets.rs. The cameras above render into regions of the surface instead.
+
+    pub fn add_texture_camera(&mut self, camera: Camera, target: RenderTarget) -> TextureCameraId {
+        let id = self.render_targets.add(self.context.device(), self.context.cache(), camera, target);
+        if id.0 == self.target_views.len() {
+            let view = CameraView::new(
+                self.context.device(),
+                &self.camera_bind_group_layout,
+                &self.frames,
+                &self.local_lights,
+                Camera::new(1.0),
+            );
+            self.target_views.push(view);
+        }
+        id
+    }
+
+    pub fn texture_camera(&self, id: TextureCameraId) -> &Camera {
+        self.render_targets.camera(id)
+    }
+
+    pub fn texture_camera_mut(&mut self, id: TextureCameraId) -> &mut Camera {
+        self.render_targets.camera_mut(id)
+    }
+
+    pub fn render_target(&self, id: TextureCameraId) -> &RenderTarget {
+        self.render_targets.target(id)
+    }
+
+    // A new resolution gives the materials sampling the old texture the new one.
+    pub fn set_render_target(&mut self, id: TextureCameraId, target: RenderTarget) {
+        if let Some(old) = self.render_targets.set_target(self.context.device(), self.context.cache(), id, target) {
+            self.materials.replace_texture(&old, self.render_targets.texture(id));
+        }
+    }
+
+    // The texture the camera renders into, to add to materials.
+    pub fn render_target_texture(&self, id: TextureCameraId) -> &Rc<Texture> {
+        self.render_targets.texture(id)
+    }
+
+    // Its materials keep showing the last image.
+    pub fn remove_texture_camera(&mut self, id: TextureCameraId) {
+        self.render_targets.remove(id);
+    }
+
+    // Renders the active texture cameras in their order, like the mirrors.
+    fn render_targets(&mut self, encoder: &mut wgpu::CommandEncoder, counters: &mut RenderCounters) {
+        let keys = self.view_pipeline_keys(self.scene_format());
+
+        for id in self.render_targets.ordered_ids() {
+            let camera = self.render_targets.camera(id);
+            let view = &mut self.target_views[id.0];
+            view.uniform.update_view_proj(camera);
+            view.write_uniform(self.context.device(), encoder, &mut self.frames);
+
+            let texture = self.render_targets.texture(id);
+            let camera = ViewCamera {
+                view_proj: camera.build_view_projection_matrix(),
+                layers: camera.layers,
+                skip: &|renderable: &Renderable| self.materials.get(renderable.material).uses_texture(texture),
+            };
+            let target = ViewTarget {
+                pass: "Render Target Pass",
+                label: format!("Texture Camera {}", id.0),
+                color: &texture.view,
+                depth: self.render_targets.depth_view(id),
+                clear: self.render_targets.target(id).clear_color,
+                probes: self.probes.bind_group(),
+            };
+            self.draw_view(encoder, &self.target_views[id.0], &camera, &target, &keys, counters);
+        }
+    }
+
+    // Reflection probe API
+    //======================
+    // Cubemaps of the scene around points, sampled by materials with `REFLECTION_PROBES`, see
+    // reflection_probes.rs.
+
+    // `None` if all MAX_PROBES cubemaps are taken.
+    pub fn add_reflection_probe(&mut self, probe: ReflectionProbe) -> Option<ReflectionProbeId> {
+        if self.probe_views.is_empty() {
+            let device = self.context.device();
+            self.probe_views = (0..6 * MAX_PROBES)
+                .map(|_| CameraView::new(device, &self.camera_bind_group_layout, &self.frames, &self.local_lights, Camera::new(1.0)))
+                .collect();
+        }
+        self.probes.add(probe)
+    }
+
+    pub fn reflection_probe(&self, id: ReflectionProbeId) -> &ReflectionProbe {
+        self.probes.get(id)
+    }
+
+    pub fn set_reflection_probe(&mut self, id: ReflectionProbeId, probe: ReflectionProbe) {
+        self.probes.set(id, probe);
+    }
+
+    // Renders an on-demand probe again in the next frame.
+    pub fn refresh_reflection_probe(&mut self, id: ReflectionProbeId) {
+        self.probes.refresh(id);
+    }
+
+    pub fn remove_reflection_probe(&mut self, id: ReflectionProbeId) {
+        self.probes.remove(id);
+    }
+
+    // Renders the due cubemap faces like cameras of their own, into the probe format without
+    // multisampling, and with empty probes bound: a probe can't sample what it renders into.
+    fn render_probes(&mut self, encoder: &mut wgpu::CommandEncoder, faces: &[(ReflectionProbeId, usize)], counters: &mut RenderCounters) {
+        let keys = self.view_pipeline_keys(PROBE_FORMAT);
+        let clear = self.clear_color.unwrap_or(wgpu::Color::BLACK);
+
+        for &(id, face) in faces {
+            let camera = self.probes.get(id).face_camera(face);
+            let view = &mut self.probe_views[6 * id.0 + face];
+            view.uniform.update_view_proj(&camera);
+            view.write_uniform(self.context.device(), encoder, &mut self.frames);
+
+            let camera = ViewCamera {
+                view_proj: camera.build_view_projection_matrix(),
+                layers: camera.layers,
+                skip: &|_: &Renderable| false,
+            };
+            let target = ViewTarget {
+                pass: "Reflection Probe Pass",
+                label: format!("Probe {} Face {}", id.0, face),
+                color: self.probes.face_view(id, face),
+                depth: self.probes.depth_view(),
+                clear,
+                probes: self.probes.capture_bind_group(),
+            };
+            self.draw_view(encoder, &self.probe_views[6 * id.0 + face], &camera, &target, &keys, counters);
+        }
+    }
+
+    // The pipelines of the renderables for a view rendering into `format` without multisampling,
+    // for `draw_view`. Errors were reported when preparing the same materials for the scene.
+    fn view_pipeline_keys(&mut self, format: wgpu::TextureFormat) -> Vec<Option<PipelineKey>> {
+        let (device, queue) = (self.context.device(), self.context.queue());
+        let layouts = SceneLayouts {
+            camera: &self.camera_bind_group_layout,
+            probes: self.probes.layout(),
+        };
+        self.renderables
+            .iter()
+            .map(|renderable| {
+                let vertex = self.assets.mesh(&renderable.mesh).layout;
+                self.materials.prepare(device, queue, &layouts, renderable.material, format, 1, vertex).ok()
+            })
+            .collect()
+    }
+
+    // Draws what `camera` sees into `target`, bound through `view`, whose uniform was written for
+    // this frame. Shared by the mirrors, the texture cameras and the probe faces.
+    fn draw_view(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &CameraView,
+        camera: &ViewCamera,
+        target: &ViewTarget,
+        keys: &[Option<PipelineKey>],
+        counters: &mut RenderCounters,
+    ) {
+        let frustum = Frustum::from_matrix(&camera.view_proj);
+        let mut visible = Vec::new();
+        self.bvh.query_frustum(&frustum, |i| visible.push(i));
+        visible.extend(&self.unbounded);
+        visible.retain(|&i| {
+            let renderable = &self.renderables[i];
+            camera.layers.intersects(renderable.layers) && !(camera.skip)(renderable)
+        });
+        visible.sort_unstable();
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(target.pass),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target.color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(target.clear),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target.depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.push_debug_group(&target.label);
+        render_pass.set_bind_group(0, &view.bind_groups[self.frames.index()], &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for i in visible {
+            let (instance, renderable) = (i as u32, &self.renderables[i]);
+            let mesh = self.assets.mesh(&renderable.mesh);
+            let key = match &keys[i] {
+                Some(key) => key,
+                None => continue,
+            };
+            let material = self.materials.get(renderable.material);
+            let (pipeline, bind_group) = match (self.materials.pipeline(key), material.bind_group(key)) {
+                (Some(pipeline), Some(bind_group)) => (pipeline, bind_group),
+                _ => continue,
+            };
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(1, bind_group, &[]);
+            if self.materials.uses_probes(key) {
+                render_pass.set_bind_group(2, target.probes, &[]);
+            }
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, instance..instance + 1);
+            counters.draw_calls += 1;
+            counters.triangles += mesh.num_indices / 3;
+        }
+        render_pass.pop_debug_group();
+    }
+
+    // Renders the shadow maps of the local lights into their squares of the atlas, in one pass.
+    fn render_shadows(&mut self, encoder: &mut wgpu::CommandEncoder, counters: &mut RenderCounters) {
+        let device = self.context.device();
+        for renderable in &self.renderables {
+            let vertex = self.assets.mesh(&renderable.mesh).layout;
+            self.local_lights.atlas_mut().prepare_pipeline(device, vertex);
+        }
+        let atlas = self.local_lights.atlas();
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: atlas.view(),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for (index, view) in atlas.views().iter().enumerate() {
+            let frustum = Frustum::from_matrix(&view.view_proj);
+            let mut visible = Vec::new();
+            self.bvh.query_frustum(&frustum, |i| visible.push(i));
+            visible.extend(&self.unbounded);
+            visible.retain(|&i| view.layers.intersects(self.renderables[i].layers));
+            visible.sort_unstable();
+
+            render_pass.push_debug_group(&format!("Shadow Map {}", index));
+            atlas.set_view(&mut render_pass, index);
+            for i in visible {
+                let mesh = self.assets.mesh(&self.renderables[i].mesh);
+                let pipeline = match atlas.pipeline(mesh.layout) {
+                    Some(pipeline) => pipeline,
+                    None => continue,
+                };
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                render_pass.draw_indexed(0..mesh.num_indices, 0, i as u32..i as u32 + 1);
+                counters.draw_calls += 1;
+                counters.triangles += mesh.num_indices / 3;
+            }
+            render_pass.pop_debug_group();
+        }
+    }
+
+    // Occlusion query API
+    //======================
+    // How much of a renderable or a box the first camera sees past the scene's depth, counted by
+    // the GPU and known a few frames later, see occlusion_queries.rs. Needs pipeline statistics
+    // queries, which GL and Metal lack.
+
+    // `None` without occlusion queries, or with all of them taken.
+    pub fn add_occlusion_query(&mut self, target: OcclusionTarget) -> Option<OcclusionQueryId> {
+        self.occlusion_queries.as_mut()?.add(target)
+    }
+
+    pub fn set_occlusion_target(&mut self, id: OcclusionQueryId, target: OcclusionTarget) {
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.set_target(id, target);
+        }
+    }
+
+    pub fn remove_occlusion_query(&mut self, id: OcclusionQueryId) {
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.remove(id);
+        }
+    }
+
+    // The fragments of the target that passed the depth test. `None` until the first count arrives.
+    pub fn occlusion_fragments(&self, id: OcclusionQueryId) -> Option<u64> {
+        self.occlusion_queries.as_ref()?.fragments(id)
+    }
+
+    // Whether all of the target was hidden, e.g. to skip the AI of a monster nobody sees.
+    // `None` until the first count arrives.
+    pub fn is_occluded(&self, id: OcclusionQueryId) -> Option<bool> {
+        self.occlusion_fragments(id).map(|fragments| fragments == 0)
+    }
+
+    // GPU particle API
+    //======================
+    // Emitters of particles simulated on the GPU, bouncing off what the first camera sees, see
+    // gpu_particles.rs. The game can't read the particles; see particles.rs for ones it can.
+
+    pub fn add_gpu_particles(&mut self, settings: GpuParticleSettings) -> GpuParticlesId {
+        self.gpu_particles.add(self.context.device(), settings)
+    }
+
+    pub fn gpu_particle_settings(&self, id: GpuParticlesId) -> &GpuParticleSettings {
+        self.gpu_particles.settings(id)
+    }
+
+    // E.g. to move the emitter. Changing `max_particles` has no effect.
+    pub fn gpu_particle_settings_mut(&mut self, id: GpuParticlesId) -> &mut GpuParticleSettings {
+        self.gpu_particles.settings_mut(id)
+    }
+
+    // Its living particles vanish with it.
+    pub fn remove_gpu_particles(&mut self, id: GpuParticlesId) {
+        self.gpu_particles.remove(id);
+    }
+
+    // Draws the particles of CPU emitters this frame, sorted for the main camera, see particles.rs.
+    pub fn draw_particles(&mut self, emitters: &[&ParticleEmitter]) {
+        let eye = self.cameras[self.main_camera().0].camera.eye;
+        self.particle_sprites.queue(emitters, eye);
+    }
+
+    // Spatial query API
+    //======================
+    // Where renderables are, answered from the boxes around their meshes with the BVH (bvh.rs):
+    // fast, but only as exact as the boxes. Renderables whose meshes have no bounds (skinned ones)
+    // are never found.
+
+    // The nearest renderable the camera sees at the pixel (x, y) of the surface, and its distance.
+    pub fn pick(&mut self, camera: CameraId, x: f32, y: f32) -> Option<(RenderableId, f32)> {
+        let ray = self.cursor_ray(camera, x, y)?;
+        let layers = self.cameras[camera.0].camera.layers;
+        self.raycast(&ray, layers)
+    }
+
+    // The ray from the camera through the pixel (x, y) of the surface.
+    pub fn cursor_ray(&self, camera: CameraId, x: f32, y: f32) -> Option<Ray> {
+        let (x, y) = self.window_to_virtual(x, y)?;
+        let (width, height) = self.render_size();
+        let view = &self.cameras[camera.0];
+        let (vx, vy, w, h) = view.viewport.to_pixels(width, height);
+        if w == 0 || h == 0 {
+            return None;
+        }
+        // Pixels have y pointing down, normalized device coordinates have y pointing up.
+        let ndc_x = (x - vx as f32) / w as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - (y - vy as f32) / h as f32 * 2.0;
+        Ray::from_screen(&view.camera.build_view_projection_matrix(), ndc_x, ndc_y)
+    }
+
+    // The nearest renderable on one of `layers` the ray hits, and its distance.
+    pub fn raycast(&mut self, ray: &Ray, layers: RenderLayers) -> Option<(RenderableId, f32)> {
+        self.update_bvh();
+        let renderables = &self.renderables;
+        self.bvh
+            .raycast(ray, |i| renderables[i].layers.intersects(layers))
+            .map(|(i, distance)| (RenderableId(i), distance))
+    }
+
+    // The renderables whose bounds intersect `bounds`, e.g. for triggers and explosions.
+    pub fn renderables_in(&mut self, bounds: &Aabb) -> Vec<RenderableId> {
+        self.update_bvh();
+        let mut found = Vec::new();
+        self.bvh.query_aabb(bounds, |i| found.push(RenderableId(i)));
+        found
+    }
+
+    // Whether the camera would draw the renderable: it is on one of the camera's layers and may be
+    // inside its frustum. Doesn't know about occlusion.
+    pub fn is_visible(&mut self, camera: CameraId, id: RenderableId) -> bool {
+        self.update_bvh();
+        let camera = &self.cameras[camera.0].camera;
+        if !camera.layers.intersects(self.renderables[id.0].layers) {
+            return false;
+        }
+        match self.bvh.bounds(id.0) {
+            Some(bounds) => Frustum::from_matrix(&camera.build_view_projection_matrix()).intersects_aabb(&bounds),
+            None => true,
+        }
+    }
+
+    // Draws the boxes of the BVH down to `max_depth` for this frame: leaves green, the levels
+    // above them from red at the root to blue.
+    pub fn draw_bvh(&mut self, max_depth: usize) {
+        self.update_bvh();
+        const LEVELS: [[f32; 4]; 4] = [
+            [1.0, 0.2, 0.2, 1.0],
+            [1.0, 0.6, 0.2, 1.0],
+            [1.0, 1.0, 0.2, 1.0],
+            [0.3, 0.5, 1.0, 1.0],
+        ];
+        let debug_draw = &mut self.debug_draw;
+        self.bvh.visit_nodes(max_depth, |bounds, depth, leaf| {
+            let color = if leaf { [0.2, 1.0, 0.3, 1.0] } else { LEVELS[depth.min(LEVELS.len() - 1)] };
+            debug_draw.aabb(bounds.min, bounds.max, color);
+        });
+    }
+
+    // What of the engine's state is drawn as wireframes every frame, see debug_draw.rs.
+    pub fn set_debug_views(&mut self, views: DebugViews) {
+        self.debug_views = views;
+    }
+
+    pub fn debug_views(&self) -> DebugViews {
+        self.debug_views
+    }
+
+    // Queues the frustum of a light, or of one cascade of its shadow map, for this frame. Drawn
+    // with `DebugViews::light_frusta`, each in the color of its place in the queue.
+    pub fn draw_light_frustum(&mut self, view_proj: Matrix4<f32>) {
+        self.light_frusta.push(view_proj);
+    }
+
+    // Queues the lines of the enabled debug views. `visible` is what each camera draws this frame.
+    fn queue_debug_views(&mut self, visible: &[Vec<usize>]) {
+        const CASCADES: [[f32; 4]; 4] = [
+            [1.0, 0.3, 0.3, 1.0],
+            [0.3, 1.0, 0.3, 1.0],
+            [0.3, 0.5, 1.0, 1.0],
+            [1.0, 0.3, 1.0, 1.0],
+        ];
+        let views = self.debug_views;
+        let light_frusta = std::mem::take(&mut self.light_frusta);
+        if views.camera_frusta {
+            for view in &self.cameras {
+                self.debug_draw.frustum(&view.camera.build_view_projection_matrix(), [1.0, 1.0, 0.2, 1.0]);
+            }
+        }
+        if views.light_frusta {
+            for (i, view_proj) in light_frusta.iter().enumerate() {
+                self.debug_draw.frustum(view_proj, CASCADES[i % CASCADES.len()]);
+            }
+        }
+        if views.aabbs || views.bounding_spheres {
+            let mut drawn = vec![false; self.renderables.len()];
+            for &i in visible.iter().flatten() {
+                drawn[i] = true;
+            }
+            for (i, &drawn) in drawn.iter().enumerate() {
+                let bounds = match self.bvh.bounds(i) {
+                    Some(bounds) => bounds,
+                    None => continue,
+                };
+                let color = if drawn { [0.2, 1.0, 0.3, 1.0] } else { [1.0, 0.2, 0.2, 1.0] };
+                if views.aabbs {
+                    self.debug_draw.aabb(bounds.min, bounds.max, color);
+                }
+                if views.bounding_spheres {
+                    self.debug_draw.sphere(bounds.center(), (bounds.max - bounds.min).magnitude() / 2.0, color);
+                }
+            }
+        }
+    }
+
+    // Text API
+    //======================
+    // Text is queued for a single frame, in pixels from the top left corner of the surface.
+
+    pub fn draw_text(&mut self, x: f32, y: f32, scale: f32, color: [f32; 4], text: &str) {
+        self.text.queue_text(x, y, scale, color, text);
+    }
+
+    // UI panel API
+    //======================
+    // Nine-slice panels, queued for a single frame like text and drawn below it, see nine_slice.rs.
+
+    pub fn load_ui_atlas(&mut self, path: impl AsRef<Path>) -> Result<UiAtlasId, LoadError> {
+        Ok(self.panels.load_atlas(self.context.device(), self.context.queue(), self.context.cache(), path.as_ref())?)
+    }
+
+    pub fn draw_panel(&mut self, x: f32, y: f32, width: f32, height: f32, slice: &NineSlice, color: [f32; 4]) {
+        self.panels.queue_panel(x, y, width, height, slice, color);
+    }
+
+    pub fn ui_scale(&self) -> f32 {
+        self.panels.scale()
+    }
+
+    // Multiplies the positions and sizes of the panels, e.g. by the DPI factor of the window.
+    pub fn set_ui_scale(&mut self, scale: f32) {
+        self.panels.set_scale(scale);
+    }
+
+    // Tilemap API
+    //======================
+    // Maps made with Tiled, drawn behind the scene by the cameras that see
+    // `RenderLayers::TILEMAPS`, see tilemap.rs.
+
+    pub fn load_tilemap(&mut self, path: impl AsRef<Path>) -> Result<TilemapId, LoadError> {
+        self.tilemaps.load(self.context.device(), self.context.queue(), self.context.cache(), path.as_ref())
+    }
+
+    pub fn tilemap(&self, id: TilemapId) -> &Tilemap {
+        self.tilemaps.get(id)
+    }
+
+    // E.g. to show or hide layers.
+    pub fn tilemap_mut(&mut self, id: TilemapId) -> &mut Tilemap {
+        self.tilemaps.get_mut(id)
+    }
+
+    // Shared by all maps.
+    pub fn tilemap_camera(&self) -> &Camera2d {
+        self.tilemaps.camera()
+    }
+
+    pub fn tilemap_camera_mut(&mut self) -> &mut Camera2d {
+        self.tilemaps.camera_mut()
+    }
+
+    // Debug drawing API
+    //======================
+    // World-space lines, also queued for a single frame, see debug_draw.rs.
+
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    // Grid API
+    //======================
+    // An infinite ground grid for editor and debugging scenes, see grid.rs.
+
+    // `None` stops drawing the grid, which is the default.
+    pub fn set_grid(&mut self, settings: Option<GridSettings>) {
+        self.grid_settings = settings;
+    }
+
+    pub fn grid(&self) -> Option<GridSettings> {
+        self.grid_settings
+    }
+
+    // Clouds API
+    //======================
+    // A layer of raymarched clouds over the scene, see clouds.rs.
+
+    // `None` stops drawing the clouds, which is the default.
+    pub fn set_clouds(&mut self, settings: Option<CloudSettings>) {
+        self.cloud_settings = settings;
+    }
+
+    pub fn clouds(&self) -> Option<CloudSettings> {
+        self.cloud_settings
+    }
+
+    // Volumetrics API
+    //======================
+    // Light shafts of the local lights through a fog, occluded by their shadow maps, see
+    // volumetrics.rs.
+
+    // `None` stops drawing the light shafts, which is the default.
+    pub fn set_volumetrics(&mut self, settings: Option<VolumetricSettings>) {
+        self.volumetric_settings = settings;
+    }
+
+    pub fn volumetrics(&self) -> Option<VolumetricSettings> {
+        self.volumetric_settings
+    }
+
+    // Lighting API
+    //======================
+    // The directional light of the sun, see light.rs, and point and spot lights for materials with
+    // `LOCAL_LIGHTS`, see local_lights.rs, which cast shadows from the shadow atlas, see
+    // shadow_atlas.rs, and may shine through cookies, see light_cookies.rs.
+
+    // Ignored while there is a sky, whose sun decides the light.
+    pub fn set_light(&mut self, light: DirectionalLight) {
+        self.light = light;
+    }
+
+    // The light this frame is lit by: the sky's sun, if there is a sky.
+    pub fn light(&self) -> DirectionalLight {
+        self.sky_settings.map_or(self.light, |sky| sky.light())
+    }
+
+    // `None` if all MAX_LOCAL_LIGHTS are taken.
+    pub fn add_local_light(&mut self, light: LocalLight) -> Option<LocalLightId> {
+        self.local_lights.add(light)
+    }
+
+    pub fn local_light(&self, id: LocalLightId) -> &LocalLight {
+        self.local_lights.get(id)
+    }
+
+    pub fn set_local_light(&mut self, id: LocalLightId, light: LocalLight) {
+        self.local_lights.set(id, light);
+    }
+
+    pub fn remove_local_light(&mut self, id: LocalLightId) {
+        self.local_lights.remove(id);
+    }
+
+    // A cookie for spot lights from tightly packed 8-bit RGBA pixels (sRGB encoded), see
+    // light_cookies.rs. `None` if all MAX_COOKIES are taken.
+    pub fn add_light_cookie(&mut self, width: u32, height: u32, rgba: &[u8]) -> Option<CookieId> {
+        self.local_lights.cookies_mut().add(self.context.queue(), width, height, rgba)
+    }
+
+    // Loads a PNG or JPEG image as a cookie. `Ok(None)` if all MAX_COOKIES are taken.
+    pub fn load_light_cookie(&mut self, path: impl AsRef<Path>) -> Result<Option<CookieId>, LoadError> {
+        let image = image::open(path.as_ref())?.to_rgba8();
+        let (width, height) = image.dimensions();
+        Ok(self.add_light_cookie(width, height, &image))
+    }
+
+    pub fn remove_light_cookie(&mut self, id: CookieId) {
+        self.local_lights.cookies_mut().remove(id);
+    }
+
+    // The shadow maps of the last frame and their squares of the atlas, e.g. to draw their frusta
+    // with `draw_light_frustum`.
+    pub fn shadow_views(&self) -> &[ShadowView] {
+        self.local_lights.atlas().views()
+    }
+
+    // Sky API
+    //======================
+    // A physically based sky behind the scene, which lights it, see sky.rs.
+
+    // `None` stops drawing the sky, which is the default, and the light set with `set_light` is
+    // back.
+    pub fn set_sky(&mut self, settings: Option<SkySettings>) {
+        self.sky_settings = settings;
+    }
+
+    pub fn sky(&self) -> Option<SkySettings> {
+        self.sky_settings
+    }
+
+    // Gizmo API
+    //======================
+    // Editor handles for moving, rotating and scaling renderables with the mouse, see gizmo.rs.
+
+    // Lets the cursor at the pixel (x, y) of the surface hover and drag the gizmo's handles around
+    // the renderable, as seen by `camera`, and queues the gizmo for this frame. Returns true if
+    // the renderable's transform was changed.
+    pub fn manipulate(&mut self, gizmo: &mut Gizmo, camera: CameraId, id: RenderableId, cursor: (f32, f32), pressed: bool) -> bool {
+        let transform = *self.transforms.local(id.0);
+        // The world matrix of the parent is from the last frame, which is only off while the
+        // parent itself moves.
+        let parent = match self.transforms.parent(id.0) {
+            Some(parent) => *self.transforms.world(parent),
+            None => Matrix4::identity(),
+        };
+        let world = parent * transform.to_matrix();
+        let (width, height) = self.render_size();
+        let (_, _, _, viewport_height) = self.cameras[camera.0].viewport.to_pixels(width, height);
+        let view_camera = &self.cameras[camera.0].camera;
+        // The height of the view at the renderable's depth, spread over the viewport's pixels.
+        let forward = (view_camera.target - view_camera.eye).normalize();
+        let depth = (Point3::from_vec(world.w.truncate()) - view_camera.eye).dot(forward).max(view_camera.znear);
+        let view_height = 2.0 * depth * (view_camera.fovy.to_radians() / 2.0).tan();
+        let view = GizmoView {
+            ray: self.cursor_ray(camera, cursor.0, cursor.1),
+            transform,
+            parent,
+            world,
+            world_per_pixel: view_height / viewport_height.max(1) as f32,
+        };
+        let changed = gizmo.update(&view, pressed);
+        if let Some(transform) = changed {
+            self.transforms.set(id.0, transform);
+        }
+        let view = GizmoView {
+            world: parent * self.transforms.local(id.0).to_matrix(),
+            ..view
+        };
+        gizmo.draw(&mut self.debug_draw, &view);
+        changed.is_some()
+    }
+
+    // Selection API
+    //======================
+    // The selected renderables, outlined by every camera, and exact picking with the ID buffer for
+    // choosing them, see selection.rs and id_buffer.rs.
+
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    pub fn selection_mut(&mut self) -> &mut Selection {
+        &mut self.selection
+    }
+
+    // `None` stops outlining the selection.
+    pub fn set_selection_outline(&mut self, settings: Option<OutlineSettings>) {
+        self.outline_settings = settings;
+    }
+
+    pub fn selection_outline(&self) -> Option<OutlineSettings> {
+        self.outline_settings
+    }
+
+    // The renderable the camera shows at the pixel (x, y) of the surface, exactly rather than by
+    // its bounds like `pick`. Waits for the GPU.
+    pub fn pick_exact(&mut self, camera: CameraId, x: f32, y: f32) -> Option<RenderableId> {
+        self.pick_rect(camera, (x, y), (x + 1.0, y + 1.0)).first().copied()
+    }
+
+    // The renderables the camera shows anywhere between the corners `min` and `max` of a rectangle
+    // of the surface, in pixels, e.g. for box selection. Waits for the GPU.
+    pub fn pick_rect(&mut self, camera: CameraId, min: (f32, f32), max: (f32, f32)) -> Vec<RenderableId> {
+        let (min, max) = match (self.window_to_virtual(min.0, min.1), self.window_to_virtual(max.0, max.1)) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Vec::new(),
+        };
+        let size = self.render_size();
+        let view = &self.cameras[camera.0];
+        let (vx, vy, w, h) = view.viewport.to_pixels(size.0, size.1);
+        // Only what is inside the viewport.
+        let left = (min.0.floor().max(vx as f32) as u32).min(vx + w);
+        let top = (min.1.floor().max(vy as f32) as u32).min(vy + h);
+        let right = (max.0.ceil() as u32).clamp(left, vx + w);
+        let bottom = (max.1.ceil() as u32).clamp(top, vy + h);
+        if right == left || bottom == top {
+            return Vec::new();
+        }
+        // As they were drawn in the last frame: renderables added since have no model matrix yet.
+        let layers = view.camera.layers;
+        let draws: Vec<IdDraw> = (0..self.renderables.len().min(self.instance_capacity))
+            .filter(|&i| self.renderables[i].layers.intersects(layers))
+            .map(|i| IdDraw {
+                instance: i as u32,
+                mesh: self.assets.mesh(&self.renderables[i].mesh),
+            })
+            .collect();
+        let readback = self.id_buffer.read(
+            self.context.device(),
+            self.context.queue(),
+            size,
+            view.camera.build_view_projection_matrix(),
+            (vx, vy, w, h),
+            (left, top, right - left, bottom - top),
+            &self.instance_buffer,
+            &draws,
+        );
+        match pollster::block_on(readback) {
+            Some(bytes) => IdBuffer::ids(&bytes).into_iter().map(RenderableId).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Applies the click or box the tool finished since the last frame to the selection, picking
+    // with `camera`, and draws the box being dragged. Returns true if the selection changed.
+    pub fn apply_selection(&mut self, tool: &mut SelectionTool, camera: CameraId) -> bool {
+        if let Some((min, max)) = tool.drag_box() {
+            // Just in front of the camera, where the lines cover the box on screen.
+            let corners: Vec<Point3<f32>> = [(min.0, min.1), (max.0, min.1), (max.0, max.1), (min.0, max.1)]
+                .iter()
+                .filter_map(|&(x, y)| self.cursor_ray(camera, x, y))
+                .map(|ray| ray.at(self.cameras[camera.0].camera.znear * 0.01))
+                .collect();
+            if corners.len() == 4 {
+                for i in 0..4 {
+                    self.debug_draw.line(corners[i], corners[(i + 1) % 4], [1.0, 1.0, 1.0, 0.8]);
+                }
+            }
+        }
+        let request = match tool.take_request() {
+            Some(request) => request,
+            None => return false,
+        };
+        let found = match request.area {
+            SelectionArea::Point { x, y } => self.pick_exact(camera, x, y).into_iter().collect(),
+            SelectionArea::Box { min, max } => self.pick_rect(camera, min, max),
+        };
+        self.selection.apply(&request, &found)
+    }
+
+    // Frame capture API
+    //======================
+    // Captures for graphics debuggers, taken from inside the game, see renderdoc.rs.
+
+    // Captures the frame being rendered with RenderDoc, if the game runs under it.
+    pub fn trigger_capture(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        match &self.renderdoc {
+            Some(renderdoc) => {
+                tracing::info!("Capturing the frame with RenderDoc");
+                renderdoc.trigger_capture();
+            }
+            None => tracing::warn!("Not capturing the frame, RenderDoc is not loaded"),
+        }
+        #[cfg(not(feature = "renderdoc"))]
+        tracing::warn!("Not capturing the frame, built without the renderdoc feature");
+    }
+
+    // The first wgpu error since the last call, a validation error or the lost device, see error.rs.
+    pub fn take_device_error(&self) -> Option<Error> {
+        self.context.take_device_error()
+    }
+
+    // The settings in effect after checking them against the adapter, see capabilities.rs.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    // The device this GFX renders with, to share with the GFX of another window, see gfx_context.rs.
+    pub fn context(&self) -> &Rc<GfxContext> {
+        &self.context
+    }
+
+    // Milliseconds the GPU spent per pass, a few frames ago. Empty without timestamp queries.
+    pub fn gpu_pass_times(&self) -> &[(&'static str, f32)] {
+        self.gpu_timer.as_ref().map_or(&[], |timer| timer.times())
+    }
+
+    // Draw calls and triangles submitted by the last `render`.
+    pub fn render_counters(&self) -> RenderCounters {
+        self.counters
+    }
+
+    // Updates the globals uniform, uploaded with the next `render`.
+    pub fn update_globals(&mut self, time: &Time) {
+        self.globals.update(time);
+        self.tilemaps.set_time(time.elapsed());
+        self.clouds.set_time(time.elapsed());
+    }
+
+    // Support window resizing
+    pub fn resize(&mut self, new_width: u32, new_height: u32) {
+        if new_width > 0 && new_height > 0 {
+            self.config.width = new_width;
+            self.config.height = new_height;
+            if let Some(surface) = &self.surface {
+                surface.configure(self.context.device(), &self.config);
+            }
+            // The virtual resolution doesn't change with the window.
+            if self.letterbox.is_none() {
+                self.recreate_targets();
+                self.fit_cameras();
+            }
+        }
+    }
+
+    // The render targets sized like the frame, for the current size and samples.
+    fn recreate_targets(&mut self) {
+        let size = self.scene_target_size();
+        self.msaa_view = create_msaa_view(self.context.device(), self.scene_format(), size, self.msaa_samples);
+        self.depth_view = create_depth_view(self.context.device(), size, self.msaa_samples);
+    }
+
+    // The size the scene is rendered at: the virtual resolution, or the window size without one.
+    pub fn render_size(&self) -> (u32, u32) {
+        match &self.letterbox {
+            Some(letterbox) => (letterbox.resolution().width, letterbox.resolution().height),
+            None => (self.config.width, self.config.height),
+        }
+    }
+
+    // The size of the scene's render targets: that of the frame, or smaller with a render scale,
+    // which renders into part of them, see render_scale.rs.
+    fn scene_target_size(&self) -> (u32, u32) {
+        let (width, height) = self.render_size();
+        match &self.render_scale {
+            Some(scale) => scale.allocated_size(width, height),
+            None => (width, height),
+        }
+    }
+
+    // Whether the text overlay is drawn into the frame after the render graph, instead of into the
+    // scene's target before it: with HDR, and with a render scale, where the scene is not the frame.
+    fn overlay_on_frame(&self) -> bool {
+        self.hdr || self.render_scale.is_some()
+    }
+
+    // Converts a window position, e.g. of the mouse, to a position in the rendered frame, see
+    // letterbox.rs. `None` on the bars around the virtual resolution.
+    pub fn window_to_virtual(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        match &self.letterbox {
+            Some(letterbox) => letterbox.resolution().window_to_virtual(self.config.width, self.config.height, x, y),
+            None => Some((x, y)),
+        }
+    }
+
+    // Settings API
+    //======================
+    // Changes to the settings chosen at startup, applied from the next frame on.
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    // `Fifo` is vsync. Modes the surface doesn't support fall back to `Fifo` in wgpu.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) {
+        self.config.present_mode = mode;
+        if let Some(surface) = &self.surface {
+            surface.configure(self.context.device(), &self.config);
+        }
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.set_present_mode(if vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            wgpu::PresentMode::Immediate
+        });
+    }
+
+    pub fn msaa_samples(&self) -> u32 {
+        self.msaa_samples
+    }
+
+    // 1 turns MSAA off; anything else but 4 is not portable and falls back to 4, see capabilities.rs.
+    // Recreates the pipelines drawn into the multisampled target.
+    pub fn set_msaa_samples(&mut self, samples: u32) {
+        let samples = if samples <= 1 { 1 } else { 4 };
+        if samples == self.msaa_samples {
+            return;
+        }
+        self.msaa_samples = samples;
+        self.recreate_targets();
+        let (device, scene_format) = (self.context.device(), self.scene_format());
+        // Otherwise the overlay is drawn into the multisampled scene target, see `render`.
+        if !self.overlay_on_frame() {
+            self.text = TextOverlay::new(device, self.context.queue(), self.config.format, samples);
+            self.panels.set_samples(device, self.config.format, samples);
+        }
+        self.debug_draw = DebugDraw::new(device, &self.camera_bind_group_layout, scene_format, samples);
+        self.clear_quad = ClearQuad::new(device, scene_format, samples);
+        self.tilemaps.set_samples(device, scene_format, samples);
+        self.lens_flares.set_samples(device, scene_format, samples);
+        self.outline.set_samples(samples);
+        self.grid.set_samples(device, scene_format, samples);
+        self.clouds.set_samples(device, scene_format, samples);
+        self.volumetrics.set_samples(device, &self.camera_bind_group_layout, scene_format, samples);
+        self.sky.set_samples(device, scene_format, samples);
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.set_samples(device, scene_format, samples);
+        }
+        self.gpu_particles.set_samples(device, scene_format, samples);
+        self.particle_sprites = ParticleSprites::new(device, &self.camera_bind_group_layout, scene_format, samples);
+    }
+
+    // The format the scene is rendered in: that of the frame, or a float format with HDR.
+    pub fn scene_format(&self) -> wgpu::TextureFormat {
+        if self.hdr {
+            HDR_FORMAT
+        } else {
+            self.config.format
+        }
+    }
+
+    // The fraction of the frame's width and height the scene is rendered at, see render_scale.rs.
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale.as_ref().map_or(1.0, RenderScale::scale)
+    }
+
+    // Only with a render scale in the `GfxOptions`.
+    pub fn set_render_scale(&mut self, settings: RenderScaleSettings) {
+        let reallocate = match &mut self.render_scale {
+            Some(scale) => scale.set_settings(settings),
+            None => {
+                tracing::warn!("The render scale can only be changed when it was set in the GfxOptions");
+                return;
+            }
+        };
+        if reallocate {
+            self.recreate_targets();
+        }
+    }
+
+    pub fn virtual_resolution(&self) -> Option<VirtualResolution> {
+        self.letterbox.as_ref().map(Letterbox::resolution)
+    }
+
+    // Renders at a fixed size scaled to the window from now on, see letterbox.rs, or at the
+    // window size again with `None`.
+    pub fn set_virtual_resolution(&mut self, resolution: Option<VirtualResolution>) {
+        if resolution == self.virtual_resolution() {
+            return;
+        }
+        if resolution.is_none() && self.surface.is_none() {
+            tracing::warn!("A headless GFX needs a virtual resolution to render into");
+            return;
+        }
+        self.letterbox = resolution.map(|resolution| Letterbox::new(self.context.device(), self.context.cache(), self.config.format, resolution));
+        self.recreate_targets();
+        self.fit_cameras();
+    }
+
+    // Brings the BVH up to date with the meshes and transforms of the renderables.
+    fn update_bvh(&mut self) {
+        self.transforms.update();
+        self.unbounded.clear();
+        for (index, renderable) in self.renderables.iter().enumerate() {
+            let bounds = self.assets.mesh(&renderable.mesh).bounds;
+            if bounds.is_none() {
+                self.unbounded.push(index);
+            }
+            self.bvh.set(index, bounds.map(|bounds| bounds.transformed(self.transforms.world(index))));
+        }
+        self.bvh.update();
+    }
+
+    // The renderables each camera draws, by index: only those it is looking for, and that are
+    // inside its frustum. Cameras are culled in parallel on the job system.
+    fn cull(&self) -> Vec<Vec<usize>> {
+        let layers: Vec<RenderLayers> = self.renderables.iter().map(|r| r.layers).collect();
+        let mut visible: Vec<Vec<usize>> = vec![Vec::new(); self.cameras.len()];
+        let cameras: Vec<(Frustum, RenderLayers)> = self
+            .cameras
+            .iter()
+            .map(|view| (Frustum::from_matrix(&view.camera.build_view_projection_matrix()), view.camera.layers))
+            .collect();
+        let (bvh, unbounded) = (&self.bvh, &self.unbounded);
+        jobs::global().parallel_for(&mut visible, |camera, list| {
+            let (frustum, camera_layers) = &cameras[camera];
+            bvh.query_frustum(frustum, |i| list.push(i));
+            list.extend(unbounded);
+            list.retain(|&i| camera_layers.intersects(layers[i]));
+            // Draw in the order the renderables were added, as without culling.
+            list.sort_unstable();
+        });
+        visible
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.update_streamed_textures();
+        self.update_bvh();
+
+        // Create or update the buffers, shader variants, bind groups and pipelines of the materials
+        // that are drawn, and select the pipeline of each renderable. `None` where that failed.
+        let mut keys = Vec::with_capacity(self.renderables.len());
+        let layouts = SceneLayouts {
+            camera: &self.camera_bind_group_layout,
+            probes: self.probes.layout(),
+        };
+        let scene_format = self.scene_format();
+        for renderable in &self.renderables {
+            let vertex = self.assets.mesh(&renderable.mesh).layout;
+            let prepared = self.materials.prepare(
+                self.context.device(),
+                self.context.queue(),
+                &layouts,
+                renderable.material,
+                scene_format,
+                self.msaa_samples,
+                vertex,
+            );
+            if let Err(e) = &prepared {
+                tracing::error!("Material {:?}: {}", renderable.material, e);
+            }
+            keys.push(prepared.ok());
+        }
+
+        let (width, height) = self.render_size();
+        self.text.prepare(self.context.device(), self.context.queue(), width, height);
+        self.panels.prepare(self.context.device(), self.context.queue(), width, height);
+        // The cameras may render smaller than the frame, see render_scale.rs.
+        if let Some(scale) = &mut self.render_scale {
+            scale.update(self.gpu_timer.as_ref().map_or(&[], |timer| timer.times()));
+        }
+        let (scene_width, scene_height) = match &self.render_scale {
+            Some(scale) => scale.scaled_size(width, height),
+            None => (width, height),
+        };
+
+        // Returns the next texture to be presented by the swapchain for drawing.
+        // Headless, there is nothing to present.
+        let output = match &self.surface {
+            Some(surface) => Some(surface.get_current_texture()?),
+            None => None,
+        };
+        let mut counters = RenderCounters::default();
+
+        // Creates a view of this texture.
+        let view = output
+            .as_ref()
+            .map(|output| output.texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        // Encodes a series of GPU operations.
+        let mut encoder = self
+            .context
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        // Don't overwrite the uniforms of a frame the GPU may still be drawing.
+        self.frames.wait_for_current(self.context.device());
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.begin_frame(self.context.device());
+        }
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.begin_frame(self.context.device());
+        }
+        let frame = self.frames.index();
+        let light = self.light();
+        self.globals.set_light(&light);
+        let slot = self.frames.current_mut();
+        slot.write_globals(self.context.device(), &mut encoder, bytemuck::cast_slice(&[self.globals]));
+
+        // Upload the model matrices that changed since the last frame, in runs of consecutive
+        // renderables; renderable `i` is drawn as instance `i`. A new buffer gets all of them.
+        let mut changed = self.transforms.take_changed();
+        if self.renderables.len() > self.instance_capacity {
+            self.instance_capacity = self.renderables.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(self.context.device(), self.instance_capacity);
+            changed = (0..self.renderables.len()).collect();
+        }
+        for run in changed.chunk_by(|a, b| b - a == 1) {
+            let instances: Vec<ModelInstance> = run.iter().map(|&i| ModelInstance::new(self.transforms.world(i))).collect();
+            let offset = (run[0] * std::mem::size_of::<ModelInstance>()) as wgpu::BufferAddress;
+            self.context.queue().write_buffer(&self.instance_buffer, offset, bytemuck::cast_slice(&instances));
+        }
+        counters.instance_uploads = changed.len() as u32;
+
+        // Upload the latest camera matrices.
+        for view in &mut self.cameras {
+            view.uniform.update_view_proj(&view.camera);
+            let buffer = &view.buffers[frame];
+            slot.write_buffer(self.context.device(), &mut encoder, buffer, bytemuck::cast_slice(&[view.uniform]));
+        }
+
+        // Pose the skinned meshes before anything draws them.
+        if self.skinning.has_work() {
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin_pass(&mut encoder, "skinning");
+            }
+            self.skinning.run(&mut encoder);
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end_pass(&mut encoder);
+            }
+        }
+
+        // Test what the cameras are about to draw against what covered their view last frame.
+        let visible = self.cull();
+        self.queue_debug_views(&visible);
+        self.debug_draw.prepare(self.context.device(), self.context.queue());
+        self.particle_sprites.prepare(self.context.device(), self.context.queue());
+        let tilemap_viewports: Vec<Option<(u32, u32)>> = self
+            .cameras
+            .iter()
+            .map(|view| {
+                let (_, _, w, h) = view.viewport.to_pixels(scene_width, scene_height);
+                view.camera.layers.intersects(RenderLayers::TILEMAPS).then_some((w, h))
+            })
+            .collect();
+        self.tilemaps.prepare(self.context.device(), self.context.queue(), &tilemap_viewports);
+        if let (Some(queries), Some(view)) = (&mut self.occlusion_queries, self.cameras.first()) {
+            // The renderables the first camera draws, which the scene pass below skips otherwise.
+            let drawn: Vec<RenderableId> = visible[0]
+                .iter()
+                .filter(|&&i| {
+                    keys[i].as_ref().is_some_and(|key| {
+                        let material = self.materials.get(self.renderables[i].material);
+                        self.materials.pipeline(key).is_some() && material.bind_group(key).is_some()
+                    })
+                })
+                .map(|&i| RenderableId(i))
+                .collect();
+            queries.prepare(self.context.queue(), view.camera.eye, &drawn);
+        }
+        if let Some(occlusion) = &mut self.occlusion {
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin_pass(&mut encoder, "occlusion");
+            }
+            for (index, list) in visible.iter().enumerate() {
+                let draws: Vec<OcclusionDraw> = list
+                    .iter()
+                    .map(|&i| OcclusionDraw {
+                        index_count: self.assets.mesh(&self.renderables[i].mesh).num_indices,
+                        instance: i as u32,
+                        bounds: self.bvh.bounds(i),
+                    })
+                    .collect();
+                occlusion.cull(self.context.device(), self.context.queue(), &mut encoder, index, &draws);
+            }
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end_pass(&mut encoder);
+            }
+        }
+
+        // Passes the scene depends on, e.g. shadow maps.
+        let mut frame_target = FrameTarget {
+            view: None,
+            format: self.config.format,
+            size: (width, height),
+        };
+        if self.graph.has_nodes(Stage::BeforeScene) {
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin_pass(&mut encoder, "pre");
+            }
+            self.graph.run(Stage::BeforeScene, self.context.device(), self.context.queue(), &mut encoder, frame_target);
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end_pass(&mut encoder);
+            }
+        }
+
+        // The shadow maps of the local lights, packed for the main camera, before anything samples
+        // them.
+        let eye = self.cameras[0].camera.eye;
+        self.local_lights.prepare(self.context.queue(), eye);
+        if !self.local_lights.atlas().views().is_empty() {
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin_pass(&mut encoder, "shadows");
+            }
+            self.render_shadows(&mut encoder, &mut counters);
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end_pass(&mut encoder);
+            }
+        }
+
+        // The probes that are due, before the scene samples them.
+        let probe_faces = self.probes.take_due_faces();
+        self.probes.prepare(self.context.queue());
+        if !probe_faces.is_empty() {
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin_pass(&mut encoder, "probes");
+            }
+            self.render_probes(&mut encoder, &probe_faces, &mut counters);
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end_pass(&mut encoder);
+            }
+        }
+        // And the mirrors, which may reflect surfaces sampling the probes.
+        if !self.mirrors.ids().is_empty() {
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin_pass(&mut encoder, "mirrors");
+            }
+            self.render_mirrors(&mut encoder, &mut counters);
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end_pass(&mut encoder);
+            }
+        }
+        // Then the texture cameras, whose textures the scene may show.
+        if !self.render_targets.ordered_ids().is_empty() {
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin_pass(&mut encoder, "render targets");
+            }
+            self.render_targets(&mut encoder, &mut counters);
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end_pass(&mut encoder);
+            }
+        }
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.begin_pass(&mut encoder, "scene");
+        }
+
+        // The scene goes into the surface texture, or the virtual frame scaled to it at the end.
+        let frame_view = match (&self.letterbox, &view) {
+            (Some(letterbox), _) => letterbox.view(),
+            (None, Some(view)) => view,
+            (None, None) => unreachable!("headless GFX without a virtual resolution"),
+        };
+        // With HDR, into `scene.hdr` first, which the graph tonemaps into the frame.
+        if self.hdr {
+            self.graph.resources.ensure_texture(self.context.device(), HDR_TARGET, width, height, HDR_FORMAT);
+        }
+        // With a render scale, into part of `scene.scaled` before that, scaled up after the cameras.
+        let scaled_size = self.scene_target_size();
+        if self.render_scale.is_some() {
+            let (w, h) = scaled_size;
+            self.graph.resources.ensure_texture(self.context.device(), SCALED_TARGET, w, h, self.scene_format());
+        }
+        let output_view = match self.hdr {
+            true => &self.graph.resources.texture(HDR_TARGET).unwrap().view,
+            false => frame_view,
+        };
+        let scene_view = match self.render_scale {
+            Some(_) => &self.graph.resources.texture(SCALED_TARGET).unwrap().view,
+            None => output_view,
+        };
+
+        // With MSAA, render into the multisampled texture and resolve it into the scene target.
+        let (target, resolve_target) = match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(scene_view)),
+            None => (scene_view, None),
+        };
+        let flare_cameras: Vec<FlareCamera> = self
+            .cameras
+            .iter()
+            .map(|view| FlareCamera {
+                view_proj: view.camera.build_view_projection_matrix(),
+                viewport: view.viewport.to_pixels(scene_width, scene_height),
+                layers: view.camera.layers,
+            })
+            .collect();
+        self.lens_flares.prepare(self.context.device(), self.context.queue(), &self.depth_view, &flare_cameras);
+        if let Some(settings) = &self.grid_settings {
+            let view_projs: Vec<Matrix4<f32>> = self.cameras.iter().map(|view| view.camera.build_view_projection_matrix()).collect();
+            self.grid.prepare(self.context.device(), self.context.queue(), settings, &view_projs);
+        }
+        if let Some(settings) = &self.sky_settings {
+            let view_projs: Vec<Matrix4<f32>> = self.cameras.iter().map(|view| view.camera.build_view_projection_matrix()).collect();
+            self.sky.prepare(self.context.device(), self.context.queue(), settings, &view_projs);
+        }
+        if let Some(mut settings) = self.cloud_settings {
+            // Lit by the same sun as the scene.
+            if self.sky_settings.is_some() {
+                settings.sun_direction = light.direction;
+                settings.sun_color = light.color;
+                settings.ambient_color = light.ambient;
+            }
+            let cloud_cameras: Vec<CloudCamera> = self
+                .cameras
+                .iter()
+                .map(|view| CloudCamera {
+                    view_proj: view.camera.build_view_projection_matrix(),
+                    eye: view.camera.eye,
+                    viewport: view.viewport.to_pixels(scene_width, scene_height),
+                })
+                .collect();
+            let (device, queue) = (self.context.device(), self.context.queue());
+            self.clouds.prepare(device, queue, &settings, &cloud_cameras, &self.depth_view, scaled_size);
+        }
+        if let Some(settings) = &self.volumetric_settings {
+            let volumetric_cameras: Vec<VolumetricCamera> = self
+                .cameras
+                .iter()
+                .map(|view| VolumetricCamera {
+                    view_proj: view.camera.build_view_projection_matrix(),
+                    eye: view.camera.eye,
+                    viewport: view.viewport.to_pixels(scene_width, scene_height),
+                })
+                .collect();
+            let (device, queue) = (self.context.device(), self.context.queue());
+            self.volumetrics.prepare(device, queue, settings, &volumetric_cameras, &self.depth_view, scaled_size);
+        }
+        let outline_settings = self.outline_settings.filter(|_| !self.selection.is_empty());
+        if let Some(settings) = outline_settings {
+            let viewports: Vec<(u32, u32)> = self
+                .cameras
+                .iter()
+                .map(|view| {
+                    let (_, _, w, h) = view.viewport.to_pixels(scene_width, scene_height);
+                    (w, h)
+                })
+                .collect();
+            let layouts: Vec<VertexLayout> = self.selection.ids().iter().map(|id| self.assets.mesh(&self.renderables[id.0].mesh).layout).collect();
+            let size = self.scene_target_size();
+            self.outline.prepare(self.context.device(), self.context.queue(), settings, &viewports, &layouts, size);
+        }
+
+        // The first pass clears the surface, the ones after it draw on top.
+        let mut load = self.clear_color.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear);
+        // Clamped, so a hitch doesn't throw the GPU particles through the scene.
+        let mut particles_dt = Some(self.globals.delta_time().min(0.1));
+
+        // Draw the scene once per camera, each into its own region of the surface.
+        for (index, view) in self.cameras.iter().enumerate() {
+            let (x, y, w, h) = view.viewport.to_pixels(scene_width, scene_height);
+            let (sx, sy, sw, sh) = view.scissor.unwrap_or(view.viewport).to_pixels(scene_width, scene_height);
+            // Viewports and scissor rects must not be empty.
+            if w == 0 || h == 0 || sw == 0 || sh == 0 {
+                continue;
+            }
+            // A camera covering the whole surface clears it when the pass begins, the others clear
+            // their region by drawing over it, see clear.rs.
+            let whole = (x, y, w, h) == (0, 0, scene_width, scene_height) && (sx, sy, sw, sh) == (0, 0, scene_width, scene_height);
+            let region_clear = match view.clear.color {
+                Some(color) if whole => {
+                    load = wgpu::LoadOp::Clear(color);
+                    None
+                }
+                color => color,
+            };
+
+            {
+                // Begins recording of a render pass.
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Scene Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: target,
+                        resolve_target, // same as view unless multisampling is used.
+                        // What operations will be performed on this color attachment.
+                        ops: wgpu::Operations { load, store: true },
+                    }],
+                    // By default every camera starts with empty depth, so cameras drawing over each
+                    // other's region don't hide each other. The depth is kept for occlusion culling.
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &self.depth_view,
+                        depth_ops: Some(view.clear.depth_ops()),
+                        stencil_ops: None,
+                    }),
+                });
+                load = wgpu::LoadOp::Load;
+
+                render_pass.push_debug_group(&format!("Camera {}", index));
+                render_pass.set_viewport(x as f32, y as f32, w as f32, h as f32, 0.0, 1.0);
+                render_pass.set_scissor_rect(sx, sy, sw, sh);
+                if let Some(color) = region_clear {
+                    self.clear_quad.draw(&mut render_pass, color);
+                }
+                let (draw_calls, triangles) = self.tilemaps.draw(&mut render_pass, index);
+                counters.draw_calls += draw_calls;
+                counters.triangles += triangles;
+                render_pass.set_bind_group(0, &view.bind_groups[frame], &[]);
+                render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+                for (draw, &i) in visible[index].iter().enumerate() {
+                    let (instance, renderable) = (i as u32, &self.renderables[i]);
+                    let mesh = self.assets.mesh(&renderable.mesh);
+                    let key = match &keys[i] {
+                        Some(key) => key,
+                        None => continue,
+                    };
+                    let material = self.materials.get(renderable.material);
+                    let (pipeline, bind_group) = match (self.materials.pipeline(key), material.bind_group(key)) {
+                        (Some(pipeline), Some(bind_group)) => (pipeline, bind_group),
+                        _ => continue,
+                    };
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.set_bind_group(1, bind_group, &[]);
+                    if self.materials.uses_probes(key) {
+                        render_pass.set_bind_group(2, self.probes.bind_group(), &[]);
+                    }
+
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                    let queried = index == 0
+                        && self
+                            .occlusion_queries
+                            .as_ref()
+                            .is_some_and(|queries| queries.begin_renderable(&mut render_pass, RenderableId(i)));
+                    match &self.occlusion {
+                        // The same draw, skipped by the GPU if it is hidden.
+                        Some(occlusion) => occlusion.draw(&mut render_pass, index, draw),
+                        // Draw all indices with 1 instance, the one holding the renderable's model matrix.
+                        // Used in [[builtin(vertex_index)]] in the shader source.
+                        None => render_pass.draw_indexed(0..mesh.num_indices, 0, instance..instance + 1),
+                    }
+                    if queried {
+                        render_pass.end_pipeline_statistics_query();
+                    }
+                    counters.draw_calls += 1;
+                    counters.triangles += mesh.num_indices / 3;
+                }
+                // Hidden by the renderables only, see occlusion_queries.rs.
+                if let (0, Some(queries)) = (index, &self.occlusion_queries) {
+                    queries.draw_boxes(&mut render_pass);
+                }
+                // Behind the renderables, and under the grid.
+                if self.sky_settings.is_some() && view.camera.layers.intersects(RenderLayers::DEFAULT) {
+                    self.sky.draw(&mut render_pass, index);
+                }
+                // Over the sky, tested against the renderables.
+                if view.camera.layers.intersects(RenderLayers::DEFAULT) {
+                    self.gpu_particles.draw(&mut render_pass, &view.bind_groups[frame]);
+                    self.particle_sprites.draw(&mut render_pass, &view.bind_groups[frame]);
+                }
+                if view.camera.layers.intersects(RenderLayers::GIZMOS) {
+                    if self.grid_settings.is_some() {
+                        self.grid.draw(&mut render_pass, index);
+                    }
+                    self.debug_draw.draw(&mut render_pass);
+                }
+                render_pass.pop_debug_group();
+            }
+            if let (0, Some(queries)) = (index, &mut self.occlusion_queries) {
+                queries.end_pass();
+            }
+            // On the depth the first camera just drew, see gpu_particles.rs.
+            if let (0, Some(dt)) = (index, particles_dt.take()) {
+                let depth = DepthCamera {
+                    view: &self.depth_view,
+                    samples: self.msaa_samples,
+                    view_proj: view.camera.build_view_projection_matrix(),
+                    viewport: (x, y, w, h),
+                    collide: true,
+                };
+                let (device, queue) = (self.context.device(), self.context.queue());
+                self.gpu_particles.update(device, queue, &mut encoder, dt, &depth);
+            }
+            // Over the camera's part of the scene, before the outline and flares drawn on top of it.
+            if self.cloud_settings.is_some() {
+                self.clouds.draw(&mut encoder, target, resolve_target, index, (x, y, w, h), (sx, sy, sw, sh));
+            }
+            if self.volumetric_settings.is_some() {
+                let camera = &view.bind_groups[frame];
+                self.volumetrics.draw(&mut encoder, target, resolve_target, index, camera, (x, y, w, h), (sx, sy, sw, sh));
+            }
+            if outline_settings.is_some() {
+                let outlined: Vec<(u32, &Mesh)> = self
+                    .selection
+                    .ids()
+                    .iter()
+                    .filter(|id| self.renderables[id.0].layers.intersects(view.camera.layers))
+                    .map(|id| (id.0 as u32, self.assets.mesh(&self.renderables[id.0].mesh)))
+                    .collect();
+                let (camera, instances) = (&view.bind_groups[frame], &self.instance_buffer);
+                self.outline.draw(&mut encoder, target, resolve_target, index, (x, y, w, h), (sx, sy, sw, sh), camera, instances, &outlined);
+            }
+            // Over the camera's part of the scene, tested against its depth.
+            self.lens_flares.draw(&mut encoder, target, resolve_target, index, (x, y, w, h), (sx, sy, sw, sh));
+
+            if let Some(occlusion) = &mut self.occlusion {
+                occlusion.build_pyramid(
+                    self.context.device(),
+                    self.context.queue(),
+                    &mut encoder,
+                    index,
+                    &self.depth_view,
+                    self.msaa_samples,
+                    (x, y, w, h),
+                    view.camera.build_view_projection_matrix(),
+                );
+            }
+        }
+
+        // Without the first camera's depth, e.g. with an empty viewport, they move without colliding.
+        if let Some(dt) = particles_dt {
+            let depth = DepthCamera {
+                view: &self.depth_view,
+                samples: self.msaa_samples,
+                view_proj: Matrix4::identity(),
+                viewport: (0, 0, 1, 1),
+                collide: false,
+            };
+            let (device, queue) = (self.context.device(), self.context.queue());
+            self.gpu_particles.update(device, queue, &mut encoder, dt, &depth);
+        }
+
+        if let Some(scale) = &self.render_scale {
+            let (device, queue) = (self.context.device(), self.context.queue());
+            scale.draw(device, queue, &mut encoder, scene_view, (scene_width, scene_height), scaled_size, output_view);
+        }
+
+        // UI panels and text go on top of everything, across the whole surface. With HDR or a
+        // render scale, on the frame after the post effects instead.
+        if !self.overlay_on_frame() {
+            self.draw_overlay(&mut encoder, target, resolve_target, load);
+        }
+        self.counters = counters;
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.end_pass(&mut encoder);
+            timer.begin_pass(&mut encoder, "post");
+        }
+        frame_target.view = Some(frame_view);
+        self.graph.run(Stage::AfterScene, self.context.device(), self.context.queue(), &mut encoder, frame_target);
+        if self.overlay_on_frame() {
+            self.draw_overlay(&mut encoder, frame_view, None, wgpu::LoadOp::Load);
+        }
+        if let (Some(letterbox), Some(view)) = (&self.letterbox, &view) {
+            letterbox.draw(&mut encoder, view, self.config.width, self.config.height);
+        }
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.end_pass(&mut encoder);
+            timer.end_frame(&mut encoder);
+        }
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.end_frame(&mut encoder);
+        }
+        self.frames.finish_encoding();
+
+        // submit will accept anything that implements IntoIter
+        self.context.queue().submit(std::iter::once(encoder.finish()));
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.after_submit();
+        }
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.after_submit();
+        }
+        self.frames.end_frame(self.context.queue());
+        if let Some(output) = output {
+            output.present();
+        }
+        self.collect_garbage();
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &mut self.renderdoc {
+            for path in renderdoc.new_captures() {
+                tracing::info!("RenderDoc capture written to {}", path.display());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl GFX {
+    fn draw_overlay(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Overlay Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations { load, store: true },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.push_debug_group("Text Overlay");
+        self.panels.draw(&mut render_pass);
+        self.text.draw(&mut render_pass);
+        render_pass.pop_debug_group();
+    }
+}
+
+fn create_msaa_view(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    (width, height): (u32, u32),
+    samples: u32,
+) -> Option<wgpu::TextureView> {
+    if samples <= 1 {
+        return None;
+    }
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Color Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: samples,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+// Also for passes with depth buffers of their own, like the render graph nodes of the demos.
+pub fn create_depth_view(device: &wgpu::Device, (width, height): (u32, u32), samples: u32) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: samples,
+        dimension: wgpu::TextureDimension::D2,
+        format: Texture::DEPTH_FORMAT,
+        // Read by the occlusion culling to build the depth pyramids.
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Model Instance Buffer"),
+        size: (capacity * std::mem::size_of::<ModelInstance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
 }
 
 // Utility function to return a slice of bytes from `arbitrary` slice.
 // !! Probably should use the Bytemuck crate: `bytemuck::cast_slice(SLICE)`
 // Instead of rolling my own here.
-pub fn _as_bytes<'a, T: ?Sized>(content: &'a T) -> &'a [u8] {
+pub fn _as_bytes<T: ?Sized>(content: &T) -> &[u8] {
     let new_len = core::mem::size_of_val(content) / std::mem::size_of::<u8>();
     unsafe { core::slice::from_raw_parts(content as *const T as *const u8, new_len) }
 }
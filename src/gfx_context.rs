@@ -0,0 +1,178 @@
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use raw_window_handle::HasRawWindowHandle;
+
+use crate::capabilities::Capabilities;
+use crate::crash;
+use crate::device_cache::DeviceCache;
+use crate::error::{Error, Result};
+use crate::gfx::GfxOptions;
+
+// Graphics context
+//======================
+// The wgpu instance, the adapter, and the device with its queue, which every `GFX` renders with.
+// Each GFX holds only what belongs to its window: the surface and its configuration, the render
+// targets, cameras and renderables. A GFX creates its own context, unless it is given the context
+// of another one in `GfxOptions::shared_context`:
+//
+//     let main = GFX::new(&window, 800, 600, &options).await?;
+//     let options = GfxOptions { shared_context: Some(main.context().clone()), ..options };
+//     let tools = GFX::new(&tool_window, 400, 600, &options).await?;
+//
+// Windows that share a context share the GPU memory too: buffers and textures created on one
+// device can be bound by every GFX on it, and a secondary window doesn't pay for a device of its
+// own. The device caches (device_cache.rs) and the validation errors are shared as well, so an
+// error is reported by whichever GFX takes it first.
+//
+// The adapter is picked for the first window. The others have to be able to present from it,
+// which is the case for windows on the same machine with the usual drivers.
+
+pub struct GfxContext {
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    device: Arc<wgpu::Device>, // Shared with pending readbacks, which poll it.
+    queue: wgpu::Queue,
+    cache: Rc<DeviceCache>, // Shares identical layouts and samplers, see device_cache.rs.
+    capabilities: Capabilities, // Of the settings the context was created with.
+    device_error: Arc<Mutex<Option<Error>>>, // The first one since the last `take_device_error`.
+}
+
+impl GfxContext {
+    // Creates the device, for rendering to `window` or headless without one. The adapter has to
+    // be able to present to the window, so the window's surface is created here too.
+    pub async fn new(
+        window: Option<&dyn HasRawWindowHandle>,
+        options: &GfxOptions,
+    ) -> Result<(Rc<GfxContext>, Option<wgpu::Surface>)> {
+        // Instance of wgpu. Its primary use is to create `Adapter`s and `Surface`s.
+        let instance = wgpu::Instance::new(options.backends);
+
+        // A `Surface` represents a platform-specific surface (e.g. a window)
+        // onto which rendered images may be presented.
+        // It's the part of the window that we draw to.
+        // Created from raw window handle.
+        let surface = window.map(|window| unsafe { instance.create_surface(&window) });
+
+        // Handle to a physical graphics and/or compute device.
+        // Adapters can be used to open a connection to the corresponding `Device`
+        //on the host system
+        let chosen_adapter = options.adapter.and_then(|index| {
+            let adapter = instance
+                .enumerate_adapters(options.backends)
+                .filter(|adapter| surface.as_ref().is_none_or(|surface| adapter.is_surface_supported(surface)))
+                .nth(index);
+            if adapter.is_none() {
+                tracing::warn!("Adapter {} not found, using the default adapter", index);
+            }
+            adapter
+        });
+        let adapter = if let Some(adapter) = chosen_adapter {
+            adapter
+        } else {
+            let options = wgpu::RequestAdapterOptions {
+                power_preference: options.power_preference,
+                compatible_surface: surface.as_ref(),
+                force_fallback_adapter: false,
+            };
+
+            // Retrieves an `Adapter` which matches the given `RequestAdapterOptions`.
+            // If wgpu can't find an adapter with the required permissions,
+            // request_adapter will return None
+            instance.request_adapter(&options).await.ok_or(Error::NoAdapter)?
+        };
+
+        let info = adapter.get_info();
+        tracing::info!("Using adapter {} ({:?})", info.name, info.backend);
+        crash::set_adapter(&info);
+
+        // What of the settings the adapter supports, see capabilities.rs.
+        let capabilities = Capabilities::new(&adapter, options);
+
+        // Open connection to a graphics and/or compute device
+        // and get handle to a command queue on a device.
+        let (device, queue) = {
+            let desc = wgpu::DeviceDescriptor {
+                // Whatever any GFX on the device may use, e.g. timestamps for the pass times in
+                // the metrics, see gpu_timer.rs.
+                features: Capabilities::device_features(&adapter),
+                limits: capabilities.limits.clone(),
+                label: Some("Main Device"),
+            };
+
+            if let Some(path) = &options.trace_path {
+                if let Err(e) = std::fs::create_dir_all(path) {
+                    tracing::warn!("Could not create trace directory {}: {}", path.display(), e);
+                }
+            }
+
+            // Requests a connection to a physical device, creating a logical device.
+            // Returns the Device together with a Queue that executes command buffers.
+            adapter.request_device(&desc, options.trace_path.as_deref()).await?
+        };
+        let device = Arc::new(device);
+
+        // By default wgpu panics on validation errors. Keep the first one instead, for `App` to
+        // report with the frame it happened in.
+        let device_error = Arc::new(Mutex::new(None));
+        let first_error = device_error.clone();
+        device.on_uncaptured_error(move |e| {
+            tracing::error!("{}", e);
+            first_error.lock().unwrap().get_or_insert_with(|| Error::from_wgpu(&e));
+        });
+
+        let context = GfxContext {
+            instance,
+            adapter,
+            device,
+            queue,
+            cache: Rc::new(DeviceCache::new()),
+            capabilities,
+            device_error,
+        };
+        Ok((Rc::new(context), surface))
+    }
+
+    // A surface for another window, to render into with this context's device.
+    pub fn create_surface(&self, window: &dyn HasRawWindowHandle) -> Result<wgpu::Surface> {
+        let surface = unsafe { self.instance.create_surface(&window) };
+        if !self.adapter.is_surface_supported(&surface) {
+            return Err(Error::NoAdapter);
+        }
+        Ok(surface)
+    }
+
+    pub fn adapter(&self) -> &wgpu::Adapter {
+        &self.adapter
+    }
+
+    pub fn device(&self) -> &Arc<wgpu::Device> {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn cache(&self) -> &Rc<DeviceCache> {
+        &self.cache
+    }
+
+    // The settings the context was created with, after checking them against the adapter.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    // The first wgpu error since the last call, a validation error or the lost device, see error.rs.
+    pub fn take_device_error(&self) -> Option<Error> {
+        self.device_error.lock().unwrap().take()
+    }
+}
+
+// For `GfxOptions`, which holds the context to share.
+impl fmt::Debug for GfxContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GfxContext").field("adapter", &self.adapter.get_info().name).finish()
+    }
+}
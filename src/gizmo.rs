@@ -0,0 +1,439 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation3, SquareMatrix, Transform as _, Vector3};
+
+use crate::bounds::Ray;
+use crate::debug_draw::DebugDraw;
+use crate::game::{Event, MouseButton};
+use crate::gfx::{RenderableId, GFX};
+use crate::selection::SelectionTool;
+use crate::transform::Transform;
+
+// Gizmos
+//======================
+// The handles editors put on the selected object: three arrows to move it along an axis, three
+// circles to rotate it around one, or three boxes to scale it along one. The handle under the
+// cursor lights up, and dragging it changes the object's transform:
+//
+//     // In `Game::render`, with the cursor position and button tracked from `on_event`:
+//     let mut gizmo = Gizmo::new(GizmoMode::Translate);
+//     gfx.manipulate(&mut gizmo, camera, selected, cursor, left_pressed);
+//
+// Gizmos are drawn with the debug lines (debug_draw.rs), on top of the scene, by the cameras that
+// see `RenderLayers::GIZMOS`. They keep the same size on screen however far away the object is:
+// `size` is in pixels, converted to world units at the object's distance every frame.
+//
+// Moving and rotating follow the axes of the world (of the parent, for renderables with one),
+// scaling those of the object itself, since the scale is applied before the rotation.
+
+// Within this many pixels of a handle, the cursor is over it.
+const HOVER_PIXELS: f32 = 8.0;
+// Of the length of the handles: arrow heads, scale boxes.
+const HEAD_SIZE: f32 = 0.12;
+// Scale factors below this would flip or collapse the object.
+const MIN_SCALE_FACTOR: f32 = 0.01;
+
+const AXIS_COLORS: [[f32; 4]; 3] = [[0.9, 0.2, 0.2, 1.0], [0.3, 0.85, 0.3, 1.0], [0.25, 0.45, 1.0, 1.0]];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+
+/// What dragging a gizmo's handles does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// One of the three handles of a gizmo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl GizmoAxis {
+    const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn unit(self) -> Vector3<f32> {
+        match self {
+            GizmoAxis::X => Vector3::unit_x(),
+            GizmoAxis::Y => Vector3::unit_y(),
+            GizmoAxis::Z => Vector3::unit_z(),
+        }
+    }
+}
+
+/// Where the gizmo is this frame and how the camera sees it, see `GFX::manipulate`.
+pub struct GizmoView {
+    pub ray: Option<Ray>, // Through the cursor, `None` if it is outside the camera's viewport.
+    // The object's transform, the world matrix of its parent (identity without one) and of itself.
+    pub transform: Transform,
+    pub parent: Matrix4<f32>,
+    pub world: Matrix4<f32>,
+    pub world_per_pixel: f32, // The size of a pixel at the object's distance from the camera.
+}
+
+impl GizmoView {
+    fn center(&self) -> Point3<f32> {
+        self.world.transform_point(Point3::new(0.0, 0.0, 0.0))
+    }
+
+    // The direction of the handle in world space.
+    fn direction(&self, mode: GizmoMode, axis: GizmoAxis) -> Vector3<f32> {
+        let direction = match mode {
+            GizmoMode::Scale => self.world.transform_vector(axis.unit()),
+            GizmoMode::Translate | GizmoMode::Rotate => self.parent.transform_vector(axis.unit()),
+        };
+        if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            axis.unit()
+        }
+    }
+}
+
+// The state at the start of a drag.
+#[derive(Clone, Copy, Debug)]
+struct Drag {
+    axis: GizmoAxis,
+    start: Transform,
+    center: Point3<f32>, // Of the gizmo at the start, which stays put while the object moves.
+    // Where the cursor grabbed the handle: the distance from the center along the axis for
+    // translation and scale, the direction from the center in the plane of the circle for rotation.
+    grab: Vector3<f32>,
+}
+
+/// A translate, rotate or scale gizmo, and which of its handles is hovered or dragged.
+#[derive(Clone, Debug)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub size: f32, // The length of the handles, in pixels.
+    hovered: Option<GizmoAxis>,
+    drag: Option<Drag>,
+    was_pressed: bool,
+}
+
+impl Gizmo {
+    pub fn new(mode: GizmoMode) -> Gizmo {
+        Gizmo {
+            mode,
+            size: 100.0,
+            hovered: None,
+            drag: None,
+            was_pressed: false,
+        }
+    }
+
+    pub fn with_size(mut self, size: f32) -> Gizmo {
+        self.size = size;
+        self
+    }
+
+    // The handle under the cursor, or the one dragged.
+    pub fn hovered(&self) -> Option<GizmoAxis> {
+        self.drag.map(|drag| drag.axis).or(self.hovered)
+    }
+
+    // Whether a handle is being dragged, when the game shouldn't also move the camera or select
+    // another object.
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // Follows the cursor for a frame: hovers a handle, starts dragging it when the button goes
+    // down, and returns the new transform while it is dragged.
+    pub fn update(&mut self, view: &GizmoView, pressed: bool) -> Option<Transform> {
+        let just_pressed = pressed && !self.was_pressed;
+        self.was_pressed = pressed;
+        if !pressed {
+            self.drag = None;
+        }
+        let ray = view.ray.as_ref()?;
+        if let Some(drag) = self.drag {
+            return self.dragged(view, ray, drag);
+        }
+        self.hovered = self.hover(view, ray);
+        if let (true, Some(axis)) = (just_pressed, self.hovered) {
+            let center = view.center();
+            let grab = self.grab(view, ray, axis, center)?;
+            self.drag = Some(Drag {
+                axis,
+                start: view.transform,
+                center,
+                grab,
+            });
+        }
+        None
+    }
+
+    // Queues the handles, the hovered one highlighted.
+    pub fn draw(&self, debug_draw: &mut DebugDraw, view: &GizmoView) {
+        let center = view.center();
+        let length = self.size * view.world_per_pixel;
+        for axis in GizmoAxis::ALL {
+            let color = if self.hovered() == Some(axis) { HIGHLIGHT_COLOR } else { AXIS_COLORS[axis.index()] };
+            let direction = view.direction(self.mode, axis);
+            let tip = center + direction * length;
+            match self.mode {
+                GizmoMode::Translate => {
+                    debug_draw.line(center, tip, color);
+                    // A cone of four lines back from the tip.
+                    let (u, v) = perpendiculars(direction);
+                    let base = tip - direction * length * HEAD_SIZE * 2.0;
+                    for side in [u, -u, v, -v] {
+                        debug_draw.line(tip, base + side * length * HEAD_SIZE * 0.6, color);
+                    }
+                }
+                GizmoMode::Rotate => debug_draw.circle(center, direction, length, color),
+                GizmoMode::Scale => {
+                    debug_draw.line(center, tip, color);
+                    let half = length * HEAD_SIZE * 0.5;
+                    let rotation = Matrix4::from_cols(
+                        view.direction(self.mode, GizmoAxis::X).extend(0.0),
+                        view.direction(self.mode, GizmoAxis::Y).extend(0.0),
+                        view.direction(self.mode, GizmoAxis::Z).extend(0.0),
+                        tip.to_homogeneous(),
+                    );
+                    debug_draw.oriented_box(&rotation, Vector3::new(half, half, half), color);
+                }
+            }
+        }
+    }
+
+    // The handle nearest to the ray, if it is close enough on screen.
+    fn hover(&self, view: &GizmoView, ray: &Ray) -> Option<GizmoAxis> {
+        let center = view.center();
+        let length = self.size * view.world_per_pixel;
+        let mut best: Option<(GizmoAxis, f32)> = None;
+        for axis in GizmoAxis::ALL {
+            let direction = view.direction(self.mode, axis);
+            let distance = match self.mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    // From the ray to the nearest point of the handle.
+                    let along = closest_on_axis(ray, center, direction).unwrap_or(0.0).clamp(0.0, length);
+                    distance_to_ray(ray, center + direction * along)
+                }
+                GizmoMode::Rotate => match intersect_plane(ray, center, direction) {
+                    Some(point) => ((point - center).magnitude() - length).abs(),
+                    None => continue,
+                },
+            };
+            let pixels = distance / view.world_per_pixel.max(f32::EPSILON);
+            if pixels < HOVER_PIXELS && best.is_none_or(|(_, best)| pixels < best) {
+                best = Some((axis, pixels));
+            }
+        }
+        best.map(|(axis, _)| axis)
+    }
+
+    fn grab(&self, view: &GizmoView, ray: &Ray, axis: GizmoAxis, center: Point3<f32>) -> Option<Vector3<f32>> {
+        let direction = view.direction(self.mode, axis);
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                closest_on_axis(ray, center, direction).map(|along| Vector3::new(along, 0.0, 0.0))
+            }
+            GizmoMode::Rotate => intersect_plane(ray, center, direction).map(|point| point - center),
+        }
+    }
+
+    fn dragged(&self, view: &GizmoView, ray: &Ray, drag: Drag) -> Option<Transform> {
+        let grab = self.grab(view, ray, drag.axis, drag.center)?;
+        let direction = view.direction(self.mode, drag.axis);
+        let parent_inverse = view.parent.invert().unwrap_or_else(Matrix4::identity);
+        let mut transform = drag.start;
+        match self.mode {
+            GizmoMode::Translate => {
+                let moved = direction * (grab.x - drag.grab.x);
+                transform.translation = drag.start.translation + parent_inverse.transform_vector(moved);
+            }
+            GizmoMode::Rotate => {
+                let (from, to) = (drag.grab, grab);
+                if from.magnitude2() == 0.0 || to.magnitude2() == 0.0 {
+                    return None;
+                }
+                let angle = from.cross(to).dot(direction).atan2(from.dot(to));
+                // The world axis in the parent's space, where the rotation is.
+                let axis = parent_inverse.transform_vector(direction).normalize();
+                transform.rotation = Quaternion::from_axis_angle(axis, Rad(angle)) * drag.start.rotation;
+            }
+            GizmoMode::Scale => {
+                if drag.grab.x.abs() < f32::EPSILON {
+                    return None;
+                }
+                let factor = (grab.x / drag.grab.x).max(MIN_SCALE_FACTOR);
+                let i = drag.axis.index();
+                transform.scale[i] = drag.start.scale[i] * factor;
+            }
+        }
+        Some(transform)
+    }
+}
+
+// The distance from `origin` along the line through it in `direction` to the point nearest to
+// the ray. `None` if the ray is parallel to it.
+fn closest_on_axis(ray: &Ray, origin: Point3<f32>, direction: Vector3<f32>) -> Option<f32> {
+    let w = ray.origin - origin;
+    let b = ray.direction.dot(direction);
+    let denominator = 1.0 - b * b;
+    if denominator < 1e-6 {
+        return None;
+    }
+    Some((direction.dot(w) - b * ray.direction.dot(w)) / denominator)
+}
+
+fn distance_to_ray(ray: &Ray, point: Point3<f32>) -> f32 {
+    let along = (point - ray.origin).dot(ray.direction).max(0.0);
+    (ray.at(along) - point).magnitude()
+}
+
+// Where the ray hits the plane through `origin` with `normal`, in front of its origin.
+fn intersect_plane(ray: &Ray, origin: Point3<f32>, normal: Vector3<f32>) -> Option<Point3<f32>> {
+    let denominator = ray.direction.dot(normal);
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+    let distance = (origin - ray.origin).dot(normal) / denominator;
+    (distance >= 0.0).then(|| ray.at(distance))
+}
+
+// Two directions perpendicular to `direction` and to each other.
+fn perpendiculars(direction: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let other = if direction.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let u = direction.cross(other).normalize();
+    (u, direction.cross(u))
+}
+
+// Gizmo demo
+//======================
+
+// A gizmo on the selected renderable, dragged with the left mouse button. W, E and R switch
+// between moving, rotating and scaling it. Clicking elsewhere selects what is under the cursor,
+// exactly with the ID buffer, Ctrl+click adds to the selection and dragging a box selects what is
+// in it (selection.rs); the gizmo is on the renderable selected last.
+pub struct GizmoDemo {
+    gizmo: Gizmo,
+    tool: SelectionTool,
+    cursor: (f32, f32), // In pixels of the window.
+    pressed: bool,
+}
+
+impl GizmoDemo {
+    // Starts with `selected` selected.
+    pub fn new(gfx: &mut GFX, selected: RenderableId) -> GizmoDemo {
+        gfx.selection_mut().set(&[selected]);
+        GizmoDemo {
+            gizmo: Gizmo::new(GizmoMode::Translate).with_size(80.0),
+            tool: SelectionTool::new(),
+            cursor: (0.0, 0.0),
+            pressed: false,
+        }
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        self.tool.on_event(event);
+        match *event {
+            Event::MouseMoved { x, y } => self.cursor = (x as f32, y as f32),
+            Event::MousePressed(MouseButton::Left) => self.pressed = true,
+            Event::MouseReleased(MouseButton::Left) | Event::FocusLost => self.pressed = false,
+            Event::KeyPressed(key) if key == b'W' as u16 => self.gizmo.mode = GizmoMode::Translate,
+            Event::KeyPressed(key) if key == b'E' as u16 => self.gizmo.mode = GizmoMode::Rotate,
+            Event::KeyPressed(key) if key == b'R' as u16 => self.gizmo.mode = GizmoMode::Scale,
+            _ => {}
+        }
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        let camera = gfx.main_camera();
+        if let Some(selected) = gfx.selection().primary() {
+            gfx.manipulate(&mut self.gizmo, camera, selected, self.cursor, self.pressed);
+        }
+        // A press that grabbed a handle doesn't also select.
+        if self.gizmo.is_dragging() {
+            self.tool.cancel();
+        }
+        gfx.apply_selection(&mut self.tool, camera);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An object at the origin seen from +z, with handles 1 unit long.
+    fn view() -> GizmoView {
+        GizmoView {
+            ray: None,
+            transform: Transform::default(),
+            parent: Matrix4::identity(),
+            world: Matrix4::identity(),
+            world_per_pixel: 0.01,
+        }
+    }
+
+    // Straight down the z axis, through (`x`, `y`).
+    fn ray_through(x: f32, y: f32) -> Ray {
+        Ray::new(Point3::new(x, y, 5.0), -Vector3::unit_z())
+    }
+
+    #[test]
+    fn the_ray_hovers_the_nearest_handle_within_reach() {
+        let gizmo = Gizmo::new(GizmoMode::Translate);
+        let view = view();
+        assert_eq!(gizmo.hover(&view, &ray_through(0.5, 0.02)), Some(GizmoAxis::X));
+        assert_eq!(gizmo.hover(&view, &ray_through(-0.05, 0.7)), Some(GizmoAxis::Y));
+        // Near the center every handle is close, the nearest wins.
+        assert_eq!(gizmo.hover(&view, &ray_through(0.03, 0.01)), Some(GizmoAxis::X));
+        // More than 8 pixels away, between the handles and past their ends.
+        assert_eq!(gizmo.hover(&view, &ray_through(0.5, 0.5)), None);
+        assert_eq!(gizmo.hover(&view, &ray_through(1.2, 0.0)), None);
+        assert_eq!(gizmo.hover(&view, &ray_through(0.5, 0.09)), None);
+        // The handle pointing at the camera is only hit at the center.
+        let from_the_side = Ray::new(Point3::new(5.0, 0.05, 0.5), -Vector3::unit_x());
+        assert_eq!(gizmo.hover(&view, &from_the_side), Some(GizmoAxis::Z));
+    }
+
+    #[test]
+    fn rotation_handles_are_hit_on_their_circles() {
+        let gizmo = Gizmo::new(GizmoMode::Rotate);
+        let view = view();
+        assert_eq!(gizmo.hover(&view, &ray_through(0.6, 0.8)), Some(GizmoAxis::Z));
+        assert_eq!(gizmo.hover(&view, &ray_through(0.3, 0.4)), None);
+    }
+
+    #[test]
+    fn handles_keep_their_size_on_screen() {
+        let gizmo = Gizmo::new(GizmoMode::Translate);
+        // Twice as far, pixels cover twice as much, and so do the handles and the reach.
+        let far = GizmoView {
+            world_per_pixel: 0.02,
+            ..view()
+        };
+        assert_eq!(gizmo.hover(&far, &ray_through(1.8, 0.1)), Some(GizmoAxis::X));
+        assert_eq!(gizmo.hover(&view(), &ray_through(1.8, 0.1)), None);
+    }
+
+    #[test]
+    fn dragging_a_handle_moves_along_its_axis() {
+        let mut gizmo = Gizmo::new(GizmoMode::Translate);
+        let at = |x: f32, y: f32| GizmoView {
+            ray: Some(ray_through(x, y)),
+            ..view()
+        };
+        assert_eq!(gizmo.update(&at(0.5, 0.02), false), None);
+        assert_eq!(gizmo.hovered(), Some(GizmoAxis::X));
+        // Grabbed, then dragged off the handle: only the motion along x counts.
+        assert_eq!(gizmo.update(&at(0.5, 0.02), true), None);
+        assert!(gizmo.is_dragging());
+        let moved = gizmo.update(&at(0.8, 0.4), true).expect("dragging");
+        assert!((moved.translation - Vector3::new(0.3, 0.0, 0.0)).magnitude() < 1e-5);
+        gizmo.update(&at(0.8, 0.4), false);
+        assert!(!gizmo.is_dragging());
+        // Holding the button down elsewhere first doesn't grab a handle the cursor passes over.
+        gizmo.update(&at(0.5, 0.5), true);
+        assert_eq!(gizmo.update(&at(0.5, 0.02), true), None);
+        assert!(!gizmo.is_dragging());
+    }
+}
@@ -0,0 +1,337 @@
+use std::path::Path;
+
+use cgmath::Point3;
+
+use crate::assets::LoadError;
+use crate::camera::{Camera, Rect};
+use crate::clear::ClearSettings;
+use crate::error::{Context, Error, Result};
+use crate::gfx::{GfxOptions, GFX};
+use crate::layers::RenderLayers;
+
+// Golden image tests
+//======================
+// Renders reference scenes without a window, reads the frames back and compares them with the
+// PNGs stored in a directory, so shader and pipeline refactors that change what ends up on screen
+// don't go unnoticed:
+//
+//     learn-wgpu --golden tests/golden                   # compare, exits with 1 on differences
+//     learn-wgpu --golden tests/golden --update-golden   # (re)write the reference images
+//
+// GPUs and drivers round differently, so images are compared with a tolerance: a pixel only
+// counts as different when the perceived difference of its colors (YIQ weighted, like pixelmatch
+// does it) exceeds `PIXEL_THRESHOLD`, and a scene only fails when more than `MAX_DIFFERENT` of its
+// pixels do. For a failing scene the frame is written next to the reference as
+// `<name>.actual.png`, and `<name>.diff.png` shows the different pixels in red over a faded copy
+// of the reference.
+//
+// Every scene renders with a GFX of its own and the default graphics settings, so scenes don't
+// depend on each other or on config.toml. New scenes go into `SCENES`.
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+// Of the largest possible difference, see `color_delta`.
+const PIXEL_THRESHOLD: f32 = 0.1;
+// Fraction of the pixels.
+const MAX_DIFFERENT: f32 = 0.001;
+
+/// A scene rendered by the golden image tests, set up through the GFX API.
+struct GoldenScene {
+    name: &'static str,
+    setup: fn(&mut GFX),
+}
+
+const SCENES: &[GoldenScene] = &[
+    GoldenScene {
+        name: "cube",
+        setup: cube,
+    },
+    GoldenScene {
+        name: "cube_msaa",
+        setup: cube_msaa,
+    },
+    GoldenScene {
+        name: "split_screen",
+        setup: split_screen,
+    },
+    GoldenScene {
+        name: "text",
+        setup: text,
+    },
+    GoldenScene {
+        name: "debug_draw",
+        setup: debug_draw,
+    },
+];
+
+fn cube(gfx: &mut GFX) {
+    let (mesh, material) = (gfx.unit_cube(), gfx.default_material());
+    gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+    let main = gfx.main_camera();
+    gfx.camera_mut(main).eye = Point3::new(1.5, 1.2, 2.5);
+}
+
+fn cube_msaa(gfx: &mut GFX) {
+    cube(gfx);
+    gfx.set_msaa_samples(4);
+}
+
+fn split_screen(gfx: &mut GFX) {
+    cube(gfx);
+    let main = gfx.main_camera();
+    gfx.set_viewport(main, Rect::new(0.0, 0.0, 0.5, 1.0));
+    let mut camera = Camera::new(1.0);
+    camera.eye = Point3::new(-2.0, 0.5, 1.5);
+    let right = gfx.add_camera(camera);
+    gfx.set_viewport(right, Rect::new(0.5, 0.0, 0.5, 1.0));
+    gfx.set_camera_clear(right, ClearSettings::color(wgpu::Color::BLACK));
+}
+
+fn text(gfx: &mut GFX) {
+    gfx.draw_text(8.0, 8.0, 2.0, [1.0, 1.0, 1.0, 1.0], "GOLDEN 0123\nabc xyz !?");
+    gfx.draw_text(8.0, 120.0, 4.0, [1.0, 0.8, 0.2, 0.5], "ALPHA");
+}
+
+fn debug_draw(gfx: &mut GFX) {
+    let main = gfx.main_camera();
+    gfx.camera_mut(main).eye = Point3::new(2.0, 2.0, 3.0);
+    let lines = gfx.debug_draw();
+    lines.aabb(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5), [0.0, 1.0, 0.0, 1.0]);
+    lines.sphere(Point3::new(0.0, 0.0, 0.0), 0.75, [1.0, 0.0, 1.0, 1.0]);
+    lines.line(Point3::new(-1.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), [1.0, 0.0, 0.0, 1.0]);
+}
+
+/// The outcome of a golden image run.
+#[derive(Debug, Default)]
+pub struct GoldenReport {
+    pub passed: Vec<&'static str>,
+    pub failed: Vec<(&'static str, String)>, // With what differed.
+    pub updated: Vec<&'static str>,
+}
+
+// Renders every scene and compares it with its reference in `dir`, or writes the references with
+// `update`. `options` picks the backend and the adapter, the other settings are the defaults.
+pub fn run(dir: &Path, update: bool, options: &GfxOptions) -> Result<GoldenReport> {
+    std::fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+    let options = GfxOptions {
+        backends: options.backends,
+        adapter: options.adapter,
+        power_preference: options.power_preference,
+        ..GfxOptions::default()
+    };
+    let mut report = GoldenReport::default();
+    for scene in SCENES {
+        let frame = render(scene, &options).with_context(|| format!("cannot render golden scene '{}'", scene.name))?;
+        let reference_path = dir.join(format!("{}.png", scene.name));
+        let actual_path = dir.join(format!("{}.actual.png", scene.name));
+        let diff_path = dir.join(format!("{}.diff.png", scene.name));
+        if update {
+            save(&frame, &reference_path)?;
+            report.updated.push(scene.name);
+            continue;
+        }
+
+        let reference = match image::open(&reference_path) {
+            Ok(reference) => reference.to_rgba8(),
+            Err(e) => {
+                save(&frame, &actual_path)?;
+                let message = format!("no reference {} ({}), run with --update-golden", reference_path.display(), e);
+                report.failed.push((scene.name, message));
+                continue;
+            }
+        };
+        match check(&reference, &frame) {
+            Ok(()) => {
+                // Leftovers of an earlier failure would be confusing.
+                let _ = std::fs::remove_file(&actual_path);
+                let _ = std::fs::remove_file(&diff_path);
+                report.passed.push(scene.name);
+            }
+            Err(Mismatch::Size { rendered, reference }) => {
+                save(&frame, &actual_path)?;
+                let message = format!("rendered {:?}, reference is {:?}", rendered, reference);
+                report.failed.push((scene.name, message));
+            }
+            Err(Mismatch::Pixels { different, allowed, diff }) => {
+                save(&frame, &actual_path)?;
+                save(&diff, &diff_path)?;
+                let message = format!("{} pixels differ, {} allowed, see {}", different, allowed, diff_path.display());
+                report.failed.push((scene.name, message));
+            }
+        }
+    }
+    Ok(report)
+}
+
+// Why a frame doesn't match its reference.
+#[derive(Debug)]
+enum Mismatch {
+    Size { rendered: (u32, u32), reference: (u32, u32) },
+    Pixels { different: usize, allowed: usize, diff: image::RgbaImage },
+}
+
+// Whether `actual` matches `reference` within the tolerances, see above.
+fn check(reference: &image::RgbaImage, actual: &image::RgbaImage) -> std::result::Result<(), Mismatch> {
+    if reference.dimensions() != actual.dimensions() {
+        return Err(Mismatch::Size {
+            rendered: actual.dimensions(),
+            reference: reference.dimensions(),
+        });
+    }
+    let (different, diff) = compare(reference, actual);
+    let allowed = (MAX_DIFFERENT * (actual.width() * actual.height()) as f32) as usize;
+    if different > allowed {
+        return Err(Mismatch::Pixels { different, allowed, diff });
+    }
+    Ok(())
+}
+
+// Renders a frame of `scene` and reads it back.
+fn render(scene: &GoldenScene, options: &GfxOptions) -> Result<image::RgbaImage> {
+    let mut gfx = pollster::block_on(GFX::headless(WIDTH, HEIGHT, options))?;
+    (scene.setup)(&mut gfx);
+    gfx.render()?;
+    if let Some(e) = gfx.take_device_error() {
+        return Err(e);
+    }
+    let readback = gfx.read_frame().expect("headless GFX renders at a virtual resolution");
+    let bytes = pollster::block_on(readback).ok_or_else(|| Error::Validation("cannot read back the frame".into()))?;
+    // Headless frames are Rgba8UnormSrgb, already in the byte order and color space of PNGs.
+    debug_assert_eq!(gfx.frame_format(), wgpu::TextureFormat::Rgba8UnormSrgb);
+    Ok(image::RgbaImage::from_raw(WIDTH, HEIGHT, bytes).expect("frame has WIDTH x HEIGHT texels"))
+}
+
+fn save(image: &image::RgbaImage, path: &Path) -> Result<()> {
+    image
+        .save(path)
+        .map_err(LoadError::from)
+        .with_context(|| format!("cannot write {}", path.display()))
+}
+
+// The number of pixels that differ perceptibly, and an image showing them, see above.
+fn compare(reference: &image::RgbaImage, actual: &image::RgbaImage) -> (usize, image::RgbaImage) {
+    let mut diff = image::RgbaImage::new(reference.width(), reference.height());
+    let mut different = 0;
+    for ((a, b), out) in reference.pixels().zip(actual.pixels()).zip(diff.pixels_mut()) {
+        if color_delta(a.0, b.0) > PIXEL_THRESHOLD {
+            different += 1;
+            *out = image::Rgba([255, 0, 0, 255]);
+        } else {
+            let (y, _, _) = yiq(a.0);
+            let faded = (255.0 - (255.0 - y) * 0.1) as u8;
+            *out = image::Rgba([faded, faded, faded, 255]);
+        }
+    }
+    (different, diff)
+}
+
+// Brightness and chrominance, in the 0 to 255 range of the color channels.
+fn yiq([r, g, b, _]: [u8; 4]) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    (
+        r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_23,
+        r * 0.595_977_99 - g * 0.274_176_1 - b * 0.321_801_9,
+        r * 0.211_470_17 - g * 0.522_617_1 + b * 0.311_146_94,
+    )
+}
+
+// The perceived difference of two colors, from 0 (the same) to 1 (red against cyan; black against
+// white is 0.93). Brightness weighs most, as the eye is more sensitive to it than to hue.
+// Frames are opaque, alpha is ignored.
+fn color_delta(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let ((ya, ia, qa), (yb, ib, qb)) = (yiq(a), yiq(b));
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+    // The largest possible sum, as in pixelmatch.
+    const MAX_DELTA: f32 = 35215.0;
+    (0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / MAX_DELTA
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    fn image(width: u32, height: u32, color: [u8; 4]) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(width, height, image::Rgba(color))
+    }
+
+    // Renders the scenes on the GPU and compares them with the references in tests/golden, the
+    // same as `--golden tests/golden`. Ignored as it needs an adapter: run it with
+    // `cargo test -- --ignored` after writing the references with `--update-golden`.
+    #[test]
+    #[ignore = "needs a GPU adapter and the reference images"]
+    fn scenes_match_the_references() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        let report = run(&dir, false, &GfxOptions::default()).unwrap();
+        assert!(report.failed.is_empty(), "{:#?}", report.failed);
+        assert_eq!(report.passed.len(), SCENES.len());
+    }
+
+    #[test]
+    fn color_delta_ranges_from_the_same_to_the_most_different() {
+        assert_eq!(color_delta(WHITE, WHITE), 0.0);
+        assert_eq!(color_delta([10, 200, 30, 255], [10, 200, 30, 0]), 0.0); // Alpha is ignored.
+        assert!((color_delta(BLACK, WHITE) - 0.933).abs() < 1e-3);
+        let most = color_delta([255, 0, 0, 255], [0, 255, 255, 255]); // Red against cyan.
+        assert!((most - 1.0).abs() < 1e-3, "{}", most);
+        assert_eq!(color_delta(BLACK, WHITE), color_delta(WHITE, BLACK));
+        // Brightness weighs more than hue: gray levels a step apart are close, red and green far.
+        assert!(color_delta([100, 100, 100, 255], [102, 102, 102, 255]) < 0.001);
+        assert!(color_delta([255, 0, 0, 255], [0, 255, 0, 255]) > PIXEL_THRESHOLD);
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let reference = image(16, 8, [40, 80, 120, 255]);
+        assert!(check(&reference, &reference.clone()).is_ok());
+        let (different, _) = compare(&reference, &reference);
+        assert_eq!(different, 0);
+    }
+
+    #[test]
+    fn differences_within_the_threshold_match() {
+        let reference = image(16, 8, [40, 80, 120, 255]);
+        // Every pixel a little off, as drivers round differently.
+        let actual = image(16, 8, [42, 79, 121, 255]);
+        assert!(check(&reference, &actual).is_ok());
+    }
+
+    #[test]
+    fn a_few_different_pixels_are_allowed() {
+        // 0.1% of 100 x 100 pixels.
+        let reference = image(100, 100, BLACK);
+        let mut actual = reference.clone();
+        for x in 0..10 {
+            actual.put_pixel(x, 0, image::Rgba(WHITE));
+        }
+        assert!(check(&reference, &actual).is_ok());
+        actual.put_pixel(10, 0, image::Rgba(WHITE));
+        match check(&reference, &actual) {
+            Err(Mismatch::Pixels { different, allowed, .. }) => assert_eq!((different, allowed), (11, 10)),
+            other => panic!("expected different pixels, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        match check(&image(16, 8, BLACK), &image(8, 16, BLACK)) {
+            Err(Mismatch::Size { rendered, reference }) => assert_eq!((rendered, reference), ((8, 16), (16, 8))),
+            other => panic!("expected a size mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_diff_marks_different_pixels_in_red_over_the_faded_reference() {
+        let reference = image(2, 1, BLACK);
+        let mut actual = reference.clone();
+        actual.put_pixel(1, 0, image::Rgba(WHITE));
+        let (different, diff) = compare(&reference, &actual);
+        assert_eq!(different, 1);
+        assert_eq!(diff.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        // Black fades to a light gray, so the red stands out.
+        assert_eq!(diff.get_pixel(0, 0).0, [229, 229, 229, 255]);
+    }
+}
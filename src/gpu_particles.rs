@@ -0,0 +1,476 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::compute::ComputeKernel;
+use crate::compute_kernels::create_uniform_buffer;
+use crate::texture::Texture;
+use crate::uniform::UniformLayout;
+
+// GPU particles
+//======================
+// Emitters whose particles live and move on the GPU, for the tens of thousands of sparks or rain
+// drops the CPU particles (particles.rs) can't afford. The game can't read them back, but they
+// bounce off the scene without any CPU physics, by colliding with the depth buffer:
+//
+//     let sparks = gfx.add_gpu_particles(GpuParticleSettings::sparks().with_position(position));
+//     ...
+//     gfx.gpu_particle_settings_mut(sparks).position = welder.tip();
+//
+// Every frame a kernel (gpu_particles.wgsl) spawns the particles of the frame, moves the living
+// ones with gravity and drag, then projects them into the first camera: a particle that went
+// behind the depth buffer, but no further than `thickness` behind it, hit the surface there. The
+// kernel rebuilds the world position of the surface from its depth and its normal from the
+// depth of the texels next to it, puts the particle back on it and reflects its velocity, keeping
+// `restitution` of it along the normal and losing `friction` of it along the surface.
+//
+// It only knows what the first camera sees: particles off screen or behind something nearer fly
+// through. The kernel runs right after the first camera's pass, on the depth it just drew, so
+// that camera draws the particles as they were moved last frame and the other cameras as they
+// were moved this frame.
+//
+// Particles are drawn as quads facing each camera that draws the default layer, after the scene
+// and the sky, added onto it and tested against the depth without writing it. Their color fades
+// from `start_color` to `end_color` over their life, premultiplied by its alpha.
+//
+// The particles are a ring of `max_particles`: the spawns of a frame replace the oldest ones, so
+// a rate too high for the lifetime cuts particles short instead of dropping spawns.
+
+const WORKGROUP_SIZE: u32 = 64;
+// `Particle` in gpu_particles.wgsl, a position and a velocity.
+const PARTICLE_SIZE: u64 = 32;
+
+/// How particles bounce off the depth buffer, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthCollision {
+    pub restitution: f32, // The fraction of the speed into the surface it bounces back with.
+    pub friction: f32, // The fraction of the speed along the surface lost per bounce.
+    pub thickness: f32, // How far behind the depth buffer, in world units, is still a hit.
+}
+
+impl Default for DepthCollision {
+    fn default() -> Self {
+        DepthCollision {
+            restitution: 0.4,
+            friction: 0.2,
+            thickness: 0.5,
+        }
+    }
+}
+
+/// See above. Distances and speeds are in world units, angles in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpuParticleSettings {
+    pub max_particles: u32, // Fixed once the emitter is added.
+    pub rate: f32, // Particles per second.
+    pub position: Vector3<f32>,
+    pub direction: Vector3<f32>,
+    pub spread: f32, // Half the angle of the cone around `direction` the particles start in.
+    pub speed: f32,
+    pub speed_variation: f32, // The fraction of `speed` the speed of a particle is off by, at most.
+    pub lifetime: f32, // In seconds, off by a quarter at most.
+    pub gravity: Vector3<f32>,
+    pub drag: f32, // The fraction of the velocity lost per second.
+    pub size: f32, // The width of the quads.
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub collision: Option<DepthCollision>, // `None` flies through the scene.
+    pub seed: u32,
+}
+
+impl GpuParticleSettings {
+    // Fast, small and falling in every direction, bouncing off the floor.
+    pub fn sparks() -> GpuParticleSettings {
+        GpuParticleSettings {
+            max_particles: 20000,
+            rate: 4000.0,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+            spread: 60.0,
+            speed: 5.0,
+            speed_variation: 0.5,
+            lifetime: 1.5,
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            drag: 0.1,
+            size: 0.03,
+            start_color: [1.0, 0.8, 0.3, 1.0],
+            end_color: [1.0, 0.3, 0.0, 0.0],
+            collision: Some(DepthCollision::default()),
+            seed: 0,
+        }
+    }
+
+    // Falling in a narrow cone from high above, barely bouncing where it lands. Raise the
+    // emitter to cover more ground.
+    pub fn rain() -> GpuParticleSettings {
+        GpuParticleSettings {
+            max_particles: 50000,
+            rate: 25000.0,
+            position: Vector3::new(0.0, 15.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            spread: 30.0,
+            speed: 10.0,
+            speed_variation: 0.2,
+            lifetime: 2.0,
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            drag: 0.0,
+            size: 0.02,
+            start_color: [0.6, 0.7, 0.8, 0.6],
+            end_color: [0.6, 0.7, 0.8, 0.3],
+            collision: Some(DepthCollision {
+                restitution: 0.1,
+                friction: 0.8,
+                thickness: 0.5,
+            }),
+            seed: 0,
+        }
+    }
+
+    pub fn with_position(mut self, position: Vector3<f32>) -> GpuParticleSettings {
+        self.position = position;
+        self
+    }
+
+    pub fn with_collision(mut self, collision: Option<DepthCollision>) -> GpuParticleSettings {
+        self.collision = collision;
+        self
+    }
+}
+
+/// Identifies an emitter added with `GFX::add_gpu_particles`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GpuParticlesId(usize);
+
+/// The depth buffer the particles collide with, and the camera that drew it.
+pub struct DepthCamera<'a> {
+    pub view: &'a wgpu::TextureView,
+    pub samples: u32,
+    pub view_proj: Matrix4<f32>,
+    pub viewport: (u32, u32, u32, u32), // x, y, width and height in texels of `view`.
+    pub collide: bool, // False if the camera didn't draw this frame.
+}
+
+uniform_struct! {
+    struct SimulationParams {
+        view_proj: Matrix4<f32>,
+        inverse_view_proj: Matrix4<f32>,
+        viewport: Vector4<f32>,
+        position: Vector4<f32>,
+        direction: Vector4<f32>,
+        gravity: Vector4<f32>,
+        dt: f32,
+        lifetime: f32,
+        speed_variation: f32,
+        restitution: f32,
+        friction: f32,
+        thickness: f32,
+        collide: u32,
+        spawn_start: u32,
+        spawn_count: u32,
+        seed: u32,
+    }
+}
+assert_uniform_size!(SimulationParams, 240);
+
+uniform_struct! {
+    struct DrawParams {
+        start_color: Vector4<f32>,
+        end_color: Vector4<f32>,
+        size: f32,
+    }
+}
+assert_uniform_size!(DrawParams, 48);
+
+struct Emitter {
+    settings: GpuParticleSettings,
+    particles: wgpu::Buffer,
+    count: u32, // Of `particles`, the `max_particles` it was made for.
+    simulation_params: wgpu::Buffer,
+    draw_params: wgpu::Buffer,
+    draw_group: wgpu::BindGroup,
+    spawn_start: u32,
+    pending: f32, // Particles due to spawn, carried over to the next frame.
+    frame: u32,
+}
+
+/// The GPU particle emitters, see above.
+pub struct GpuParticles {
+    emitters: Vec<Option<Emitter>>, // By id; `None` where removed.
+    kernel: Option<(u32, ComputeKernel)>, // For the sample count of the depth buffer.
+    draw_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+}
+
+impl GpuParticles {
+    // `camera_layout` is that of the camera bind groups, which the particles are drawn with.
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, samples: u32) -> GpuParticles {
+        let draw_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GPU Particles Draw Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("GPU Particles Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_particles_draw.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU Particles Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &draw_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, &shader, format, samples);
+        GpuParticles {
+            emitters: Vec::new(),
+            kernel: None,
+            draw_layout,
+            pipeline,
+            pipeline_layout,
+            shader,
+        }
+    }
+
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, format, samples);
+    }
+
+    pub fn add(&mut self, device: &wgpu::Device, settings: GpuParticleSettings) -> GpuParticlesId {
+        let count = settings.max_particles.max(1);
+        // Zeroed, so every particle starts dead with a lifetime of 0.
+        let particles = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Particles"),
+            size: count as u64 * PARTICLE_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let simulation_params = create_uniform_buffer(device, "GPU Particles Simulation Params", &simulation_params(&settings, 0.0, None));
+        let draw_params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Particles Draw Params"),
+            contents: &draw_params(&settings).to_uniform_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let draw_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GPU Particles Draw Bind Group"),
+            layout: &self.draw_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: draw_params.as_entire_binding(),
+            }],
+        });
+        let emitter = Emitter {
+            settings,
+            particles,
+            count,
+            simulation_params,
+            draw_params,
+            draw_group,
+            spawn_start: 0,
+            pending: 0.0,
+            frame: 0,
+        };
+        let index = match self.emitters.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.emitters.push(None);
+                self.emitters.len() - 1
+            }
+        };
+        self.emitters[index] = Some(emitter);
+        GpuParticlesId(index)
+    }
+
+    pub fn settings(&self, id: GpuParticlesId) -> &GpuParticleSettings {
+        &self.emitters[id.0].as_ref().expect("GPU particles were removed").settings
+    }
+
+    // Read every frame, so changes take effect on the next one, except for `max_particles`.
+    pub fn settings_mut(&mut self, id: GpuParticlesId) -> &mut GpuParticleSettings {
+        &mut self.emitters[id.0].as_mut().expect("GPU particles were removed").settings
+    }
+
+    pub fn remove(&mut self, id: GpuParticlesId) {
+        self.emitters[id.0] = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emitters.iter().all(Option::is_none)
+    }
+
+    // Spawns, moves and collides the particles of every emitter by `dt` seconds. Call after the
+    // first camera's pass, with the depth it drew.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, dt: f32, depth: &DepthCamera) {
+        if self.is_empty() {
+            return;
+        }
+        if self.kernel.as_ref().map(|(samples, _)| *samples) != Some(depth.samples) {
+            // The same shader reads multisampled depth and plain depth, which has one sample.
+            let wgsl = include_str!("gpu_particles.wgsl");
+            let wgsl = if depth.samples > 1 {
+                wgsl.to_string()
+            } else {
+                wgsl.replace("texture_depth_multisampled_2d", "texture_depth_2d")
+                    .replace("textureNumSamples(depth)", "1")
+                    .replace("textureLoad(depth, texel, i)", "textureLoad(depth, texel, 0)")
+            };
+            let kernel = ComputeKernel::new(device, "GPU Particles", &wgsl, "main").expect("built-in kernel is valid");
+            self.kernel = Some((depth.samples, kernel));
+        }
+        let kernel = &self.kernel.as_ref().unwrap().1;
+
+        for emitter in self.emitters.iter_mut().flatten() {
+            let settings = &emitter.settings;
+            emitter.pending += settings.rate.max(0.0) * dt;
+            let spawn_count = (emitter.pending.floor() as u32).min(emitter.count);
+            emitter.pending -= emitter.pending.floor();
+
+            let mut params = simulation_params(settings, dt, depth.collide.then_some(depth));
+            params.spawn_start = emitter.spawn_start;
+            params.spawn_count = spawn_count;
+            // A different draw of random numbers for the particles spawned every frame.
+            params.seed = settings.seed.wrapping_add(emitter.frame.wrapping_mul(0x9e37_79b9));
+            queue.write_buffer(&emitter.simulation_params, 0, &params.to_uniform_bytes());
+            queue.write_buffer(&emitter.draw_params, 0, &draw_params(settings).to_uniform_bytes());
+            emitter.spawn_start = (emitter.spawn_start + spawn_count) % emitter.count;
+            emitter.frame = emitter.frame.wrapping_add(1);
+
+            // The depth view changes with the surface size, so this bind group is made every frame.
+            let group = kernel
+                .bind_group(
+                    device,
+                    0,
+                    &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: emitter.simulation_params.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: emitter.particles.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(depth.view),
+                        },
+                    ],
+                )
+                .expect("GPU particle resources match the kernel");
+            kernel.dispatch_2d(encoder, &[&group], (WORKGROUP_SIZE, 1), emitter.count, 1);
+        }
+    }
+
+    // Draws every emitter into a camera's pass, after its scene.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a wgpu::BindGroup) {
+        if self.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        for emitter in self.emitters.iter().flatten() {
+            render_pass.set_bind_group(1, &emitter.draw_group, &[]);
+            render_pass.set_vertex_buffer(0, emitter.particles.slice(..));
+            render_pass.draw(0..6, 0..emitter.count);
+        }
+    }
+}
+
+// Without a camera, or without collision, the particles don't collide.
+fn simulation_params(settings: &GpuParticleSettings, dt: f32, depth: Option<&DepthCamera>) -> SimulationParams {
+    let collision = settings.collision.filter(|_| depth.is_some()).unwrap_or_default();
+    let view_proj = depth.map_or(Matrix4::identity(), |depth| depth.view_proj);
+    let (x, y, w, h) = depth.map_or((0, 0, 1, 1), |depth| depth.viewport);
+    let direction = match settings.direction.magnitude2() > 0.0 {
+        true => settings.direction.normalize(),
+        false => Vector3::unit_y(),
+    };
+    SimulationParams {
+        view_proj,
+        inverse_view_proj: view_proj.invert().unwrap_or(Matrix4::identity()),
+        viewport: Vector4::new(x as f32, y as f32, w as f32, h as f32),
+        position: settings.position.extend(settings.spread.clamp(0.0, 180.0).to_radians().cos()),
+        direction: direction.extend(settings.speed),
+        gravity: settings.gravity.extend(settings.drag),
+        dt,
+        lifetime: settings.lifetime,
+        speed_variation: settings.speed_variation,
+        restitution: collision.restitution,
+        friction: collision.friction,
+        thickness: collision.thickness,
+        collide: (depth.is_some() && settings.collision.is_some()) as u32,
+        spawn_start: 0,
+        spawn_count: 0,
+        seed: settings.seed,
+    }
+}
+
+fn draw_params(settings: &GpuParticleSettings) -> DrawParams {
+    DrawParams {
+        start_color: settings.start_color.into(),
+        end_color: settings.end_color.into(),
+        size: settings.size,
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("GPU Particles Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: PARTICLE_SIZE,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                // Light adds up, and leaves the alpha of the scene alone.
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Hidden by the scene without hiding each other, so they need no sorting.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,150 @@
+// Spawns, moves and collides the particles of a GPU emitter, see gpu_particles.rs.
+
+struct Particle {
+    position: vec4<f32>; // w is the age, in seconds.
+    velocity: vec4<f32>; // w is the lifetime, 0 before the particle was first spawned.
+};
+
+struct Particles {
+    particles: array<Particle>;
+};
+
+struct Params {
+    // Of the first camera last frame, whose depth is in `depth`.
+    view_proj: mat4x4<f32>;
+    inverse_view_proj: mat4x4<f32>;
+    viewport: vec4<f32>; // x, y, width and height in texels of `depth`.
+    position: vec4<f32>; // Of the emitter; w is the cosine of the spread.
+    direction: vec4<f32>; // Unit; w is the speed.
+    gravity: vec4<f32>; // w is the drag.
+    dt: f32;
+    lifetime: f32;
+    speed_variation: f32; // Fraction of the speed.
+    restitution: f32;
+    friction: f32;
+    thickness: f32; // In world units, behind the depth buffer.
+    collide: u32; // 0 without collision, or without last frame's depth.
+    spawn_start: u32; // The particles respawned this frame, in the ring of all of them.
+    spawn_count: u32;
+    seed: u32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var<storage, read_write> particles: Particles;
+[[group(0), binding(2)]]
+var depth: texture_depth_multisampled_2d;
+
+fn hash(x: u32) -> u32 {
+    var h = x;
+    h = (h ^ 61u) ^ (h >> 16u);
+    h = h * 9u;
+    h = h ^ (h >> 4u);
+    h = h * 0x27d4eb2du;
+    h = h ^ (h >> 15u);
+    return h;
+}
+
+// Uniform in [0, 1), a different one for every `stream` of the same particle.
+fn random(index: u32, stream: u32) -> f32 {
+    return f32(hash(index * 4u + stream + params.seed) & 0xffffffu) / 16777216.0;
+}
+
+// A direction within the spread cone around the emitter's direction.
+fn spawn_direction(index: u32) -> vec3<f32> {
+    let z = mix(1.0, params.position.w, random(index, 0u));
+    let angle = random(index, 1u) * 6.2831853;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    let local = vec3<f32>(r * cos(angle), r * sin(angle), z);
+    // A basis around the direction.
+    let d = params.direction.xyz;
+    let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(d.y) > 0.99);
+    let t = normalize(cross(helper, d));
+    let b = cross(d, t);
+    return t * local.x + b * local.y + d * local.z;
+}
+
+// The nearest sample of the depth buffer at a texel of the viewport.
+fn load_depth(texel: vec2<i32>) -> f32 {
+    let samples = i32(textureNumSamples(depth));
+    var nearest = 1.0;
+    for (var i = 0; i < samples; i = i + 1) {
+        nearest = min(nearest, textureLoad(depth, texel, i));
+    }
+    return nearest;
+}
+
+// The world position at `uv` of the viewport and a depth.
+fn unproject(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = params.inverse_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// The world position of the depth buffer at a texel, clamped to the viewport.
+fn surface_position(texel: vec2<i32>) -> vec3<f32> {
+    let min_texel = vec2<i32>(params.viewport.xy);
+    let texel = clamp(texel, min_texel, min_texel + vec2<i32>(params.viewport.zw) - vec2<i32>(1));
+    let uv = (vec2<f32>(texel) + 0.5 - params.viewport.xy) / params.viewport.zw;
+    return unproject(uv, load_depth(texel));
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let count = arrayLength(&particles.particles);
+    let index = id.x;
+    if (index >= count) {
+        return;
+    }
+    var particle = particles.particles[index];
+
+    // Respawn the particles of this frame's part of the ring.
+    if ((index + count - params.spawn_start) % count < params.spawn_count) {
+        let speed = params.direction.w * (1.0 + params.speed_variation * (random(index, 2u) * 2.0 - 1.0));
+        particle.position = vec4<f32>(params.position.xyz, 0.0);
+        particle.velocity = vec4<f32>(spawn_direction(index) * speed, params.lifetime * mix(0.75, 1.25, random(index, 3u)));
+        particles.particles[index] = particle;
+        return;
+    }
+    if (particle.position.w >= particle.velocity.w) {
+        return;
+    }
+
+    var velocity = particle.velocity.xyz + params.gravity.xyz * params.dt;
+    velocity = velocity * max(1.0 - params.gravity.w * params.dt, 0.0);
+    var position = particle.position.xyz + velocity * params.dt;
+
+    if (params.collide != 0u) {
+        let clip = params.view_proj * vec4<f32>(position, 1.0);
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        let on_screen = clip.w > 0.0 && all(uv >= vec2<f32>(0.0)) && all(uv < vec2<f32>(1.0));
+        if (on_screen) {
+            let texel = vec2<i32>(params.viewport.xy + uv * params.viewport.zw);
+            let surface_depth = load_depth(texel);
+            // Behind the surface, but not so far behind it that it is passing behind an object.
+            if (ndc.z > surface_depth && surface_depth < 1.0) {
+                let surface = surface_position(texel);
+                // The normal from the neighboring texels, turned towards the camera.
+                let right = surface_position(texel + vec2<i32>(1, 0)) - surface;
+                let down = surface_position(texel + vec2<i32>(0, 1)) - surface;
+                var normal = normalize(cross(right, down) + vec3<f32>(0.0, 1e-6, 0.0));
+                let ray = surface - unproject(uv, 0.0);
+                if (dot(normal, ray) > 0.0) {
+                    normal = -normal;
+                }
+                if (distance(position, surface) < params.thickness && dot(velocity, normal) < 0.0) {
+                    // Back onto the surface, bouncing off it.
+                    let normal_speed = dot(velocity, normal);
+                    let tangent = velocity - normal * normal_speed;
+                    velocity = tangent * (1.0 - params.friction) - normal * normal_speed * params.restitution;
+                    position = surface + normal * 0.001;
+                }
+            }
+        }
+    }
+
+    particle.position = vec4<f32>(position, particle.position.w + params.dt);
+    particle.velocity = vec4<f32>(velocity, particle.velocity.w);
+    particles.particles[index] = particle;
+}
@@ -0,0 +1,70 @@
+// Draws the particles of a GPU emitter as quads facing the camera, added onto the scene, see
+// gpu_particles.rs.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct DrawParams {
+    start_color: vec4<f32>; // At birth, premultiplied by its alpha when drawn.
+    end_color: vec4<f32>; // At death.
+    size: f32; // Width of a particle, in world units.
+};
+[[group(1), binding(0)]]
+var<uniform> params: DrawParams;
+
+// `Particle` in gpu_particles.wgsl, per instance.
+struct ParticleInput {
+    [[location(0)]] position: vec4<f32>;
+    [[location(1)]] velocity: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec3<f32>;
+    [[location(1)]] offset: vec2<f32>; // From the center, -1 to 1.
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32, particle: ParticleInput) -> VertexOutput {
+    var out: VertexOutput;
+    let age = particle.position.w;
+    let lifetime = particle.velocity.w;
+    if (age >= lifetime) {
+        // Dead or never spawned: outside the clip volume.
+        out.clip_position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+        out.color = vec3<f32>(0.0);
+        out.offset = vec2<f32>(0.0);
+        return out;
+    }
+    // Two triangles, from the six vertices of the instance.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[index];
+    let forward = normalize(camera.eye.xyz - particle.position.xyz);
+    let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(forward.y) > 0.99);
+    let right = normalize(cross(helper, forward));
+    let up = cross(forward, right);
+    let world = particle.position.xyz + (right * corner.x + up * corner.y) * params.size * 0.5;
+    let color = mix(params.start_color, params.end_color, age / lifetime);
+    out.clip_position = camera.view_proj * vec4<f32>(world, 1.0);
+    out.color = color.rgb * color.a;
+    out.offset = corner;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Round, brightest at the center.
+    let falloff = max(1.0 - dot(in.offset, in.offset), 0.0);
+    return vec4<f32>(in.color * falloff, 0.0);
+}
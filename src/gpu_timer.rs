@@ -0,0 +1,160 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+// GPU timing
+//======================
+// How long the GPU spends in each pass of a frame, measured with timestamp queries:
+//  1. `begin_pass` and `end_pass` write a timestamp before and after a pass,
+//  2. `end_frame` resolves the timestamps into a buffer and copies them into a readout buffer,
+//     one per frame in flight,
+//  3. a few frames later, once the GPU got there, the readout is mapped and turned into times.
+// So the times lag the frame they are read in. Adapters without `Features::TIMESTAMP_QUERY`
+// (e.g. GL, and some older drivers) have no timer, and report no pass times.
+
+const MAX_PASSES: u32 = 8;
+const TIMESTAMP_SIZE: u64 = wgpu::QUERY_SIZE as u64;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+struct Readout {
+    buffer: wgpu::Buffer,
+    passes: Vec<&'static str>, // Of the frame that was copied into it.
+    map: Option<MapFuture>,    // While the GPU has not finished that frame.
+}
+
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readouts: Vec<Readout>,
+    current: usize,
+    passes: Vec<&'static str>, // Of the frame being encoded.
+    timing: bool,              // False for frames whose readout is still in use.
+    period: f32,               // Nanoseconds per timestamp tick.
+    times: Vec<(&'static str, f32)>,
+}
+
+impl GpuTimer {
+    // The device needs `Features::TIMESTAMP_QUERY`, see `Capabilities::timestamp_queries`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, frames_in_flight: usize) -> GpuTimer {
+        let size = MAX_PASSES as u64 * 2 * TIMESTAMP_SIZE;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_PASSES * 2,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readouts = (0..frames_in_flight.max(1) + 1)
+            .map(|_| Readout {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("GPU Timer Readout Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                passes: Vec::new(),
+                map: None,
+            })
+            .collect();
+        GpuTimer {
+            query_set,
+            resolve_buffer,
+            readouts,
+            current: 0,
+            passes: Vec::new(),
+            timing: false,
+            period: queue.get_timestamp_period(),
+            times: Vec::new(),
+        }
+    }
+
+    // Picks up the times of finished frames. Call before encoding a frame.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+        for readout in &mut self.readouts {
+            let done = match &mut readout.map {
+                Some(map) => poll_once(map),
+                None => continue,
+            };
+            match done {
+                Some(Ok(())) => {
+                    let data = readout.buffer.slice(..).get_mapped_range();
+                    let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                    self.times = readout
+                        .passes
+                        .iter()
+                        .zip(timestamps.chunks_exact(2))
+                        .map(|(&pass, t)| (pass, t[1].saturating_sub(t[0]) as f32 * self.period / 1_000_000.0))
+                        .collect();
+                    drop(data);
+                    readout.buffer.unmap();
+                    readout.map = None;
+                    readout.passes.clear();
+                }
+                Some(Err(_)) => {
+                    readout.map = None;
+                    readout.passes.clear();
+                }
+                None => {}
+            }
+        }
+        self.passes.clear();
+        // If the GPU is that far behind, skip timing this frame rather than wait.
+        self.timing = self.readouts[self.current].map.is_none();
+    }
+
+    pub fn begin_pass(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        if self.timing && self.passes.len() < MAX_PASSES as usize {
+            encoder.write_timestamp(&self.query_set, self.passes.len() as u32 * 2);
+            self.passes.push(label);
+        }
+    }
+
+    // Ends the pass started last.
+    pub fn end_pass(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.timing && !self.passes.is_empty() {
+            encoder.write_timestamp(&self.query_set, self.passes.len() as u32 * 2 - 1);
+        }
+    }
+
+    // Copies the frame's timestamps out, at the end of its command buffer.
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.timing || self.passes.is_empty() {
+            return;
+        }
+        let count = self.passes.len() as u32 * 2;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        let readout = &mut self.readouts[self.current];
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &readout.buffer, 0, count as u64 * TIMESTAMP_SIZE);
+        readout.passes = std::mem::take(&mut self.passes);
+    }
+
+    // Call after submitting the frame.
+    pub fn after_submit(&mut self) {
+        if !self.timing || self.readouts[self.current].passes.is_empty() {
+            return;
+        }
+        let readout = &mut self.readouts[self.current];
+        readout.map = Some(Box::pin(readout.buffer.slice(..).map_async(wgpu::MapMode::Read)));
+        self.current = (self.current + 1) % self.readouts.len();
+    }
+
+    // Milliseconds per pass, of the latest frame that finished on the GPU.
+    pub fn times(&self) -> &[(&'static str, f32)] {
+        &self.times
+    }
+}
+
+// Polls without a waker to notify; wgpu completes its futures from `Device::poll`.
+fn poll_once(future: &mut MapFuture) -> Option<Result<(), wgpu::BufferAsyncError>> {
+    let mut context = Context::from_waker(Waker::noop());
+    match future.as_mut().poll(&mut context) {
+        Poll::Ready(result) => Some(result),
+        Poll::Pending => None,
+    }
+}
@@ -0,0 +1,224 @@
+use cgmath::{Matrix4, SquareMatrix, Vector4};
+
+use crate::reflection::ShaderReflection;
+use crate::texture::Texture;
+use crate::uniform::UniformLayout;
+
+// Ground grid
+//======================
+// Editors and debugging scenes need a sense of scale and of where the origin is. The grid is a
+// plane of lines at a height, drawn without any geometry: a triangle covers each camera's viewport
+// and every pixel intersects its ray with the plane (grid.wgsl):
+//
+//     gfx.set_grid(Some(GridSettings::default()));
+//
+// Minor lines are `spacing` apart, and every `major_every`th line is a major one. The x axis (where
+// z is 0) and the z axis (where x is 0) are drawn in their own colors, red and blue like the
+// gizmos. Lines stay a pixel wide however far away they are, and fade out towards
+// `fade_distance` and where the plane is seen edge on, which hides the aliasing there.
+//
+// Every pixel of the grid is at the depth of the plane, so it is drawn after the renderables, hidden
+// behind those in front of the plane and blended over those below it. It doesn't write the depth,
+// so the debug lines and the passes after it see through it. Like the debug lines, only cameras
+// that see `RenderLayers::GIZMOS` draw it.
+
+/// How the ground grid looks, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridSettings {
+    pub spacing: f32, // Between minor lines, in world units.
+    pub major_every: u32,
+    pub fade_distance: f32, // In world units from the camera.
+    pub height: f32, // Of the plane, along y.
+    pub minor_color: [f32; 4],
+    pub major_color: [f32; 4],
+    pub x_axis_color: [f32; 4],
+    pub z_axis_color: [f32; 4],
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        GridSettings {
+            spacing: 1.0,
+            major_every: 10,
+            fade_distance: 100.0,
+            height: 0.0,
+            minor_color: [0.5, 0.5, 0.5, 0.35],
+            major_color: [0.65, 0.65, 0.65, 0.7],
+            x_axis_color: [0.9, 0.2, 0.2, 1.0],
+            z_axis_color: [0.25, 0.45, 1.0, 1.0],
+        }
+    }
+}
+
+uniform_struct! {
+    struct GridParams {
+        inverse_view_proj: Matrix4<f32>,
+        minor_color: Vector4<f32>,
+        major_color: Vector4<f32>,
+        x_axis_color: Vector4<f32>,
+        z_axis_color: Vector4<f32>,
+        spacing: f32,
+        major_every: f32,
+        fade_distance: f32,
+        height: f32,
+    }
+}
+assert_uniform_size!(GridParams, 144);
+
+/// Draws the ground grid for every camera, see above.
+pub struct Grid {
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    params_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+    params_stride: u32,
+    params_capacity: usize, // In cameras.
+}
+
+impl Grid {
+    // `camera_layout` is that of the camera bind groups, which the grid is drawn with.
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, samples: u32) -> Grid {
+        let wgsl = include_str!("grid.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        // Every camera has its own part of the parameters' buffer.
+        let mut entries = reflection.layout_entries(1);
+        for entry in &mut entries {
+            if let wgpu::BindingType::Buffer { has_dynamic_offset, .. } = &mut entry.ty {
+                *has_dynamic_offset = true;
+            }
+        }
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid Bind Group Layout"),
+            entries: &entries,
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Grid Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, &shader, format, samples);
+        let params_stride = device.limits().min_uniform_buffer_offset_alignment.max(GridParams::SIZE as u32);
+        let params_capacity = 4;
+        let params_buffer = create_params_buffer(device, params_stride, params_capacity);
+        let params_bind_group = create_params_bind_group(device, &params_layout, &params_buffer);
+        Grid {
+            pipeline,
+            pipeline_layout,
+            shader,
+            params_layout,
+            params_buffer,
+            params_bind_group,
+            params_stride,
+            params_capacity,
+        }
+    }
+
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, format, samples);
+    }
+
+    // Uploads the grid's parameters for every camera, by its view projection matrix.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, settings: &GridSettings, view_projs: &[Matrix4<f32>]) {
+        if view_projs.len() > self.params_capacity {
+            self.params_capacity = view_projs.len().next_power_of_two();
+            self.params_buffer = create_params_buffer(device, self.params_stride, self.params_capacity);
+            self.params_bind_group = create_params_bind_group(device, &self.params_layout, &self.params_buffer);
+        }
+        for (index, view_proj) in view_projs.iter().enumerate() {
+            let params = GridParams {
+                inverse_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity),
+                minor_color: settings.minor_color.into(),
+                major_color: settings.major_color.into(),
+                x_axis_color: settings.x_axis_color.into(),
+                z_axis_color: settings.z_axis_color.into(),
+                spacing: settings.spacing.max(f32::EPSILON),
+                major_every: settings.major_every.max(1) as f32,
+                fade_distance: settings.fade_distance,
+                height: settings.height,
+            };
+            let offset = index as u64 * self.params_stride as u64;
+            queue.write_buffer(&self.params_buffer, offset, &params.to_uniform_bytes());
+        }
+    }
+
+    // Draws the grid for camera `index`, into a scene pass with the camera's bind group set.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) {
+        if index >= self.params_capacity {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.params_bind_group, &[index as u32 * self.params_stride]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Grid Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Tested against the renderables, but not written: the grid is see-through.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+fn create_params_buffer(device: &wgpu::Device, stride: u32, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Grid Params"),
+        size: stride as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_params_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Grid Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(GridParams::SIZE as u64),
+            }),
+        }],
+    })
+}
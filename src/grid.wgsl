@@ -0,0 +1,103 @@
+// The infinite ground grid, see grid.rs. A triangle covers the viewport, every pixel finds where
+// its ray through the camera meets the plane, and draws the lines there at that depth.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct GridParams {
+    inverse_view_proj: mat4x4<f32>;
+    minor_color: vec4<f32>;
+    major_color: vec4<f32>;
+    x_axis_color: vec4<f32>;
+    z_axis_color: vec4<f32>;
+    spacing: f32; // Between minor lines, in world units.
+    major_every: f32; // Minor lines per major line.
+    fade_distance: f32; // From the camera, where the grid has faded out.
+    height: f32; // Of the plane, along y.
+};
+[[group(1), binding(0)]]
+var<uniform> params: GridParams;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = params.inverse_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// How much of a line every `spacing` units covers the pixel at `position`, from 0 to 1.
+fn lines(position: vec2<f32>, spacing: f32) -> f32 {
+    let coordinate = position / spacing;
+    // The size of the pixel in grid cells, so lines are a pixel wide at any distance.
+    let width = max(fwidth(coordinate), vec2<f32>(0.0001));
+    let distance = abs(fract(coordinate - 0.5) - 0.5) / width;
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+// How much of the line along `axis` = 0 covers the pixel.
+fn axis_line(coordinate: f32) -> f32 {
+    let width = max(fwidth(coordinate), 0.0001);
+    return 1.0 - min(abs(coordinate) / width, 1.0);
+}
+
+struct FragmentOutput {
+    [[location(0)]] color: vec4<f32>;
+    [[builtin(frag_depth)]] depth: f32;
+};
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let near = unproject(in.ndc, 0.0);
+    let far = unproject(in.ndc, 1.0);
+    let direction = far - near;
+    // Rays parallel to the plane, or meeting it behind the camera, miss it.
+    var t = -1.0;
+    if (abs(direction.y) > 0.000001) {
+        t = (params.height - near.y) / direction.y;
+    }
+    if (t <= 0.0 || t > 1.0) {
+        discard;
+    }
+    let position = near + direction * t;
+    let clip = camera.view_proj * vec4<f32>(position, 1.0);
+
+    let minor = lines(position.xz, params.spacing);
+    let major = lines(position.xz, params.spacing * params.major_every);
+    var color = params.minor_color * minor;
+    color = mix(color, params.major_color, major);
+    // The x axis runs where z is 0, the z axis where x is 0.
+    color = mix(color, params.x_axis_color, axis_line(position.z));
+    color = mix(color, params.z_axis_color, axis_line(position.x));
+
+    // Faded out with the distance, and where the plane is seen at a grazing angle, where the lines
+    // would alias into noise.
+    let distance = length(position - camera.eye.xyz);
+    let fade = 1.0 - smoothStep(params.fade_distance * 0.5, params.fade_distance, distance);
+    let grazing = smoothStep(0.0, 0.15, abs(normalize(direction).y));
+    color.a = color.a * fade * grazing;
+    if (color.a <= 0.001) {
+        discard;
+    }
+
+    var out: FragmentOutput;
+    out.color = color;
+    out.depth = clip.z / clip.w;
+    return out;
+}
@@ -0,0 +1,49 @@
+// Builds a 256 bin histogram of log2 luminance.
+// Bin 0 counts (nearly) black pixels, bins 1-255 cover [min_log_lum, min_log_lum + log_lum_range].
+
+struct HistogramParams {
+    min_log_lum: f32;
+    inv_log_lum_range: f32;
+};
+
+struct Histogram {
+    bins: array<atomic<u32>, 256>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: HistogramParams;
+[[group(0), binding(1)]]
+var input: texture_2d<f32>;
+[[group(0), binding(2)]]
+var<storage, read_write> histogram: Histogram;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let lum = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (lum < 0.0001) {
+        return 0u;
+    }
+    let log_lum = clamp((log2(lum) - params.min_log_lum) * params.inv_log_lum_range, 0.0, 1.0);
+    return u32(log_lum * 254.0 + 1.0);
+}
+
+[[stage(compute), workgroup_size(16, 16)]]
+fn main(
+    [[builtin(global_invocation_id)]] id: vec3<u32>,
+    [[builtin(local_invocation_index)]] local_index: u32,
+) {
+    // Count in workgroup memory first, to keep contention on the global bins low.
+    atomicStore(&local_bins[local_index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(input);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x < size.x && pos.y < size.y) {
+        let bin = luminance_bin(textureLoad(input, pos, 0).rgb);
+        atomicAdd(&local_bins[bin], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram.bins[local_index], atomicLoad(&local_bins[local_index]));
+}
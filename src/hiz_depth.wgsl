@@ -0,0 +1,29 @@
+// Copies the depth of a camera's viewport into the first level of its depth pyramid, keeping the
+// farthest sample of each pixel.
+
+struct Params {
+    origin: vec2<i32>; // Of the viewport in the depth texture.
+};
+
+[[group(0), binding(0)]]
+var depth: texture_depth_multisampled_2d;
+[[group(0), binding(1)]]
+var output: texture_storage_2d<r32float, write>;
+[[group(0), binding(2)]]
+var<uniform> params: Params;
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    let samples = i32(textureNumSamples(depth));
+    var farthest = 0.0;
+    for (var i = 0; i < samples; i = i + 1) {
+        farthest = max(farthest, textureLoad(depth, params.origin + pos, i));
+    }
+    textureStore(output, pos, vec4<f32>(farthest));
+}
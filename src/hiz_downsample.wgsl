@@ -0,0 +1,30 @@
+// Builds the next level of a depth pyramid: every texel is the farthest depth of the texels it
+// covers in the level above. Odd sizes round down, so the texels of an odd level are read three
+// at a time along that axis; nothing is left out.
+
+[[group(0), binding(0)]]
+var input: texture_2d<f32>;
+[[group(0), binding(1)]]
+var output: texture_storage_2d<r32float, write>;
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    let input_size = textureDimensions(input);
+    let max_pos = input_size - vec2<i32>(1, 1);
+    let span = vec2<i32>(2, 2) + input_size % vec2<i32>(2, 2);
+    let src = pos * 2;
+    var farthest = 0.0;
+    for (var y = 0; y < span.y; y = y + 1) {
+        for (var x = 0; x < span.x; x = x + 1) {
+            let texel = min(src + vec2<i32>(x, y), max_pos);
+            farthest = max(farthest, textureLoad(input, texel, 0).r);
+        }
+    }
+    textureStore(output, pos, vec4<f32>(farthest));
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cgmath::Matrix4;
+
+use crate::gfx::create_depth_view;
+use crate::mesh::{Mesh, VertexLayout};
+use crate::readback::Readback;
+use crate::reflection::ShaderReflection;
+use crate::texture::Texture;
+use crate::transform::ModelInstance;
+use crate::vertex_layout::POSITION_LOCATION;
+
+// ID buffer picking
+//======================
+// `GFX::pick` finds renderables from the boxes around their meshes, which is fast but rough: a
+// click next to a sphere, inside its box, still hits it. For exact picking, the renderables are
+// drawn again as seen by the camera into an `r32uint` texture, each pixel holding the index of
+// the renderable it shows plus one (id_buffer.wgsl), and the pixels of interest are read back:
+// one for a click, a rectangle for box selection.
+//
+// The ID buffer is only drawn when asked, and only inside the rectangle read back, in a command
+// buffer of its own. Reading it back waits for the GPU, so it is for clicks rather than for every
+// frame.
+
+/// A renderable to draw into the ID buffer.
+pub struct IdDraw<'a> {
+    pub instance: u32, // Its index, which is also its instance in the instance buffer.
+    pub mesh: &'a Mesh,
+}
+
+/// Draws renderables into an ID texture and reads parts of it back.
+pub struct IdBuffer {
+    bind_group: wgpu::BindGroup,
+    camera_buffer: wgpu::Buffer,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<VertexLayout, wgpu::RenderPipeline>,
+    // The ID and depth textures, at the size of the frame, created on first use.
+    targets: Option<(wgpu::Texture, wgpu::TextureView, wgpu::TextureView, (u32, u32))>,
+}
+
+impl IdBuffer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+    pub fn new(device: &wgpu::Device) -> IdBuffer {
+        let wgsl = include_str!("id_buffer.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("ID Buffer"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("ID Buffer Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ID Buffer Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ID Buffer Camera"),
+            size: std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ID Buffer Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+        IdBuffer {
+            bind_group,
+            camera_buffer,
+            shader,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            targets: None,
+        }
+    }
+
+    // Draws `draws` as seen through `view_proj` into `viewport` of a frame of `size`, and reads back
+    // `rect` (x, y, width, height in pixels, inside the viewport). `instances` is the GFX's
+    // instance buffer, with the model matrices of the renderables. Decode the bytes with `ids`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn read(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        queue: &wgpu::Queue,
+        size: (u32, u32),
+        view_proj: Matrix4<f32>,
+        viewport: (u32, u32, u32, u32),
+        rect: (u32, u32, u32, u32),
+        instances: &wgpu::Buffer,
+        draws: &[IdDraw],
+    ) -> Readback {
+        if self.targets.as_ref().is_none_or(|targets| targets.3 != size) {
+            self.targets = Some(create_targets(device, size));
+        }
+        for draw in draws {
+            if !self.pipelines.contains_key(&draw.mesh.layout) {
+                let pipeline = self.create_pipeline(device, draw.mesh.layout);
+                self.pipelines.insert(draw.mesh.layout, pipeline);
+            }
+        }
+        let view_proj: [[f32; 4]; 4] = view_proj.into();
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[view_proj]));
+
+        let (texture, view, depth_view, _) = self.targets.as_ref().expect("created above");
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ID Buffer Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("ID Buffer Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            let (x, y, w, h) = viewport;
+            render_pass.set_viewport(x as f32, y as f32, w as f32, h as f32, 0.0, 1.0);
+            render_pass.set_scissor_rect(rect.0, rect.1, rect.2, rect.3);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_vertex_buffer(1, instances.slice(..));
+            for draw in draws {
+                render_pass.set_pipeline(&self.pipelines[&draw.mesh.layout]);
+                render_pass.set_vertex_buffer(0, draw.mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(draw.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                render_pass.draw_indexed(0..draw.mesh.num_indices, 0, draw.instance..draw.instance + 1);
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let source = wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: rect.0,
+                y: rect.1,
+                z: 0,
+            },
+            aspect: wgpu::TextureAspect::All,
+        };
+        let extent = wgpu::Extent3d {
+            width: rect.2,
+            height: rect.3,
+            depth_or_array_layers: 1,
+        };
+        Readback::from_texture(device.clone(), queue, source, Self::FORMAT, extent)
+    }
+
+    // The renderable indices in read back ID buffer pixels, each once, in the order first seen.
+    pub fn ids(bytes: &[u8]) -> Vec<usize> {
+        let mut ids = Vec::new();
+        for pixel in bytes.chunks_exact(4) {
+            let id = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            if id != 0 && !ids.contains(&(id as usize - 1)) {
+                ids.push(id as usize - 1);
+            }
+        }
+        ids
+    }
+
+    // Only the position of the vertices matters, wherever in the vertex it is.
+    fn create_pipeline(&self, device: &wgpu::Device, layout: VertexLayout) -> wgpu::RenderPipeline {
+        let mut desc = layout.desc();
+        desc.attributes.retain(|attribute| attribute.shader_location == POSITION_LOCATION);
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ID Buffer Pipeline"),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[desc.layout(), ModelInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: Self::FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            // Culled like the materials, so what is picked is what is seen.
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+}
+
+fn create_targets(device: &wgpu::Device, (width, height): (u32, u32)) -> (wgpu::Texture, wgpu::TextureView, wgpu::TextureView, (u32, u32)) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("ID Buffer"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: IdBuffer::FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_view = create_depth_view(device, (width, height), 1);
+    (texture, view, depth_view, (width, height))
+}
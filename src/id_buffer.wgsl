@@ -0,0 +1,44 @@
+// Writes the index of the renderable drawn, plus one, into an `r32uint` texture, so 0 is nothing.
+// See id_buffer.rs.
+
+struct IdCamera {
+    view_proj: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: IdCamera;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_0: vec4<f32>;
+    [[location(6)]] model_1: vec4<f32>;
+    [[location(7)]] model_2: vec4<f32>;
+    [[location(8)]] model_3: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0), interpolate(flat)]] id: u32;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(instance_index)]] index: u32,
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    // Renderable `i` is drawn as instance `i`.
+    out.id = index + 1u;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] u32 {
+    return in.id;
+}
@@ -0,0 +1,307 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+// Job system
+//======================
+// A fixed pool of worker threads that run small closures ("jobs"). Every worker has its own queue:
+// jobs spawned from a worker go to the back of its queue, and it takes its next job from the back too,
+// which keeps related work on one core. Jobs spawned from other threads go to a shared queue.
+// A worker that runs out of work steals from the front of the other queues before it goes to sleep.
+//
+//     jobs::global().scope(|s| {
+//         s.spawn(|| cull(&scene, &camera_a));
+//         s.spawn(|| cull(&scene, &camera_b));
+//     }); // Returns when both are done, so the jobs may borrow from the caller.
+//
+//     jobs::global().parallel_for(&mut particles, |_, particle| particle.update(dt));
+//
+// The thread waiting for a scope runs jobs itself instead of blocking, so scopes can be nested.
+// A panicking job is caught, and the panic resumed on the thread that owns the scope.
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct Shared {
+    injector: Mutex<VecDeque<Job>>,
+    queues: Vec<Mutex<VecDeque<Job>>>,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+}
+
+thread_local! {
+    // (pool id, worker index) of the worker running on this thread.
+    static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+// Tells pools apart, so a worker of one pool never pushes into the queue of another.
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct JobSystem {
+    id: usize,
+    shared: Arc<Shared>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl JobSystem {
+    pub fn new(thread_count: usize) -> JobSystem {
+        let thread_count = thread_count.max(1);
+        let id = NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed);
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            queues: (0..thread_count).map(|_| Mutex::new(VecDeque::new())).collect(),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let workers = (0..thread_count)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("job worker {}", index))
+                    .spawn(move || {
+                        WORKER.with(|worker| worker.set(Some((id, index))));
+                        while !shared.shutdown.load(Ordering::Acquire) {
+                            if let Some(job) = shared.find_job(Some(index)) {
+                                job();
+                                continue;
+                            }
+                            // Spawning pushes and notifies under this lock, so a job pushed since
+                            // the look above is found by this one, or wakes the worker.
+                            let guard = shared.sleep.lock().unwrap();
+                            if shared.shutdown.load(Ordering::Acquire) {
+                                break;
+                            }
+                            match shared.find_job(Some(index)) {
+                                Some(job) => {
+                                    drop(guard);
+                                    job();
+                                }
+                                None => drop(shared.wake.wait(guard).unwrap()),
+                            }
+                        }
+                    })
+                    .expect("failed to spawn job worker thread")
+            })
+            .collect();
+
+        JobSystem { id, shared, workers }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.shared.queues.len()
+    }
+
+    // Runs `job` on some worker, without waiting for it.
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        self.push(Box::new(move || {
+            // Keep the worker alive.
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                tracing::error!("A job panicked");
+            }
+        }));
+    }
+
+    // Runs `f`, which may spawn jobs borrowing from the caller; returns once all of them have finished.
+    pub fn scope<'env, R>(&self, f: impl FnOnce(&Scope<'_, 'env>) -> R) -> R {
+        let scope = Scope {
+            system: self,
+            state: Arc::new(ScopeState {
+                pending: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+            }),
+            _env: PhantomData,
+        };
+        // Waits in drop as well, so borrowed data outlives the jobs even if `f` panics.
+        let result = f(&scope);
+        scope.wait();
+        if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+        result
+    }
+
+    // Calls `f(index, item)` for every item, spread over the workers in chunks.
+    pub fn parallel_for<T: Send>(&self, items: &mut [T], f: impl Fn(usize, &mut T) + Sync) {
+        let chunk_size = items.len().div_ceil(self.thread_count() * 4).max(1);
+        let f = &f;
+        self.scope(|s| {
+            for (chunk_index, chunk) in items.chunks_mut(chunk_size).enumerate() {
+                s.spawn(move || {
+                    for (i, item) in chunk.iter_mut().enumerate() {
+                        f(chunk_index * chunk_size + i, item);
+                    }
+                });
+            }
+        });
+    }
+
+    fn push(&self, job: Job) {
+        let _sleep = self.shared.sleep.lock().unwrap();
+        match WORKER.with(Cell::get) {
+            Some((pool, index)) if pool == self.id => self.shared.queues[index].lock().unwrap().push_back(job),
+            _ => self.shared.injector.lock().unwrap().push_back(job),
+        }
+        self.shared.wake.notify_one();
+    }
+
+    // Runs one queued job on the calling thread. Returns false if there was none.
+    fn help(&self) -> bool {
+        let index = match WORKER.with(Cell::get) {
+            Some((pool, index)) if pool == self.id => Some(index),
+            _ => None,
+        };
+        match self.shared.find_job(index) {
+            Some(job) => {
+                job();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Shared {
+    // The next job for worker `index`: its own newest job, the oldest shared job, or a stolen one.
+    fn find_job(&self, index: Option<usize>) -> Option<Job> {
+        if let Some(index) = index {
+            if let Some(job) = self.queues[index].lock().unwrap().pop_back() {
+                return Some(job);
+            }
+        }
+        if let Some(job) = self.injector.lock().unwrap().pop_front() {
+            return Some(job);
+        }
+        let start = index.map_or(0, |index| index + 1);
+        (0..self.queues.len())
+            .map(|i| (start + i) % self.queues.len())
+            .filter(|&victim| Some(victim) != index)
+            .find_map(|victim| self.queues[victim].lock().unwrap().pop_front())
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        {
+            let _sleep = self.shared.sleep.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::Release);
+            self.shared.wake.notify_all();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct ScopeState {
+    pending: AtomicUsize,
+    panic: Mutex<Option<Box<dyn std::any::Any + Send>>>,
+}
+
+/// Spawns jobs that may borrow data living for `'env`, see `JobSystem::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    system: &'scope JobSystem,
+    state: Arc<ScopeState>,
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'env) {
+        let state = self.state.clone();
+        state.pending.fetch_add(1, Ordering::AcqRel);
+        let job: Box<dyn FnOnce() + Send + 'env> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                state.panic.lock().unwrap().get_or_insert(payload);
+            }
+            state.pending.fetch_sub(1, Ordering::AcqRel);
+        });
+        // SAFETY: the scope does not return (or unwind) before `pending` is back to zero,
+        // so nothing the job borrows goes away while it may still run.
+        let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'env>, Job>(job) };
+        self.system.push(job);
+    }
+
+    fn wait(&self) {
+        while self.state.pending.load(Ordering::Acquire) > 0 {
+            if !self.system.help() {
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+impl Drop for Scope<'_, '_> {
+    fn drop(&mut self) {
+        self.wait();
+    }
+}
+
+// The job system shared by the engine and the game, with a worker for every core but one
+// (the main thread helps out while it waits on a scope).
+pub fn global() -> &'static JobSystem {
+    static GLOBAL: OnceLock<JobSystem> = OnceLock::new();
+    GLOBAL.get_or_init(|| {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        JobSystem::new(cores.saturating_sub(1))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoped_jobs_borrow_from_the_stack() {
+        let jobs = JobSystem::new(3);
+        let numbers: Vec<u64> = (1..=100).collect();
+        let mut sums = [0u64; 4];
+        jobs.scope(|s| {
+            for (sum, chunk) in sums.iter_mut().zip(numbers.chunks(25)) {
+                s.spawn(move || *sum = chunk.iter().sum());
+            }
+        });
+        assert_eq!(sums, [325, 950, 1575, 2200]);
+    }
+
+    #[test]
+    fn parallel_for_visits_every_index_once() {
+        let jobs = JobSystem::new(3);
+        let visits: Vec<AtomicUsize> = (0..1000).map(|_| AtomicUsize::new(0)).collect();
+        let mut items: Vec<usize> = vec![0; visits.len()];
+        jobs.parallel_for(&mut items, |index, item| {
+            *item = index;
+            visits[index].fetch_add(1, Ordering::Relaxed);
+        });
+        assert!(visits.iter().all(|count| count.load(Ordering::Relaxed) == 1));
+        assert!(items.iter().enumerate().all(|(index, &item)| item == index));
+    }
+
+    #[test]
+    fn a_panicking_job_panics_the_scope_after_the_other_jobs() {
+        let jobs = JobSystem::new(2);
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            jobs.scope(|s| {
+                s.spawn(|| panic!("job failed"));
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        thread::sleep(std::time::Duration::from_millis(5));
+                        finished.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+            })
+        }));
+        let payload = result.expect_err("the panic should reach the scope's caller");
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"job failed"));
+        assert_eq!(finished.load(Ordering::Relaxed), 8);
+        // The workers survive it.
+        let mut items = [0; 4];
+        jobs.parallel_for(&mut items, |index, item| *item = index);
+        assert_eq!(items, [0, 1, 2, 3]);
+    }
+}
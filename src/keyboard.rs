@@ -1,27 +1,93 @@
 use std::collections::VecDeque;
-static NKEYS: u16 = 255;
 static BUFFER_SIZE: u8 = 16;
 
+/// Which keys are held down: one bit per virtual-key code (0 to 255).
+/// It is `Copy`, so a game can keep the state of the previous frame and compare.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct KeyboardState {
+    bits: [u64; 4],
+}
+
+impl KeyboardState {
+    pub fn is_pressed(&self, keycode: u16) -> bool {
+        keycode < 256 && self.bits[keycode as usize / 64] & (1 << (keycode % 64)) != 0
+    }
+
+    // Whether any of `keycodes` is held down.
+    pub fn any_pressed(&self, keycodes: &[u16]) -> bool {
+        keycodes.iter().any(|&keycode| self.is_pressed(keycode))
+    }
+
+    // The keys held down, in the order of their codes.
+    pub fn iter_pressed(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..256u16).filter(move |&keycode| self.is_pressed(keycode))
+    }
+
+    // The keys held down now that were not in `earlier`.
+    pub fn pressed_since(&self, earlier: &KeyboardState) -> KeyboardState {
+        let mut bits = self.bits;
+        for (bits, earlier) in bits.iter_mut().zip(earlier.bits) {
+            *bits &= !earlier;
+        }
+        KeyboardState { bits }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.bits.iter().map(|bits| bits.count_ones()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == [0; 4]
+    }
+
+    // Codes above 255 are not virtual keys, and are ignored.
+    fn set(&mut self, keycode: u16, pressed: bool) {
+        if keycode < 256 {
+            let bit = 1 << (keycode % 64);
+            if pressed {
+                self.bits[keycode as usize / 64] |= bit;
+            } else {
+                self.bits[keycode as usize / 64] &= !bit;
+            }
+        }
+    }
+}
+
 pub struct Keyboard {
     auto_repeat_enabled: bool,
-    key_states: Vec<bool>,
+    key_states: KeyboardState,
     key_buffer: VecDeque<Event>,
     char_buffer: VecDeque<u16>,
+    dead_char: Option<u16>, // Until the next character, see `dead_char`.
 }
 
 impl Keyboard {
     pub fn new() -> Keyboard {
         Keyboard {
             auto_repeat_enabled: false,
-            key_states: vec![false; NKEYS as usize],
+            key_states: KeyboardState::default(),
             key_buffer: VecDeque::<Event>::with_capacity(BUFFER_SIZE as usize),
             char_buffer: VecDeque::<u16>::with_capacity(BUFFER_SIZE as usize),
+            dead_char: None,
         }
     }
 
     // Key Event Stuff
     pub fn key_is_pressed(&self, keycode: u16) -> bool {
-        self.key_states[keycode as usize]
+        self.key_states.is_pressed(keycode)
+    }
+
+    pub fn any_pressed(&self, keycodes: &[u16]) -> bool {
+        self.key_states.any_pressed(keycodes)
+    }
+
+    pub fn iter_pressed(&self) -> impl Iterator<Item = u16> + '_ {
+        self.key_states.iter_pressed()
+    }
+
+    // A copy of which keys are held down right now.
+    pub fn state(&self) -> KeyboardState {
+        self.key_states
     }
 
     pub fn read_key(&mut self) -> Option<Event> {
@@ -41,6 +107,12 @@ impl Keyboard {
         self.char_buffer.is_empty()
     }
 
+    // The accent of a dead key that waits for the next key to combine with, e.g. for a text field
+    // to show it. Only the Win32 window reports dead keys.
+    pub fn dead_char(&self) -> Option<u16> {
+        self.dead_char
+    }
+
     pub fn flush_key(&mut self) {
         self.key_buffer.clear();
     }
@@ -69,7 +141,7 @@ impl Keyboard {
     }
 
     pub fn on_key_pressed(&mut self, keycode: u16) {
-        self.key_states[keycode as usize] = true;
+        self.key_states.set(keycode, true);
         self.key_buffer.push_back(Event {
             event_type: EventType::Press,
             code: keycode,
@@ -79,7 +151,7 @@ impl Keyboard {
     }
 
     pub fn on_key_released(&mut self, keycode: u16) {
-        self.key_states[keycode as usize] = false;
+        self.key_states.set(keycode, false);
         self.key_buffer.push_back(Event {
             event_type: EventType::Release,
             code: keycode,
@@ -88,12 +160,18 @@ impl Keyboard {
     }
 
     pub fn on_char(&mut self, character: u16) {
+        self.dead_char = None;
         self.char_buffer.push_back(character);
         Self::trim_buffer(&mut self.char_buffer)
     }
 
+    pub fn on_dead_char(&mut self, character: u16) {
+        self.dead_char = Some(character);
+    }
+
     pub fn clear_state(&mut self) {
-        self.key_states.fill(false);
+        self.key_states = KeyboardState::default();
+        self.dead_char = None;
     }
 
     // Trims the buffer back to BUFFER_SIZE
@@ -102,6 +180,12 @@ impl Keyboard {
     }
 }
 
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Event {
     event_type: EventType,
     code: u16,
@@ -125,7 +209,7 @@ impl Event {
     }
 
     pub fn get_code(&self) -> u16 {
-        return self.code;
+        self.code
     }
 }
 
@@ -143,3 +227,77 @@ pub enum EventType {
     Release,
     Invalid,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(keycodes: &[u16]) -> KeyboardState {
+        let mut state = KeyboardState::default();
+        for &keycode in keycodes {
+            state.set(keycode, true);
+        }
+        state
+    }
+
+    #[test]
+    fn keys_map_to_their_own_bits() {
+        let mut keys = state(&[0, 63, 64, 255]);
+        for keycode in [0, 63, 64, 255] {
+            assert!(keys.is_pressed(keycode), "{}", keycode);
+        }
+        for keycode in [1, 62, 65, 127, 128, 254] {
+            assert!(!keys.is_pressed(keycode), "{}", keycode);
+        }
+        keys.set(63, false);
+        assert!(!keys.is_pressed(63) && keys.is_pressed(64));
+        assert_eq!(keys.count(), 3);
+
+        // Not virtual keys: never pressed, and they don't wrap around onto the low codes.
+        let mut unchanged = keys;
+        unchanged.set(256, true);
+        unchanged.set(300, true);
+        unchanged.set(u16::MAX, true);
+        assert_eq!(unchanged, keys);
+        assert!(!unchanged.is_pressed(256));
+    }
+
+    #[test]
+    fn pressed_since_keeps_the_new_keys() {
+        let earlier = state(&[0x10, 0x41, 200]);
+        let now = state(&[0x10, 0x42, 200, 201]);
+        let pressed = now.pressed_since(&earlier);
+        assert_eq!(pressed.iter_pressed().collect::<Vec<_>>(), [0x42, 201]);
+        assert!(now.pressed_since(&now).is_empty());
+        assert_eq!(KeyboardState::default().pressed_since(&earlier), KeyboardState::default());
+    }
+
+    #[test]
+    fn pressed_keys_are_listed_in_code_order() {
+        let keys = state(&[255, 3, 64, 63]);
+        assert_eq!(keys.iter_pressed().collect::<Vec<_>>(), [3, 63, 64, 255]);
+        assert_eq!(keys.count(), 4);
+        assert!(!keys.is_empty());
+        assert!(keys.any_pressed(&[1, 2, 64]));
+        assert!(!keys.any_pressed(&[1, 2, 65, 256]));
+        assert!(!keys.any_pressed(&[]));
+
+        let none = KeyboardState::default();
+        assert!(none.is_empty());
+        assert_eq!(none.count(), 0);
+        assert_eq!(none.iter_pressed().next(), None);
+    }
+
+    #[test]
+    fn the_keyboard_tracks_its_state() {
+        let mut keyboard = Keyboard::new();
+        keyboard.on_key_pressed(0x41);
+        keyboard.on_key_pressed(0x11);
+        assert!(keyboard.any_pressed(&[0x11, 0x12]));
+        assert_eq!(keyboard.iter_pressed().collect::<Vec<_>>(), [0x11, 0x41]);
+        keyboard.on_key_released(0x41);
+        assert_eq!(keyboard.state(), state(&[0x11]));
+        keyboard.clear_state();
+        assert!(keyboard.state().is_empty());
+    }
+}
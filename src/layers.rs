@@ -0,0 +1,69 @@
+use std::ops::{BitAnd, BitOr, Not};
+
+/// A 32-bit mask of render layers.
+/// Renderables are drawn by a camera (or pass) only when their layers intersect the camera's layers,
+/// e.g. to draw shadow casters only, the UI layer only, or to exclude editor gizmos from screenshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    pub const NONE: RenderLayers = RenderLayers(0);
+    pub const ALL: RenderLayers = RenderLayers(!0);
+
+    // Layers used by the engine itself. Layers 8 to 31 are free for the application.
+    pub const DEFAULT: RenderLayers = RenderLayers::layer(0);
+    pub const SHADOW_CASTERS: RenderLayers = RenderLayers::layer(1);
+    pub const UI: RenderLayers = RenderLayers::layer(2);
+    pub const GIZMOS: RenderLayers = RenderLayers::layer(3);
+    pub const TILEMAPS: RenderLayers = RenderLayers::layer(4);
+
+    /// The mask containing only layer `index` (0..32).
+    pub const fn layer(index: u32) -> RenderLayers {
+        RenderLayers(1 << index)
+    }
+
+    pub fn with(self, other: RenderLayers) -> RenderLayers {
+        self | other
+    }
+
+    pub fn without(self, other: RenderLayers) -> RenderLayers {
+        self & !other
+    }
+
+    /// True if at least one layer is in both masks.
+    pub fn intersects(self, other: RenderLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// True if every layer of `other` is also in `self`.
+    pub fn contains(self, other: RenderLayers) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        RenderLayers::DEFAULT
+    }
+}
+
+impl BitOr for RenderLayers {
+    type Output = RenderLayers;
+    fn bitor(self, rhs: RenderLayers) -> RenderLayers {
+        RenderLayers(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for RenderLayers {
+    type Output = RenderLayers;
+    fn bitand(self, rhs: RenderLayers) -> RenderLayers {
+        RenderLayers(self.0 & rhs.0)
+    }
+}
+
+impl Not for RenderLayers {
+    type Output = RenderLayers;
+    fn not(self) -> RenderLayers {
+        RenderLayers(!self.0)
+    }
+}
@@ -0,0 +1,396 @@
+use std::ops::Range;
+
+use cgmath::{Matrix4, Point3, Vector4};
+
+use crate::layers::RenderLayers;
+
+// Lens flares
+//======================
+// Bright lights seen through a camera leave a glare around them and a chain of reflections of the
+// lens' aperture ("ghosts") along the line from the light through the center of the image:
+//
+//     gfx.add_lens_flare(LensFlare::new(Point3::new(10.0, 8.0, -20.0), [1.0, 0.9, 0.7]).with_intensity(2.0));
+//
+// Every element of a flare is a sprite at `distance` along that line: 0 is the light itself, 1 the
+// center of the viewport and 2 the point mirrored through it. `LensFlare::new` comes with a glare,
+// a streak and a few ghosts; `with_elements` replaces them.
+//
+// Flares are drawn after each camera's part of the scene, added on top of it. Their vertex shader
+// tests a small grid of pixels around the light against the camera's depth, so a light fades out
+// as it moves behind something, or out of the viewport, instead of popping. With HDR, flares are
+// drawn into the HDR scene and can be brighter than white.
+
+// How much wider than tall `FlareShape::Streak` is.
+const STREAK_LENGTH: f32 = 8.0;
+
+/// The look of a flare element, see lens_flare.wgsl.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlareShape {
+    Glow,
+    Ring,
+    Disc,
+    Streak, // Horizontal, as from an anamorphic lens.
+}
+
+/// One sprite of a flare.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlareElement {
+    pub shape: FlareShape,
+    pub distance: f32, // Along the line through the center of the viewport, see above.
+    pub size: f32, // As a fraction of the viewport's height.
+    pub color: [f32; 4], // Multiplied with the light's; alpha scales the brightness.
+}
+
+impl FlareElement {
+    pub fn new(shape: FlareShape, distance: f32, size: f32, color: [f32; 4]) -> FlareElement {
+        FlareElement {
+            shape,
+            distance,
+            size,
+            color,
+        }
+    }
+}
+
+/// Identifies a flare added with `GFX::add_lens_flare`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LensFlareId(pub(crate) usize);
+
+/// A light seen as a lens flare.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LensFlare {
+    pub position: Point3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub occlusion_radius: f32, // In pixels, of the grid tested against the depth.
+    pub elements: Vec<FlareElement>,
+    pub layers: RenderLayers, // Seen by the cameras seeing one of these layers.
+}
+
+impl LensFlare {
+    pub fn new(position: Point3<f32>, color: [f32; 3]) -> LensFlare {
+        use FlareShape::*;
+        LensFlare {
+            position,
+            color,
+            intensity: 1.0,
+            occlusion_radius: 8.0,
+            elements: vec![
+                FlareElement::new(Glow, 0.0, 0.3, [1.0, 1.0, 1.0, 1.0]),
+                FlareElement::new(Streak, 0.0, 0.05, [0.8, 0.9, 1.0, 0.6]),
+                FlareElement::new(Disc, 0.4, 0.05, [1.0, 0.6, 0.3, 0.3]),
+                FlareElement::new(Ring, 0.7, 0.1, [0.4, 1.0, 0.6, 0.2]),
+                FlareElement::new(Disc, 1.2, 0.03, [0.6, 0.6, 1.0, 0.4]),
+                FlareElement::new(Disc, 1.5, 0.08, [0.5, 0.8, 1.0, 0.2]),
+                FlareElement::new(Ring, 1.9, 0.15, [1.0, 0.5, 0.8, 0.15]),
+            ],
+            layers: RenderLayers::ALL,
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> LensFlare {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_occlusion_radius(mut self, occlusion_radius: f32) -> LensFlare {
+        self.occlusion_radius = occlusion_radius;
+        self
+    }
+
+    pub fn with_elements(mut self, elements: Vec<FlareElement>) -> LensFlare {
+        self.elements = elements;
+        self
+    }
+
+    pub fn with_layers(mut self, layers: RenderLayers) -> LensFlare {
+        self.layers = layers;
+        self
+    }
+}
+
+// `SpriteInput` in lens_flare.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct FlareSprite {
+    center: [f32; 2],
+    half_size: [f32; 2],
+    color: [f32; 4],
+    light: [f32; 4],
+    bounds: [f32; 4],
+    shape: u32,
+}
+
+impl FlareSprite {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+        0 => Float32x2, 1 => Float32x2, 2 => Float32x4, 3 => Float32x4, 4 => Float32x4, 5 => Uint32
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<FlareSprite>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// What `LensFlares::prepare` needs of a camera.
+pub struct FlareCamera {
+    pub view_proj: Matrix4<f32>,
+    pub viewport: (u32, u32, u32, u32), // x, y, width and height in pixels.
+    pub layers: RenderLayers,
+}
+
+/// The flares, and the sprites of every camera for this frame.
+pub struct LensFlares {
+    flares: Vec<Option<LensFlare>>, // By id; `None` where removed.
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: Option<wgpu::BindGroup>, // Of the depth, made by `prepare`.
+    sprites: Vec<FlareSprite>,
+    ranges: Vec<Range<u32>>, // Of the sprites, by camera.
+    buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl LensFlares {
+    // `format` and `samples` are those of the scene target, and of the depth.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) -> LensFlares {
+        let bind_group_layout = create_bind_group_layout(device, samples);
+        let capacity = 64;
+        LensFlares {
+            flares: Vec::new(),
+            pipeline: create_pipeline(device, &bind_group_layout, format, samples),
+            bind_group_layout,
+            bind_group: None,
+            sprites: Vec::new(),
+            ranges: Vec::new(),
+            buffer: create_sprite_buffer(device, capacity),
+            capacity,
+        }
+    }
+
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        self.bind_group_layout = create_bind_group_layout(device, samples);
+        self.pipeline = create_pipeline(device, &self.bind_group_layout, format, samples);
+        self.bind_group = None;
+    }
+
+    pub fn add(&mut self, flare: LensFlare) -> LensFlareId {
+        let index = match self.flares.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.flares.push(None);
+                self.flares.len() - 1
+            }
+        };
+        self.flares[index] = Some(flare);
+        LensFlareId(index)
+    }
+
+    pub fn get(&self, id: LensFlareId) -> &LensFlare {
+        self.flares[id.0].as_ref().expect("lens flare was removed")
+    }
+
+    pub fn set(&mut self, id: LensFlareId, flare: LensFlare) {
+        self.flares[id.0] = Some(flare);
+    }
+
+    pub fn remove(&mut self, id: LensFlareId) {
+        self.flares[id.0] = None;
+    }
+
+    // Projects the flares through every camera and uploads their sprites, to be drawn with `draw`
+    // after the camera's scene pass into `depth`.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, depth: &wgpu::TextureView, cameras: &[FlareCamera]) {
+        self.sprites.clear();
+        self.ranges.clear();
+        for camera in cameras {
+            let start = self.sprites.len() as u32;
+            for flare in self.flares.iter().flatten() {
+                if camera.layers.intersects(flare.layers) {
+                    push_sprites(&mut self.sprites, flare, camera);
+                }
+            }
+            self.ranges.push(start..self.sprites.len() as u32);
+        }
+        if self.sprites.is_empty() {
+            return;
+        }
+        if self.sprites.len() > self.capacity {
+            self.capacity = self.sprites.len().next_power_of_two();
+            self.buffer = create_sprite_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.sprites));
+        // The depth view changes with the surface size, so this bind group is made every frame.
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Lens Flare Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth),
+            }],
+        }));
+    }
+
+    // Records the pass adding the flares of camera `index` to the scene, within its viewport and
+    // scissor rect (x, y, width, height in pixels).
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        index: usize,
+        viewport: (u32, u32, u32, u32),
+        scissor: (u32, u32, u32, u32),
+    ) {
+        let (range, bind_group) = match (self.ranges.get(index), &self.bind_group) {
+            (Some(range), Some(bind_group)) if !range.is_empty() => (range.clone(), bind_group),
+            _ => return,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Lens Flare Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        let (x, y, w, h) = viewport;
+        let (sx, sy, sw, sh) = scissor;
+        render_pass.set_viewport(x as f32, y as f32, w as f32, h as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(sx, sy, sw, sh);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..6, range);
+    }
+}
+
+// Adds the sprites of `flare` as seen by `camera`, none if the light is behind it or far outside
+// its viewport.
+fn push_sprites(sprites: &mut Vec<FlareSprite>, flare: &LensFlare, camera: &FlareCamera) {
+    let clip = camera.view_proj * Vector4::new(flare.position.x, flare.position.y, flare.position.z, 1.0);
+    if clip.w <= 0.0 {
+        return;
+    }
+    let (ndc_x, ndc_y, depth) = (clip.x / clip.w, clip.y / clip.w, clip.z / clip.w);
+    if !(0.0..=1.0).contains(&depth) || ndc_x.abs() > 1.5 || ndc_y.abs() > 1.5 {
+        return;
+    }
+    let (x, y, w, h) = camera.viewport;
+    let (x, y, w, h) = (x as f32, y as f32, w as f32, h as f32);
+    // Pixels have y pointing down, normalized device coordinates have y pointing up.
+    let light = [x + (ndc_x + 1.0) / 2.0 * w, y + (1.0 - ndc_y) / 2.0 * h, depth, flare.occlusion_radius];
+    let bounds = [x, y, x + w, y + h];
+    for element in &flare.elements {
+        let scale = 1.0 - element.distance;
+        let stretch = if element.shape == FlareShape::Streak { STREAK_LENGTH } else { 1.0 };
+        let color = [
+            flare.color[0] * element.color[0] * flare.intensity,
+            flare.color[1] * element.color[1] * flare.intensity,
+            flare.color[2] * element.color[2] * flare.intensity,
+            element.color[3],
+        ];
+        sprites.push(FlareSprite {
+            center: [ndc_x * scale, ndc_y * scale],
+            half_size: [element.size * h / w * stretch, element.size],
+            color,
+            light,
+            bounds,
+            shape: element.shape as u32,
+        });
+    }
+}
+
+// The depth is multisampled with MSAA; the shader reads its first sample either way.
+fn create_bind_group_layout(device: &wgpu::Device, samples: u32) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Lens Flare Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: samples > 1,
+            },
+            count: None,
+        }],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    let wgsl = include_str!("lens_flare.wgsl");
+    // The same shader reads multisampled depth and plain depth, like occlusion.rs.
+    let wgsl = if samples > 1 {
+        wgsl.to_string()
+    } else {
+        wgsl.replace("texture_depth_multisampled_2d", "texture_depth_2d")
+    };
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Lens Flare Shader"),
+        source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Lens Flare Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Lens Flare Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[FlareSprite::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                // Light adds up, and leaves the alpha of the scene alone.
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+fn create_sprite_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Lens Flare Sprite Buffer"),
+        size: (capacity * std::mem::size_of::<FlareSprite>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,83 @@
+// Lens flare sprites: quads around points of the camera's viewport, in normalized device
+// coordinates, faded by how much of their light the depth buffer shows. See lens_flare.rs.
+
+[[group(0), binding(0)]]
+var depth: texture_depth_multisampled_2d;
+
+struct SpriteInput {
+    [[location(0)]] center: vec2<f32>;
+    [[location(1)]] half_size: vec2<f32>;
+    [[location(2)]] color: vec4<f32>;
+    [[location(3)]] light: vec4<f32>; // In the depth texture: pixel x and y, depth, occlusion radius in pixels.
+    [[location(4)]] bounds: vec4<f32>; // Of the viewport in pixels: min x and y, max x and y.
+    [[location(5)]] shape: u32;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>; // From -1 to 1 across the sprite.
+    [[location(1)]] color: vec4<f32>;
+    [[location(2), interpolate(flat)]] shape: u32;
+};
+
+// The fraction of a 5x5 grid of depth samples around the light that is behind it. Samples outside
+// the viewport count as hidden, so flares fade out at its edges.
+fn visibility(light: vec4<f32>, bounds: vec4<f32>) -> f32 {
+    var visible = 0.0;
+    for (var y = -2; y <= 2; y = y + 1) {
+        for (var x = -2; x <= 2; x = x + 1) {
+            let p = light.xy + vec2<f32>(f32(x), f32(y)) * light.w * 0.5;
+            if (p.x >= bounds.x && p.y >= bounds.y && p.x < bounds.z && p.y < bounds.w) {
+                if (light.z <= textureLoad(depth, vec2<i32>(p), 0)) {
+                    visible = visible + 1.0;
+                }
+            }
+        }
+    }
+    return visible / 25.0;
+}
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32, sprite: SpriteInput) -> VertexOutput {
+    // Two triangles: (-1, -1) (1, -1) (-1, 1) and (-1, 1) (1, -1) (1, 1).
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[index];
+    let visible = visibility(sprite.light, sprite.bounds);
+
+    var out: VertexOutput;
+    // Hidden sprites collapse to a point and cost no fragments.
+    out.clip_position = vec4<f32>(sprite.center + corner * sprite.half_size * sign(visible), 0.0, 1.0);
+    out.uv = corner;
+    out.color = vec4<f32>(sprite.color.rgb * sprite.color.a * visible, 1.0);
+    out.shape = sprite.shape;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let r = length(in.uv);
+    var intensity = 0.0;
+    switch (in.shape) {
+        // Glow: bright in the center, falling off quickly.
+        case 0u: {
+            intensity = pow(max(1.0 - r, 0.0), 4.0);
+        }
+        // Ring: a thin band near the edge.
+        case 1u: {
+            intensity = 1.0 - smoothStep(0.0, 0.1, abs(r - 0.85));
+        }
+        // Disc: flat with a soft edge, like the ghost of an aperture.
+        case 2u: {
+            intensity = 0.5 * (1.0 - smoothStep(0.7, 1.0, r));
+        }
+        // Streak: a glow stretched along x by its size, thinner towards the ends.
+        default: {
+            intensity = pow(max(1.0 - r, 0.0), 2.0) * (1.0 - abs(in.uv.x));
+        }
+    }
+    // Added to the scene, see the blend state in lens_flare.rs.
+    return vec4<f32>(in.color.rgb * intensity, 0.0);
+}
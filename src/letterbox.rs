@@ -0,0 +1,219 @@
+use crate::device_cache::DeviceCache;
+
+// Virtual resolution
+//======================
+// By default the scene is rendered at the size of the window. With a virtual resolution it is
+// rendered at a fixed size into a texture of its own, which is then scaled to the window, with
+// black bars above and below (letterbox) or left and right (pillarbox) where the aspect ratios
+// differ. Pixel art stays pixel art at any window size, and 3D scenes can be rendered at a lower
+// resolution than the window:
+//
+//     gfx.set_virtual_resolution(Some(VirtualResolution::new(320, 180).with_integer_scale(true)));
+//
+// Everything drawn by the GFX uses the virtual resolution: viewports, the text overlay, and the
+// frame handed to the render graph nodes after the scene. Window coordinates, e.g. of the mouse,
+// are converted with `GFX::window_to_virtual`.
+
+/// A fixed size to render at, and how it is scaled to the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VirtualResolution {
+    pub width: u32,
+    pub height: u32,
+    // `Nearest` for crisp pixels, `Linear` for smooth scaling.
+    pub filter: wgpu::FilterMode,
+    // Scale by whole numbers only, so every virtual pixel covers the same number of window pixels.
+    // Windows smaller than the virtual resolution still scale it down to fit.
+    pub integer_scale: bool,
+}
+
+impl VirtualResolution {
+    pub fn new(width: u32, height: u32) -> VirtualResolution {
+        VirtualResolution {
+            width: width.max(1),
+            height: height.max(1),
+            filter: wgpu::FilterMode::Nearest,
+            integer_scale: false,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: wgpu::FilterMode) -> VirtualResolution {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_integer_scale(mut self, integer_scale: bool) -> VirtualResolution {
+        self.integer_scale = integer_scale;
+        self
+    }
+
+    // The region of a `width` x `height` window the image is scaled into, as (x, y, width, height)
+    // in pixels: as large as it fits while keeping its aspect ratio, centered between the bars.
+    pub fn viewport(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let mut scale = (width as f32 / self.width as f32).min(height as f32 / self.height as f32);
+        if self.integer_scale && scale >= 1.0 {
+            scale = scale.floor();
+        }
+        let w = ((self.width as f32 * scale) as u32).clamp(1, width.max(1));
+        let h = ((self.height as f32 * scale) as u32).clamp(1, height.max(1));
+        (width.saturating_sub(w) / 2, height.saturating_sub(h) / 2, w, h)
+    }
+
+    // Converts a position in a `width` x `height` window to virtual pixels. `None` on the bars.
+    pub fn window_to_virtual(&self, width: u32, height: u32, x: f32, y: f32) -> Option<(f32, f32)> {
+        let (vx, vy, w, h) = self.viewport(width, height);
+        let u = (x - vx as f32) / w as f32;
+        let v = (y - vy as f32) / h as f32;
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+        Some((u * self.width as f32, v * self.height as f32))
+    }
+}
+
+/// The texture the scene is rendered into at the virtual resolution, and the pass scaling it to
+/// the window.
+pub struct Letterbox {
+    resolution: VirtualResolution,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Letterbox {
+    // `format` is the format of the surface, which the virtual frame shares so the pipelines
+    // drawing the scene work with either.
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &DeviceCache,
+        format: wgpu::TextureFormat,
+        resolution: VirtualResolution,
+    ) -> Letterbox {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Virtual Frame"),
+            size: wgpu::Extent3d {
+                width: resolution.width,
+                height: resolution.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = cache.sampler(device, &wgpu::SamplerDescriptor {
+            label: Some("Virtual Frame Sampler"),
+            mag_filter: resolution.filter,
+            min_filter: resolution.filter,
+            ..Default::default()
+        });
+
+        let bind_group_layout = cache.bind_group_layout(device, Some("Letterbox Bind Group Layout"), &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Letterbox Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Letterbox Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("letterbox.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Letterbox Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Letterbox Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Letterbox {
+            resolution,
+            texture,
+            view,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn resolution(&self) -> VirtualResolution {
+        self.resolution
+    }
+
+    // Copied from by `GFX::read_frame`.
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    // The virtual frame, rendered into instead of the surface.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // Scales the virtual frame into `target`, a `width` x `height` window, and paints the bars.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, width: u32, height: u32) {
+        let (x, y, w, h) = self.resolution.viewport(width, height);
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Letterbox Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_viewport(x as f32, y as f32, w as f32, h as f32, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
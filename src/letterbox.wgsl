@@ -0,0 +1,28 @@
+// Scales the image rendered at the virtual resolution to the viewport of the window it is shown
+// in, see letterbox.rs.
+
+[[group(0), binding(0)]]
+var t_frame: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_frame: sampler;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    // A triangle over the whole viewport: (-1, -1), (3, -1) and (-1, 3).
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // Texture coordinates have y pointing down.
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(t_frame, s_frame, in.uv);
+}
@@ -0,0 +1,138 @@
+#[macro_use]
+pub mod error;
+#[macro_use]
+pub mod uniform;
+pub mod adapters;
+pub mod app;
+pub mod assets;
+pub mod bloom;
+pub mod boids;
+pub mod bounds;
+pub mod bvh;
+pub mod camera;
+pub mod camera_path;
+pub mod capabilities;
+pub mod clear;
+pub mod clouds;
+pub mod cli;
+pub mod compute;
+pub mod compute_kernels;
+pub mod config;
+pub mod crash;
+pub mod debug_draw;
+pub mod device_cache;
+pub mod dynamic_mesh;
+pub mod exposure;
+pub mod fluid;
+pub mod frames;
+pub mod game;
+pub mod jobs;
+pub mod gfx;
+pub mod gfx_context;
+pub mod gizmo;
+pub mod golden;
+pub mod gpu_particles;
+pub mod gpu_timer;
+pub mod grid;
+pub mod id_buffer;
+pub mod keyboard;
+pub mod latency;
+pub mod layers;
+pub mod lens_flare;
+pub mod light;
+pub mod light_cookies;
+pub mod letterbox;
+pub mod life;
+pub mod limiter;
+pub mod loader;
+pub mod local_lights;
+pub mod logging;
+pub mod marching_cubes;
+pub mod material;
+pub mod metrics;
+pub mod mirrors;
+pub mod mesh;
+pub mod mouse;
+pub mod nbody;
+pub mod nine_slice;
+pub mod noise;
+pub mod occlusion;
+pub mod occlusion_queries;
+pub mod overrides;
+pub mod packing;
+pub mod particles;
+pub mod panic;
+pub mod platform;
+pub mod power;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod reaction_diffusion;
+pub mod readback;
+pub mod reflection;
+pub mod reflection_probes;
+pub mod replay;
+pub mod render_graph;
+pub mod render_scale;
+pub mod render_targets;
+#[cfg(feature = "renderdoc")]
+pub mod renderdoc;
+pub mod rng;
+pub mod scene;
+pub mod scripting;
+pub mod selection;
+#[cfg(feature = "settings_ui")]
+pub mod settings_ui;
+pub mod shader_import;
+pub mod shadow_atlas;
+pub mod skeleton;
+pub mod skinning;
+pub mod sky;
+pub mod spline;
+pub mod stats;
+pub mod streaming;
+pub mod synthetic;
+pub mod text;
+pub mod texture;
+pub mod tiled;
+pub mod tilemap;
+pub mod time;
+pub mod transform;
+pub mod ui;
+pub mod variants;
+pub mod vertex_layout;
+pub mod volumetrics;
+pub mod win32_common;
+pub mod window;
+#[cfg(feature = "winit")]
+pub mod winit_window;
+pub mod world_time;
+#[cfg(target_os = "linux")]
+pub mod x11_window;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl Vertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x3,
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                },
+            ],
+        }
+    }
+}
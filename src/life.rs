@@ -0,0 +1,606 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+
+use cgmath::Vector2;
+use windows::Win32::UI::Input::KeyboardAndMouse::{VK_ADD, VK_OEM_MINUS, VK_OEM_PLUS, VK_SPACE, VK_SUBTRACT};
+
+use crate::compute::ComputeKernel;
+use crate::game::{Event, MouseButton};
+use crate::gfx::GFX;
+use crate::reflection::ShaderReflection;
+use crate::render_graph::{NodeContext, RenderNode, Stage};
+use crate::rng::Rng;
+use crate::uniform::UniformLayout;
+
+// Cellular automata
+//======================
+// Conway's Game of Life and other life-like rules, stepped on the GPU and drawn over the frame:
+//
+//     learn-wgpu --life 320x180 --life-rule highlife --life-rate 20
+//
+// The grid lives in two `r32uint` textures of the render graph, "life.cells.0" and
+// "life.cells.1". A step reads one and writes the next generation into the other (life.wgsl),
+// so they take turns being the current one. Every frame, before the scene, `LifeSimulation` runs
+// the steps that are due and `LifeDraw` fills the frame with the current generation afterwards,
+// scaled to fit with square cells.
+//
+// The game drives it through `Life`, which advances at `steps_per_second` from `update` and
+// forwards edits to the nodes. Holding the left mouse button paints live cells under the cursor,
+// the right one erases them. Keys:
+//
+//     Space   pause and resume
+//     N       one step, while paused
+//     + / -   double or halve the rate
+//     R       the next of the built-in rules
+//     C       clear the grid, and G fills it at random again
+
+pub const CELLS: [&str; 2] = ["life.cells.0", "life.cells.1"];
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+
+/// More steps than this in a frame are dropped, so a slow frame doesn't make the next one slower.
+const MAX_STEPS_PER_FRAME: u32 = 16;
+
+// Rules
+//======================
+
+/// A life-like rule: which counts of live neighbors (0 to 8) bring a dead cell to life, and which
+/// keep a live one alive, as bit masks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LifeRule {
+    pub birth: u32,
+    pub survival: u32,
+}
+
+impl LifeRule {
+    pub const CONWAY: LifeRule = LifeRule::new(&[3], &[2, 3]);
+    pub const HIGH_LIFE: LifeRule = LifeRule::new(&[3, 6], &[2, 3]);
+    pub const SEEDS: LifeRule = LifeRule::new(&[2], &[]);
+    pub const DAY_AND_NIGHT: LifeRule = LifeRule::new(&[3, 6, 7, 8], &[3, 4, 6, 7, 8]);
+
+    /// The rules `R` cycles through, with their names.
+    pub const BUILT_IN: [(&'static str, LifeRule); 4] = [
+        ("conway", LifeRule::CONWAY),
+        ("highlife", LifeRule::HIGH_LIFE),
+        ("seeds", LifeRule::SEEDS),
+        ("daynight", LifeRule::DAY_AND_NIGHT),
+    ];
+
+    pub const fn new(birth: &[u32], survival: &[u32]) -> LifeRule {
+        LifeRule {
+            birth: mask(birth),
+            survival: mask(survival),
+        }
+    }
+
+    // A built-in rule by name, or one in the B/S notation, e.g. "B36/S23" for HighLife.
+    pub fn parse(text: &str) -> Option<LifeRule> {
+        let text = text.to_ascii_lowercase();
+        if let Some((_, rule)) = LifeRule::BUILT_IN.iter().find(|(name, _)| *name == text) {
+            return Some(*rule);
+        }
+        let (birth, survival) = text.split_once('/')?;
+        let counts = |digits: &str| {
+            digits.chars().try_fold(0, |mask, c| match c.to_digit(10) {
+                Some(count) if count <= 8 => Some(mask | 1 << count),
+                _ => None,
+            })
+        };
+        Some(LifeRule {
+            birth: counts(birth.strip_prefix('b')?)?,
+            survival: counts(survival.strip_prefix('s')?)?,
+        })
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        LifeRule::BUILT_IN.iter().find(|(_, rule)| rule == self).map(|(name, _)| *name)
+    }
+}
+
+const fn mask(counts: &[u32]) -> u32 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < counts.len() {
+        mask |= 1 << counts[i];
+        i += 1;
+    }
+    mask
+}
+
+// In the B/S notation.
+impl fmt::Display for LifeRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = |mask: u32| (0..=8).filter(|count| mask & 1 << count != 0).map(|count| count.to_string()).collect::<String>();
+        write!(f, "B{}/S{}", counts(self.birth), counts(self.survival))
+    }
+}
+
+// Life API
+//======================
+
+/// See above.
+#[derive(Clone, Copy, Debug)]
+pub struct LifeSettings {
+    pub width: u32, // Of the grid, in cells.
+    pub height: u32,
+    pub rule: LifeRule,
+    pub steps_per_second: f32,
+    pub density: f32, // Of live cells at the start and after G.
+    pub brush_radius: u32, // In cells.
+    pub seed: u64,
+}
+
+impl Default for LifeSettings {
+    fn default() -> Self {
+        LifeSettings {
+            width: 320,
+            height: 180,
+            rule: LifeRule::CONWAY,
+            steps_per_second: 10.0,
+            density: 0.25,
+            brush_radius: 2,
+            seed: 1,
+        }
+    }
+}
+
+// What `Life` asks the nodes to do at the next frame.
+#[derive(Default)]
+struct Shared {
+    rule: Option<LifeRule>, // Changed since the last frame.
+    steps: u32,
+    current: usize, // Index in `CELLS` of the latest generation.
+    paints: Vec<(u32, u32, bool)>, // Brush centers in cells, and whether they paint live cells.
+    fill: Option<f32>, // Replace all cells with live ones at this density, 0 clears.
+}
+
+/// The automaton, as seen from the game, see above.
+pub struct Life {
+    settings: LifeSettings,
+    shared: Rc<RefCell<Shared>>,
+    paused: bool,
+    accumulator: f32, // The fraction of a step due but not run yet.
+    cursor: Option<(f32, f32)>, // In window pixels.
+    brush: Option<bool>, // While a mouse button is held, whether it paints live cells.
+}
+
+impl Life {
+    // Adds the nodes to the render graph, which fill the grid at random on their first frame.
+    pub fn new(gfx: &mut GFX, settings: LifeSettings) -> Life {
+        tracing::info!("Life: {}x{} cells, rule {}", settings.width, settings.height, settings.rule);
+        let shared = Rc::new(RefCell::new(Shared {
+            rule: Some(settings.rule),
+            fill: Some(settings.density),
+            ..Default::default()
+        }));
+        gfx.add_render_node(Box::new(LifeSimulation::new(settings, shared.clone())));
+        gfx.add_render_node(Box::new(LifeDraw::new(settings, shared.clone())));
+        Life {
+            settings,
+            shared,
+            paused: false,
+            accumulator: 0.0,
+            cursor: None,
+            brush: None,
+        }
+    }
+
+    pub fn rule(&self) -> LifeRule {
+        self.settings.rule
+    }
+
+    pub fn set_rule(&mut self, rule: LifeRule) {
+        self.settings.rule = rule;
+        self.shared.borrow_mut().rule = Some(rule);
+    }
+
+    pub fn steps_per_second(&self) -> f32 {
+        self.settings.steps_per_second
+    }
+
+    pub fn set_steps_per_second(&mut self, steps_per_second: f32) {
+        self.settings.steps_per_second = steps_per_second.clamp(0.25, 1000.0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.accumulator = 0.0;
+    }
+
+    // Runs one generation at the next frame, whether paused or not.
+    pub fn step(&mut self) {
+        self.shared.borrow_mut().steps += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.shared.borrow_mut().fill = Some(0.0);
+    }
+
+    pub fn randomize(&mut self, density: f32) {
+        self.shared.borrow_mut().fill = Some(density.clamp(0.0, 1.0));
+    }
+
+    // Makes the cells within the brush radius of (x, y) alive or dead at the next frame.
+    pub fn paint(&mut self, x: u32, y: u32, alive: bool) {
+        self.shared.borrow_mut().paints.push((x, y, alive));
+    }
+
+    // The cell under a window position, e.g. of the mouse. `None` outside the grid.
+    pub fn cell_at(&self, gfx: &GFX, x: f32, y: f32) -> Option<(u32, u32)> {
+        let (x, y) = gfx.window_to_virtual(x, y)?;
+        let (offset, cell_size) = fit(gfx.render_size(), (self.settings.width, self.settings.height));
+        let (column, row) = ((x - offset.x) / cell_size, (y - offset.y) / cell_size);
+        if column < 0.0 || row < 0.0 || column >= self.settings.width as f32 || row >= self.settings.height as f32 {
+            return None;
+        }
+        Some((column as u32, row as u32))
+    }
+
+    // Call from `Game::update`: schedules the steps due in `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        if self.paused {
+            return;
+        }
+        self.accumulator += dt * self.settings.steps_per_second;
+        let steps = self.accumulator.floor();
+        self.accumulator -= steps;
+        let mut shared = self.shared.borrow_mut();
+        shared.steps = (shared.steps + steps as u32).min(MAX_STEPS_PER_FRAME);
+    }
+
+    // Call from `Game::on_event`, for the mouse and keys above.
+    pub fn on_event(&mut self, event: &Event) {
+        match *event {
+            Event::MouseMoved { x, y } => self.cursor = Some((x as f32, y as f32)),
+            Event::MousePressed(button) => self.brush = Some(button == MouseButton::Left),
+            Event::MouseReleased(_) | Event::FocusLost => self.brush = None,
+            Event::KeyPressed(key) => match key {
+                VK_SPACE => self.set_paused(!self.paused),
+                VK_ADD | VK_OEM_PLUS => self.set_steps_per_second(self.settings.steps_per_second * 2.0),
+                VK_SUBTRACT | VK_OEM_MINUS => self.set_steps_per_second(self.settings.steps_per_second * 0.5),
+                key if key == b'N' as u16 && self.paused => self.step(),
+                key if key == b'R' as u16 => {
+                    let built_in = &LifeRule::BUILT_IN;
+                    let next = built_in.iter().position(|(_, rule)| *rule == self.settings.rule).map_or(0, |i| (i + 1) % built_in.len());
+                    tracing::info!("Life rule: {} ({})", built_in[next].0, built_in[next].1);
+                    self.set_rule(built_in[next].1);
+                }
+                key if key == b'C' as u16 => self.clear(),
+                key if key == b'G' as u16 => self.randomize(self.settings.density),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // Call from `Game::render`: paints under the cursor while a mouse button is held.
+    pub fn render(&mut self, gfx: &GFX) {
+        if let (Some(alive), Some((x, y))) = (self.brush, self.cursor) {
+            if let Some((column, row)) = self.cell_at(gfx, x, y) {
+                self.paint(column, row, alive);
+            }
+        }
+    }
+}
+
+// Where the grid goes in a frame of `frame` pixels: centered, as large as fits with square cells.
+// Returns the offset of its top left corner and the size of a cell, in pixels.
+fn fit(frame: (u32, u32), grid: (u32, u32)) -> (Vector2<f32>, f32) {
+    let cell_size = (frame.0 as f32 / grid.0 as f32).min(frame.1 as f32 / grid.1 as f32);
+    let offset = Vector2::new(
+        (frame.0 as f32 - grid.0 as f32 * cell_size) * 0.5,
+        (frame.1 as f32 - grid.1 as f32 * cell_size) * 0.5,
+    );
+    (offset, cell_size)
+}
+
+// Simulation
+//======================
+
+uniform_struct! {
+    struct StepParams {
+        birth: u32,
+        survival: u32,
+    }
+}
+assert_uniform_size!(StepParams, 8);
+
+// The kernel, with a bind group for each direction between the textures, created on the first run.
+struct Kernel {
+    kernel: ComputeKernel,
+    params: wgpu::Buffer,
+    bind_groups: [wgpu::BindGroup; 2], // From `CELLS[i]` to the other one.
+}
+
+/// Applies the edits and runs the steps `Life` asked for, before the scene, see above.
+pub struct LifeSimulation {
+    settings: LifeSettings,
+    shared: Rc<RefCell<Shared>>,
+    rng: Rng,
+    kernel: Option<Kernel>,
+}
+
+impl LifeSimulation {
+    fn new(settings: LifeSettings, shared: Rc<RefCell<Shared>>) -> LifeSimulation {
+        LifeSimulation {
+            settings,
+            shared,
+            rng: Rng::new(settings.seed),
+            kernel: None,
+        }
+    }
+
+    fn create_kernel(&self, ctx: &mut NodeContext) -> Kernel {
+        let (width, height) = (self.settings.width, self.settings.height);
+        for name in CELLS {
+            ctx.resources.ensure_texture(ctx.device, name, width, height, FORMAT);
+        }
+        // The shader is part of the crate, so failing to build it is a bug.
+        let kernel = ComputeKernel::new(ctx.device, "Life Step", include_str!("life.wgsl"), "step").expect("built-in kernel is valid");
+        let params = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Life Step Params"),
+            size: StepParams::SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = |from: &str, to: &str| {
+            let entries = [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&ctx.resources.texture(from).unwrap().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&ctx.resources.texture(to).unwrap().view),
+                },
+            ];
+            kernel.bind_group(ctx.device, 0, &entries).expect("life textures match the kernel")
+        };
+        let bind_groups = [bind_group(CELLS[0], CELLS[1]), bind_group(CELLS[1], CELLS[0])];
+        Kernel {
+            kernel,
+            params,
+            bind_groups,
+        }
+    }
+
+    // Writes `rows` rows of `cells` into the current texture, from (column, row).
+    fn write_cells(&self, ctx: &NodeContext, current: usize, column: u32, row: u32, cells: &[u32], rows: u32) {
+        let texture = &ctx.resources.texture(CELLS[current]).unwrap().texture;
+        let width = cells.len() as u32 / rows;
+        ctx.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: column, y: row, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(cells),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * width),
+                rows_per_image: NonZeroU32::new(rows),
+            },
+            wgpu::Extent3d {
+                width,
+                height: rows,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+impl RenderNode for LifeSimulation {
+    fn name(&self) -> &str {
+        "Life Simulation"
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::BeforeScene
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        CELLS.to_vec()
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        if self.kernel.is_none() {
+            self.kernel = Some(self.create_kernel(ctx));
+        }
+        let shared = self.shared.clone();
+        let mut shared = shared.borrow_mut();
+        let (width, height) = (self.settings.width, self.settings.height);
+
+        // Queue writes land before the steps of this frame, so they edit the generation the
+        // first step reads.
+        if let Some(density) = shared.fill.take() {
+            let cells: Vec<u32> = (0..width * height).map(|_| (self.rng.next_f32() < density) as u32).collect();
+            self.write_cells(ctx, shared.current, 0, 0, &cells, height);
+        }
+        let radius = self.settings.brush_radius as i32;
+        for (x, y, alive) in std::mem::take(&mut shared.paints) {
+            // One row of the disk at a time, cut at the edges of the grid.
+            for dy in -radius..=radius {
+                let row = y as i32 + dy;
+                if row < 0 || row >= height as i32 {
+                    continue;
+                }
+                let half = ((radius * radius - dy * dy) as f32).sqrt() as i32;
+                let (first, last) = ((x as i32 - half).max(0), (x as i32 + half).min(width as i32 - 1));
+                if first <= last {
+                    let cells = vec![alive as u32; (last - first + 1) as usize];
+                    self.write_cells(ctx, shared.current, first as u32, row as u32, &cells, 1);
+                }
+            }
+        }
+
+        let kernel = self.kernel.as_ref().unwrap();
+        if let Some(rule) = shared.rule.take() {
+            let params = StepParams {
+                birth: rule.birth,
+                survival: rule.survival,
+            };
+            ctx.queue.write_buffer(&kernel.params, 0, &params.to_uniform_bytes());
+        }
+        for _ in 0..std::mem::take(&mut shared.steps) {
+            let bind_group = &kernel.bind_groups[shared.current];
+            kernel.kernel.dispatch_2d(encoder, &[bind_group], WORKGROUP_SIZE, width, height);
+            shared.current = 1 - shared.current;
+        }
+    }
+}
+
+// Drawing
+//======================
+
+uniform_struct! {
+    struct DrawParams {
+        offset: Vector2<f32>,
+        cell_size: f32,
+    }
+}
+assert_uniform_size!(DrawParams, 16);
+
+// The pipeline for the frame's format, and a bind group for each texture.
+struct DrawState {
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    params: wgpu::Buffer,
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// Draws the current generation over the frame, see above.
+pub struct LifeDraw {
+    settings: LifeSettings,
+    shared: Rc<RefCell<Shared>>,
+    state: Option<DrawState>,
+}
+
+impl LifeDraw {
+    fn new(settings: LifeSettings, shared: Rc<RefCell<Shared>>) -> LifeDraw {
+        LifeDraw {
+            settings,
+            shared,
+            state: None,
+        }
+    }
+
+    fn create_state(ctx: &NodeContext, format: wgpu::TextureFormat) -> DrawState {
+        let device = ctx.device;
+        let wgsl = include_str!("life_draw.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("Life Draw"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Life Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Life Draw Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Life Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Life Draw Params"),
+            size: DrawParams::SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = |name: &str| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Life Draw Bind Group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&ctx.resources.texture(name).unwrap().view),
+                    },
+                ],
+            })
+        };
+        let bind_groups = [bind_group(CELLS[0]), bind_group(CELLS[1])];
+        DrawState {
+            pipeline,
+            format,
+            params,
+            bind_groups,
+        }
+    }
+}
+
+impl RenderNode for LifeDraw {
+    fn name(&self) -> &str {
+        "Life"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        CELLS.to_vec()
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let view = match ctx.frame.view {
+            Some(view) if CELLS.iter().all(|name| ctx.resources.texture(name).is_some()) => view,
+            _ => return,
+        };
+        let format = ctx.frame.format;
+        if self.state.as_ref().is_none_or(|state| state.format != format) {
+            self.state = Some(Self::create_state(ctx, format));
+        }
+        let state = self.state.as_ref().unwrap();
+
+        let (offset, cell_size) = fit(ctx.frame.size, (self.settings.width, self.settings.height));
+        ctx.queue.write_buffer(&state.params, 0, &DrawParams { offset, cell_size }.to_uniform_bytes());
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Life Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&state.pipeline);
+        render_pass.set_bind_group(0, &state.bind_groups[self.shared.borrow().current], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
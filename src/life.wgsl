@@ -0,0 +1,45 @@
+// One generation of a life-like cellular automaton, see life.rs.
+//
+// A cell holds its age: 0 when dead, the number of generations it has lived otherwise (up to 255),
+// which the visualization colors by. The grid wraps around at the edges.
+
+struct Params {
+    birth: u32; // Bit n set: a dead cell with n live neighbors comes alive.
+    survival: u32; // Bit n set: a live cell with n live neighbors stays alive.
+};
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var cells: texture_2d<u32>;
+[[group(0), binding(2)]]
+var next: texture_storage_2d<r32uint, write>;
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn step([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(cells);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    var neighbors = 0u;
+    for (var dy: i32 = -1; dy <= 1; dy = dy + 1) {
+        for (var dx: i32 = -1; dx <= 1; dx = dx + 1) {
+            let neighbor = (pos + vec2<i32>(dx, dy) + size) % size;
+            if ((dx != 0 || dy != 0) && textureLoad(cells, neighbor, 0).r > 0u) {
+                neighbors = neighbors + 1u;
+            }
+        }
+    }
+
+    let age = textureLoad(cells, pos, 0).r;
+    var next_age = 0u;
+    if (age > 0u) {
+        if ((params.survival & (1u << neighbors)) != 0u) {
+            next_age = min(age + 1u, 255u);
+        }
+    } else if ((params.birth & (1u << neighbors)) != 0u) {
+        next_age = 1u;
+    }
+    textureStore(next, pos, vec4<u32>(next_age, 0u, 0u, 0u));
+}
@@ -0,0 +1,33 @@
+// Draws the cells of the automaton over the whole frame, see life.rs.
+
+struct DrawParams {
+    offset: vec2<f32>; // Of the grid in the frame, in pixels.
+    cell_size: f32; // In pixels.
+};
+[[group(0), binding(0)]]
+var<uniform> params: DrawParams;
+[[group(0), binding(1)]]
+var cells: texture_2d<u32>;
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    // A triangle over the whole viewport: (-1, -1), (3, -1) and (-1, 3).
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let size = textureDimensions(cells);
+    let cell = vec2<i32>(floor((position.xy - params.offset) / params.cell_size));
+    if (cell.x < 0 || cell.y < 0 || cell.x >= size.x || cell.y >= size.y) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let age = textureLoad(cells, cell, 0).r;
+    if (age == 0u) {
+        return vec4<f32>(0.03, 0.03, 0.05, 1.0);
+    }
+    // Newborn cells are yellow and turn blue as they get older.
+    let t = min(f32(age - 1u) / 32.0, 1.0);
+    return vec4<f32>(mix(vec3<f32>(1.0, 0.85, 0.3), vec3<f32>(0.15, 0.35, 1.0), t), 1.0);
+}
@@ -0,0 +1,55 @@
+use cgmath::{InnerSpace, Vector3};
+
+// Directional light
+//======================
+// The light of the sun: parallel rays from one direction, the same everywhere in the scene, plus
+// an ambient color that lights everything from all directions. It is part of the globals every
+// camera binds (time.rs), and the built-in shader (shader.wgsl) lights meshes with normals by it:
+//
+//     gfx.set_light(DirectionalLight::new(Vector3::new(-0.3, 1.0, 0.2), [1.0, 0.9, 0.8]));
+//
+// The default is the light the built-in shader always had: white, from above and a little to the
+// side, with enough ambient light that the sides facing away still read. With a sky, its sun
+// decides the light instead, see sky.rs.
+
+/// The scene's sun, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>, // Towards the light.
+    pub color: [f32; 3], // Times the intensity.
+    pub ambient: [f32; 3],
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vector3<f32>, color: [f32; 3]) -> DirectionalLight {
+        DirectionalLight {
+            direction,
+            color,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_ambient(mut self, ambient: [f32; 3]) -> DirectionalLight {
+        self.ambient = ambient;
+        self
+    }
+
+    // The direction, normalized; straight up if it has no length.
+    pub fn normalized_direction(&self) -> Vector3<f32> {
+        if self.direction.magnitude2() > 0.0 {
+            self.direction.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        DirectionalLight {
+            direction: Vector3::new(0.4, 1.0, 0.6),
+            color: [0.7, 0.7, 0.7],
+            ambient: [0.3, 0.3, 0.3],
+        }
+    }
+}
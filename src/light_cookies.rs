@@ -0,0 +1,116 @@
+use std::num::NonZeroU32;
+
+// Light cookies
+//======================
+// A cookie is an image a spot light shines through, like a slide in a projector or the shadow of
+// a window frame: the light's color is multiplied by the cookie where it falls, looked up with the
+// light's projection, the same as that of its shadow map (local_lights.rs):
+//
+//     let cookie = gfx.load_light_cookie("assets/window.png")?.expect("a free cookie");
+//     gfx.add_local_light(LocalLight::spot(position, direction, color, 10.0, 30.0).with_cookie(cookie));
+//
+// The cookie covers the square around the light's cone, as seen from the light with the top of
+// the image up, so the circle inside the square is what shows. Point lights ignore their cookie.
+//
+// Shaders can't index textures, so all cookies are layers of one texture array, scaled to
+// `COOKIE_RESOLUTION` when they are added. It is bound next to the local lights:
+//   group(0) binding(5)   texture_2d_array<f32>, a layer per cookie
+//   group(0) binding(6)   sampler
+
+pub const MAX_COOKIES: usize = 8;
+// The width and height of every layer, in pixels.
+pub const COOKIE_RESOLUTION: u32 = 256;
+
+/// Identifies a cookie added with `GFX::add_light_cookie`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CookieId(pub(crate) usize);
+
+/// The cookies of the local lights, see above.
+pub struct LightCookies {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    used: [bool; MAX_COOKIES], // By layer, which is the id.
+}
+
+impl LightCookies {
+    pub fn new(device: &wgpu::Device) -> LightCookies {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Light Cookies"),
+            size: wgpu::Extent3d {
+                width: COOKIE_RESOLUTION,
+                height: COOKIE_RESOLUTION,
+                depth_or_array_layers: MAX_COOKIES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Light Cookies View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Beyond the edges, the edge, which is outside the cone anyway.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Light Cookie Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        LightCookies {
+            texture,
+            view,
+            sampler,
+            used: [false; MAX_COOKIES],
+        }
+    }
+
+    // Takes the first free layer for tightly packed 8-bit RGBA pixels (sRGB encoded), scaled to
+    // the layer's size. `None` if all MAX_COOKIES are taken.
+    pub fn add(&mut self, queue: &wgpu::Queue, width: u32, height: u32, rgba: &[u8]) -> Option<CookieId> {
+        let layer = self.used.iter().position(|used| !used)?;
+        self.used[layer] = true;
+        let image = image::RgbaImage::from_raw(width, height, rgba.to_vec()).expect("pixels match the size");
+        let image = image::imageops::resize(&image, COOKIE_RESOLUTION, COOKIE_RESOLUTION, image::imageops::FilterType::Triangle);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer as u32,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &image,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * COOKIE_RESOLUTION),
+                rows_per_image: NonZeroU32::new(COOKIE_RESOLUTION),
+            },
+            wgpu::Extent3d {
+                width: COOKIE_RESOLUTION,
+                height: COOKIE_RESOLUTION,
+                depth_or_array_layers: 1,
+            },
+        );
+        Some(CookieId(layer))
+    }
+
+    // Frees the layer. Lights still using the cookie show the next one added in its place.
+    pub fn remove(&mut self, id: CookieId) {
+        self.used[id.0] = false;
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+}
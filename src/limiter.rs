@@ -0,0 +1,151 @@
+use std::time::{Duration, Instant};
+
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+#[cfg(windows)]
+use windows::Win32::Foundation::PWSTR;
+use windows::Win32::System::Threading::{SetWaitableTimer, WaitForSingleObject};
+#[cfg(windows)]
+use windows::Win32::System::Threading::{CreateWaitableTimerExW, CREATE_WAITABLE_TIMER_HIGH_RESOLUTION};
+
+// Frame rate limiter
+//======================
+// Caps the frame rate independently of vsync, e.g. to save battery, or for tools that run in the background.
+// Sleeping alone is not precise enough: a regular sleep easily oversleeps by a millisecond or more.
+// So the limiter sleeps on a high resolution waitable timer (Windows 10 1803 and later) until shortly
+// before the deadline, and spins for the rest.
+
+#[cfg(windows)]
+const TIMER_ALL_ACCESS: u32 = 0x1F0003;
+const INFINITE: u32 = u32::MAX;
+
+// How long before the deadline to stop sleeping and start spinning.
+const SPIN_MARGIN: Duration = Duration::from_micros(500);
+const SPIN_MARGIN_LOW_RESOLUTION: Duration = Duration::from_millis(2);
+
+pub struct FrameLimiter {
+    max_fps: Option<f32>,
+    timer: HANDLE, // Invalid if high resolution timers are not available.
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(max_fps: Option<f32>) -> FrameLimiter {
+        // Off Windows the timer stays invalid, and the limiter falls back to a regular sleep.
+        #[cfg(not(windows))]
+        let timer = HANDLE::default();
+        #[cfg(windows)]
+        let timer = unsafe {
+            CreateWaitableTimerExW(
+                std::ptr::null(),
+                PWSTR::default(),
+                CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+                TIMER_ALL_ACCESS,
+            )
+        };
+        FrameLimiter {
+            max_fps,
+            timer,
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn max_fps(&self) -> Option<f32> {
+        self.max_fps
+    }
+
+    // `None` removes the cap.
+    pub fn set_max_fps(&mut self, max_fps: Option<f32>) {
+        self.max_fps = max_fps.filter(|fps| *fps > 0.0);
+        self.next_frame = Instant::now();
+    }
+
+    // Waits until the next frame is due. Call once per frame.
+    pub fn wait(&mut self) {
+        let fps = match self.max_fps {
+            Some(fps) => fps,
+            None => return,
+        };
+        let deadline = self.next_frame;
+        let margin = if self.timer.is_invalid() {
+            SPIN_MARGIN_LOW_RESOLUTION
+        } else {
+            SPIN_MARGIN
+        };
+
+        let now = Instant::now();
+        if deadline > now + margin {
+            self.sleep(deadline - now - margin);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+
+        let frame_time = Duration::from_secs_f32(1.0 / fps);
+        self.next_frame = next_frame(deadline, Instant::now(), frame_time);
+    }
+
+    fn sleep(&self, duration: Duration) {
+        if self.timer.is_invalid() {
+            std::thread::sleep(duration);
+            return;
+        }
+        unsafe {
+            // Negative due times are relative, in 100 nanosecond units.
+            let due_time = -((duration.as_nanos() / 100) as i64);
+            if SetWaitableTimer(self.timer, &due_time, 0, None, std::ptr::null(), false).as_bool() {
+                WaitForSingleObject(self.timer, INFINITE);
+            } else {
+                std::thread::sleep(duration);
+            }
+        }
+    }
+}
+
+// Schedules the next frame from the deadline, so the frame rate doesn't drift.
+// After a frame that took too long, starts over from now instead of rushing to catch up.
+fn next_frame(deadline: Instant, now: Instant, frame_time: Duration) -> Instant {
+    if deadline + frame_time < now {
+        now + frame_time
+    } else {
+        deadline + frame_time
+    }
+}
+
+impl Drop for FrameLimiter {
+    fn drop(&mut self) {
+        if !self.timer.is_invalid() {
+            unsafe {
+                CloseHandle(self.timer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_TIME: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn frames_are_scheduled_from_the_deadline() {
+        let deadline = Instant::now();
+        let now = deadline + Duration::from_millis(3);
+        assert_eq!(next_frame(deadline, now, FRAME_TIME), deadline + FRAME_TIME);
+        // Too late for the next deadline: a frame from now, not right away.
+        let now = deadline + Duration::from_millis(25);
+        assert_eq!(next_frame(deadline, now, FRAME_TIME), now + FRAME_TIME);
+    }
+
+    #[test]
+    fn the_frame_after_an_overrun_is_paced() {
+        let mut limiter = FrameLimiter::new(Some(100.0));
+        limiter.wait();
+        std::thread::sleep(3 * FRAME_TIME); // A slow frame.
+        limiter.wait();
+        let start = Instant::now();
+        limiter.wait();
+        // Sleeps never return early, only late.
+        assert!(start.elapsed() >= FRAME_TIME - Duration::from_millis(1), "{:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::jobs;
+use crate::Vertex;
+
+// Background loading
+//======================
+// Decoding images and parsing models is slow, and only needs the CPU. `Loader` does it on the
+// job system (jobs.rs); the main thread drains the finished jobs with `finished` and uploads them to the GPU.
+// Nothing here touches wgpu, so the workers never contend with rendering for the device.
+
+pub enum Job {
+    Texture(PathBuf),
+    Mesh(PathBuf),
+}
+
+/// The CPU side of an asset, ready to be uploaded.
+pub enum Loaded {
+    Texture { width: u32, height: u32, pixels: Vec<u8> },
+    Mesh { vertices: Vec<Vertex>, indices: Vec<u16> },
+}
+
+pub struct Finished {
+    pub path: PathBuf,
+    pub result: Result<Loaded, String>,
+}
+
+pub struct Loader {
+    finished_tx: Sender<Finished>,
+    finished: Receiver<Finished>,
+    pending: usize,
+}
+
+impl Loader {
+    pub fn new() -> Loader {
+        let (finished_tx, finished) = channel();
+        Loader {
+            finished_tx,
+            finished,
+            pending: 0,
+        }
+    }
+
+    pub fn queue(&mut self, job: Job) {
+        let finished = self.finished_tx.clone();
+        jobs::global().spawn(move || {
+            let result = match job {
+                Job::Texture(path) => Finished {
+                    result: decode_image(&path),
+                    path,
+                },
+                Job::Mesh(path) => Finished {
+                    result: parse_obj(&path),
+                    path,
+                },
+            };
+            // Fails when the loader is gone, and the result with it.
+            let _ = finished.send(result);
+        });
+        self.pending += 1;
+    }
+
+    // The jobs finished since the last call.
+    pub fn finished(&mut self) -> Vec<Finished> {
+        let finished: Vec<Finished> = self.finished.try_iter().collect();
+        self.pending -= finished.len();
+        finished
+    }
+
+    // Number of jobs queued or running.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+impl Default for Loader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn decode_image(path: &Path) -> Result<Loaded, String> {
+    let image = image::open(path).map_err(|e| e.to_string())?.to_rgba8();
+    let (width, height) = image.dimensions();
+    Ok(Loaded::Texture {
+        width,
+        height,
+        pixels: image.into_raw(),
+    })
+}
+
+// Reads the positions and faces of a Wavefront OBJ file. Faces with more than three corners are
+// split into a fan of triangles. The optional color after a position (`v x y z r g b`) is used
+// as the vertex color, white otherwise. Normals and texture coordinates are not used yet.
+fn parse_obj(path: &Path) -> Result<Loaded, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let values: Vec<f32> = words
+                    .map(|word| word.parse::<f32>().map_err(|_| error("invalid number")))
+                    .collect::<Result<_, _>>()?;
+                let (position, color) = match values.len() {
+                    3 | 4 => ([values[0], values[1], values[2]], [1.0; 3]),
+                    6 => ([values[0], values[1], values[2]], [values[3], values[4], values[5]]),
+                    _ => return Err(error("expected 3 or 6 values")),
+                };
+                vertices.push(Vertex { position, color });
+            }
+            Some("f") => {
+                // Corners are `v`, `v/vt`, `v/vt/vn` or `v//vn`, 1-based or negative (relative to the end).
+                let corners: Vec<u16> = words
+                    .map(|word| {
+                        let index: i64 = word.split('/').next().unwrap().parse().map_err(|_| error("invalid index"))?;
+                        let index = if index < 0 { vertices.len() as i64 + index } else { index - 1 };
+                        if index < 0 || index as usize >= vertices.len() {
+                            return Err(error("index out of range"));
+                        }
+                        u16::try_from(index).map_err(|_| error("more than 65536 vertices"))
+                    })
+                    .collect::<Result<_, _>>()?;
+                if corners.len() < 3 {
+                    return Err(error("face with less than 3 corners"));
+                }
+                for i in 1..corners.len() - 1 {
+                    indices.extend_from_slice(&[corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {} // Comments, normals, texture coordinates, groups and materials.
+        }
+    }
+    Ok(Loaded::Mesh { vertices, indices })
+}
@@ -0,0 +1,374 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, MetricSpace, Point3, Vector3, Vector4};
+
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::layers::RenderLayers;
+use crate::light_cookies::{CookieId, LightCookies};
+use crate::shadow_atlas::{ShadowAtlas, ShadowRequest, MAX_SHADOW_VIEWS};
+use crate::uniform::UniformLayout;
+
+// Local lights
+//======================
+// Point and spot lights: light from a position, fading out with the square of the distance and
+// smoothly to nothing at `range`. A spot light lights a cone, fully within `inner_angle` of its
+// direction and fading out towards `outer_angle`:
+//
+//     let lamp = gfx.add_local_light(LocalLight::point(Point3::new(0.0, 2.0, 0.0), [1.0, 0.8, 0.6], 8.0));
+//     let material = Material::new(shader).with_defines(ShaderDefines::new().with_flag("LOCAL_LIGHTS", true));
+//
+// The built-in shader adds them to the directional light per pixel, for meshes with normals, when
+// `LOCAL_LIGHTS` is defined. Lights `with_shadows` render shadow maps into the shadow atlas, which
+// decides how large each map is, see shadow_atlas.rs. Spot lights `with_cookie` shine through an
+// image, see light_cookies.rs.
+//
+// They are bound next to every camera, after the globals (time.rs), so any shader can declare them:
+//   group(0) binding(2)   uniform: the number of lights, the lights, then the shadow maps
+//   group(0) binding(3)   texture_depth_2d, the shadow atlas
+//   group(0) binding(4)   sampler_comparison
+//   group(0) binding(5-6) the cookies, see light_cookies.rs
+// Up to MAX_LOCAL_LIGHTS lights; the lights of the uniform are in the order of their ids.
+
+pub const MAX_LOCAL_LIGHTS: usize = 16;
+
+/// Identifies a light added with `GFX::add_local_light`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LocalLightId(pub(crate) usize);
+
+/// Where a local light shines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightShape {
+    // In all directions.
+    Point,
+    // Within a cone around `direction`; the angles are from the direction, in degrees.
+    Spot {
+        direction: Vector3<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+/// A point or spot light, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LocalLight {
+    pub position: Point3<f32>,
+    pub shape: LightShape,
+    pub color: [f32; 3], // Times the intensity.
+    pub range: f32, // Beyond which it lights nothing.
+    pub shadows: bool,
+    pub shadow_priority: f32, // Relative to the other lights, see shadow_atlas.rs.
+    pub caster_layers: RenderLayers, // Only renderables on one of these layers cast shadows.
+    pub cookie: Option<CookieId>, // Of spot lights.
+}
+
+impl LocalLight {
+    pub fn point(position: Point3<f32>, color: [f32; 3], range: f32) -> LocalLight {
+        LocalLight {
+            position,
+            shape: LightShape::Point,
+            color,
+            range,
+            shadows: false,
+            shadow_priority: 1.0,
+            caster_layers: RenderLayers::DEFAULT | RenderLayers::SHADOW_CASTERS,
+            cookie: None,
+        }
+    }
+
+    // A cone of `angle` degrees from `direction`, fading out over its outer fifth.
+    pub fn spot(position: Point3<f32>, direction: Vector3<f32>, color: [f32; 3], range: f32, angle: f32) -> LocalLight {
+        LocalLight {
+            shape: LightShape::Spot {
+                direction,
+                inner_angle: angle * 0.8,
+                outer_angle: angle,
+            },
+            ..LocalLight::point(position, color, range)
+        }
+    }
+
+    // Casts shadows, with maps the larger the higher `priority` is.
+    pub fn with_shadows(mut self, priority: f32) -> LocalLight {
+        self.shadows = true;
+        self.shadow_priority = priority;
+        self
+    }
+
+    // Only renderables on one of `layers` cast its shadows, e.g. to leave out a window's glass.
+    pub fn with_caster_layers(mut self, layers: RenderLayers) -> LocalLight {
+        self.caster_layers = layers;
+        self
+    }
+
+    pub fn with_cookie(mut self, cookie: CookieId) -> LocalLight {
+        self.cookie = Some(cookie);
+        self
+    }
+
+    // The view projection matrices of the light's shadow maps: one for a spot light, and one per
+    // face of the cube for a point light, in the order +x, -x, +y, -y, +z, -z.
+    pub fn shadow_view_projs(&self) -> Vec<Matrix4<f32>> {
+        let near = (self.range * 0.01).max(0.01);
+        let far = self.range.max(near * 2.0);
+        match self.shape {
+            LightShape::Point => {
+                let proj = OPENGL_TO_WGPU_MATRIX * cgmath::perspective(cgmath::Deg(90.0), 1.0, near, far);
+                CUBE_FACES
+                    .iter()
+                    .map(|&(forward, up)| proj * Matrix4::look_to_rh(self.position, forward.into(), up.into()))
+                    .collect()
+            }
+            LightShape::Spot { direction, outer_angle, .. } => {
+                let direction = normalized_or(direction, -Vector3::unit_y());
+                let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+                let fovy = (2.0 * outer_angle).clamp(1.0, 170.0);
+                let proj = OPENGL_TO_WGPU_MATRIX * cgmath::perspective(cgmath::Deg(fovy), 1.0, near, far);
+                vec![proj * Matrix4::look_to_rh(self.position, direction, up)]
+            }
+        }
+    }
+}
+
+// The direction every face of a point light's cube looks in, and its up.
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+];
+
+fn normalized_or(v: Vector3<f32>, fallback: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        fallback
+    }
+}
+
+uniform_struct! {
+    // A light as the shaders see it.
+    #[derive(Clone, Copy)]
+    struct LightParams {
+        position: Vector4<f32>, // w is the range.
+        color: Vector4<f32>, // w is the index of the first shadow map, -1 without shadows.
+        direction: Vector4<f32>, // Of spot lights.
+        // The cosines of the outer and inner angle; z is 1 for point lights; w is the cookie, -1
+        // without one.
+        cone: Vector4<f32>,
+        projection: Matrix4<f32>, // The view projection of spot lights, which the cookie is looked up with.
+    }
+}
+
+uniform_struct! {
+    // A shadow map as the shaders see it.
+    #[derive(Clone, Copy)]
+    struct ShadowViewParams {
+        view_proj: Matrix4<f32>,
+        rect: Vector4<f32>, // In the atlas, in texture coordinates: the corner in xy, the size in zw.
+    }
+}
+
+uniform_struct! {
+    struct LocalLightsUniform {
+        count: u32,
+        lights: [LightParams; MAX_LOCAL_LIGHTS],
+        views: [ShadowViewParams; MAX_SHADOW_VIEWS],
+    }
+}
+assert_uniform_size!(LocalLightsUniform, 16 + 128 * MAX_LOCAL_LIGHTS + 80 * MAX_SHADOW_VIEWS);
+
+/// The local lights, their uniform buffer, their shadow atlas and their cookies.
+pub struct LocalLights {
+    slots: Vec<Option<LocalLight>>, // By id; `None` where removed.
+    atlas: ShadowAtlas,
+    cookies: LightCookies,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+}
+
+impl LocalLights {
+    // With an atlas of `atlas_size` pixels wide and high.
+    pub fn new(device: &wgpu::Device, atlas_size: u32) -> LocalLights {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Local Lights Buffer"),
+            size: LocalLightsUniform::SIZE as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Compares with linear filtering, which softens the edges of the shadows a little.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        LocalLights {
+            slots: Vec::new(),
+            atlas: ShadowAtlas::new(device, atlas_size),
+            cookies: LightCookies::new(device),
+            uniform_buffer,
+            sampler,
+        }
+    }
+
+    // Takes the first free slot, `None` if all MAX_LOCAL_LIGHTS are taken.
+    pub fn add(&mut self, light: LocalLight) -> Option<LocalLightId> {
+        match self.slots.iter().position(Option::is_none) {
+            Some(index) => {
+                self.slots[index] = Some(light);
+                Some(LocalLightId(index))
+            }
+            None if self.slots.len() < MAX_LOCAL_LIGHTS => {
+                self.slots.push(Some(light));
+                Some(LocalLightId(self.slots.len() - 1))
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove(&mut self, id: LocalLightId) {
+        self.slots[id.0] = None;
+    }
+
+    pub fn get(&self, id: LocalLightId) -> &LocalLight {
+        self.slots[id.0].as_ref().expect("light was removed")
+    }
+
+    pub fn set(&mut self, id: LocalLightId, light: LocalLight) {
+        *self.slots[id.0].as_mut().expect("light was removed") = light;
+    }
+
+    // Packs the shadow maps for a camera at `eye` into the atlas, and uploads the lights.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, eye: Point3<f32>) {
+        let requests: Vec<ShadowRequest> = self
+            .slots
+            .iter()
+            .map(|slot| match slot {
+                Some(light) if light.shadows && light.range > 0.0 => ShadowRequest {
+                    view_projs: light.shadow_view_projs(),
+                    // Near lights matter more, fading to half at the edge of their range.
+                    importance: light.shadow_priority.max(0.0) * light.range / (eye.distance(light.position) + light.range),
+                    layers: light.caster_layers,
+                },
+                _ => ShadowRequest {
+                    view_projs: Vec::new(),
+                    importance: 0.0,
+                    layers: RenderLayers::NONE,
+                },
+            })
+            .collect();
+        let first_views = self.atlas.allocate(queue, &requests);
+
+        let empty = LightParams {
+            position: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            color: Vector4::new(0.0, 0.0, 0.0, -1.0),
+            direction: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            cone: Vector4::new(-2.0, -1.0, 1.0, -1.0),
+            projection: Matrix4::from_scale(1.0),
+        };
+        let mut uniform = LocalLightsUniform {
+            count: self.slots.len() as u32,
+            lights: [empty; MAX_LOCAL_LIGHTS],
+            views: [ShadowViewParams {
+                view_proj: Matrix4::from_scale(1.0),
+                rect: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            }; MAX_SHADOW_VIEWS],
+        };
+        // Removed lights leave holes, which light nothing.
+        for (index, slot) in self.slots.iter().enumerate() {
+            let light = match slot {
+                Some(light) => light,
+                None => continue,
+            };
+            let first_view = first_views[index].map_or(-1.0, |first| first as f32);
+            let [r, g, b] = light.color;
+            let (direction, cone, projection) = match light.shape {
+                // Every direction is within the cone.
+                LightShape::Point => (Vector3::new(0.0, 0.0, 0.0), Vector4::new(-2.0, -1.0, 1.0, -1.0), Matrix4::from_scale(1.0)),
+                LightShape::Spot {
+                    direction,
+                    inner_angle,
+                    outer_angle,
+                } => {
+                    let outer = outer_angle.to_radians().cos();
+                    let inner = inner_angle.min(outer_angle).to_radians().cos().max(outer + 1e-4);
+                    let cookie = light.cookie.map_or(-1.0, |cookie| cookie.0 as f32);
+                    let projection = light.shadow_view_projs()[0];
+                    (normalized_or(direction, -Vector3::unit_y()), Vector4::new(outer, inner, 0.0, cookie), projection)
+                }
+            };
+            uniform.lights[index] = LightParams {
+                position: light.position.to_vec().extend(light.range.max(0.0)),
+                color: Vector4::new(r, g, b, first_view),
+                direction: direction.extend(0.0),
+                cone,
+                projection,
+            };
+        }
+        let size = self.atlas.size();
+        for (index, view) in self.atlas.views().iter().enumerate() {
+            uniform.views[index] = ShadowViewParams {
+                view_proj: view.view_proj,
+                rect: view.uv_rect(size),
+            };
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &uniform.to_uniform_bytes());
+    }
+
+    pub fn atlas(&self) -> &ShadowAtlas {
+        &self.atlas
+    }
+
+    pub fn atlas_mut(&mut self) -> &mut ShadowAtlas {
+        &mut self.atlas
+    }
+
+    pub fn cookies_mut(&mut self) -> &mut LightCookies {
+        &mut self.cookies
+    }
+
+    // The entries of the camera bind groups, see above.
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 5] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(self.atlas.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(self.cookies.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(self.cookies.sampler()),
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caster_layers_pick_the_shadow_casters() {
+        let lamp = LocalLight::point(Point3::new(0.0, 2.0, 0.0), [1.0; 3], 8.0);
+        assert_eq!(lamp.caster_layers, RenderLayers::DEFAULT | RenderLayers::SHADOW_CASTERS);
+
+        let spot = LocalLight::spot(Point3::new(0.0, 2.0, 0.0), -Vector3::unit_y(), [1.0; 3], 8.0, 30.0)
+            .with_caster_layers(RenderLayers::SHADOW_CASTERS)
+            .with_shadows(2.0);
+        assert_eq!(spot.caster_layers, RenderLayers::SHADOW_CASTERS);
+        assert!(spot.shadows && spot.shadow_priority == 2.0);
+        assert_eq!(spot.shadow_view_projs().len(), 1);
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+// Logging
+//======================
+// Everything logs through `tracing` (and the `log` records of wgpu end up there as well).
+// The `RUST_LOG` environment variable picks the levels, e.g. `RUST_LOG=debug` or
+// `RUST_LOG=learn_wgpu=trace,wgpu_core=info`. Records go to:
+//  - stderr,
+//  - optionally a log file (`--log-file`),
+//  - a ring buffer holding the most recent lines, which the stats overlay shows (see `recent`).
+//
+// The game loop opens `frame`, `update` and `render` spans, so a profiler that understands
+// tracing spans (or `RUST_LOG=trace` with span events) shows where the frame time goes.
+
+const DEFAULT_FILTER: &str = "info,wgpu_core=warn,wgpu_hal=warn,naga=warn";
+const RING_CAPACITY: usize = 64;
+
+static RING: OnceLock<Arc<Mutex<VecDeque<String>>>> = OnceLock::new();
+
+// Installs the global subscriber. Call once, early in `main`.
+pub fn init(log_file: Option<&Path>) {
+    init_with_level(log_file, Level::INFO);
+}
+
+// The same, logging `level` and above of our own records unless `RUST_LOG` says otherwise.
+// Only the first call installs the subscriber, see `AppBuilder::log_level`.
+pub fn init_with_level(log_file: Option<&Path>, level: Level) {
+    if RING.get().is_some() {
+        tracing::debug!("Logging is already set up");
+        return;
+    }
+    let default_filter = DEFAULT_FILTER.replacen("info", &level.as_str().to_lowercase(), 1);
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+
+    let file = log_file.and_then(|path| match File::create(path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Could not create log file {}: {}", path.display(), e);
+            None
+        }
+    });
+    let file_layer = file.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(Mutex::new(file))
+    });
+
+    let ring = RING.get_or_init(|| Arc::new(Mutex::new(VecDeque::with_capacity(RING_CAPACITY))));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(file_layer)
+        .with(RingLayer { lines: ring.clone() })
+        .init();
+}
+
+// The last `count` log lines, oldest first. Empty before `init`.
+pub fn recent(count: usize) -> Vec<String> {
+    match RING.get() {
+        Some(ring) => {
+            let lines = ring.lock().unwrap();
+            lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+// Keeps formatted records in a bounded queue for the in-app overlay.
+struct RingLayer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl<S: Subscriber> Layer<S> for RingLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = match *event.metadata().level() {
+            Level::ERROR => "ERROR",
+            Level::WARN => "WARN",
+            Level::INFO => "INFO",
+            Level::DEBUG => "DEBUG",
+            Level::TRACE => "TRACE",
+        };
+        let mut line = format!("{} ", level);
+        event.record(&mut MessageVisitor(&mut line));
+
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == RING_CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+// Writes the message first and the other fields as `name=value` after it.
+struct MessageVisitor<'a>(&'a mut String);
+
+impl Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else if !field.name().starts_with("log.") {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// Reduces a luminance histogram (see histogram.wgsl) to the average luminance,
+// smoothed over time. Also clears the histogram for the next frame.
+// Dispatched as a single workgroup.
+
+struct AverageParams {
+    min_log_lum: f32;
+    log_lum_range: f32;
+    pixel_count: f32;
+    adaptation: f32; // How far to move towards this frame's average, in [0, 1].
+};
+
+struct Histogram {
+    bins: array<atomic<u32>, 256>;
+};
+
+struct Average {
+    luminance: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: AverageParams;
+[[group(0), binding(1)]]
+var<storage, read_write> histogram: Histogram;
+[[group(0), binding(2)]]
+var<storage, read_write> average: Average;
+
+var<workgroup> weighted: array<f32, 256>;
+
+[[stage(compute), workgroup_size(256)]]
+fn main([[builtin(local_invocation_index)]] index: u32) {
+    let count = atomicLoad(&histogram.bins[index]);
+    weighted[index] = f32(count) * f32(index);
+    atomicStore(&histogram.bins[index], 0u);
+    workgroupBarrier();
+
+    // Parallel sum of the weighted bins.
+    for (var cutoff = 128u; cutoff > 0u; cutoff = cutoff >> 1u) {
+        if (index < cutoff) {
+            weighted[index] = weighted[index] + weighted[index + cutoff];
+        }
+        workgroupBarrier();
+    }
+
+    if (index == 0u) {
+        // Black pixels (bin 0) do not contribute to the average.
+        let lit_pixels = max(params.pixel_count - f32(count), 1.0);
+        let mean_bin = weighted[0] / lit_pixels - 1.0;
+        let log_lum = mean_bin / 254.0 * params.log_lum_range + params.min_log_lum;
+        let lum = exp2(log_lum);
+        average.luminance = average.luminance + (lum - average.luminance) * params.adaptation;
+    }
+}
@@ -1,44 +1,528 @@
-#[macro_use]
-mod error;
-use error::Win32Error;
-mod app;
-mod gfx;
-mod keyboard;
-mod mouse;
-mod win32_common;
-mod window;
-use app::App;
-pub type Result<T> = core::result::Result<T, Win32Error>;
-
-fn main() -> Result<()> {
-    let mut app = App::new();
-    app.run()
+use learn_wgpu::app::{App, RedrawMode};
+use learn_wgpu::game::{Event, Frame, Game, Input};
+use learn_wgpu::gfx::GFX;
+use learn_wgpu::layers::RenderLayers;
+use learn_wgpu::mesh::Mesh;
+use learn_wgpu::platform::Platform;
+use learn_wgpu::scene::Scene;
+use learn_wgpu::scripting::ScriptHost;
+use learn_wgpu::time::Time;
+use learn_wgpu::error::Result;
+use learn_wgpu::Vertex;
+#[cfg(feature = "physics")]
+use learn_wgpu::physics;
+#[cfg(feature = "physics")]
+use learn_wgpu::transform;
+use learn_wgpu::{
+    adapters, boids, camera_path, cli, config, dynamic_mesh, fluid, gizmo, golden, latency, life, logging,
+    marching_cubes, metrics, nbody, noise, panic, particles, reaction_diffusion, render_targets, replay, rng,
+    skinning, spline, tilemap, ui, volumetrics, world_time,
+};
+use std::path::PathBuf;
+use windows::Win32::UI::Input::KeyboardAndMouse::VK_ESCAPE;
+
+fn main() {
+    panic::install();
+    if let Err(e) = start() {
+        // The error names what failed and why, see error.rs.
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn start() -> Result<()> {
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(cli::CliError::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    logging::init(options.log_file.as_deref());
+
+    let config_path = config::Config::default_path();
+    let mut config = config::Config::load(&config_path);
+    if !config_path.exists() {
+        // Leave the defaults where they can be edited.
+        if let Err(e) = config.save(&config_path) {
+            tracing::warn!("Could not write {}: {}", config_path.display(), e);
+        }
+    }
+    config.apply(&options);
+    if options.list_adapters {
+        print!("{}", adapters::report(config.gfx_options().backends));
+        return Ok(());
+    }
+    if let Some(dir) = &options.golden {
+        let report = golden::run(dir, options.update_golden, &config.gfx_options())?;
+        for name in &report.updated {
+            println!("updated {}", name);
+        }
+        for name in &report.passed {
+            println!("ok      {}", name);
+        }
+        for (name, message) in &report.failed {
+            println!("FAILED  {}: {}", name, message);
+        }
+        if !report.failed.is_empty() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let player = options.replay.as_ref().map(|path| match replay::Player::open(path) {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Cannot replay {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    });
+    if let Some(player) = &player {
+        // Mouse positions only match in a window of the recorded size.
+        config.window.width = player.header().width;
+        config.window.height = player.header().height;
+    }
+
+    let mut gfx_options = config.gfx_options();
+    gfx_options.trace_path = options.trace.clone();
+    let mut game = Pentagon {
+        quit_key: config.keys.key("quit").unwrap_or(VK_ESCAPE),
+        quit: false,
+        script: options.script.clone().map(ScriptHost::new),
+        save_key: config.keys.key("save_scene"),
+        save_requested: false,
+        scene_path: options.scene.clone().unwrap_or_else(|| PathBuf::from("scene.ron")),
+        nbody: options.nbody.map(|count| nbody::NBodySettings {
+            count,
+            tiled: !options.nbody_untiled,
+            ..Default::default()
+        }),
+        boids: options.boids.map(|count| boids::BoidsSettings {
+            count,
+            ..Default::default()
+        }),
+        life_settings: options.life.map(|(width, height)| {
+            let defaults = life::LifeSettings::default();
+            life::LifeSettings {
+                width,
+                height,
+                rule: options.life_rule.unwrap_or(defaults.rule),
+                steps_per_second: options.life_rate.unwrap_or(defaults.steps_per_second),
+                ..defaults
+            }
+        }),
+        life: None,
+        reaction_diffusion: options.reaction_diffusion,
+        fluid_settings: options.fluid.then(fluid::FluidSettings::default),
+        fluid: None,
+        metaballs: options.metaballs,
+        light_shafts: options.light_shafts,
+        shafts: None,
+        skinning: options.skinning,
+        arm: None,
+        particles: options.particles.then(particles::ParticlesDemo::new),
+        trail: options.trail,
+        trail_demo: None,
+        gizmo: options.gizmo,
+        gizmo_demo: None,
+        track: options.track,
+        track_demo: None,
+        fly_through: options.camera_path.then(camera_path::FlyThrough::new),
+        terrain: options.terrain,
+        monitors: options.monitors,
+        monitors_demo: None,
+        tilemap_path: options.tilemap.clone(),
+        tilemap: None,
+        ui: options.ui,
+        ui_demo: None,
+        world_time: options
+            .day_length
+            .map(|seconds| world_time::WorldTime::new(world_time::DayCycle::default().with_day_length(seconds), 8.0)),
+        #[cfg(feature = "physics")]
+        physics: physics::Physics::new(),
+    };
+    let mut builder = App::builder()
+        .window(config.window_builder())
+        .title("learn-wgpu")
+        .gfx_options(gfx_options);
+    if game.scene_path.exists() {
+        builder = builder.scene(&game.scene_path);
+    }
+    if options.winit {
+        #[cfg(feature = "winit")]
+        {
+            let app = builder.build_winit();
+            return run(app, &options, &config, player, &mut game);
+        }
+        #[cfg(not(feature = "winit"))]
+        {
+            eprintln!("--winit needs a build with the winit feature");
+            std::process::exit(2);
+        }
+    }
+    let app = builder.build();
+    run(app, &options, &config, player, &mut game)
+}
+
+// The settings that don't depend on the platform, then the game loop.
+fn run<P: Platform>(
+    mut app: App<P>,
+    options: &cli::CliOptions,
+    config: &config::Config,
+    player: Option<replay::Player>,
+    game: &mut Pentagon,
+) -> Result<()> {
+    app.max_frames = options.frames;
+    if config.window.redraw_on_demand {
+        app.redraw_mode = RedrawMode::OnDemand;
+    }
+    let fps = |fps: f32| (fps > 0.0).then_some(fps);
+    app.background_fps = fps(config.window.background_fps);
+    app.occluded_fps = fps(config.window.occluded_fps);
+    app.battery_fps = fps(config.window.battery_fps);
+    if let Some(key) = config.keys.key("toggle_stats") {
+        app.stats_key = key;
+    }
+    if let Some(key) = config.keys.key("toggle_fullscreen") {
+        app.fullscreen_key = key;
+    }
+    if let Some(key) = config.keys.key("flush_metrics") {
+        app.metrics_key = key;
+    }
+    #[cfg(feature = "settings_ui")]
+    if let Some(key) = config.keys.key("toggle_settings") {
+        app.settings_key = key;
+    }
+    #[cfg(feature = "renderdoc")]
+    if let Some(key) = config.keys.key("capture_frame") {
+        app.capture_key = key;
+    }
+    app.metrics = options.metrics.clone().map(metrics::MetricsSink::new);
+    if options.latency {
+        app.latency = Some(latency::LatencyTracker::new());
+    }
+    if let Some(seed) = options.seed {
+        app.rng = rng::Rng::new(seed);
+    }
+    app.record_path = options.record.clone();
+    if let Some(player) = player {
+        app.replay(player);
+    }
+    app.run(game)
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    color: [f32; 3],
+// The demo: a single pentagon, drawn with the default material, and whatever the script
+// and the scene add. Escape quits, F5 saves the scene (without the pentagon, which is built in code).
+// With the `physics` feature, a few cubes fall onto an invisible floor below the pentagon.
+// With `--nbody`, a galaxy of bodies is simulated and drawn over it, see nbody.rs, and with
+// `--boids` a flock of boids, see boids.rs. `--life` runs a cellular automaton over the whole
+// window instead, painted with the mouse, see life.rs, and `--reaction-diffusion` a Gray-Scott
+// simulation, see reaction_diffusion.rs. `--fluid` is a fluid stirred with the mouse, see fluid.rs.
+// `--metaballs` adds a few reflective blobs around the pentagon, meshed with marching_cubes.rs,
+// over a mirror floor, see mirrors.rs. `--light-shafts` shines a spot light through a window and a
+// fog from behind it, see volumetrics.rs, and `--skinning` an arm bent on the GPU with its
+// skeleton drawn over it, see skinning.rs and skeleton.rs.
+// `--particles` adds a fire and sparks simulated on the CPU, see particles.rs.
+// `--trail` adds a ribbon trailing a point around it, rebuilt every frame, see dynamic_mesh.rs.
+// `--gizmo` selects it and puts a gizmo on it to drag it around with the mouse; clicks select other
+// renderables, see gizmo.rs and selection.rs. `--track` runs a cube along a spline around it, see
+// spline.rs. `--camera-path` flies the camera around it once, see camera_path.rs, and `--terrain`
+// adds hills of procedural noise behind it, see noise.rs. `--monitors` puts two monitors beside it
+// showing what texture cameras see, see render_targets.rs.
+// `--tilemap` draws a map made with Tiled behind everything, see tilemap.rs, and `--ui` a menu
+// over it, see ui.rs.
+// `--day-length` lights it with a sky through a day and night from eight in the morning, see
+// world_time.rs, logging sunrise and sunset; a script can set the time and be called at times
+// of day.
+struct Pentagon {
+    quit_key: u16,
+    quit: bool,
+    script: Option<ScriptHost>,
+    save_key: Option<u16>,
+    save_requested: bool,
+    scene_path: PathBuf, // Loaded by `App` at startup if it exists, and saved to.
+    nbody: Option<nbody::NBodySettings>,
+    boids: Option<boids::BoidsSettings>,
+    life_settings: Option<life::LifeSettings>,
+    life: Option<life::Life>,
+    reaction_diffusion: Option<reaction_diffusion::ReactionDiffusionSettings>,
+    fluid_settings: Option<fluid::FluidSettings>,
+    fluid: Option<fluid::Fluid>,
+    metaballs: bool,
+    light_shafts: bool,
+    shafts: Option<volumetrics::LightShafts>,
+    skinning: bool,
+    arm: Option<skinning::SkinnedArm>,
+    particles: Option<particles::ParticlesDemo>,
+    trail: bool,
+    trail_demo: Option<dynamic_mesh::TrailDemo>,
+    gizmo: bool,
+    gizmo_demo: Option<gizmo::GizmoDemo>,
+    track: bool,
+    track_demo: Option<spline::TrackDemo>,
+    fly_through: Option<camera_path::FlyThrough>,
+    terrain: bool,
+    monitors: bool,
+    monitors_demo: Option<render_targets::Monitors>,
+    tilemap_path: Option<PathBuf>,
+    tilemap: Option<tilemap::TilemapDemo>,
+    ui: bool,
+    ui_demo: Option<ui::UiDemo>,
+    world_time: Option<world_time::WorldTime>,
+    #[cfg(feature = "physics")]
+    physics: physics::Physics,
 }
 
-impl Vertex {
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x3,
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                },
-            ],
+impl Game for Pentagon {
+    fn init(&mut self, gfx: &mut GFX) {
+        let mesh = Mesh::new(gfx.device(), "Pentagon", VERTICES, INDICES);
+        let material = gfx.default_material();
+        let mesh = gfx.add_mesh(mesh);
+        let pentagon = gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+        if self.gizmo {
+            self.gizmo_demo = Some(gizmo::GizmoDemo::new(gfx, pentagon));
+        }
+        if let Some(script) = &mut self.script {
+            script.init(gfx);
+        }
+        // The simulations run in compute shaders, which GL and older drivers lack, see capabilities.rs.
+        let simulations = self.nbody.is_some()
+            || self.boids.is_some()
+            || self.life_settings.is_some()
+            || self.reaction_diffusion.is_some()
+            || self.fluid_settings.is_some();
+        if simulations && !gfx.capabilities().compute_shaders {
+            tracing::warn!("The adapter has no compute shaders, leaving out the simulations");
+        } else {
+            if let Some(settings) = self.nbody {
+                nbody::add_nodes(gfx, settings);
+            }
+            if let Some(settings) = self.boids {
+                boids::add_nodes(gfx, settings);
+            }
+            if let Some(settings) = self.life_settings {
+                self.life = Some(life::Life::new(gfx, settings));
+            }
+            if let Some(settings) = self.reaction_diffusion {
+                reaction_diffusion::add_nodes(gfx, settings);
+            }
+            if let Some(settings) = self.fluid_settings {
+                self.fluid = Some(fluid::Fluid::new(gfx, settings));
+            }
+        }
+        if self.metaballs {
+            marching_cubes::add_metaballs(gfx);
+        }
+        if self.light_shafts {
+            self.shafts = Some(volumetrics::LightShafts::new(gfx));
+        }
+        if self.skinning {
+            self.arm = Some(skinning::SkinnedArm::new(gfx));
+        }
+        if self.trail {
+            self.trail_demo = Some(dynamic_mesh::TrailDemo::new(gfx));
+        }
+        if self.track {
+            self.track_demo = Some(spline::TrackDemo::new(gfx));
+        }
+        if self.terrain {
+            noise::add_terrain(gfx);
+        }
+        if self.monitors {
+            self.monitors_demo = Some(render_targets::Monitors::new(gfx));
+        }
+        if let Some(path) = &self.tilemap_path {
+            match tilemap::TilemapDemo::new(gfx, path) {
+                Ok(tilemap) => self.tilemap = Some(tilemap),
+                Err(e) => tracing::error!("Failed to load tilemap {}: {}", path.display(), e),
+            }
+        }
+        if self.ui {
+            self.ui_demo = Some(ui::UiDemo::new(gfx));
+        }
+        #[cfg(feature = "physics")]
+        self.init_physics(gfx);
+    }
+
+    fn update(&mut self, time: &mut Time, input: &Input) {
+        if let Some(script) = &mut self.script {
+            script.update(time.delta());
+        }
+        if let Some(world_time) = &mut self.world_time {
+            let was_day = world_time.is_day();
+            match &mut self.script {
+                Some(script) => script.update_world_time(world_time, time.delta()),
+                None => {
+                    world_time.update(time.delta());
+                }
+            }
+            if world_time.is_day() != was_day {
+                let (hours, minutes) = world_time.clock();
+                let sun = if world_time.is_day() { "rises" } else { "sets" };
+                tracing::info!("Day {}, {:02}:{:02}: the sun {}", world_time.day() + 1, hours, minutes, sun);
+            }
+        }
+        if let Some(life) = &mut self.life {
+            life.update(time.delta());
+        }
+        if let Some(shafts) = &mut self.shafts {
+            shafts.update(time.delta());
+        }
+        if let Some(arm) = &mut self.arm {
+            arm.update(time.delta());
+        }
+        if let Some(particles) = &mut self.particles {
+            particles.update(time.delta(), input.rng);
+        }
+        if let Some(trail) = &mut self.trail_demo {
+            trail.update(time.delta());
+        }
+        if let Some(monitors) = &mut self.monitors_demo {
+            monitors.update(time.delta());
+        }
+        if let Some(track) = &mut self.track_demo {
+            track.update(time.delta());
+        }
+        if let Some(fly_through) = &mut self.fly_through {
+            fly_through.update(time.delta());
+        }
+        #[cfg(feature = "physics")]
+        self.physics.step(time.delta());
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        #[cfg(feature = "physics")]
+        {
+            self.physics.sync(frame.gfx, frame.alpha);
+            self.physics.debug_draw(frame.gfx.debug_draw());
+        }
+        if let Some(script) = &mut self.script {
+            script.apply(frame.gfx);
+        }
+        if let Some(world_time) = &self.world_time {
+            world_time.apply(frame.gfx);
+        }
+        if let Some(life) = &mut self.life {
+            life.render(frame.gfx);
+        }
+        if let Some(fluid) = &mut self.fluid {
+            fluid.render(frame.gfx);
+        }
+        if let Some(shafts) = &mut self.shafts {
+            shafts.render(frame.gfx);
+        }
+        if let Some(arm) = &mut self.arm {
+            arm.render(frame.gfx);
+        }
+        if let Some(particles) = &self.particles {
+            particles.render(frame.gfx);
+        }
+        if let Some(trail) = &mut self.trail_demo {
+            trail.render(frame.gfx);
+        }
+        if let Some(monitors) = &mut self.monitors_demo {
+            monitors.render(frame.gfx);
+        }
+        if let Some(gizmo) = &mut self.gizmo_demo {
+            gizmo.render(frame.gfx);
+        }
+        if let Some(track) = &mut self.track_demo {
+            track.render(frame.gfx);
+        }
+        if let Some(fly_through) = &mut self.fly_through {
+            fly_through.render(frame.gfx);
+        }
+        if let Some(tilemap) = &mut self.tilemap {
+            tilemap.render(frame.gfx);
+        }
+        if let Some(ui_demo) = &mut self.ui_demo {
+            ui_demo.render(frame.gfx);
+        }
+        if std::mem::take(&mut self.save_requested) {
+            match Scene::capture(frame.gfx).save(&self.scene_path) {
+                Ok(()) => tracing::info!("Saved the scene to {}", self.scene_path.display()),
+                Err(e) => tracing::error!("Failed to save scene {}: {}", self.scene_path.display(), e),
+            }
+        }
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        if *event == Event::KeyPressed(self.quit_key) {
+            self.quit = true;
+        }
+        if self.save_key.is_some_and(|key| *event == Event::KeyPressed(key)) {
+            self.save_requested = true;
+        }
+        if let Some(life) = &mut self.life {
+            life.on_event(event);
+        }
+        if let Some(fluid) = &mut self.fluid {
+            fluid.on_event(event);
+        }
+        if let Some(track) = &mut self.track_demo {
+            track.on_event(event);
+        }
+        if let Some(fly_through) = &mut self.fly_through {
+            fly_through.on_event(event);
+        }
+        if let Some(tilemap) = &mut self.tilemap {
+            tilemap.on_event(event);
+        }
+        if let Some(shafts) = &mut self.shafts {
+            shafts.on_event(event);
+        }
+        if let Some(arm) = &mut self.arm {
+            arm.on_event(event);
+        }
+        if let Some(particles) = &mut self.particles {
+            particles.on_event(event);
+        }
+        if let Some(trail) = &mut self.trail_demo {
+            trail.on_event(event);
+        }
+        if let Some(monitors) = &mut self.monitors_demo {
+            monitors.on_event(event);
+        }
+        if let Some(gizmo) = &mut self.gizmo_demo {
+            gizmo.on_event(event);
+        }
+        if let Some(ui_demo) = &mut self.ui_demo {
+            ui_demo.on_event(event);
+        }
+    }
+
+    fn should_exit(&self) -> bool {
+        self.quit || self.fly_through.as_ref().is_some_and(|fly_through| fly_through.is_finished())
+    }
+}
+
+#[cfg(feature = "physics")]
+impl Pentagon {
+    fn init_physics(&mut self, gfx: &mut GFX) {
+        use rapier3d::prelude::{ColliderBuilder, RigidBodyBuilder};
+        use transform::Transform;
+
+        self.physics.add_collider(ColliderBuilder::cuboid(2.0, 0.1, 2.0).translation([0.0, -0.7, 0.0].into()));
+        let material = gfx.default_material();
+        for i in 0..3 {
+            let size = 0.2;
+            let position = [i as f32 * 0.3 - 0.3, 0.8 + i as f32 * 0.4, 0.0];
+            let body = self.physics.add_body(
+                RigidBodyBuilder::dynamic()
+                    .translation(position.into())
+                    .rotation([0.3 * i as f32, 0.5, 0.2].into()),
+                ColliderBuilder::cuboid(size / 2.0, size / 2.0, size / 2.0),
+            );
+            let cube = gfx.unit_cube();
+            let renderable = gfx.add_renderable(cube, material, RenderLayers::DEFAULT);
+            let scale = cgmath::Vector3::new(size, size, size);
+            gfx.set_transform(renderable, Transform::IDENTITY.with_scale(scale));
+            self.physics.attach(renderable, body);
         }
     }
 }
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use cgmath::{InnerSpace, Point3, Vector3, Vector4};
+
+use crate::gfx::GFX;
+use crate::layers::RenderLayers;
+use crate::lens_flare::LensFlare;
+use crate::material::{Material, ReflectiveParams};
+use crate::mesh::Mesh;
+use crate::mirrors::Mirror;
+use crate::reflection_probes::{ProbeUpdate, ReflectionProbe};
+use crate::variants::ShaderDefines;
+use crate::vertex_layout::VertexData;
+
+// Marching cubes
+//======================
+// Turns a 3D scalar field, e.g. the sum of a few metaballs or the density of voxel terrain, into a
+// triangle mesh of the surface where the field equals an iso value:
+//
+//     let field = ScalarField::from_fn([32, 32, 32], origin, 0.1, |p| density(p));
+//     let surface = marching_cubes::polygonize(&field, 0.5);
+//     for mesh in surface.to_meshes(gfx.device(), "Terrain", false) {
+//         let mesh = gfx.add_mesh(mesh);
+//         gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+//     }
+//
+// Values above the iso value are inside. Every cube between 8 neighboring samples is classified by
+// which of its corners are inside, and gets the triangles of that case, with their vertices on the
+// edges between inside and outside corners, where the field crosses the iso value. Vertices are
+// shared between neighboring cubes, and their normals come from the gradient of the field, so the
+// surface is smooth and closed where it doesn't leave the field.
+//
+// The triangles of each of the 256 cases are derived from the cube's faces when first needed,
+// rather than copied from the usual table: on every face, the crossed edges are joined in pairs,
+// cutting off the inside corners where two of them are diagonal, and the joined edges form the
+// outlines of the polygons. Since a face is classified only from its own 4 corners, neighboring
+// cubes agree on it and the surface has no cracks.
+//
+// This runs on the CPU, for surfaces built once or now and then. Meshes have 16-bit indices, so
+// larger surfaces are split into several meshes.
+
+/// Samples of a scalar field on a regular grid.
+#[derive(Clone, Debug)]
+pub struct ScalarField {
+    size: [usize; 3], // Samples along x, y and z.
+    values: Vec<f32>, // x first, then y, then z.
+    pub origin: Point3<f32>, // Position of the first sample.
+    pub spacing: f32, // Between neighboring samples.
+}
+
+impl ScalarField {
+    // All samples 0.
+    pub fn new(size: [usize; 3], origin: Point3<f32>, spacing: f32) -> ScalarField {
+        ScalarField {
+            size,
+            values: vec![0.0; size[0] * size[1] * size[2]],
+            origin,
+            spacing,
+        }
+    }
+
+    // Samples `f` at the position of every sample.
+    pub fn from_fn(size: [usize; 3], origin: Point3<f32>, spacing: f32, f: impl Fn(Point3<f32>) -> f32) -> ScalarField {
+        let mut field = ScalarField::new(size, origin, spacing);
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let value = f(field.position(x, y, z));
+                    field.set(x, y, z, value);
+                }
+            }
+        }
+        field
+    }
+
+    // The sum of balls of `radius`, 1 at their centers and falling smoothly to 0 at `radius`;
+    // around 0.5 is a good iso value.
+    pub fn metaballs(size: [usize; 3], origin: Point3<f32>, spacing: f32, centers: &[Point3<f32>], radius: f32) -> ScalarField {
+        ScalarField::from_fn(size, origin, spacing, |p| {
+            centers
+                .iter()
+                .map(|center| {
+                    let r = ((p - center).magnitude() / radius).min(1.0);
+                    (1.0 - r * r).powi(2)
+                })
+                .sum()
+        })
+    }
+
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[self.index(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: f32) {
+        let index = self.index(x, y, z);
+        self.values[index] = value;
+    }
+
+    pub fn position(&self, x: usize, y: usize, z: usize) -> Point3<f32> {
+        self.origin + Vector3::new(x as f32, y as f32, z as f32) * self.spacing
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.size[0] * (y + self.size[1] * z)
+    }
+
+    // Towards increasing values, from the neighboring samples, one-sided at the borders.
+    fn gradient(&self, x: usize, y: usize, z: usize) -> Vector3<f32> {
+        let [sx, sy, sz] = self.size;
+        let axis = |before: f32, after: f32, span: usize| (after - before) / span.max(1) as f32;
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(sx - 1));
+        let (y0, y1) = (y.saturating_sub(1), (y + 1).min(sy - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(sz - 1));
+        Vector3::new(
+            axis(self.get(x0, y, z), self.get(x1, y, z), x1 - x0),
+            axis(self.get(x, y0, z), self.get(x, y1, z), y1 - y0),
+            axis(self.get(x, y, z0), self.get(x, y, z1), z1 - z0),
+        )
+    }
+}
+
+/// The triangles `polygonize` found, with a normal per vertex pointing out of the surface.
+#[derive(Clone, Debug, Default)]
+pub struct IsoSurface {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+impl IsoSurface {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    // Uploads the surface, in as many meshes as 16-bit indices need, with compressed vertices if
+    // `packed`, see packing.rs.
+    pub fn to_meshes(&self, device: &wgpu::Device, label: &str, packed: bool) -> Vec<Mesh> {
+        let mut meshes = Vec::new();
+        let mut remap: HashMap<u32, u16> = HashMap::new();
+        let empty = || VertexData::default().with_normals(Vec::new()).with_packing(packed);
+        let mut vertices = empty();
+        let mut indices: Vec<u16> = Vec::new();
+        for triangle in self.indices.chunks_exact(3) {
+            // Room for 3 new vertices, or the mesh so far is finished.
+            if vertices.len() + 3 > u16::MAX as usize + 1 {
+                meshes.push(Mesh::with_attributes(device, label, &vertices, &indices));
+                remap.clear();
+                vertices = empty();
+                indices.clear();
+            }
+            for &index in triangle {
+                let local = *remap.entry(index).or_insert_with(|| {
+                    vertices.positions.push(self.positions[index as usize]);
+                    vertices.normals.as_mut().unwrap().push(self.normals[index as usize]);
+                    (vertices.positions.len() - 1) as u16
+                });
+                indices.push(local);
+            }
+        }
+        if !indices.is_empty() {
+            meshes.push(Mesh::with_attributes(device, label, &vertices, &indices));
+        }
+        meshes
+    }
+}
+
+// Extracts the surface where `field` equals `iso`, see above.
+pub fn polygonize(field: &ScalarField, iso: f32) -> IsoSurface {
+    let table = triangle_table();
+    let [sx, sy, sz] = field.size;
+    let mut surface = IsoSurface::default();
+    // Vertices by the sample at the start of their edge and the axis of the edge.
+    let mut edge_vertices: HashMap<(usize, usize), u32> = HashMap::new();
+
+    for z in 0..sz.saturating_sub(1) {
+        for y in 0..sy.saturating_sub(1) {
+            for x in 0..sx.saturating_sub(1) {
+                let corner = |i: usize| (x + (i & 1), y + ((i >> 1) & 1), z + ((i >> 2) & 1));
+                let mut case = 0;
+                for i in 0..8 {
+                    let (cx, cy, cz) = corner(i);
+                    if field.get(cx, cy, cz) > iso {
+                        case |= 1 << i;
+                    }
+                }
+
+                for triangle in &table[case] {
+                    let mut vertices = [0u32; 3];
+                    for (vertex, &edge) in vertices.iter_mut().zip(triangle) {
+                        let (a, b) = EDGES[edge as usize];
+                        let (a, b) = (corner(a), corner(b));
+                        let axis = EDGE_AXES[edge as usize];
+                        *vertex = *edge_vertices.entry((field.index(a.0, a.1, a.2), axis)).or_insert_with(|| {
+                            let (va, vb) = (field.get(a.0, a.1, a.2), field.get(b.0, b.1, b.2));
+                            let t = ((iso - va) / (vb - va)).clamp(0.0, 1.0);
+                            let (pa, pb) = (field.position(a.0, a.1, a.2), field.position(b.0, b.1, b.2));
+                            let position = pa + (pb - pa) * t;
+                            let gradient = field.gradient(a.0, a.1, a.2) * (1.0 - t) + field.gradient(b.0, b.1, b.2) * t;
+                            // Values decrease going out.
+                            let normal = if gradient.magnitude2() > 0.0 { -gradient.normalize() } else { Vector3::unit_y() };
+                            surface.positions.push(position.into());
+                            surface.normals.push(normal.into());
+                            (surface.positions.len() - 1) as u32
+                        });
+                    }
+                    surface.indices.extend(vertices);
+                }
+            }
+        }
+    }
+    surface
+}
+
+// Metaballs demo
+//======================
+
+// A few blobs melting into each other around the origin, drawn with the default shader. They
+// reflect what is around them, captured by a reflection probe at their center that follows moving
+// things a face per frame, see reflection_probes.rs.
+pub fn add_metaballs(gfx: &mut GFX) {
+    let centers = [Point3::new(-0.35, -0.1, 0.0), Point3::new(0.3, 0.15, -0.1), Point3::new(0.0, 0.35, 0.2)];
+    let field = ScalarField::metaballs([48, 48, 48], Point3::new(-1.0, -1.0, -1.0), 2.0 / 47.0, &centers, 0.6);
+    let surface = polygonize(&field, 0.5);
+    tracing::info!("Metaballs: {} vertices, {} triangles", surface.positions.len(), surface.indices.len() / 3);
+    let shader = gfx.material(gfx.default_material()).shader();
+    let material = Material::new(shader)
+        .with_defines(ShaderDefines::new().with_flag("REFLECTION_PROBES", true))
+        .with_uniform(&ReflectiveParams {
+            color: Vector4::new(0.9, 0.8, 1.0, 1.0),
+            reflectivity: 0.6,
+        });
+    let material = gfx.add_material(material).expect("built-in shader has reflection probes");
+    let probe = ReflectionProbe::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(3.0, 3.0, 3.0))
+        .with_update(ProbeUpdate::Amortized { faces_per_frame: 1 });
+    if gfx.add_reflection_probe(probe).is_none() {
+        tracing::warn!("Metaballs: no reflection probe left");
+    }
+    // A sun behind the blobs, its flare fading where they cover it.
+    gfx.add_lens_flare(LensFlare::new(Point3::new(0.2, 0.4, -4.0), [1.0, 0.9, 0.7]).with_intensity(1.5));
+    // Compressed like loaded meshes when `compress_vertices` is set in the config.
+    let packed = gfx.assets().compresses_vertices();
+    for mesh in surface.to_meshes(gfx.device(), "Metaballs", packed) {
+        let mesh = gfx.add_mesh(mesh);
+        gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+    }
+
+    // A mirror floor below them.
+    let mirror = gfx.add_mirror(Mirror::new(Point3::new(0.0, -1.0, 0.0), Vector3::unit_y()));
+    match gfx.add_mirror_material(mirror, Vector4::new(0.3, 0.3, 0.35, 1.0), 0.8) {
+        Ok(floor_material) => {
+            let floor = VertexData::new(vec![[-2.0, -1.0, -2.0], [-2.0, -1.0, 2.0], [2.0, -1.0, 2.0], [2.0, -1.0, -2.0]])
+                .with_normals(vec![[0.0, 1.0, 0.0]; 4]);
+            let floor = gfx.add_mesh(Mesh::with_attributes(gfx.device(), "Mirror Floor", &floor, &[0, 1, 2, 0, 2, 3]));
+            gfx.add_renderable(floor, floor_material, RenderLayers::DEFAULT);
+        }
+        Err(e) => tracing::warn!("Metaballs: no mirror floor: {}", e),
+    }
+}
+
+// The cube
+//======================
+// Corner i is at (i & 1, (i >> 1) & 1, (i >> 2) & 1) in the cube.
+
+// The corners at the ends of every edge, and the axis along it.
+const EDGES: [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7), // Along x.
+    (0, 2), (1, 3), (4, 6), (5, 7), // Along y.
+    (0, 4), (1, 5), (2, 6), (3, 7), // Along z.
+];
+const EDGE_AXES: [usize; 12] = [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+
+// The corners of every face, in order around it.
+const FACES: [[usize; 4]; 6] = [
+    [0, 2, 6, 4], // x = 0
+    [1, 3, 7, 5], // x = 1
+    [0, 1, 5, 4], // y = 0
+    [2, 3, 7, 6], // y = 1
+    [0, 1, 3, 2], // z = 0
+    [4, 5, 7, 6], // z = 1
+];
+
+// For every case, the triangles as edges their vertices are on, see above.
+fn triangle_table() -> &'static [Vec<[u8; 3]>] {
+    static TABLE: OnceLock<Vec<Vec<[u8; 3]>>> = OnceLock::new();
+    TABLE.get_or_init(|| (0..256).map(case_triangles).collect())
+}
+
+fn case_triangles(case: usize) -> Vec<[u8; 3]> {
+    let inside = |corner: usize| case & (1 << corner) != 0;
+    let edge = |a: usize, b: usize| EDGES.iter().position(|&e| e == (a.min(b), a.max(b))).unwrap();
+    let corner_position = |i: usize| Vector3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32);
+    let midpoint = |e: usize| (corner_position(EDGES[e].0) + corner_position(EDGES[e].1)) * 0.5;
+
+    // Every crossed edge is on two faces, and joined to one crossed edge on each: to the next one
+    // around the outline on one face, and from the previous one on the other. Going along a join,
+    // the inside corner it cuts off is on the right, seen from outside the cube, which makes the
+    // outlines counter-clockwise seen from outside the surface.
+    let mut next = [None; 12];
+    for face in FACES {
+        let sides: Vec<usize> = (0..4).map(|k| edge(face[k], face[(k + 1) % 4])).collect();
+        let crossed: Vec<usize> = (0..4).filter(|&k| inside(face[k]) != inside(face[(k + 1) % 4])).collect();
+        let center: Vector3<f32> = face.iter().map(|&c| corner_position(c)).sum::<Vector3<f32>>() * 0.25;
+        let normal = center - Vector3::new(0.5, 0.5, 0.5);
+        let mut join = |a: usize, b: usize, inside_corner: usize| {
+            let (a, b) = (sides[a], sides[b]);
+            let left = normal.dot((midpoint(b) - midpoint(a)).cross(corner_position(inside_corner) - midpoint(a)));
+            if left < 0.0 {
+                next[a] = Some(b);
+            } else {
+                next[b] = Some(a);
+            }
+        };
+        match crossed.len() {
+            2 => {
+                let inside_corner = *face.iter().find(|&&c| inside(c)).unwrap();
+                join(crossed[0], crossed[1], inside_corner);
+            }
+            // Two inside corners on a diagonal: each is cut off by the two sides next to it.
+            4 => {
+                for k in (0..4).filter(|&k| inside(face[k])) {
+                    join((k + 3) % 4, k, face[k]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Follow the joins around every outline, and cut it into triangles.
+    let mut triangles = Vec::new();
+    let mut visited = [false; 12];
+    for start in 0..12 {
+        if visited[start] || next[start].is_none() {
+            continue;
+        }
+        let mut outline = vec![start];
+        visited[start] = true;
+        let mut current = next[start].unwrap();
+        while current != start {
+            outline.push(current);
+            visited[current] = true;
+            current = next[current].unwrap();
+        }
+        triangulate(&outline, &mut triangles);
+    }
+    triangles
+}
+
+// Cuts an outline into triangles, without a new edge between two vertices on the same face of the
+// cube: the neighboring cube may have one there too, which would join 3 triangles or more.
+fn triangulate(outline: &[usize], triangles: &mut Vec<[u8; 3]>) {
+    fn corners(edge: usize) -> [usize; 2] {
+        [EDGES[edge].0, EDGES[edge].1]
+    }
+    let same_face = |a: usize, b: usize| {
+        FACES.iter().any(|face| corners(a).iter().chain(&corners(b)).all(|c| face.contains(c)))
+    };
+    fn cut(outline: &[usize], same_face: &dyn Fn(usize, usize) -> bool, triangles: &mut Vec<[u8; 3]>) -> bool {
+        let n = outline.len();
+        if n == 3 {
+            triangles.push([outline[0] as u8, outline[1] as u8, outline[2] as u8]);
+            return true;
+        }
+        // Cut off a corner of the outline, and the rest the same way.
+        for i in 0..n {
+            let (previous, next) = (outline[(i + n - 1) % n], outline[(i + 1) % n]);
+            if same_face(previous, next) {
+                continue;
+            }
+            let rest: Vec<usize> = (1..n).map(|k| outline[(i + k) % n]).collect();
+            let count = triangles.len();
+            triangles.push([previous as u8, outline[i] as u8, next as u8]);
+            if cut(&rest, same_face, triangles) {
+                return true;
+            }
+            triangles.truncate(count);
+        }
+        false
+    }
+    // Every one of the 256 cases has such a triangulation.
+    let found = cut(outline, &same_face, triangles);
+    assert!(found, "no triangulation of outline {:?}", outline);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f32 = 0.8;
+    const SPACING: f32 = 0.1;
+
+    // A sphere inside the field, from the distance to its surface, positive inside.
+    fn sphere() -> IsoSurface {
+        let field = ScalarField::from_fn([24, 24, 24], Point3::new(-1.15, -1.15, -1.15), SPACING, |p| {
+            RADIUS - (p - Point3::new(0.0, 0.0, 0.0)).magnitude()
+        });
+        polygonize(&field, 0.0)
+    }
+
+    #[test]
+    fn a_sphere_is_closed() {
+        let surface = sphere();
+        assert!(!surface.is_empty());
+        // Every edge of every triangle is shared with exactly one other triangle, which runs along
+        // it the other way if both face outwards.
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for triangle in surface.indices.chunks_exact(3) {
+            for k in 0..3 {
+                let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+                assert_ne!(a, b, "degenerate triangle {:?}", triangle);
+                *edges.entry((a, b)).or_default() += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {}-{} is used {} times in one direction", a, b, count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {}-{} has one triangle", a, b);
+        }
+    }
+
+    #[test]
+    fn sphere_vertices_are_on_the_surface() {
+        let surface = sphere();
+        for (position, normal) in surface.positions.iter().zip(&surface.normals) {
+            let (position, normal) = (Vector3::from(*position), Vector3::from(*normal));
+            let distance = position.magnitude();
+            assert!((distance - RADIUS).abs() < 0.05 * SPACING, "vertex {:?} is {} from the center", position, distance);
+            assert!(normal.dot(position / distance) > 0.99, "normal {:?} at {:?} doesn't point out", normal, position);
+        }
+        // The triangles face outwards too.
+        for triangle in surface.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| Vector3::from(surface.positions[triangle[k] as usize]));
+            assert!((b - a).cross(c - a).dot(a + b + c) > 0.0);
+        }
+    }
+}
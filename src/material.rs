@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use cgmath::Vector4;
+
+use crate::device_cache::DeviceCache;
+use crate::reflection::{ReflectError, ShaderReflection};
+use crate::texture::Texture;
+use crate::transform::ModelInstance;
+use crate::uniform::UniformLayout;
+use crate::mesh::VertexLayout;
+use crate::overrides::{self, PipelineConstants};
+use crate::reflection_probes::PROBES_GROUP;
+use crate::variants::{self, ShaderDefines};
+
+// Material shaders follow a fixed binding convention:
+//   group(0) binding(0)   camera uniform
+//   group(0) binding(1-6) optional, the globals (time.rs) and the local lights (local_lights.rs).
+//   group(1)              material resources, in binding order:
+//                         the uniform buffer is the material parameter block,
+//                         the i-th texture binding is the material's texture i,
+//                         the i-th sampler binding is the sampler of texture i.
+//   group(2)              optional, the reflection probes, see reflection_probes.rs.
+// and use `vs_main` / `fs_main` as entry points.
+// The group(1) layout is reflected from the shader source, per variant: defines may add or remove
+// bindings, see variants.rs. Variants with the same bindings share one layout, see device_cache.rs.
+
+const MATERIAL_GROUP: u32 = 1;
+
+// Uniform buffers are bound in multiples of 16 bytes.
+const PARAMS_ALIGNMENT: usize = 16;
+
+uniform_struct! {
+    /// Parameters of the default material shader.
+    pub struct ColorParams {
+        pub color: Vector4<f32>,
+    }
+}
+assert_uniform_size!(ColorParams, 16);
+
+uniform_struct! {
+    /// Parameters of the default material shader with `REFLECTION_PROBES` or `PLANAR_MIRROR`.
+    pub struct ReflectiveParams {
+        pub color: Vector4<f32>,
+        pub reflectivity: f32, // 0 is the plain color, 1 a mirror.
+    }
+}
+assert_uniform_size!(ReflectiveParams, 32);
+
+uniform_struct! {
+    /// Parameters of the default material shader with `EMISSIVE`, see `GFX::add_emissive_material`.
+    pub struct EmissiveParams {
+        pub color: Vector4<f32>,
+        pub emissive: Vector4<f32>, // rgb times the intensity in w, brighter than white in HDR.
+    }
+}
+assert_uniform_size!(EmissiveParams, 32);
+
+/// Identifies a shader registered with `GFX::add_shader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderId(usize);
+
+/// Identifies a material registered with `GFX::add_material`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+/// A shader together with its parameter block and textures.
+pub struct Material {
+    shader: ShaderId,
+    params: Vec<u8>,
+    textures: Vec<Rc<Texture>>,
+    defines: ShaderDefines, // Over those of the shader and the GFX, see variants.rs.
+
+    // GPU resources, created lazily by `Materials::prepare`.
+    params_buffer: Option<wgpu::Buffer>,
+    // The shader variant drawn for each vertex layout, and a bind group per variant.
+    variants: HashMap<VertexLayout, VariantId>,
+    bind_groups: HashMap<VariantId, wgpu::BindGroup>,
+    params_dirty: bool,
+}
+
+impl Material {
+    pub fn new(shader: ShaderId) -> Material {
+        Material {
+            shader,
+            params: vec![0; PARAMS_ALIGNMENT],
+            textures: Vec::new(),
+            defines: ShaderDefines::new(),
+            params_buffer: None,
+            variants: HashMap::new(),
+            bind_groups: HashMap::new(),
+            params_dirty: true,
+        }
+    }
+
+    pub fn shader(&self) -> ShaderId {
+        self.shader
+    }
+
+    // Sets the parameter block, which must match the layout of the
+    // group(1) uniform struct of the shader.
+    pub fn set_params<T: bytemuck::Pod>(&mut self, params: &T) {
+        self.set_raw_params(bytemuck::bytes_of(params));
+    }
+
+    // Sets the parameter block from bytes, e.g. the `params` of another material.
+    pub fn set_raw_params(&mut self, bytes: &[u8]) {
+        let mut bytes = bytes.to_vec();
+        let padded_len = bytes.len().div_ceil(PARAMS_ALIGNMENT).max(1) * PARAMS_ALIGNMENT;
+        bytes.resize(padded_len, 0);
+        self.set_param_bytes(bytes);
+    }
+
+    // The parameter block, padded to a multiple of 16 bytes.
+    pub fn params(&self) -> &[u8] {
+        &self.params
+    }
+
+    fn set_param_bytes(&mut self, bytes: Vec<u8>) {
+        // A parameter block of a different size needs a new buffer, and thus a new bind group.
+        if bytes.len() != self.params.len() {
+            self.params_buffer = None;
+            self.bind_groups.clear();
+        }
+        self.params = bytes;
+        self.params_dirty = true;
+    }
+
+    pub fn with_params<T: bytemuck::Pod>(mut self, params: &T) -> Material {
+        self.set_params(params);
+        self
+    }
+
+    // Sets the parameter block from a `uniform_struct!`, laid out by the WGSL rules.
+    pub fn set_uniform<T: UniformLayout>(&mut self, params: &T) {
+        let mut bytes = params.to_uniform_bytes();
+        bytes.resize(bytes.len().max(PARAMS_ALIGNMENT), 0);
+        self.set_param_bytes(bytes);
+    }
+
+    pub fn with_uniform<T: UniformLayout>(mut self, params: &T) -> Material {
+        self.set_uniform(params);
+        self
+    }
+
+    pub fn add_texture(&mut self, texture: Rc<Texture>) {
+        self.textures.push(texture);
+        self.bind_groups.clear();
+    }
+
+    pub fn with_texture(mut self, texture: Rc<Texture>) -> Material {
+        self.add_texture(texture);
+        self
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn texture(&self, index: usize) -> Option<&Rc<Texture>> {
+        self.textures.get(index)
+    }
+
+    pub fn set_texture(&mut self, index: usize, texture: Rc<Texture>) {
+        self.textures[index] = texture;
+        self.bind_groups.clear();
+    }
+
+    // Whether the material samples this very texture, not a copy of it.
+    pub fn uses_texture(&self, texture: &Rc<Texture>) -> bool {
+        self.textures.iter().any(|t| Rc::ptr_eq(t, texture))
+    }
+
+    pub fn defines(&self) -> &ShaderDefines {
+        &self.defines
+    }
+
+    // Selects a different variant of the shader from the next frame on.
+    pub fn set_defines(&mut self, defines: ShaderDefines) {
+        self.defines = defines;
+        self.variants.clear();
+    }
+
+    pub fn with_defines(mut self, defines: ShaderDefines) -> Material {
+        self.set_defines(defines);
+        self
+    }
+
+    // The bind group for the variant of `key`, see `Materials::prepare`.
+    pub fn bind_group(&self, key: &PipelineKey) -> Option<&wgpu::BindGroup> {
+        self.bind_groups.get(&key.variant)
+    }
+}
+
+// A shader compiled with one set of defines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct VariantId(usize);
+
+// The bind group layouts the GFX provides to every material pipeline.
+pub struct SceneLayouts<'a> {
+    pub camera: &'a wgpu::BindGroupLayout,
+    pub probes: &'a wgpu::BindGroupLayout, // Only for shaders that declare group(2).
+}
+
+// Everything that selects a distinct render pipeline for a material.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    variant: VariantId,
+    format: wgpu::TextureFormat,
+    samples: u32,
+    vertex: VertexLayout,
+}
+
+// The source of a shader, and the variants compiled from it.
+struct Shader {
+    label: String,
+    source: String,
+    // The fragment stage, for shaders with a module per stage (GLSL, see shader_import.rs).
+    fragment: Option<String>,
+    defaults: ShaderDefines, // The weakest defines, see variants.rs.
+    constants: PipelineConstants, // Applied to every variant, see overrides.rs.
+    variants: HashMap<ShaderDefines, VariantId>,
+}
+
+// A shader compiled with one set of defines, and the material bind group layout reflected from it.
+struct Variant {
+    shader: ShaderId,
+    label: String,
+    module: wgpu::ShaderModule,
+    fragment_module: Option<wgpu::ShaderModule>, // `None` if `module` has both stages.
+    reflection: ShaderReflection,
+    layout: Rc<wgpu::BindGroupLayout>, // Shared with the other variants that have the same bindings.
+}
+
+impl Variant {
+    fn new(
+        device: &wgpu::Device,
+        cache: &DeviceCache,
+        id: ShaderId,
+        shader: &Shader,
+        defines: &ShaderDefines,
+    ) -> Result<Variant, ReflectError> {
+        let label = if defines.is_empty() {
+            shader.label.clone()
+        } else {
+            format!("{} [{}]", shader.label, defines)
+        };
+        // Reflecting also parses and validates the source,
+        // so broken shaders are reported here instead of panicking inside wgpu.
+        let compile = |source: &str, label: &str| -> Result<(wgpu::ShaderModule, ShaderReflection), ReflectError> {
+            let wgsl = variants::preprocess(source, defines)?;
+            let wgsl = overrides::specialize(&wgsl, &shader.constants)?;
+            let reflection = ShaderReflection::from_wgsl(&wgsl)?;
+            let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+            });
+            Ok((module, reflection))
+        };
+        let (module, mut reflection) = compile(&shader.source, &label)?;
+        let fragment_module = match &shader.fragment {
+            Some(source) => {
+                let (module, fragment) = compile(source, &format!("{} Fragment", label))?;
+                reflection = reflection.merged(fragment)?;
+                Some(module)
+            }
+            None => None,
+        };
+        let layout_label = format!("{} Material Bind Group Layout", label);
+        let layout = cache.bind_group_layout(device, Some(&layout_label), &reflection.layout_entries(MATERIAL_GROUP));
+        Ok(Variant {
+            shader: id,
+            label,
+            module,
+            fragment_module,
+            reflection,
+            layout,
+        })
+    }
+}
+
+/// Owns all shaders and materials, and caches the shader variants, bind groups
+/// and pipeline variants created for them.
+pub struct Materials {
+    shaders: Vec<Shader>,
+    variants: Vec<Variant>,
+    materials: Vec<Material>,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    defines: ShaderDefines, // For all shaders.
+    cache: Rc<DeviceCache>, // For the material bind group layouts.
+}
+
+impl Materials {
+    pub fn new(cache: Rc<DeviceCache>) -> Materials {
+        Materials {
+            shaders: Vec::new(),
+            variants: Vec::new(),
+            materials: Vec::new(),
+            pipelines: HashMap::new(),
+            defines: ShaderDefines::new(),
+            cache,
+        }
+    }
+
+    // Registers a shader, after compiling it with its defaults and the global defines to report
+    // errors right away.
+    pub fn add_shader(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        wgsl: &str,
+        defaults: ShaderDefines,
+    ) -> Result<ShaderId, ReflectError> {
+        self.add_shader_stages(device, label, wgsl, None, defaults)
+    }
+
+    // Registers a shader whose fragment stage is in a module of its own. `vertex` may contain both.
+    pub fn add_shader_stages(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        vertex: &str,
+        fragment: Option<&str>,
+        defaults: ShaderDefines,
+    ) -> Result<ShaderId, ReflectError> {
+        self.shaders.push(Shader {
+            label: label.to_string(),
+            source: vertex.to_string(),
+            fragment: fragment.map(str::to_string),
+            defaults,
+            constants: PipelineConstants::new(),
+            variants: HashMap::new(),
+        });
+        let id = ShaderId(self.shaders.len() - 1);
+        if let Err(e) = self.variant(device, id, &ShaderDefines::new()) {
+            self.shaders.pop();
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    // Replaces the source of a shader. Its variants are compiled again right away, its pipelines
+    // rebuilt and the bind groups of its materials recreated the next time they are prepared.
+    // If any variant fails to compile, the old shader stays in place.
+    pub fn reload_shader(
+        &mut self,
+        device: &wgpu::Device,
+        id: ShaderId,
+        wgsl: &str,
+        fragment: Option<&str>,
+    ) -> Result<(), ReflectError> {
+        let constants = self.shaders[id.0].constants.clone();
+        self.recompile(device, id, wgsl.to_string(), fragment.map(str::to_string), constants)
+    }
+
+    // Overrides constants of a shader, see overrides.rs. Like a reload, the old constants stay in
+    // place if any variant fails to compile with the new ones.
+    pub fn set_constants(&mut self, device: &wgpu::Device, id: ShaderId, constants: PipelineConstants) -> Result<(), ReflectError> {
+        let shader = &self.shaders[id.0];
+        let (source, fragment) = (shader.source.clone(), shader.fragment.clone());
+        self.recompile(device, id, source, fragment, constants)
+    }
+
+    fn recompile(
+        &mut self,
+        device: &wgpu::Device,
+        id: ShaderId,
+        source: String,
+        fragment: Option<String>,
+        constants: PipelineConstants,
+    ) -> Result<(), ReflectError> {
+        let shader = &self.shaders[id.0];
+        let recompiled = Shader {
+            label: shader.label.clone(),
+            source,
+            fragment,
+            defaults: shader.defaults.clone(),
+            constants,
+            variants: HashMap::new(),
+        };
+        let compiled = shader
+            .variants
+            .iter()
+            .map(|(defines, &variant)| Ok((variant, Variant::new(device, &self.cache, id, &recompiled, defines)?)))
+            .collect::<Result<Vec<_>, ReflectError>>()?;
+
+        let shader = &mut self.shaders[id.0];
+        shader.source = recompiled.source;
+        shader.fragment = recompiled.fragment;
+        shader.constants = recompiled.constants;
+        for (variant, compiled) in compiled {
+            self.variants[variant.0] = compiled;
+        }
+        let variants = &self.variants;
+        self.pipelines.retain(|key, _| variants[key.variant.0].shader != id);
+        for material in self.materials.iter_mut().filter(|material| material.shader == id) {
+            material.bind_groups.clear();
+        }
+        Ok(())
+    }
+
+    // The defines of every shader, e.g. quality settings. Materials select their variants again.
+    pub fn defines(&self) -> &ShaderDefines {
+        &self.defines
+    }
+
+    pub fn set_defines(&mut self, defines: ShaderDefines) {
+        self.defines = defines;
+        for material in &mut self.materials {
+            material.variants.clear();
+        }
+    }
+
+    // The variant of the shader for `defines` on top of its defaults and the global defines,
+    // compiled on first use.
+    fn variant(&mut self, device: &wgpu::Device, id: ShaderId, defines: &ShaderDefines) -> Result<VariantId, ReflectError> {
+        let shader = &self.shaders[id.0];
+        let defines = shader.defaults.merged(&self.defines).merged(defines);
+        if let Some(&variant) = shader.variants.get(&defines) {
+            return Ok(variant);
+        }
+        let variant = Variant::new(device, &self.cache, id, shader, &defines)?;
+        tracing::debug!("Compiled shader variant {}", variant.label);
+        self.variants.push(variant);
+        let variant = VariantId(self.variants.len() - 1);
+        self.shaders[id.0].variants.insert(defines, variant);
+        Ok(variant)
+    }
+
+    // The variant a material is drawn with for meshes of `vertex`, selected on first use.
+    fn material_variant(&mut self, device: &wgpu::Device, id: MaterialId, vertex: VertexLayout) -> Result<VariantId, ReflectError> {
+        let material = &self.materials[id.0];
+        if let Some(&variant) = material.variants.get(&vertex) {
+            return Ok(variant);
+        }
+        let (shader, defines) = (material.shader, material.defines.merged(&vertex.defines()));
+        let variant = self.variant(device, shader, &defines)?;
+        self.materials[id.0].variants.insert(vertex, variant);
+        Ok(variant)
+    }
+
+    // Registers a material after checking that it provides the resources its shader expects.
+    pub fn add_material(&mut self, device: &wgpu::Device, material: Material) -> Result<MaterialId, ReflectError> {
+        let variant = self.variant(device, material.shader, &material.defines)?;
+        self.validate(&material, variant)?;
+        self.materials.push(material);
+        Ok(MaterialId(self.materials.len() - 1))
+    }
+
+    fn validate(&self, material: &Material, variant: VariantId) -> Result<(), ReflectError> {
+        let bindings = self.variants[variant.0].reflection.group(MATERIAL_GROUP);
+        let (mut textures, mut samplers) = (0, 0);
+        for binding in bindings {
+            match binding.ty {
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    min_binding_size,
+                    ..
+                } => {
+                    let expected = min_binding_size.map_or(0, |size| size.get());
+                    if (material.params.len() as u64) < expected {
+                        return Err(ReflectError::BufferTooSmall {
+                            group: MATERIAL_GROUP,
+                            binding: binding.binding,
+                            expected,
+                            actual: material.params.len() as u64,
+                        });
+                    }
+                }
+                wgpu::BindingType::Texture { .. } => {
+                    textures += 1;
+                    if textures > material.textures.len() {
+                        return Err(ReflectError::MissingBinding {
+                            group: MATERIAL_GROUP,
+                            binding: binding.binding,
+                        });
+                    }
+                }
+                wgpu::BindingType::Sampler(_) => {
+                    samplers += 1;
+                    if samplers > material.textures.len() {
+                        return Err(ReflectError::MissingBinding {
+                            group: MATERIAL_GROUP,
+                            binding: binding.binding,
+                        });
+                    }
+                }
+                _ => {
+                    return Err(ReflectError::WrongResourceType {
+                        group: MATERIAL_GROUP,
+                        binding: binding.binding,
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: MaterialId) -> &Material {
+        &self.materials[id.0]
+    }
+
+    // Gives every material sampling `old` the `new` texture instead, e.g. a resized render target.
+    pub fn replace_texture(&mut self, old: &Rc<Texture>, new: &Rc<Texture>) {
+        for material in &mut self.materials {
+            for index in 0..material.textures.len() {
+                if Rc::ptr_eq(&material.textures[index], old) {
+                    material.set_texture(index, new.clone());
+                }
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, id: MaterialId) -> &mut Material {
+        &mut self.materials[id.0]
+    }
+
+    pub fn pipeline(&self, key: &PipelineKey) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(key)
+    }
+
+    // Makes sure the material has an up to date parameter buffer, and the shader variant, bind
+    // group and pipeline for drawing meshes of `vertex` into targets of `format` with `samples`.
+    // Everything is created on first use and cached afterwards. Returns the key of the pipeline.
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &SceneLayouts,
+        id: MaterialId,
+        format: wgpu::TextureFormat,
+        samples: u32,
+        vertex: VertexLayout,
+    ) -> Result<PipelineKey, ReflectError> {
+        let variant = self.material_variant(device, id, vertex)?;
+        // Parameters and textures may have changed since the material was added.
+        self.validate(&self.materials[id.0], variant)?;
+
+        let material = &mut self.materials[id.0];
+        let shader = &self.variants[variant.0];
+
+        // Parameter block
+        match &material.params_buffer {
+            Some(buffer) if material.params_dirty => queue.write_buffer(buffer, 0, &material.params),
+            Some(_) => {}
+            None => {
+                material.params_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Material {} Params Buffer", id.0)),
+                    contents: &material.params,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                }));
+            }
+        }
+        material.params_dirty = false;
+
+        // Bind group, with the material's resources assigned to the reflected bindings.
+        if !material.bind_groups.contains_key(&variant) {
+            let mut textures = material.textures.iter();
+            let mut samplers = material.textures.iter();
+            let entries: Vec<wgpu::BindGroupEntry> = shader
+                .reflection
+                .group(MATERIAL_GROUP)
+                .iter()
+                .map(|binding| wgpu::BindGroupEntry {
+                    binding: binding.binding,
+                    resource: match binding.ty {
+                        wgpu::BindingType::Texture { .. } => {
+                            wgpu::BindingResource::TextureView(&textures.next().unwrap().view)
+                        }
+                        wgpu::BindingType::Sampler(_) => {
+                            wgpu::BindingResource::Sampler(&samplers.next().unwrap().sampler)
+                        }
+                        _ => material.params_buffer.as_ref().unwrap().as_entire_binding(),
+                    },
+                })
+                .collect();
+            shader.reflection.validate_entries(MATERIAL_GROUP, &entries)?;
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!("Material {} Bind Group", id.0)),
+                layout: &shader.layout,
+                entries: &entries,
+            });
+            material.bind_groups.insert(variant, bind_group);
+        }
+
+        // Pipeline variant
+        let key = PipelineKey {
+            variant,
+            format,
+            samples,
+            vertex,
+        };
+        self.pipelines
+            .entry(key)
+            .or_insert_with(|| Self::create_pipeline(device, shader, layouts, &key));
+        Ok(key)
+    }
+
+    // Whether the pipeline of `key` samples the reflection probes, which are then bound in group(2).
+    pub fn uses_probes(&self, key: &PipelineKey) -> bool {
+        !self.variants[key.variant.0].reflection.group(PROBES_GROUP).is_empty()
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        shader: &Variant,
+        layouts: &SceneLayouts,
+        key: &PipelineKey,
+    ) -> wgpu::RenderPipeline {
+        let (format, samples) = (key.format, key.samples);
+        let vertex = key.vertex.desc();
+        // Handle to pipeline layout.
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline Layout", shader.label)),
+            bind_group_layouts: &if shader.reflection.group(PROBES_GROUP).is_empty() {
+                vec![layouts.camera, &shader.layout]
+            } else {
+                vec![layouts.camera, &shader.layout, layouts.probes]
+            },
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{} Pipeline ({:?}, {}x, {:?})", shader.label, format, samples, key.vertex)),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader.module,
+                entry_point: "vs_main",
+                // Type of vertices we want to pass to the vertex shader, and the model matrix per instance.
+                buffers: &[vertex.layout(), ModelInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader.fragment_module.as_ref().unwrap_or(&shader.module),
+                entry_point: "fs_main",
+                // The targets field tells wgpu what color outputs it should set up.
+                // Currently, we only need one for the surface.
+                targets: &[wgpu::ColorTargetState {
+                    format,                                 // Surface's format.
+                    blend: Some(wgpu::BlendState::REPLACE), // Replace old with new.
+                    write_mask: wgpu::ColorWrites::ALL, // write to all colors: red, blue, green, and alpha.
+                }],
+            }),
+            // The primitive field describes how to interpret our vertices when converting them into triangles.
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList, // Each three vertices will correspond to one triangle.
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw, // a triangle is facing forward if the vertices are arranged in a counter-clockwise direction.
+                cull_mode: Some(wgpu::Face::Back), // Not front-facing triangles are excluded from render (culled).
+                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                polygon_mode: wgpu::PolygonMode::Fill,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            // Nearer fragments hide farther ones.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: samples, // Samples per pixel, 1 without multisampling.
+                mask: !0, // Use all samples.
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+}
@@ -0,0 +1,131 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+use crate::bounds::Aabb;
+use crate::packing::PackedVertex;
+use crate::variants::ShaderDefines;
+use crate::vertex_layout::{VertexAttributes, VertexData};
+use crate::Vertex;
+
+/// How the vertices of a mesh are stored. Pipelines are created per layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VertexLayout {
+    Full,
+    // Half floats and 8-bit colors, see packing.rs.
+    Packed,
+    // The attributes the mesh was built with, see vertex_layout.rs.
+    Flexible(VertexAttributes),
+}
+
+impl VertexLayout {
+    pub fn desc(self) -> VertexBufferDesc {
+        let layout = match self {
+            VertexLayout::Full => Vertex::desc(),
+            VertexLayout::Packed => PackedVertex::desc(),
+            VertexLayout::Flexible(attributes) => {
+                return VertexBufferDesc {
+                    stride: attributes.stride(),
+                    attributes: attributes.attributes(),
+                }
+            }
+        };
+        VertexBufferDesc {
+            stride: layout.array_stride,
+            attributes: layout.attributes.to_vec(),
+        }
+    }
+
+    // The shader defines of meshes with this layout, see variants.rs. `Vertex` has a color in
+    // both of its layouts.
+    pub fn defines(self) -> ShaderDefines {
+        match self {
+            VertexLayout::Full => ShaderDefines::new().with_flag("VERTEX_COLOR", true),
+            VertexLayout::Packed => ShaderDefines::new()
+                .with_flag("VERTEX_COLOR", true)
+                .with_flag("PACKED_VERTICES", true),
+            VertexLayout::Flexible(attributes) => attributes.defines(),
+        }
+    }
+}
+
+/// A vertex buffer layout that owns its attributes, which flexible layouts build at runtime.
+pub struct VertexBufferDesc {
+    pub stride: wgpu::BufferAddress,
+    pub attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl VertexBufferDesc {
+    pub fn layout(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.stride,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+/// Vertex and index buffers of a piece of geometry, uploaded to the GPU.
+pub struct Mesh {
+    // Shared with the compute pass that writes it for skinned meshes, see skinning.rs.
+    pub vertex_buffer: Rc<wgpu::Buffer>,
+    pub index_buffer: Rc<wgpu::Buffer>, // Shared with the next version of a `DynamicMesh`.
+    pub num_indices: u32,
+    pub layout: VertexLayout,
+    // Around the vertices in model space, for culling and picking. `None` for meshes that change
+    // shape on the GPU, which are never culled.
+    pub bounds: Option<Aabb>,
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, label: &str, vertices: &[Vertex], indices: &[u16]) -> Mesh {
+        let bounds = vertex_bounds(vertices);
+        Self::from_bytes(device, label, bytemuck::cast_slice(vertices), indices, VertexLayout::Full, bounds)
+    }
+
+    // Compresses the vertices to half the size, see packing.rs.
+    pub fn new_packed(device: &wgpu::Device, label: &str, vertices: &[Vertex], indices: &[u16]) -> Mesh {
+        let packed: Vec<PackedVertex> = vertices.iter().map(PackedVertex::new).collect();
+        let bounds = vertex_bounds(vertices);
+        Self::from_bytes(device, label, bytemuck::cast_slice(&packed), indices, VertexLayout::Packed, bounds)
+    }
+
+    // A mesh with the attributes `vertices` has, see vertex_layout.rs.
+    pub fn with_attributes(device: &wgpu::Device, label: &str, vertices: &VertexData, indices: &[u16]) -> Mesh {
+        let bounds = Aabb::from_points(vertices.positions.iter().map(|&p| p.into()));
+        let layout = VertexLayout::Flexible(vertices.attributes());
+        Self::from_bytes(device, label, &vertices.interleave(), indices, layout, bounds)
+    }
+
+    fn from_bytes(
+        device: &wgpu::Device,
+        label: &str,
+        vertices: &[u8],
+        indices: &[u16],
+        layout: VertexLayout,
+        bounds: Option<Aabb>,
+    ) -> Mesh {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", label)),
+            contents: vertices,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", label)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Mesh {
+            vertex_buffer: Rc::new(vertex_buffer),
+            index_buffer: Rc::new(index_buffer),
+            num_indices: indices.len() as u32,
+            layout,
+            bounds,
+        }
+    }
+}
+
+fn vertex_bounds(vertices: &[Vertex]) -> Option<Aabb> {
+    Aabb::from_points(vertices.iter().map(|v| v.position.into()))
+}
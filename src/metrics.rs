@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::stats::RenderCounters;
+
+// Frame metrics
+//======================
+// For performance dashboards: with `--metrics <file>`, `App` records for every frame
+//  - the CPU time of the frame: events, updates and rendering, without the frame limiter's wait,
+//  - the GPU time of each pass, if the adapter supports timestamp queries (see gpu_timer.rs),
+//  - the draw calls and triangles,
+//  - the memory in use: the resident memory of the process, and the number of loaded assets.
+// The samples are written to the file when the app exits, and when the `flush_metrics` key
+// (F6 by default) is pressed. A `.json` file gets an array of objects, anything else CSV with
+// one column per GPU pass. Every flush rewrites the whole file.
+
+/// The metrics of one frame.
+#[derive(Clone, Debug, Serialize)]
+pub struct FrameMetrics {
+    pub frame: u64,
+    pub cpu_ms: f32,
+    pub gpu_ms: BTreeMap<&'static str, f32>, // Per pass, empty without timestamp queries.
+    pub draw_calls: u32,
+    pub triangles: u32,
+    pub memory_bytes: u64,
+    pub textures: usize,
+    pub meshes: usize,
+    pub shaders: usize,
+}
+
+impl FrameMetrics {
+    pub fn new(frame: u64, cpu_ms: f32, counters: RenderCounters) -> FrameMetrics {
+        FrameMetrics {
+            frame,
+            cpu_ms,
+            gpu_ms: BTreeMap::new(),
+            draw_calls: counters.draw_calls,
+            triangles: counters.triangles,
+            memory_bytes: process_memory(),
+            textures: 0,
+            meshes: 0,
+            shaders: 0,
+        }
+    }
+}
+
+/// Collects frame metrics and writes them to a file, see above.
+pub struct MetricsSink {
+    path: PathBuf,
+    samples: Vec<FrameMetrics>,
+}
+
+impl MetricsSink {
+    pub fn new(path: impl Into<PathBuf>) -> MetricsSink {
+        MetricsSink {
+            path: path.into(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, metrics: FrameMetrics) {
+        self.samples.push(metrics);
+    }
+
+    pub fn samples(&self) -> &[FrameMetrics] {
+        &self.samples
+    }
+
+    // Writes all samples recorded so far.
+    pub fn flush(&self) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        let json = self.path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        if json {
+            serde_json::to_writer_pretty(&mut file, &self.samples)?;
+        } else {
+            self.write_csv(&mut file)?;
+        }
+        file.flush()
+    }
+
+    fn write_csv(&self, out: &mut impl Write) -> std::io::Result<()> {
+        // A column for every pass that was timed in any frame.
+        let mut passes: Vec<&'static str> = self.samples.iter().flat_map(|s| s.gpu_ms.keys().copied()).collect();
+        passes.sort_unstable();
+        passes.dedup();
+
+        write!(out, "frame,cpu_ms")?;
+        for pass in &passes {
+            write!(out, ",gpu_{}_ms", pass)?;
+        }
+        writeln!(out, ",draw_calls,triangles,memory_bytes,textures,meshes,shaders")?;
+        for sample in &self.samples {
+            write!(out, "{},{:.3}", sample.frame, sample.cpu_ms)?;
+            for pass in &passes {
+                match sample.gpu_ms.get(pass) {
+                    Some(ms) => write!(out, ",{:.3}", ms)?,
+                    None => write!(out, ",")?,
+                }
+            }
+            writeln!(
+                out,
+                ",{},{},{},{},{},{}",
+                sample.draw_calls, sample.triangles, sample.memory_bytes, sample.textures, sample.meshes, sample.shaders
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Resident memory of the process in bytes, zero where unknown.
+#[cfg(windows)]
+fn process_memory() -> u64 {
+    use windows::Win32::System::ProcessStatus::{K32GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+    use windows::Win32::System::Threading::GetCurrentProcess;
+
+    let mut counters = PROCESS_MEMORY_COUNTERS::default();
+    let size = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+    let ok = unsafe { K32GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, size) };
+    if ok.as_bool() {
+        counters.WorkingSetSize as u64
+    } else {
+        0
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_memory() -> u64 {
+    // The second field of statm is the resident set, in pages.
+    let pages = std::fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+        .unwrap_or(0);
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    pages * page_size.max(0) as u64
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn process_memory() -> u64 {
+    0
+}
@@ -0,0 +1,236 @@
+use std::rc::Rc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, SquareMatrix, Transform, Vector3, Vector4};
+
+use crate::camera::{Camera, OPENGL_TO_WGPU_MATRIX};
+use crate::device_cache::DeviceCache;
+use crate::gfx::create_depth_view;
+use crate::layers::RenderLayers;
+use crate::material::MaterialId;
+use crate::texture::Texture;
+
+// Planar mirrors
+//======================
+// Flat reflective surfaces (mirrors, water, polished floors) reflect exactly what a camera would
+// see from the other side of their plane. Every frame, before the scene, each mirror renders the
+// scene through one of the GFX cameras mirrored through its plane into a texture of its own, and
+// the surfaces in the plane sample that texture where they are on screen:
+//
+//     let mirror = gfx.add_mirror(Mirror::new(Point3::new(0.0, -0.5, 0.0), Vector3::unit_y()));
+//     let material = gfx.add_mirror_material(mirror, Vector4::new(0.8, 0.8, 0.9, 1.0), 0.7)?;
+//
+// The mirrored camera must not see what is behind the mirror, which would show up in the
+// reflection. Instead of a clip plane in every material shader, the near plane of its projection
+// is replaced by the mirror's plane ("oblique near-plane clipping", Lengyel 2005), which skews the
+// depth range a little but clips for free.
+//
+// Mirroring turns the scene inside out, so the triangles it faces would be culled as back faces.
+// The mirrored image is flipped along x, which turns them back, and the mirror material flips it
+// again when sampling, see `PLANAR_MIRROR` in shader.wgsl.
+//
+// A mirror's reflection is only right as seen by its camera, and mirrors don't show other mirrors,
+// nor themselves. A camera behind the mirror's plane sees nothing in it.
+
+/// A flat mirror: a plane through `point`, reflecting on the side `normal` points to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mirror {
+    pub point: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub resolution: (u32, u32), // Of its texture, in pixels.
+    pub camera: usize, // The index of the GFX camera the reflection is rendered for.
+    pub layers: RenderLayers, // Only renderables on one of these layers are reflected.
+}
+
+impl Mirror {
+    pub fn new(point: Point3<f32>, normal: Vector3<f32>) -> Mirror {
+        Mirror {
+            point,
+            normal: normal.normalize(),
+            resolution: (1024, 1024),
+            camera: 0,
+            layers: RenderLayers::ALL,
+        }
+    }
+
+    pub fn with_resolution(mut self, width: u32, height: u32) -> Mirror {
+        self.resolution = (width, height);
+        self
+    }
+
+    pub fn with_camera(mut self, camera: usize) -> Mirror {
+        self.camera = camera;
+        self
+    }
+
+    pub fn with_layers(mut self, layers: RenderLayers) -> Mirror {
+        self.layers = layers;
+        self
+    }
+
+    // The plane as (normal, distance), with the reflecting side positive.
+    fn plane(&self) -> Vector4<f32> {
+        let normal = self.normal.normalize();
+        normal.extend(-normal.dot(self.point.to_vec()))
+    }
+
+    // Mirrors points through the plane.
+    pub fn reflection_matrix(&self) -> Matrix4<f32> {
+        let p = self.plane();
+        let (n, d) = (p.truncate(), p.w);
+        #[rustfmt::skip]
+        let matrix = Matrix4::new(
+            1.0 - 2.0 * n.x * n.x, -2.0 * n.x * n.y, -2.0 * n.x * n.z, 0.0,
+            -2.0 * n.x * n.y, 1.0 - 2.0 * n.y * n.y, -2.0 * n.y * n.z, 0.0,
+            -2.0 * n.x * n.z, -2.0 * n.y * n.z, 1.0 - 2.0 * n.z * n.z, 0.0,
+            -2.0 * d * n.x, -2.0 * d * n.y, -2.0 * d * n.z, 1.0,
+        );
+        matrix
+    }
+
+    // The view projection matrix and the eye of `camera` mirrored through the plane, see above.
+    // `None` if the camera is behind the mirror.
+    pub fn mirrored_view_proj(&self, camera: &Camera) -> Option<(Matrix4<f32>, Point3<f32>)> {
+        let plane = self.plane();
+        if plane.dot(camera.eye.to_homogeneous()) <= 0.0 {
+            return None;
+        }
+        let reflection = self.reflection_matrix();
+        let view = Matrix4::look_at_rh(camera.eye, camera.target, camera.up) * reflection;
+        let mut proj: Matrix4<f32> = cgmath::perspective(cgmath::Deg(camera.fovy), camera.aspect, camera.znear, camera.zfar);
+
+        // The plane in view space, where the mirrored eye is at the origin, behind it.
+        let clip = view.invert()?.transpose() * plane;
+        // The corner of the view volume opposite the plane becomes the far corner, and the near
+        // plane the clip plane: the third row of the (OpenGL style) projection is replaced.
+        let corner = proj.invert()? * Vector4::new(clip.x.signum(), clip.y.signum(), 1.0, 1.0);
+        let c = clip * (2.0 / clip.dot(corner));
+        proj.x.z = c.x - proj.x.w;
+        proj.y.z = c.y - proj.y.w;
+        proj.z.z = c.z - proj.z.w;
+        proj.w.z = c.w - proj.w.w;
+
+        let flip_x = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        Some((flip_x * OPENGL_TO_WGPU_MATRIX * proj * view, reflection.transform_point(camera.eye)))
+    }
+}
+
+/// Identifies a mirror added with `GFX::add_mirror`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MirrorId(pub(crate) usize);
+
+struct MirrorSlot {
+    mirror: Mirror,
+    texture: Rc<Texture>,
+    depth_view: wgpu::TextureView,
+    materials: Vec<MaterialId>, // Sampling `texture`.
+}
+
+/// The mirrors and the textures they render into.
+pub struct Mirrors {
+    slots: Vec<Option<MirrorSlot>>, // By id; `None` where removed.
+    format: wgpu::TextureFormat, // Of the scene.
+}
+
+impl Mirrors {
+    pub fn new(format: wgpu::TextureFormat) -> Mirrors {
+        Mirrors { slots: Vec::new(), format }
+    }
+
+    pub fn add(&mut self, device: &wgpu::Device, cache: &DeviceCache, mirror: Mirror) -> MirrorId {
+        let slot = MirrorSlot {
+            texture: Rc::new(create_texture(device, cache, self.format, mirror.resolution)),
+            depth_view: create_depth_view(device, mirror.resolution, 1),
+            mirror,
+            materials: Vec::new(),
+        };
+        let index = match self.slots.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.slots[index] = Some(slot);
+        MirrorId(index)
+    }
+
+    pub fn get(&self, id: MirrorId) -> &Mirror {
+        &self.slot(id).mirror
+    }
+
+    // Returns true if the texture was recreated for a new resolution, see `materials`.
+    pub fn set(&mut self, device: &wgpu::Device, cache: &DeviceCache, id: MirrorId, mirror: Mirror) -> bool {
+        let format = self.format;
+        let slot = self.slots[id.0].as_mut().expect("mirror was removed");
+        let resized = slot.mirror.resolution != mirror.resolution;
+        if resized {
+            slot.texture = Rc::new(create_texture(device, cache, format, mirror.resolution));
+            slot.depth_view = create_depth_view(device, mirror.resolution, 1);
+        }
+        slot.mirror = mirror;
+        resized
+    }
+
+    pub fn remove(&mut self, id: MirrorId) {
+        self.slots[id.0] = None;
+    }
+
+    pub fn ids(&self) -> Vec<MirrorId> {
+        (0..self.slots.len()).filter(|&i| self.slots[i].is_some()).map(MirrorId).collect()
+    }
+
+    pub fn texture(&self, id: MirrorId) -> &Rc<Texture> {
+        &self.slot(id).texture
+    }
+
+    pub fn depth_view(&self, id: MirrorId) -> &wgpu::TextureView {
+        &self.slot(id).depth_view
+    }
+
+    // The materials sampling the mirror's texture, which must be given a new one when it is resized.
+    pub fn materials(&self, id: MirrorId) -> &[MaterialId] {
+        &self.slot(id).materials
+    }
+
+    pub fn add_material(&mut self, id: MirrorId, material: MaterialId) {
+        self.slots[id.0].as_mut().expect("mirror was removed").materials.push(material);
+    }
+
+    // Whether `material` samples any mirror, and must not be drawn into one.
+    pub fn is_mirror_material(&self, material: MaterialId) -> bool {
+        self.slots.iter().flatten().any(|slot| slot.materials.contains(&material))
+    }
+
+    fn slot(&self, id: MirrorId) -> &MirrorSlot {
+        self.slots[id.0].as_ref().expect("mirror was removed")
+    }
+}
+
+fn create_texture(device: &wgpu::Device, cache: &DeviceCache, format: wgpu::TextureFormat, (width, height): (u32, u32)) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Mirror Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = cache.sampler(device, &wgpu::SamplerDescriptor {
+        label: Some("Mirror Sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    Texture {
+        texture: Rc::new(texture),
+        view,
+        sampler,
+    }
+}
+
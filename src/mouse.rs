@@ -1,7 +1,40 @@
 use std::collections::VecDeque;
 
 static BUFFER_SIZE: usize = 16;
-static WHEEL_DELTA: usize = 120;
+// Wheel units per notch of a wheel, as in WM_MOUSEWHEEL. Precision touchpads send fractions of it.
+pub static WHEEL_DELTA: f32 = 120.0;
+// How far one line of scrolling moves, in pixels, to convert between lines and pixels.
+pub static PIXELS_PER_LINE: f32 = 20.0;
+
+/// How far the mouse moved and scrolled since the last `Mouse::take_frame_delta`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MouseDelta {
+    // From the cursor position, in pixels. Follows the pointer acceleration of the OS and stops
+    // at the edges of the screen: for dragging things around.
+    pub x: isize,
+    pub y: isize,
+    // From the device itself (raw input), in device units. Keeps going at the edges of the screen:
+    // for looking around with a camera. Always zero on X11, which has no raw input here.
+    pub raw_x: isize,
+    pub raw_y: isize,
+    // Scrolling, positive is up and right, with fractions from precision touchpads: in lines, one
+    // per notch of a wheel, for lists that scroll by rows, and in pixels, for smooth scrolling.
+    pub scroll_lines: (f32, f32),
+    pub scroll_pixels: (f32, f32),
+}
+
+impl std::ops::AddAssign for MouseDelta {
+    fn add_assign(&mut self, other: MouseDelta) {
+        self.x += other.x;
+        self.y += other.y;
+        self.raw_x += other.raw_x;
+        self.raw_y += other.raw_y;
+        self.scroll_lines.0 += other.scroll_lines.0;
+        self.scroll_lines.1 += other.scroll_lines.1;
+        self.scroll_pixels.0 += other.scroll_pixels.0;
+        self.scroll_pixels.1 += other.scroll_pixels.1;
+    }
+}
 
 pub struct Mouse {
     x: isize,
@@ -9,8 +42,13 @@ pub struct Mouse {
     left_is_pressed: bool,
     right_is_pressed: bool,
     is_in_window: bool,
-    wheel_delta_carry: usize,
+    // Scrolled lines that didn't add up to a whole WheelUp/WheelDown event yet.
+    wheel_carry: f32,
     buffer: VecDeque<Event>,
+    // The position of the last move, `None` after the cursor left the window, so that coming
+    // back elsewhere doesn't count as movement.
+    last_pos: Option<(isize, isize)>,
+    delta: MouseDelta,
 }
 
 impl Mouse {
@@ -21,8 +59,10 @@ impl Mouse {
             left_is_pressed: false,
             right_is_pressed: false,
             is_in_window: false,
-            wheel_delta_carry: 0,
-            buffer: VecDeque::<Event>::with_capacity(BUFFER_SIZE as usize),
+            wheel_carry: 0.0,
+            buffer: VecDeque::<Event>::with_capacity(BUFFER_SIZE),
+            last_pos: None,
+            delta: MouseDelta::default(),
         }
     }
 
@@ -62,18 +102,29 @@ impl Mouse {
         self.buffer.clear();
     }
 
+    // All movement since the last call, without draining the event buffer. `App` calls it once
+    // per frame and hands the result to the game as `Input::mouse_delta`.
+    pub fn take_frame_delta(&mut self) -> MouseDelta {
+        std::mem::take(&mut self.delta)
+    }
+
     pub fn on_mouse_move(&mut self, new_x: isize, new_y: isize) {
+        if let Some((x, y)) = self.last_pos {
+            self.delta.x += new_x - x;
+            self.delta.y += new_y - y;
+        }
+        self.last_pos = Some((new_x, new_y));
         self.x = new_x;
         self.y = new_y;
 
-        self.buffer.push_back(Event::new(EventType::Move, &self));
+        self.buffer.push_back(Event::new(EventType::Move, self));
         self.trim_buffer();
     }
 
     pub fn on_left_pressed(&mut self) {
         self.left_is_pressed = true;
 
-        self.buffer.push_back(Event::new(EventType::LPress, &self));
+        self.buffer.push_back(Event::new(EventType::LPress, self));
         self.trim_buffer();
 
     }
@@ -81,64 +132,100 @@ impl Mouse {
     pub fn on_left_released(&mut self) {
         self.left_is_pressed = false;
 
-        self.buffer.push_back(Event::new(EventType::LRelease, &self));
+        self.buffer.push_back(Event::new(EventType::LRelease, self));
         self.trim_buffer();
     }
 
     pub fn on_right_pressed(&mut self) {
         self.right_is_pressed = true;
 
-        self.buffer.push_back(Event::new(EventType::RPress, &self));
+        self.buffer.push_back(Event::new(EventType::RPress, self));
         self.trim_buffer();
     }
 
     pub fn on_right_released(&mut self) {
         self.right_is_pressed = false;
 
-        self.buffer.push_back(Event::new(EventType::RRelease, &self));
+        self.buffer.push_back(Event::new(EventType::RRelease, self));
         self.trim_buffer();
     }
 
     pub fn on_wheel_up(&mut self) {
-        self.buffer.push_back(Event::new(EventType::WheelUp, &self));
+        self.buffer.push_back(Event::new(EventType::WheelUp, self));
         self.trim_buffer();
     }
 
     pub fn on_wheel_down(&mut self) {
-        self.buffer.push_back(Event::new(EventType::WheelDown, &self));
+        self.buffer.push_back(Event::new(EventType::WheelDown, self));
         self.trim_buffer();
     }
 
-    pub fn on_wheel_delta(&mut self, x: isize, y: isize, delta: usize) {
-        self.wheel_delta_carry += delta;
-        while self.wheel_delta_carry >= WHEEL_DELTA {
-            self.wheel_delta_carry -= WHEEL_DELTA;
+    // Scrolling by lines, from wheels.
+    pub fn on_scroll_lines(&mut self, x: f32, y: f32) {
+        self.delta.scroll_lines.0 += x;
+        self.delta.scroll_lines.1 += y;
+        self.delta.scroll_pixels.0 += x * PIXELS_PER_LINE;
+        self.delta.scroll_pixels.1 += y * PIXELS_PER_LINE;
+        self.carry_wheel(y);
+    }
+
+    // Scrolling by pixels, from touchpads that report it that way (winit on macOS and Wayland).
+    pub fn on_scroll_pixels(&mut self, x: f32, y: f32) {
+        self.delta.scroll_lines.0 += x / PIXELS_PER_LINE;
+        self.delta.scroll_lines.1 += y / PIXELS_PER_LINE;
+        self.delta.scroll_pixels.0 += x;
+        self.delta.scroll_pixels.1 += y;
+        self.carry_wheel(y / PIXELS_PER_LINE);
+    }
+
+    // Every whole line becomes a WheelUp or WheelDown event, the rest carries over to the next
+    // delta. Turning around drops the rest, so the first line the other way isn't swallowed.
+    fn carry_wheel(&mut self, lines: f32) {
+        if lines * self.wheel_carry < 0.0 {
+            self.wheel_carry = 0.0;
+        }
+        self.wheel_carry += lines;
+        while self.wheel_carry >= 1.0 {
+            self.wheel_carry -= 1.0;
             self.on_wheel_up();
         }
-        while self.wheel_delta_carry <= WHEEL_DELTA {
-            self.wheel_delta_carry += WHEEL_DELTA;
+        while self.wheel_carry <= -1.0 {
+            self.wheel_carry += 1.0;
             self.on_wheel_down();
         }
     }
 
+    // Relative motion reported by the device, see `MouseDelta`.
+    pub fn on_raw_move(&mut self, dx: isize, dy: isize) {
+        self.delta.raw_x += dx;
+        self.delta.raw_y += dy;
+    }
+
     pub fn on_mouse_leave(&mut self) {
         self.is_in_window = false;
-        self.buffer.push_back(Event::new(EventType::Leave, &self));
+        self.last_pos = None;
+        self.buffer.push_back(Event::new(EventType::Leave, self));
         self.trim_buffer();
     }
 
     pub fn on_mouse_enter(&mut self) {
         self.is_in_window = true;
-        self.buffer.push_back(Event::new(EventType::Enter, &self));
+        self.buffer.push_back(Event::new(EventType::Enter, self));
         self.trim_buffer();
     }
 
     pub fn trim_buffer(&mut self) {
-        self.buffer.truncate(BUFFER_SIZE as usize)
+        self.buffer.truncate(BUFFER_SIZE)
     }
 
 }
 
+impl Default for Mouse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Event {
     event_type: EventType,
     x: isize,
@@ -163,7 +250,7 @@ impl Default for Event {
 impl Event {
     pub fn new(event_type: EventType, parent: &Mouse) -> Event {
         Event{
-            event_type: event_type,
+            event_type,
             x: parent.x,
             y: parent.y,
             left_is_pressed: parent.left_is_pressed,
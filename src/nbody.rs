@@ -0,0 +1,377 @@
+use cgmath::{Deg, Matrix4, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::compute::ComputeKernel;
+use crate::gfx::{self, GFX};
+use crate::reflection::ShaderReflection;
+use crate::render_graph::{NodeContext, RenderNode, Stage};
+use crate::rng::Rng;
+use crate::texture::Texture;
+use crate::uniform::UniformLayout;
+
+// N-body simulation
+//======================
+// A galaxy of bodies pulling on each other, simulated and drawn entirely on the GPU:
+//
+//     learn-wgpu --nbody 8192
+//
+// The bodies live in two storage buffers in the render graph resources: "nbody.positions" (xyz,
+// and the mass in w) and "nbody.velocities". Every frame, before the scene, `NBodySimulation`
+// sums the pull of all bodies on each (nbody.wgsl) and moves them. After the scene, `NBodyDraw`
+// draws the positions buffer as instanced, shaded spheres on top of it, seen from a camera
+// circling the galaxy.
+//
+// Summing over all pairs costs count² reads of the positions. With `tiled`, each workgroup loads
+// the positions into workgroup memory a tile at a time and every invocation reads them from
+// there, which is several times faster on most GPUs for large counts. Both give the same result;
+// compare with `--nbody-untiled`.
+//
+// The simulation advances by `dt` per rendered frame rather than with `update`, so it runs faster
+// at higher frame rates, which is fine for a demo.
+
+pub const POSITIONS: &str = "nbody.positions";
+pub const VELOCITIES: &str = "nbody.velocities";
+
+/// Workgroup size of the kernels, and the tile size of `accelerate_tiled`.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// See above.
+#[derive(Clone, Copy, Debug)]
+pub struct NBodySettings {
+    pub count: u32,
+    pub tiled: bool, // Load the positions through workgroup memory, see above.
+    pub dt: f32, // Simulated time per frame.
+    pub gravity: f32,
+    pub softening: f32, // Added to distances, so close encounters don't fling bodies away.
+    pub radius: f32, // Of the galaxy at the start, in world units.
+    pub body_radius: f32, // Drawn size of a body of mass 1.
+    pub seed: u64,
+}
+
+impl Default for NBodySettings {
+    fn default() -> Self {
+        NBodySettings {
+            count: 4096,
+            tiled: true,
+            dt: 0.01,
+            gravity: 1.0,
+            softening: 0.5,
+            radius: 40.0,
+            body_radius: 0.15,
+            seed: 1,
+        }
+    }
+}
+
+// Adds the simulation and the drawing of its bodies to the render graph, see above.
+pub fn add_nodes(gfx: &mut GFX, settings: NBodySettings) {
+    tracing::info!("N-body simulation of {} bodies ({})", settings.count, if settings.tiled { "tiled" } else { "untiled" });
+    gfx.add_render_node(Box::new(NBodySimulation::new(settings)));
+    gfx.add_render_node(Box::new(NBodyDraw::new(settings)));
+}
+
+uniform_struct! {
+    struct SimulationParams {
+        count: u32,
+        dt: f32,
+        gravity: f32,
+        softening: f32,
+    }
+}
+assert_uniform_size!(SimulationParams, 16);
+
+uniform_struct! {
+    struct DrawParams {
+        view: Matrix4<f32>,
+        projection: Matrix4<f32>,
+        radius: f32,
+    }
+}
+assert_uniform_size!(DrawParams, 144);
+
+// The starting positions and velocities: a disk of light bodies on circular orbits around a heavy
+// one in the middle.
+fn initial_bodies(settings: &NBodySettings) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+    let rng = Rng::new(settings.seed);
+    let count = settings.count as usize;
+    let center_mass = count as f32 * 0.5;
+    let mut positions = Vec::with_capacity(count);
+    let mut velocities = Vec::with_capacity(count);
+    positions.push([0.0, 0.0, 0.0, center_mass]);
+    velocities.push([0.0; 4]);
+    for _ in 1..count {
+        // Uniform over the disk, a little thicker near the middle.
+        let r = settings.radius * rng.next_f32().sqrt().max(0.05);
+        let angle = rng.range(0.0, std::f32::consts::TAU);
+        let thickness = 0.05 * settings.radius * (1.0 - r / settings.radius);
+        let (x, z, y) = (r * angle.cos(), r * angle.sin(), rng.range(-thickness, thickness));
+        let mass = rng.range(0.5, 2.0);
+        positions.push([x, y, z, mass]);
+
+        // Fast enough to orbit the mass inside the radius, the bodies of the disk spread evenly.
+        let inside = center_mass + (count as f32) * (r / settings.radius).powi(2);
+        let speed = (settings.gravity * inside / (r + settings.softening)).sqrt();
+        velocities.push([-angle.sin() * speed, 0.0, angle.cos() * speed, 0.0]);
+    }
+    (positions, velocities)
+}
+
+// The kernels, with a bind group each for the buffers, created on the first run.
+struct Kernels {
+    accelerate: ComputeKernel,
+    integrate: ComputeKernel,
+    accelerate_bind_group: wgpu::BindGroup,
+    integrate_bind_group: wgpu::BindGroup,
+    _params: wgpu::Buffer,
+}
+
+/// Moves the bodies one step, before the scene, see above.
+pub struct NBodySimulation {
+    settings: NBodySettings,
+    kernels: Option<Kernels>,
+}
+
+impl NBodySimulation {
+    pub fn new(settings: NBodySettings) -> NBodySimulation {
+        NBodySimulation { settings, kernels: None }
+    }
+
+    fn create_kernels(&self, ctx: &mut NodeContext) -> Kernels {
+        let settings = &self.settings;
+        let size = settings.count as wgpu::BufferAddress * 16;
+        let usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+        ctx.resources.ensure_buffer(ctx.device, POSITIONS, size, usage | wgpu::BufferUsages::VERTEX);
+        ctx.resources.ensure_buffer(ctx.device, VELOCITIES, size, usage);
+        let (positions, velocities) = initial_bodies(settings);
+        ctx.queue.write_buffer(ctx.resources.buffer(POSITIONS).unwrap(), 0, bytemuck::cast_slice(&positions));
+        ctx.queue.write_buffer(ctx.resources.buffer(VELOCITIES).unwrap(), 0, bytemuck::cast_slice(&velocities));
+
+        let params = SimulationParams {
+            count: settings.count,
+            dt: settings.dt,
+            gravity: settings.gravity,
+            softening: settings.softening,
+        };
+        let params = ctx.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("N-Body Params"),
+            contents: &params.to_uniform_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // The shaders are part of the crate, so failing to build them is a bug.
+        let wgsl = include_str!("nbody.wgsl");
+        let entry_point = if settings.tiled { "accelerate_tiled" } else { "accelerate" };
+        let accelerate = ComputeKernel::new(ctx.device, "N-Body Accelerate", wgsl, entry_point).expect("built-in kernel is valid");
+        let integrate = ComputeKernel::new(ctx.device, "N-Body Integrate", wgsl, "integrate").expect("built-in kernel is valid");
+        let entries = [
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: ctx.resources.buffer(POSITIONS).unwrap().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: ctx.resources.buffer(VELOCITIES).unwrap().as_entire_binding(),
+            },
+        ];
+        let accelerate_bind_group = accelerate.bind_group(ctx.device, 0, &entries).expect("N-body buffers match the kernel");
+        let integrate_bind_group = integrate.bind_group(ctx.device, 0, &entries).expect("N-body buffers match the kernel");
+        Kernels {
+            accelerate,
+            integrate,
+            accelerate_bind_group,
+            integrate_bind_group,
+            _params: params,
+        }
+    }
+}
+
+impl RenderNode for NBodySimulation {
+    fn name(&self) -> &str {
+        "N-Body Simulation"
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::BeforeScene
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![POSITIONS, VELOCITIES]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        if self.settings.count == 0 {
+            return;
+        }
+        if self.kernels.is_none() {
+            self.kernels = Some(self.create_kernels(ctx));
+        }
+        let kernels = self.kernels.as_ref().unwrap();
+        let workgroup = (WORKGROUP_SIZE, 1);
+        // Separate passes, so that all velocities are updated before any body moves.
+        kernels.accelerate.dispatch_2d(encoder, &[&kernels.accelerate_bind_group], workgroup, self.settings.count, 1);
+        kernels.integrate.dispatch_2d(encoder, &[&kernels.integrate_bind_group], workgroup, self.settings.count, 1);
+    }
+}
+
+// The pipeline for the frame's format, and its depth buffer for the frame's size.
+struct DrawState {
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    params: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    depth: Option<(wgpu::TextureView, (u32, u32))>,
+}
+
+/// Draws the bodies on top of the scene, see above.
+pub struct NBodyDraw {
+    settings: NBodySettings,
+    angle: f32, // Of the camera around the galaxy, in radians.
+    state: Option<DrawState>,
+}
+
+impl NBodyDraw {
+    pub fn new(settings: NBodySettings) -> NBodyDraw {
+        NBodyDraw {
+            settings,
+            angle: 0.0,
+            state: None,
+        }
+    }
+
+    fn create_state(device: &wgpu::Device, format: wgpu::TextureFormat) -> DrawState {
+        let wgsl = include_str!("nbody_draw.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("N-Body Draw"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("N-Body Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("N-Body Draw Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("N-Body Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                // A quad of 6 vertices per body, at the position read from the simulation's buffer.
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: 16,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Its own depth buffer, so the bodies hide each other but not the scene.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("N-Body Draw Params"),
+            size: DrawParams::SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("N-Body Draw Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            }],
+        });
+        DrawState {
+            pipeline,
+            format,
+            params,
+            bind_group,
+            depth: None,
+        }
+    }
+}
+
+impl RenderNode for NBodyDraw {
+    fn name(&self) -> &str {
+        "N-Body"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![POSITIONS]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (view, positions) = match (ctx.frame.view, ctx.resources.buffer(POSITIONS)) {
+            (Some(view), Some(positions)) => (view, positions),
+            _ => return,
+        };
+        let format = ctx.frame.format;
+        if self.state.as_ref().is_none_or(|state| state.format != format) {
+            self.state = Some(Self::create_state(ctx.device, format));
+        }
+        let state = self.state.as_mut().unwrap();
+        let (width, height) = ctx.frame.size;
+        if state.depth.as_ref().is_none_or(|(_, size)| *size != (width, height)) {
+            state.depth = Some((gfx::create_depth_view(ctx.device, (width, height), 1), (width, height)));
+        }
+
+        // Circling the galaxy slowly, a little above its plane.
+        self.angle += 0.002;
+        let distance = self.settings.radius * 2.5;
+        let eye = Point3::new(self.angle.sin() * distance, distance * 0.4, self.angle.cos() * distance);
+        let params = DrawParams {
+            view: Matrix4::look_at_rh(eye, Point3::new(0.0, 0.0, 0.0), Vector3::unit_y()),
+            projection: OPENGL_TO_WGPU_MATRIX
+                * cgmath::perspective(Deg(45.0), width as f32 / height.max(1) as f32, 0.1, distance * 4.0),
+            radius: self.settings.body_radius,
+        };
+        ctx.queue.write_buffer(&state.params, 0, &params.to_uniform_bytes());
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("N-Body Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &state.depth.as_ref().unwrap().0,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&state.pipeline);
+        render_pass.set_bind_group(0, &state.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, positions.slice(..));
+        render_pass.draw(0..6, 0..self.settings.count);
+    }
+}
@@ -0,0 +1,100 @@
+// Gravity between every pair of bodies, see nbody.rs. `accelerate` (or `accelerate_tiled`) updates
+// the velocities from the positions, then `integrate` moves the bodies, in separate passes so that
+// every body sees the positions of the same step.
+
+struct Params {
+    count: u32;
+    dt: f32;
+    gravity: f32;
+    softening: f32;
+};
+
+// xyz is the position, w the mass.
+struct Positions {
+    data: array<vec4<f32>>;
+};
+
+struct Velocities {
+    data: array<vec4<f32>>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var<storage, read_write> positions: Positions;
+[[group(0), binding(2)]]
+var<storage, read_write> velocities: Velocities;
+
+// The workgroup size, and the bodies loaded into workgroup memory at a time by `accelerate_tiled`.
+let TILE: u32 = 64u;
+
+var<workgroup> tile: array<vec4<f32>, 64>;
+
+// The pull of `other` on a body at `position`, per unit of gravity. The softening keeps close
+// encounters from flinging bodies away, and makes the pull of a body on itself 0.
+fn attraction(position: vec3<f32>, other: vec4<f32>) -> vec3<f32> {
+    let d = other.xyz - position;
+    let inv_distance = inverseSqrt(dot(d, d) + params.softening * params.softening);
+    return d * (other.w * inv_distance * inv_distance * inv_distance);
+}
+
+fn store_velocity(index: u32, acceleration: vec3<f32>) {
+    let velocity = velocities.data[index].xyz + acceleration * params.gravity * params.dt;
+    velocities.data[index] = vec4<f32>(velocity, 0.0);
+}
+
+// Every invocation reads every position from the storage buffer.
+[[stage(compute), workgroup_size(64)]]
+fn accelerate([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.count) {
+        return;
+    }
+    let position = positions.data[index].xyz;
+    var acceleration = vec3<f32>(0.0);
+    for (var i = 0u; i < params.count; i = i + 1u) {
+        acceleration = acceleration + attraction(position, positions.data[i]);
+    }
+    store_velocity(index, acceleration);
+}
+
+// The workgroup loads the positions a tile at a time, each invocation one of them, and every
+// invocation reads the tile from workgroup memory: a storage read per body and invocation
+// becomes one per body and workgroup.
+[[stage(compute), workgroup_size(64)]]
+fn accelerate_tiled(
+    [[builtin(global_invocation_id)]] id: vec3<u32>,
+    [[builtin(local_invocation_id)]] local: vec3<u32>,
+) {
+    let index = id.x;
+    // Invocations past the last body still load their part of the tiles and reach the barriers.
+    let position = positions.data[min(index, params.count - 1u)].xyz;
+    var acceleration = vec3<f32>(0.0);
+    for (var start = 0u; start < params.count; start = start + TILE) {
+        let other = start + local.x;
+        if (other < params.count) {
+            tile[local.x] = positions.data[other];
+        } else {
+            // Without mass, so it doesn't pull.
+            tile[local.x] = vec4<f32>(0.0);
+        }
+        workgroupBarrier();
+        for (var i = 0u; i < TILE; i = i + 1u) {
+            acceleration = acceleration + attraction(position, tile[i]);
+        }
+        workgroupBarrier();
+    }
+    if (index < params.count) {
+        store_velocity(index, acceleration);
+    }
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn integrate([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.count) {
+        return;
+    }
+    let body = positions.data[index];
+    positions.data[index] = vec4<f32>(body.xyz + velocities.data[index].xyz * params.dt, body.w);
+}
@@ -0,0 +1,51 @@
+// Draws the bodies of the N-body simulation as shaded spheres, on camera-facing quads. See nbody.rs.
+
+struct DrawParams {
+    view: mat4x4<f32>;
+    projection: mat4x4<f32>;
+    radius: f32; // Of a body of mass 1, in world units.
+};
+[[group(0), binding(0)]]
+var<uniform> params: DrawParams;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>; // -1 to 1 across the quad.
+    [[location(1)]] color: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] vertex_index: u32,
+    [[location(0)]] body: vec4<f32>, // Position and mass, per instance.
+) -> VertexOutput {
+    // Two triangles.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    // Heavier bodies are larger, by the cube root of the mass like spheres of the same density.
+    let radius = params.radius * pow(max(body.w, 0.0001), 1.0 / 3.0);
+    let center = params.view * vec4<f32>(body.xyz, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = params.projection * (center + vec4<f32>(corner * radius, 0.0, 0.0));
+    out.uv = corner;
+    // Light bodies are blue, heavy ones orange.
+    let heat = clamp(log2(max(body.w, 0.0001)) * 0.25 + 0.5, 0.0, 1.0);
+    out.color = mix(vec3<f32>(0.4, 0.6, 1.0), vec3<f32>(1.0, 0.6, 0.2), heat);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let r2 = dot(in.uv, in.uv);
+    if (r2 > 1.0) {
+        discard;
+    }
+    // The normal of the sphere seen through this pixel, lit from the upper left.
+    let normal = vec3<f32>(in.uv, sqrt(1.0 - r2));
+    let light = 0.35 + 0.65 * max(dot(normal, normalize(vec3<f32>(-0.4, 0.6, 0.7))), 0.0);
+    return vec4<f32>(in.color * light, 1.0);
+}
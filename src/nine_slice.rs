@@ -0,0 +1,435 @@
+use std::ops::Range;
+use std::path::Path;
+
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::device_cache::DeviceCache;
+use crate::texture::Texture;
+
+// Nine-slice panels
+//======================
+// UI panels and buttons drawn from a small image at any size. The image is cut into nine patches
+// by its borders: the corners are drawn as they are, the edges are stretched along one axis and
+// the center along both, so a 16x16 frame makes a panel of any size without blurring its corners:
+//
+//     let atlas = gfx.load_ui_atlas("res/ui.png")?;
+//     let button = NineSlice::new(atlas, [0, 0, 16, 16], [4, 4, 4, 4]);
+//     gfx.draw_panel(20.0, 20.0, 120.0, 32.0, &button, [1.0, 1.0, 1.0, 1.0]);
+//
+// Like text, panels are queued for a single frame, in pixels from the top left of the frame, and
+// drawn on top of the scene but below the text, in the order they were queued. Positions are
+// multiplied by the UI scale (`GFX::set_ui_scale`, e.g. the window's DPI factor) and then snapped
+// to whole pixels, so edges stay sharp and panels next to each other don't leave gaps.
+
+/// Identifies an image loaded with `GFX::load_ui_atlas`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UiAtlasId(usize);
+
+impl UiAtlasId {
+    // A single white texel, always there: panels of a flat color, see `NineSlice::solid`.
+    pub const WHITE: UiAtlasId = UiAtlasId(0);
+}
+
+/// A region of an atlas and the borders that cut it into nine patches, all in texels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NineSlice {
+    pub atlas: UiAtlasId,
+    pub region: [u32; 4], // x, y, width, height.
+    pub border: [u32; 4], // Left, top, right, bottom.
+    // Screen pixels per texel of the corners and edges, before the UI scale. Whole numbers keep
+    // pixel art crisp.
+    pub border_scale: f32,
+}
+
+impl NineSlice {
+    pub fn new(atlas: UiAtlasId, region: [u32; 4], border: [u32; 4]) -> NineSlice {
+        NineSlice {
+            atlas,
+            region,
+            border,
+            border_scale: 1.0,
+        }
+    }
+
+    // A rectangle of the color it is drawn with.
+    pub fn solid() -> NineSlice {
+        NineSlice::new(UiAtlasId::WHITE, [0, 0, 1, 1], [0, 0, 0, 0])
+    }
+
+    pub fn with_border_scale(mut self, border_scale: f32) -> NineSlice {
+        self.border_scale = border_scale;
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PatchInstance {
+    position: [f32; 2],
+    size: [f32; 2],
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    color: [f32; 4],
+}
+
+impl PatchInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x2, 1 => Float32x2, 2 => Float32x2, 3 => Float32x2, 4 => Float32x4
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PatchInstance>() as wgpu::BufferAddress,
+            // One patch per instance, the 6 vertices of its quad come from the vertex index.
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+struct UiAtlas {
+    bind_group: wgpu::BindGroup,
+    size: (f32, f32), // In texels.
+}
+
+/// Queues nine-slice panels every frame and draws them on top of the scene.
+pub struct NineSliceRenderer {
+    pipeline: wgpu::RenderPipeline,
+    projection_buffer: wgpu::Buffer,
+    projection_bind_group: wgpu::BindGroup,
+    projection_layout: wgpu::BindGroupLayout,
+    atlas_layout: wgpu::BindGroupLayout,
+    atlases: Vec<UiAtlas>,
+    scale: f32,
+    instances: Vec<PatchInstance>,
+    batches: Vec<(UiAtlasId, Range<u32>)>, // Consecutive patches from the same atlas.
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    drawn: Vec<(UiAtlasId, Range<u32>)>, // The batches uploaded by `prepare`.
+}
+
+impl NineSliceRenderer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, cache: &DeviceCache, format: wgpu::TextureFormat, samples: u32) -> NineSliceRenderer {
+        let projection_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Nine-Slice Projection Buffer"),
+            size: std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let projection_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Nine-Slice Projection Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let projection_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Nine-Slice Projection Bind Group"),
+            layout: &projection_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: projection_buffer.as_entire_binding(),
+            }],
+        });
+        let atlas_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("UI Atlas Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline = create_pipeline(device, &projection_layout, &atlas_layout, format, samples);
+
+        let instance_capacity = 64;
+        let mut renderer = NineSliceRenderer {
+            pipeline,
+            projection_buffer,
+            projection_bind_group,
+            projection_layout,
+            atlas_layout,
+            atlases: Vec::new(),
+            scale: 1.0,
+            instances: Vec::new(),
+            batches: Vec::new(),
+            instance_buffer: create_instance_buffer(device, instance_capacity),
+            instance_capacity,
+            drawn: Vec::new(),
+        };
+        let white = Texture::from_rgba8(device, queue, cache, "UI White", 1, 1, &[255; 4]);
+        renderer.add_atlas(device, cache, &white, (1, 1));
+        renderer
+    }
+
+    // Recreates the pipeline for a new sample count; the atlases are kept.
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        self.pipeline = create_pipeline(device, &self.projection_layout, &self.atlas_layout, format, samples);
+    }
+
+    pub fn load_atlas(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, cache: &DeviceCache, path: &Path) -> Result<UiAtlasId, image::ImageError> {
+        let image = image::open(path)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        let texture = Texture::from_rgba8(device, queue, cache, &path.to_string_lossy(), width, height, &image);
+        Ok(self.add_atlas(device, cache, &texture, (width, height)))
+    }
+
+    fn add_atlas(&mut self, device: &wgpu::Device, cache: &DeviceCache, texture: &Texture, (width, height): (u32, u32)) -> UiAtlasId {
+        // Nearest filtering keeps the corners crisp at whole-number border scales.
+        let sampler = cache.sampler(device, &wgpu::SamplerDescriptor {
+            label: Some("UI Atlas Sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("UI Atlas Bind Group"),
+            layout: &self.atlas_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        self.atlases.push(UiAtlas {
+            bind_group,
+            size: (width as f32, height as f32),
+        });
+        UiAtlasId(self.atlases.len() - 1)
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.max(f32::EPSILON);
+    }
+
+    // Queues a panel covering (`x`, `y`) to (`x + width`, `y + height`) for the next frame, its
+    // patches multiplied by `color`.
+    pub fn queue_panel(&mut self, x: f32, y: f32, width: f32, height: f32, slice: &NineSlice, color: [f32; 4]) {
+        let atlas = match self.atlases.get(slice.atlas.0) {
+            Some(atlas) => atlas,
+            None => return,
+        };
+        let first = self.instances.len() as u32;
+        self.instances.extend(patches(slice, atlas.size, self.scale, [x, y, width, height], color));
+        let end = self.instances.len() as u32;
+        if end == first {
+            return;
+        }
+        match self.batches.last_mut() {
+            Some((atlas, range)) if *atlas == slice.atlas && range.end == first => range.end = end,
+            _ => self.batches.push((slice.atlas, first..end)),
+        }
+    }
+
+    // Uploads the queued panels and the projection for a `width` x `height` frame, to be drawn by
+    // `draw` this frame.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        // Pixels, y down, to clip space; whole pixels fall on pixel edges.
+        let projection = OPENGL_TO_WGPU_MATRIX * cgmath::ortho(0.0, width as f32, height as f32, 0.0, -1.0, 1.0);
+        let matrix: [[f32; 4]; 4] = projection.into();
+        queue.write_buffer(&self.projection_buffer, 0, bytemuck::cast_slice(&[matrix]));
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.instances));
+        self.drawn = std::mem::take(&mut self.batches);
+        self.instances.clear();
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.drawn.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.projection_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        for (atlas, instances) in &self.drawn {
+            render_pass.set_bind_group(1, &self.atlases[atlas.0].bind_group, &[]);
+            render_pass.draw(0..6, instances.clone());
+        }
+    }
+}
+
+// The patches of `slice` covering `rect` (x, y, width and height before the UI `scale`), in an
+// atlas of `atlas_size` texels.
+fn patches(slice: &NineSlice, atlas_size: (f32, f32), scale: f32, rect: [f32; 4], color: [f32; 4]) -> Vec<PatchInstance> {
+    let [x, y, width, height] = rect;
+    // Snap the outline first, then fit the borders into it, so they don't move by a pixel
+    // relative to each other as the panel moves.
+    let (x0, y0) = ((x * scale).round(), (y * scale).round());
+    let (x1, y1) = (((x + width) * scale).round(), ((y + height) * scale).round());
+    if x1 <= x0 || y1 <= y0 {
+        return Vec::new();
+    }
+    let border_scale = slice.border_scale * scale;
+    let [left, top, right, bottom] = slice.border.map(|b| (b as f32 * border_scale).round());
+    // Panels smaller than their borders squeeze the borders and lose the middle.
+    let fit = |a: f32, b: f32, size: f32| {
+        if a + b <= size {
+            (a, b)
+        } else {
+            let a = (size * a / (a + b)).round();
+            (a, size - a)
+        }
+    };
+    let (left, right) = fit(left, right, x1 - x0);
+    let (top, bottom) = fit(top, bottom, y1 - y0);
+    let xs = [x0, x0 + left, x1 - right, x1];
+    let ys = [y0, y0 + top, y1 - bottom, y1];
+
+    let [rx, ry, rw, rh] = slice.region;
+    let [bl, bt, br, bb] = slice.border;
+    let us = [rx, rx + bl, (rx + rw).saturating_sub(br), rx + rw].map(|u| u as f32 / atlas_size.0);
+    let vs = [ry, ry + bt, (ry + rh).saturating_sub(bb), ry + rh].map(|v| v as f32 / atlas_size.1);
+
+    let mut patches = Vec::with_capacity(9);
+    for row in 0..3 {
+        for column in 0..3 {
+            let size = [xs[column + 1] - xs[column], ys[row + 1] - ys[row]];
+            if size[0] <= 0.0 || size[1] <= 0.0 {
+                continue;
+            }
+            patches.push(PatchInstance {
+                position: [xs[column], ys[row]],
+                size,
+                uv_min: [us[column], vs[row]],
+                uv_max: [us[column + 1], vs[row + 1]],
+                color,
+            });
+        }
+    }
+    patches
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    projection_layout: &wgpu::BindGroupLayout,
+    atlas_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Nine-Slice Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("nine_slice.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Nine-Slice Pipeline Layout"),
+        bind_group_layouts: &[projection_layout, atlas_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Nine-Slice Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[PatchInstance::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Nine-Slice Instance Buffer"),
+        size: (capacity * std::mem::size_of::<PatchInstance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [f32; 4] = [1.0; 4];
+
+    fn rects(patches: &[PatchInstance]) -> Vec<[f32; 4]> {
+        patches.iter().map(|p| [p.position[0], p.position[1], p.size[0], p.size[1]]).collect()
+    }
+
+    #[test]
+    fn corners_keep_their_size_and_the_rest_stretches() {
+        let slice = NineSlice::new(UiAtlasId(1), [16, 0, 16, 16], [4, 4, 4, 4]);
+        let patches = patches(&slice, (32.0, 16.0), 1.0, [10.0, 20.0, 100.0, 30.0], WHITE);
+        #[rustfmt::skip]
+        assert_eq!(rects(&patches), [
+            [10.0, 20.0, 4.0, 4.0], [14.0, 20.0, 92.0, 4.0], [106.0, 20.0, 4.0, 4.0],
+            [10.0, 24.0, 4.0, 22.0], [14.0, 24.0, 92.0, 22.0], [106.0, 24.0, 4.0, 22.0],
+            [10.0, 46.0, 4.0, 4.0], [14.0, 46.0, 92.0, 4.0], [106.0, 46.0, 4.0, 4.0],
+        ]);
+        // The patches cut the region at its borders.
+        assert_eq!((patches[0].uv_min, patches[0].uv_max), ([0.5, 0.0], [0.625, 0.25]));
+        assert_eq!((patches[4].uv_min, patches[4].uv_max), ([0.625, 0.25], [0.875, 0.75]));
+        assert_eq!((patches[8].uv_min, patches[8].uv_max), ([0.875, 0.75], [1.0, 1.0]));
+    }
+
+    #[test]
+    fn the_ui_scale_and_border_scale_snap_to_pixels() {
+        let slice = NineSlice::new(UiAtlasId(1), [0, 0, 16, 16], [4, 4, 4, 4]).with_border_scale(2.0);
+        let patches = patches(&slice, (32.0, 16.0), 1.5, [10.1, 10.1, 40.0, 40.0], WHITE);
+        // 10.1 * 1.5 rounds to 15, 50.1 * 1.5 to 75, and the borders are 4 * 2 * 1.5 pixels.
+        assert_eq!(rects(&patches)[0], [15.0, 15.0, 12.0, 12.0]);
+        assert_eq!(rects(&patches)[4], [27.0, 27.0, 36.0, 36.0]);
+        assert_eq!(rects(&patches)[8], [63.0, 63.0, 12.0, 12.0]);
+    }
+
+    #[test]
+    fn small_panels_squeeze_their_borders() {
+        let slice = NineSlice::new(UiAtlasId(1), [0, 0, 16, 16], [4, 4, 12, 4]);
+        let patches = patches(&slice, (32.0, 16.0), 1.0, [0.0, 0.0, 8.0, 40.0], WHITE);
+        // 8 pixels are split 1:3 between the left and right border, and the middle column is gone.
+        assert_eq!(patches.len(), 6);
+        assert_eq!(rects(&patches)[..2], [[0.0, 0.0, 2.0, 4.0], [2.0, 0.0, 6.0, 4.0]]);
+    }
+
+    #[test]
+    fn solid_panels_are_one_patch_and_empty_panels_none() {
+        let patches = patches(&NineSlice::solid(), (1.0, 1.0), 1.0, [5.0, 5.0, 20.0, 10.0], WHITE);
+        assert_eq!(rects(&patches), [[5.0, 5.0, 20.0, 10.0]]);
+        assert_eq!((patches[0].uv_min, patches[0].uv_max), ([0.0, 0.0], [1.0, 1.0]));
+        assert!(super::patches(&NineSlice::solid(), (1.0, 1.0), 1.0, [5.0, 5.0, 0.2, 10.0], WHITE).is_empty());
+    }
+}
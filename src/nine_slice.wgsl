@@ -0,0 +1,48 @@
+// Nine-slice panels: one instance per patch, a quad generated from the vertex index, see nine_slice.rs.
+
+struct Projection {
+    // From pixels, y down, to clip space.
+    matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> projection: Projection;
+[[group(1), binding(0)]]
+var t_atlas: texture_2d<f32>;
+[[group(1), binding(1)]]
+var s_atlas: sampler;
+
+struct PatchInput {
+    [[location(0)]] position: vec2<f32>; // Top left corner, in whole pixels.
+    [[location(1)]] size: vec2<f32>;
+    [[location(2)]] uv_min: vec2<f32>;
+    [[location(3)]] uv_max: vec2<f32>;
+    [[location(4)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32, patch: PatchInput) -> VertexOutput {
+    // Two triangles: (0, 0) (1, 0) (0, 1) and (0, 1) (1, 0) (1, 1).
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[index];
+
+    var out: VertexOutput;
+    out.clip_position = projection.matrix * vec4<f32>(patch.position + corner * patch.size, 0.0, 1.0);
+    out.uv = mix(patch.uv_min, patch.uv_max, corner);
+    out.color = patch.color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(t_atlas, s_atlas, in.uv) * in.color;
+}
@@ -0,0 +1,431 @@
+use std::rc::Rc;
+
+use cgmath::{Vector2, Vector3, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::compute::ComputeKernel;
+use crate::device_cache::DeviceCache;
+use crate::gfx::GFX;
+use crate::layers::RenderLayers;
+use crate::mesh::Mesh;
+use crate::texture::Texture;
+use crate::uniform::UniformLayout;
+use crate::vertex_layout::VertexData;
+
+// Procedural noise
+//======================
+// Textures of smooth randomness for terrain heights, cloud densities and variation in materials,
+// generated by a compute kernel (noise.wgsl):
+//
+//     let heights = gfx.generate_noise("Heights", &NoiseSettings::new(NoiseKind::Perlin), 512, 512);
+//
+// Three kinds of noise:
+//
+//  - Perlin: gradients at the corners of square cells, blended smoothly. The classic.
+//  - Simplex: gradients at the corners of triangles. Fewer grid artifacts along the axes.
+//  - Worley: the distance to the nearest of random points, one per cell. Cells, stones, the
+//    billows of clouds.
+//
+// Octaves are summed as fractal Brownian motion: each octave `lacunarity` times the frequency and
+// `gain` times the amplitude of the one before, so large shapes get ever finer detail. Each octave
+// is seeded differently, and the result is normalized to [0, 1], in the red, green and blue
+// channels of an `rgba8unorm` texture, which is linear rather than sRGB since it holds data.
+// The same seed gives the same noise, but the noise doesn't tile.
+//
+// `sample` computes the same noise on the CPU, for terrain collision, for placing objects on the
+// terrain, and to check the kernel against. GPUs round floats a little differently, so the two
+// agree to about 1e-3, not exactly; the 8-bit texture rounds to 1/255 anyway.
+
+pub const NOISE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+const WORKGROUP: (u32, u32) = (8, 8);
+
+/// The basis function of the noise, see above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Worley,
+}
+
+/// The parameters of a noise texture, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseSettings {
+    pub kind: NoiseKind,
+    pub seed: u32,
+    pub frequency: f32, // Cells of the first octave across the texture.
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+    pub offset: [f32; 2], // In cells of the first octave, e.g. to generate neighbouring tiles.
+}
+
+impl NoiseSettings {
+    pub fn new(kind: NoiseKind) -> NoiseSettings {
+        NoiseSettings {
+            kind,
+            seed: 0,
+            frequency: 8.0,
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+            offset: [0.0, 0.0],
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u32) -> NoiseSettings {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: f32) -> NoiseSettings {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> NoiseSettings {
+        self.octaves = octaves;
+        self
+    }
+}
+
+uniform_struct! {
+    struct NoiseParams {
+        offset: Vector2<f32>,
+        frequency: f32,
+        lacunarity: f32,
+        gain: f32,
+        seed: u32,
+        octaves: u32,
+        kind: u32,
+    }
+}
+assert_uniform_size!(NoiseParams, 32);
+
+/// Generates noise textures on the GPU, see above.
+pub struct NoiseGenerator {
+    kernel: Option<ComputeKernel>, // Created on first use.
+}
+
+impl NoiseGenerator {
+    pub fn new() -> NoiseGenerator {
+        NoiseGenerator { kernel: None }
+    }
+
+    // A `width` x `height` texture of noise, sampled with repeat addressing and linear filtering.
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &DeviceCache,
+        label: &str,
+        settings: &NoiseSettings,
+        width: u32,
+        height: u32,
+    ) -> Texture {
+        let kernel = self
+            .kernel
+            .get_or_insert_with(|| ComputeKernel::new(device, "Noise", include_str!("noise.wgsl"), "main").expect("built-in kernel is valid"));
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: NOISE_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let params = NoiseParams {
+            offset: settings.offset.into(),
+            frequency: settings.frequency,
+            lacunarity: settings.lacunarity,
+            gain: settings.gain,
+            seed: settings.seed,
+            octaves: settings.octaves.max(1),
+            kind: settings.kind as u32,
+        };
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Noise Params"),
+            contents: &params.to_uniform_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = kernel
+            .bind_group(
+                device,
+                0,
+                &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                ],
+            )
+            .expect("bindings match the built-in kernel");
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Noise Encoder"),
+        });
+        kernel.dispatch_2d(&mut encoder, &[&bind_group], WORKGROUP, width, height);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let sampler = cache.sampler(device, &wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Texture {
+            texture: Rc::new(texture),
+            view,
+            sampler,
+        }
+    }
+}
+
+impl Default for NoiseGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The noise at (u, v) of the texture, each from 0 to 1 across it, in [0, 1]. The CPU version of
+// the kernel, see above.
+pub fn sample(settings: &NoiseSettings, u: f32, v: f32) -> f32 {
+    let p = [u * settings.frequency + settings.offset[0], v * settings.frequency + settings.offset[1]];
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut amplitudes = 0.0;
+    let mut frequency = 1.0;
+    for octave in 0..settings.octaves.max(1) {
+        let p = [p[0] * frequency, p[1] * frequency];
+        let seed = settings.seed.wrapping_add(octave);
+        let value = match settings.kind {
+            NoiseKind::Perlin => perlin(p, seed),
+            NoiseKind::Simplex => simplex(p, seed),
+            NoiseKind::Worley => worley(p, seed),
+        };
+        total += value * amplitude;
+        amplitudes += amplitude;
+        amplitude *= settings.gain;
+        frequency *= settings.lacunarity;
+    }
+    (total / f32::max(amplitudes, 0.0001) * 0.5 + 0.5).clamp(0.0, 1.0)
+}
+
+// The noise at the center of every pixel of a `width` x `height` texture, row by row, as the
+// kernel computes it.
+pub fn generate_cpu(settings: &NoiseSettings, width: u32, height: u32) -> Vec<f32> {
+    let mut values = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            values.push(sample(settings, (x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32));
+        }
+    }
+    values
+}
+
+fn hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn hash_cell(cell: [i32; 2], seed: u32) -> u32 {
+    hash(cell[0] as u32 ^ hash(cell[1] as u32 ^ hash(seed)))
+}
+
+fn to_unit(value: u32) -> f32 {
+    (value >> 8) as f32 / 16777216.0
+}
+
+fn gradient(cell: [i32; 2], seed: u32) -> [f32; 2] {
+    let angle = (hash_cell(cell, seed) & 7) as f32 * std::f32::consts::FRAC_PI_4;
+    [angle.cos(), angle.sin()]
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn perlin(p: [f32; 2], seed: u32) -> f32 {
+    let cell = [p[0].floor() as i32, p[1].floor() as i32];
+    let f = [p[0] - p[0].floor(), p[1] - p[1].floor()];
+    let corner = |dx: i32, dy: i32| {
+        let gradient = gradient([cell[0].wrapping_add(dx), cell[1].wrapping_add(dy)], seed);
+        dot(gradient, [f[0] - dx as f32, f[1] - dy as f32])
+    };
+    let (u, v) = (fade(f[0]), fade(f[1]));
+    mix(mix(corner(0, 0), corner(1, 0), u), mix(corner(0, 1), corner(1, 1), u), v) * std::f32::consts::SQRT_2
+}
+
+fn simplex(p: [f32; 2], seed: u32) -> f32 {
+    let skew = (3.0f32.sqrt() - 1.0) / 2.0;
+    let unskew = (3.0 - 3.0f32.sqrt()) / 6.0;
+    let s = (p[0] + p[1]) * skew;
+    let cell = [(p[0] + s).floor(), (p[1] + s).floor()];
+    let t = (cell[0] + cell[1]) * unskew;
+    let x0 = [p[0] - cell[0] + t, p[1] - cell[1] + t];
+    let step = if x0[0] > x0[1] { [1.0, 0.0] } else { [0.0, 1.0] };
+    let x1 = [x0[0] - step[0] + unskew, x0[1] - step[1] + unskew];
+    let x2 = [x0[0] - 1.0 + 2.0 * unskew, x0[1] - 1.0 + 2.0 * unskew];
+    let i = [cell[0] as i32, cell[1] as i32];
+    let corners = [(i, x0), ([i[0] + step[0] as i32, i[1] + step[1] as i32], x1), ([i[0] + 1, i[1] + 1], x2)];
+    let mut total = 0.0;
+    for (corner, x) in corners {
+        let t = 0.5 - dot(x, x);
+        if t > 0.0 {
+            total += t * t * t * t * dot(gradient(corner, seed), x);
+        }
+    }
+    total * 70.0
+}
+
+fn worley(p: [f32; 2], seed: u32) -> f32 {
+    let cell = [p[0].floor() as i32, p[1].floor() as i32];
+    let mut nearest: f32 = 1.0;
+    for y in -1..=1 {
+        for x in -1..=1 {
+            let neighbour = [cell[0] + x, cell[1] + y];
+            let h = hash_cell(neighbour, seed);
+            let point = [neighbour[0] as f32 + to_unit(h), neighbour[1] as f32 + to_unit(hash(h))];
+            let d = [point[0] - p[0], point[1] - p[1]];
+            nearest = nearest.min(dot(d, d).sqrt());
+        }
+    }
+    nearest * 2.0 - 1.0
+}
+
+// Terrain demo
+//======================
+
+// Vertices along each side of a tile.
+const TILE_VERTICES: u32 = 48;
+const TILE_SIZE: f32 = 1.2;
+const TILE_HEIGHT: f32 = 0.5;
+
+// Three tiles of terrain behind the pentagon: Perlin, simplex and Worley noise, left to right. The
+// heights come from the CPU noise, and the same noise generated on the GPU glows on top of them
+// through the mesh's uvs, so the bright patches sit on the peaks wherever the two agree.
+pub fn add_terrain(gfx: &mut GFX) {
+    let tiles = [
+        NoiseSettings::new(NoiseKind::Perlin).with_octaves(5),
+        NoiseSettings::new(NoiseKind::Simplex).with_seed(7),
+        NoiseSettings::new(NoiseKind::Worley).with_frequency(4.0).with_octaves(2),
+    ];
+    for (i, settings) in tiles.iter().enumerate() {
+        let x = (i as f32 - 1.0) * (TILE_SIZE + 0.2);
+        let mesh = Mesh::with_attributes(gfx.device(), "Terrain", &terrain(settings, Vector3::new(x, -0.8, -1.5)), &terrain_indices());
+        let mesh = gfx.add_mesh(mesh);
+        let texture = gfx.generate_noise("Terrain Noise", settings, 256, 256);
+        let glow = gfx.texture(&texture).clone();
+        let material = gfx
+            .add_emissive_material(Vector4::new(1.0, 1.0, 1.0, 1.0), Vector3::new(0.5, 0.45, 0.3), 1.0, Some(glow))
+            .expect("the default shader has an emissive texture");
+        gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+    }
+}
+
+// A tile centered on `center`, its vertices at the pixel centers of a `TILE_VERTICES` square
+// texture of the noise, as `generate_cpu` samples it.
+fn terrain(settings: &NoiseSettings, center: Vector3<f32>) -> VertexData {
+    let heights = generate_cpu(settings, TILE_VERTICES, TILE_VERTICES);
+    let (mut positions, mut colors, mut uvs) = (Vec::new(), Vec::new(), Vec::new());
+    for y in 0..TILE_VERTICES {
+        for x in 0..TILE_VERTICES {
+            let uv = [(x as f32 + 0.5) / TILE_VERTICES as f32, (y as f32 + 0.5) / TILE_VERTICES as f32];
+            let height = heights[(y * TILE_VERTICES + x) as usize];
+            positions.push([
+                center.x + (uv[0] - 0.5) * TILE_SIZE,
+                center.y + height * TILE_HEIGHT,
+                center.z + (uv[1] - 0.5) * TILE_SIZE,
+            ]);
+            // From green valleys to white tops.
+            colors.push([0.2 + 0.8 * height * height, 0.35 + 0.6 * height, 0.2 + 0.8 * height * height]);
+            uvs.push(uv);
+        }
+    }
+    VertexData {
+        colors: Some(colors),
+        uvs: Some(uvs),
+        ..VertexData::new(positions)
+    }
+}
+
+// Two triangles per quad of the grid, counterclockwise seen from above.
+fn terrain_indices() -> Vec<u16> {
+    let n = TILE_VERTICES as u16;
+    let mut indices = Vec::new();
+    for y in 0..n - 1 {
+        for x in 0..n - 1 {
+            let (a, b, c, d) = (y * n + x, y * n + x + 1, (y + 1) * n + x, (y + 1) * n + x + 1);
+            indices.extend_from_slice(&[a, c, b, b, c, d]);
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [NoiseKind; 3] = [NoiseKind::Perlin, NoiseKind::Simplex, NoiseKind::Worley];
+
+    #[test]
+    fn the_same_seed_gives_the_same_noise() {
+        for kind in KINDS {
+            let settings = NoiseSettings::new(kind).with_seed(3);
+            assert_eq!(generate_cpu(&settings, 16, 16), generate_cpu(&settings, 16, 16));
+            assert_ne!(generate_cpu(&settings, 16, 16), generate_cpu(&settings.with_seed(4), 16, 16), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn the_noise_stays_in_range_and_varies() {
+        for kind in KINDS {
+            for octaves in [1, 4] {
+                let values = generate_cpu(&NoiseSettings::new(kind).with_octaves(octaves), 64, 64);
+                assert!(values.iter().all(|v| (0.0..=1.0).contains(v)), "{:?}", kind);
+                let (min, max) = values.iter().fold((1.0f32, 0.0f32), |(min, max), &v| (min.min(v), max.max(v)));
+                assert!(max - min > 0.3, "{:?} with {} octaves spans {}..{}", kind, octaves, min, max);
+            }
+        }
+    }
+
+    #[test]
+    fn perlin_is_flat_at_the_cell_corners() {
+        // The gradients are dotted with a zero offset there, whatever the seed.
+        let settings = NoiseSettings::new(NoiseKind::Perlin).with_octaves(1).with_frequency(4.0);
+        for seed in 0..4 {
+            assert_eq!(sample(&settings.with_seed(seed), 0.25, 0.5), 0.5);
+        }
+    }
+
+    #[test]
+    fn generate_cpu_samples_the_pixel_centers() {
+        let settings = NoiseSettings::new(NoiseKind::Simplex);
+        let values = generate_cpu(&settings, 4, 2);
+        assert_eq!(values.len(), 8);
+        assert_eq!(values[5], sample(&settings, 1.5 / 4.0, 1.5 / 2.0));
+    }
+}
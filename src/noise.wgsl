@@ -0,0 +1,136 @@
+// Procedural noise, see noise.rs, whose CPU versions of these functions must stay in step.
+
+struct NoiseParams {
+    offset: vec2<f32>; // Added to the coordinates, in cells of the first octave.
+    frequency: f32; // Cells of the first octave across the texture.
+    lacunarity: f32; // Frequency multiplier from one octave to the next.
+    gain: f32; // Amplitude multiplier from one octave to the next.
+    seed: u32;
+    octaves: u32;
+    kind: u32; // 0 Perlin, 1 simplex, 2 Worley.
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: NoiseParams;
+[[group(0), binding(1)]]
+var output: texture_storage_2d<rgba8unorm, write>;
+
+// PCG hash (Jarzynski and Olano, "Hash Functions for GPU Rendering").
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash_cell(cell: vec2<i32>, seed: u32) -> u32 {
+    return hash(u32(cell.x) ^ hash(u32(cell.y) ^ hash(seed)));
+}
+
+// In [0, 1), from 24 bits of the hash.
+fn to_unit(value: u32) -> f32 {
+    return f32(value >> 8u) / 16777216.0;
+}
+
+// One of 8 unit directions.
+fn gradient(cell: vec2<i32>, seed: u32) -> vec2<f32> {
+    let angle = f32(hash_cell(cell, seed) & 7u) * 0.785398163;
+    return vec2<f32>(cos(angle), sin(angle));
+}
+
+fn fade(t: vec2<f32>) -> vec2<f32> {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+// In [-1, 1].
+fn perlin(p: vec2<f32>, seed: u32) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    let a = dot(gradient(cell, seed), f);
+    let b = dot(gradient(cell + vec2<i32>(1, 0), seed), f - vec2<f32>(1.0, 0.0));
+    let c = dot(gradient(cell + vec2<i32>(0, 1), seed), f - vec2<f32>(0.0, 1.0));
+    let d = dot(gradient(cell + vec2<i32>(1, 1), seed), f - vec2<f32>(1.0, 1.0));
+    let u = fade(f);
+    // Unit gradients reach at most sqrt(1/2).
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y) * 1.41421356;
+}
+
+// In about [-1, 1]. Gustavson, "Simplex noise demystified".
+fn simplex(p: vec2<f32>, seed: u32) -> f32 {
+    let skew = 0.366025404; // (sqrt(3) - 1) / 2
+    let unskew = 0.211324865; // (3 - sqrt(3)) / 6
+    let cell = floor(p + (p.x + p.y) * skew);
+    let x0 = p - cell + (cell.x + cell.y) * unskew;
+    var step = vec2<f32>(0.0, 1.0);
+    if (x0.x > x0.y) {
+        step = vec2<f32>(1.0, 0.0);
+    }
+    let x1 = x0 - step + unskew;
+    let x2 = x0 - 1.0 + 2.0 * unskew;
+    let i = vec2<i32>(cell);
+    var total = 0.0;
+    let t0 = 0.5 - dot(x0, x0);
+    if (t0 > 0.0) {
+        total = total + t0 * t0 * t0 * t0 * dot(gradient(i, seed), x0);
+    }
+    let t1 = 0.5 - dot(x1, x1);
+    if (t1 > 0.0) {
+        total = total + t1 * t1 * t1 * t1 * dot(gradient(i + vec2<i32>(step), seed), x1);
+    }
+    let t2 = 0.5 - dot(x2, x2);
+    if (t2 > 0.0) {
+        total = total + t2 * t2 * t2 * t2 * dot(gradient(i + vec2<i32>(1, 1), seed), x2);
+    }
+    return total * 70.0;
+}
+
+// The distance to the nearest of one random point per cell, mapped from [0, 1] to [-1, 1].
+fn worley(p: vec2<f32>, seed: u32) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    var nearest = 1.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let neighbour = cell + vec2<i32>(x, y);
+            let h = hash_cell(neighbour, seed);
+            let point = vec2<f32>(neighbour) + vec2<f32>(to_unit(h), to_unit(hash(h)));
+            nearest = min(nearest, length(point - p));
+        }
+    }
+    return nearest * 2.0 - 1.0;
+}
+
+fn noise(p: vec2<f32>, seed: u32) -> f32 {
+    if (params.kind == 1u) {
+        return simplex(p, seed);
+    }
+    if (params.kind == 2u) {
+        return worley(p, seed);
+    }
+    return perlin(p, seed);
+}
+
+// Octaves summed and normalized back to [0, 1].
+fn fbm(p: vec2<f32>) -> f32 {
+    var total = 0.0;
+    var amplitude = 1.0;
+    var amplitudes = 0.0;
+    var frequency = 1.0;
+    for (var octave = 0u; octave < params.octaves; octave = octave + 1u) {
+        total = total + noise(p * frequency, params.seed + octave) * amplitude;
+        amplitudes = amplitudes + amplitude;
+        amplitude = amplitude * params.gain;
+        frequency = frequency * params.lacunarity;
+    }
+    return clamp(total / max(amplitudes, 0.0001) * 0.5 + 0.5, 0.0, 1.0);
+}
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(output);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+    let p = (vec2<f32>(pos) + 0.5) / vec2<f32>(size) * params.frequency + params.offset;
+    let value = fbm(p);
+    textureStore(output, pos, vec4<f32>(value, value, value, 1.0));
+}
@@ -0,0 +1,385 @@
+use cgmath::{Matrix4, Vector2};
+
+use crate::bounds::Aabb;
+use crate::compute::ComputeKernel;
+use crate::uniform::UniformLayout;
+
+// Occlusion culling
+//======================
+// Frustum culling (bvh.rs) drops what is outside a camera's view, but in dense scenes most of
+// what is inside is hidden behind something nearer. Rendering that anyway costs vertex work and
+// overdraw.
+//
+// After a camera drew its part of the frame, its depth is reduced into a "Hi-Z" pyramid: a mip
+// chain where every texel holds the farthest depth of the pixels below it. Before the camera
+// draws the next frame, a compute pass projects the bounds of everything it is about to draw with
+// the matrices of the last frame, looks up the pyramid level where the box covers at most 2x2
+// texels, and hides the object if its nearest point is behind the farthest depth there. Every
+// object is drawn with an indirect draw, and the compute pass sets the instance count of hidden
+// ones to 0, so the CPU never waits for the result.
+//
+// The test uses last frame's depth, so objects uncovered by a fast camera or a moving occluder may
+// pop in one frame late. Objects without bounds (skinned meshes) are always drawn.
+
+const PYRAMID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+const IMAGE_WORKGROUP: (u32, u32) = (8, 8);
+const CULL_WORKGROUP: (u32, u32) = (64, 1);
+
+uniform_struct! {
+    struct DepthParams {
+        origin: Vector2<i32>,
+    }
+}
+assert_uniform_size!(DepthParams, 8);
+
+uniform_struct! {
+    struct CullParams {
+        view_proj: Matrix4<f32>,
+        size: Vector2<f32>,
+        count: u32,
+        levels: u32,
+    }
+}
+assert_uniform_size!(CullParams, 80);
+
+/// An indexed draw of one instance the occlusion pass may skip.
+#[derive(Clone, Copy, Debug)]
+pub struct OcclusionDraw {
+    pub index_count: u32,
+    pub instance: u32,
+    pub bounds: Option<Aabb>, // In world space.
+}
+
+// The arguments of `draw_indexed_indirect`.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+// `Object` in occlusion.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuObject {
+    min: [f32; 4],
+    max: [f32; 4],
+}
+
+struct Pyramid {
+    size: (u32, u32),
+    levels: u32,
+    view: wgpu::TextureView,              // All levels, read by the culling pass.
+    level_views: Vec<wgpu::TextureView>,  // One per level, written while building.
+    downsample: Vec<wgpu::BindGroup>,     // Level `i` into level `i + 1`.
+    view_proj: Option<Matrix4<f32>>,      // Of the camera when it was built, `None` before that.
+}
+
+impl Pyramid {
+    fn new(device: &wgpu::Device, downsample: &ComputeKernel, size: (u32, u32)) -> Pyramid {
+        // Down to 1x1.
+        let levels = 32 - size.0.max(size.1).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Pyramid"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PYRAMID_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let level_views: Vec<wgpu::TextureView> = (0..levels)
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let downsample = level_views
+            .windows(2)
+            .map(|pair| {
+                downsample
+                    .bind_group(
+                        device,
+                        0,
+                        &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(&pair[0]),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::TextureView(&pair[1]),
+                            },
+                        ],
+                    )
+                    .expect("downsample bind group matches its shader")
+            })
+            .collect();
+        Pyramid {
+            size,
+            levels,
+            view,
+            level_views,
+            downsample,
+            view_proj: None,
+        }
+    }
+
+    fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.size.0 >> level).max(1), (self.size.1 >> level).max(1))
+    }
+}
+
+// The pyramid and draw buffers of one camera.
+struct CameraOcclusion {
+    pyramid: Option<Pyramid>,
+    draws: wgpu::Buffer,
+    objects: wgpu::Buffer,
+    capacity: usize, // Of the draw and object buffers.
+    depth_params: wgpu::Buffer,
+    cull_params: wgpu::Buffer,
+    cull_group: Option<wgpu::BindGroup>, // Recreated with the pyramid and buffers.
+}
+
+impl CameraOcclusion {
+    fn new(device: &wgpu::Device) -> CameraOcclusion {
+        // Written before every use.
+        let uniform = |label: &str, size: usize| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let (draws, objects) = create_draw_buffers(device, 1);
+        CameraOcclusion {
+            pyramid: None,
+            draws,
+            objects,
+            capacity: 1,
+            depth_params: uniform("Depth Pyramid Params", DepthParams::SIZE),
+            cull_params: uniform("Occlusion Params", CullParams::SIZE),
+            cull_group: None,
+        }
+    }
+}
+
+/// The depth pyramids of the cameras and the passes that build and test against them.
+pub struct Occlusion {
+    depth: Option<(u32, ComputeKernel)>, // For depth with this many samples per pixel.
+    downsample: ComputeKernel,
+    cull: ComputeKernel,
+    cameras: Vec<CameraOcclusion>, // By camera index, added on first use.
+}
+
+impl Occlusion {
+    // The shaders are part of the crate, so failing to build them is a bug.
+    pub fn new(device: &wgpu::Device) -> Occlusion {
+        let kernel = |label: &str, wgsl: &str| ComputeKernel::new(device, label, wgsl, "main").expect("built-in kernel is valid");
+        Occlusion {
+            depth: None,
+            downsample: kernel("Depth Pyramid Downsample", include_str!("hiz_downsample.wgsl")),
+            cull: kernel("Occlusion Culling", include_str!("occlusion.wgsl")),
+            cameras: Vec::new(),
+        }
+    }
+
+    // Uploads the draws of a camera for this frame, and records the pass that skips those hidden
+    // in its last frame. Until the camera has a pyramid, everything is drawn.
+    pub fn cull(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: usize,
+        draws: &[OcclusionDraw],
+    ) {
+        let cull = &self.cull;
+        let target = camera_occlusion(&mut self.cameras, device, camera);
+        if draws.len() > target.capacity {
+            target.capacity = draws.len().next_power_of_two();
+            (target.draws, target.objects) = create_draw_buffers(device, target.capacity);
+            target.cull_group = None;
+        }
+
+        let args: Vec<DrawIndexedIndirect> = draws
+            .iter()
+            .map(|draw| DrawIndexedIndirect {
+                index_count: draw.index_count,
+                instance_count: 1,
+                first_index: 0,
+                base_vertex: 0,
+                first_instance: draw.instance,
+            })
+            .collect();
+        queue.write_buffer(&target.draws, 0, bytemuck::cast_slice(&args));
+
+        let pyramid = match &target.pyramid {
+            Some(pyramid) if !draws.is_empty() => pyramid,
+            _ => return,
+        };
+        let view_proj = match pyramid.view_proj {
+            Some(view_proj) => view_proj,
+            None => return,
+        };
+        let objects: Vec<GpuObject> = draws
+            .iter()
+            .map(|draw| match draw.bounds {
+                Some(bounds) => GpuObject {
+                    min: [bounds.min.x, bounds.min.y, bounds.min.z, 0.0],
+                    max: [bounds.max.x, bounds.max.y, bounds.max.z, 0.0],
+                },
+                None => GpuObject {
+                    min: [0.0, 0.0, 0.0, 1.0],
+                    max: [0.0; 4],
+                },
+            })
+            .collect();
+        queue.write_buffer(&target.objects, 0, bytemuck::cast_slice(&objects));
+        let params = CullParams {
+            view_proj,
+            size: Vector2::new(pyramid.size.0 as f32, pyramid.size.1 as f32),
+            count: draws.len() as u32,
+            levels: pyramid.levels,
+        };
+        queue.write_buffer(&target.cull_params, 0, &params.to_uniform_bytes());
+
+        let cull_group = target.cull_group.get_or_insert_with(|| {
+            cull.bind_group(
+                device,
+                0,
+                &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: target.cull_params.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: target.objects.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: target.draws.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&pyramid.view),
+                    },
+                ],
+            )
+            .expect("occlusion bind group matches its shader")
+        });
+        cull.dispatch_2d(encoder, &[cull_group], CULL_WORKGROUP, draws.len() as u32, 1);
+    }
+
+    // Records draw `index` of those last passed to `cull` for the camera.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: usize, index: usize) {
+        let offset = (index * std::mem::size_of::<DrawIndexedIndirect>()) as wgpu::BufferAddress;
+        render_pass.draw_indexed_indirect(&self.cameras[camera].draws, offset);
+    }
+
+    // Records the passes that build the camera's pyramid from the depth it just drew into
+    // `viewport` (x, y, width, height in pixels) with `view_proj`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_pyramid(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: usize,
+        depth: &wgpu::TextureView,
+        samples: u32,
+        viewport: (u32, u32, u32, u32),
+        view_proj: Matrix4<f32>,
+    ) {
+        let (x, y, width, height) = viewport;
+        if self.depth.as_ref().map(|(s, _)| *s) != Some(samples) {
+            // The same shader reads multisampled depth and plain depth, which has one sample.
+            let wgsl = include_str!("hiz_depth.wgsl");
+            let wgsl = if samples > 1 {
+                wgsl.to_string()
+            } else {
+                wgsl.replace("texture_depth_multisampled_2d", "texture_depth_2d")
+                    .replace("textureNumSamples(depth)", "1")
+            };
+            let kernel = ComputeKernel::new(device, "Depth Pyramid Copy", &wgsl, "main").expect("built-in kernel is valid");
+            self.depth = Some((samples, kernel));
+        }
+        let (depth_kernel, downsample) = (&self.depth.as_ref().unwrap().1, &self.downsample);
+
+        let target = camera_occlusion(&mut self.cameras, device, camera);
+        if target.pyramid.as_ref().map(|pyramid| pyramid.size) != Some((width, height)) {
+            target.pyramid = Some(Pyramid::new(device, downsample, (width, height)));
+            target.cull_group = None;
+        }
+        let pyramid = target.pyramid.as_mut().unwrap();
+
+        let params = DepthParams {
+            origin: Vector2::new(x as i32, y as i32),
+        };
+        queue.write_buffer(&target.depth_params, 0, &params.to_uniform_bytes());
+        // The depth view changes with the surface size, so this bind group is made every frame.
+        let depth_group = depth_kernel
+            .bind_group(
+                device,
+                0,
+                &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(depth),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&pyramid.level_views[0]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: target.depth_params.as_entire_binding(),
+                    },
+                ],
+            )
+            .expect("depth pyramid bind group matches its shader");
+        depth_kernel.dispatch_2d(encoder, &[&depth_group], IMAGE_WORKGROUP, width, height);
+        for (level, bind_group) in (1..pyramid.levels).zip(&pyramid.downsample) {
+            let (width, height) = pyramid.level_size(level);
+            downsample.dispatch_2d(encoder, &[bind_group], IMAGE_WORKGROUP, width, height);
+        }
+        pyramid.view_proj = Some(view_proj);
+    }
+}
+
+// The state of `camera`, added on first use.
+fn camera_occlusion<'a>(cameras: &'a mut Vec<CameraOcclusion>, device: &wgpu::Device, camera: usize) -> &'a mut CameraOcclusion {
+    while cameras.len() <= camera {
+        cameras.push(CameraOcclusion::new(device));
+    }
+    &mut cameras[camera]
+}
+
+fn create_draw_buffers(device: &wgpu::Device, capacity: usize) -> (wgpu::Buffer, wgpu::Buffer) {
+    let draws = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Occlusion Draw Buffer"),
+        size: (capacity * std::mem::size_of::<DrawIndexedIndirect>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let objects = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Occlusion Object Buffer"),
+        size: (capacity * std::mem::size_of::<GpuObject>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    (draws, objects)
+}
@@ -0,0 +1,94 @@
+// Tests the bounds of the objects a camera is about to draw against the depth pyramid of its
+// last frame, and turns the indirect draws of those hidden behind it into draws of 0 instances.
+
+struct Params {
+    view_proj: mat4x4<f32>; // Of the camera when the pyramid was built.
+    size: vec2<f32>;        // Of the first level of the pyramid, in pixels.
+    count: u32;             // Objects to test.
+    levels: u32;            // Of the pyramid.
+};
+
+// World bounds. `min.w` is 1 for objects without bounds, which are never hidden.
+struct Object {
+    min: vec4<f32>;
+    max: vec4<f32>;
+};
+
+struct Objects {
+    objects: array<Object>;
+};
+
+// `DrawIndexedIndirect` arguments, 5 per object: index count, instance count, first index,
+// base vertex, first instance.
+struct Draws {
+    args: array<u32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var<storage, read> objects: Objects;
+[[group(0), binding(2)]]
+var<storage, read_write> draws: Draws;
+[[group(0), binding(3)]]
+var pyramid: texture_2d<f32>;
+
+fn is_hidden(object: Object) -> bool {
+    if (object.min.w > 0.0) {
+        return false;
+    }
+
+    // The rectangle and nearest depth the box covered on the screen.
+    var lo = vec3<f32>(1.0, 1.0, 1.0);
+    var hi = vec3<f32>(-1.0, -1.0, 0.0);
+    for (var i = 0; i < 8; i = i + 1) {
+        let corner = vec3<f32>(
+            select(object.min.x, object.max.x, (i & 1) != 0),
+            select(object.min.y, object.max.y, (i & 2) != 0),
+            select(object.min.z, object.max.z, (i & 4) != 0),
+        );
+        let clip = params.view_proj * vec4<f32>(corner, 1.0);
+        // Boxes reaching behind the camera cover the whole screen.
+        if (clip.w <= 0.0001) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        lo = min(lo, ndc);
+        hi = max(hi, ndc);
+    }
+    // Off screen last frame, nothing to compare with.
+    if (lo.x > 1.0 || lo.y > 1.0 || hi.x < -1.0 || hi.y < -1.0) {
+        return false;
+    }
+
+    // Pixels have y pointing down, normalized device coordinates have y pointing up.
+    let top_left = clamp(vec2<f32>(lo.x, -hi.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0)) * params.size;
+    let bottom_right = clamp(vec2<f32>(hi.x, -lo.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0)) * params.size;
+    let extent = max(bottom_right.x - top_left.x, bottom_right.y - top_left.y);
+
+    // The level where the rectangle covers at most 2x2 texels.
+    let level = min(u32(max(ceil(log2(max(extent, 1.0))), 0.0)), params.levels - 1u);
+    let level_size = textureDimensions(pyramid, i32(level));
+    let first = min(vec2<i32>(top_left) >> vec2<u32>(level), level_size - vec2<i32>(1, 1));
+    let last = min(vec2<i32>(bottom_right) >> vec2<u32>(level), level_size - vec2<i32>(1, 1));
+    var farthest = 0.0;
+    for (var y = first.y; y <= last.y; y = y + 1) {
+        for (var x = first.x; x <= last.x; x = x + 1) {
+            farthest = max(farthest, textureLoad(pyramid, vec2<i32>(x, y), i32(level)).r);
+        }
+    }
+    // Hidden if even its nearest point is behind everything drawn there.
+    return lo.z > farthest;
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.count) {
+        return;
+    }
+    let object = objects.objects[index];
+    if (is_hidden(object)) {
+        draws.args[index * 5u + 1u] = 0u;
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use cgmath::Point3;
+
+use crate::bounds::Aabb;
+use crate::gfx::RenderableId;
+use crate::texture::Texture;
+
+// Occlusion queries
+//======================
+// Whether something is hidden behind the scene, counted by the GPU while it draws: a query wraps
+// a draw and counts the fragments that pass the depth test. wgpu 0.12 can create occlusion query
+// sets but has no commands to begin them in a render pass, so the queries are pipeline statistics
+// queries of the fragment shader invocations instead (`Features::PIPELINE_STATISTICS_QUERY`,
+// Vulkan and DX12). The fragment shaders of the scene have no side effects, so the depth test
+// runs before them and fragments behind the depth buffer are not counted. Without the feature
+// there are no queries.
+//
+// A query counts either the draw of a renderable, or a proxy box drawn without color or depth for
+// gameplay checks like "can the player see the door?":
+//
+//     let query = gfx.add_occlusion_query(OcclusionTarget::Aabb(door_bounds)).expect("occlusion queries");
+//     ...
+//     if gfx.is_visible(query) == Some(true) { ... }
+//
+// Both are counted in the first camera, after its renderables, so a box is hidden by them but not
+// by the sky or the grid. Renderables the camera doesn't draw count 0, and a camera inside a box
+// sees it without counting, as the faces around it are behind the near plane.
+//
+// Like the GPU times (gpu_timer.rs), the counts are copied out at the end of the frame and picked
+// up once the GPU got there, so they lag a few frames behind; a new query has no result yet.
+
+pub const MAX_OCCLUSION_QUERIES: usize = 64;
+const RESULT_SIZE: u64 = wgpu::QUERY_SIZE as u64;
+// `BoxInput` in occlusion_query.wgsl, a min and a max corner.
+const BOX_SIZE: u64 = 24;
+// The corners of a cube as a triangle strip, see occlusion_query.wgsl.
+const BOX_VERTICES: u32 = 14;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Identifies a query added with `GFX::add_occlusion_query`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OcclusionQueryId(u32);
+
+/// What an occlusion query counts the visible fragments of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OcclusionTarget {
+    Renderable(RenderableId),
+    Aabb(Aabb), // A proxy box in world space, drawn only for the query.
+}
+
+struct Query {
+    target: OcclusionTarget,
+    fragments: Option<u64>, // Of the latest frame the GPU finished.
+}
+
+struct Readout {
+    buffer: wgpu::Buffer,
+    queries: Vec<OcclusionQueryId>, // By index in the query set, of the frame copied into it.
+    known: Vec<(OcclusionQueryId, u64)>, // Counted without the GPU in that frame.
+    map: Option<MapFuture>, // While the GPU has not finished that frame.
+}
+
+/// The occlusion queries of the first camera, see above.
+pub struct OcclusionQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readouts: Vec<Readout>,
+    current: usize,
+    queries: HashMap<OcclusionQueryId, Query>,
+    next_id: u32,
+    querying: bool, // False for frames whose readout is still in use.
+    drawn: bool, // Whether the first camera drew this frame.
+    // This frame's queries by index in the query set: first the boxes, then the renderables.
+    frame: Vec<OcclusionQueryId>,
+    known: Vec<(OcclusionQueryId, u64)>,
+    renderables: HashMap<RenderableId, u32>,
+    box_count: u32,
+    boxes: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+}
+
+impl OcclusionQueries {
+    // The device needs `Features::PIPELINE_STATISTICS_QUERY`, see `Capabilities::occlusion_queries`.
+    // `camera_layout` is that of the camera bind groups, which the boxes are drawn with.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        samples: u32,
+        frames_in_flight: usize,
+    ) -> OcclusionQueries {
+        let size = MAX_OCCLUSION_QUERIES as u64 * RESULT_SIZE;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Occlusion Queries"),
+            ty: wgpu::QueryType::PipelineStatistics(wgpu::PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS),
+            count: MAX_OCCLUSION_QUERIES as u32,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Query Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readouts = (0..frames_in_flight.max(1) + 1)
+            .map(|_| Readout {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Occlusion Query Readout Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                queries: Vec::new(),
+                known: Vec::new(),
+                map: None,
+            })
+            .collect();
+        let boxes = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Query Boxes"),
+            size: MAX_OCCLUSION_QUERIES as u64 * BOX_SIZE,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Occlusion Query Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("occlusion_query.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Occlusion Query Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, &shader, format, samples);
+        OcclusionQueries {
+            query_set,
+            resolve_buffer,
+            readouts,
+            current: 0,
+            queries: HashMap::new(),
+            next_id: 0,
+            querying: false,
+            drawn: false,
+            frame: Vec::new(),
+            known: Vec::new(),
+            renderables: HashMap::new(),
+            box_count: 0,
+            boxes,
+            pipeline,
+            pipeline_layout,
+            shader,
+        }
+    }
+
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, format, samples);
+    }
+
+    // `None` if all MAX_OCCLUSION_QUERIES are taken.
+    pub fn add(&mut self, target: OcclusionTarget) -> Option<OcclusionQueryId> {
+        if self.queries.len() >= MAX_OCCLUSION_QUERIES {
+            return None;
+        }
+        let id = OcclusionQueryId(self.next_id);
+        self.next_id += 1;
+        self.queries.insert(id, Query { target, fragments: None });
+        Some(id)
+    }
+
+    // Results of the query still on their way are dropped.
+    pub fn remove(&mut self, id: OcclusionQueryId) {
+        self.queries.remove(&id);
+    }
+
+    // Moves the query, e.g. a proxy box along with what it stands for. Keeps the last result until
+    // the new one arrives.
+    pub fn set_target(&mut self, id: OcclusionQueryId, target: OcclusionTarget) {
+        if let Some(query) = self.queries.get_mut(&id) {
+            query.target = target;
+        }
+    }
+
+    // The fragments that passed the depth test, per pixel rather than per sample, in the latest
+    // frame the GPU finished. `None` until then.
+    pub fn fragments(&self, id: OcclusionQueryId) -> Option<u64> {
+        self.queries.get(&id).and_then(|query| query.fragments)
+    }
+
+    // Picks up the counts of finished frames. Call before encoding a frame.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+        for readout in &mut self.readouts {
+            let done = match &mut readout.map {
+                Some(map) => poll_once(map),
+                None => continue,
+            };
+            match done {
+                Some(Ok(())) => {
+                    let data = readout.buffer.slice(..).get_mapped_range();
+                    let counts: &[u64] = bytemuck::cast_slice(&data);
+                    let results = readout.queries.iter().zip(counts.iter().copied()).chain(readout.known.iter().map(|(id, count)| (id, *count)));
+                    for (id, count) in results {
+                        if let Some(query) = self.queries.get_mut(id) {
+                            query.fragments = Some(count);
+                        }
+                    }
+                    drop(data);
+                    readout.buffer.unmap();
+                    readout.map = None;
+                    readout.queries.clear();
+                    readout.known.clear();
+                }
+                Some(Err(_)) => {
+                    readout.map = None;
+                    readout.queries.clear();
+                    readout.known.clear();
+                }
+                None => {}
+            }
+        }
+        self.frame.clear();
+        self.known.clear();
+        self.renderables.clear();
+        self.box_count = 0;
+        self.drawn = false;
+        // If the GPU is that far behind, skip querying this frame rather than wait.
+        self.querying = self.readouts[self.current].map.is_none();
+    }
+
+    // Assigns this frame's queries their place in the query set, seen from `eye`. `drawn` are the
+    // renderables the first camera draws.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, eye: Point3<f32>, drawn: &[RenderableId]) {
+        if !self.querying {
+            return;
+        }
+        let mut boxes: Vec<[f32; 6]> = Vec::new();
+        let mut renderables = Vec::new();
+        for (&id, query) in &self.queries {
+            match query.target {
+                OcclusionTarget::Aabb(aabb) if aabb.contains(eye) => self.known.push((id, 1)),
+                OcclusionTarget::Aabb(aabb) => {
+                    boxes.push([aabb.min.x, aabb.min.y, aabb.min.z, aabb.max.x, aabb.max.y, aabb.max.z]);
+                    self.frame.push(id);
+                }
+                OcclusionTarget::Renderable(renderable) if drawn.contains(&renderable) => renderables.push((renderable, id)),
+                OcclusionTarget::Renderable(_) => self.known.push((id, 0)),
+            }
+        }
+        self.box_count = boxes.len() as u32;
+        for (renderable, id) in renderables {
+            self.renderables.insert(renderable, self.frame.len() as u32);
+            self.frame.push(id);
+        }
+        if !boxes.is_empty() {
+            queue.write_buffer(&self.boxes, 0, bytemuck::cast_slice(&boxes));
+        }
+    }
+
+    // Begins the query of `renderable`, if it has one, before its draw in the first camera's pass.
+    // True if it did, and the query must be ended after the draw.
+    pub fn begin_renderable<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, renderable: RenderableId) -> bool {
+        match self.renderables.get(&renderable) {
+            Some(&index) => {
+                render_pass.begin_pipeline_statistics_query(&self.query_set, index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Draws the proxy boxes, after the renderables of the first camera's pass, with the camera's
+    // bind group set.
+    pub fn draw_boxes<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.box_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.boxes.slice(..));
+        for index in 0..self.box_count {
+            render_pass.begin_pipeline_statistics_query(&self.query_set, index);
+            render_pass.draw(0..BOX_VERTICES, index..index + 1);
+            render_pass.end_pipeline_statistics_query();
+        }
+    }
+
+    // Call after the first camera's pass, which began all of this frame's queries.
+    pub fn end_pass(&mut self) {
+        self.drawn = true;
+    }
+
+    // Copies the frame's counts out, at the end of its command buffer.
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.querying {
+            return;
+        }
+        // Without the first camera's pass, the queries were never begun.
+        if !self.drawn {
+            self.frame.clear();
+        }
+        let readout = &mut self.readouts[self.current];
+        if !self.frame.is_empty() {
+            let count = self.frame.len() as u32;
+            encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &readout.buffer, 0, count as u64 * RESULT_SIZE);
+        }
+        readout.queries = std::mem::take(&mut self.frame);
+        readout.known = std::mem::take(&mut self.known);
+    }
+
+    // Call after submitting the frame.
+    pub fn after_submit(&mut self) {
+        if !self.querying {
+            return;
+        }
+        let readout = &mut self.readouts[self.current];
+        if readout.queries.is_empty() && readout.known.is_empty() {
+            return;
+        }
+        readout.map = Some(Box::pin(readout.buffer.slice(..).map_async(wgpu::MapMode::Read)));
+        self.current = (self.current + 1) % self.readouts.len();
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Occlusion Query Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: BOX_SIZE,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::empty(),
+            }],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        // Tested against the renderables, and invisible: neither the depth nor the color is written.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+// Polls without a waker to notify; wgpu completes its futures from `Device::poll`.
+fn poll_once(future: &mut MapFuture) -> Option<Result<(), wgpu::BufferAsyncError>> {
+    let mut context = Context::from_waker(Waker::noop());
+    match future.as_mut().poll(&mut context) {
+        Poll::Ready(result) => Some(result),
+        Poll::Pending => None,
+    }
+}
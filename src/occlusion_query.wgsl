@@ -0,0 +1,31 @@
+// The proxy boxes of occlusion queries, see occlusion_queries.rs. Tested against the scene's
+// depth without writing it or the color: all that matters is how many fragments pass.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct BoxInput {
+    [[location(0)]] min: vec3<f32>;
+    [[location(1)]] max: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32, box: BoxInput) -> [[builtin(position)]] vec4<f32> {
+    // The corners of a cube as a strip of 14 vertices, one bit per vertex.
+    let bit = 1u << index;
+    let corner = vec3<f32>(
+        select(0.0, 1.0, (0x287au & bit) != 0u),
+        select(0.0, 1.0, (0x02afu & bit) != 0u),
+        select(0.0, 1.0, (0x31e3u & bit) != 0u),
+    );
+    return camera.view_proj * vec4<f32>(mix(box.min, box.max, corner), 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_main() -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(0.0);
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use crate::reflection::ReflectError;
+
+// Pipeline constants
+//======================
+// Kernels and shaders have numbers that are fixed for a pipeline but not for the program:
+// workgroup sizes that suit one GPU better than another, sample counts and other quality knobs.
+// This wgpu doesn't support WGSL's `override` declarations yet; instead any module-scope constant
+// can be overridden when the pipeline is created:
+//
+//     let SAMPLES: i32 = 8;                                     // In the shader.
+//
+//     let constants = PipelineConstants::new().with("SAMPLES", 16.0).with_workgroup_size([128, 1, 1]);
+//     let kernel = ComputeKernel::with_constants(device, "Blur", wgsl, "main", &constants)?;
+//
+// The source is parsed, the values are replaced in naga's IR, and the module is validated and
+// written back to WGSL for wgpu: no string templating, and nothing to change on the Rust side.
+// Values are converted to the type of the constant, integers are rounded and booleans are true for
+// anything but 0. Overriding a constant the shader doesn't have is an error, so typos don't go
+// unnoticed.
+
+/// Values for module-scope constants of a shader, by name, and the workgroup size of its compute
+/// entry points.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineConstants {
+    values: BTreeMap<String, f64>,
+    workgroup_size: Option<[u32; 3]>,
+}
+
+impl PipelineConstants {
+    pub fn new() -> PipelineConstants {
+        PipelineConstants::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: f64) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn with(mut self, name: &str, value: f64) -> PipelineConstants {
+        self.set(name, value);
+        self
+    }
+
+    pub fn set_workgroup_size(&mut self, size: [u32; 3]) {
+        self.workgroup_size = Some(size);
+    }
+
+    pub fn with_workgroup_size(mut self, size: [u32; 3]) -> PipelineConstants {
+        self.set_workgroup_size(size);
+        self
+    }
+
+    pub fn workgroup_size(&self) -> Option<[u32; 3]> {
+        self.workgroup_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.workgroup_size.is_none()
+    }
+}
+
+// The WGSL source with the constants replaced, see above.
+pub fn specialize(wgsl: &str, constants: &PipelineConstants) -> Result<String, ReflectError> {
+    if constants.is_empty() {
+        return Ok(wgsl.to_string());
+    }
+    let mut module = naga::front::wgsl::parse_str(wgsl).map_err(|e| ReflectError::Parse(e.emit_to_string(wgsl)))?;
+
+    for (name, &value) in &constants.values {
+        let handle = module
+            .constants
+            .iter()
+            .find(|(_, constant)| constant.name.as_deref() == Some(name.as_str()))
+            .map(|(handle, _)| handle)
+            .ok_or_else(|| ReflectError::UnknownConstant(name.clone()))?;
+        match &mut module.constants[handle].inner {
+            naga::ConstantInner::Scalar { value: scalar, .. } => {
+                *scalar = match scalar {
+                    naga::ScalarValue::Sint(_) => naga::ScalarValue::Sint(value.round() as i64),
+                    naga::ScalarValue::Uint(_) => naga::ScalarValue::Uint(value.round().max(0.0) as u64),
+                    naga::ScalarValue::Float(_) => naga::ScalarValue::Float(value),
+                    naga::ScalarValue::Bool(_) => naga::ScalarValue::Bool(value != 0.0),
+                }
+            }
+            naga::ConstantInner::Composite { .. } => {
+                return Err(ReflectError::Validation(format!("constant {} is not a scalar", name)))
+            }
+        }
+    }
+    if let Some(size) = constants.workgroup_size {
+        for entry_point in module.entry_points.iter_mut().filter(|e| e.stage == naga::ShaderStage::Compute) {
+            entry_point.workgroup_size = size;
+        }
+    }
+
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| ReflectError::Validation(format!("{:?}", e)))?;
+    naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())
+        .map_err(|e| ReflectError::Validation(e.to_string()))
+}
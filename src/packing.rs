@@ -0,0 +1,207 @@
+use crate::Vertex;
+
+// Vertex compression
+//======================
+// Large scenes are often limited by how fast the GPU can read vertices, not by how many it can
+// shade. Most attributes don't need 32-bit floats:
+//  - positions and texture coordinates fit in half floats (16 bits, 3 significant digits), which is
+//    plenty for models of up to a few hundred units around their origin,
+//  - normals and tangents are unit vectors, 10 bits per component is finer than lighting can show,
+//    with the 2 bits left for the handedness of the tangent frame,
+//  - vertex colors are 8 bits per channel.
+// Compression happens once, when the mesh is built (`Mesh::new_packed`, `VertexData::with_packing`
+// for flexible layouts, or `compress_vertices` in the config for loaded meshes and metaballs).
+// The GPU expands half floats and 8-bit colors on its own, so shaders don't change; 10-10-10-2
+// vectors arrive as a `u32` and are expanded by `unpack_snorm10x3_2` in the shaders that read
+// normals (shader.wgsl and selection.wgsl), with `PACKED_VERTICES` defined.
+//
+//     Vertex        position 3 x f32, color 3 x f32     24 bytes
+//     PackedVertex  position 4 x f16, color 4 x u8      12 bytes
+
+// The formats of packed attributes, see vertex_layout.rs.
+pub const POSITION_FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::Float16x4;
+pub const UV_FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::Float16x2;
+pub const NORMAL_FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::Uint32; // And tangents.
+pub const COLOR_FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::Unorm8x4;
+
+/// `Vertex` with half-float positions and 8-bit colors, see above.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedVertex {
+    position: [u16; 4], // w is 1.
+    color: [u8; 4],     // a is 1.
+}
+
+impl PackedVertex {
+    pub fn new(vertex: &Vertex) -> PackedVertex {
+        PackedVertex {
+            position: pack_position(vertex.position),
+            color: pack_color(vertex.color),
+        }
+    }
+
+    // The same shader locations as `Vertex::desc`, so the same shaders draw both.
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PackedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: POSITION_FORMAT,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[u16; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: COLOR_FORMAT,
+                },
+            ],
+        }
+    }
+}
+
+// Converts to a half float, rounding to the nearest, ties to even. Values too large for a half
+// become infinite.
+pub fn f16_from_f32(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+    // Infinity and NaN.
+    if exponent == 0xFF {
+        return sign | 0x7C00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1F {
+        return sign | 0x7C00;
+    }
+    if exponent <= 0 {
+        // Too small for a normal half: a subnormal with the implicit 1 in the mantissa, or zero.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        return sign | shift_rounded(mantissa, (14 - exponent) as u32) as u16;
+    }
+    // A carry out of the mantissa while rounding correctly bumps the exponent, up to infinity.
+    sign | shift_rounded(((exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+// `value >> shift`, rounded to the nearest, ties to even.
+fn shift_rounded(value: u32, shift: u32) -> u32 {
+    let half = 1 << (shift - 1);
+    let rest = value & ((1 << shift) - 1);
+    let shifted = value >> shift;
+    if rest > half || (rest == half && shifted & 1 == 1) {
+        shifted + 1
+    } else {
+        shifted
+    }
+}
+
+// A position in `POSITION_FORMAT`, with a w of 1.
+pub fn pack_position(position: [f32; 3]) -> [u16; 4] {
+    let [x, y, z] = position;
+    [f16_from_f32(x), f16_from_f32(y), f16_from_f32(z), f16_from_f32(1.0)]
+}
+
+// A color in `COLOR_FORMAT`, opaque.
+pub fn pack_color(color: [f32; 3]) -> [u8; 4] {
+    let [r, g, b] = color;
+    [unorm8(r), unorm8(g), unorm8(b), 255]
+}
+
+pub fn pack_uv(uv: [f32; 2]) -> [u16; 2] {
+    uv.map(f16_from_f32)
+}
+
+// Packs a unit vector into 10 bits per component, see `unpack_snorm10x3_2` in shader.wgsl.
+pub fn pack_normal(normal: [f32; 3]) -> u32 {
+    pack_snorm10x3_2(normal, 0.0)
+}
+
+// Packs a unit tangent and the handedness of the tangent frame (the sign of the bitangent).
+pub fn pack_tangent(tangent: [f32; 3], handedness: f32) -> u32 {
+    pack_snorm10x3_2(tangent, handedness.signum())
+}
+
+fn pack_snorm10x3_2(v: [f32; 3], w: f32) -> u32 {
+    let snorm = |value: f32, max: f32, bits: u32| {
+        let value = (value.clamp(-1.0, 1.0) * max).round() as i32;
+        (value as u32) & ((1 << bits) - 1)
+    };
+    snorm(v[0], 511.0, 10) | snorm(v[1], 511.0, 10) << 10 | snorm(v[2], 511.0, 10) << 20 | snorm(w, 1.0, 2) << 30
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zeros_keep_their_sign() {
+        assert_eq!(f16_from_f32(0.0), 0x0000);
+        assert_eq!(f16_from_f32(-0.0), 0x8000);
+    }
+
+    #[test]
+    fn small_values_become_subnormals() {
+        assert_eq!(f16_from_f32(2f32.powi(-14)), 0x0400); // The smallest normal half.
+        assert_eq!(f16_from_f32(2f32.powi(-24)), 0x0001); // The smallest subnormal.
+        assert_eq!(f16_from_f32(-2f32.powi(-24)), 0x8001);
+        assert_eq!(f16_from_f32(2f32.powi(-15) + 2f32.powi(-24)), 0x0201);
+        // Halfway to the smallest subnormal rounds to the even 0, just above it rounds up.
+        assert_eq!(f16_from_f32(2f32.powi(-25)), 0x0000);
+        assert_eq!(f16_from_f32(2f32.powi(-25) * 1.01), 0x0001);
+        assert_eq!(f16_from_f32(2f32.powi(-26)), 0x0000);
+        // 1.5 and 2.5 times the smallest subnormal both round to 2.
+        assert_eq!(f16_from_f32(3.0 * 2f32.powi(-25)), 0x0002);
+        assert_eq!(f16_from_f32(5.0 * 2f32.powi(-25)), 0x0002);
+    }
+
+    #[test]
+    fn ties_round_to_even() {
+        let ulp = 2f32.powi(-10); // Of halves between 1 and 2.
+        assert_eq!(f16_from_f32(1.0), 0x3C00);
+        assert_eq!(f16_from_f32(1.0 + ulp), 0x3C01);
+        assert_eq!(f16_from_f32(1.0 + 0.5 * ulp), 0x3C00);
+        assert_eq!(f16_from_f32(1.0 + 1.5 * ulp), 0x3C02);
+        assert_eq!(f16_from_f32(1.0 + 0.5 * ulp + 2f32.powi(-20)), 0x3C01);
+        assert_eq!(f16_from_f32(1.0 + 0.4 * ulp), 0x3C00);
+        // A carry out of the mantissa moves to the next exponent.
+        assert_eq!(f16_from_f32(2.0 - 0.5 * ulp), 0x4000);
+    }
+
+    #[test]
+    fn large_values_overflow_to_infinity() {
+        assert_eq!(f16_from_f32(65504.0), 0x7BFF); // The largest half.
+        assert_eq!(f16_from_f32(65519.0), 0x7BFF);
+        assert_eq!(f16_from_f32(65520.0), 0x7C00);
+        assert_eq!(f16_from_f32(1.0e6), 0x7C00);
+        assert_eq!(f16_from_f32(-1.0e6), 0xFC00);
+        assert_eq!(f16_from_f32(f32::INFINITY), 0x7C00);
+        assert_eq!(f16_from_f32(f32::NEG_INFINITY), 0xFC00);
+    }
+
+    #[test]
+    fn nan_stays_nan() {
+        for nan in [f32::NAN, -f32::NAN, f32::from_bits(0x7F80_0001)] {
+            let half = f16_from_f32(nan);
+            assert_eq!(half & 0x7C00, 0x7C00, "{:#x}", half);
+            assert_ne!(half & 0x03FF, 0, "{:#x}", half);
+        }
+    }
+
+    #[test]
+    fn unit_vectors_pack_into_10_bit_fields() {
+        assert_eq!(pack_normal([1.0, 0.0, 0.0]), 511);
+        assert_eq!(pack_normal([0.0, -1.0, 0.0]), 0x201 << 10);
+        assert_eq!(pack_normal([0.0, 0.0, 2.0]), 511 << 20);
+        assert_eq!(pack_tangent([0.0, 0.0, 0.0], 1.0), 1 << 30);
+        assert_eq!(pack_tangent([0.0, 0.0, 0.0], -1.0), 3 << 30);
+    }
+}
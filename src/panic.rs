@@ -0,0 +1,114 @@
+use std::backtrace::Backtrace;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(windows)]
+use windows::Win32::UI::Input::KeyboardAndMouse::ReleaseCapture;
+#[cfg(windows)]
+use windows::Win32::UI::WindowsAndMessaging::{ClipCursor, MessageBoxW, MB_ICONERROR, MB_OK, MB_TOPMOST};
+
+#[cfg(windows)]
+use crate::win32_common::WideString;
+
+// Panic handling
+//======================
+// A panic inside the window procedure cannot unwind into Windows, and a panic on the main thread
+// leaves a frozen, white window behind, possibly with the cursor still confined to it.
+// The hook installed here:
+//  1. prints the panic as usual,
+//  2. appends it, with a backtrace and the crash context (see crash.rs), to `crash.log`
+//     next to the executable, and writes a minidump,
+//  3. releases the cursor clip and mouse capture,
+//  4. shows the error in a message box, and aborts once it is closed.
+// Minidumps and steps 3 and 4 are Windows only; elsewhere the printed message has to do.
+// Crashes that are not panics go through crash.rs directly.
+
+const LOG_FILE: &str = "crash.log";
+
+pub fn install() {
+    crate::crash::install_exception_filter();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let dump = crate::crash::write_minidump();
+        let mut report = report(info);
+        if let Some(dump) = &dump {
+            report.push_str(&format!("Minidump: {}\n", dump.display()));
+        }
+        report.push_str(&crate::crash::context_report());
+        report.push('\n');
+        let log_path = log_path();
+        let logged = write_log(&log_path, &report).is_ok();
+
+        #[cfg(not(windows))]
+        if logged {
+            eprintln!("Details were written to {}", log_path.display());
+        }
+        #[cfg(windows)]
+        unsafe {
+            ClipCursor(std::ptr::null());
+            ReleaseCapture();
+
+            let mut text = panic_message(info);
+            if logged {
+                text.push_str(&format!("\n\nDetails were written to {}", log_path.display()));
+            }
+            // Keep the wide strings alive while MessageBoxW reads them.
+            let text = WideString::new(&text);
+            let caption = WideString::new("Unexpected error");
+            MessageBoxW(
+                0,
+                text.as_pwstr(),
+                caption.as_pwstr(),
+                MB_OK | MB_ICONERROR | MB_TOPMOST,
+            );
+        }
+        std::process::abort();
+    }));
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    };
+    match info.location() {
+        Some(location) => format!("{} ({}:{})", message, location.file(), location.line()),
+        None => message,
+    }
+}
+
+fn report(info: &PanicHookInfo) -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let thread = std::thread::current();
+    format!(
+        "=== panic at {} (seconds since 1970) on thread '{}' ===\n{}\n\n{}\n",
+        seconds,
+        thread.name().unwrap_or("<unnamed>"),
+        panic_message(info),
+        Backtrace::force_capture(),
+    )
+}
+
+// Next to the executable, or in the working directory if that is unknown.
+pub(crate) fn log_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(LOG_FILE)))
+        .unwrap_or_else(|| PathBuf::from(LOG_FILE))
+}
+
+pub(crate) fn write_log(path: &Path, report: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(report.as_bytes())
+}
@@ -0,0 +1,615 @@
+use cgmath::{MetricSpace, Point3, Vector3};
+
+use crate::game::Event;
+use crate::gfx::GFX;
+use crate::rng::Rng;
+use crate::texture::Texture;
+
+// CPU particles
+//======================
+// The compute examples (boids.rs, nbody.rs) keep their particles on the GPU, where the game can't
+// see them. These particles live on the CPU instead, in plain vectors the game can read and change
+// between updates: test them against the physics, kill them when they hit something, or spawn an
+// effect where they land. They are fine for the hundreds or few thousands of an effect, not for
+// the millions of a simulation.
+//
+//     let mut sparks = ParticleEmitter::new(EmitterSettings::sparks()).with_position(position);
+//     ...
+//     sparks.update(dt, input.rng);
+//     gfx.draw_particles(&[&sparks]); // Every frame, like the debug lines.
+//
+// An emitter spawns particles in a shape, `rate` per second and in bursts. Each lives for a random
+// lifetime, moves with its velocity, pulled by gravity and slowed by drag, and its size, color and
+// speed follow curves over its life, from 0 at birth to 1 at death.
+//
+// Particles are drawn as round sprites facing each camera that draws the default layer, one
+// instance each, after the scene and tested against its depth without writing it. They are blended
+// over what is behind them by the alpha of their color, premultiplied, so they are sorted back to
+// front, from the main camera: other cameras may see some of them in the wrong order.
+//
+// The pool of an emitter is allocated once for `max_particles`, dead particles are swapped out,
+// and an emitter at its limit skips spawning until some die.
+
+/// A value that changes over the life of a particle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T> {
+    keys: Vec<(f32, T)>, // Sorted by time, from 0 to 1.
+}
+
+pub trait CurveValue: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl CurveValue for f32 {
+    fn lerp(self, other: f32, t: f32) -> f32 {
+        self + (other - self) * t
+    }
+}
+
+impl CurveValue for [f32; 4] {
+    fn lerp(self, other: [f32; 4], t: f32) -> [f32; 4] {
+        [0, 1, 2, 3].map(|i| self[i].lerp(other[i], t))
+    }
+}
+
+impl<T: CurveValue> Curve<T> {
+    pub fn constant(value: T) -> Curve<T> {
+        Curve { keys: vec![(0.0, value)] }
+    }
+
+    // From `start` at birth to `end` at death.
+    pub fn linear(start: T, end: T) -> Curve<T> {
+        Curve {
+            keys: vec![(0.0, start), (1.0, end)],
+        }
+    }
+
+    // Keys at any times in [0, 1], in any order. Between them values are interpolated linearly,
+    // before the first and after the last they are held.
+    pub fn new(mut keys: Vec<(f32, T)>) -> Curve<T> {
+        assert!(!keys.is_empty(), "a curve needs at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Curve { keys }
+    }
+
+    pub fn sample(&self, t: f32) -> T {
+        let next = self.keys.partition_point(|(time, _)| *time <= t);
+        match next {
+            0 => self.keys[0].1,
+            n if n == self.keys.len() => self.keys[n - 1].1,
+            n => {
+                let ((t0, a), (t1, b)) = (self.keys[n - 1], self.keys[n]);
+                a.lerp(b, (t - t0) / (t1 - t0).max(f32::EPSILON))
+            }
+        }
+    }
+}
+
+/// Where an emitter spawns particles, and in which direction they start, around its position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmitterShape {
+    Point, // In every direction.
+    Sphere { radius: f32 }, // Anywhere inside, away from the center.
+    Cone { angle: f32, radius: f32 }, // From a disc, within `angle` degrees of up (+y).
+}
+
+impl EmitterShape {
+    // A position relative to the emitter and a unit direction.
+    fn sample(&self, rng: &Rng) -> (Vector3<f32>, Vector3<f32>) {
+        match *self {
+            EmitterShape::Point => (Vector3::new(0.0, 0.0, 0.0), random_direction(rng)),
+            EmitterShape::Sphere { radius } => {
+                let direction = random_direction(rng);
+                // The cube root spreads them evenly through the volume rather than at the center.
+                (direction * radius * rng.next_f32().cbrt(), direction)
+            }
+            EmitterShape::Cone { angle, radius } => {
+                let around = rng.range(0.0, std::f32::consts::TAU);
+                let distance = radius * rng.next_f32().sqrt();
+                let offset = Vector3::new(around.cos() * distance, 0.0, around.sin() * distance);
+                // Uniform over the cap of the sphere within `angle` of up.
+                let cos_max = angle.to_radians().cos();
+                let cos = rng.range(cos_max, 1.0);
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let around = rng.range(0.0, std::f32::consts::TAU);
+                (offset, Vector3::new(around.cos() * sin, cos, around.sin() * sin))
+            }
+        }
+    }
+}
+
+fn random_direction(rng: &Rng) -> Vector3<f32> {
+    let y = rng.range(-1.0, 1.0);
+    let around = rng.range(0.0, std::f32::consts::TAU);
+    let r = (1.0 - y * y).max(0.0).sqrt();
+    Vector3::new(around.cos() * r, y, around.sin() * r)
+}
+
+/// How an emitter spawns particles and how they behave, see above.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmitterSettings {
+    pub shape: EmitterShape,
+    pub rate: f32, // Particles per second, 0 for bursts only.
+    pub lifetime: (f32, f32), // The range of lifetimes, in seconds.
+    pub speed: (f32, f32), // The range of starting speeds, along the shape's direction.
+    pub gravity: Vector3<f32>,
+    pub drag: f32, // The fraction of the velocity lost per second.
+    pub size: Curve<f32>, // The width of the quads, in world units.
+    pub color: Curve<[f32; 4]>,
+    pub speed_over_lifetime: Curve<f32>, // Multiplies the velocity when moving.
+    pub max_particles: usize,
+}
+
+impl EmitterSettings {
+    // Rising flames, from yellow to a dark red.
+    pub fn fire() -> EmitterSettings {
+        EmitterSettings {
+            shape: EmitterShape::Cone { angle: 15.0, radius: 0.3 },
+            rate: 60.0,
+            lifetime: (0.6, 1.2),
+            speed: (0.8, 1.5),
+            gravity: Vector3::new(0.0, 1.0, 0.0),
+            drag: 0.5,
+            size: Curve::linear(0.4, 0.1),
+            color: Curve::new(vec![
+                (0.0, [1.0, 0.9, 0.4, 1.0]),
+                (0.4, [1.0, 0.4, 0.1, 0.8]),
+                (1.0, [0.4, 0.05, 0.0, 0.0]),
+            ]),
+            speed_over_lifetime: Curve::constant(1.0),
+            max_particles: 200,
+        }
+    }
+
+    // Slow, growing puffs of grey.
+    pub fn smoke() -> EmitterSettings {
+        EmitterSettings {
+            shape: EmitterShape::Cone { angle: 10.0, radius: 0.2 },
+            rate: 12.0,
+            lifetime: (2.5, 4.0),
+            speed: (0.4, 0.8),
+            gravity: Vector3::new(0.0, 0.2, 0.0),
+            drag: 0.3,
+            size: Curve::linear(0.3, 1.5),
+            color: Curve::new(vec![(0.0, [0.5, 0.5, 0.5, 0.0]), (0.2, [0.5, 0.5, 0.5, 0.5]), (1.0, [0.4, 0.4, 0.4, 0.0])]),
+            speed_over_lifetime: Curve::linear(1.0, 0.3),
+            max_particles: 100,
+        }
+    }
+
+    // Fast, small and falling, in every direction.
+    pub fn sparks() -> EmitterSettings {
+        EmitterSettings {
+            shape: EmitterShape::Point,
+            rate: 40.0,
+            lifetime: (0.4, 0.9),
+            speed: (3.0, 6.0),
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            drag: 0.1,
+            size: Curve::constant(0.05),
+            color: Curve::linear([1.0, 0.8, 0.3, 1.0], [1.0, 0.3, 0.0, 0.0]),
+            speed_over_lifetime: Curve::constant(1.0),
+            max_particles: 300,
+        }
+    }
+
+    // A single burst, see `ParticleEmitter::burst`.
+    pub fn explosion() -> EmitterSettings {
+        EmitterSettings {
+            shape: EmitterShape::Sphere { radius: 0.2 },
+            rate: 0.0,
+            lifetime: (0.5, 1.0),
+            speed: (4.0, 8.0),
+            gravity: Vector3::new(0.0, -2.0, 0.0),
+            drag: 2.0,
+            size: Curve::linear(0.5, 1.2),
+            color: Curve::new(vec![
+                (0.0, [1.0, 1.0, 0.8, 1.0]),
+                (0.3, [1.0, 0.5, 0.1, 0.8]),
+                (1.0, [0.2, 0.1, 0.1, 0.0]),
+            ]),
+            speed_over_lifetime: Curve::constant(1.0),
+            max_particles: 150,
+        }
+    }
+}
+
+/// A live particle. The game may move it, change its velocity or kill it between updates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle {
+    pub position: Vector3<f32>, // In world space.
+    pub velocity: Vector3<f32>,
+    pub age: f32, // In seconds since it was spawned.
+    pub lifetime: f32,
+    pub size: f32, // From the curves, as of the last update.
+    pub color: [f32; 4],
+}
+
+impl Particle {
+    // From 0 at birth to 1 at death.
+    pub fn life(&self) -> f32 {
+        (self.age / self.lifetime.max(f32::EPSILON)).min(1.0)
+    }
+
+    // Makes the next update remove it.
+    pub fn kill(&mut self) {
+        self.age = self.lifetime;
+    }
+}
+
+/// Spawns and moves particles, see above.
+pub struct ParticleEmitter {
+    pub settings: EmitterSettings,
+    pub position: Vector3<f32>,
+    pub emitting: bool, // Whether `rate` spawns particles. Bursts and live particles go on.
+    particles: Vec<Particle>,
+    spawn_debt: f32, // The fraction of a particle owed by the rate since the last spawn.
+}
+
+impl ParticleEmitter {
+    pub fn new(settings: EmitterSettings) -> ParticleEmitter {
+        ParticleEmitter {
+            particles: Vec::with_capacity(settings.max_particles),
+            settings,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            emitting: true,
+            spawn_debt: 0.0,
+        }
+    }
+
+    pub fn with_position(mut self, position: Vector3<f32>) -> ParticleEmitter {
+        self.position = position;
+        self
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn particles_mut(&mut self) -> &mut [Particle] {
+        &mut self.particles
+    }
+
+    // Whether it stopped emitting and all of its particles died, e.g. to drop a finished effect.
+    pub fn is_finished(&self) -> bool {
+        (!self.emitting || self.settings.rate <= 0.0) && self.particles.is_empty()
+    }
+
+    // Spawns `count` particles at once, as many as fit.
+    pub fn burst(&mut self, count: usize, rng: &Rng) {
+        for _ in 0..count {
+            self.spawn(rng);
+        }
+    }
+
+    // Ages, moves and removes particles, then spawns those owed by the rate.
+    pub fn update(&mut self, dt: f32, rng: &Rng) {
+        let settings = &self.settings;
+        let drag = (1.0 - settings.drag * dt).max(0.0);
+        let mut i = 0;
+        while i < self.particles.len() {
+            let particle = &mut self.particles[i];
+            particle.age += dt;
+            if particle.age >= particle.lifetime {
+                self.particles.swap_remove(i);
+                continue;
+            }
+            let life = particle.life();
+            particle.velocity = (particle.velocity + settings.gravity * dt) * drag;
+            particle.position += particle.velocity * settings.speed_over_lifetime.sample(life) * dt;
+            particle.size = settings.size.sample(life);
+            particle.color = settings.color.sample(life);
+            i += 1;
+        }
+        if self.emitting && settings.rate > 0.0 {
+            self.spawn_debt += settings.rate * dt;
+            while self.spawn_debt >= 1.0 {
+                self.spawn_debt -= 1.0;
+                self.spawn(rng);
+            }
+        }
+    }
+
+    fn spawn(&mut self, rng: &Rng) {
+        if self.particles.len() >= self.settings.max_particles {
+            return;
+        }
+        let settings = &self.settings;
+        let (offset, direction) = settings.shape.sample(rng);
+        self.particles.push(Particle {
+            position: self.position + offset,
+            velocity: direction * rng.range(settings.speed.0, settings.speed.1),
+            age: 0.0,
+            lifetime: rng.range(settings.lifetime.0, settings.lifetime.1),
+            size: settings.size.sample(0.0),
+            color: settings.color.sample(0.0),
+        });
+    }
+}
+
+/// A particle as drawn, `SpriteInput` in particles.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpriteInstance {
+    position: [f32; 3],
+    size: f32,
+    color: [f32; 4],
+}
+
+// The living particles of the emitters, farthest from `eye` first.
+fn sprites(emitters: &[&ParticleEmitter], eye: Point3<f32>) -> Vec<SpriteInstance> {
+    let mut sprites: Vec<(f32, SpriteInstance)> = emitters
+        .iter()
+        .flat_map(|emitter| emitter.particles())
+        .map(|particle| {
+            let sprite = SpriteInstance {
+                position: particle.position.into(),
+                size: particle.size,
+                color: particle.color,
+            };
+            (eye.distance2(Point3::from(sprite.position)), sprite)
+        })
+        .collect();
+    sprites.sort_by(|a, b| b.0.total_cmp(&a.0));
+    sprites.into_iter().map(|(_, sprite)| sprite).collect()
+}
+
+/// Draws the particles queued for a frame, see above.
+pub struct ParticleSprites {
+    pipeline: wgpu::RenderPipeline,
+    sprites: Vec<SpriteInstance>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    instance_count: u32,
+}
+
+impl ParticleSprites {
+    // `camera_layout` is that of the camera bind groups, which the particles are drawn with.
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, samples: u32) -> ParticleSprites {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Sprites Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("particles.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Sprites Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Sprites Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<SpriteInstance>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32, 2 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    // Premultiplied "over", leaving the alpha of the scene alone.
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Hidden by the scene, and drawn in order over each other.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: samples,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let instance_capacity = 256;
+        ParticleSprites {
+            pipeline,
+            sprites: Vec::new(),
+            instance_buffer: create_instance_buffer(device, instance_capacity),
+            instance_capacity,
+            instance_count: 0,
+        }
+    }
+
+    // Queues the emitters' particles for this frame, sorted for a camera at `eye`.
+    pub fn queue(&mut self, emitters: &[&ParticleEmitter], eye: Point3<f32>) {
+        self.sprites.extend(sprites(emitters, eye));
+    }
+
+    // Uploads the queued particles, to be drawn by `draw` this frame.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.sprites.len() > self.instance_capacity {
+            self.instance_capacity = self.sprites.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.sprites));
+        self.instance_count = self.sprites.len() as u32;
+        self.sprites.clear();
+    }
+
+    // Draws the particles into a camera's pass, after its scene.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &'a wgpu::BindGroup) {
+        if self.instance_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.instance_count);
+    }
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Particle Sprite Buffer"),
+        size: (capacity * std::mem::size_of::<SpriteInstance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// Particles demo
+//======================
+
+// The height of the floor the sparks bounce on, that of the physics floor (see main.rs).
+const FLOOR: f32 = -0.6;
+
+// A fire with smoke rising from it left of the pentagon, and sparks right of it, bouncing on the
+// floor until they are too slow, which the game does with the particles' positions. B sets off an
+// explosion above the pentagon, dropped once its particles died.
+pub struct ParticlesDemo {
+    fire: ParticleEmitter,
+    smoke: ParticleEmitter,
+    sparks: ParticleEmitter,
+    explosions: Vec<ParticleEmitter>,
+    burst_requested: bool,
+}
+
+impl ParticlesDemo {
+    pub fn new() -> ParticlesDemo {
+        ParticlesDemo {
+            fire: ParticleEmitter::new(EmitterSettings::fire()).with_position(Vector3::new(-1.0, FLOOR, 0.0)),
+            smoke: ParticleEmitter::new(EmitterSettings::smoke()).with_position(Vector3::new(-1.0, FLOOR + 0.6, 0.0)),
+            sparks: ParticleEmitter::new(EmitterSettings::sparks()).with_position(Vector3::new(0.9, 0.2, 0.0)),
+            explosions: Vec::new(),
+            burst_requested: false,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32, rng: &Rng) {
+        if std::mem::take(&mut self.burst_requested) {
+            let position = Vector3::new(rng.range(-0.5, 0.5), 0.9, 0.0);
+            let mut explosion = ParticleEmitter::new(EmitterSettings::explosion()).with_position(position);
+            explosion.burst(explosion.settings.max_particles, rng);
+            self.explosions.push(explosion);
+        }
+        for emitter in [&mut self.fire, &mut self.smoke, &mut self.sparks].into_iter().chain(&mut self.explosions) {
+            emitter.update(dt, rng);
+        }
+        self.explosions.retain(|explosion| !explosion.is_finished());
+        for particle in self.sparks.particles_mut() {
+            if particle.position.y < FLOOR && particle.velocity.y < 0.0 {
+                if particle.velocity.y > -1.0 {
+                    particle.kill();
+                }
+                particle.position.y = FLOOR;
+                particle.velocity.y *= -0.5;
+            }
+        }
+    }
+
+    pub fn render(&self, gfx: &mut GFX) {
+        let mut emitters = vec![&self.smoke, &self.fire, &self.sparks];
+        emitters.extend(&self.explosions);
+        gfx.draw_particles(&emitters);
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        if *event == Event::KeyPressed(b'B' as u16) {
+            self.burst_requested = true;
+        }
+    }
+}
+
+impl Default for ParticlesDemo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(position: Vector3<f32>, color: [f32; 4]) -> Particle {
+        Particle {
+            position,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            age: 0.0,
+            lifetime: 1.0,
+            size: 1.0,
+            color,
+        }
+    }
+
+    #[test]
+    fn curves_interpolate_between_keys_and_hold_the_ends() {
+        let curve = Curve::new(vec![(1.0, 4.0), (0.5, 2.0)]);
+        assert_eq!(curve.sample(0.0), 2.0);
+        assert_eq!(curve.sample(0.75), 3.0);
+        assert_eq!(curve.sample(2.0), 4.0);
+        assert_eq!(Curve::constant(7.0).sample(0.3), 7.0);
+        assert_eq!(Curve::linear([0.0; 4], [1.0, 2.0, 3.0, 4.0]).sample(0.5), [0.5, 1.0, 1.5, 2.0]);
+    }
+
+    #[test]
+    fn emitters_spawn_at_their_rate_up_to_their_limit() {
+        let rng = Rng::new(1);
+        let mut settings = EmitterSettings::fire();
+        settings.rate = 10.0;
+        settings.lifetime = (10.0, 10.0);
+        settings.max_particles = 15;
+        let mut emitter = ParticleEmitter::new(settings);
+        emitter.update(0.55, &rng);
+        assert_eq!(emitter.particles().len(), 5);
+        emitter.update(0.5, &rng);
+        assert_eq!(emitter.particles().len(), 10);
+        emitter.burst(100, &rng);
+        assert_eq!(emitter.particles().len(), 15);
+    }
+
+    #[test]
+    fn killed_and_old_particles_are_removed() {
+        let rng = Rng::new(2);
+        let mut emitter = ParticleEmitter::new(EmitterSettings::explosion());
+        emitter.burst(10, &rng);
+        emitter.particles_mut()[0].kill();
+        emitter.update(0.0, &rng);
+        assert_eq!(emitter.particles().len(), 9);
+        assert!(emitter.particles().iter().all(|particle| particle.life() == 0.0));
+        emitter.update(1.0, &rng);
+        assert!(emitter.particles().is_empty());
+    }
+
+    #[test]
+    fn sprites_are_sorted_back_to_front() {
+        let mut near = ParticleEmitter::new(EmitterSettings::smoke());
+        near.particles.push(particle(Vector3::new(0.0, 0.0, 1.0), [1.0, 0.0, 0.0, 0.5]));
+        let mut far = ParticleEmitter::new(EmitterSettings::smoke());
+        far.particles.push(particle(Vector3::new(0.0, 0.0, -3.0), [0.0, 0.0, 1.0, 0.5]));
+        far.particles.push(particle(Vector3::new(0.0, 0.0, -1.0), [0.0, 1.0, 0.0, 0.5]));
+        let sprites = sprites(&[&near, &far], Point3::new(0.0, 0.0, 2.0));
+        // The alpha is kept for the blend state, not folded into the color.
+        let colors: Vec<[f32; 4]> = sprites.iter().map(|sprite| sprite.color).collect();
+        assert_eq!(colors, [[0.0, 0.0, 1.0, 0.5], [0.0, 1.0, 0.0, 0.5], [1.0, 0.0, 0.0, 0.5]]);
+        assert_eq!(sprites[0].position, [0.0, 0.0, -3.0]);
+    }
+
+    #[test]
+    fn the_sprite_shader_is_valid() {
+        crate::reflection::ShaderReflection::from_wgsl(include_str!("particles.wgsl")).unwrap();
+    }
+}
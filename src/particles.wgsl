@@ -0,0 +1,53 @@
+// Draws the particles of CPU emitters as round sprites facing the camera, blended over the
+// scene, see particles.rs.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+// `SpriteInstance` in particles.rs.
+struct SpriteInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] size: f32;
+    [[location(2)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+    [[location(1)]] offset: vec2<f32>; // From the center, -1 to 1.
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32, sprite: SpriteInput) -> VertexOutput {
+    // Two triangles, from the six vertices of the instance.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[index];
+    let forward = normalize(camera.eye.xyz - sprite.position);
+    let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(forward.y) > 0.99);
+    let right = normalize(cross(helper, forward));
+    let up = cross(forward, right);
+    let world = sprite.position + (right * corner.x + up * corner.y) * sprite.size * 0.5;
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world, 1.0);
+    out.color = sprite.color;
+    out.offset = corner;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Round, most opaque at the center, and premultiplied for the blend state.
+    let alpha = in.color.a * max(1.0 - dot(in.offset, in.offset), 0.0);
+    return vec4<f32>(in.color.rgb * alpha, alpha);
+}
@@ -0,0 +1,200 @@
+use cgmath::{Matrix4, Point3, Quaternion, Vector3};
+use rapier3d::prelude::{
+    BroadPhase, CCDSolver, Collider, ColliderHandle, ColliderSet, ImpulseJointSet, IntegrationParameters,
+    IslandManager, Isometry, MultibodyJointSet, NarrowPhase, PhysicsPipeline, QueryPipeline, Real, RigidBody,
+    RigidBodyHandle, RigidBodySet,
+};
+
+use crate::debug_draw::DebugDraw;
+use crate::gfx::{RenderableId, GFX};
+
+// Physics
+//======================
+// Rigid bodies and colliders simulated by rapier, enabled with the `physics` feature.
+// A body can be attached to a renderable, which then follows it:
+//
+//     fn update(&mut self, time: &mut Time, _input: &Input) {
+//         self.physics.step(time.delta()); // Once per fixed update tick.
+//     }
+//
+//     fn render(&mut self, frame: &mut Frame) {
+//         self.physics.sync(frame.gfx, frame.alpha);
+//     }
+//
+// `sync` interpolates between the last two steps by `alpha`, so bodies move smoothly even when
+// the frame rate is higher than the update rate. It sets the translation and rotation of the
+// renderables and leaves their scale alone, which lets a unit mesh stand in for any box.
+
+const FIXED_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+const DYNAMIC_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const SLEEPING_COLOR: [f32; 4] = [0.2, 0.4, 1.0, 1.0];
+
+// A body and the renderable that follows it.
+struct Attachment {
+    renderable: RenderableId,
+    body: RigidBodyHandle,
+    previous: Isometry<Real>, // Before the last step, for interpolation.
+}
+
+/// The physics world, see above.
+pub struct Physics {
+    pub gravity: rapier3d::math::Vector<Real>,
+    integration_parameters: IntegrationParameters,
+    pipeline: PhysicsPipeline,
+    islands: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
+    attachments: Vec<Attachment>,
+}
+
+impl Physics {
+    pub fn new() -> Physics {
+        Physics {
+            gravity: rapier3d::math::Vector::new(0.0, -9.81, 0.0),
+            integration_parameters: IntegrationParameters::default(),
+            pipeline: PhysicsPipeline::new(),
+            islands: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies: RigidBodySet::new(),
+            colliders: ColliderSet::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            query_pipeline: QueryPipeline::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    // Adds a body with a collider attached to it.
+    pub fn add_body(&mut self, body: impl Into<RigidBody>, collider: impl Into<Collider>) -> RigidBodyHandle {
+        let body = self.bodies.insert(body);
+        self.colliders.insert_with_parent(collider, body, &mut self.bodies);
+        body
+    }
+
+    // Adds a collider that never moves, like the ground.
+    pub fn add_collider(&mut self, collider: impl Into<Collider>) -> ColliderHandle {
+        self.colliders.insert(collider)
+    }
+
+    // Makes `renderable` follow `body`, see `sync`.
+    pub fn attach(&mut self, renderable: RenderableId, body: RigidBodyHandle) {
+        let previous = *self.bodies[body].position();
+        self.attachments.push(Attachment {
+            renderable,
+            body,
+            previous,
+        });
+    }
+
+    pub fn body(&self, handle: RigidBodyHandle) -> &RigidBody {
+        &self.bodies[handle]
+    }
+
+    // For applying forces and impulses, or moving kinematic bodies.
+    pub fn body_mut(&mut self, handle: RigidBodyHandle) -> &mut RigidBody {
+        &mut self.bodies[handle]
+    }
+
+    // Advances the simulation by `dt` seconds. Call it from `Game::update`, once per tick.
+    pub fn step(&mut self, dt: f32) {
+        if dt <= 0.0 {
+            return; // Paused.
+        }
+        for attachment in &mut self.attachments {
+            attachment.previous = *self.bodies[attachment.body].position();
+        }
+        self.integration_parameters.dt = dt;
+        self.pipeline.step(
+            &self.gravity,
+            &self.integration_parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            Some(&mut self.query_pipeline),
+            &(),
+            &(),
+        );
+    }
+
+    // Moves the attached renderables to their bodies, `alpha` of the way from the previous step
+    // to the last one.
+    pub fn sync(&self, gfx: &mut GFX, alpha: f32) {
+        for attachment in &self.attachments {
+            let current = self.bodies[attachment.body].position();
+            let position = attachment.previous.lerp_slerp(current, alpha);
+            let mut transform = *gfx.transform(attachment.renderable);
+            transform.translation = to_vector(&position);
+            transform.rotation = to_quaternion(&position);
+            gfx.set_transform(attachment.renderable, transform);
+        }
+    }
+
+    // Outlines all colliders: fixed ones in grey, moving ones in green, sleeping ones in blue.
+    pub fn debug_draw(&self, draw: &mut DebugDraw) {
+        for (_, collider) in self.colliders.iter() {
+            let color = match collider.parent().map(|parent| &self.bodies[parent]) {
+                Some(body) if body.is_sleeping() => SLEEPING_COLOR,
+                Some(body) if body.is_dynamic() || body.is_kinematic() => DYNAMIC_COLOR,
+                _ => FIXED_COLOR,
+            };
+            let position = collider.position();
+            let shape = collider.shape();
+            if let Some(ball) = shape.as_ball() {
+                draw.sphere(to_point(position), ball.radius, color);
+            } else if let Some(cuboid) = shape.as_cuboid() {
+                let transform = Matrix4::from_translation(to_vector(position)) * Matrix4::from(to_quaternion(position));
+                let half_extents = cuboid.half_extents;
+                draw.oriented_box(&transform, Vector3::new(half_extents.x, half_extents.y, half_extents.z), color);
+            } else if let Some(capsule) = shape.as_capsule() {
+                let a = position * capsule.segment.a;
+                let b = position * capsule.segment.b;
+                let (a, b) = (Point3::new(a.x, a.y, a.z), Point3::new(b.x, b.y, b.z));
+                draw.sphere(a, capsule.radius, color);
+                draw.sphere(b, capsule.radius, color);
+                draw.line(a, b, color);
+            } else {
+                // Everything else is drawn by its bounds.
+                let aabb = collider.compute_aabb();
+                draw.aabb(
+                    Point3::new(aabb.mins.x, aabb.mins.y, aabb.mins.z),
+                    Point3::new(aabb.maxs.x, aabb.maxs.y, aabb.maxs.z),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn to_vector(position: &Isometry<Real>) -> Vector3<f32> {
+    let t = position.translation.vector;
+    Vector3::new(t.x, t.y, t.z)
+}
+
+fn to_point(position: &Isometry<Real>) -> Point3<f32> {
+    let t = position.translation.vector;
+    Point3::new(t.x, t.y, t.z)
+}
+
+fn to_quaternion(position: &Isometry<Real>) -> Quaternion<f32> {
+    let q = position.rotation;
+    Quaternion::new(q.w, q.i, q.j, q.k)
+}
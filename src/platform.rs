@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use raw_window_handle::HasRawWindowHandle;
+
+use crate::error::Result;
+use crate::game::{Event, Input, MouseButton, ScrollDelta};
+use crate::gfx::{GfxOptions, GFX};
+use crate::keyboard::Keyboard;
+use crate::mouse::{Mouse, MouseDelta};
+use crate::rng::Rng;
+
+// Platforms
+//======================
+// `App` runs on top of a `Platform`: the OS window, its message loop, and the keyboard and mouse
+// state it collects. Everything above it (the game loop, rendering, replays) is the same on all
+// platforms. There are three implementations:
+//  - `Window`, hand-rolled on Win32 (window.rs), the default,
+//  - `X11Window`, hand-rolled on Xlib (x11_window.rs), the default on Linux,
+//  - `WinitWindow`, on winit (winit_window.rs), with the `winit` feature, for machines
+//    where the native path misbehaves and for other operating systems.
+//
+// All of them report keys as Win32 virtual-key codes, so key bindings work the same on each.
+
+/// The window `WindowBuilder::build` and `AppBuilder::build` create on this platform.
+#[cfg(not(target_os = "linux"))]
+pub type NativeWindow = crate::window::Window;
+#[cfg(target_os = "linux")]
+pub type NativeWindow = crate::x11_window::X11Window;
+
+pub trait Platform: HasRawWindowHandle {
+    // Creates the OS window and the graphics state that renders into it.
+    fn initialize(&mut self, gfx_options: &GfxOptions) -> Result<()>;
+
+    fn gfx_mut(&mut self) -> Option<&mut GFX>;
+
+    // Drops the graphics state while the window still exists.
+    fn release_gfx(&mut self);
+
+    // Replaces the graphics state with a new one on a new device, for when the GPU was removed or
+    // reset, see `ErrorCategory::DeviceRemoved`. Everything created on the old one is gone.
+    fn recreate_gfx(&mut self, gfx_options: &GfxOptions) -> Result<()>;
+
+    // Size of the client area, in pixels.
+    fn size(&self) -> (i32, i32);
+
+    // Switches to a window with a client area of `width` x `height`, or to borderless fullscreen
+    // on the primary monitor. The new size arrives through `take_resize` once the OS applied it.
+    fn set_window_mode(&mut self, width: i32, height: i32, fullscreen: bool);
+
+    fn is_fullscreen(&self) -> bool;
+
+    // False until the window is first shown, and while it is minimized.
+    fn is_visible(&self) -> bool;
+
+    fn is_focused(&self) -> bool;
+
+    // True while the window is shown but can't be seen, e.g. covered by other windows or on
+    // another virtual desktop. Not every platform can tell, see `App::occluded_fps`.
+    fn is_occluded(&self) -> bool;
+
+    fn input<'a>(&'a self, rng: &'a Rng) -> Input<'a>;
+
+    // The mouse movement since the last call, see `Mouse::take_frame_delta`.
+    fn take_mouse_delta(&mut self) -> MouseDelta;
+
+    fn next_event(&mut self) -> Option<Event>;
+
+    // When the oldest input since the last call arrived, for measuring latency, see latency.rs.
+    fn take_input_time(&mut self) -> Option<Instant>;
+
+    // The new client size, if the window was resized since the last call.
+    fn take_resize(&mut self) -> Option<(u32, u32)>;
+
+    // True if the window has to be drawn again since the last call: the OS asked for it to be
+    // repainted (it was uncovered or restored), it was resized, or input arrived.
+    fn take_invalidated(&mut self) -> bool;
+
+    // Handles `event` as if the user caused it, see synthetic.rs.
+    fn inject_event(&mut self, event: Event);
+
+    // With `false`, the real keyboard and mouse are ignored while a replay drives the input.
+    fn set_accept_os_input(&mut self, accept: bool);
+
+    // Handles all pending OS messages without blocking. Returns false once the window was closed.
+    fn pump_messages(&mut self) -> bool;
+
+    // Sleeps until `deadline`, or until the next message without one, handling messages as they
+    // arrive. Returns false once the window was closed.
+    fn wait_messages(&mut self, deadline: Option<Instant>) -> bool;
+}
+
+// Updates the keyboard and mouse state for `event`, and queues it for the game. For platforms
+// that translate their input to `Event`s first; `Window` handles most messages itself, and only
+// passes on the events no message can be fabricated for, see synthetic.rs.
+pub(crate) fn apply_event(keyboard: &mut Keyboard, mouse: &mut Mouse, events: &mut VecDeque<Event>, event: Event) {
+    match event {
+        Event::KeyPressed(code) => keyboard.on_key_pressed(code),
+        Event::KeyReleased(code) => keyboard.on_key_released(code),
+        Event::Char(character) => keyboard.on_char(character),
+        Event::MouseMoved { x, y } => mouse.on_mouse_move(x as isize, y as isize),
+        Event::MousePressed(MouseButton::Left) => mouse.on_left_pressed(),
+        Event::MousePressed(MouseButton::Right) => mouse.on_right_pressed(),
+        Event::MouseReleased(MouseButton::Left) => mouse.on_left_released(),
+        Event::MouseReleased(MouseButton::Right) => mouse.on_right_released(),
+        Event::MouseRawMoved { dx, dy } => mouse.on_raw_move(dx as isize, dy as isize),
+        Event::MouseScrolled(ScrollDelta::Lines { x, y }) => mouse.on_scroll_lines(x, y),
+        Event::MouseScrolled(ScrollDelta::Pixels { x, y }) => mouse.on_scroll_pixels(x, y),
+        Event::FocusLost => keyboard.clear_state(),
+        Event::OcclusionChanged { .. } | Event::PowerChanged { .. } => {}
+    }
+    events.push_back(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse::EventType;
+    use crate::replay::ReplayFrame;
+
+    // Feeds `events` through `apply_event`, as a platform or a replay would.
+    fn apply_all(events: &[Event]) -> (Mouse, Vec<Event>) {
+        let (mut keyboard, mut mouse, mut queue) = (Keyboard::new(), Mouse::new(), VecDeque::new());
+        for event in events {
+            apply_event(&mut keyboard, &mut mouse, &mut queue, *event);
+        }
+        (mouse, queue.into_iter().collect())
+    }
+
+    // What a recording of `events` plays back.
+    fn replayed(events: Vec<Event>) -> Vec<Event> {
+        let frame = ReplayFrame { dt: 0.016, events };
+        let recorded: ReplayFrame = serde_json::from_str(&serde_json::to_string(&frame).unwrap()).unwrap();
+        recorded.events
+    }
+
+    fn wheel_events(mouse: &mut Mouse) -> Vec<bool> {
+        std::iter::from_fn(|| mouse.read())
+            .filter_map(|event| match event.get_type() {
+                EventType::WheelUp => Some(true),
+                EventType::WheelDown => Some(false),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raw_motion_is_queued_and_replayed() {
+        let events = [Event::MouseRawMoved { dx: 3, dy: -2 }, Event::MouseRawMoved { dx: 4, dy: 1 }];
+        let (mut mouse, queued) = apply_all(&events);
+        let delta = mouse.take_frame_delta();
+        assert_eq!((delta.raw_x, delta.raw_y), (7, -1));
+        assert_eq!(queued, events);
+        assert_eq!(apply_all(&replayed(queued)).0.take_frame_delta(), delta);
+    }
+
+    #[test]
+    fn scrolling_is_queued_and_replayed() {
+        let events = [
+            Event::MouseScrolled(ScrollDelta::Lines { x: 0.0, y: 0.75 }),
+            Event::MouseScrolled(ScrollDelta::Pixels { x: 10.0, y: 5.0 }),
+            Event::MouseScrolled(ScrollDelta::Lines { x: 0.0, y: 0.5 }),
+        ];
+        let (mut mouse, queued) = apply_all(&events);
+        assert_eq!(queued, events);
+        let delta = mouse.take_frame_delta();
+        assert_eq!(delta.scroll_lines, (0.5, 1.5));
+        assert_eq!(delta.scroll_pixels, (10.0, 30.0));
+        // 0.75 + 0.25 lines made a whole notch, the last 0.5 carries over to the next scroll.
+        assert_eq!(wheel_events(&mut mouse), [true]);
+
+        let (mut replayed_mouse, _) = apply_all(&replayed(queued));
+        assert_eq!(replayed_mouse.take_frame_delta(), delta);
+        assert_eq!(wheel_events(&mut replayed_mouse), [true]);
+        apply_event(
+            &mut Keyboard::new(),
+            &mut replayed_mouse,
+            &mut VecDeque::new(),
+            Event::MouseScrolled(ScrollDelta::Lines { x: 0.0, y: 0.5 }),
+        );
+        assert_eq!(wheel_events(&mut replayed_mouse), [true]);
+    }
+}
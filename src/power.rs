@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+#[cfg(windows)]
+use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+// Power source
+//======================
+// Whether the machine runs on battery, for `App` to throttle the frame rate, see app.rs.
+// Windows reports it with GetSystemPowerStatus; on Linux a mains supply in /sys/class/power_supply
+// that is offline means battery. Desktops, and machines where neither can tell, count as plugged in.
+
+// Plugging in is not urgent, and reading /sys every frame is not free.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct PowerMonitor {
+    on_battery: bool,
+    next_poll: Instant,
+}
+
+impl PowerMonitor {
+    // Starts out plugged in, so that the first `poll` reports running on battery.
+    pub fn new() -> PowerMonitor {
+        PowerMonitor {
+            on_battery: false,
+            next_poll: Instant::now(),
+        }
+    }
+
+    pub fn on_battery(&self) -> bool {
+        self.on_battery
+    }
+
+    // Checks the power source if it is time to, and returns it if it changed since the last call.
+    pub fn poll(&mut self) -> Option<bool> {
+        let now = Instant::now();
+        if now < self.next_poll {
+            return None;
+        }
+        self.next_poll = now + POLL_INTERVAL;
+        let on_battery = on_battery();
+        if on_battery == self.on_battery {
+            return None;
+        }
+        self.on_battery = on_battery;
+        tracing::info!("Running on {}", if on_battery { "battery" } else { "AC power" });
+        Some(on_battery)
+    }
+}
+
+impl Default for PowerMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(windows)]
+fn on_battery() -> bool {
+    let mut status = SYSTEM_POWER_STATUS::default();
+    // ACLineStatus is 0 offline, 1 online and 255 unknown.
+    unsafe { GetSystemPowerStatus(&mut status).as_bool() && status.ACLineStatus == 0 }
+}
+
+#[cfg(target_os = "linux")]
+fn on_battery() -> bool {
+    let supplies = match std::fs::read_dir("/sys/class/power_supply") {
+        Ok(supplies) => supplies,
+        Err(_) => return false,
+    };
+    let read = |path: std::path::PathBuf| std::fs::read_to_string(path).unwrap_or_default();
+    let mut mains = supplies
+        .flatten()
+        .map(|supply| supply.path())
+        .filter(|path| read(path.join("type")).trim() == "Mains")
+        .peekable();
+    // Without a mains supply this is a desktop, or a laptop that doesn't say.
+    mains.peek().is_some() && mains.all(|path| read(path.join("online")).trim() == "0")
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn on_battery() -> bool {
+    false
+}
@@ -0,0 +1,462 @@
+use std::num::NonZeroU32;
+
+use cgmath::Vector4;
+
+use crate::compute::ComputeKernel;
+use crate::gfx::GFX;
+use crate::reflection::ShaderReflection;
+use crate::render_graph::{NodeContext, NodeParameter, RenderNode, Stage};
+use crate::rng::Rng;
+use crate::uniform::UniformLayout;
+
+// Reaction-diffusion
+//======================
+// The Gray-Scott model, in which two chemicals spreading and reacting grow into coral, mazes,
+// spots that divide, and more, depending on two rates (reaction_diffusion.wgsl):
+//
+//     learn-wgpu --reaction-diffusion coral
+//
+// The concentrations live in two `rg32float` textures of the render graph, "reaction_diffusion.0"
+// and "reaction_diffusion.1", a texel for every `scale` x `scale` pixels of the frame. A step reads
+// one and writes the other; steps run in pairs, so every frame ends with the result in the first.
+// The textures are refilled with a few random drops of v when the frame changes size.
+//
+// `ReactionDiffusionSimulation` runs the steps before the scene and `ReactionDiffusionDraw` maps v
+// through a palette over the whole frame afterwards. The rates, the diffusion, the time step, the
+// steps per frame and the palette are node parameters, changed while it runs from the settings
+// panel or a script, see render_graph.rs:
+//
+//     set_parameter("Reaction-Diffusion Simulation", "feed", 0.03);
+
+pub const CHEMICALS: [&str; 2] = ["reaction_diffusion.0", "reaction_diffusion.1"];
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+
+/// Feed and kill rates of well-known patterns, for `--reaction-diffusion <name>`.
+pub const PRESETS: [(&str, f32, f32); 5] = [
+    ("coral", 0.0545, 0.062),
+    ("mitosis", 0.0367, 0.0649),
+    ("maze", 0.029, 0.057),
+    ("spots", 0.03, 0.062),
+    ("waves", 0.014, 0.045),
+];
+
+/// Colors for the concentration of v, from none to the most.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Palette {
+    Ocean,
+    Fire,
+    Grayscale,
+}
+
+impl Palette {
+    const ALL: [Palette; 3] = [Palette::Ocean, Palette::Fire, Palette::Grayscale];
+
+    // Gradient stops: the color in xyz, its position in w.
+    fn stops(self) -> [Vector4<f32>; 4] {
+        match self {
+            Palette::Ocean => [
+                Vector4::new(0.0, 0.02, 0.05, 0.0),
+                Vector4::new(0.0, 0.2, 0.4, 0.2),
+                Vector4::new(0.2, 0.7, 0.8, 0.5),
+                Vector4::new(0.95, 1.0, 0.9, 1.0),
+            ],
+            Palette::Fire => [
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+                Vector4::new(0.5, 0.02, 0.0, 0.25),
+                Vector4::new(1.0, 0.4, 0.0, 0.6),
+                Vector4::new(1.0, 1.0, 0.6, 1.0),
+            ],
+            Palette::Grayscale => [
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+                Vector4::new(1.0, 1.0, 1.0, 1.0),
+                Vector4::new(1.0, 1.0, 1.0, 1.0),
+            ],
+        }
+    }
+}
+
+/// See above.
+#[derive(Clone, Copy, Debug)]
+pub struct ReactionDiffusionSettings {
+    pub feed: f32, // Of u.
+    pub kill: f32, // Of v.
+    pub diffusion_u: f32,
+    pub diffusion_v: f32,
+    pub dt: f32, // Per step. Much above 1 the simulation becomes unstable.
+    pub steps: u32, // Per frame, rounded up to an even number.
+    pub scale: u32, // Frame pixels per texel, along each side.
+    pub drops: u32, // Of v at the start.
+    pub palette: Palette,
+    pub seed: u64,
+}
+
+impl Default for ReactionDiffusionSettings {
+    fn default() -> Self {
+        ReactionDiffusionSettings {
+            feed: 0.0545,
+            kill: 0.062,
+            diffusion_u: 1.0,
+            diffusion_v: 0.5,
+            dt: 1.0,
+            steps: 8,
+            scale: 2,
+            drops: 16,
+            palette: Palette::Ocean,
+            seed: 1,
+        }
+    }
+}
+
+impl ReactionDiffusionSettings {
+    // The default settings with the rates of one of `PRESETS`.
+    pub fn preset(name: &str) -> Option<ReactionDiffusionSettings> {
+        let (_, feed, kill) = PRESETS.iter().find(|(preset, ..)| preset.eq_ignore_ascii_case(name))?;
+        Some(ReactionDiffusionSettings {
+            feed: *feed,
+            kill: *kill,
+            ..Default::default()
+        })
+    }
+}
+
+// Adds the simulation and its display to the render graph, see above.
+pub fn add_nodes(gfx: &mut GFX, settings: ReactionDiffusionSettings) {
+    tracing::info!("Reaction-diffusion with feed {} and kill {}", settings.feed, settings.kill);
+    gfx.add_render_node(Box::new(ReactionDiffusionSimulation::new(settings)));
+    gfx.add_render_node(Box::new(ReactionDiffusionDraw::new(settings)));
+}
+
+// Simulation
+//======================
+
+uniform_struct! {
+    struct StepParams {
+        feed: f32,
+        kill: f32,
+        diffusion_u: f32,
+        diffusion_v: f32,
+        dt: f32,
+    }
+}
+assert_uniform_size!(StepParams, 20);
+
+// The kernel, with a bind group for each direction between the textures, for their size.
+struct Kernel {
+    kernel: ComputeKernel,
+    params: wgpu::Buffer,
+    bind_groups: [wgpu::BindGroup; 2], // From `CHEMICALS[i]` to the other one.
+    size: (u32, u32),
+}
+
+/// Runs the steps of a frame, before the scene, see above.
+pub struct ReactionDiffusionSimulation {
+    settings: ReactionDiffusionSettings,
+    rng: Rng,
+    kernel: Option<Kernel>,
+}
+
+impl ReactionDiffusionSimulation {
+    pub fn new(settings: ReactionDiffusionSettings) -> ReactionDiffusionSimulation {
+        ReactionDiffusionSimulation {
+            settings,
+            rng: Rng::new(settings.seed),
+            kernel: None,
+        }
+    }
+
+    fn create_kernel(&self, ctx: &mut NodeContext, size: (u32, u32)) -> Kernel {
+        for name in CHEMICALS {
+            ctx.resources.ensure_texture(ctx.device, name, size.0, size.1, FORMAT);
+        }
+        // The shader is part of the crate, so failing to build it is a bug.
+        let wgsl = include_str!("reaction_diffusion.wgsl");
+        let kernel = ComputeKernel::new(ctx.device, "Reaction-Diffusion Step", wgsl, "step").expect("built-in kernel is valid");
+        let params = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Reaction-Diffusion Params"),
+            size: StepParams::SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = |from: &str, to: &str| {
+            let entries = [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&ctx.resources.texture(from).unwrap().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&ctx.resources.texture(to).unwrap().view),
+                },
+            ];
+            kernel.bind_group(ctx.device, 0, &entries).expect("reaction-diffusion textures match the kernel")
+        };
+        let bind_groups = [bind_group(CHEMICALS[0], CHEMICALS[1]), bind_group(CHEMICALS[1], CHEMICALS[0])];
+        Kernel {
+            kernel,
+            params,
+            bind_groups,
+            size,
+        }
+    }
+
+    // Fills the first texture with u, and squares of v at random places.
+    fn seed(&self, ctx: &NodeContext, (width, height): (u32, u32)) {
+        let mut texels = vec![[1.0f32, 0.0]; (width * height) as usize];
+        for _ in 0..self.settings.drops {
+            let half = self.rng.range(3.0, 10.0) as i32;
+            let (x, y) = (self.rng.range(0.0, width as f32) as i32, self.rng.range(0.0, height as f32) as i32);
+            for row in (y - half).max(0)..(y + half).min(height as i32) {
+                for column in (x - half).max(0)..(x + half).min(width as i32) {
+                    texels[(row as u32 * width + column as u32) as usize] = [0.5, 0.25];
+                }
+            }
+        }
+        ctx.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &ctx.resources.texture(CHEMICALS[0]).unwrap().texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(8 * width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+impl RenderNode for ReactionDiffusionSimulation {
+    fn name(&self) -> &str {
+        "Reaction-Diffusion Simulation"
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::BeforeScene
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        CHEMICALS.to_vec()
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let scale = self.settings.scale.max(1);
+        let size = ((ctx.frame.size.0 / scale).max(1), (ctx.frame.size.1 / scale).max(1));
+        if self.kernel.as_ref().is_none_or(|kernel| kernel.size != size) {
+            self.kernel = Some(self.create_kernel(ctx, size));
+            self.seed(ctx, size);
+        }
+        let kernel = self.kernel.as_ref().unwrap();
+
+        let settings = &self.settings;
+        let params = StepParams {
+            feed: settings.feed,
+            kill: settings.kill,
+            diffusion_u: settings.diffusion_u,
+            diffusion_v: settings.diffusion_v,
+            dt: settings.dt,
+        };
+        ctx.queue.write_buffer(&kernel.params, 0, &params.to_uniform_bytes());
+        for _ in 0..settings.steps.div_ceil(2) {
+            for bind_group in &kernel.bind_groups {
+                kernel.kernel.dispatch_2d(encoder, &[bind_group], WORKGROUP_SIZE, size.0, size.1);
+            }
+        }
+    }
+
+    fn parameters(&self) -> Vec<NodeParameter> {
+        let settings = &self.settings;
+        vec![
+            NodeParameter::new("feed", settings.feed, 0.0..=0.1),
+            NodeParameter::new("kill", settings.kill, 0.0..=0.1),
+            NodeParameter::new("diffusion_u", settings.diffusion_u, 0.0..=1.0),
+            NodeParameter::new("diffusion_v", settings.diffusion_v, 0.0..=1.0),
+            NodeParameter::new("dt", settings.dt, 0.0..=1.5),
+            NodeParameter::new("steps", settings.steps as f32, 0.0..=32.0),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        let settings = &mut self.settings;
+        match name {
+            "feed" => settings.feed = value,
+            "kill" => settings.kill = value,
+            "diffusion_u" => settings.diffusion_u = value,
+            "diffusion_v" => settings.diffusion_v = value,
+            "dt" => settings.dt = value,
+            "steps" => settings.steps = value.round().max(0.0) as u32,
+            _ => {}
+        }
+    }
+}
+
+// Drawing
+//======================
+
+uniform_struct! {
+    struct DrawParams {
+        palette: [Vector4<f32>; 4],
+        scale: f32,
+    }
+}
+assert_uniform_size!(DrawParams, 80);
+
+// The pipeline for the frame's format, and the bind group for the texture of the current size.
+struct DrawState {
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    params: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: Option<(wgpu::BindGroup, (u32, u32))>,
+}
+
+/// Draws the concentration of v over the frame, see above.
+pub struct ReactionDiffusionDraw {
+    settings: ReactionDiffusionSettings,
+    state: Option<DrawState>,
+}
+
+impl ReactionDiffusionDraw {
+    pub fn new(settings: ReactionDiffusionSettings) -> ReactionDiffusionDraw {
+        ReactionDiffusionDraw { settings, state: None }
+    }
+
+    fn create_state(device: &wgpu::Device, format: wgpu::TextureFormat) -> DrawState {
+        let wgsl = include_str!("reaction_diffusion_draw.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("Reaction-Diffusion Draw"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Reaction-Diffusion Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Reaction-Diffusion Draw Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Reaction-Diffusion Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Reaction-Diffusion Draw Params"),
+            size: DrawParams::SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        DrawState {
+            pipeline,
+            format,
+            params,
+            bind_group_layout,
+            bind_group: None,
+        }
+    }
+}
+
+impl RenderNode for ReactionDiffusionDraw {
+    fn name(&self) -> &str {
+        "Reaction-Diffusion"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![CHEMICALS[0]]
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let (view, chemicals) = match (ctx.frame.view, ctx.resources.texture(CHEMICALS[0])) {
+            (Some(view), Some(chemicals)) => (view, chemicals),
+            _ => return,
+        };
+        let format = ctx.frame.format;
+        if self.state.as_ref().is_none_or(|state| state.format != format) {
+            self.state = Some(Self::create_state(ctx.device, format));
+        }
+        let state = self.state.as_mut().unwrap();
+        // The texture is replaced when the frame changes size.
+        let size = (chemicals.width, chemicals.height);
+        if state.bind_group.as_ref().is_none_or(|(_, bound)| *bound != size) {
+            let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Reaction-Diffusion Draw Bind Group"),
+                layout: &state.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: state.params.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&chemicals.view),
+                    },
+                ],
+            });
+            state.bind_group = Some((bind_group, size));
+        }
+
+        let params = DrawParams {
+            palette: self.settings.palette.stops(),
+            scale: self.settings.scale.max(1) as f32,
+        };
+        ctx.queue.write_buffer(&state.params, 0, &params.to_uniform_bytes());
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Reaction-Diffusion Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&state.pipeline);
+        render_pass.set_bind_group(0, &state.bind_group.as_ref().unwrap().0, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn parameters(&self) -> Vec<NodeParameter> {
+        let palette = Palette::ALL.iter().position(|palette| *palette == self.settings.palette).unwrap_or(0);
+        vec![NodeParameter::new("palette", palette as f32, 0.0..=(Palette::ALL.len() - 1) as f32)]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        if name == "palette" {
+            let index = (value.round().max(0.0) as usize).min(Palette::ALL.len() - 1);
+            self.settings.palette = Palette::ALL[index];
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// One step of the Gray-Scott reaction-diffusion model, see reaction_diffusion.rs.
+//
+// Every texel holds the concentrations of two chemicals, u in r and v in g. Both diffuse, u is
+// fed in and v removed at constant rates, and v turns u into more v where they meet:
+//
+//     du/dt = Du ∇²u - uv² + feed (1 - u)
+//     dv/dt = Dv ∇²v + uv² - (feed + kill) v
+//
+// The grid wraps around at the edges.
+
+struct Params {
+    feed: f32;
+    kill: f32;
+    diffusion_u: f32;
+    diffusion_v: f32;
+    dt: f32;
+};
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var input: texture_2d<f32>;
+[[group(0), binding(2)]]
+var output: texture_storage_2d<rg32float, write>;
+
+fn load(pos: vec2<i32>, size: vec2<i32>) -> vec2<f32> {
+    return textureLoad(input, (pos + size) % size, 0).rg;
+}
+
+[[stage(compute), workgroup_size(8, 8)]]
+fn step([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(input);
+    let pos = vec2<i32>(id.xy);
+    if (pos.x >= size.x || pos.y >= size.y) {
+        return;
+    }
+
+    // The Laplacian, from the 3x3 neighborhood.
+    let center = load(pos, size);
+    let sides = load(pos + vec2<i32>(-1, 0), size) + load(pos + vec2<i32>(1, 0), size)
+        + load(pos + vec2<i32>(0, -1), size) + load(pos + vec2<i32>(0, 1), size);
+    let corners = load(pos + vec2<i32>(-1, -1), size) + load(pos + vec2<i32>(1, -1), size)
+        + load(pos + vec2<i32>(-1, 1), size) + load(pos + vec2<i32>(1, 1), size);
+    let laplacian = sides * 0.2 + corners * 0.05 - center;
+
+    let u = center.x;
+    let v = center.y;
+    let reaction = u * v * v;
+    let du = params.diffusion_u * laplacian.x - reaction + params.feed * (1.0 - u);
+    let dv = params.diffusion_v * laplacian.y + reaction - (params.feed + params.kill) * v;
+    let next = clamp(center + vec2<f32>(du, dv) * params.dt, vec2<f32>(0.0), vec2<f32>(1.0));
+    textureStore(output, pos, vec4<f32>(next, 0.0, 1.0));
+}
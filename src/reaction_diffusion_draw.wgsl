@@ -0,0 +1,36 @@
+// Shows the concentration of v through a palette, see reaction_diffusion.rs.
+
+struct DrawParams {
+    // Gradient stops: the color in rgb, and where it sits between 0 and 1 in w, increasing.
+    palette: array<vec4<f32>, 4>;
+    scale: f32; // Frame pixels per texel.
+};
+[[group(0), binding(0)]]
+var<uniform> params: DrawParams;
+[[group(0), binding(1)]]
+var cells: texture_2d<f32>;
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    // A triangle over the whole viewport: (-1, -1), (3, -1) and (-1, 3).
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let size = textureDimensions(cells);
+    let texel = min(vec2<i32>(position.xy / params.scale), size - vec2<i32>(1, 1));
+    // v rarely goes above about 0.5.
+    let t = clamp(textureLoad(cells, texel, 0).g * 2.0, 0.0, 1.0);
+
+    var color = params.palette[0].rgb;
+    for (var i: i32 = 1; i < 4; i = i + 1) {
+        let from = params.palette[i - 1];
+        let to = params.palette[i];
+        if (t > from.w) {
+            color = mix(from.rgb, to.rgb, clamp((t - from.w) / max(to.w - from.w, 0.0001), 0.0, 1.0));
+        }
+    }
+    return vec4<f32>(color, 1.0);
+}
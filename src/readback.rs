@@ -0,0 +1,143 @@
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// GPU readback
+//======================
+// Copying data back from the GPU takes three steps:
+//  1. copy the buffer or texture into a staging buffer that can be mapped (MAP_READ | COPY_DST),
+//  2. map the staging buffer, which only completes once the device has been polled,
+//  3. copy the mapped bytes out, dropping the row padding of texture copies.
+// `Readback` performs steps 2 and 3 as a future. Used for picking, screenshots and compute results.
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Resolves to the bytes copied from the GPU, or `None` if the staging buffer could not be mapped.
+pub struct Readback {
+    device: Arc<wgpu::Device>,
+    staging: wgpu::Buffer,
+    map: MapFuture,
+    // Texture copies: (padded bytes per row, unpadded bytes per row, number of rows).
+    rows: Option<(usize, usize, usize)>,
+}
+
+impl Readback {
+    // Copies `range` of `buffer` (which needs `BufferUsages::COPY_SRC`) into a staging buffer.
+    pub fn from_buffer(
+        device: Arc<wgpu::Device>,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        range: Range<wgpu::BufferAddress>,
+    ) -> Readback {
+        let size = range.end - range.start;
+        let staging = create_staging_buffer(&device, size);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(buffer, range.start, &staging, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self::map(device, staging, None)
+    }
+
+    // Copies a region of a texture (which needs `TextureUsages::COPY_SRC`) into a staging buffer.
+    pub fn from_texture(
+        device: Arc<wgpu::Device>,
+        queue: &wgpu::Queue,
+        source: wgpu::ImageCopyTexture,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+    ) -> Readback {
+        let info = format.describe();
+        let blocks_x = size.width.div_ceil(info.block_dimensions.0 as u32);
+        let blocks_y = size.height.div_ceil(info.block_dimensions.1 as u32);
+
+        // Rows in the staging buffer must be a multiple of 256 bytes apart.
+        let unpadded_bytes_per_row = blocks_x * info.block_size as u32;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let rows = blocks_y * size.depth_or_array_layers;
+
+        let staging = create_staging_buffer(&device, (padded_bytes_per_row * rows) as wgpu::BufferAddress);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            source,
+            wgpu::ImageCopyBuffer {
+                buffer: &staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: NonZeroU32::new(blocks_y),
+                },
+            },
+            size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let rows = Some((padded_bytes_per_row as usize, unpadded_bytes_per_row as usize, rows as usize));
+        Self::map(device, staging, rows)
+    }
+
+    fn map(device: Arc<wgpu::Device>, staging: wgpu::Buffer, rows: Option<(usize, usize, usize)>) -> Readback {
+        let map = Box::pin(staging.slice(..).map_async(wgpu::MapMode::Read));
+        Readback {
+            device,
+            staging,
+            map,
+            rows,
+        }
+    }
+
+    // Copies the mapped staging buffer out, without row padding.
+    fn read_mapped(&self) -> Vec<u8> {
+        let data = self.staging.slice(..).get_mapped_range();
+        let bytes = match self.rows {
+            None => data.to_vec(),
+            Some((padded, unpadded, rows)) => {
+                let mut bytes = Vec::with_capacity(unpadded * rows);
+                for row in data.chunks(padded).take(rows) {
+                    bytes.extend_from_slice(&row[..unpadded]);
+                }
+                bytes
+            }
+        };
+        drop(data);
+        self.staging.unmap();
+        bytes
+    }
+}
+
+impl Future for Readback {
+    type Output = Option<Vec<u8>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The map callback only fires while the device is polled.
+        // Poll without blocking, so a readback never stalls the thread that drives it.
+        self.device.poll(wgpu::Maintain::Poll);
+        match self.map.as_mut().poll(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Some(self.read_mapped())),
+            Poll::Ready(Err(_)) => Poll::Ready(None),
+            Poll::Pending => {
+                // Ask to be polled again, nothing else will drive the device for us.
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn create_staging_buffer(device: &wgpu::Device, size: wgpu::BufferAddress) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
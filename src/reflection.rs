@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::num::NonZeroU64;
+
+// Shader reflection
+//======================
+// Parses and validates WGSL with naga and derives the resource bindings the shader declares,
+// so bind group layouts are built from the shader itself instead of being maintained by hand.
+
+/// A resource binding declared by a shader.
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding {
+    pub binding: u32,
+    pub name: Option<String>,
+    pub ty: wgpu::BindingType,
+    pub visibility: wgpu::ShaderStages, // The stages whose entry points use the binding.
+}
+
+/// The bind groups of a shader module, by group index.
+pub struct ShaderReflection {
+    groups: BTreeMap<u32, Vec<ReflectedBinding>>,
+}
+
+impl ShaderReflection {
+    pub fn from_wgsl(source: &str) -> Result<ShaderReflection, ReflectError> {
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|e| ReflectError::Parse(e.emit_to_string(source)))?;
+        Self::from_module(&module)
+    }
+
+    pub fn from_module(module: &naga::Module) -> Result<ShaderReflection, ReflectError> {
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(module)
+        .map_err(|e| ReflectError::Validation(format!("{:?}", e)))?;
+
+        // Computes the sizes of all types, for the minimum binding size of uniform buffers.
+        let mut layouter = naga::proc::Layouter::default();
+        layouter
+            .update(&module.types, &module.constants)
+            .map_err(|e| ReflectError::Validation(format!("{:?}", e)))?;
+
+        let sampled = sampled_textures(module);
+        let mut groups: BTreeMap<u32, Vec<ReflectedBinding>> = BTreeMap::new();
+        for (handle, var) in module.global_variables.iter() {
+            let binding = match &var.binding {
+                Some(binding) => binding,
+                None => continue, // Not a resource.
+            };
+
+            // A binding is visible to every stage that has an entry point using it.
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (index, entry_point) in module.entry_points.iter().enumerate() {
+                if !info.get_entry_point(index)[handle].is_empty() {
+                    visibility |= match entry_point.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                }
+            }
+
+            let filterable = sampled.as_ref().is_none_or(|sampled| sampled.contains(&handle));
+            let ty = binding_type(module, &layouter, var, filterable)?;
+            groups.entry(binding.group).or_default().push(ReflectedBinding {
+                binding: binding.binding,
+                name: var.name.clone(),
+                ty,
+                visibility,
+            });
+        }
+
+        for bindings in groups.values_mut() {
+            bindings.sort_by_key(|b| b.binding);
+        }
+
+        Ok(ShaderReflection { groups })
+    }
+
+    // The bindings of both shaders, for pipelines whose stages are in different modules. A binding
+    // used by both stages must have the same type in both.
+    pub fn merged(mut self, other: ShaderReflection) -> Result<ShaderReflection, ReflectError> {
+        for (group, bindings) in other.groups {
+            let merged = self.groups.entry(group).or_default();
+            for binding in bindings {
+                match merged.iter_mut().find(|b| b.binding == binding.binding) {
+                    // A binding only declared by a stage says little about its type, e.g. whether a
+                    // texture is filtered, so the stage using it decides.
+                    Some(_) if binding.visibility.is_empty() => {}
+                    Some(existing) if existing.visibility.is_empty() => *existing = binding,
+                    Some(existing) if existing.ty == binding.ty => existing.visibility |= binding.visibility,
+                    Some(_) => return Err(ReflectError::BindingMismatch { group, binding: binding.binding }),
+                    None => merged.push(binding),
+                }
+            }
+            merged.sort_by_key(|b| b.binding);
+        }
+        Ok(self)
+    }
+
+    /// The bindings of `group`, sorted by binding index. Empty if the shader does not use the group.
+    pub fn group(&self, group: u32) -> &[ReflectedBinding] {
+        self.groups.get(&group).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn group_indices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.groups.keys().copied()
+    }
+
+    pub fn layout_entries(&self, group: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+        self.group(group)
+            .iter()
+            .map(|b| wgpu::BindGroupLayoutEntry {
+                binding: b.binding,
+                visibility: b.visibility,
+                ty: b.ty,
+                count: None,
+            })
+            .collect()
+    }
+
+    pub fn create_bind_group_layout(
+        &self,
+        device: &wgpu::Device,
+        label: Option<&str>,
+        group: u32,
+    ) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label,
+            entries: &self.layout_entries(group),
+        })
+    }
+
+    // Checks that `entries` provide exactly the resources the shader expects in `group`,
+    // each of the right kind and large enough.
+    pub fn validate_entries(&self, group: u32, entries: &[wgpu::BindGroupEntry]) -> Result<(), ReflectError> {
+        let expected = self.group(group);
+
+        for binding in expected {
+            let entry = entries
+                .iter()
+                .find(|e| e.binding == binding.binding)
+                .ok_or(ReflectError::MissingBinding { group, binding: binding.binding })?;
+
+            let matches = match (&binding.ty, &entry.resource) {
+                (
+                    wgpu::BindingType::Buffer { min_binding_size, .. },
+                    wgpu::BindingResource::Buffer(buffer),
+                ) => match (min_binding_size, buffer.size) {
+                    (Some(min), Some(size)) if size < *min => {
+                        return Err(ReflectError::BufferTooSmall {
+                            group,
+                            binding: binding.binding,
+                            expected: min.get(),
+                            actual: size.get(),
+                        })
+                    }
+                    _ => true,
+                },
+                (wgpu::BindingType::Sampler(_), wgpu::BindingResource::Sampler(_)) => true,
+                (wgpu::BindingType::Texture { .. }, wgpu::BindingResource::TextureView(_)) => true,
+                (wgpu::BindingType::StorageTexture { .. }, wgpu::BindingResource::TextureView(_)) => true,
+                _ => false,
+            };
+            if !matches {
+                return Err(ReflectError::WrongResourceType { group, binding: binding.binding });
+            }
+        }
+
+        if let Some(extra) = entries
+            .iter()
+            .find(|e| !expected.iter().any(|b| b.binding == e.binding))
+        {
+            return Err(ReflectError::UnexpectedBinding { group, binding: extra.binding });
+        }
+
+        Ok(())
+    }
+}
+
+// The textures read through a sampler, `None` if the shader samples textures passed as function
+// arguments and it can't tell which. Float textures only read with `textureLoad` are reflected as
+// unfilterable, so they can be bound to formats like `r32float`.
+fn sampled_textures(module: &naga::Module) -> Option<HashSet<naga::Handle<naga::GlobalVariable>>> {
+    let functions = module
+        .functions
+        .iter()
+        .map(|(_, function)| function)
+        .chain(module.entry_points.iter().map(|entry_point| &entry_point.function));
+    let mut sampled = HashSet::new();
+    for function in functions {
+        for (_, expression) in function.expressions.iter() {
+            if let naga::Expression::ImageSample { image, .. } = *expression {
+                match function.expressions[image] {
+                    naga::Expression::GlobalVariable(var) => sampled.insert(var),
+                    _ => return None,
+                };
+            }
+        }
+    }
+    Some(sampled)
+}
+
+fn binding_type(
+    module: &naga::Module,
+    layouter: &naga::proc::Layouter,
+    var: &naga::GlobalVariable,
+    filterable: bool, // For float textures.
+) -> Result<wgpu::BindingType, ReflectError> {
+    let ty = match var.class {
+        naga::StorageClass::Uniform => wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(layouter[var.ty].size as u64),
+        },
+        naga::StorageClass::Storage { access } => wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            },
+            has_dynamic_offset: false,
+            // Storage buffers usually end in a runtime sized array.
+            min_binding_size: None,
+        },
+        naga::StorageClass::Handle => match module.types[var.ty].inner {
+            naga::TypeInner::Sampler { comparison: false } => {
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)
+            }
+            naga::TypeInner::Sampler { comparison: true } => {
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison)
+            }
+            naga::TypeInner::Image { dim, arrayed, class } => {
+                let view_dimension = view_dimension(dim, arrayed);
+                match class {
+                    naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                        sample_type: match kind {
+                            naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                            naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                            _ => wgpu::TextureSampleType::Float { filterable },
+                        },
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                        access: if access.contains(naga::StorageAccess::LOAD | naga::StorageAccess::STORE) {
+                            wgpu::StorageTextureAccess::ReadWrite
+                        } else if access.contains(naga::StorageAccess::STORE) {
+                            wgpu::StorageTextureAccess::WriteOnly
+                        } else {
+                            wgpu::StorageTextureAccess::ReadOnly
+                        },
+                        format: storage_format(format),
+                        view_dimension,
+                    },
+                }
+            }
+            _ => return Err(ReflectError::UnsupportedResource(var.name.clone())),
+        },
+        _ => return Err(ReflectError::UnsupportedResource(var.name.clone())),
+    };
+    Ok(ty)
+}
+
+fn view_dimension(dim: naga::ImageDimension, arrayed: bool) -> wgpu::TextureViewDimension {
+    match (dim, arrayed) {
+        (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+        (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+        (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+        (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+        (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+        (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+    use naga::StorageFormat as Sf;
+    use wgpu::TextureFormat as Tf;
+    match format {
+        Sf::R8Unorm => Tf::R8Unorm,
+        Sf::R8Snorm => Tf::R8Snorm,
+        Sf::R8Uint => Tf::R8Uint,
+        Sf::R8Sint => Tf::R8Sint,
+        Sf::R16Uint => Tf::R16Uint,
+        Sf::R16Sint => Tf::R16Sint,
+        Sf::R16Float => Tf::R16Float,
+        Sf::Rg8Unorm => Tf::Rg8Unorm,
+        Sf::Rg8Snorm => Tf::Rg8Snorm,
+        Sf::Rg8Uint => Tf::Rg8Uint,
+        Sf::Rg8Sint => Tf::Rg8Sint,
+        Sf::R32Uint => Tf::R32Uint,
+        Sf::R32Sint => Tf::R32Sint,
+        Sf::R32Float => Tf::R32Float,
+        Sf::Rg16Uint => Tf::Rg16Uint,
+        Sf::Rg16Sint => Tf::Rg16Sint,
+        Sf::Rg16Float => Tf::Rg16Float,
+        Sf::Rgba8Unorm => Tf::Rgba8Unorm,
+        Sf::Rgba8Snorm => Tf::Rgba8Snorm,
+        Sf::Rgba8Uint => Tf::Rgba8Uint,
+        Sf::Rgba8Sint => Tf::Rgba8Sint,
+        Sf::Rgb10a2Unorm => Tf::Rgb10a2Unorm,
+        Sf::Rg11b10Float => Tf::Rg11b10Float,
+        Sf::Rg32Uint => Tf::Rg32Uint,
+        Sf::Rg32Sint => Tf::Rg32Sint,
+        Sf::Rg32Float => Tf::Rg32Float,
+        Sf::Rgba16Uint => Tf::Rgba16Uint,
+        Sf::Rgba16Sint => Tf::Rgba16Sint,
+        Sf::Rgba16Float => Tf::Rgba16Float,
+        Sf::Rgba32Uint => Tf::Rgba32Uint,
+        Sf::Rgba32Sint => Tf::Rgba32Sint,
+        Sf::Rgba32Float => Tf::Rgba32Float,
+    }
+}
+
+/// The error type for shaders that cannot be reflected, or resources that do not match the shader.
+#[derive(Debug)]
+pub enum ReflectError {
+    Preprocess(String), // See variants.rs.
+    Parse(String),
+    Validation(String),
+    UnsupportedResource(Option<String>),
+    MissingBinding { group: u32, binding: u32 },
+    UnexpectedBinding { group: u32, binding: u32 },
+    WrongResourceType { group: u32, binding: u32 },
+    BufferTooSmall { group: u32, binding: u32, expected: u64, actual: u64 },
+    UnknownConstant(String), // Overridden, but not in the shader, see overrides.rs.
+    BindingMismatch { group: u32, binding: u32 }, // Between the stages of a pipeline.
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectError::Preprocess(e) => write!(f, "shader preprocessor error: {}", e),
+            ReflectError::Parse(e) => write!(f, "shader parse error:\n{}", e),
+            ReflectError::Validation(e) => write!(f, "shader validation error: {}", e),
+            ReflectError::UnsupportedResource(name) => {
+                write!(f, "unsupported resource binding {:?}", name)
+            }
+            ReflectError::MissingBinding { group, binding } => {
+                write!(f, "no resource provided for group {} binding {}", group, binding)
+            }
+            ReflectError::UnexpectedBinding { group, binding } => {
+                write!(f, "shader does not use group {} binding {}", group, binding)
+            }
+            ReflectError::WrongResourceType { group, binding } => {
+                write!(f, "wrong resource type for group {} binding {}", group, binding)
+            }
+            ReflectError::BufferTooSmall { group, binding, expected, actual } => write!(
+                f,
+                "buffer for group {} binding {} is {} bytes, shader expects at least {}",
+                group, binding, actual, expected
+            ),
+            ReflectError::UnknownConstant(name) => write!(f, "shader has no constant {} to override", name),
+            ReflectError::BindingMismatch { group, binding } => {
+                write!(f, "group {} binding {} has a different type in each stage", group, binding)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
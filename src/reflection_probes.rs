@@ -0,0 +1,396 @@
+use std::num::NonZeroU32;
+
+use cgmath::{Point3, Vector3, Vector4};
+
+use crate::camera::Camera;
+use crate::layers::RenderLayers;
+use crate::uniform::UniformLayout;
+
+// Reflection probes
+//======================
+// A probe renders the scene around a point into a small cubemap, which materials sample for their
+// reflections:
+//
+//     let probe = gfx.add_reflection_probe(ReflectionProbe::new(Point3::new(0.0, 1.0, 0.0), Vector3::new(4.0, 2.0, 4.0)));
+//     let material = Material::new(shader).with_defines(ShaderDefines::new().with_flag("REFLECTION_PROBES", true));
+//
+// A cubemap only holds what is visible from its center, so reflections slide over surfaces away
+// from it. Each probe has a box, usually the room it is in, and the reflection ray is intersected
+// with the box before looking up the cubemap in the direction of that point ("box projection"),
+// which is right for the walls of the box and close enough for what is near them.
+//
+// Every surface blends the probes whose box it is in, and those whose box it is within
+// `blend_distance` of, so reflections don't pop when moving from one room to the next.
+//
+// Probes are rendered on demand, once when added and then after `GFX::refresh_reflection_probe`,
+// or a few faces every frame, for probes near moving things. The cubemaps are rendered without
+// reflections of their own.
+//
+// Material shaders declare the probes in group(2), see shader.wgsl:
+//   group(2) binding(0)   uniform: the number of probes, then their boxes
+//   group(2) binding(1)   sampler
+//   group(2) binding(2+i) texture_cube<f32> of probe i
+// The faces are stored mirrored along z, so they render with the scene's right-handed cameras;
+// shaders negate z of the direction they look up.
+
+pub const PROBES_GROUP: u32 = 2;
+pub const MAX_PROBES: usize = 4;
+// Of every face, in pixels.
+pub const PROBE_RESOLUTION: u32 = 128;
+pub const PROBE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Identifies a probe added with `GFX::add_reflection_probe`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ReflectionProbeId(pub(crate) usize);
+
+/// When the cubemap of a probe is rendered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProbeUpdate {
+    // When added and when refreshed, all faces in one frame.
+    OnDemand,
+    // Continuously, this many of the 6 faces every frame.
+    Amortized { faces_per_frame: u32 },
+}
+
+/// A point the scene is captured from, and the box its reflections are projected onto.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReflectionProbe {
+    pub position: Point3<f32>,
+    pub half_extents: Vector3<f32>, // Of the box, around `position`.
+    pub blend_distance: f32, // Beyond the box, over which the probe fades out.
+    pub update: ProbeUpdate,
+    pub layers: RenderLayers, // Only renderables on one of these layers are captured.
+}
+
+impl ReflectionProbe {
+    pub fn new(position: Point3<f32>, half_extents: Vector3<f32>) -> ReflectionProbe {
+        ReflectionProbe {
+            position,
+            half_extents,
+            blend_distance: 0.5,
+            update: ProbeUpdate::OnDemand,
+            layers: RenderLayers::ALL,
+        }
+    }
+
+    pub fn with_update(mut self, update: ProbeUpdate) -> ReflectionProbe {
+        self.update = update;
+        self
+    }
+
+    pub fn with_blend_distance(mut self, blend_distance: f32) -> ReflectionProbe {
+        self.blend_distance = blend_distance;
+        self
+    }
+
+    // The camera rendering `face` of the cubemap, in the order +x, -x, +y, -y, +z, -z. Looks along
+    // the face's axis mirrored along z, see above.
+    pub fn face_camera(&self, face: usize) -> Camera {
+        let (forward, up) = FACES[face];
+        let mut camera = Camera::new(1.0);
+        camera.eye = self.position;
+        camera.target = self.position + Vector3::from(forward);
+        camera.up = Vector3::from(up);
+        camera.fovy = 90.0;
+        camera.znear = 0.05;
+        camera.auto_aspect = false;
+        camera.layers = self.layers;
+        camera
+    }
+}
+
+// The direction every camera looks in, and its up, by face.
+const FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+];
+
+uniform_struct! {
+    // A probe as the shaders see it.
+    #[derive(Clone, Copy)]
+    struct ProbeParams {
+        position: Vector4<f32>, // w is the blend distance.
+        half_extents: Vector4<f32>,
+    }
+}
+
+uniform_struct! {
+    struct ProbesUniform {
+        count: u32,
+        probes: [ProbeParams; MAX_PROBES],
+    }
+}
+assert_uniform_size!(ProbesUniform, 16 + 32 * MAX_PROBES);
+
+// A probe and the faces of its cubemap still to render.
+struct ProbeSlot {
+    probe: ReflectionProbe,
+    pending: u32, // Faces to render before the cubemap is complete.
+    next_face: usize, // Where amortized rendering continues.
+}
+
+/// The probes, their cubemaps, and the group(2) bind groups for sampling them.
+pub struct ReflectionProbes {
+    slots: Vec<Option<ProbeSlot>>, // By id; `None` where removed.
+    face_views: Vec<wgpu::TextureView>, // Render targets, 6 per probe.
+    depth_view: wgpu::TextureView,
+    uniform_buffer: wgpu::Buffer,
+    uniform_dirty: bool,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    // The same with empty cubemaps, bound while rendering the probes: they can't sample what they
+    // render into.
+    capture_bind_group: wgpu::BindGroup,
+}
+
+impl ReflectionProbes {
+    pub fn new(device: &wgpu::Device) -> ReflectionProbes {
+        let mut entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        for i in 0..MAX_PROBES {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + i as u32,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                count: None,
+            });
+        }
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Reflection Probes Bind Group Layout"),
+            entries: &entries,
+        });
+
+        // All cubemaps in one texture, 6 layers each.
+        let cubemaps = create_cubemaps(device, "Reflection Probes", PROBE_RESOLUTION, MAX_PROBES);
+        // Starts out zeroed, i.e. black.
+        let empty = create_cubemaps(device, "Empty Reflection Probe", 1, 1);
+        let cube_view = |texture: &wgpu::Texture, probe: usize| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Reflection Probe Cube View"),
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                base_array_layer: 6 * probe as u32,
+                array_layer_count: NonZeroU32::new(6),
+                ..Default::default()
+            })
+        };
+        let cube_views: Vec<wgpu::TextureView> = (0..MAX_PROBES).map(|i| cube_view(&cubemaps, i)).collect();
+        let empty_view = cube_view(&empty, 0);
+        let face_views = (0..6 * MAX_PROBES as u32)
+            .map(|layer| {
+                cubemaps.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Reflection Probe Face View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let depth_view = crate::gfx::create_depth_view(device, (PROBE_RESOLUTION, PROBE_RESOLUTION), 1);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Reflection Probes Buffer"),
+            size: std::mem::size_of::<ProbesUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Reflection Probe Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = |label: &str, views: &[&wgpu::TextureView]| {
+            let mut entries = vec![
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ];
+            for (i, view) in views.iter().enumerate() {
+                entries.push(wgpu::BindGroupEntry {
+                    binding: 2 + i as u32,
+                    resource: wgpu::BindingResource::TextureView(view),
+                });
+            }
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &layout,
+                entries: &entries,
+            })
+        };
+        let bind_group_main = bind_group("Reflection Probes Bind Group", &cube_views.iter().collect::<Vec<_>>());
+        let capture_bind_group = bind_group("Reflection Probe Capture Bind Group", &[&empty_view; MAX_PROBES]);
+
+        ReflectionProbes {
+            slots: Vec::new(),
+            face_views,
+            depth_view,
+            uniform_buffer,
+            uniform_dirty: true,
+            layout,
+            bind_group: bind_group_main,
+            capture_bind_group,
+        }
+    }
+
+    // Takes the first free cubemap, `None` if all MAX_PROBES are taken.
+    pub fn add(&mut self, probe: ReflectionProbe) -> Option<ReflectionProbeId> {
+        let slot = Some(ProbeSlot {
+            probe,
+            pending: 6,
+            next_face: 0,
+        });
+        self.uniform_dirty = true;
+        match self.slots.iter().position(Option::is_none) {
+            Some(index) => {
+                self.slots[index] = slot;
+                Some(ReflectionProbeId(index))
+            }
+            None if self.slots.len() < MAX_PROBES => {
+                self.slots.push(slot);
+                Some(ReflectionProbeId(self.slots.len() - 1))
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove(&mut self, id: ReflectionProbeId) {
+        self.slots[id.0] = None;
+        self.uniform_dirty = true;
+    }
+
+    pub fn get(&self, id: ReflectionProbeId) -> &ReflectionProbe {
+        &self.slots[id.0].as_ref().expect("probe was removed").probe
+    }
+
+    // Moving a probe renders it again.
+    pub fn set(&mut self, id: ReflectionProbeId, probe: ReflectionProbe) {
+        let slot = self.slots[id.0].as_mut().expect("probe was removed");
+        slot.probe = probe;
+        slot.pending = 6;
+        self.uniform_dirty = true;
+    }
+
+    // Renders all faces of an on-demand probe again, e.g. after the scene around it changed.
+    pub fn refresh(&mut self, id: ReflectionProbeId) {
+        if let Some(slot) = &mut self.slots[id.0] {
+            slot.pending = 6;
+        }
+    }
+
+    // The (probe, face) pairs to render this frame, marked as rendered.
+    pub fn take_due_faces(&mut self) -> Vec<(ReflectionProbeId, usize)> {
+        let mut faces = Vec::new();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let slot = match slot {
+                Some(slot) => slot,
+                None => continue,
+            };
+            let count = match slot.probe.update {
+                // A complete cubemap first, then a few faces every frame.
+                ProbeUpdate::Amortized { faces_per_frame } if slot.pending == 0 => faces_per_frame.min(6),
+                _ => slot.pending,
+            };
+            for _ in 0..count {
+                faces.push((ReflectionProbeId(index), slot.next_face));
+                slot.next_face = (slot.next_face + 1) % 6;
+            }
+            slot.pending = 0;
+        }
+        faces
+    }
+
+    // Uploads the boxes of the probes if they changed.
+    pub fn prepare(&mut self, queue: &wgpu::Queue) {
+        if !self.uniform_dirty {
+            return;
+        }
+        self.uniform_dirty = false;
+        let mut uniform = ProbesUniform {
+            count: self.slots.len() as u32,
+            probes: [ProbeParams {
+                position: Vector4::new(0.0, 0.0, 0.0, 0.0),
+                half_extents: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            }; MAX_PROBES],
+        };
+        // Removed probes leave holes, which get a box nothing is in.
+        for (index, slot) in self.slots.iter().enumerate() {
+            if let Some(ProbeSlot { probe, .. }) = slot {
+                let p = probe.position;
+                uniform.probes[index] = ProbeParams {
+                    position: Vector4::new(p.x, p.y, p.z, probe.blend_distance.max(1e-4)),
+                    half_extents: probe.half_extents.extend(0.0),
+                };
+            } else {
+                uniform.probes[index].position.w = 1e-4;
+                uniform.probes[index].half_extents = Vector4::new(-1.0, -1.0, -1.0, 0.0);
+            }
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &uniform.to_uniform_bytes());
+    }
+
+    pub fn face_view(&self, id: ReflectionProbeId, face: usize) -> &wgpu::TextureView {
+        &self.face_views[6 * id.0 + face]
+    }
+
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_view
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn capture_bind_group(&self) -> &wgpu::BindGroup {
+        &self.capture_bind_group
+    }
+}
+
+fn create_cubemaps(device: &wgpu::Device, label: &str, resolution: u32, count: usize) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: 6 * count as u32,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: PROBE_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    })
+}
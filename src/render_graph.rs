@@ -0,0 +1,386 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use crate::clear::ClearSettings;
+
+// Render graph
+//======================
+// GFX runs the nodes of its render graph around its own scene pass, in the same command buffer.
+// Nodes communicate through named resources (textures and buffers) owned by the graph:
+// a node looks up its inputs by name and creates or resizes its outputs on demand.
+//
+// This is also how crates using the engine add their own passes, without touching gfx.rs:
+// implement `RenderNode` (or wrap a closure in a `CallbackNode`), pick the `Stage` it runs in and
+// declare the resources it reads and writes. Within a stage, a node runs after the nodes that
+// write what it reads, otherwise in the order the nodes were added. At `Stage::AfterScene`,
+// `NodeContext::frame` holds the frame's texture, for passes that draw on top of the scene.
+// Nodes can be turned off and on again by name, e.g. from a settings menu, and be told how to
+// clear their targets with `RenderGraph::set_clear`. Nodes with numbers worth tweaking while they
+// run list them in `RenderNode::parameters`; the settings panel shows them as sliders and scripts
+// set them with `set_parameter`.
+
+/// A texture owned by the render graph, usable as sampled texture, storage texture and render target.
+pub struct GraphTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+}
+
+/// The named textures and buffers shared between render graph nodes.
+#[derive(Default)]
+pub struct GraphResources {
+    textures: HashMap<String, GraphTexture>,
+    buffers: HashMap<String, (wgpu::Buffer, wgpu::BufferAddress)>, // With their size.
+}
+
+impl GraphResources {
+    pub fn texture(&self, name: &str) -> Option<&GraphTexture> {
+        self.textures.get(name)
+    }
+
+    pub fn buffer(&self, name: &str) -> Option<&wgpu::Buffer> {
+        self.buffers.get(name).map(|(buffer, _)| buffer)
+    }
+
+    // Returns the texture `name`, (re)creating it if it does not exist yet
+    // or does not have the requested size and format.
+    pub fn ensure_texture(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> &GraphTexture {
+        let stale = match self.textures.get(name) {
+            Some(t) => t.width != width || t.height != height || t.format != format,
+            None => true,
+        };
+        if stale {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(name),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST,
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.textures.insert(
+                name.to_string(),
+                GraphTexture {
+                    texture,
+                    view,
+                    width,
+                    height,
+                    format,
+                },
+            );
+        }
+        &self.textures[name]
+    }
+
+    // Returns the buffer `name`, creating it if it does not exist yet or is too small.
+    pub fn ensure_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        size: wgpu::BufferAddress,
+        usage: wgpu::BufferUsages,
+    ) -> &wgpu::Buffer {
+        if self.buffers.get(name).is_none_or(|(_, current)| *current < size) {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(name),
+                size,
+                usage,
+                mapped_at_creation: false,
+            });
+            self.buffers.insert(name.to_string(), (buffer, size));
+        }
+        &self.buffers[name].0
+    }
+}
+
+/// Where in the frame a node runs, relative to the scene pass of GFX.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    // Before the scene is drawn, e.g. to render shadow maps or run simulations it draws from.
+    BeforeScene,
+    // After the scene and the text overlay, e.g. post-processing.
+    AfterScene,
+}
+
+/// The frame being rendered.
+#[derive(Clone, Copy)]
+pub struct FrameTarget<'a> {
+    // From `Stage::AfterScene` on, `None` before. Not multisampled.
+    pub view: Option<&'a wgpu::TextureView>,
+    pub format: wgpu::TextureFormat,
+    pub size: (u32, u32),
+}
+
+/// What a node gets access to while it records its commands.
+pub struct NodeContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub resources: &'a mut GraphResources,
+    pub frame: FrameTarget<'a>,
+    // Configured for the node with `RenderGraph::set_clear`, `None` if the node decides itself.
+    pub clear: Option<ClearSettings>,
+}
+
+/// A number of a node that can be changed while it runs, see above.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeParameter {
+    pub name: &'static str,
+    pub value: f32,
+    pub range: RangeInclusive<f32>, // Of sensible values, for sliders.
+}
+
+impl NodeParameter {
+    pub fn new(name: &'static str, value: f32, range: RangeInclusive<f32>) -> NodeParameter {
+        NodeParameter { name, value, range }
+    }
+}
+
+/// A pass of the render graph.
+pub trait RenderNode {
+    fn name(&self) -> &str;
+
+    fn stage(&self) -> Stage {
+        Stage::AfterScene
+    }
+
+    // The names of the graph resources the node reads and writes, to order the nodes.
+    fn reads(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    // Records the commands of the node. Inputs that do not exist (yet) should be skipped quietly.
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder);
+
+    // The tweakable numbers of the node, with their current values.
+    fn parameters(&self) -> Vec<NodeParameter> {
+        Vec::new()
+    }
+
+    // Called with one of the names of `parameters`. Values outside its range are allowed.
+    fn set_parameter(&mut self, _name: &str, _value: f32) {}
+}
+
+type EncodeFn = dyn FnMut(&mut NodeContext, &mut wgpu::CommandEncoder);
+
+/// A node made of a closure, for passes that need no type of their own:
+///
+/// ```ignore
+/// gfx.add_render_node(Box::new(
+///     CallbackNode::new("Vignette", |ctx, encoder| { ... })
+///         .reads(&["scene.hdr"])
+///         .writes(&["scene.vignette"]),
+/// ));
+/// ```
+pub struct CallbackNode {
+    name: String,
+    stage: Stage,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    encode: Box<EncodeFn>,
+}
+
+impl CallbackNode {
+    pub fn new(name: &str, encode: impl FnMut(&mut NodeContext, &mut wgpu::CommandEncoder) + 'static) -> CallbackNode {
+        CallbackNode {
+            name: name.to_string(),
+            stage: Stage::AfterScene,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            encode: Box::new(encode),
+        }
+    }
+
+    pub fn stage(mut self, stage: Stage) -> CallbackNode {
+        self.stage = stage;
+        self
+    }
+
+    pub fn reads(mut self, names: &[&str]) -> CallbackNode {
+        self.reads.extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
+    pub fn writes(mut self, names: &[&str]) -> CallbackNode {
+        self.writes.extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+}
+
+impl RenderNode for CallbackNode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        self.reads.iter().map(String::as_str).collect()
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        self.writes.iter().map(String::as_str).collect()
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        (self.encode)(ctx, encoder)
+    }
+}
+
+/// The nodes, in the order they run, and the resources they share.
+#[derive(Default)]
+pub struct RenderGraph {
+    nodes: Vec<Box<dyn RenderNode>>,
+    sorted: bool, // Whether `nodes` are in running order, see `sort`.
+    disabled: HashSet<String>,
+    clears: HashMap<String, ClearSettings>,
+    pub resources: GraphResources,
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph::default()
+    }
+
+    pub fn add_node(&mut self, node: Box<dyn RenderNode>) {
+        self.nodes.push(node);
+        self.sorted = false;
+    }
+
+    pub fn has_nodes(&self, stage: Stage) -> bool {
+        self.nodes.iter().any(|node| node.stage() == stage && self.is_enabled(node.name()))
+    }
+
+    pub fn node_names(&self) -> Vec<&str> {
+        self.nodes.iter().map(|node| node.name()).collect()
+    }
+
+    // Skips the nodes called `name` until they are enabled again.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        if enabled {
+            self.disabled.remove(name);
+        } else {
+            self.disabled.insert(name.to_string());
+        }
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        !self.disabled.contains(name)
+    }
+
+    // How the nodes called `name` clear their targets, handed to them in `NodeContext::clear`.
+    // `None` leaves it to the node again.
+    pub fn set_clear(&mut self, name: &str, clear: Option<ClearSettings>) {
+        match clear {
+            Some(clear) => self.clears.insert(name.to_string(), clear),
+            None => self.clears.remove(name),
+        };
+    }
+
+    pub fn clear(&self, name: &str) -> Option<ClearSettings> {
+        self.clears.get(name).copied()
+    }
+
+    // The parameters of the node called `name`, see `RenderNode::parameters`.
+    pub fn parameters(&self, name: &str) -> Vec<NodeParameter> {
+        self.nodes.iter().filter(|node| node.name() == name).flat_map(|node| node.parameters()).collect()
+    }
+
+    // Returns false if no node called `node` has the parameter.
+    pub fn set_parameter(&mut self, node: &str, parameter: &str, value: f32) -> bool {
+        let mut found = false;
+        for node in self.nodes.iter_mut().filter(|n| n.name() == node) {
+            if node.parameters().iter().any(|p| p.name == parameter) {
+                node.set_parameter(parameter, value);
+                found = true;
+            }
+        }
+        found
+    }
+
+    // Records the nodes of `stage`.
+    pub fn run(
+        &mut self,
+        stage: Stage,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: FrameTarget,
+    ) {
+        if !self.sorted {
+            self.sort();
+        }
+        let mut ctx = NodeContext {
+            device,
+            queue,
+            resources: &mut self.resources,
+            frame,
+            clear: None,
+        };
+        let disabled = &self.disabled;
+        for node in self.nodes.iter_mut().filter(|node| node.stage() == stage && !disabled.contains(node.name())) {
+            // Groups the commands of each node under its name in RenderDoc and PIX captures.
+            encoder.push_debug_group(node.name());
+            ctx.clear = self.clears.get(node.name()).copied();
+            node.run(&mut ctx, encoder);
+            encoder.pop_debug_group();
+        }
+    }
+
+    // Puts the nodes in running order: by stage, then each after the nodes that write what it
+    // reads, keeping the order they were added in otherwise.
+    fn sort(&mut self) {
+        let mut pending: Vec<Box<dyn RenderNode>> = std::mem::take(&mut self.nodes);
+        for stage in [Stage::BeforeScene, Stage::AfterScene] {
+            loop {
+                let in_stage: Vec<usize> = (0..pending.len()).filter(|&i| pending[i].stage() == stage).collect();
+                if in_stage.is_empty() {
+                    break;
+                }
+                // The first node none of the other pending nodes of the stage writes an input of.
+                let ready = in_stage.iter().copied().find(|&i| {
+                    let reads = pending[i].reads();
+                    in_stage
+                        .iter()
+                        .all(|&j| j == i || !pending[j].writes().iter().any(|name| reads.contains(name)))
+                });
+                let next = match ready {
+                    Some(i) => i,
+                    None => {
+                        tracing::warn!(
+                            "Render graph nodes depend on each other in a cycle, running '{}' first",
+                            pending[in_stage[0]].name()
+                        );
+                        in_stage[0]
+                    }
+                };
+                self.nodes.push(pending.remove(next));
+            }
+        }
+        self.sorted = true;
+    }
+}
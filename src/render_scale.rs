@@ -0,0 +1,280 @@
+use cgmath::Vector2;
+
+use crate::reflection::ShaderReflection;
+use crate::uniform::UniformLayout;
+
+// Render scale
+//======================
+// The cost of a 3D scene grows with its pixels. With a render scale below 1 the cameras render
+// into a smaller texture, which is then scaled up to the frame, and the text overlay is drawn on
+// top of that at full size, so it stays sharp:
+//
+//     GfxOptions { render_scale: Some(RenderScaleSettings::fixed(0.75)), ..Default::default() }
+//
+// Dynamic resolution picks the scale itself: every `ADJUST_INTERVAL` frames it compares the
+// average GPU time of the frame (see gpu_timer.rs) with the target, and moves the scale by the
+// square root of their ratio, since the cost of a frame grows with the area. It scales down as
+// soon as frames are too slow, but only scales up with some headroom, so the scale doesn't flip
+// back and forth. Without timestamp queries there are no GPU times, and the scale stays put.
+//
+// The texture is allocated for the largest scale the settings allow, and the scene is rendered
+// into its top left corner, so changing the scale doesn't recreate the render targets. Render
+// graph nodes after the scene, and the scene's HDR texture, are at full size.
+
+/// The render graph texture the scene is rendered into with a render scale.
+pub const SCALED_TARGET: &str = "scene.scaled";
+
+// Frames the GPU times are averaged over before adjusting the scale.
+const ADJUST_INTERVAL: usize = 30;
+// Dynamic scales are multiples of this, so small changes in the frame time don't move them.
+const SCALE_STEP: f32 = 0.05;
+// Frames must be this much faster than the target before the scale goes up.
+const HEADROOM: f32 = 0.85;
+
+/// Holds a frame rate by adjusting the render scale, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DynamicResolution {
+    pub target_frame_time: f32, // In milliseconds of GPU time.
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl DynamicResolution {
+    pub fn new(target_fps: f32) -> DynamicResolution {
+        DynamicResolution {
+            target_frame_time: 1000.0 / target_fps.max(1.0),
+            min_scale: 0.5,
+            max_scale: 1.0,
+        }
+    }
+
+    pub fn with_range(mut self, min_scale: f32, max_scale: f32) -> DynamicResolution {
+        self.min_scale = min_scale;
+        self.max_scale = max_scale;
+        self
+    }
+}
+
+/// The size the scene is rendered at, relative to the frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderScaleSettings {
+    pub scale: f32, // The starting scale with dynamic resolution.
+    pub dynamic: Option<DynamicResolution>,
+}
+
+impl RenderScaleSettings {
+    pub fn fixed(scale: f32) -> RenderScaleSettings {
+        RenderScaleSettings { scale, dynamic: None }
+    }
+
+    pub fn dynamic(dynamic: DynamicResolution) -> RenderScaleSettings {
+        RenderScaleSettings {
+            scale: dynamic.max_scale,
+            dynamic: Some(dynamic),
+        }
+    }
+
+    // The largest scale, which the texture is allocated for.
+    fn max_scale(&self) -> f32 {
+        let max = self.dynamic.map_or(self.scale, |dynamic| dynamic.max_scale.max(dynamic.min_scale));
+        max.clamp(0.1, 1.0)
+    }
+
+    fn min_scale(&self) -> f32 {
+        self.dynamic.map_or(self.scale, |dynamic| dynamic.min_scale).clamp(0.1, self.max_scale())
+    }
+}
+
+uniform_struct! {
+    struct UpscaleParams {
+        uv_scale: Vector2<f32>,
+    }
+}
+assert_uniform_size!(UpscaleParams, 8);
+
+/// The current scale, and the pass scaling the scene up to the frame.
+pub struct RenderScale {
+    settings: RenderScaleSettings,
+    scale: f32,
+    frame_times: Vec<f32>, // Since the last adjustment.
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+}
+
+impl RenderScale {
+    // `format` is that of the scene, which is also what it is scaled into.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, settings: RenderScaleSettings) -> RenderScale {
+        let wgsl = include_str!("render_scale.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        let bind_group_layout = reflection.create_bind_group_layout(device, Some("Upscale"), 0);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Upscale Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Upscale Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Upscale Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Upscale Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Upscale Params"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        RenderScale {
+            scale: settings.scale.clamp(settings.min_scale(), settings.max_scale()),
+            settings,
+            frame_times: Vec::new(),
+            pipeline,
+            bind_group_layout,
+            sampler,
+            params,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn settings(&self) -> RenderScaleSettings {
+        self.settings
+    }
+
+    // Returns true if the texture must be reallocated, see `allocated_size`.
+    pub fn set_settings(&mut self, settings: RenderScaleSettings) -> bool {
+        let reallocate = settings.max_scale() != self.settings.max_scale();
+        self.scale = settings.scale.clamp(settings.min_scale(), settings.max_scale());
+        self.settings = settings;
+        self.frame_times.clear();
+        reallocate
+    }
+
+    // The size of a `width` x `height` frame at the current scale.
+    pub fn scaled_size(&self, width: u32, height: u32) -> (u32, u32) {
+        scale_size(width, height, self.scale)
+    }
+
+    // The size of the texture and the render targets, for the largest scale.
+    pub fn allocated_size(&self, width: u32, height: u32) -> (u32, u32) {
+        scale_size(width, height, self.settings.max_scale())
+    }
+
+    // Adds the GPU times of a frame's passes, and adjusts a dynamic scale every `ADJUST_INTERVAL`
+    // frames, see above.
+    pub fn update(&mut self, pass_times: &[(&'static str, f32)]) {
+        let dynamic = match self.settings.dynamic {
+            Some(dynamic) if !pass_times.is_empty() => dynamic,
+            _ => return,
+        };
+        self.frame_times.push(pass_times.iter().map(|(_, time)| time).sum());
+        if self.frame_times.len() < ADJUST_INTERVAL {
+            return;
+        }
+        let average = self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32;
+        self.frame_times.clear();
+        if average <= 0.0 {
+            return;
+        }
+        let ratio = dynamic.target_frame_time / average;
+        if ratio >= 1.0 && ratio * HEADROOM < 1.0 {
+            return;
+        }
+        let scale = (self.scale * ratio.sqrt() / SCALE_STEP).round() * SCALE_STEP;
+        let scale = scale.clamp(self.settings.min_scale(), self.settings.max_scale());
+        if scale != self.scale {
+            tracing::debug!("Render scale {:.2} -> {:.2} ({:.1} ms per frame)", self.scale, scale, average);
+            self.scale = scale;
+        }
+    }
+
+    // Records the pass scaling the top left `size` of `source`, which is `allocated` large, up to
+    // all of `target`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        size: (u32, u32),
+        allocated: (u32, u32),
+        target: &wgpu::TextureView,
+    ) {
+        let params = UpscaleParams {
+            uv_scale: Vector2::new(size.0 as f32 / allocated.0 as f32, size.1 as f32 / allocated.1 as f32),
+        };
+        queue.write_buffer(&self.params, 0, &params.to_uniform_bytes());
+        // The source texture is recreated when the frame is resized.
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Upscale Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Upscale Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn scale_size(width: u32, height: u32, scale: f32) -> (u32, u32) {
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
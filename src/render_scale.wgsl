@@ -0,0 +1,33 @@
+// Scales the part of the scene texture the scene was rendered into up to the whole frame. See
+// render_scale.rs.
+
+struct UpscaleParams {
+    uv_scale: vec2<f32>; // The rendered part of the texture, from its top left corner.
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: UpscaleParams;
+[[group(0), binding(1)]]
+var scene: texture_2d<f32>;
+[[group(0), binding(2)]]
+var scene_sampler: sampler;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    // A triangle over the whole frame, with uv (0, 0) in the top left corner.
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(scene, scene_sampler, in.uv * params.uv_scale);
+}
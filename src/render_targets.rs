@@ -0,0 +1,308 @@
+use std::rc::Rc;
+
+use cgmath::{Point3, Vector3, Vector4};
+
+use crate::camera::Camera;
+use crate::device_cache::DeviceCache;
+use crate::game::Event;
+use crate::gfx::{create_depth_view, GFX};
+use crate::layers::RenderLayers;
+use crate::mesh::Mesh;
+use crate::texture::Texture;
+use crate::vertex_layout::VertexData;
+
+// Render targets
+//======================
+// Cameras that render the scene into a texture of their own instead of a region of the surface,
+// for security camera monitors, rear-view mirrors and portals. The texture is an ordinary material
+// texture, e.g. the glow of an emissive material, so the screen of a monitor shows it unlit:
+//
+//     let id = gfx.add_texture_camera(camera, RenderTarget::new(512, 288));
+//     let screen = gfx.render_target_texture(id).clone();
+//     let material = gfx.add_emissive_material(Vector4::new(0.0, 0.0, 0.0, 1.0), Vector3::new(1.0, 1.0, 1.0), 1.0, Some(screen))?;
+//
+// Every frame, before the scene, the active targets render in ascending `order` (then in the order
+// they were added), so a target may show what a target of a lower order rendered this frame, and
+// the one of a higher order what it rendered last frame. A target never draws the surfaces
+// sampling its own texture, which it is writing.
+//
+// Like the mirrors (mirrors.rs), they draw the renderables without multisampling, the sky, the
+// grid or the post effects, in the format of the scene.
+
+/// Where and when a texture camera renders, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderTarget {
+    pub resolution: (u32, u32), // Of its texture, in pixels.
+    pub order: i32, // Lower orders render first.
+    pub clear_color: wgpu::Color,
+    pub active: bool, // Inactive targets keep their last image.
+}
+
+impl RenderTarget {
+    pub fn new(width: u32, height: u32) -> RenderTarget {
+        RenderTarget {
+            resolution: (width.max(1), height.max(1)),
+            order: 0,
+            clear_color: wgpu::Color::BLACK,
+            active: true,
+        }
+    }
+
+    pub fn with_order(mut self, order: i32) -> RenderTarget {
+        self.order = order;
+        self
+    }
+
+    pub fn with_clear_color(mut self, color: wgpu::Color) -> RenderTarget {
+        self.clear_color = color;
+        self
+    }
+
+    // Width over height, for cameras with `auto_aspect`.
+    pub fn aspect(&self) -> f32 {
+        self.resolution.0 as f32 / self.resolution.1 as f32
+    }
+}
+
+/// Identifies a camera added with `GFX::add_texture_camera`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureCameraId(pub(crate) usize);
+
+struct TargetSlot {
+    camera: Camera,
+    target: RenderTarget,
+    texture: Rc<Texture>,
+    depth_view: wgpu::TextureView,
+}
+
+/// The texture cameras and the textures they render into.
+pub struct RenderTargets {
+    slots: Vec<Option<TargetSlot>>, // By id; `None` where removed.
+    format: wgpu::TextureFormat, // Of the scene.
+}
+
+impl RenderTargets {
+    pub fn new(format: wgpu::TextureFormat) -> RenderTargets {
+        RenderTargets { slots: Vec::new(), format }
+    }
+
+    pub fn add(&mut self, device: &wgpu::Device, cache: &DeviceCache, mut camera: Camera, target: RenderTarget) -> TextureCameraId {
+        if camera.auto_aspect {
+            camera.aspect = target.aspect();
+        }
+        let slot = TargetSlot {
+            camera,
+            texture: Rc::new(create_texture(device, cache, self.format, target.resolution)),
+            depth_view: create_depth_view(device, target.resolution, 1),
+            target,
+        };
+        let index = match self.slots.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.slots[index] = Some(slot);
+        TextureCameraId(index)
+    }
+
+    pub fn camera(&self, id: TextureCameraId) -> &Camera {
+        &self.slot(id).camera
+    }
+
+    pub fn camera_mut(&mut self, id: TextureCameraId) -> &mut Camera {
+        &mut self.slots[id.0].as_mut().expect("texture camera was removed").camera
+    }
+
+    pub fn target(&self, id: TextureCameraId) -> &RenderTarget {
+        &self.slot(id).target
+    }
+
+    // Returns the replaced texture if it was recreated for a new resolution, so the materials
+    // sampling it can be given the new one.
+    pub fn set_target(&mut self, device: &wgpu::Device, cache: &DeviceCache, id: TextureCameraId, target: RenderTarget) -> Option<Rc<Texture>> {
+        let format = self.format;
+        let slot = self.slots[id.0].as_mut().expect("texture camera was removed");
+        let resized = slot.target.resolution != target.resolution;
+        let old = resized.then(|| {
+            slot.depth_view = create_depth_view(device, target.resolution, 1);
+            std::mem::replace(&mut slot.texture, Rc::new(create_texture(device, cache, format, target.resolution)))
+        });
+        if slot.camera.auto_aspect {
+            slot.camera.aspect = target.aspect();
+        }
+        slot.target = target;
+        old
+    }
+
+    pub fn remove(&mut self, id: TextureCameraId) {
+        self.slots[id.0] = None;
+    }
+
+    // The active targets, in the order they render.
+    pub fn ordered_ids(&self) -> Vec<TextureCameraId> {
+        let mut ids: Vec<TextureCameraId> = (0..self.slots.len())
+            .filter(|&i| self.slots[i].as_ref().is_some_and(|slot| slot.target.active))
+            .map(TextureCameraId)
+            .collect();
+        // Stable, so equal orders keep the order they were added in.
+        ids.sort_by_key(|&id| self.slot(id).target.order);
+        ids
+    }
+
+    pub fn texture(&self, id: TextureCameraId) -> &Rc<Texture> {
+        &self.slot(id).texture
+    }
+
+    pub fn depth_view(&self, id: TextureCameraId) -> &wgpu::TextureView {
+        &self.slot(id).depth_view
+    }
+
+    fn slot(&self, id: TextureCameraId) -> &TargetSlot {
+        self.slots[id.0].as_ref().expect("texture camera was removed")
+    }
+}
+
+fn create_texture(device: &wgpu::Device, cache: &DeviceCache, format: wgpu::TextureFormat, (width, height): (u32, u32)) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Render Target Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        // Copied from for `GFX::read_texture`, e.g. to save what a camera saw.
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = cache.sampler(device, &wgpu::SamplerDescriptor {
+        label: Some("Render Target Sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    Texture {
+        texture: Rc::new(texture),
+        view,
+        sampler,
+    }
+}
+
+// Monitors demo
+//======================
+
+// The security camera's sharp and pixelated resolutions, 16:9 like its monitor.
+const SHARP: (u32, u32) = (320, 180);
+const PIXELATED: (u32, u32) = (32, 18);
+const SECURITY_MONITOR: Point3<f32> = Point3::new(-1.3, 0.3, -0.5);
+const REAR_MONITOR: Point3<f32> = Point3::new(1.3, 0.3, -0.5);
+
+// Two monitors beside the pentagon. The left one shows a security camera circling it, the right one
+// a camera looking at the left monitor, which renders after the security camera and so shows its
+// image of this frame. M freezes the security camera, P pixelates it and X unplugs the camera of
+// the right monitor for good, which keeps showing its last image.
+pub struct Monitors {
+    security: TextureCameraId,
+    rear: Option<TextureCameraId>, // `None` once unplugged.
+    time: f32,
+    freeze: bool,
+    pixelate: bool,
+    unplug: bool,
+}
+
+impl Monitors {
+    pub fn new(gfx: &mut GFX) -> Monitors {
+        let night = wgpu::Color {
+            r: 0.02,
+            g: 0.04,
+            b: 0.08,
+            a: 1.0,
+        };
+        let security = gfx.add_texture_camera(Camera::new(1.0), RenderTarget::new(SHARP.0, SHARP.1).with_clear_color(night));
+        let rear_camera = Camera {
+            eye: Point3::new(0.2, 0.3, 1.2),
+            target: SECURITY_MONITOR,
+            fovy: 30.0,
+            ..Camera::new(1.0)
+        };
+        let rear = gfx.add_texture_camera(rear_camera, RenderTarget::new(256, 256).with_order(1));
+        add_monitor(gfx, "Security Monitor", SECURITY_MONITOR, (0.8, 0.45), security);
+        add_monitor(gfx, "Rear Monitor", REAR_MONITOR, (0.5, 0.5), rear);
+        Monitors {
+            security,
+            rear: Some(rear),
+            time: 0.0,
+            freeze: false,
+            pixelate: false,
+            unplug: false,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        let mut target = *gfx.render_target(self.security);
+        if std::mem::take(&mut self.freeze) {
+            target.active = !target.active;
+        }
+        if std::mem::take(&mut self.pixelate) {
+            target.resolution = if target.resolution == SHARP { PIXELATED } else { SHARP };
+        }
+        gfx.set_render_target(self.security, target);
+        if std::mem::take(&mut self.unplug) {
+            if let Some(rear) = self.rear.take() {
+                gfx.remove_texture_camera(rear);
+            }
+        }
+        let angle = self.time * 0.5;
+        let camera = gfx.texture_camera_mut(self.security);
+        camera.eye = Point3::new(2.0 * angle.sin(), 0.8, 2.0 * angle.cos());
+        camera.target = Point3::new(0.0, 0.0, 0.0);
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyPressed(key) if key == b'M' as u16 => self.freeze = true,
+            Event::KeyPressed(key) if key == b'P' as u16 => self.pixelate = true,
+            Event::KeyPressed(key) if key == b'X' as u16 => self.unplug = true,
+            _ => {}
+        }
+    }
+}
+
+// A screen facing +z showing what the texture camera renders, unlit.
+fn add_monitor(gfx: &mut GFX, label: &str, center: Point3<f32>, (width, height): (f32, f32), camera: TextureCameraId) {
+    let corners = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+    let vertices = VertexData {
+        colors: Some(vec![[1.0, 1.0, 1.0]; 4]),
+        // The top of the texture is at v = 0.
+        uvs: Some(corners.iter().map(|&(x, y)| [x + 0.5, 0.5 - y]).collect()),
+        ..VertexData::new(corners.iter().map(|&(x, y)| [center.x + x * width, center.y + y * height, center.z]).collect())
+    };
+    let mesh = gfx.add_mesh(Mesh::with_attributes(gfx.device(), label, &vertices, &[0, 1, 2, 0, 2, 3]));
+    let screen = gfx.render_target_texture(camera).clone();
+    let material = gfx
+        .add_emissive_material(Vector4::new(0.0, 0.0, 0.0, 1.0), Vector3::new(1.0, 1.0, 1.0), 1.0, Some(screen))
+        .expect("the default shader has an emissive texture");
+    gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_targets_are_at_least_a_pixel() {
+        let target = RenderTarget::new(0, 0).with_order(2);
+        assert_eq!(target.resolution, (1, 1));
+        assert_eq!((target.order, target.active), (2, true));
+        assert_eq!(RenderTarget::new(SHARP.0, SHARP.1).aspect(), 16.0 / 9.0);
+    }
+}
@@ -0,0 +1,96 @@
+use std::ffi::c_void;
+use std::path::PathBuf;
+
+// RenderDoc captures
+//======================
+// With the `renderdoc` feature, the GFX talks to RenderDoc's in-application API when the game runs
+// under RenderDoc (launched from it, or with the RenderDoc library found next to the executable or
+// on the library path). `GFX::trigger_capture`, bound to F11 by default, then captures the frame
+// that is being rendered, so a glitch that shows up once can be captured right when it does,
+// without setting up the capture in RenderDoc's UI first.
+//
+// The library has to be loaded before the device is created to hook into the graphics API, which
+// is why the GFX does this first thing. Without RenderDoc nothing changes, and captures are ignored
+// with a warning.
+
+type GetApi = unsafe extern "C" fn(version: renderdoc_sys::RENDERDOC_Version, api: *mut *mut c_void) -> i32;
+
+#[cfg(windows)]
+const LIBRARY: &str = "renderdoc.dll";
+#[cfg(target_os = "macos")]
+const LIBRARY: &str = "librenderdoc.dylib";
+#[cfg(all(unix, not(target_os = "macos")))]
+const LIBRARY: &str = "librenderdoc.so";
+
+/// The in-application API of a loaded RenderDoc.
+pub struct RenderDoc {
+    api: renderdoc_sys::RENDERDOC_API_1_4_1,
+    _library: libloading::Library, // Keeps the function pointers valid.
+    captures: u32, // Seen by `new_captures`.
+}
+
+impl RenderDoc {
+    // Loads RenderDoc, or `None` if it isn't available.
+    pub fn load() -> Option<RenderDoc> {
+        // Safety: RenderDoc's initialization has no preconditions, and the API table it fills in
+        // lives as long as the library, which is kept loaded together with the copy below.
+        unsafe {
+            let library = match libloading::Library::new(LIBRARY) {
+                Ok(library) => library,
+                Err(e) => {
+                    tracing::debug!("RenderDoc is not available: {}", e);
+                    return None;
+                }
+            };
+            let get_api: libloading::Symbol<GetApi> = library.get(b"RENDERDOC_GetAPI\0").ok()?;
+            let mut api = std::ptr::null_mut();
+            if get_api(renderdoc_sys::eRENDERDOC_API_Version_1_4_1, &mut api) != 1 || api.is_null() {
+                tracing::warn!("{} doesn't support RenderDoc API 1.4.1", LIBRARY);
+                return None;
+            }
+            let api = *(api as *const renderdoc_sys::RENDERDOC_API_1_4_1);
+            let captures = api.GetNumCaptures.map_or(0, |count| count());
+            tracing::info!("RenderDoc loaded, captures are triggered with GFX::trigger_capture");
+            Some(RenderDoc {
+                api,
+                _library: library,
+                captures,
+            })
+        }
+    }
+
+    // Captures the next frame presented, see above.
+    pub fn trigger_capture(&self) {
+        if let Some(trigger) = self.api.TriggerCapture {
+            // Safety: the function pointer comes from the loaded library.
+            unsafe { trigger() }
+        }
+    }
+
+    // The files of the captures finished since the last call.
+    pub fn new_captures(&mut self) -> Vec<PathBuf> {
+        let (count, get) = match (self.api.GetNumCaptures, self.api.GetCapture) {
+            // Safety: the function pointer comes from the loaded library.
+            (Some(count), Some(get)) => (unsafe { count() }, get),
+            _ => return Vec::new(),
+        };
+        let paths = (self.captures..count)
+            .filter_map(|index| {
+                // Safety: the first call asks for the length of the path including the terminating
+                // null, the second one writes that many bytes.
+                unsafe {
+                    let mut length = 0;
+                    if get(index, std::ptr::null_mut(), &mut length, std::ptr::null_mut()) != 1 || length == 0 {
+                        return None;
+                    }
+                    let mut path = vec![0u8; length as usize];
+                    get(index, path.as_mut_ptr().cast(), &mut length, std::ptr::null_mut());
+                    path.truncate(path.iter().position(|&c| c == 0).unwrap_or(path.len()));
+                    Some(PathBuf::from(String::from_utf8_lossy(&path).into_owned()))
+                }
+            })
+            .collect();
+        self.captures = count;
+        paths
+    }
+}
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::Event;
+
+// Replays
+//======================
+// A recording holds everything that makes a session differ from the next one:
+//  - the seed of the `Rng`,
+//  - the real time that passed before every frame, which decides how many fixed updates run,
+//  - the input events handed to the game in every frame.
+// Replaying feeds these back in place of the clock and the window, while input from the real
+// mouse and keyboard is ignored. Everything else is computed the same way again, so a recorded
+// session plays out bit-for-bit identically, and a bug that happened once can be stepped through
+// in the debugger.
+//
+// The file is JSON, one line per frame after a header line, and is written as the session runs,
+// so a recording survives a crash up to the last frame or so. Assets that load in the background
+// may arrive a frame earlier or later, which only matters to games that act on them.
+
+const VERSION: u32 = 1;
+
+/// The first line of a recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub seed: u64,
+    pub fixed_dt: f32,
+    // Client area size; mouse positions are only comparable in a window of the same size.
+    pub width: i32,
+    pub height: i32,
+}
+
+impl ReplayHeader {
+    pub fn new(seed: u64, fixed_dt: f32, width: i32, height: i32) -> ReplayHeader {
+        ReplayHeader {
+            version: VERSION,
+            seed,
+            fixed_dt,
+            width,
+            height,
+        }
+    }
+}
+
+/// One frame of a recording.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    // Real time since the previous frame, in seconds, as `Time::begin_frame` measured it.
+    pub dt: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Empty,
+    Version(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{}", e),
+            ReplayError::Json(e) => write!(f, "{}", e),
+            ReplayError::Empty => write!(f, "the recording is empty"),
+            ReplayError::Version(version) => {
+                write!(f, "recorded with version {}, this build plays version {}", version, VERSION)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ReplayError {
+    fn from(e: serde_json::Error) -> Self {
+        ReplayError::Json(e)
+    }
+}
+
+/// Writes a recording, frame by frame.
+pub struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, header: &ReplayHeader) -> Result<Recorder, ReplayError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut file, header)?;
+        writeln!(file)?;
+        Ok(Recorder { file })
+    }
+
+    pub fn record(&mut self, frame: &ReplayFrame) {
+        let result = serde_json::to_writer(&mut self.file, frame)
+            .map_err(ReplayError::from)
+            .and_then(|()| {
+                writeln!(self.file)?;
+                // Every frame reaches the file, in case the session ends in a crash.
+                Ok(self.file.flush()?)
+            });
+        if let Err(e) = result {
+            tracing::error!("Failed to record frame: {}", e);
+        }
+    }
+}
+
+/// Reads a recording back, frame by frame.
+pub struct Player {
+    header: ReplayHeader,
+    lines: Lines<BufReader<File>>,
+    frame: u64,
+}
+
+impl Player {
+    pub fn open(path: &Path) -> Result<Player, ReplayError> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: ReplayHeader = serde_json::from_str(&lines.next().ok_or(ReplayError::Empty)??)?;
+        if header.version != VERSION {
+            return Err(ReplayError::Version(header.version));
+        }
+        Ok(Player { header, lines, frame: 0 })
+    }
+
+    pub fn header(&self) -> &ReplayHeader {
+        &self.header
+    }
+
+    // The next recorded frame, or `None` at the end of the recording.
+    pub fn next_frame(&mut self) -> Option<ReplayFrame> {
+        let line = self.lines.next()?;
+        self.frame += 1;
+        match line.map_err(ReplayError::from).and_then(|line| Ok(serde_json::from_str(&line)?)) {
+            Ok(frame) => Some(frame),
+            Err(e) => {
+                // A crash may have cut the last line short.
+                tracing::warn!("Recording ends at frame {}: {}", self.frame, e);
+                None
+            }
+        }
+    }
+}
+
+/// Whether `App` records or replays the session.
+pub enum Replay {
+    Off,
+    Recording(Recorder),
+    Playing(Player),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::app::run_updates;
+    use crate::game::{Game, Input};
+    use crate::keyboard::{Keyboard, KeyboardState};
+    use crate::mouse::{Mouse, MouseDelta};
+    use crate::platform::apply_event;
+    use crate::rng::Rng;
+    use crate::time::Time;
+
+    const FIXED_DT: f32 = 1.0 / 60.0;
+    const RIGHT: u16 = 0x27;
+    const SPACE: u16 = 0x20;
+
+    // Its state depends on everything a recording holds: the keys, the mouse, the random numbers
+    // and the number of updates the frame times add up to.
+    #[derive(Debug, Default, PartialEq)]
+    struct Walker {
+        x: f32,
+        y: f32,
+        look: (isize, isize),
+        jumps: Vec<u32>,
+        updates: u32,
+        elapsed: f64,
+    }
+
+    impl Game for Walker {
+        fn update(&mut self, time: &mut Time, input: &Input) {
+            self.updates += 1;
+            if input.keys.is_pressed(RIGHT) {
+                self.x += 3.0 * time.delta();
+            }
+            if input.keys.pressed_since(&input.previous_keys).is_pressed(SPACE) {
+                self.jumps.push(input.rng.next_u32());
+            }
+            self.y += input.rng.range(-1.0, 1.0) * time.delta();
+            self.look.0 += input.mouse_delta.raw_x;
+            self.look.1 += input.mouse_delta.raw_y;
+            self.elapsed = time.elapsed();
+        }
+    }
+
+    // The part of `App` between the window and the game.
+    struct Session {
+        time: Time,
+        rng: Rng,
+        keyboard: Keyboard,
+        mouse: Mouse,
+        previous_keys: KeyboardState,
+        mouse_delta: MouseDelta,
+        accumulator: f32,
+    }
+
+    impl Session {
+        fn new(seed: u64) -> Session {
+            Session {
+                time: Time::new(),
+                rng: Rng::new(seed),
+                keyboard: Keyboard::new(),
+                mouse: Mouse::new(),
+                previous_keys: KeyboardState::default(),
+                mouse_delta: MouseDelta::default(),
+                accumulator: 0.0,
+            }
+        }
+
+        // As `App::frame`: the events of the frame, then the updates its time pays for.
+        fn frame(&mut self, game: &mut Walker, frame: &ReplayFrame) {
+            self.accumulator += self.time.begin_frame_with(frame.dt);
+            let mut queue = VecDeque::new();
+            for &event in &frame.events {
+                apply_event(&mut self.keyboard, &mut self.mouse, &mut queue, event);
+            }
+            self.mouse_delta += self.mouse.take_frame_delta();
+            let input = Input {
+                keyboard: &self.keyboard,
+                keys: self.keyboard.state(),
+                previous_keys: KeyboardState::default(),
+                mouse: &self.mouse,
+                mouse_delta: MouseDelta::default(),
+                rng: &self.rng,
+            };
+            run_updates(
+                game,
+                &mut self.time,
+                input,
+                &mut self.accumulator,
+                FIXED_DT,
+                &mut self.previous_keys,
+                &mut self.mouse_delta,
+            );
+        }
+    }
+
+    // Irregular frame times and input, as a real session has.
+    fn random_frame(rng: &Rng) -> ReplayFrame {
+        let mut events = Vec::new();
+        for (key, chance) in [(RIGHT, 0.1), (SPACE, 0.2)] {
+            if rng.chance(chance) {
+                events.push(if rng.chance(0.5) { Event::KeyPressed(key) } else { Event::KeyReleased(key) });
+            }
+        }
+        if rng.chance(0.5) {
+            events.push(Event::MouseRawMoved {
+                dx: rng.below(21) as i32 - 10,
+                dy: rng.below(21) as i32 - 10,
+            });
+        }
+        ReplayFrame {
+            dt: rng.range(0.004, 0.05),
+            events,
+        }
+    }
+
+    #[test]
+    fn a_replay_plays_out_like_the_recorded_session() {
+        let path = std::env::temp_dir().join(format!("replay-test-{}.jsonl", std::process::id()));
+        let input = Rng::new(99);
+
+        let mut recorded = Walker::default();
+        let mut session = Session::new(1234);
+        let mut recorder = Recorder::create(&path, &ReplayHeader::new(session.rng.seed(), FIXED_DT, 800, 600)).unwrap();
+        for _ in 0..500 {
+            let frame = random_frame(&input);
+            session.frame(&mut recorded, &frame);
+            recorder.record(&frame);
+        }
+        drop(recorder);
+        assert!(recorded.updates > 500 && !recorded.jumps.is_empty() && recorded.x > 0.0);
+
+        let mut player = Player::open(&path).unwrap();
+        assert_eq!(player.header().fixed_dt, FIXED_DT);
+        let mut replayed = Walker::default();
+        let mut session = Session::new(player.header().seed);
+        while let Some(frame) = player.next_frame() {
+            session.frame(&mut replayed, &frame);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed, recorded);
+    }
+}
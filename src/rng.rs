@@ -0,0 +1,78 @@
+use std::cell::Cell;
+
+// Random numbers
+//======================
+// Gameplay randomness comes from the `Rng` handed to `Game::update` through `Input`, never from
+// the operating system, so a session seeded with the same value makes the same choices.
+// This is what lets replays (see replay.rs) reproduce a session exactly.
+// The generator is PCG32 (pcg-random.org): small, fast and good enough for games, not for secrets.
+
+const MULTIPLIER: u64 = 6364136223846793005;
+const INCREMENT: u64 = 1442695040888963407;
+
+/// A seeded pseudo random number generator, see above.
+pub struct Rng {
+    // Interior mutability lets games draw numbers through the shared `Input`.
+    state: Cell<u64>,
+    seed: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        let rng = Rng {
+            state: Cell::new(0),
+            seed,
+        };
+        rng.next_u32();
+        rng.state.set(rng.state.get().wrapping_add(seed));
+        rng.next_u32();
+        rng
+    }
+
+    // Seeded from the clock, for sessions that don't need to be reproduced.
+    pub fn from_time() -> Rng {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        Rng::new(nanos)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u32(&self) -> u32 {
+        let state = self.state.get();
+        self.state.set(state.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT));
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        let rotation = (state >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    // Uniform in [0, 1).
+    pub fn next_f32(&self) -> f32 {
+        // The top 24 bits fill the mantissa exactly.
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    // Uniform in [min, max).
+    pub fn range(&self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    // Uniform in [0, count), without the bias of a plain modulo.
+    pub fn below(&self, count: u32) -> u32 {
+        assert!(count > 0, "Rng::below needs a non-empty range");
+        let threshold = count.wrapping_neg() % count;
+        loop {
+            let value = self.next_u32();
+            if value >= threshold {
+                return value % count;
+            }
+        }
+    }
+
+    pub fn chance(&self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{LoadError, ShaderHandle};
+use crate::gfx::{RenderableId, GFX};
+use crate::layers::RenderLayers;
+use crate::material::{Material, MaterialId};
+use crate::reflection::ReflectError;
+use crate::transform::Transform;
+use crate::variants::ShaderDefines;
+
+// Scene files
+//======================
+// A scene is the camera, the materials and the renderables of `GFX`, written out as RON or JSON
+// (picked by the file extension), so an authored scene can be saved and loaded again:
+//
+//     (
+//         camera: (eye: (0.0, 1.0, 3.0), target: (0.0, 0.0, 0.0), fovy: 45.0, znear: 0.1, zfar: 100.0, layers: 4294967295),
+//         materials: [
+//             (shader: Some("shaders/textured.wgsl"), params: [1.0, 1.0, 1.0, 1.0], textures: ["brick.png"]),
+//         ],
+//         entities: [
+//             (mesh: Cube, material: 0, layers: 1, transform: (translation: (x: 0.0, y: -1.0, z: 0.0))),
+//             (mesh: File("models/teapot.obj"), material: 0, layers: 1),
+//         ],
+//     )
+//
+// Assets are referred to by their files, so only what was loaded from a file can be saved:
+// meshes built in code (other than the unit cube), shaders compiled from strings and textures
+// that are not bound with `GFX::bind_texture` are left out, with a warning.
+// A material without a shader file uses the shader of the default material.
+// There are no lights yet, so they are not part of a scene.
+
+/// A scene as it is stored on disk, see above.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub camera: CameraDesc,
+    pub materials: Vec<MaterialDesc>,
+    pub entities: Vec<EntityDesc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraDesc {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    pub layers: u32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            eye: [0.0, 0.0, 2.0],
+            target: [0.0, 0.0, 0.0],
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            layers: RenderLayers::ALL.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialDesc {
+    pub shader: Option<PathBuf>,
+    // The parameter block, as it is laid out in the shader's uniform struct.
+    pub params: Vec<f32>,
+    pub textures: Vec<PathBuf>,
+    pub defines: ShaderDefines, // See variants.rs.
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EntityDesc {
+    pub mesh: MeshRef,
+    pub material: usize, // Index into `Scene::materials`.
+    #[serde(default = "default_layers")]
+    pub layers: u32,
+    #[serde(default)]
+    pub transform: Transform,
+}
+
+fn default_layers() -> u32 {
+    RenderLayers::DEFAULT.0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MeshRef {
+    Cube,
+    File(PathBuf), // An OBJ model, loaded in the background.
+}
+
+/// What `Scene::instantiate` added to `GFX`.
+pub struct SceneInstance {
+    pub renderables: Vec<RenderableId>,
+    pub materials: Vec<MaterialId>,
+    // Keeps the shader files loaded and watched.
+    pub shaders: Vec<ShaderHandle>,
+}
+
+/// Why a scene could not be saved or loaded.
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    Json(serde_json::Error),
+    UnknownFormat(PathBuf),
+    Asset(LoadError),
+    BadMaterial(usize),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Ron(e) => write!(f, "{}", e),
+            SceneError::Json(e) => write!(f, "{}", e),
+            SceneError::UnknownFormat(path) => write!(f, "{}: expected a .ron or .json file", path.display()),
+            SceneError::Asset(e) => write!(f, "{}", e),
+            SceneError::BadMaterial(index) => write!(f, "no material {} in the scene", index),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for SceneError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        SceneError::Ron(e.code)
+    }
+}
+
+impl From<ron::Error> for SceneError {
+    fn from(e: ron::Error) -> Self {
+        SceneError::Ron(e)
+    }
+}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(e: serde_json::Error) -> Self {
+        SceneError::Json(e)
+    }
+}
+
+impl From<LoadError> for SceneError {
+    fn from(e: LoadError) -> Self {
+        SceneError::Asset(e)
+    }
+}
+
+impl From<ReflectError> for SceneError {
+    fn from(e: ReflectError) -> Self {
+        SceneError::Asset(LoadError::Shader(e))
+    }
+}
+
+enum Format {
+    Ron,
+    Json,
+}
+
+fn format_of(path: &Path) -> Result<Format, SceneError> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("ron") => Ok(Format::Ron),
+        Some("json") => Ok(Format::Json),
+        _ => Err(SceneError::UnknownFormat(path.to_path_buf())),
+    }
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Scene, SceneError> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(match format_of(path)? {
+            Format::Ron => ron::from_str(&text)?,
+            Format::Json => serde_json::from_str(&text)?,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SceneError> {
+        let text = match format_of(path)? {
+            Format::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+            Format::Json => serde_json::to_string_pretty(self)?,
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    // Describes the main camera and the renderables of `gfx`, with the materials they use.
+    pub fn capture(gfx: &GFX) -> Scene {
+        let camera = gfx.camera(gfx.main_camera());
+        let mut scene = Scene {
+            camera: CameraDesc {
+                eye: camera.eye.into(),
+                target: camera.target.into(),
+                fovy: camera.fovy,
+                znear: camera.znear,
+                zfar: camera.zfar,
+                layers: camera.layers.0,
+            },
+            ..Default::default()
+        };
+
+        let assets = gfx.assets();
+        let mut material_indices: HashMap<MaterialId, usize> = HashMap::new();
+        for (index, renderable) in gfx.renderables().iter().enumerate() {
+            let mesh = if assets.is_unit_cube(&renderable.mesh) {
+                MeshRef::Cube
+            } else if let Some(path) = assets.mesh_path(&renderable.mesh) {
+                MeshRef::File(path.to_path_buf())
+            } else {
+                tracing::warn!("Not saving a renderable with a mesh that was not loaded from a file");
+                continue;
+            };
+            let material = *material_indices.entry(renderable.material).or_insert_with(|| {
+                scene.materials.push(describe_material(gfx, renderable.material));
+                scene.materials.len() - 1
+            });
+            scene.entities.push(EntityDesc {
+                mesh,
+                material,
+                layers: renderable.layers.0,
+                transform: *gfx.transforms().local(index),
+            });
+        }
+        scene
+    }
+
+    // Adds the scene to `gfx`, loading the files it refers to, and points the main camera at it.
+    pub fn instantiate(&self, gfx: &mut GFX) -> Result<SceneInstance, SceneError> {
+        let mut instance = SceneInstance {
+            renderables: Vec::new(),
+            materials: Vec::new(),
+            shaders: Vec::new(),
+        };
+
+        for desc in &self.materials {
+            let shader = match &desc.shader {
+                Some(path) => {
+                    let handle = gfx.load_shader(path)?;
+                    let id = gfx.assets().shader(&handle);
+                    instance.shaders.push(handle);
+                    id
+                }
+                None => gfx.material(gfx.default_material()).shader(),
+            };
+            let textures = desc
+                .textures
+                .iter()
+                .map(|path| gfx.load_texture(path))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut material = Material::new(shader).with_defines(desc.defines.clone());
+            material.set_raw_params(bytemuck::cast_slice(&desc.params));
+            for texture in &textures {
+                material.add_texture(gfx.assets().texture(texture).clone());
+            }
+            let id = gfx.add_material(material)?;
+            for (index, texture) in textures.into_iter().enumerate() {
+                gfx.bind_texture(id, index, texture);
+            }
+            instance.materials.push(id);
+        }
+
+        for entity in &self.entities {
+            let material = *instance
+                .materials
+                .get(entity.material)
+                .ok_or(SceneError::BadMaterial(entity.material))?;
+            let mesh = match &entity.mesh {
+                MeshRef::Cube => gfx.unit_cube(),
+                MeshRef::File(path) => gfx.load_mesh_async(path),
+            };
+            let renderable = gfx.add_renderable(mesh, material, RenderLayers(entity.layers));
+            gfx.set_transform(renderable, entity.transform);
+            instance.renderables.push(renderable);
+        }
+
+        let camera = gfx.camera_mut(gfx.main_camera());
+        camera.eye = self.camera.eye.into();
+        camera.target = self.camera.target.into();
+        camera.fovy = self.camera.fovy;
+        camera.znear = self.camera.znear;
+        camera.zfar = self.camera.zfar;
+        camera.layers = RenderLayers(self.camera.layers);
+
+        Ok(instance)
+    }
+}
+
+fn describe_material(gfx: &GFX, id: MaterialId) -> MaterialDesc {
+    let material = gfx.material(id);
+    let assets = gfx.assets();
+    let shader = assets.shader_path(material.shader()).map(Path::to_path_buf);
+    if shader.is_none() && material.shader() != gfx.material(gfx.default_material()).shader() {
+        tracing::warn!("Saving material {:?} with the default shader, its own was not loaded from a file", id);
+    }
+    let textures = (0..material.texture_count())
+        .filter_map(|index| {
+            let path = gfx
+                .bound_texture(id, index)
+                .and_then(|texture| assets.texture_path(texture));
+            if path.is_none() {
+                tracing::warn!("Not saving texture {} of material {:?}, it was not loaded from a file", index, id);
+            }
+            path.map(Path::to_path_buf)
+        })
+        .collect();
+    MaterialDesc {
+        shader,
+        params: material
+            .params()
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect(),
+        textures,
+        defines: material.defines().clone(),
+    }
+}
@@ -0,0 +1,327 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use cgmath::{Point3, Vector4};
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+
+use crate::gfx::{RenderableId, GFX};
+use crate::layers::RenderLayers;
+use crate::material::{ColorParams, Material, MaterialId};
+use crate::world_time::{AlarmId, WorldTime};
+
+// Scripting
+//======================
+// Scenes can be set up and animated from a rhai script (https://rhai.rs), which is reloaded when
+// the file changes, so it can be iterated on without recompiling. A script defines functions:
+//
+//     fn init() {                  // once, after loading
+//         this.material = material(1.0, 0.5, 0.0, 1.0);
+//         this.cube = spawn_entity("cube");
+//         set_material(this.cube, this.material);
+//         on_update("spin");       // registers more update callbacks, `update` is called anyway
+//     }
+//     fn update(dt) { ... }        // every fixed update tick
+//     fn reload() { ... }          // after the file changed and compiled again
+//
+// Functions keep their state in `this`, an object map that survives reloads.
+// Floating point arguments need a decimal point in rhai (`1.0`, not `1`).
+//
+// Bindings:
+//     spawn_entity(mesh) -> entity         mesh is "cube", or the path of an OBJ model
+//     material(r, g, b, a) -> material     uses the default shader; material 0 is the default material
+//     set_color(material, r, g, b, a)
+//     set_material(entity, material)
+//     set_visible(entity, visible)
+//     camera(x, y, z)                      the eye of the main camera
+//     look_at(x, y, z)                     the target of the main camera
+//     set_parameter(node, name, value)     of a render graph node, see render_graph.rs
+//     on_time_of_day(hour, callback)       calls `callback(hour)` every day at `hour`, see world_time.rs
+//     time_of_day() -> hour
+//     set_time_of_day(hour)
+//     log(message)
+//
+// Bindings only record commands; `ScriptHost::apply` carries them out on the GFX, after `update`.
+// The time of day bindings need a world time, which `ScriptHost::update_world_time` advances;
+// without one, `time_of_day` stays at 0 and the callbacks are never called. Like the update
+// callbacks, they are forgotten when the script is reloaded.
+
+enum Command {
+    Spawn { mesh: String },
+    Material { color: [f32; 4] },
+    SetColor { material: INT, color: [f32; 4] },
+    SetMaterial { entity: INT, material: INT },
+    SetVisible { entity: INT, visible: bool },
+    Camera { eye: [f32; 3] },
+    LookAt { target: [f32; 3] },
+    SetParameter { node: String, name: String, value: f32 },
+}
+
+#[derive(Default)]
+struct Shared {
+    commands: Vec<Command>,
+    // Ids handed out to the script; commands are carried out in order, so they match
+    // the indices of `ScriptHost::entities` and `ScriptHost::materials`.
+    next_entity: INT,
+    next_material: INT,
+    update_callbacks: Vec<String>,
+    time_callbacks: Vec<(f32, String)>, // Registered since the last `update_world_time`.
+    reset_alarms: bool, // The script was reloaded.
+    set_time_of_day: Option<f32>,
+    time_of_day: f32,
+}
+
+pub struct ScriptHost {
+    path: PathBuf,
+    engine: Engine,
+    ast: Option<AST>,
+    scope: Scope<'static>,
+    this: Dynamic,
+    shared: Rc<RefCell<Shared>>,
+    entities: Vec<RenderableId>,
+    materials: Vec<MaterialId>,
+    alarms: Vec<AlarmId>, // Of the world time, for `on_time_of_day`.
+    initialized: bool,
+}
+
+impl ScriptHost {
+    // Compiles the script at `path`. Errors are logged, and the script does nothing until it is fixed.
+    pub fn new(path: impl AsRef<Path>) -> ScriptHost {
+        let shared = Rc::new(RefCell::new(Shared {
+            next_material: 1, // 0 is the default material.
+            ..Default::default()
+        }));
+        let mut host = ScriptHost {
+            path: path.as_ref().to_path_buf(),
+            engine: create_engine(&shared),
+            ast: None,
+            scope: Scope::new(),
+            this: Dynamic::from_map(Map::new()),
+            shared,
+            entities: Vec::new(),
+            materials: Vec::new(),
+            alarms: Vec::new(),
+            initialized: false,
+        };
+        host.ast = host.compile();
+        host
+    }
+
+    fn compile(&self) -> Option<AST> {
+        match self.engine.compile_file(self.path.clone()) {
+            Ok(ast) => Some(ast),
+            Err(e) => {
+                tracing::error!("Script {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    // Calls `init` and watches the file for changes.
+    pub fn init(&mut self, gfx: &mut GFX) {
+        gfx.watch_file(&self.path);
+        self.materials = vec![gfx.default_material()];
+        self.call("init", ());
+        self.initialized = true;
+        self.apply(gfx);
+    }
+
+    // Runs the update callbacks.
+    pub fn update(&mut self, dt: f32) {
+        self.call("update", (dt as FLOAT,));
+        let callbacks = self.shared.borrow().update_callbacks.clone();
+        for callback in callbacks {
+            self.call(&callback, (dt as FLOAT,));
+        }
+    }
+
+    // Schedules the time of day callbacks the script registered, advances `world_time` by `dt`, and
+    // calls the callbacks of the alarms it passed.
+    pub fn update_world_time(&mut self, world_time: &mut WorldTime, dt: f32) {
+        {
+            let mut shared = self.shared.borrow_mut();
+            if std::mem::take(&mut shared.reset_alarms) {
+                for id in self.alarms.drain(..) {
+                    world_time.cancel(id);
+                }
+            }
+            for (hour, callback) in shared.time_callbacks.drain(..) {
+                self.alarms.push(world_time.at(hour, &callback));
+            }
+            if let Some(hours) = shared.set_time_of_day.take() {
+                world_time.set_hours(hours);
+            }
+        }
+        let events = world_time.update(dt);
+        self.shared.borrow_mut().time_of_day = world_time.hours();
+        for event in events {
+            if self.alarms.contains(&event.alarm) {
+                self.call(&event.name, (event.hour as FLOAT,));
+            }
+        }
+    }
+
+    // Reloads the script if it changed, and carries out the commands of the script so far.
+    pub fn apply(&mut self, gfx: &mut GFX) {
+        if self.initialized && gfx.changed_files().contains(&self.path) {
+            if let Some(ast) = self.compile() {
+                tracing::info!("Reloaded script {}", self.path.display());
+                self.ast = Some(ast);
+                {
+                    let mut shared = self.shared.borrow_mut();
+                    shared.update_callbacks.clear();
+                    shared.time_callbacks.clear();
+                    shared.reset_alarms = true;
+                }
+                self.call("reload", ());
+            }
+        }
+
+        let commands = std::mem::take(&mut self.shared.borrow_mut().commands);
+        for command in commands {
+            self.execute(gfx, command);
+        }
+    }
+
+    fn execute(&mut self, gfx: &mut GFX, command: Command) {
+        match command {
+            Command::Spawn { mesh } => {
+                let mesh = if mesh == "cube" {
+                    gfx.unit_cube()
+                } else {
+                    gfx.load_mesh_async(&mesh)
+                };
+                let material = gfx.default_material();
+                self.entities.push(gfx.add_renderable(mesh, material, RenderLayers::DEFAULT));
+            }
+            Command::Material { color } => {
+                let shader = gfx.material_mut(gfx.default_material()).shader();
+                let material = Material::new(shader).with_uniform(&ColorParams { color: color.into() });
+                match gfx.add_material(material) {
+                    Ok(id) => self.materials.push(id),
+                    Err(e) => tracing::error!("Script material: {}", e),
+                }
+            }
+            Command::SetColor { material, color } => {
+                if let Some(&id) = self.materials.get(material as usize) {
+                    gfx.material_mut(id).set_uniform(&ColorParams {
+                        color: Vector4::from(color),
+                    });
+                }
+            }
+            Command::SetMaterial { entity, material } => {
+                if let (Some(&entity), Some(&material)) =
+                    (self.entities.get(entity as usize), self.materials.get(material as usize))
+                {
+                    gfx.set_material(entity, material);
+                }
+            }
+            Command::SetVisible { entity, visible } => {
+                if let Some(&entity) = self.entities.get(entity as usize) {
+                    let layers = if visible { RenderLayers::DEFAULT } else { RenderLayers::NONE };
+                    gfx.set_layers(entity, layers);
+                }
+            }
+            Command::Camera { eye } => {
+                let camera = gfx.main_camera();
+                gfx.camera_mut(camera).eye = Point3::from(eye);
+            }
+            Command::LookAt { target } => {
+                let camera = gfx.main_camera();
+                gfx.camera_mut(camera).target = Point3::from(target);
+            }
+            Command::SetParameter { node, name, value } => {
+                if !gfx.render_graph_mut().set_parameter(&node, &name, value) {
+                    tracing::warn!("Script: no render graph node '{}' with parameter '{}'", node, name);
+                }
+            }
+        }
+    }
+
+    // Calls a script function with `this` bound, if the script defines it.
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs) {
+        let ast = match &self.ast {
+            Some(ast) => ast,
+            None => return,
+        };
+        if !ast.iter_functions().any(|f| f.name == name) {
+            return;
+        }
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.this);
+        if let Err(e) = self
+            .engine
+            .call_fn_with_options::<Dynamic>(options, &mut self.scope, ast, name, args)
+        {
+            tracing::error!("Script {} in {}: {}", self.path.display(), name, e);
+        }
+    }
+}
+
+fn create_engine(shared: &Rc<RefCell<Shared>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|text| tracing::info!("script: {}", text));
+    engine.on_debug(|text, _, position| tracing::debug!("script {:?}: {}", position, text));
+    engine.register_fn("log", |message: &str| tracing::info!("script: {}", message));
+
+    let s = shared.clone();
+    engine.register_fn("spawn_entity", move |mesh: &str| -> INT {
+        let mut shared = s.borrow_mut();
+        shared.commands.push(Command::Spawn { mesh: mesh.to_string() });
+        shared.next_entity += 1;
+        shared.next_entity - 1
+    });
+    let s = shared.clone();
+    engine.register_fn("material", move |r: FLOAT, g: FLOAT, b: FLOAT, a: FLOAT| -> INT {
+        let mut shared = s.borrow_mut();
+        shared.commands.push(Command::Material { color: color(r, g, b, a) });
+        shared.next_material += 1;
+        shared.next_material - 1
+    });
+    let s = shared.clone();
+    engine.register_fn("set_color", move |material: INT, r: FLOAT, g: FLOAT, b: FLOAT, a: FLOAT| {
+        let color = color(r, g, b, a);
+        s.borrow_mut().commands.push(Command::SetColor { material, color });
+    });
+    let s = shared.clone();
+    engine.register_fn("set_material", move |entity: INT, material: INT| {
+        s.borrow_mut().commands.push(Command::SetMaterial { entity, material });
+    });
+    let s = shared.clone();
+    engine.register_fn("set_visible", move |entity: INT, visible: bool| {
+        s.borrow_mut().commands.push(Command::SetVisible { entity, visible });
+    });
+    let s = shared.clone();
+    engine.register_fn("camera", move |x: FLOAT, y: FLOAT, z: FLOAT| {
+        let eye = [x as f32, y as f32, z as f32];
+        s.borrow_mut().commands.push(Command::Camera { eye });
+    });
+    let s = shared.clone();
+    engine.register_fn("look_at", move |x: FLOAT, y: FLOAT, z: FLOAT| {
+        let target = [x as f32, y as f32, z as f32];
+        s.borrow_mut().commands.push(Command::LookAt { target });
+    });
+    let s = shared.clone();
+    engine.register_fn("set_parameter", move |node: &str, name: &str, value: FLOAT| {
+        let (node, name) = (node.to_string(), name.to_string());
+        s.borrow_mut().commands.push(Command::SetParameter { node, name, value: value as f32 });
+    });
+    let s = shared.clone();
+    engine.register_fn("on_update", move |callback: &str| {
+        s.borrow_mut().update_callbacks.push(callback.to_string());
+    });
+    let s = shared.clone();
+    engine.register_fn("on_time_of_day", move |hour: FLOAT, callback: &str| {
+        s.borrow_mut().time_callbacks.push((hour as f32, callback.to_string()));
+    });
+    let s = shared.clone();
+    engine.register_fn("time_of_day", move || -> FLOAT { s.borrow().time_of_day as FLOAT });
+    let s = shared.clone();
+    engine.register_fn("set_time_of_day", move |hour: FLOAT| {
+        s.borrow_mut().set_time_of_day = Some(hour as f32);
+    });
+    engine
+}
+
+fn color(r: FLOAT, g: FLOAT, b: FLOAT, a: FLOAT) -> [f32; 4] {
+    [r as f32, g as f32, b as f32, a as f32]
+}
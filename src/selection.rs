@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+
+use cgmath::{Vector2, Vector4};
+use windows::Win32::UI::Input::KeyboardAndMouse::VK_CONTROL;
+
+use crate::game::{Event, MouseButton};
+use crate::gfx::RenderableId;
+use crate::mesh::{Mesh, VertexLayout};
+use crate::packing;
+use crate::transform::ModelInstance;
+use crate::uniform::UniformLayout;
+use crate::variants::{preprocess, ShaderDefines};
+use crate::vertex_layout::{NORMAL_LOCATION, POSITION_LOCATION};
+
+// Selection
+//======================
+// What editors need to let the user pick the objects to work on, in three parts:
+//
+//  - `Selection`, the set of selected renderables, kept by the GFX (`GFX::selection_mut`).
+//  - `SelectionTool`, which turns mouse and keyboard events into clicks and boxes with the usual
+//    meaning: a click selects what is under the cursor, Ctrl+click adds or removes it, dragging
+//    a box selects everything visible in it, Ctrl+drag adds it to the selection.
+//  - An outline around the selected renderables, drawn by every camera after the scene.
+//
+// The game feeds the tool its events and applies it once per frame, which picks exactly with the
+// ID buffer (id_buffer.rs) and draws the box while it is dragged:
+//
+//     fn on_event(&mut self, event: &Event) { self.tool.on_event(event); }
+//     fn render(&mut self, frame: &mut Frame) { frame.gfx.apply_selection(&mut self.tool, camera); }
+//
+// The outline uses the stencil buffer: the selected renderables are drawn once into it, marking
+// their pixels, then again pushed outwards by the outline's width on screen, colored only where
+// they are not marked. What is left is a band around their silhouettes, also around the parts
+// hidden behind other objects, so the selection can always be found.
+
+// A drag shorter than this, in pixels, is a click.
+const DRAG_PIXELS: f32 = 4.0;
+// The stencil value of the selected renderables' pixels.
+const STENCIL_MARK: u32 = 1;
+const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+
+/// The selected renderables, in the order they were selected.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selection {
+    ids: Vec<RenderableId>,
+}
+
+impl Selection {
+    pub fn new() -> Selection {
+        Selection::default()
+    }
+
+    pub fn ids(&self) -> &[RenderableId] {
+        &self.ids
+    }
+
+    pub fn contains(&self, id: RenderableId) -> bool {
+        self.ids.contains(&id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // The one selected last, e.g. to put a gizmo on.
+    pub fn primary(&self) -> Option<RenderableId> {
+        self.ids.last().copied()
+    }
+
+    // Replaces the selection.
+    pub fn set(&mut self, ids: &[RenderableId]) {
+        self.ids.clear();
+        for &id in ids {
+            self.add(id);
+        }
+    }
+
+    pub fn add(&mut self, id: RenderableId) {
+        if !self.contains(id) {
+            self.ids.push(id);
+        }
+    }
+
+    pub fn remove(&mut self, id: RenderableId) {
+        self.ids.retain(|&selected| selected != id);
+    }
+
+    pub fn toggle(&mut self, id: RenderableId) {
+        if self.contains(id) {
+            self.remove(id);
+        } else {
+            self.add(id);
+        }
+    }
+
+    // Applies what a click or a box found, see above. Returns true if the selection changed.
+    pub fn apply(&mut self, request: &SelectionRequest, found: &[RenderableId]) -> bool {
+        let before = self.ids.clone();
+        match (request.area, request.additive) {
+            (SelectionArea::Point { .. }, true) => {
+                if let Some(&id) = found.first() {
+                    self.toggle(id);
+                }
+            }
+            (SelectionArea::Box { .. }, true) => found.iter().for_each(|&id| self.add(id)),
+            (_, false) => self.set(found),
+        }
+        self.ids != before
+    }
+}
+
+/// Where the user selected, in pixels of the surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionArea {
+    Point { x: f32, y: f32 },
+    Box { min: (f32, f32), max: (f32, f32) },
+}
+
+/// A finished click or box, see `SelectionTool`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectionRequest {
+    pub area: SelectionArea,
+    pub additive: bool, // Ctrl was held: add to the selection rather than replace it.
+}
+
+/// Turns the left mouse button and Ctrl into selection requests, see above.
+#[derive(Clone, Debug, Default)]
+pub struct SelectionTool {
+    cursor: (f32, f32),
+    control: bool,
+    pressed_at: Option<(f32, f32)>,
+    request: Option<SelectionRequest>,
+}
+
+impl SelectionTool {
+    pub fn new() -> SelectionTool {
+        SelectionTool::default()
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        match *event {
+            Event::MouseMoved { x, y } => self.cursor = (x as f32, y as f32),
+            Event::KeyPressed(VK_CONTROL) => self.control = true,
+            Event::KeyReleased(VK_CONTROL) => self.control = false,
+            Event::MousePressed(MouseButton::Left) => self.pressed_at = Some(self.cursor),
+            Event::MouseReleased(MouseButton::Left) => {
+                if let Some(start) = self.pressed_at.take() {
+                    let area = match self.drag_box_from(start) {
+                        Some((min, max)) => SelectionArea::Box { min, max },
+                        None => SelectionArea::Point {
+                            x: self.cursor.0,
+                            y: self.cursor.1,
+                        },
+                    };
+                    self.request = Some(SelectionRequest {
+                        area,
+                        additive: self.control,
+                    });
+                }
+            }
+            Event::FocusLost => {
+                self.control = false;
+                self.pressed_at = None;
+            }
+            _ => {}
+        }
+    }
+
+    // Forgets the press in progress, e.g. when it grabbed a gizmo instead.
+    pub fn cancel(&mut self) {
+        self.pressed_at = None;
+    }
+
+    // The box being dragged, as (min, max) corners, once the cursor moved far enough.
+    pub fn drag_box(&self) -> Option<((f32, f32), (f32, f32))> {
+        self.drag_box_from(self.pressed_at?)
+    }
+
+    // The click or box finished since the last call.
+    pub fn take_request(&mut self) -> Option<SelectionRequest> {
+        self.request.take()
+    }
+
+    fn drag_box_from(&self, start: (f32, f32)) -> Option<((f32, f32), (f32, f32))> {
+        let (x, y) = self.cursor;
+        if (x - start.0).abs() < DRAG_PIXELS && (y - start.1).abs() < DRAG_PIXELS {
+            return None;
+        }
+        Some(((start.0.min(x), start.1.min(y)), (start.0.max(x), start.1.max(y))))
+    }
+}
+
+/// How the selection is outlined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutlineSettings {
+    pub color: [f32; 4],
+    pub width: f32, // In pixels.
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        OutlineSettings {
+            color: [1.0, 0.6, 0.1, 1.0],
+            width: 3.0,
+        }
+    }
+}
+
+uniform_struct! {
+    struct OutlineParams {
+        color: Vector4<f32>,
+        viewport: Vector2<f32>,
+        width: f32,
+    }
+}
+assert_uniform_size!(OutlineParams, 32);
+
+// The two pipelines of a vertex layout, see above.
+struct OutlinePipelines {
+    mask: wgpu::RenderPipeline,
+    outline: wgpu::RenderPipeline,
+}
+
+/// Draws the outline around the selected renderables, see above.
+pub struct SelectionOutline {
+    pipeline_layout: wgpu::PipelineLayout,
+    params_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+    params_stride: u32,
+    params_capacity: usize, // In cameras.
+    pipelines: HashMap<VertexLayout, OutlinePipelines>,
+    format: wgpu::TextureFormat,
+    samples: u32,
+    // The stencil buffer, at the size of the scene's render targets.
+    stencil: Option<(wgpu::TextureView, (u32, u32))>,
+}
+
+impl SelectionOutline {
+    // `camera_layout` is that of the camera bind groups, which the outlines are drawn with.
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, samples: u32) -> SelectionOutline {
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Outline Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    // Every camera has its own part of the buffer.
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(OutlineParams::SIZE as u64),
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let params_stride = device.limits().min_uniform_buffer_offset_alignment.max(OutlineParams::SIZE as u32);
+        let params_capacity = 4;
+        let params_buffer = create_params_buffer(device, params_stride, params_capacity);
+        let params_bind_group = create_params_bind_group(device, &params_layout, &params_buffer);
+        SelectionOutline {
+            pipeline_layout,
+            params_layout,
+            params_buffer,
+            params_bind_group,
+            params_stride,
+            params_capacity,
+            pipelines: HashMap::new(),
+            format,
+            samples,
+            stencil: None,
+        }
+    }
+
+    // Recreates the pipelines and the stencil buffer for a new sample count.
+    pub fn set_samples(&mut self, samples: u32) {
+        self.samples = samples;
+        self.pipelines.clear();
+        self.stencil = None;
+    }
+
+    // Uploads the outline's parameters for every camera, by the size of its viewport, and creates
+    // what the meshes of `layouts` and scene targets of `size` need.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: OutlineSettings,
+        viewports: &[(u32, u32)],
+        layouts: &[VertexLayout],
+        size: (u32, u32),
+    ) {
+        if viewports.len() > self.params_capacity {
+            self.params_capacity = viewports.len().next_power_of_two();
+            self.params_buffer = create_params_buffer(device, self.params_stride, self.params_capacity);
+            self.params_bind_group = create_params_bind_group(device, &self.params_layout, &self.params_buffer);
+        }
+        for (index, &(width, height)) in viewports.iter().enumerate() {
+            let params = OutlineParams {
+                color: settings.color.into(),
+                viewport: Vector2::new(width.max(1) as f32, height.max(1) as f32),
+                width: settings.width,
+            };
+            let offset = index as u64 * self.params_stride as u64;
+            queue.write_buffer(&self.params_buffer, offset, &params.to_uniform_bytes());
+        }
+        for &layout in layouts {
+            if !self.pipelines.contains_key(&layout) {
+                let pipelines = self.create_pipelines(device, layout);
+                self.pipelines.insert(layout, pipelines);
+            }
+        }
+        if self.stencil.as_ref().is_none_or(|(_, stencil_size)| *stencil_size != size) {
+            self.stencil = Some((create_stencil_view(device, size, self.samples), size));
+        }
+    }
+
+    // Outlines `draws` (instance and mesh of the selected renderables) as seen by camera `index`,
+    // whose bind group is `camera`, over its part of the scene.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        index: usize,
+        viewport: (u32, u32, u32, u32),
+        scissor: (u32, u32, u32, u32),
+        camera: &wgpu::BindGroup,
+        instances: &wgpu::Buffer,
+        draws: &[(u32, &Mesh)],
+    ) {
+        let stencil = match &self.stencil {
+            Some((stencil, _)) if !draws.is_empty() && index < self.params_capacity => stencil,
+            _ => return,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Selection Outline Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            // Only the stencil is used, the depth is in the format but never tested.
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: stencil,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: false,
+                }),
+            }),
+        });
+        let (x, y, w, h) = viewport;
+        let (sx, sy, sw, sh) = scissor;
+        render_pass.set_viewport(x as f32, y as f32, w as f32, h as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(sx, sy, sw, sh);
+        render_pass.set_stencil_reference(STENCIL_MARK);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, &self.params_bind_group, &[index as u32 * self.params_stride]);
+        render_pass.set_vertex_buffer(1, instances.slice(..));
+        // All marks first, so the outline of one renderable doesn't cover another.
+        for pass in 0..2 {
+            for &(instance, mesh) in draws {
+                let pipelines = match self.pipelines.get(&mesh.layout) {
+                    Some(pipelines) => pipelines,
+                    None => continue,
+                };
+                render_pass.set_pipeline(if pass == 0 { &pipelines.mask } else { &pipelines.outline });
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                render_pass.draw_indexed(0..mesh.num_indices, 0, instance..instance + 1);
+            }
+        }
+    }
+
+    // Meshes with normals push the outline along them, see selection.wgsl.
+    fn create_pipelines(&self, device: &wgpu::Device, layout: VertexLayout) -> OutlinePipelines {
+        let mut desc = layout.desc();
+        desc.attributes
+            .retain(|attribute| attribute.shader_location == POSITION_LOCATION || attribute.shader_location == NORMAL_LOCATION);
+        let normal = desc.attributes.iter().find(|attribute| attribute.shader_location == NORMAL_LOCATION);
+        let defines = ShaderDefines::new()
+            .with_flag("VERTEX_NORMAL", normal.is_some())
+            .with_flag("PACKED_VERTICES", normal.is_some_and(|normal| normal.format == packing::NORMAL_FORMAT));
+        let wgsl = preprocess(include_str!("selection.wgsl"), &defines).expect("built-in shader is valid");
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let create = |label: &str, vertex: &str, fragment: &str, write_mask: wgpu::ColorWrites, stencil: wgpu::StencilFaceState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: vertex,
+                    buffers: &[desc.layout(), ModelInstance::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fragment,
+                    targets: &[wgpu::ColorTargetState {
+                        format: self.format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask,
+                    }],
+                }),
+                // Pushed outwards, triangles may turn around.
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: STENCIL_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState {
+                        front: stencil,
+                        back: stencil,
+                        read_mask: 0xff,
+                        write_mask: 0xff,
+                    },
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: self.samples,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+        };
+        let mark = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Replace,
+        };
+        let unmarked = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::NotEqual,
+            pass_op: wgpu::StencilOperation::Keep,
+            ..mark
+        };
+        OutlinePipelines {
+            mask: create("Outline Mask Pipeline", "vs_mask", "fs_mask", wgpu::ColorWrites::empty(), mark),
+            outline: create("Outline Pipeline", "vs_outline", "fs_outline", wgpu::ColorWrites::ALL, unmarked),
+        }
+    }
+}
+
+fn create_params_buffer(device: &wgpu::Device, stride: u32, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Outline Params"),
+        size: stride as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_params_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Outline Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(OutlineParams::SIZE as u64),
+            }),
+        }],
+    })
+}
+
+fn create_stencil_view(device: &wgpu::Device, (width, height): (u32, u32), samples: u32) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Outline Stencil"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: samples,
+        dimension: wgpu::TextureDimension::D2,
+        format: STENCIL_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool_after(events: &[Event]) -> SelectionTool {
+        let mut tool = SelectionTool::new();
+        events.iter().for_each(|event| tool.on_event(event));
+        tool
+    }
+
+    #[test]
+    fn a_short_drag_is_a_click() {
+        let mut tool = tool_after(&[
+            Event::MouseMoved { x: 100, y: 50 },
+            Event::MousePressed(MouseButton::Left),
+            Event::MouseMoved { x: 102, y: 47 },
+            Event::MouseReleased(MouseButton::Left),
+        ]);
+        let request = tool.take_request().expect("released");
+        assert_eq!(request.area, SelectionArea::Point { x: 102.0, y: 47.0 });
+        assert!(!request.additive);
+        assert_eq!(tool.take_request(), None);
+    }
+
+    #[test]
+    fn a_drag_is_a_box_between_its_corners() {
+        let mut tool = tool_after(&[
+            Event::MouseMoved { x: 100, y: 50 },
+            Event::KeyPressed(VK_CONTROL),
+            Event::MousePressed(MouseButton::Left),
+            Event::MouseMoved { x: 40, y: 80 },
+        ]);
+        assert_eq!(tool.drag_box(), Some(((40.0, 50.0), (100.0, 80.0))));
+        tool.on_event(&Event::MouseReleased(MouseButton::Left));
+        let request = tool.take_request().expect("released");
+        assert_eq!(request.area, SelectionArea::Box { min: (40.0, 50.0), max: (100.0, 80.0) });
+        assert!(request.additive);
+        assert_eq!(tool.drag_box(), None);
+    }
+
+    #[test]
+    fn a_cancelled_press_selects_nothing() {
+        let mut tool = tool_after(&[Event::MousePressed(MouseButton::Left), Event::MouseMoved { x: 50, y: 50 }]);
+        tool.cancel();
+        tool.on_event(&Event::MouseReleased(MouseButton::Left));
+        assert_eq!(tool.take_request(), None);
+        // Losing the focus also lets go of Ctrl.
+        let tool = tool_after(&[Event::KeyPressed(VK_CONTROL), Event::FocusLost]);
+        assert!(!tool.control);
+    }
+}
@@ -0,0 +1,93 @@
+// Outlines around the selected renderables, see selection.rs. `vs_mask` marks their pixels in the
+// stencil buffer, `vs_outline` draws them again a few pixels larger where the stencil isn't marked.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct OutlineParams {
+    color: vec4<f32>;
+    viewport: vec2<f32>; // In pixels.
+    width: f32; // In pixels.
+};
+[[group(1), binding(0)]]
+var<uniform> params: OutlineParams;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+#ifdef VERTEX_NORMAL
+#ifdef PACKED_VERTICES
+    [[location(4)]] normal: u32; // 10-10-10-2, see packing.rs.
+#else
+    [[location(4)]] normal: vec3<f32>;
+#endif
+#endif
+};
+
+struct InstanceInput {
+    [[location(5)]] model_0: vec4<f32>;
+    [[location(6)]] model_1: vec4<f32>;
+    [[location(7)]] model_2: vec4<f32>;
+    [[location(8)]] model_3: vec4<f32>;
+};
+
+#ifdef PACKED_VERTICES
+// Expands a normal or tangent packed by `pack_normal` or `pack_tangent` in packing.rs: xyz is the
+// vector, w the handedness (-1 or 1, 0 for normals).
+fn unpack_snorm10x3_2(packed: u32) -> vec4<f32> {
+    // Moving each field to the top of an i32 and back extends its sign.
+    let x = bitcast<i32>(packed << 22u) >> 22u;
+    let y = bitcast<i32>(packed << 12u) >> 22u;
+    let z = bitcast<i32>(packed << 2u) >> 22u;
+    let w = bitcast<i32>(packed) >> 30u;
+    let v = vec4<f32>(f32(x) / 511.0, f32(y) / 511.0, f32(z) / 511.0, f32(w));
+    return max(v, vec4<f32>(-1.0));
+}
+#endif
+
+[[stage(vertex)]]
+fn vs_mask(model: VertexInput, instance: InstanceInput) -> [[builtin(position)]] vec4<f32> {
+    let model_matrix = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    return camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+[[stage(vertex)]]
+fn vs_outline(model: VertexInput, instance: InstanceInput) -> [[builtin(position)]] vec4<f32> {
+    let model_matrix = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    var position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    // The direction to push the vertex in, on screen: along its normal if the mesh has normals,
+    // away from the center of the renderable otherwise, which is only right for convex meshes.
+#ifdef VERTEX_NORMAL
+#ifdef PACKED_VERTICES
+    let model_normal = unpack_snorm10x3_2(model.normal).xyz;
+#else
+    let model_normal = model.normal;
+#endif
+    let normal = camera.view_proj * model_matrix * vec4<f32>(model_normal, 0.0);
+    var direction = normal.xy * params.viewport;
+#else
+    let center = camera.view_proj * model_matrix * vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    var direction = vec2<f32>(0.0, 0.0);
+    if (center.w > 0.0 && position.w > 0.0) {
+        direction = (position.xy / position.w - center.xy / center.w) * params.viewport;
+    }
+#endif
+    if (length(direction) > 0.0) {
+        // Normalized device coordinates span 2 across the viewport, and are multiplied by w.
+        position = position + vec4<f32>(normalize(direction) * params.width * 2.0 / params.viewport * position.w, 0.0, 0.0);
+    }
+    return position;
+}
+
+[[stage(fragment)]]
+fn fs_mask() -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
+
+[[stage(fragment)]]
+fn fs_outline() -> [[location(0)]] vec4<f32> {
+    return params.color;
+}
@@ -0,0 +1,643 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use std::time::Instant;
+
+use wgpu::util::DeviceExt;
+
+use crate::debug_draw::DebugViews;
+use crate::game::{Event, MouseButton, ScrollDelta};
+use crate::gfx::GFX;
+use crate::mouse::PIXELS_PER_LINE;
+use crate::platform::Platform;
+use crate::render_graph::{NodeContext, NodeParameter, RenderNode};
+
+// Settings panel
+//======================
+// With the `settings_ui` feature, the `toggle_settings` key (F2 by default) opens a panel to
+// change the graphics settings while the game runs: window size and fullscreen, the present mode,
+// MSAA, which render graph nodes (post effects) run and their parameters, and the debug views.
+// Changes go through the same calls a game would use, `Platform::set_window_mode`,
+// `GFX::set_present_mode`, `GFX::set_msaa_samples`, `RenderGraph::set_enabled`,
+// `RenderGraph::set_parameter` and `GFX::set_debug_views`, and take effect from the next frame on.
+//
+// The panel is drawn with egui. egui only produces triangles; `UiNode` draws them on top of the
+// frame as the last render graph node. The window events reach the game as well.
+
+const NODE_NAME: &str = "Settings UI";
+
+// What `SettingsPanel::run` produced for `UiNode` to draw this frame.
+#[derive(Default)]
+struct UiFrame {
+    meshes: Vec<egui::ClippedMesh>,
+    textures: egui::TexturesDelta,
+}
+
+/// The settings panel, see above.
+pub struct SettingsPanel {
+    ctx: egui::Context,
+    frame: Rc<RefCell<UiFrame>>,
+    open: bool,
+    attached: bool,
+    start: Instant,
+    pointer: egui::Pos2,
+    events: Vec<egui::Event>,
+    // The window size being edited, applied with the button.
+    width: i32,
+    height: i32,
+}
+
+impl SettingsPanel {
+    pub fn new() -> SettingsPanel {
+        SettingsPanel {
+            ctx: egui::Context::default(),
+            frame: Rc::new(RefCell::new(UiFrame::default())),
+            open: false,
+            attached: false,
+            start: Instant::now(),
+            pointer: egui::Pos2::ZERO,
+            events: Vec::new(),
+            width: 0,
+            height: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    // Passes a window event on to egui.
+    pub fn on_event(&mut self, event: &Event) {
+        if !self.open {
+            return;
+        }
+        let modifiers = egui::Modifiers::default();
+        match *event {
+            Event::MouseMoved { x, y } => {
+                self.pointer = egui::pos2(x as f32, y as f32);
+                self.events.push(egui::Event::PointerMoved(self.pointer));
+            }
+            Event::MousePressed(button) | Event::MouseReleased(button) => {
+                self.events.push(egui::Event::PointerButton {
+                    pos: self.pointer,
+                    button: match button {
+                        MouseButton::Left => egui::PointerButton::Primary,
+                        MouseButton::Right => egui::PointerButton::Secondary,
+                    },
+                    pressed: matches!(event, Event::MousePressed(_)),
+                    modifiers,
+                });
+            }
+            Event::KeyPressed(code) | Event::KeyReleased(code) => {
+                if let Some(key) = egui_key(code) {
+                    self.events.push(egui::Event::Key {
+                        key,
+                        pressed: matches!(event, Event::KeyPressed(_)),
+                        modifiers,
+                    });
+                }
+            }
+            Event::Char(unit) => {
+                // Control characters arrive as keys; surrogate pairs are not supported.
+                if let Some(Ok(c)) = char::decode_utf16([unit]).next() {
+                    if !c.is_control() {
+                        self.events.push(egui::Event::Text(c.to_string()));
+                    }
+                }
+            }
+            Event::FocusLost => self.events.push(egui::Event::PointerGone),
+            Event::MouseScrolled(delta) => {
+                let (x, y) = match delta {
+                    ScrollDelta::Lines { x, y } => (x * PIXELS_PER_LINE, y * PIXELS_PER_LINE),
+                    ScrollDelta::Pixels { x, y } => (x, y),
+                };
+                self.events.push(egui::Event::Scroll(egui::vec2(x, y)));
+            }
+            Event::MouseRawMoved { .. } | Event::OcclusionChanged { .. } | Event::PowerChanged { .. } => {}
+        }
+    }
+
+    // Lays out the panel and applies what was changed. Call once per frame, before rendering.
+    pub fn run(&mut self, window: &mut impl Platform) {
+        let (width, height) = window.size();
+        let fullscreen = window.is_fullscreen();
+        let gfx = window.gfx_mut().expect("window is initialized");
+        if !self.attached {
+            gfx.add_render_node(Box::new(UiNode::new(self.frame.clone())));
+            self.attached = true;
+            self.width = width;
+            self.height = height;
+        }
+        if !self.open {
+            self.events.clear();
+            self.frame.borrow_mut().meshes.clear();
+            return;
+        }
+
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            pixels_per_point: Some(1.0),
+            max_texture_side: Some(gfx.capabilities().max_texture_size as usize),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+
+        let mut settings = Settings::read(gfx, fullscreen);
+        let before = settings.clone();
+        let mut apply_size = false;
+        let mut open = self.open;
+        let (edit_width, edit_height) = (&mut self.width, &mut self.height);
+        let output = self.ctx.run(input, |ctx| {
+            egui::Window::new("Graphics settings").open(&mut open).show(ctx, |ui| {
+                ui.heading("Display");
+                ui.checkbox(&mut settings.fullscreen, "Fullscreen");
+                ui.add_enabled_ui(!settings.fullscreen, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(edit_width).clamp_range(320..=7680));
+                        ui.label("x");
+                        ui.add(egui::DragValue::new(edit_height).clamp_range(200..=4320));
+                        apply_size = ui.button("Apply").clicked();
+                    });
+                });
+                egui::ComboBox::from_label("Present mode")
+                    .selected_text(present_mode_name(settings.present_mode))
+                    .show_ui(ui, |ui| {
+                        for mode in [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox, wgpu::PresentMode::Immediate] {
+                            ui.selectable_value(&mut settings.present_mode, mode, present_mode_name(mode));
+                        }
+                    });
+
+                ui.separator();
+                ui.heading("Rendering");
+                ui.horizontal(|ui| {
+                    ui.label("MSAA");
+                    ui.radio_value(&mut settings.msaa_samples, 1, "Off");
+                    ui.radio_value(&mut settings.msaa_samples, 4, "4x");
+                });
+
+                ui.separator();
+                ui.heading("Post effects");
+                if settings.nodes.is_empty() {
+                    ui.label("None");
+                }
+                for (name, enabled) in &mut settings.nodes {
+                    ui.checkbox(enabled, name.as_str());
+                }
+                for (name, parameters) in &mut settings.parameters {
+                    ui.collapsing(name.as_str(), |ui| {
+                        for parameter in parameters {
+                            ui.add(egui::Slider::new(&mut parameter.value, parameter.range.clone()).text(parameter.name));
+                        }
+                    });
+                }
+
+                ui.separator();
+                ui.heading("Debug views");
+                let views = &mut settings.debug_views;
+                ui.checkbox(&mut views.camera_frusta, "Camera frusta");
+                ui.checkbox(&mut views.light_frusta, "Light frusta");
+                ui.checkbox(&mut views.aabbs, "Bounding boxes");
+                ui.checkbox(&mut views.bounding_spheres, "Bounding spheres");
+            });
+        });
+        self.open = open;
+
+        settings.apply(&before, gfx);
+        if settings.fullscreen != before.fullscreen || apply_size {
+            window.set_window_mode(self.width, self.height, settings.fullscreen);
+        }
+
+        let meshes = self.ctx.tessellate(output.shapes);
+        let mut frame = self.frame.borrow_mut();
+        frame.meshes = meshes;
+        frame.textures.append(output.textures_delta);
+    }
+}
+
+impl Default for SettingsPanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The settings shown in the panel.
+#[derive(Clone, PartialEq)]
+struct Settings {
+    fullscreen: bool,
+    present_mode: wgpu::PresentMode,
+    msaa_samples: u32,
+    nodes: Vec<(String, bool)>,
+    parameters: Vec<(String, Vec<NodeParameter>)>, // Of the nodes that have any.
+    debug_views: DebugViews,
+}
+
+impl Settings {
+    fn read(gfx: &mut GFX, fullscreen: bool) -> Settings {
+        let graph = gfx.render_graph_mut();
+        let nodes = graph
+            .node_names()
+            .into_iter()
+            .filter(|&name| name != NODE_NAME)
+            .map(|name| (name.to_string(), graph.is_enabled(name)))
+            .collect();
+        let parameters = graph
+            .node_names()
+            .into_iter()
+            .map(|name| (name.to_string(), graph.parameters(name)))
+            .filter(|(_, parameters)| !parameters.is_empty())
+            .collect();
+        Settings {
+            fullscreen,
+            present_mode: gfx.present_mode(),
+            msaa_samples: gfx.msaa_samples(),
+            nodes,
+            parameters,
+            debug_views: gfx.debug_views(),
+        }
+    }
+
+    // Applies what changed since `before`, except the window mode.
+    fn apply(&self, before: &Settings, gfx: &mut GFX) {
+        if self.present_mode != before.present_mode {
+            gfx.set_present_mode(self.present_mode);
+        }
+        if self.msaa_samples != before.msaa_samples {
+            gfx.set_msaa_samples(self.msaa_samples);
+        }
+        if self.debug_views != before.debug_views {
+            gfx.set_debug_views(self.debug_views);
+        }
+        for ((name, enabled), (_, was_enabled)) in self.nodes.iter().zip(&before.nodes) {
+            if enabled != was_enabled {
+                gfx.render_graph_mut().set_enabled(name, *enabled);
+            }
+        }
+        for ((node, parameters), (_, before)) in self.parameters.iter().zip(&before.parameters) {
+            for (parameter, was) in parameters.iter().zip(before) {
+                if parameter.value != was.value {
+                    gfx.render_graph_mut().set_parameter(node, parameter.name, parameter.value);
+                }
+            }
+        }
+    }
+}
+
+fn present_mode_name(mode: wgpu::PresentMode) -> &'static str {
+    match mode {
+        wgpu::PresentMode::Fifo => "Vsync (Fifo)",
+        wgpu::PresentMode::Mailbox => "Mailbox",
+        wgpu::PresentMode::Immediate => "Immediate",
+    }
+}
+
+// The egui key of a virtual-key code, for the keys that edit text and move the focus.
+fn egui_key(code: u16) -> Option<egui::Key> {
+    let key = match code {
+        0x08 => egui::Key::Backspace,
+        0x09 => egui::Key::Tab,
+        0x0D => egui::Key::Enter,
+        0x1B => egui::Key::Escape,
+        0x20 => egui::Key::Space,
+        0x23 => egui::Key::End,
+        0x24 => egui::Key::Home,
+        0x25 => egui::Key::ArrowLeft,
+        0x26 => egui::Key::ArrowUp,
+        0x27 => egui::Key::ArrowRight,
+        0x28 => egui::Key::ArrowDown,
+        0x2E => egui::Key::Delete,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// Drawing
+//======================
+
+// A texture egui asked for (the font atlas, mostly), bound in group 1.
+struct UiTexture {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+// The render graph node that draws the panel, see above.
+struct UiNode {
+    frame: Rc<RefCell<UiFrame>>,
+    // Created on first use, for the format of the frame.
+    pipeline: Option<(wgpu::TextureFormat, UiPipeline)>,
+    textures: HashMap<egui::TextureId, UiTexture>,
+    vertex_buffer: Option<(wgpu::Buffer, u64)>, // With their size.
+    index_buffer: Option<(wgpu::Buffer, u64)>,
+}
+
+struct UiPipeline {
+    pipeline: wgpu::RenderPipeline,
+    screen_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
+    texture_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl UiNode {
+    fn new(frame: Rc<RefCell<UiFrame>>) -> UiNode {
+        UiNode {
+            frame,
+            pipeline: None,
+            textures: HashMap::new(),
+            vertex_buffer: None,
+            index_buffer: None,
+        }
+    }
+
+    // Creates, replaces or patches the textures egui changed.
+    fn update_textures(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, pipeline: &UiPipeline, format: wgpu::TextureFormat, delta: &egui::TexturesDelta) {
+        // Sampled as linear colors when the target is sRGB, see egui.wgsl.
+        let texture_format = if format.describe().srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        for (id, image_delta) in &delta.set {
+            let (size, pixels): ([usize; 2], Vec<u8>) = match &image_delta.image {
+                egui::ImageData::Color(image) => (image.size, image.pixels.iter().flat_map(|c| c.to_array()).collect()),
+                egui::ImageData::Alpha(image) => (image.size, image.srgba_pixels(1.0).flat_map(|c| c.to_array()).collect()),
+            };
+            let extent = wgpu::Extent3d {
+                width: size[0] as u32,
+                height: size[1] as u32,
+                depth_or_array_layers: 1,
+            };
+            let origin = match image_delta.pos {
+                Some([x, y]) => wgpu::Origin3d { x: x as u32, y: y as u32, z: 0 },
+                None => {
+                    let texture = device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some("egui Texture"),
+                        size: extent,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: texture_format,
+                        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    });
+                    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("egui Texture Bind Group"),
+                        layout: &pipeline.texture_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(&view),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(&pipeline.sampler),
+                            },
+                        ],
+                    });
+                    self.textures.insert(*id, UiTexture { texture, bind_group });
+                    wgpu::Origin3d::ZERO
+                }
+            };
+            let texture = match self.textures.get(id) {
+                Some(texture) => &texture.texture,
+                None => continue, // A patch of a texture we never got.
+            };
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(4 * extent.width),
+                    rows_per_image: NonZeroU32::new(extent.height),
+                },
+                extent,
+            );
+        }
+    }
+}
+
+impl RenderNode for UiNode {
+    fn name(&self) -> &str {
+        NODE_NAME
+    }
+
+    fn run(&mut self, ctx: &mut NodeContext, encoder: &mut wgpu::CommandEncoder) {
+        let target = match ctx.frame.view {
+            Some(view) => view,
+            None => return,
+        };
+        let format = ctx.frame.format;
+        let (width, height) = ctx.frame.size;
+        if !matches!(&self.pipeline, Some((current, _)) if *current == format) {
+            self.pipeline = Some((format, UiPipeline::new(ctx.device, format)));
+            // The textures were made for the old format; egui sends them again after a restart only.
+            self.textures.clear();
+        }
+        let (_, pipeline) = self.pipeline.take().unwrap();
+
+        let frame_cell = self.frame.clone();
+        let mut frame = frame_cell.borrow_mut();
+        let delta = std::mem::take(&mut frame.textures);
+        self.update_textures(ctx.device, ctx.queue, &pipeline, format, &delta);
+
+        let screen = [width as f32, height as f32, if format.describe().srgb { 1.0 } else { 0.0 }, 0.0];
+        ctx.queue.write_buffer(&pipeline.screen_buffer, 0, bytemuck::cast_slice(&screen));
+
+        // All meshes go into one vertex and one index buffer.
+        let mut vertices: Vec<egui::epaint::Vertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let mut draws = Vec::new();
+        for egui::ClippedMesh(clip, mesh) in &frame.meshes {
+            let first_index = indices.len() as u32;
+            let base_vertex = vertices.len() as i32;
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+            draws.push((*clip, mesh.texture_id, first_index..indices.len() as u32, base_vertex));
+        }
+        if !draws.is_empty() {
+            let vertex_buffer = upload(ctx.device, ctx.queue, &mut self.vertex_buffer, "egui Vertex Buffer", bytemuck::cast_slice(&vertices), wgpu::BufferUsages::VERTEX);
+            let index_buffer = upload(ctx.device, ctx.queue, &mut self.index_buffer, "egui Index Buffer", bytemuck::cast_slice(&indices), wgpu::BufferUsages::INDEX);
+
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Settings UI Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    // Draw on top of the frame.
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&pipeline.pipeline);
+            pass.set_bind_group(0, &pipeline.screen_bind_group, &[]);
+            pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            for (clip, texture_id, range, base_vertex) in draws {
+                let texture = match self.textures.get(&texture_id) {
+                    Some(texture) => texture,
+                    None => continue,
+                };
+                // Clip rects are in points, which are pixels here; scissor rects must not be empty.
+                let x = (clip.min.x.max(0.0) as u32).min(width);
+                let y = (clip.min.y.max(0.0) as u32).min(height);
+                let w = (clip.max.x.max(0.0) as u32).min(width).saturating_sub(x);
+                let h = (clip.max.y.max(0.0) as u32).min(height).saturating_sub(y);
+                if w == 0 || h == 0 {
+                    continue;
+                }
+                pass.set_scissor_rect(x, y, w, h);
+                pass.set_bind_group(1, &texture.bind_group, &[]);
+                pass.draw_indexed(range, base_vertex, 0..1);
+            }
+        }
+
+        for id in &delta.free {
+            self.textures.remove(id);
+        }
+        self.pipeline = Some((format, pipeline));
+    }
+}
+
+impl UiPipeline {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> UiPipeline {
+        let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("egui Screen Buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let screen_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("egui Screen Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("egui Screen Bind Group"),
+            layout: &screen_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_buffer.as_entire_binding(),
+            }],
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("egui Texture Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("egui Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("egui Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("egui.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("egui Pipeline Layout"),
+            bind_group_layouts: &[&screen_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("egui Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<egui::epaint::Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Unorm8x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    // egui's colors are premultiplied.
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(), // egui's winding order varies, so no culling.
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        UiPipeline {
+            pipeline,
+            screen_buffer,
+            screen_bind_group,
+            texture_layout,
+            sampler,
+        }
+    }
+}
+
+// Writes `data` into `buffer`, growing it to the next power of two if it is too small.
+fn upload<'a>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &'a mut Option<(wgpu::Buffer, u64)>,
+    label: &str,
+    data: &[u8],
+    usage: wgpu::BufferUsages,
+) -> &'a wgpu::Buffer {
+    let size = data.len() as u64;
+    if buffer.as_ref().is_none_or(|(_, current)| *current < size) {
+        let size = size.next_power_of_two().max(1024);
+        let created = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        *buffer = Some((created, size));
+    }
+    let (buffer, _) = buffer.as_ref().unwrap();
+    queue.write_buffer(buffer, 0, data);
+    buffer
+}
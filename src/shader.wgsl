@@ -1,28 +1,382 @@
 // Vertex shader
 
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+// See time.rs.
+struct Globals {
+    time: f32;
+    delta_time: f32;
+    frame: u32;
+    resolution: vec2<f32>;
+    light_direction: vec4<f32>;
+    light_color: vec4<f32>;
+    ambient: vec4<f32>;
+};
+[[group(0), binding(1)]]
+var<uniform> globals: Globals;
+
+#ifdef LOCAL_LIGHTS
+// See local_lights.rs and shadow_atlas.rs.
+struct LocalLight {
+    position: vec4<f32>; // w is the range.
+    color: vec4<f32>; // w is the index of the first shadow map, -1 without shadows.
+    direction: vec4<f32>; // Of spot lights.
+    // The cosines of the outer and inner angle; z is 1 for point lights; w is the cookie, -1
+    // without one.
+    cone: vec4<f32>;
+    projection: mat4x4<f32>; // The view projection of spot lights, for the cookie.
+};
+struct ShadowView {
+    view_proj: mat4x4<f32>;
+    rect: vec4<f32>; // In the atlas: the corner in xy, the size in zw.
+};
+struct LocalLights {
+    count: u32;
+    lights: array<LocalLight, 16>;
+    views: array<ShadowView, 32>;
+};
+[[group(0), binding(2)]]
+var<uniform> local_lights: LocalLights;
+[[group(0), binding(3)]]
+var shadow_atlas: texture_depth_2d;
+[[group(0), binding(4)]]
+var shadow_sampler: sampler_comparison;
+// See light_cookies.rs.
+[[group(0), binding(5)]]
+var cookies: texture_2d_array<f32>;
+[[group(0), binding(6)]]
+var cookie_sampler: sampler;
+#endif
+
+struct MaterialParams {
+    color: vec4<f32>;
+#ifdef EMISSIVE
+    emissive: vec4<f32>; // Light of its own, added after lighting: rgb times the intensity in a.
+#endif
+#ifdef REFLECTION_PROBES
+    reflectivity: f32;
+#else
+#ifdef PLANAR_MIRROR
+    reflectivity: f32;
+#endif
+#endif
+};
+[[group(1), binding(0)]]
+var<uniform> material: MaterialParams;
+
+#ifdef PLANAR_MIRROR
+// What the mirror of the surface sees, mirrored along x, see mirrors.rs.
+[[group(1), binding(1)]]
+var mirror_texture: texture_2d<f32>;
+[[group(1), binding(2)]]
+var mirror_sampler: sampler;
+#endif
+
+#ifdef EMISSIVE_TEXTURE
+// Multiplies the emissive color, by the mesh's uvs.
+[[group(1), binding(3)]]
+var emissive_texture: texture_2d<f32>;
+[[group(1), binding(4)]]
+var emissive_sampler: sampler;
+#endif
+
+#ifdef REFLECTION_PROBES
+// See reflection_probes.rs.
+struct Probe {
+    position: vec4<f32>; // w is the blend distance.
+    half_extents: vec4<f32>;
+};
+struct Probes {
+    count: u32;
+    probes: array<Probe, 4>;
+};
+[[group(2), binding(0)]]
+var<uniform> probes: Probes;
+[[group(2), binding(1)]]
+var probe_sampler: sampler;
+[[group(2), binding(2)]]
+var probe_0: texture_cube<f32>;
+[[group(2), binding(3)]]
+var probe_1: texture_cube<f32>;
+[[group(2), binding(4)]]
+var probe_2: texture_cube<f32>;
+[[group(2), binding(5)]]
+var probe_3: texture_cube<f32>;
+#endif
+
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
+#ifdef VERTEX_COLOR
     [[location(1)]] color: vec3<f32>;
+#endif
+#ifdef VERTEX_UV
+    [[location(2)]] uv: vec2<f32>;
+#endif
+#ifdef VERTEX_NORMAL
+#ifdef PACKED_VERTICES
+    [[location(4)]] normal: u32; // 10-10-10-2, see packing.rs.
+#else
+    [[location(4)]] normal: vec3<f32>;
+#endif
+#endif
+};
+
+// The model matrix of the renderable, see transform.rs.
+struct InstanceInput {
+    [[location(5)]] model_0: vec4<f32>;
+    [[location(6)]] model_1: vec4<f32>;
+    [[location(7)]] model_2: vec4<f32>;
+    [[location(8)]] model_3: vec4<f32>;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] color: vec3<f32>;
+#ifdef REFLECTION_PROBES
+    [[location(1)]] world_position: vec3<f32>;
+    [[location(2)]] normal: vec3<f32>;
+#endif
+#ifdef PLANAR_MIRROR
+    [[location(3)]] mirror_position: vec4<f32>; // The clip position, interpolated without the divide.
+#endif
+#ifdef LOCAL_LIGHTS
+    [[location(4)]] light_position: vec3<f32>;
+    [[location(5)]] light_normal: vec3<f32>; // 0 for meshes without normals, which stay unlit.
+    [[location(6)]] albedo: vec3<f32>; // The color before lighting.
+#endif
+#ifdef EMISSIVE_TEXTURE
+    [[location(7)]] uv: vec2<f32>;
+#endif
 };
 
+#ifdef PACKED_VERTICES
+// Expands a normal or tangent packed by `pack_normal` or `pack_tangent` in packing.rs: xyz is the
+// vector, w the handedness (-1 or 1, 0 for normals).
+fn unpack_snorm10x3_2(packed: u32) -> vec4<f32> {
+    // Moving each field to the top of an i32 and back extends its sign.
+    let x = bitcast<i32>(packed << 22u) >> 22u;
+    let y = bitcast<i32>(packed << 12u) >> 22u;
+    let z = bitcast<i32>(packed << 2u) >> 22u;
+    let w = bitcast<i32>(packed) >> 30u;
+    let v = vec4<f32>(f32(x) / 511.0, f32(y) / 511.0, f32(z) / 511.0, f32(w));
+    return max(v, vec4<f32>(-1.0));
+}
+#endif
+
 [[stage(vertex)]]
 fn vs_main(
     model: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
     var out: VertexOutput;
+#ifdef VERTEX_COLOR
     out.color = model.color;
-    out.clip_position = vec4<f32>(model.position, 1.0);
+#else
+    out.color = vec3<f32>(1.0);
+#endif
+#ifdef LOCAL_LIGHTS
+    out.albedo = out.color;
+#endif
+#ifdef VERTEX_NORMAL
+#ifdef PACKED_VERTICES
+    let model_normal = unpack_snorm10x3_2(model.normal).xyz;
+#else
+    let model_normal = model.normal;
+#endif
+    // The directional light, so shapes read without a lit material, see light.rs.
+    let normal = normalize((model_matrix * vec4<f32>(model_normal, 0.0)).xyz);
+    let diffuse = globals.light_color.rgb * max(dot(normal, globals.light_direction.xyz), 0.0);
+    out.color = out.color * (globals.ambient.rgb + diffuse);
+#endif
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+#ifdef LOCAL_LIGHTS
+    out.light_position = world_position.xyz;
+#ifdef VERTEX_NORMAL
+    out.light_normal = normal;
+#else
+    out.light_normal = vec3<f32>(0.0);
+#endif
+#endif
+#ifdef REFLECTION_PROBES
+    out.world_position = world_position.xyz;
+#ifdef VERTEX_NORMAL
+    out.normal = normal;
+#else
+    out.normal = vec3<f32>(0.0);
+#endif
+#endif
+    out.clip_position = camera.view_proj * world_position;
+#ifdef PLANAR_MIRROR
+    out.mirror_position = out.clip_position;
+#endif
+#ifdef EMISSIVE_TEXTURE
+#ifdef VERTEX_UV
+    out.uv = model.uv;
+#else
+    out.uv = vec2<f32>(0.5);
+#endif
+#endif
     return out;
 }
 
 // Fragment shader
 
+#ifdef REFLECTION_PROBES
+// Texture bindings can't be indexed.
+fn sample_probe(index: u32, direction: vec3<f32>) -> vec3<f32> {
+    // The faces are mirrored along z.
+    let direction = vec3<f32>(direction.xy, -direction.z);
+    if (index == 0u) {
+        return textureSampleLevel(probe_0, probe_sampler, direction, 0.0).rgb;
+    } else if (index == 1u) {
+        return textureSampleLevel(probe_1, probe_sampler, direction, 0.0).rgb;
+    } else if (index == 2u) {
+        return textureSampleLevel(probe_2, probe_sampler, direction, 0.0).rgb;
+    }
+    return textureSampleLevel(probe_3, probe_sampler, direction, 0.0).rgb;
+}
+
+// The probes around `position`, blended by how far inside their boxes it is, looked up where the
+// ray from there along `ray` leaves each box.
+fn probe_reflection(position: vec3<f32>, ray: vec3<f32>) -> vec4<f32> {
+    var color = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var i = 0u; i < probes.count; i = i + 1u) {
+        let probe = probes.probes[i];
+        let center = probe.position.xyz;
+        let extents = probe.half_extents.xyz;
+        let outside = length(max(abs(position - center) - extents, vec3<f32>(0.0)));
+        let weight = 1.0 - clamp(outside / probe.position.w, 0.0, 1.0);
+        if (weight > 0.0 && all(extents > vec3<f32>(0.0))) {
+            // Distances along the ray to the planes of the box; the nearest exit is where it leaves.
+            let to_max = (center + extents - position) / ray;
+            let to_min = (center - extents - position) / ray;
+            let exits = max(to_max, to_min);
+            let distance = max(min(min(exits.x, exits.y), exits.z), 0.0);
+            let hit = position + ray * distance;
+            color = color + sample_probe(i, hit - center) * weight;
+            total = total + weight;
+        }
+    }
+    if (total > 0.0) {
+        return vec4<f32>(color / total, min(total, 1.0));
+    }
+    return vec4<f32>(0.0);
+}
+#endif
+
+#ifdef LOCAL_LIGHTS
+// How much of `light` reaches `position` past the shadow casters, from its shadow maps.
+fn local_shadow(light: LocalLight, position: vec3<f32>) -> f32 {
+    if (light.color.w < 0.0) {
+        return 1.0;
+    }
+    var index = u32(light.color.w);
+    if (light.cone.z > 0.0) {
+        // The face of the cube around a point light that the position is on, in the order +x, -x,
+        // +y, -y, +z, -z.
+        let d = position - light.position.xyz;
+        let a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            index = index + select(1u, 0u, d.x > 0.0);
+        } else if (a.y >= a.z) {
+            index = index + select(3u, 2u, d.y > 0.0);
+        } else {
+            index = index + select(5u, 4u, d.z > 0.0);
+        }
+    }
+    let view = local_lights.views[index];
+    let clip = view.view_proj * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    // Within the map's square, a texel from its edges, so the filter doesn't reach the next map.
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_atlas));
+    let uv = clamp(ndc.xy * vec2<f32>(0.5, -0.5) + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    let atlas_uv = clamp(view.rect.xy + uv * view.rect.zw, view.rect.xy + texel, view.rect.xy + view.rect.zw - texel);
+    return textureSampleCompareLevel(shadow_atlas, shadow_sampler, atlas_uv, ndc.z);
+}
+
+// The color of the cookie of a spot light where it falls on `position`.
+fn cookie(light: LocalLight, position: vec3<f32>) -> vec3<f32> {
+    let clip = light.projection * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + 0.5;
+    return textureSampleLevel(cookies, cookie_sampler, uv, i32(light.cone.w), 0.0).rgb;
+}
+
+// The light of the local lights falling on `position`, facing `normal`.
+fn local_lighting(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < local_lights.count; i = i + 1u) {
+        let light = local_lights.lights[i];
+        let to_light = light.position.xyz - position;
+        let distance = length(to_light);
+        let range = light.position.w;
+        if (distance > 0.0 && distance < range) {
+            let l = to_light / distance;
+            let n_dot_l = max(dot(normal, l), 0.0);
+            // With the square of the distance, smoothly down to 0 at the range.
+            let window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+            let falloff = window * window / (distance * distance + 1.0);
+            let cone = smoothStep(light.cone.x, light.cone.y, dot(-l, light.direction.xyz));
+            if (n_dot_l * cone > 0.0) {
+                var color = light.color.rgb;
+                if (light.cone.w >= 0.0) {
+                    color = color * cookie(light, position);
+                }
+                total = total + color * n_dot_l * falloff * cone * local_shadow(light, position);
+            }
+        }
+    }
+    return total;
+}
+#endif
+
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
-}
\ No newline at end of file
+#ifdef LOCAL_LIGHTS
+    // Per pixel, on top of the directional light of the vertex shader.
+    var lit = in.color;
+    if (any(in.light_normal != vec3<f32>(0.0))) {
+        lit = lit + in.albedo * local_lighting(in.light_position, normalize(in.light_normal));
+    }
+#else
+    let lit = in.color;
+#endif
+    // Light of the surface's own, added after lighting and reflections.
+    var emission = vec3<f32>(0.0);
+#ifdef EMISSIVE
+    emission = material.emissive.rgb * material.emissive.a;
+#ifdef EMISSIVE_TEXTURE
+    emission = emission * textureSample(emissive_texture, emissive_sampler, in.uv).rgb;
+#endif
+#endif
+#ifdef REFLECTION_PROBES
+    let normal = normalize(in.normal);
+    let ray = reflect(normalize(in.world_position - camera.eye.xyz), normal);
+    let reflection = probe_reflection(in.world_position, ray);
+    let base = lit * material.color.rgb;
+    let color = mix(base, reflection.rgb, material.reflectivity * reflection.a);
+    return vec4<f32>(color + emission, material.color.a);
+#else
+#ifdef PLANAR_MIRROR
+    // The mirror texture covers the camera's view, flipped along x.
+    let ndc = in.mirror_position.xy / in.mirror_position.w;
+    let reflection = textureSample(mirror_texture, mirror_sampler, vec2<f32>(0.5 - 0.5 * ndc.x, 0.5 - 0.5 * ndc.y)).rgb;
+    let color = mix(lit * material.color.rgb, reflection, material.reflectivity);
+    return vec4<f32>(color + emission, material.color.a);
+#else
+    return vec4<f32>(lit * material.color.rgb + emission, material.color.a);
+#endif
+#endif
+}
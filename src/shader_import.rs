@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use crate::assets::LoadError;
+use crate::reflection::ReflectError;
+
+// SPIR-V and GLSL shaders
+//======================
+// Everything downstream of loading (reflection, variants, constant overrides) works on WGSL, so
+// shaders in other languages are translated to WGSL with naga when they are loaded, and are used
+// like any other shader from then on:
+//
+//     gfx.load_shader("shaders/toon.spv")?;   // SPIR-V with `vs_main` and `fs_main` entry points
+//     gfx.load_shader("shaders/toon.vert")?;  // GLSL, with its fragment stage in toon.frag
+//
+//     let wgsl = shader_import::to_wgsl(&std::fs::read("blur.comp")?, ShaderFormat::Glsl(naga::ShaderStage::Compute))?;
+//     let kernel = ComputeKernel::new(device, "Blur", &wgsl, "main")?;
+//
+// The format is told by the file extension: `.wgsl`, `.spv`, and `.vert`, `.frag` and `.comp` for
+// GLSL. GLSL has one file per stage and always calls its entry point `main`; for material shaders
+// the vertex and the fragment stage are translated separately, renamed to `vs_main` and `fs_main`,
+// and become two shader modules of the same pipeline, see material.rs.
+//
+// Bindings and vertex inputs follow the material conventions of WGSL shaders: the camera in
+// group 0, the material in group 1, the vertex attributes at locations 0 and 1 and the model matrix
+// at locations 5 to 8. SPIR-V written for Vulkan has its clip space Y flipped on import.
+
+/// The language of a shader file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderFormat {
+    Wgsl,
+    SpirV,
+    Glsl(naga::ShaderStage),
+}
+
+impl ShaderFormat {
+    // By file extension, see above. `None` for unknown extensions.
+    pub fn from_path(path: &Path) -> Option<ShaderFormat> {
+        let format = match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "wgsl" => ShaderFormat::Wgsl,
+            "spv" => ShaderFormat::SpirV,
+            "vert" => ShaderFormat::Glsl(naga::ShaderStage::Vertex),
+            "frag" => ShaderFormat::Glsl(naga::ShaderStage::Fragment),
+            "comp" => ShaderFormat::Glsl(naga::ShaderStage::Compute),
+            _ => return None,
+        };
+        Some(format)
+    }
+}
+
+// Translates `code` to WGSL. GLSL entry points are renamed after their stage, see above.
+pub fn to_wgsl(code: &[u8], format: ShaderFormat) -> Result<String, ReflectError> {
+    let mut module = match format {
+        ShaderFormat::Wgsl => {
+            return String::from_utf8(code.to_vec()).map_err(|e| ReflectError::Parse(e.to_string()));
+        }
+        ShaderFormat::SpirV => naga::front::spv::parse_u8_slice(code, &naga::front::spv::Options::default())
+            .map_err(|e| ReflectError::Parse(format!("SPIR-V: {}", e)))?,
+        ShaderFormat::Glsl(stage) => {
+            let source = std::str::from_utf8(code).map_err(|e| ReflectError::Parse(e.to_string()))?;
+            naga::front::glsl::Parser::default()
+                .parse(&stage.into(), source)
+                .map_err(|errors| ReflectError::Parse(glsl_errors(source, &errors)))?
+        }
+    };
+    if let ShaderFormat::Glsl(stage) = format {
+        for entry_point in &mut module.entry_points {
+            entry_point.name = match stage {
+                naga::ShaderStage::Vertex => "vs_main",
+                naga::ShaderStage::Fragment => "fs_main",
+                naga::ShaderStage::Compute => "main",
+            }
+            .to_string();
+        }
+    }
+
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| ReflectError::Validation(format!("{:?}", e)))?;
+    naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())
+        .map_err(|e| ReflectError::Validation(e.to_string()))
+}
+
+// "line 3: ..." for each error, like the WGSL front end reports them.
+fn glsl_errors(source: &str, errors: &[naga::front::glsl::Error]) -> String {
+    errors
+        .iter()
+        .map(|error| match error.meta.to_range() {
+            Some(range) => format!("line {}: {}", source[..range.start.min(source.len())].matches('\n').count() + 1, error),
+            None => error.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A material shader file translated to WGSL.
+pub struct MaterialShader {
+    pub wgsl: String,
+    // The fragment stage of GLSL shaders, in a module of its own, and the file it was read from.
+    pub fragment: Option<(String, PathBuf)>,
+}
+
+// Reads and translates a material shader, see above.
+pub fn load_material_shader(path: &Path) -> Result<MaterialShader, LoadError> {
+    let format = ShaderFormat::from_path(path).unwrap_or(ShaderFormat::Wgsl);
+    match format {
+        ShaderFormat::Wgsl | ShaderFormat::SpirV => Ok(MaterialShader {
+            wgsl: to_wgsl(&std::fs::read(path)?, format)?,
+            fragment: None,
+        }),
+        ShaderFormat::Glsl(naga::ShaderStage::Vertex) => {
+            let fragment_path = path.with_extension("frag");
+            let fragment_format = ShaderFormat::Glsl(naga::ShaderStage::Fragment);
+            Ok(MaterialShader {
+                wgsl: to_wgsl(&std::fs::read(path)?, format)?,
+                fragment: Some((to_wgsl(&std::fs::read(&fragment_path)?, fragment_format)?, fragment_path)),
+            })
+        }
+        ShaderFormat::Glsl(_) => Err(ReflectError::Parse(format!(
+            "{} is not a vertex shader, load GLSL material shaders by their .vert file",
+            path.display()
+        ))
+        .into()),
+    }
+}
@@ -0,0 +1,22 @@
+// The depth of the scene as a light sees it, into the light's square of the shadow atlas, see
+// shadow_atlas.rs. There is no fragment stage: only the depth is written.
+
+struct ShadowView {
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> view: ShadowView;
+
+// The model matrix of the renderable, see transform.rs.
+struct InstanceInput {
+    [[location(5)]] model_0: vec4<f32>;
+    [[location(6)]] model_1: vec4<f32>;
+    [[location(7)]] model_2: vec4<f32>;
+    [[location(8)]] model_3: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[location(0)]] position: vec3<f32>, instance: InstanceInput) -> [[builtin(position)]] vec4<f32> {
+    let model_matrix = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    return view.view_proj * model_matrix * vec4<f32>(position, 1.0);
+}
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+
+use cgmath::{Matrix4, Vector4};
+
+use crate::layers::RenderLayers;
+use crate::mesh::VertexLayout;
+use crate::reflection::ShaderReflection;
+use crate::texture::Texture;
+use crate::transform::ModelInstance;
+use crate::uniform::UniformLayout;
+
+// Shadow atlas
+//======================
+// Every local light that casts shadows (local_lights.rs) renders the depth of the scene as it sees
+// it into shadow maps: one for a spot light, six for a point light, one per face of a cube around
+// it. Shaders can't index textures, so instead of a texture per map, all maps are squares of one
+// large depth texture, the atlas, and the lighting shader looks up the square of each map, see
+// shader.wgsl.
+//
+// The atlas is packed anew every frame. Maps are squares with power of two sides, from
+// `MIN_SHADOW_RESOLUTION` to half the atlas, and are cut from it like a quadtree: a free square
+// is split into quarters until they have the size of the map, and the other quarters stay free.
+// Lights are packed in order of importance, their shadow priority times how near the camera is
+// compared to their range; the most important gets the largest maps, and every light gets half
+// the size for every time its importance halves:
+//
+//     gfx.add_local_light(LocalLight::spot(position, direction, color, 10.0, 30.0).with_shadows(2.0));
+//
+// A light that doesn't fit tries smaller maps, down to the smallest, and casts no shadow when even
+// those don't fit, as do lights beyond `MAX_SHADOW_VIEWS` maps. The atlas is
+// `GfxOptions::shadow_atlas_size` pixels wide and high.
+//
+// The maps are rendered with a depth-only pipeline per vertex layout (shadow.wgsl), biased by the
+// slope of the surfaces against shadow acne.
+
+pub const SHADOW_FORMAT: wgpu::TextureFormat = Texture::DEPTH_FORMAT;
+pub const MAX_SHADOW_VIEWS: usize = 32;
+// The side of the smallest maps, in pixels.
+pub const MIN_SHADOW_RESOLUTION: u32 = 64;
+
+/// A square of the atlas, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+/// Cuts squares with power of two sides from a square atlas, see above.
+pub struct AtlasAllocator {
+    size: u32,
+    min_size: u32,
+    free: Vec<Vec<(u32, u32)>>, // The corners of the free squares, by level: their side is `size >> level`.
+}
+
+impl AtlasAllocator {
+    // `size` and `min_size` are rounded up to powers of two.
+    pub fn new(size: u32, min_size: u32) -> AtlasAllocator {
+        let size = size.next_power_of_two();
+        let min_size = min_size.next_power_of_two().min(size);
+        let levels = (size / min_size).trailing_zeros() as usize + 1;
+        let mut allocator = AtlasAllocator {
+            size,
+            min_size,
+            free: vec![Vec::new(); levels],
+        };
+        allocator.clear();
+        allocator
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    // Frees everything.
+    pub fn clear(&mut self) {
+        for level in &mut self.free {
+            level.clear();
+        }
+        self.free[0].push((0, 0));
+    }
+
+    // A free square of `size` rounded up to a power of two, `None` if there is none. Splits the
+    // smallest larger square if no square has that size.
+    pub fn allocate(&mut self, size: u32) -> Option<AtlasRect> {
+        let size = size.next_power_of_two().clamp(self.min_size, self.size);
+        let level = (self.size / size).trailing_zeros() as usize;
+        let mut from = (0..=level).rev().find(|&l| !self.free[l].is_empty())?;
+        let (x, y) = self.free[from].pop()?;
+        while from < level {
+            from += 1;
+            // Keeps the top left quarter, and frees the others, the top right one to be taken next.
+            let half = self.size >> from;
+            self.free[from].extend([(x + half, y + half), (x, y + half), (x + half, y)]);
+        }
+        Some(AtlasRect { x, y, size })
+    }
+
+    // Frees a square. Freed quarters are not merged back into larger squares until `clear`.
+    pub fn deallocate(&mut self, rect: AtlasRect) {
+        let level = (self.size / rect.size).trailing_zeros() as usize;
+        self.free[level].push((rect.x, rect.y));
+    }
+}
+
+/// The shadow maps a light asks for, see `ShadowAtlas::allocate`.
+pub struct ShadowRequest {
+    pub view_projs: Vec<Matrix4<f32>>, // One per map.
+    pub importance: f32, // See above; nothing is allocated for 0.
+    pub layers: RenderLayers, // Only renderables on one of these layers cast shadows.
+}
+
+/// A shadow map in the atlas.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowView {
+    pub view_proj: Matrix4<f32>,
+    pub rect: AtlasRect,
+    pub layers: RenderLayers,
+}
+
+impl ShadowView {
+    // The square as texture coordinates of an atlas of `size`: the corner in xy, the size in zw.
+    pub fn uv_rect(&self, size: u32) -> Vector4<f32> {
+        let size = size as f32;
+        let rect = self.rect;
+        Vector4::new(rect.x as f32 / size, rect.y as f32 / size, rect.size as f32 / size, rect.size as f32 / size)
+    }
+}
+
+uniform_struct! {
+    struct ShadowViewParams {
+        view_proj: Matrix4<f32>,
+    }
+}
+assert_uniform_size!(ShadowViewParams, 64);
+
+/// The atlas texture, the maps packed into it this frame, and the pipelines rendering them.
+pub struct ShadowAtlas {
+    allocator: AtlasAllocator,
+    view: wgpu::TextureView,
+    views: Vec<ShadowView>,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<VertexLayout, wgpu::RenderPipeline>,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+    params_stride: u32,
+}
+
+impl ShadowAtlas {
+    pub fn new(device: &wgpu::Device, size: u32) -> ShadowAtlas {
+        let allocator = AtlasAllocator::new(size.max(2 * MIN_SHADOW_RESOLUTION), MIN_SHADOW_RESOLUTION);
+        let size = allocator.size();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Atlas"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let wgsl = include_str!("shadow.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        // Every map has its own part of the parameters' buffer.
+        let mut entries = reflection.layout_entries(0);
+        for entry in &mut entries {
+            if let wgpu::BindingType::Buffer { has_dynamic_offset, .. } = &mut entry.ty {
+                *has_dynamic_offset = true;
+            }
+        }
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &entries,
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&params_layout],
+            push_constant_ranges: &[],
+        });
+        let params_stride = device.limits().min_uniform_buffer_offset_alignment.max(ShadowViewParams::SIZE as u32);
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Params Buffer"),
+            size: params_stride as u64 * MAX_SHADOW_VIEWS as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Params Bind Group"),
+            layout: &params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &params_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(ShadowViewParams::SIZE as u64),
+                }),
+            }],
+        });
+        ShadowAtlas {
+            allocator,
+            view,
+            views: Vec::new(),
+            shader,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            params_buffer,
+            params_bind_group,
+            params_stride,
+        }
+    }
+
+    // The side of the atlas, in pixels.
+    pub fn size(&self) -> u32 {
+        self.allocator.size()
+    }
+
+    // Packs the maps of the requests in order of importance, see above, and uploads their matrices.
+    // Returns the index of the first view of every request, `None` for those without a shadow.
+    pub fn allocate(&mut self, queue: &wgpu::Queue, requests: &[ShadowRequest]) -> Vec<Option<usize>> {
+        self.allocator.clear();
+        self.views.clear();
+        let mut order: Vec<usize> = (0..requests.len())
+            .filter(|&i| requests[i].importance > 0.0 && !requests[i].view_projs.is_empty())
+            .collect();
+        order.sort_by(|&a, &b| requests[b].importance.total_cmp(&requests[a].importance));
+        let top = order.first().map_or(1.0, |&i| requests[i].importance);
+        let largest = self.size() / 2;
+
+        let mut first_views = vec![None; requests.len()];
+        for i in order {
+            let request = &requests[i];
+            if self.views.len() + request.view_projs.len() > MAX_SHADOW_VIEWS {
+                continue;
+            }
+            let halvings = (top / request.importance).log2().floor().clamp(0.0, 31.0) as u32;
+            let mut resolution = (largest >> halvings).max(MIN_SHADOW_RESOLUTION);
+            let rects = loop {
+                if let Some(rects) = self.allocate_all(resolution, request.view_projs.len()) {
+                    break Some(rects);
+                }
+                if resolution <= MIN_SHADOW_RESOLUTION {
+                    break None;
+                }
+                resolution /= 2;
+            };
+            if let Some(rects) = rects {
+                first_views[i] = Some(self.views.len());
+                for (&view_proj, rect) in request.view_projs.iter().zip(rects) {
+                    self.views.push(ShadowView {
+                        view_proj,
+                        rect,
+                        layers: request.layers,
+                    });
+                }
+            }
+        }
+
+        for (index, view) in self.views.iter().enumerate() {
+            let params = ShadowViewParams {
+                view_proj: view.view_proj,
+            };
+            let offset = index as u64 * self.params_stride as u64;
+            queue.write_buffer(&self.params_buffer, offset, &params.to_uniform_bytes());
+        }
+        first_views
+    }
+
+    // `count` squares of `size`, or none of them.
+    fn allocate_all(&mut self, size: u32, count: usize) -> Option<Vec<AtlasRect>> {
+        let mut rects = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.allocator.allocate(size) {
+                Some(rect) => rects.push(rect),
+                None => {
+                    for rect in rects {
+                        self.allocator.deallocate(rect);
+                    }
+                    return None;
+                }
+            }
+        }
+        Some(rects)
+    }
+
+    // The maps packed by the last `allocate`.
+    pub fn views(&self) -> &[ShadowView] {
+        &self.views
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // Creates the pipeline for meshes of `vertex`, if there is none yet.
+    pub fn prepare_pipeline(&mut self, device: &wgpu::Device, vertex: VertexLayout) {
+        if !self.pipelines.contains_key(&vertex) {
+            let pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, vertex);
+            self.pipelines.insert(vertex, pipeline);
+        }
+    }
+
+    pub fn pipeline(&self, vertex: VertexLayout) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(&vertex)
+    }
+
+    // Restricts a pass into the atlas to the square of map `index`, and binds its matrix.
+    pub fn set_view<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) {
+        let rect = self.views[index].rect;
+        render_pass.set_viewport(rect.x as f32, rect.y as f32, rect.size as f32, rect.size as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(rect.x, rect.y, rect.size, rect.size);
+        render_pass.set_bind_group(0, &self.params_bind_group, &[index as u32 * self.params_stride]);
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    vertex: VertexLayout,
+) -> wgpu::RenderPipeline {
+    let desc = vertex.desc();
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("Shadow Pipeline ({:?})", vertex)),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[desc.layout(), ModelInstance::desc()],
+        },
+        fragment: None,
+        // Both sides cast shadows, so open meshes like the pentagon do too.
+        primitive: wgpu::PrimitiveState {
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: SHADOW_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn overlap(a: &AtlasRect, b: &AtlasRect) -> bool {
+        a.x < b.x + b.size && b.x < a.x + a.size && a.y < b.y + b.size && b.y < a.y + a.size
+    }
+
+    fn assert_packed(rects: &[AtlasRect], atlas: u32) {
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.x + a.size <= atlas && a.y + a.size <= atlas, "{:?} is outside the atlas", a);
+            assert_eq!((a.x % a.size, a.y % a.size), (0, 0), "{:?} is not aligned to its size", a);
+            for b in &rects[i + 1..] {
+                assert!(!overlap(a, b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn squares_never_overlap() {
+        let rng = Rng::new(5);
+        let mut allocator = AtlasAllocator::new(2048, 64);
+        let mut rects = Vec::new();
+        for _ in 0..200 {
+            let size = 64 << rng.below(5);
+            if let Some(rect) = allocator.allocate(size) {
+                assert_eq!(rect.size, size);
+                rects.push(rect);
+            }
+        }
+        assert_packed(&rects, 2048);
+        let area: u32 = rects.iter().map(|rect| rect.size * rect.size).sum();
+        assert!(area <= 2048 * 2048);
+    }
+
+    #[test]
+    fn sizes_round_up_and_clamp() {
+        let mut allocator = AtlasAllocator::new(1000, 50);
+        assert_eq!(allocator.size(), 1024);
+        assert_eq!(allocator.allocate(10).unwrap().size, 64);
+        assert_eq!(allocator.allocate(100).unwrap().size, 128);
+        allocator.clear();
+        assert_eq!(allocator.allocate(5000), Some(AtlasRect { x: 0, y: 0, size: 1024 }));
+    }
+
+    #[test]
+    fn freed_squares_are_reused() {
+        let mut allocator = AtlasAllocator::new(1024, 64);
+        let rects: Vec<AtlasRect> = (0..4).map(|_| allocator.allocate(512).unwrap()).collect();
+        assert_eq!(allocator.allocate(512), None);
+
+        // The same square comes back for the same size.
+        allocator.deallocate(rects[2]);
+        assert_eq!(allocator.allocate(512), Some(rects[2]));
+
+        // Smaller squares are cut from a freed one, and nowhere else.
+        allocator.deallocate(rects[1]);
+        let small: Vec<AtlasRect> = (0..4).map(|_| allocator.allocate(256).unwrap()).collect();
+        assert!(small.iter().all(|rect| overlap(rect, &rects[1])));
+        assert_eq!(allocator.allocate(256), None);
+        let mut all = small;
+        all.extend([rects[0], rects[2], rects[3]]);
+        assert_packed(&all, 1024);
+    }
+
+    #[test]
+    fn full_atlas_allocates_nothing_until_freed() {
+        let mut allocator = AtlasAllocator::new(512, 64);
+        let rects: Vec<AtlasRect> = std::iter::from_fn(|| allocator.allocate(64)).collect();
+        assert_eq!(rects.len(), 64); // Every 64 pixel square of the atlas.
+        assert_packed(&rects, 512);
+        assert_eq!(allocator.allocate(64), None);
+        assert_eq!(allocator.allocate(512), None);
+
+        allocator.deallocate(rects[10]);
+        assert_eq!(allocator.allocate(64), Some(rects[10]));
+        assert_eq!(allocator.allocate(64), None);
+
+        // Quarters are not merged back, only `clear` frees the whole atlas again.
+        for &rect in &rects[..4] {
+            allocator.deallocate(rect);
+        }
+        assert_eq!(allocator.allocate(128), None);
+        allocator.clear();
+        assert_eq!(allocator.allocate(512), Some(AtlasRect { x: 0, y: 0, size: 512 }));
+    }
+}
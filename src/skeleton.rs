@@ -0,0 +1,491 @@
+use std::fmt;
+
+use cgmath::{Euler, InnerSpace, Matrix4, Point3, Quaternion, SquareMatrix, Transform as _, Vector3, VectorSpace};
+use windows::Win32::UI::Input::KeyboardAndMouse::{VK_HOME, VK_LEFT, VK_OEM_COMMA, VK_OEM_PERIOD, VK_RIGHT, VK_SPACE};
+
+use crate::debug_draw::DebugDraw;
+use crate::game::Event;
+use crate::transform::Transform;
+
+// Skeletons and animation clips
+//======================
+// Skinning (skinning.rs) only takes the final joint matrices. Where they come from is described
+// here the way glTF stores it, so a loader fills these types in directly:
+//
+//  - A `Skeleton` is a list of joints, each with a parent (parents come first), a rest transform
+//    relative to the parent, and the inverse bind matrix taking the mesh into the joint's space.
+//  - A `Pose` is one local transform per joint, starting from the rest transforms.
+//  - An `AnimationClip` holds keyframes per joint, sampled into a pose at a time.
+//  - An `AnimationPlayer` keeps the time, and can be scrubbed from the keyboard.
+//
+//     let mut pose = skeleton.rest_pose();
+//     player.advance(dt, &clip);
+//     clip.sample(player.time(), &mut pose);
+//     gfx.set_joint_matrices(skin, &skeleton.joint_matrices(&pose));
+//
+// When a skinned mesh looks wrong, `GFX::draw_skeleton` draws the bones over it with the joint
+// names, and `Skeleton::inspect` lists the pose of every joint and flags the usual suspects:
+// scales near zero, rotations that are not unit quaternions, matrices that can't be inverted.
+//
+// The player's keys, while it is fed events: Space plays and pauses, Left and Right step a frame
+// back and forth (pausing), Home goes back to the start, Comma and Period halve and double the speed.
+
+// The step of Left and Right, in seconds.
+const FRAME_STEP: f32 = 1.0 / 30.0;
+// Of the joint axes and the joint markers, relative to the bone they start.
+const AXIS_LENGTH: f32 = 0.25;
+
+const BONE_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const JOINT_COLOR: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.3, 0.9, 1.0];
+const AXIS_COLORS: [[f32; 4]; 3] = [[0.9, 0.2, 0.2, 1.0], [0.3, 0.85, 0.3, 1.0], [0.25, 0.45, 1.0, 1.0]];
+
+/// A joint of a skeleton.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>, // Always a joint before this one.
+    pub rest: Transform, // Relative to the parent.
+    pub inverse_bind: Matrix4<f32>, // From the mesh's model space into the joint's space at bind time.
+}
+
+/// The joint hierarchy of a skinned mesh, see above.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+/// The local transforms of a skeleton's joints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub locals: Vec<Transform>,
+}
+
+impl Skeleton {
+    // Joints whose parent doesn't come before them are made roots, with a warning.
+    pub fn new(mut joints: Vec<Joint>) -> Skeleton {
+        for (i, joint) in joints.iter_mut().enumerate() {
+            if joint.parent.is_some_and(|parent| parent >= i) {
+                tracing::warn!("Joint {} ({}) comes before its parent, made a root", i, joint.name);
+                joint.parent = None;
+            }
+        }
+        Skeleton { joints }
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            locals: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+
+    // The transforms of the joints in the mesh's model space, parents before children.
+    pub fn model_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        let mut matrices: Vec<Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for (i, joint) in self.joints.iter().enumerate() {
+            let local = pose.locals.get(i).unwrap_or(&joint.rest).to_matrix();
+            let matrix = match joint.parent {
+                Some(parent) => matrices[parent] * local,
+                None => local,
+            };
+            matrices.push(matrix);
+        }
+        matrices
+    }
+
+    // What `GFX::set_joint_matrices` takes: from the bind pose to the pose.
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        self.model_matrices(pose)
+            .into_iter()
+            .zip(&self.joints)
+            .map(|(matrix, joint)| matrix * joint.inverse_bind)
+            .collect()
+    }
+
+    // The pose of every joint and what looks wrong with it, see above.
+    pub fn inspect(&self, pose: &Pose) -> Vec<JointReport> {
+        let model = self.model_matrices(pose);
+        self.joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| {
+                let local = *pose.locals.get(i).unwrap_or(&joint.rest);
+                let mut problems = Vec::new();
+                if (local.rotation.magnitude() - 1.0).abs() > 1e-3 {
+                    problems.push("rotation is not a unit quaternion");
+                }
+                if local.scale.x.abs().min(local.scale.y.abs()).min(local.scale.z.abs()) < 1e-4 {
+                    problems.push("scale is close to zero");
+                }
+                if joint.inverse_bind.invert().is_none() {
+                    problems.push("inverse bind matrix can't be inverted");
+                }
+                let finite = |m: &Matrix4<f32>| AsRef::<[f32; 16]>::as_ref(m).iter().all(|v| v.is_finite());
+                if !finite(&model[i]) {
+                    problems.push("transform is not finite");
+                }
+                JointReport {
+                    index: i,
+                    name: joint.name.clone(),
+                    depth: self.depth(i),
+                    local,
+                    position: model[i].transform_point(Point3::new(0.0, 0.0, 0.0)),
+                    problems,
+                }
+            })
+            .collect()
+    }
+
+    // Lines from every joint to its parent, and the axes of every joint, placed by `model` (the
+    // renderable's world matrix). The `highlight`ed joint and its bone are drawn in another color.
+    pub fn debug_draw(&self, debug_draw: &mut DebugDraw, pose: &Pose, model: &Matrix4<f32>, highlight: Option<usize>) {
+        let matrices: Vec<Matrix4<f32>> = self.model_matrices(pose).into_iter().map(|m| model * m).collect();
+        let origin = |m: &Matrix4<f32>| m.transform_point(Point3::new(0.0, 0.0, 0.0));
+        for (i, joint) in self.joints.iter().enumerate() {
+            let position = origin(&matrices[i]);
+            let highlighted = highlight == Some(i);
+            // Axes as long as a quarter of the bone from the parent, or of a unit for roots.
+            let length = match joint.parent {
+                Some(parent) => {
+                    let parent_position = origin(&matrices[parent]);
+                    debug_draw.line(parent_position, position, if highlighted { HIGHLIGHT_COLOR } else { BONE_COLOR });
+                    (position - parent_position).magnitude()
+                }
+                None => 1.0,
+            } * AXIS_LENGTH;
+            let length = if length > 0.0 { length } else { AXIS_LENGTH };
+            for (axis, color) in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()].into_iter().zip(AXIS_COLORS) {
+                let direction = matrices[i].transform_vector(axis);
+                if direction.magnitude2() > 0.0 {
+                    debug_draw.line(position, position + direction.normalize() * length, color);
+                }
+            }
+            let color = if highlighted { HIGHLIGHT_COLOR } else { JOINT_COLOR };
+            debug_draw.sphere(position, length * 0.3, color);
+        }
+    }
+
+    fn depth(&self, mut joint: usize) -> usize {
+        let mut depth = 0;
+        while let Some(parent) = self.joints[joint].parent {
+            joint = parent;
+            depth += 1;
+        }
+        depth
+    }
+}
+
+/// The pose of a joint, from `Skeleton::inspect`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JointReport {
+    pub index: usize,
+    pub name: String,
+    pub depth: usize, // 0 for roots.
+    pub local: Transform,
+    pub position: Point3<f32>, // In the mesh's model space.
+    pub problems: Vec<&'static str>,
+}
+
+// One line, indented by depth, e.g. "  elbow t (0.00, 1.00, 0.00) r (0, 0, 45) s (1.00, 1.00, 1.00)".
+impl fmt::Display for JointReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (t, s) = (self.local.translation, self.local.scale);
+        let euler = Euler::from(self.local.rotation);
+        let degrees = |angle: cgmath::Rad<f32>| cgmath::Deg::from(angle).0;
+        write!(
+            f,
+            "{:indent$}{} t ({:.2}, {:.2}, {:.2}) r ({:.0}, {:.0}, {:.0}) s ({:.2}, {:.2}, {:.2})",
+            "",
+            self.name,
+            t.x,
+            t.y,
+            t.z,
+            degrees(euler.x),
+            degrees(euler.y),
+            degrees(euler.z),
+            s.x,
+            s.y,
+            s.z,
+            indent = self.depth * 2
+        )?;
+        for problem in &self.problems {
+            write!(f, " [{}]", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// A transform at a time of an animation clip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f32, // In seconds.
+    pub transform: Transform,
+}
+
+/// The keyframes of one joint, in time order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JointTrack {
+    pub joint: usize,
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// An animation of a skeleton, see above.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32, // In seconds, the time of the last keyframe.
+    pub tracks: Vec<JointTrack>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str, mut tracks: Vec<JointTrack>) -> AnimationClip {
+        for track in &mut tracks {
+            track.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        }
+        let duration = tracks
+            .iter()
+            .filter_map(|track| track.keyframes.last())
+            .map(|keyframe| keyframe.time)
+            .fold(0.0, f32::max);
+        AnimationClip {
+            name: name.to_string(),
+            duration,
+            tracks,
+        }
+    }
+
+    // Sets the joints with a track to their transforms at `time`, interpolated between keyframes:
+    // linearly for translation and scale, along the shortest arc for rotation. Joints without a
+    // track keep their transform.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for track in &self.tracks {
+            let keyframes = &track.keyframes;
+            let local = match pose.locals.get_mut(track.joint) {
+                Some(local) if !keyframes.is_empty() => local,
+                _ => continue,
+            };
+            let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+            *local = match next {
+                0 => keyframes[0].transform,
+                n if n == keyframes.len() => keyframes[n - 1].transform,
+                n => {
+                    let (a, b) = (&keyframes[n - 1], &keyframes[n]);
+                    let t = (time - a.time) / (b.time - a.time).max(f32::EPSILON);
+                    interpolate(&a.transform, &b.transform, t)
+                }
+            };
+        }
+    }
+}
+
+fn interpolate(a: &Transform, b: &Transform, t: f32) -> Transform {
+    // The same rotation may be stored with either sign; take the one closer to `a`.
+    let rotation: Quaternion<f32> = if a.rotation.dot(b.rotation) < 0.0 { -b.rotation } else { b.rotation };
+    Transform {
+        translation: a.translation.lerp(b.translation, t),
+        rotation: a.rotation.nlerp(rotation, t),
+        scale: a.scale.lerp(b.scale, t),
+    }
+}
+
+/// The playback time of a clip, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationPlayer {
+    time: f32,
+    pub speed: f32,
+    pub playing: bool,
+    pub looping: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new() -> AnimationPlayer {
+        AnimationPlayer {
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            looping: true,
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    // Jumps to `time`, kept within the clip.
+    pub fn seek(&mut self, time: f32, clip: &AnimationClip) {
+        self.time = self.wrap(time, clip);
+    }
+
+    // Moves the time along by `dt` seconds at the player's speed while it plays.
+    pub fn advance(&mut self, dt: f32, clip: &AnimationClip) {
+        if self.playing {
+            self.time = self.wrap(self.time + dt * self.speed, clip);
+        }
+    }
+
+    // The scrubbing keys, see above. Returns true if the event was one of them.
+    pub fn on_event(&mut self, event: &Event, clip: &AnimationClip) -> bool {
+        let key = match *event {
+            Event::KeyPressed(key) => key,
+            _ => return false,
+        };
+        match key {
+            VK_SPACE => self.playing = !self.playing,
+            VK_LEFT | VK_RIGHT => {
+                self.playing = false;
+                let step = if key == VK_LEFT { -FRAME_STEP } else { FRAME_STEP };
+                self.seek(self.time + step, clip);
+            }
+            VK_HOME => self.time = 0.0,
+            VK_OEM_COMMA => self.speed *= 0.5,
+            VK_OEM_PERIOD => self.speed *= 2.0,
+            _ => return false,
+        }
+        true
+    }
+
+    fn wrap(&self, time: f32, clip: &AnimationClip) -> f32 {
+        if clip.duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            time.rem_euclid(clip.duration)
+        } else {
+            time.clamp(0.0, clip.duration)
+        }
+    }
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Rad, Rotation3};
+
+    // A root at the origin and a child one unit up, bound where they rest.
+    fn arm() -> Skeleton {
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        Skeleton::new(vec![
+            Joint {
+                name: "root".to_string(),
+                parent: None,
+                rest: Transform::IDENTITY,
+                inverse_bind: Matrix4::identity(),
+            },
+            Joint {
+                name: "tip".to_string(),
+                parent: Some(0),
+                rest: Transform::from_translation(up),
+                inverse_bind: Matrix4::from_translation(-up),
+            },
+        ])
+    }
+
+    fn turn(time: f32, angle: f32) -> Keyframe {
+        Keyframe {
+            time,
+            transform: Transform::IDENTITY.with_rotation(Quaternion::from_angle_z(Rad(angle))),
+        }
+    }
+
+    fn assert_near(a: Matrix4<f32>, b: Matrix4<f32>) {
+        let (a, b): (&[f32; 16], &[f32; 16]) = (a.as_ref(), b.as_ref());
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn the_bind_pose_gives_identity_joint_matrices() {
+        let skeleton = arm();
+        let matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        assert_eq!(matrices.len(), 2);
+        for matrix in matrices {
+            assert_near(matrix, Matrix4::identity());
+        }
+        let model = skeleton.model_matrices(&skeleton.rest_pose());
+        assert_near(model[1], Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let skeleton = arm();
+        let mut pose = skeleton.rest_pose();
+        pose.locals[0] = turn(0.0, std::f32::consts::FRAC_PI_2).transform;
+        let tip = skeleton.inspect(&pose)[1].position;
+        assert!((tip.x + 1.0).abs() < 1e-5 && tip.y.abs() < 1e-5, "{:?}", tip);
+    }
+
+    #[test]
+    fn joints_before_their_parent_are_made_roots() {
+        let mut joints = arm().joints().to_vec();
+        joints[0].parent = Some(1);
+        assert_eq!(Skeleton::new(joints).joints()[0].parent, None);
+    }
+
+    #[test]
+    fn clips_interpolate_between_keyframes_and_hold_the_ends() {
+        let skeleton = arm();
+        let clip = AnimationClip::new(
+            "Turn",
+            vec![JointTrack {
+                joint: 0,
+                keyframes: vec![turn(2.0, 1.0), turn(0.0, 0.0)],
+            }],
+        );
+        assert_eq!(clip.duration, 2.0);
+        let angle = |time: f32| {
+            let mut pose = skeleton.rest_pose();
+            clip.sample(time, &mut pose);
+            // The tip joint has no track and keeps its rest transform.
+            assert_eq!(pose.locals[1], skeleton.joints()[1].rest);
+            2.0 * pose.locals[0].rotation.v.z.atan2(pose.locals[0].rotation.s)
+        };
+        assert!((angle(-1.0)).abs() < 1e-5);
+        assert!((angle(1.0) - 0.5).abs() < 1e-2);
+        assert!((angle(3.0) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn inspect_flags_degenerate_joints() {
+        let skeleton = arm();
+        let mut pose = skeleton.rest_pose();
+        assert!(skeleton.inspect(&pose).iter().all(|report| report.problems.is_empty()));
+        pose.locals[1] = pose.locals[1].with_scale(Vector3::new(1.0, 0.0, 1.0));
+        let reports = skeleton.inspect(&pose);
+        assert_eq!(reports[1].problems, ["scale is close to zero"]);
+        assert_eq!(reports[1].depth, 1);
+        assert!(reports[1].to_string().starts_with("  tip t (0.00, 1.00, 0.00)"));
+    }
+
+    #[test]
+    fn the_player_wraps_when_looping_and_scrubs_from_the_keyboard() {
+        let clip = AnimationClip::new(
+            "Turn",
+            vec![JointTrack {
+                joint: 0,
+                keyframes: vec![turn(0.0, 0.0), turn(1.0, 1.0)],
+            }],
+        );
+        let mut player = AnimationPlayer::new();
+        player.advance(1.25, &clip);
+        assert!((player.time() - 0.25).abs() < 1e-5);
+        player.looping = false;
+        player.advance(2.0, &clip);
+        assert_eq!(player.time(), 1.0);
+        assert!(player.on_event(&Event::KeyPressed(VK_LEFT), &clip));
+        assert!(!player.playing);
+        assert!((player.time() - (1.0 - FRAME_STEP)).abs() < 1e-5);
+        player.advance(0.5, &clip);
+        assert!((player.time() - (1.0 - FRAME_STEP)).abs() < 1e-5);
+        assert!(player.on_event(&Event::KeyPressed(VK_HOME), &clip));
+        assert_eq!(player.time(), 0.0);
+        assert!(!player.on_event(&Event::KeyPressed(b'Q' as u16), &clip));
+    }
+}
@@ -0,0 +1,372 @@
+use std::rc::Rc;
+
+use cgmath::{Matrix4, Quaternion, Rad, Rotation3, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::compute::ComputeKernel;
+use crate::game::Event;
+use crate::gfx::{RenderableId, GFX};
+use crate::layers::RenderLayers;
+use crate::mesh::{Mesh, VertexLayout};
+use crate::skeleton::{AnimationClip, AnimationPlayer, Joint, JointTrack, Keyframe, Skeleton};
+use crate::transform::Transform;
+use crate::Vertex;
+
+// GPU skinning
+//======================
+// Skinned meshes are deformed by a compute pass at the start of the frame, before anything draws:
+// every vertex of the bind pose is moved by up to four joint matrices, blended by its weights, and
+// written into the vertex buffer of an ordinary `Mesh`. The scene pass, materials and the passes
+// games add to the render graph then draw the posed mesh like any static one, with the same
+// shaders.
+//
+//     let (skin, mesh) = gfx.add_skinned_mesh("Arm", &vertices, &indices, 2);
+//     let arm = gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+//     ...
+//     gfx.set_joint_matrices(skin, &[shoulder, elbow]); // Every frame the pose changes.
+//
+// Joint matrices take a vertex from the bind pose to its posed position in model space, i.e. the
+// joint's current transform times the inverse of its bind transform. Skins whose joints didn't
+// change since the last frame are not skinned again. Adapters without compute shaders draw skinned
+// meshes in their bind pose.
+
+const WORKGROUP_SIZE: (u32, u32) = (64, 1);
+
+/// A vertex of a skinned mesh in its bind pose, with the joints that move it. The weights should
+/// add up to 1; unused joints get weight 0.
+#[derive(Clone, Copy, Debug)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+// `SkinnedVertex` in the layout of skinning.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuSkinnedVertex {
+    position: [f32; 4],
+    color: [f32; 4],
+    joints: [u32; 4],
+    weights: [f32; 4],
+}
+
+/// Identifies a skin registered with `GFX::add_skinned_mesh`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SkinId(usize);
+
+struct Skin {
+    label: String,
+    vertex_count: u32,
+    joint_count: usize,
+    joints: wgpu::Buffer,
+    bind_group: Option<wgpu::BindGroup>, // `None` without compute shaders.
+    // The joints changed since the mesh was last skinned.
+    dirty: bool,
+}
+
+/// The skinned meshes and the compute pass that poses them.
+pub struct Skinning {
+    enabled: bool, // The adapter has compute shaders.
+    kernel: Option<ComputeKernel>,
+    skins: Vec<Skin>,
+}
+
+impl Skinning {
+    pub fn new(enabled: bool) -> Skinning {
+        Skinning {
+            enabled,
+            kernel: None,
+            skins: Vec::new(),
+        }
+    }
+
+    // Uploads the bind pose, and returns the mesh the skinned vertices are written to. All joints
+    // start out as the identity, i.e. in the bind pose.
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        vertices: &[SkinnedVertex],
+        indices: &[u16],
+        joint_count: usize,
+    ) -> (SkinId, Mesh) {
+        let bind_pose: Vec<GpuSkinnedVertex> = vertices
+            .iter()
+            .map(|v| GpuSkinnedVertex {
+                position: [v.position[0], v.position[1], v.position[2], 1.0],
+                color: [v.color[0], v.color[1], v.color[2], 1.0],
+                // Out of range joints would read past the matrices.
+                joints: v.joints.map(|joint| joint.min(joint_count.max(1) as u32 - 1)),
+                weights: v.weights,
+            })
+            .collect();
+        let bind_pose_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Bind Pose Buffer", label)),
+            contents: bytemuck::cast_slice(&bind_pose),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let identity: Vec<[[f32; 4]; 4]> = vec![Matrix4::<f32>::identity().into(); joint_count.max(1)];
+        let joints = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Joint Buffer", label)),
+            contents: bytemuck::cast_slice(&identity),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        if !self.enabled {
+            tracing::warn!("Skin {}: no compute shaders, drawing the bind pose", label);
+            let vertices: Vec<Vertex> = vertices
+                .iter()
+                .map(|v| Vertex {
+                    position: v.position,
+                    color: v.color,
+                })
+                .collect();
+            self.skins.push(Skin {
+                label: label.to_string(),
+                vertex_count: vertices.len() as u32,
+                joint_count: joint_count.max(1),
+                joints,
+                bind_group: None,
+                dirty: false,
+            });
+            return (SkinId(self.skins.len() - 1), Mesh::new(device, label, &vertices, indices));
+        }
+
+        // Written by the compute pass, read as vertices by the render passes.
+        let output = Rc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} Vertex Buffer", label)),
+            size: (vertices.len().max(1) * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        }));
+
+        // The shader is part of the crate, so failing to build it is a bug.
+        let kernel = self.kernel.get_or_insert_with(|| {
+            ComputeKernel::new(device, "Skinning", include_str!("skinning.wgsl"), "main").expect("built-in kernel is valid")
+        });
+        let bind_group = kernel
+            .bind_group(
+                device,
+                0,
+                &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: bind_pose_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: joints.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: output.as_entire_binding(),
+                    },
+                ],
+            )
+            .expect("skinning bind group matches its shader");
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", label)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let mesh = Mesh {
+            vertex_buffer: output,
+            index_buffer: Rc::new(index_buffer),
+            num_indices: indices.len() as u32,
+            layout: VertexLayout::Full,
+            // The pose is only known on the GPU.
+            bounds: None,
+        };
+
+        self.skins.push(Skin {
+            label: label.to_string(),
+            vertex_count: vertices.len() as u32,
+            joint_count: joint_count.max(1),
+            joints,
+            bind_group: Some(bind_group),
+            dirty: true,
+        });
+        (SkinId(self.skins.len() - 1), mesh)
+    }
+
+    // Poses the skin for the next frames. Extra matrices are ignored, missing ones keep their value.
+    pub fn set_joint_matrices(&mut self, queue: &wgpu::Queue, id: SkinId, matrices: &[Matrix4<f32>]) {
+        let skin = &mut self.skins[id.0];
+        if matrices.len() > skin.joint_count {
+            tracing::warn!("Skin {}: {} joint matrices for {} joints", skin.label, matrices.len(), skin.joint_count);
+        }
+        let matrices: Vec<[[f32; 4]; 4]> = matrices.iter().take(skin.joint_count).map(|&m| m.into()).collect();
+        queue.write_buffer(&skin.joints, 0, bytemuck::cast_slice(&matrices));
+        skin.dirty = skin.bind_group.is_some();
+    }
+
+    // True if a skin needs to be posed this frame.
+    pub fn has_work(&self) -> bool {
+        self.skins.iter().any(|skin| skin.dirty)
+    }
+
+    // Records the compute passes that pose the skins whose joints changed.
+    pub fn run(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let kernel = match &self.kernel {
+            Some(kernel) => kernel,
+            None => return,
+        };
+        for skin in self.skins.iter_mut().filter(|skin| skin.dirty) {
+            let bind_group = match &skin.bind_group {
+                Some(bind_group) => bind_group,
+                None => continue,
+            };
+            encoder.push_debug_group(&skin.label);
+            kernel.dispatch_2d(encoder, &[bind_group], WORKGROUP_SIZE, skin.vertex_count, 1);
+            encoder.pop_debug_group();
+            skin.dirty = false;
+        }
+    }
+}
+
+// Skinning demo
+//======================
+
+// The arm's length, and how many rings of 4 vertices run along it.
+const ARM_LENGTH: f32 = 1.0;
+const ARM_RINGS: usize = 9;
+
+/// A square tube right of the pentagon, bending at its shoulder (the bottom) and its elbow (the
+/// middle), played from an animation clip. The vertices around the elbow blend both joints, so it
+/// bends smoothly. Its skeleton is drawn over it; the player's keys scrub the clip (see skeleton.rs),
+/// J highlights the next joint and I logs the pose of every joint.
+pub struct SkinnedArm {
+    skin: SkinId,
+    arm: RenderableId,
+    skeleton: Skeleton,
+    clip: AnimationClip,
+    player: AnimationPlayer,
+    highlight: Option<usize>,
+    inspect_requested: bool,
+}
+
+impl SkinnedArm {
+    pub fn new(gfx: &mut GFX) -> SkinnedArm {
+        let half_width = 0.06;
+        let corners = [[-1.0, -1.0], [-1.0, 1.0], [1.0, 1.0], [1.0, -1.0]]; // x and z, outwards counterclockwise.
+        let mut vertices = Vec::new();
+        for ring in 0..ARM_RINGS {
+            let along = ring as f32 / (ARM_RINGS - 1) as f32;
+            let y = along * ARM_LENGTH;
+            // The forearm's weight ramps up across the elbow.
+            let forearm = ((along - 0.4) / 0.2).clamp(0.0, 1.0);
+            for [x, z] in corners {
+                vertices.push(SkinnedVertex {
+                    position: [x * half_width, y, z * half_width],
+                    color: [0.9 - 0.5 * along, 0.5, 0.3 + 0.6 * along],
+                    joints: [0, 1, 0, 0],
+                    weights: [1.0 - forearm, forearm, 0.0, 0.0],
+                });
+            }
+        }
+        let mut indices = Vec::new();
+        for ring in 0..ARM_RINGS as u16 - 1 {
+            for corner in 0..4 {
+                let (a, b) = (ring * 4 + corner, ring * 4 + (corner + 1) % 4);
+                indices.extend_from_slice(&[a, b, b + 4, a, b + 4, a + 4]);
+            }
+        }
+        let material = gfx.default_material();
+        let (skin, mesh) = gfx.add_skinned_mesh("Arm", &vertices, &indices, 2);
+        let arm = gfx.add_renderable(mesh, material, RenderLayers::DEFAULT);
+        gfx.set_transform(arm, Transform::from_translation(Vector3::new(0.9, -0.5, 0.0)));
+        let (skeleton, clip) = arm_skeleton();
+        SkinnedArm {
+            skin,
+            arm,
+            skeleton,
+            clip,
+            player: AnimationPlayer::new(),
+            highlight: None,
+            inspect_requested: false,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.player.advance(dt, &self.clip);
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        let mut pose = self.skeleton.rest_pose();
+        self.clip.sample(self.player.time(), &mut pose);
+        gfx.set_joint_matrices(self.skin, &self.skeleton.joint_matrices(&pose));
+        let camera = gfx.main_camera();
+        gfx.draw_skeleton(self.arm, &self.skeleton, &pose, self.highlight, Some(camera));
+        if std::mem::take(&mut self.inspect_requested) {
+            tracing::info!("{} at {:.2}s:", self.clip.name, self.player.time());
+            for report in self.skeleton.inspect(&pose) {
+                tracing::info!("{}", report);
+            }
+        }
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        if self.player.on_event(event, &self.clip) {
+            return;
+        }
+        let count = self.skeleton.joints().len();
+        match *event {
+            Event::KeyPressed(key) if key == b'J' as u16 => {
+                self.highlight = match self.highlight {
+                    Some(joint) if joint + 1 < count => Some(joint + 1),
+                    Some(_) => None,
+                    None => (count > 0).then_some(0),
+                };
+            }
+            Event::KeyPressed(key) if key == b'I' as u16 => self.inspect_requested = true,
+            _ => {}
+        }
+    }
+}
+
+// The shoulder at the bottom of the arm and the elbow half way up, and a wave: the shoulder sways,
+// the elbow bends up to 90 degrees and back.
+fn arm_skeleton() -> (Skeleton, AnimationClip) {
+    let elbow = Vector3::new(0.0, ARM_LENGTH * 0.5, 0.0);
+    let skeleton = Skeleton::new(vec![
+        Joint {
+            name: "shoulder".to_string(),
+            parent: None,
+            rest: Transform::IDENTITY,
+            inverse_bind: Matrix4::identity(),
+        },
+        Joint {
+            name: "elbow".to_string(),
+            parent: Some(0),
+            rest: Transform::from_translation(elbow),
+            inverse_bind: Matrix4::from_translation(-elbow),
+        },
+    ]);
+    let keyframes = |angles: [f32; 5], translation: Vector3<f32>| {
+        (0..5)
+            .map(|i| Keyframe {
+                time: i as f32,
+                transform: Transform::from_translation(translation).with_rotation(Quaternion::from_angle_z(Rad(angles[i]))),
+            })
+            .collect()
+    };
+    let bend = std::f32::consts::FRAC_PI_4;
+    let clip = AnimationClip::new(
+        "Wave",
+        vec![
+            JointTrack {
+                joint: 0,
+                keyframes: keyframes([0.0, 0.4, 0.0, -0.4, 0.0], Vector3::new(0.0, 0.0, 0.0)),
+            },
+            JointTrack {
+                joint: 1,
+                keyframes: keyframes([0.0, bend, 2.0 * bend, bend, 0.0], elbow),
+            },
+        ],
+    );
+    (skeleton, clip)
+}
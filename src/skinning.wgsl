@@ -0,0 +1,53 @@
+// Deforms the bind pose of a skinned mesh by its joint matrices, into a vertex buffer.
+
+struct SkinnedVertex {
+    position: vec4<f32>;
+    color: vec4<f32>;
+    joints: vec4<u32>;
+    weights: vec4<f32>;
+};
+
+struct BindPose {
+    vertices: array<SkinnedVertex>;
+};
+
+struct Joints {
+    matrices: array<mat4x4<f32>>;
+};
+
+// The vertices in the layout of `Vertex`: position and color, 3 floats each.
+struct Output {
+    floats: array<f32>;
+};
+
+[[group(0), binding(0)]]
+var<storage, read> bind_pose: BindPose;
+[[group(0), binding(1)]]
+var<storage, read> joints: Joints;
+[[group(0), binding(2)]]
+var<storage, read_write> output: Output;
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= arrayLength(&bind_pose.vertices)) {
+        return;
+    }
+    let vertex = bind_pose.vertices[index];
+    let position = vec4<f32>(vertex.position.xyz, 1.0);
+
+    // The weights add up to 1, unused joints have weight 0.
+    var skinned = vec4<f32>(0.0);
+    for (var i = 0u; i < 4u; i = i + 1u) {
+        let joint = vertex.joints[i];
+        skinned = skinned + vertex.weights[i] * (joints.matrices[joint] * position);
+    }
+
+    let base = index * 6u;
+    output.floats[base] = skinned.x;
+    output.floats[base + 1u] = skinned.y;
+    output.floats[base + 2u] = skinned.z;
+    output.floats[base + 3u] = vertex.color.r;
+    output.floats[base + 4u] = vertex.color.g;
+    output.floats[base + 5u] = vertex.color.b;
+}
@@ -0,0 +1,433 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::light::DirectionalLight;
+use crate::reflection::ShaderReflection;
+use crate::texture::Texture;
+use crate::uniform::UniformLayout;
+
+// Atmospheric sky
+//======================
+// A sky lit by the sun instead of a clear color, from the analytic model of Preetham, Shirley and
+// Smits ("A practical analytic model for daylight"), drawn behind the scene by every camera that
+// sees `RenderLayers::DEFAULT` (sky.wgsl):
+//
+//     gfx.set_sky(Some(SkySettings::default().with_sun(Vector3::new(0.2, 0.3, -1.0))));
+//
+// The model fits the luminance and color of clear skies to the angle from the zenith and the angle
+// from the sun, for a sun at any height and a `turbidity` from 2, a clear mountain sky, to about
+// 10, hazy summer air. The sky brightens and whitens around the sun and towards the horizon, and
+// reddens as the sun sets. Its luminance is in thousands of candela per square meter, which
+// `exposure` scales to the scene's units. Below the horizon is the ground, in `ground_albedo` lit
+// by the sun and the sky.
+//
+// The sky also decides the directional light (light.rs): the sun's color is its light through the
+// atmosphere, attenuated by Rayleigh scattering and haze along the air mass towards it, so it turns
+// orange and dims as it sets. The ambient light is the color of the sky at the zenith. The clouds
+// (clouds.rs) are lit by the same sun. The model is only fitted for the sun above the horizon, so
+// the sky and the light fade out at dusk, as the sun sinks just below it, into `night_color`. By
+// night the moon lights the scene, in `moon_color`, with `night_ambient` from everywhere.
+// world_time.rs moves the sun and the moon over the day.
+//
+// The sky is drawn after the opaque renderables at the far plane, so only where none are; a camera
+// that keeps another's depth (`ClearSettings::KEEP`) draws it where neither drew anything.
+// Transparent renderables blended over the clear color before the sky is drawn don't show it.
+
+/// The atmosphere and the sun, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkySettings {
+    pub sun_direction: Vector3<f32>, // Towards the sun.
+    pub turbidity: f32, // From about 2, clear, to 10, hazy.
+    pub exposure: f32, // Scene units per thousand candela per square meter.
+    pub sun_intensity: f32, // Of the light, outside the atmosphere.
+    pub sun_disk_radius: f32, // Angular, in radians.
+    pub sun_disk_intensity: f32, // Of the disk, times the light's color.
+    pub ground_albedo: [f32; 3],
+    pub moon_direction: Vector3<f32>, // Towards the moon.
+    pub moon_color: [f32; 3], // Of its light.
+    pub moon_disk_radius: f32,
+    pub night_color: [f32; 3], // Of the sky.
+    pub night_ambient: [f32; 3],
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        SkySettings {
+            sun_direction: Vector3::new(0.3, 0.6, -0.7),
+            turbidity: 2.5,
+            exposure: 0.03,
+            sun_intensity: 1.0,
+            sun_disk_radius: 0.01,
+            sun_disk_intensity: 50.0,
+            ground_albedo: [0.25, 0.23, 0.2],
+            moon_direction: Vector3::new(-0.3, -0.6, 0.7),
+            moon_color: [0.06, 0.07, 0.1],
+            moon_disk_radius: 0.012,
+            night_color: [0.002, 0.003, 0.008],
+            night_ambient: [0.02, 0.025, 0.04],
+        }
+    }
+}
+
+impl SkySettings {
+    pub fn with_sun(mut self, direction: Vector3<f32>) -> SkySettings {
+        self.sun_direction = direction;
+        self
+    }
+
+    pub fn with_turbidity(mut self, turbidity: f32) -> SkySettings {
+        self.turbidity = turbidity;
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f32) -> SkySettings {
+        self.exposure = exposure;
+        self
+    }
+
+    // The sun's direction, normalized; straight up if it has no length.
+    pub fn sun(&self) -> Vector3<f32> {
+        if self.sun_direction.magnitude2() > 0.0 {
+            self.sun_direction.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+
+    pub fn with_moon(mut self, direction: Vector3<f32>) -> SkySettings {
+        self.moon_direction = direction;
+        self
+    }
+
+    // The moon's direction, normalized; straight down if it has no length.
+    pub fn moon(&self) -> Vector3<f32> {
+        if self.moon_direction.magnitude2() > 0.0 {
+            self.moon_direction.normalize()
+        } else {
+            -Vector3::unit_y()
+        }
+    }
+
+    // The directional light of the sky, see above: the sun's by day, the moon's by night, and both
+    // dimmed around dusk.
+    pub fn light(&self) -> DirectionalLight {
+        let model = SkyModel::new(self);
+        let transmittance = sun_transmittance(self.sun(), self.turbidity);
+        let day = model.twilight * self.sun_intensity;
+        let night = (1.0 - model.twilight) * smoothstep(-0.05, 0.1, self.moon().y);
+        let ambient = model.radiance(Vector3::unit_y());
+        DirectionalLight {
+            // The diffuse lighting has a single light.
+            direction: if model.twilight >= 0.5 { self.sun() } else { self.moon() },
+            color: [0, 1, 2].map(|i| transmittance[i] * day + self.moon_color[i] * night),
+            ambient: [0, 1, 2].map(|i| ambient[i] + self.night_ambient[i] * (1.0 - model.twilight)),
+        }
+    }
+
+    // The sky's linear color along `direction`, without the sun's and moon's disks. The CPU version of the
+    // shader, e.g. for a fog color matching the horizon.
+    pub fn radiance(&self, direction: Vector3<f32>) -> [f32; 3] {
+        SkyModel::new(self).radiance(direction)
+    }
+}
+
+// The Perez coefficients and zenith values of a sky, see sky.wgsl.
+struct SkyModel {
+    perez: [[f32; 3]; 5], // A to E, each for Y, x and y.
+    zenith: [f32; 3], // Divided by the Perez function at the zenith.
+    sun: Vector3<f32>,
+    twilight: f32, // 1 by day, fading to 0 as the sun sets.
+    exposure: f32,
+    ground: [f32; 3],
+    night: [f32; 3],
+}
+
+impl SkyModel {
+    fn new(settings: &SkySettings) -> SkyModel {
+        let t = settings.turbidity.clamp(1.7, 10.0);
+        let sun = settings.sun();
+        // The model is fitted for the sun above the horizon.
+        let theta_s = sun.y.clamp(0.0, 1.0).acos().min(std::f32::consts::FRAC_PI_2 - 0.01);
+        let perez = [
+            [0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608],
+            [-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092],
+            [-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102],
+            [0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537],
+            [-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529],
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let angles = [theta_s * theta_s * theta_s, theta_s * theta_s, theta_s, 1.0];
+        let dot4 = |a: [f32; 4]| a[0] * angles[0] + a[1] * angles[1] + a[2] * angles[2] + a[3] * angles[3];
+        let zenith_x = t * t * dot4([0.00166, -0.00375, 0.00209, 0.0])
+            + t * dot4([-0.02903, 0.06377, -0.03202, 0.00394])
+            + dot4([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y_chromaticity = t * t * dot4([0.00275, -0.00610, 0.00317, 0.0])
+            + t * dot4([-0.04214, 0.08970, -0.04153, 0.00516])
+            + dot4([0.15346, -0.26756, 0.06670, 0.26688]);
+        let zenith = [zenith_y, zenith_x, zenith_y_chromaticity];
+        let mut model = SkyModel {
+            perez,
+            zenith: [0.0; 3],
+            sun,
+            twilight: smoothstep(-0.1, 0.02, sun.y),
+            exposure: settings.exposure,
+            ground: [0.0; 3],
+            night: settings.night_color,
+        };
+        // Divided by the Perez function at the zenith, whose angle to the sun is theta_s.
+        let at_zenith = model.perez(1.0, theta_s, theta_s.cos());
+        model.zenith = [zenith[0] / at_zenith[0], zenith[1] / at_zenith[1], zenith[2] / at_zenith[2]];
+        // Lambertian ground, lit by the sun and the sky above it.
+        let transmittance = sun_transmittance(sun, settings.turbidity);
+        let ambient = model.sky(Vector3::unit_y());
+        let sun_light = sun.y.max(0.0) * model.twilight * settings.sun_intensity;
+        let albedo = settings.ground_albedo;
+        model.ground = [0, 1, 2].map(|i| albedo[i] * (transmittance[i] * sun_light + ambient[i]));
+        model
+    }
+
+    fn perez(&self, cos_theta: f32, gamma: f32, cos_gamma: f32) -> [f32; 3] {
+        let [a, b, c, d, e] = self.perez;
+        let f = |i: usize| (1.0 + a[i] * (b[i] / cos_theta).exp()) * (1.0 + c[i] * (d[i] * gamma).exp() + e[i] * cos_gamma * cos_gamma);
+        [f(0), f(1), f(2)]
+    }
+
+    // Above the horizon, as in the shader.
+    fn sky(&self, direction: Vector3<f32>) -> [f32; 3] {
+        let above = Vector3::new(direction.x, direction.y.max(0.0), direction.z);
+        let above = if above.magnitude2() > 0.0 { above.normalize() } else { Vector3::unit_y() };
+        let cos_theta = above.y.max(0.01);
+        let cos_gamma = above.dot(self.sun).clamp(-1.0, 1.0);
+        let f = self.perez(cos_theta, cos_gamma.acos(), cos_gamma);
+        let luminance = (self.zenith[0] * f[0]).max(0.0) * self.exposure * self.twilight;
+        let day = xyy_to_rgb(luminance, self.zenith[1] * f[1], self.zenith[2] * f[2]);
+        [0, 1, 2].map(|i| day[i] + self.night[i] * (1.0 - self.twilight))
+    }
+
+    fn radiance(&self, direction: Vector3<f32>) -> [f32; 3] {
+        let sky = self.sky(direction);
+        let ground = smoothstep(0.0, 0.02, -direction.y);
+        [
+            sky[0] + (self.ground[0] - sky[0]) * ground,
+            sky[1] + (self.ground[1] - sky[1]) * ground,
+            sky[2] + (self.ground[2] - sky[2]) * ground,
+        ]
+    }
+}
+
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn xyy_to_rgb(luminance: f32, x: f32, y: f32) -> [f32; 3] {
+    let y = y.max(0.0001);
+    let (cx, cy, cz) = (x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    [
+        (3.2406 * cx - 1.5372 * cy - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * cy + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * cy + 1.0570 * cz).max(0.0),
+    ]
+}
+
+// How much of the sun's red, green and blue light gets through the atmosphere towards `sun`:
+// Rayleigh scattering and aerosols by Ångström's formula, along the relative air mass of Kasten.
+fn sun_transmittance(sun: Vector3<f32>, turbidity: f32) -> [f32; 3] {
+    let zenith_angle = sun.y.clamp(-1.0, 1.0).acos().to_degrees().min(93.0);
+    let air_mass = 1.0 / (zenith_angle.to_radians().cos() + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    // Wavelengths in micrometers.
+    [0.68f32, 0.55, 0.44].map(|wavelength| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    })
+}
+
+uniform_struct! {
+    struct SkyParams {
+        inverse_view_proj: Matrix4<f32>,
+        perez_a: Vector4<f32>,
+        perez_b: Vector4<f32>,
+        perez_c: Vector4<f32>,
+        perez_d: Vector4<f32>,
+        perez_e: Vector4<f32>,
+        zenith: Vector4<f32>,
+        sun_direction: Vector4<f32>,
+        sun_color: Vector4<f32>,
+        ground_color: Vector4<f32>,
+        moon_direction: Vector4<f32>,
+        moon_color: Vector4<f32>,
+        night_color: Vector4<f32>,
+    }
+}
+assert_uniform_size!(SkyParams, 256);
+
+/// Draws the sky for every camera, see above.
+pub struct Sky {
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    params_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+    params_stride: u32,
+    params_capacity: usize, // In cameras.
+}
+
+impl Sky {
+    // `camera_layout` is that of the camera bind groups, which the sky is drawn with.
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, samples: u32) -> Sky {
+        let wgsl = include_str!("sky.wgsl");
+        let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+        // Every camera has its own part of the parameters' buffer.
+        let mut entries = reflection.layout_entries(1);
+        for entry in &mut entries {
+            if let wgpu::BindingType::Buffer { has_dynamic_offset, .. } = &mut entry.ty {
+                *has_dynamic_offset = true;
+            }
+        }
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sky Bind Group Layout"),
+            entries: &entries,
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Sky Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, &shader, format, samples);
+        let params_stride = device.limits().min_uniform_buffer_offset_alignment.max(SkyParams::SIZE as u32);
+        let params_capacity = 4;
+        let params_buffer = create_params_buffer(device, params_stride, params_capacity);
+        let params_bind_group = create_params_bind_group(device, &params_layout, &params_buffer);
+        Sky {
+            pipeline,
+            pipeline_layout,
+            shader,
+            params_layout,
+            params_buffer,
+            params_bind_group,
+            params_stride,
+            params_capacity,
+        }
+    }
+
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, format, samples);
+    }
+
+    // Uploads the sky's parameters for every camera, by its view projection matrix.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, settings: &SkySettings, view_projs: &[Matrix4<f32>]) {
+        if view_projs.len() > self.params_capacity {
+            self.params_capacity = view_projs.len().next_power_of_two();
+            self.params_buffer = create_params_buffer(device, self.params_stride, self.params_capacity);
+            self.params_bind_group = create_params_bind_group(device, &self.params_layout, &self.params_buffer);
+        }
+        let model = SkyModel::new(settings);
+        let transmittance = sun_transmittance(model.sun, settings.turbidity);
+        let coefficient = |i: usize| Vector4::new(model.perez[i][0], model.perez[i][1], model.perez[i][2], 0.0);
+        let disk = settings.sun_disk_intensity * settings.sun_intensity;
+        // The moon's disk shows through the day sky, and glows like its light at night.
+        let moon = settings.moon_color;
+        let moon_disk = settings.sun_disk_intensity * 0.5;
+        for (index, view_proj) in view_projs.iter().enumerate() {
+            let params = SkyParams {
+                inverse_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity),
+                perez_a: coefficient(0),
+                perez_b: coefficient(1),
+                perez_c: coefficient(2),
+                perez_d: coefficient(3),
+                perez_e: coefficient(4),
+                zenith: Vector4::new(model.zenith[0], model.zenith[1], model.zenith[2], model.exposure * model.twilight),
+                sun_direction: model.sun.extend(settings.sun_disk_radius.cos()),
+                sun_color: Vector4::new(transmittance[0] * disk, transmittance[1] * disk, transmittance[2] * disk, 0.0),
+                ground_color: Vector4::new(model.ground[0], model.ground[1], model.ground[2], 0.0),
+                moon_direction: settings.moon().extend(settings.moon_disk_radius.cos()),
+                moon_color: Vector4::new(moon[0] * moon_disk, moon[1] * moon_disk, moon[2] * moon_disk, 0.0),
+                night_color: Vector4::new(model.night[0], model.night[1], model.night[2], 1.0 - model.twilight),
+            };
+            let offset = index as u64 * self.params_stride as u64;
+            queue.write_buffer(&self.params_buffer, offset, &params.to_uniform_bytes());
+        }
+    }
+
+    // Draws the sky for camera `index`, into a scene pass with the camera's bind group set.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) {
+        if index >= self.params_capacity {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.params_bind_group, &[index as u32 * self.params_stride]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Sky Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // At the far plane, where the depth was cleared to: only where nothing was drawn.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+fn create_params_buffer(device: &wgpu::Device, stride: u32, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Sky Params"),
+        size: stride as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_params_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Sky Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(SkyParams::SIZE as u64),
+            }),
+        }],
+    })
+}
@@ -0,0 +1,96 @@
+// The Preetham sky, see sky.rs, whose CPU version of `sky_radiance` must stay in step. A triangle
+// at the far plane covers the viewport, and every pixel looks up the sky along its ray.
+
+struct SkyParams {
+    inverse_view_proj: mat4x4<f32>;
+    // The Perez coefficients A to E of the luminance Y and the chromaticities x and y, in xyz.
+    perez_a: vec4<f32>;
+    perez_b: vec4<f32>;
+    perez_c: vec4<f32>;
+    perez_d: vec4<f32>;
+    perez_e: vec4<f32>;
+    zenith: vec4<f32>; // Y, x and y at the zenith, divided by the Perez function there; w scales Y.
+    sun_direction: vec4<f32>; // Towards the sun; w is the cosine of the disk's angular radius.
+    sun_color: vec4<f32>; // Of the disk.
+    ground_color: vec4<f32>;
+    moon_direction: vec4<f32>; // Towards the moon; w is the cosine of the disk's angular radius.
+    moon_color: vec4<f32>; // Of the disk.
+    night_color: vec4<f32>; // w is how far the night has fallen, from 0 to 1.
+};
+[[group(1), binding(0)]]
+var<uniform> params: SkyParams;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    var out: VertexOutput;
+    // At the far plane, so it is only drawn where nothing else is.
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = params.inverse_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// The Perez function of Y, x and y, at an angle with cosine `cos_theta` from the zenith and
+// `gamma` from the sun.
+fn perez(cos_theta: f32, gamma: f32, cos_gamma: f32) -> vec3<f32> {
+    let a = params.perez_a.xyz;
+    let b = params.perez_b.xyz;
+    let c = params.perez_c.xyz;
+    let d = params.perez_d.xyz;
+    let e = params.perez_e.xyz;
+    return (1.0 + a * exp(b / cos_theta)) * (1.0 + c * exp(d * gamma) + e * cos_gamma * cos_gamma);
+}
+
+// Linear sRGB, from the luminance and chromaticities.
+fn xyy_to_rgb(yxy: vec3<f32>) -> vec3<f32> {
+    let luminance = yxy.x;
+    let x = yxy.y;
+    let y = max(yxy.z, 0.0001);
+    let xyz = vec3<f32>(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    let rgb = vec3<f32>(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    );
+    return max(rgb, vec3<f32>(0.0));
+}
+
+fn sky_radiance(direction: vec3<f32>) -> vec3<f32> {
+    // Below the horizon, the sky at the horizon, which the ground covers.
+    let above = normalize(vec3<f32>(direction.x, max(direction.y, 0.0), direction.z));
+    let cos_theta = max(above.y, 0.01);
+    let cos_gamma = clamp(dot(above, params.sun_direction.xyz), -1.0, 1.0);
+    let yxy = params.zenith.xyz * perez(cos_theta, acos(cos_gamma), cos_gamma);
+    let day = xyy_to_rgb(vec3<f32>(max(yxy.x, 0.0) * params.zenith.w, yxy.y, yxy.z));
+    let sky = day + params.night_color.rgb * params.night_color.w;
+    return mix(sky, params.ground_color.rgb, smoothStep(0.0, 0.02, -direction.y));
+}
+
+// 1 within the disk around `center.xyz`, whose angular radius has the cosine `center.w`, with its
+// edge smoothed over a little of the radius.
+fn disk(direction: vec3<f32>, center: vec4<f32>) -> f32 {
+    let edge = (1.0 - center.w) * 0.2;
+    return smoothStep(center.w - edge, center.w + edge, dot(direction, center.xyz));
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let direction = normalize(unproject(in.ndc, 1.0) - unproject(in.ndc, 0.0));
+    var color = sky_radiance(direction);
+    // The sun's and moon's disks, above the horizon.
+    let above = step(0.0, direction.y);
+    color = color + params.sun_color.rgb * disk(direction, params.sun_direction) * above;
+    color = color + params.moon_color.rgb * disk(direction, params.moon_direction) * above;
+    return vec4<f32>(color, 1.0);
+}
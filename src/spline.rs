@@ -0,0 +1,483 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rotation, Vector3};
+
+use crate::debug_draw::DebugDraw;
+use crate::game::Event;
+use crate::gfx::{RenderableId, GFX};
+use crate::layers::RenderLayers;
+use crate::transform::Transform;
+
+// Splines
+//======================
+// Smooth curves through or near a list of points, for camera paths, objects following tracks,
+// roads or ropes. Every kind is stored as cubic Bezier segments, which are cheap to evaluate and
+// easy to differentiate:
+//
+//  - Bezier: the points are the curve's own controls, 3 per segment plus 1. The curve goes
+//    through every third point and is pulled towards the others.
+//  - Catmull-Rom: the curve goes through every point, the tangents taken from the neighbours.
+//    The easiest to author, e.g. from positions clicked in the scene.
+//  - Hermite: the curve goes through every point with the tangent given for it.
+//
+// A spline's parameter `t` runs from 0 at the start to the number of segments at the end, with
+// whole numbers at the joins. Its speed along the curve is not constant: segments of different
+// lengths get the same range of `t`, and Bezier controls bunch the points up. For something moving
+// at a constant speed, go by distance instead, with `t_at_distance`, which looks the parameter up in
+// a table of arc lengths built with the spline.
+//
+// `frame` gives an orientation along the curve, for cameras and objects that follow it. Frenet
+// frames flip around at inflections and are undefined on straight parts, so `frames` instead
+// carries one frame along the curve, turning it only as much as the tangent turns.
+
+// Samples per segment of the arc length table, and of the curves drawn by `debug_draw`.
+const LENGTH_SAMPLES: usize = 32;
+const DRAW_SEGMENTS: usize = 16;
+
+const CONTROL_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const FRAME_COLORS: [[f32; 4]; 3] = [[0.9, 0.2, 0.2, 1.0], [0.3, 0.85, 0.3, 1.0], [0.25, 0.45, 1.0, 1.0]];
+
+/// A cubic Bezier segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CubicSegment {
+    pub points: [Point3<f32>; 4], // The ends are the first and last, the others pull the curve.
+}
+
+impl CubicSegment {
+    // From `p0` to `p1`, leaving with the tangent `m0` and arriving with `m1`.
+    pub fn hermite(p0: Point3<f32>, m0: Vector3<f32>, p1: Point3<f32>, m1: Vector3<f32>) -> CubicSegment {
+        CubicSegment {
+            points: [p0, p0 + m0 / 3.0, p1 - m1 / 3.0, p1],
+        }
+    }
+
+    pub fn position(&self, t: f32) -> Point3<f32> {
+        let [p0, p1, p2, p3] = self.points;
+        let s = 1.0 - t;
+        let weights = [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t];
+        Point3::from_vec(p0.to_vec() * weights[0] + p1.to_vec() * weights[1] + p2.to_vec() * weights[2] + p3.to_vec() * weights[3])
+    }
+
+    // The velocity along the curve, per unit of `t`.
+    pub fn derivative(&self, t: f32) -> Vector3<f32> {
+        let [p0, p1, p2, p3] = self.points;
+        let s = 1.0 - t;
+        (p1 - p0) * (3.0 * s * s) + (p2 - p1) * (6.0 * s * t) + (p3 - p2) * (3.0 * t * t)
+    }
+}
+
+/// An orientation along a spline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub position: Point3<f32>,
+    pub tangent: Vector3<f32>, // Forward, along the curve.
+    pub normal: Vector3<f32>, // Up.
+    pub binormal: Vector3<f32>, // Right, tangent x normal.
+}
+
+impl Frame {
+    // The rotation taking -z to the tangent and +y to the normal, like a camera looking along
+    // the curve.
+    pub fn rotation(&self) -> Quaternion<f32> {
+        Quaternion::look_at(-self.tangent, self.normal).invert()
+    }
+}
+
+/// A curve made of cubic segments, see above.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spline {
+    segments: Vec<CubicSegment>,
+    // The distance along the curve at every sample of every segment, starting with 0.
+    lengths: Vec<f32>,
+}
+
+impl Spline {
+    pub fn from_segments(segments: Vec<CubicSegment>) -> Spline {
+        let mut lengths = vec![0.0];
+        let mut total = 0.0;
+        for segment in &segments {
+            let mut previous = segment.position(0.0);
+            for i in 1..=LENGTH_SAMPLES {
+                let position = segment.position(i as f32 / LENGTH_SAMPLES as f32);
+                total += (position - previous).magnitude();
+                lengths.push(total);
+                previous = position;
+            }
+        }
+        Spline { segments, lengths }
+    }
+
+    // Controls of consecutive segments, which share their ends: 4, 7, 10... points. Points left
+    // over after the last full segment are ignored.
+    pub fn bezier(points: &[Point3<f32>]) -> Spline {
+        let segments = points
+            .windows(4)
+            .step_by(3)
+            .map(|window| CubicSegment {
+                points: [window[0], window[1], window[2], window[3]],
+            })
+            .collect();
+        Spline::from_segments(segments)
+    }
+
+    // Through every point. A `closed` spline also goes from the last point back to the first.
+    pub fn catmull_rom(points: &[Point3<f32>], closed: bool) -> Spline {
+        let n = points.len();
+        if n < 2 {
+            return Spline::from_segments(Vec::new());
+        }
+        // Open ends mirror their neighbour, so the curve leaves and arrives straight.
+        let point = |i: isize| -> Point3<f32> {
+            if closed {
+                points[i.rem_euclid(n as isize) as usize]
+            } else if i < 0 {
+                points[0] + (points[0] - points[1])
+            } else if i >= n as isize {
+                points[n - 1] + (points[n - 1] - points[n - 2])
+            } else {
+                points[i as usize]
+            }
+        };
+        let count = if closed { n } else { n - 1 };
+        let segments = (0..count as isize)
+            .map(|i| {
+                let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+                CubicSegment::hermite(p1, (p2 - p0) * 0.5, p2, (p3 - p1) * 0.5)
+            })
+            .collect();
+        Spline::from_segments(segments)
+    }
+
+    // Through every point, with its tangent. `tangents` is as long as `points`.
+    pub fn hermite(points: &[Point3<f32>], tangents: &[Vector3<f32>]) -> Spline {
+        assert_eq!(points.len(), tangents.len(), "a Hermite spline needs a tangent per point");
+        let segments = (1..points.len())
+            .map(|i| CubicSegment::hermite(points[i - 1], tangents[i - 1], points[i], tangents[i]))
+            .collect();
+        Spline::from_segments(segments)
+    }
+
+    // The largest `t`.
+    pub fn end(&self) -> f32 {
+        self.segments.len() as f32
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.lengths.last().expect("starts with 0")
+    }
+
+    pub fn position(&self, t: f32) -> Point3<f32> {
+        match self.locate(t) {
+            Some((segment, t)) => segment.position(t),
+            None => Point3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // The velocity per unit of `t`, whose length is the curve's speed there.
+    pub fn derivative(&self, t: f32) -> Vector3<f32> {
+        match self.locate(t) {
+            Some((segment, t)) => segment.derivative(t),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn tangent(&self, t: f32) -> Vector3<f32> {
+        let derivative = self.derivative(t);
+        if derivative.magnitude2() > 0.0 {
+            derivative.normalize()
+        } else {
+            // Where controls coincide the curve stops for an instant; look a little further on.
+            let next = self.derivative((t + 1e-3).min(self.end()));
+            if next.magnitude2() > 0.0 {
+                next.normalize()
+            } else {
+                Vector3::unit_z()
+            }
+        }
+    }
+
+    // The `t` at `distance` along the curve, clamped to its ends.
+    pub fn t_at_distance(&self, distance: f32) -> f32 {
+        if self.segments.is_empty() {
+            return 0.0;
+        }
+        let distance = distance.clamp(0.0, self.length());
+        let i = self.lengths.partition_point(|&length| length < distance).clamp(1, self.lengths.len() - 1);
+        let (a, b) = (self.lengths[i - 1], self.lengths[i]);
+        let fraction = if b > a { (distance - a) / (b - a) } else { 0.0 };
+        ((i - 1) as f32 + fraction) / LENGTH_SAMPLES as f32
+    }
+
+    // An orientation at `t`, the normal as close to `up` as the tangent allows. Simple, but it
+    // spins around when the curve passes straight up or down; `frames` doesn't.
+    pub fn frame(&self, t: f32, up: Vector3<f32>) -> Frame {
+        let tangent = self.tangent(t);
+        let mut binormal = tangent.cross(up);
+        if binormal.magnitude2() < 1e-8 {
+            binormal = tangent.cross(if up.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() });
+        }
+        let binormal = binormal.normalize();
+        Frame {
+            position: self.position(t),
+            tangent,
+            normal: binormal.cross(tangent),
+            binormal,
+        }
+    }
+
+    // `count` frames evenly spaced by distance, from start to end, the first with its normal
+    // towards `up` and each next one turned as little as possible from the one before.
+    pub fn frames(&self, count: usize, up: Vector3<f32>) -> Vec<Frame> {
+        let mut frames: Vec<Frame> = Vec::with_capacity(count);
+        for i in 0..count {
+            let distance = self.length() * i as f32 / (count.max(2) - 1) as f32;
+            let t = self.t_at_distance(distance);
+            let frame = match frames.last() {
+                None => self.frame(t, up),
+                Some(previous) => {
+                    // Rotate the previous frame by the rotation between the two tangents.
+                    let tangent = self.tangent(t);
+                    let turn = Quaternion::from_arc(previous.tangent, tangent, None);
+                    let normal = (turn * previous.normal).normalize();
+                    Frame {
+                        position: self.position(t),
+                        tangent,
+                        normal,
+                        binormal: tangent.cross(normal).normalize(),
+                    }
+                }
+            };
+            frames.push(frame);
+        }
+        frames
+    }
+
+    // Queues the curve as lines, and with `controls` the lines between the Bezier controls of every
+    // segment, which show where the tangents pull it.
+    pub fn debug_draw(&self, debug_draw: &mut DebugDraw, color: [f32; 4], controls: bool) {
+        for segment in &self.segments {
+            let mut previous = segment.points[0];
+            for i in 1..=DRAW_SEGMENTS {
+                let position = segment.position(i as f32 / DRAW_SEGMENTS as f32);
+                debug_draw.line(previous, position, color);
+                previous = position;
+            }
+            if controls {
+                let [p0, p1, p2, p3] = segment.points;
+                debug_draw.line(p0, p1, CONTROL_COLOR);
+                debug_draw.line(p2, p3, CONTROL_COLOR);
+            }
+        }
+    }
+
+    // Queues the axes of `frames`, `size` long: tangent in blue, normal in green, binormal in red.
+    pub fn debug_draw_frames(debug_draw: &mut DebugDraw, frames: &[Frame], size: f32) {
+        for frame in frames {
+            let axes = [frame.binormal, frame.normal, frame.tangent];
+            for (axis, color) in axes.into_iter().zip(FRAME_COLORS) {
+                debug_draw.line(frame.position, frame.position + axis * size, color);
+            }
+        }
+    }
+
+    // The segment `t` falls in, and `t` within it.
+    fn locate(&self, t: f32) -> Option<(&CubicSegment, f32)> {
+        let last = self.segments.len().checked_sub(1)?;
+        let t = t.clamp(0.0, self.end());
+        let i = (t.floor() as usize).min(last);
+        Some((&self.segments[i], t - i as f32))
+    }
+}
+
+// Track demo
+//======================
+
+const TRACK_POINTS: usize = 6;
+const TRACK_SPEED: f32 = 0.8; // Units per second.
+
+/// The kinds of spline the track demo cycles through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TrackKind {
+    CatmullRom,
+    Hermite,
+    Bezier,
+}
+
+// A cube running around the pentagon at a constant speed, along a wavy loop through six points,
+// turned with the curve. The curve, its controls and a few frames along it are drawn with the debug
+// lines. K switches between the kinds of spline made from the same points: through them with
+// Catmull-Rom or with tangents around the loop (Hermite), or near them, with every other point a
+// Bezier control.
+pub struct TrackDemo {
+    cube: RenderableId,
+    kind: TrackKind,
+    spline: Spline,
+    frames: Vec<Frame>,
+    distance: f32,
+    switch_requested: bool,
+}
+
+impl TrackDemo {
+    pub fn new(gfx: &mut GFX) -> TrackDemo {
+        let cube = gfx.unit_cube();
+        let material = gfx.default_material();
+        let cube = gfx.add_renderable(cube, material, RenderLayers::DEFAULT);
+        let kind = TrackKind::CatmullRom;
+        let spline = track(kind);
+        TrackDemo {
+            cube,
+            kind,
+            frames: spline.frames(24, Vector3::unit_y()),
+            spline,
+            distance: 0.0,
+            switch_requested: false,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.distance = (self.distance + TRACK_SPEED * dt) % self.spline.length().max(f32::EPSILON);
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        if std::mem::take(&mut self.switch_requested) {
+            self.kind = match self.kind {
+                TrackKind::CatmullRom => TrackKind::Hermite,
+                TrackKind::Hermite => TrackKind::Bezier,
+                TrackKind::Bezier => TrackKind::CatmullRom,
+            };
+            tracing::info!("Track: {:?}", self.kind);
+            self.spline = track(self.kind);
+            self.frames = self.spline.frames(24, Vector3::unit_y());
+            self.distance = 0.0;
+        }
+        let frame = self.spline.frame(self.spline.t_at_distance(self.distance), Vector3::unit_y());
+        let transform = Transform::from_translation(frame.position.to_vec())
+            .with_rotation(frame.rotation())
+            .with_scale(Vector3::new(0.08, 0.08, 0.16));
+        gfx.set_transform(self.cube, transform);
+        let debug_draw = gfx.debug_draw();
+        self.spline.debug_draw(debug_draw, [1.0, 0.8, 0.2, 1.0], true);
+        Spline::debug_draw_frames(debug_draw, &self.frames, 0.1);
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        if *event == Event::KeyPressed(b'K' as u16) {
+            self.switch_requested = true;
+        }
+    }
+}
+
+// Around the pentagon, bobbing up and down. Bezier controls and Hermite tangents repeat the first
+// point at the end to close the loop.
+fn track(kind: TrackKind) -> Spline {
+    let points: Vec<Point3<f32>> = (0..TRACK_POINTS)
+        .map(|i| {
+            let angle = i as f32 / TRACK_POINTS as f32 * std::f32::consts::TAU;
+            let radius = if i % 2 == 0 { 1.0 } else { 0.75 };
+            Point3::new(angle.cos() * radius, 0.25 * (2.0 * angle).sin(), angle.sin() * radius)
+        })
+        .collect();
+    let mut closed = points.clone();
+    closed.push(points[0]);
+    match kind {
+        TrackKind::CatmullRom => Spline::catmull_rom(&points, true),
+        TrackKind::Hermite => {
+            let tangents: Vec<Vector3<f32>> = closed.iter().map(|p| Vector3::new(-p.z, 0.0, p.x) * 1.5).collect();
+            Spline::hermite(&closed, &tangents)
+        }
+        TrackKind::Bezier => Spline::bezier(&closed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Vec<Point3<f32>> {
+        vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn assert_near(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_its_points() {
+        let points = square();
+        let open = Spline::catmull_rom(&points, false);
+        assert_eq!(open.end(), 3.0);
+        for (i, point) in points.iter().enumerate() {
+            assert_near(open.position(i as f32), *point);
+        }
+        let closed = Spline::catmull_rom(&points, true);
+        assert_eq!(closed.end(), 4.0);
+        for (i, point) in points.iter().enumerate() {
+            assert_near(closed.position(i as f32), *point);
+        }
+        // And back to the first.
+        assert_near(closed.position(4.0), points[0]);
+        assert!(Spline::catmull_rom(&points[..1], false).end() == 0.0);
+    }
+
+    #[test]
+    fn t_at_distance_goes_from_end_to_end_and_never_back() {
+        let spline = Spline::catmull_rom(&square(), false);
+        assert_eq!(spline.t_at_distance(0.0), 0.0);
+        assert_eq!(spline.t_at_distance(-1.0), 0.0);
+        assert!((spline.t_at_distance(spline.length()) - spline.end()).abs() < 1e-4);
+        assert!((spline.t_at_distance(spline.length() + 1.0) - spline.end()).abs() < 1e-4);
+        let mut previous = 0.0;
+        for i in 1..=100 {
+            let t = spline.t_at_distance(spline.length() * i as f32 / 100.0);
+            assert!(t >= previous, "{} after {}", t, previous);
+            previous = t;
+        }
+        // Halfway along is halfway through the middle segment, by symmetry.
+        assert_near(spline.position(spline.t_at_distance(spline.length() / 2.0)), spline.position(1.5));
+    }
+
+    #[test]
+    fn hermite_leaves_and_arrives_with_its_tangents() {
+        let points = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)];
+        let tangents = [Vector3::new(0.0, 2.0, 0.0), Vector3::new(0.0, -2.0, 0.0)];
+        let spline = Spline::hermite(&points, &tangents);
+        assert_near(spline.position(1.0), points[1]);
+        assert!((spline.derivative(0.0) - tangents[0]).magnitude() < 1e-4);
+        assert!((spline.derivative(1.0) - tangents[1]).magnitude() < 1e-4);
+    }
+
+    #[test]
+    #[should_panic(expected = "a tangent per point")]
+    fn hermite_needs_a_tangent_per_point() {
+        Spline::hermite(&square(), &[Vector3::unit_x()]);
+    }
+
+    #[test]
+    fn bezier_goes_through_every_third_point() {
+        let mut points = square();
+        points.extend([Point3::new(0.0, 2.0, 0.0), Point3::new(0.0, 3.0, 0.0), Point3::new(2.0, 3.0, 0.0), Point3::new(9.0, 9.0, 0.0)]);
+        let spline = Spline::bezier(&points);
+        // The last point doesn't make a segment.
+        assert_eq!(spline.end(), 2.0);
+        assert_near(spline.position(1.0), points[3]);
+        assert_near(spline.position(2.0), points[6]);
+    }
+
+    #[test]
+    fn frames_turn_with_the_curve_without_flipping() {
+        let spline = Spline::catmull_rom(&square(), false);
+        let frames = spline.frames(20, Vector3::unit_z());
+        for pair in frames.windows(2) {
+            assert!(pair[0].normal.dot(pair[1].normal) > 0.9);
+        }
+        for frame in &frames {
+            assert!(frame.tangent.dot(frame.normal).abs() < 1e-4);
+            assert!((frame.tangent.cross(frame.normal) - frame.binormal).magnitude() < 1e-4);
+            // The rotation looks along the tangent.
+            assert!((frame.rotation() * -Vector3::unit_z() - frame.tangent).magnitude() < 1e-3);
+        }
+    }
+}
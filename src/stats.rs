@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+
+// Frame statistics
+//======================
+// `App` records the duration and the draw counts of every frame. The summary over the last
+// `HISTORY` frames is shown by the stats overlay (toggled with F3), and can be read through
+// `Frame::stats` for benchmarks.
+
+/// Number of frames the statistics are computed over.
+const HISTORY: usize = 240;
+
+/// What `GFX::render` submitted in one frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderCounters {
+    pub draw_calls: u32,
+    pub triangles: u32,
+    pub instance_uploads: u32, // Model matrices that changed and were uploaded, see transform.rs.
+}
+
+#[derive(Default)]
+pub struct FrameStats {
+    frame_times: VecDeque<f32>, // In seconds, oldest first.
+    last: RenderCounters,
+}
+
+impl FrameStats {
+    pub fn new() -> FrameStats {
+        FrameStats::default()
+    }
+
+    pub fn record(&mut self, frame_time: f32, counters: RenderCounters) {
+        if self.frame_times.len() == HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        self.last = counters;
+    }
+
+    // Frame times in milliseconds, zero before the first frame.
+    pub fn min_ms(&self) -> f32 {
+        self.frame_times.iter().copied().reduce(f32::min).unwrap_or(0.0) * 1000.0
+    }
+
+    pub fn max_ms(&self) -> f32 {
+        self.frame_times.iter().copied().reduce(f32::max).unwrap_or(0.0) * 1000.0
+    }
+
+    pub fn avg_ms(&self) -> f32 {
+        let sum: f32 = self.frame_times.iter().sum();
+        sum / self.frame_times.len().max(1) as f32 * 1000.0
+    }
+
+    // 99% of the recent frames were at least this fast.
+    pub fn p99_ms(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let mut sorted: Vec<f32> = self.frame_times.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        let index = ((sorted.len() as f32 * 0.99).ceil() as usize).clamp(1, sorted.len()) - 1;
+        sorted[index] * 1000.0
+    }
+
+    pub fn fps(&self) -> f32 {
+        let avg = self.avg_ms();
+        if avg > 0.0 {
+            1000.0 / avg
+        } else {
+            0.0
+        }
+    }
+
+    // Draw calls and triangles of the last frame.
+    pub fn counters(&self) -> RenderCounters {
+        self.last
+    }
+
+    // The text shown by the overlay.
+    pub fn summary(&self) -> String {
+        format!(
+            "FPS {:.0}\nFRAME {:.2} MS (MIN {:.2} MAX {:.2} P99 {:.2})\nDRAWS {} TRIS {} MATRICES {}",
+            self.fps(),
+            self.avg_ms(),
+            self.min_ms(),
+            self.max_ms(),
+            self.p99_ms(),
+            self.last.draw_calls,
+            self.last.triangles,
+            self.last.instance_uploads,
+        )
+    }
+}
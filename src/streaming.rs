@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::num::{NonZeroU32, NonZeroU8};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::device_cache::DeviceCache;
+use crate::jobs;
+use crate::texture::Texture;
+
+// Texture streaming
+//======================
+// Loading every texture up front keeps a large scene on a loading screen.
+// Instead, textures are requested by path and arrive in the background:
+//  1. jobs on the job system decode the image and build its mip chain on the CPU,
+//  2. each frame `update` uploads a limited number of bytes, always picking the smallest
+//     pending mip level of any texture, so the whole scene gets a blurry version first (mip tail first),
+//  3. after each upload the texture is re-viewed from its largest uploaded level down,
+//  4. when the textures on the GPU exceed the VRAM budget, the least recently used ones are evicted.
+//     Requesting an evicted texture again streams it back in.
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Identifies a texture requested with `TextureStreamer::request`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StreamedTextureId(usize);
+
+// One level of a decoded mip chain, as tightly packed RGBA8 pixels.
+struct MipLevel {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+// Sent back by the decoding jobs. `mips[0]` is the full resolution image.
+struct Decoded {
+    id: StreamedTextureId,
+    mips: Result<Vec<MipLevel>, String>,
+}
+
+enum State {
+    // Waiting for, or being decoded by, a job.
+    Decoding,
+    // On the GPU, with `pending` mip levels still to be uploaded (largest level first).
+    Resident {
+        texture: Rc<wgpu::Texture>,
+        bytes: u64,
+        pending: Vec<MipLevel>,
+        // The view over the uploaded levels, `None` until the first upload.
+        current: Option<Rc<Texture>>,
+    },
+    Evicted,
+    Failed,
+}
+
+struct Entry {
+    path: PathBuf,
+    state: State,
+    last_used: u64,
+}
+
+/// Streams textures from disk, see above.
+pub struct TextureStreamer {
+    decoded_tx: Sender<Decoded>,
+    decoded: Receiver<Decoded>,
+    entries: Vec<Entry>,
+    by_path: HashMap<PathBuf, StreamedTextureId>,
+    frame: u64,
+    resident_bytes: u64,
+    /// Upper bound for the GPU memory of streamed textures, in bytes.
+    pub vram_budget: u64,
+    /// Maximum number of bytes uploaded per `update`. At least one mip level is always uploaded.
+    pub upload_bytes_per_frame: u64,
+    anisotropy: Option<NonZeroU8>, // Of the samplers, see capabilities.rs.
+    max_texture_size: u32, // Width and height of the largest level uploaded, see capabilities.rs.
+    cache: Rc<DeviceCache>, // The sampler is shared by all streamed textures.
+}
+
+impl TextureStreamer {
+    pub fn new(vram_budget: u64, cache: Rc<DeviceCache>) -> TextureStreamer {
+        let (decoded_tx, decoded) = channel();
+        TextureStreamer {
+            decoded_tx,
+            decoded,
+            entries: Vec::new(),
+            by_path: HashMap::new(),
+            frame: 0,
+            resident_bytes: 0,
+            vram_budget,
+            upload_bytes_per_frame: 4 * 1024 * 1024,
+            anisotropy: None,
+            max_texture_size: wgpu::Limits::default().max_texture_dimension_2d,
+            cache,
+        }
+    }
+
+    // Samples the streamed textures with anisotropic filtering.
+    pub fn with_anisotropy(mut self, anisotropy: Option<NonZeroU8>) -> TextureStreamer {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    // Skips the mip levels larger than `size`, for devices that cannot create them.
+    pub fn with_max_texture_size(mut self, size: u32) -> TextureStreamer {
+        self.max_texture_size = size;
+        self
+    }
+
+    // Starts streaming the image at `path`. Requesting the same path twice returns the same id.
+    pub fn request(&mut self, path: impl AsRef<Path>) -> StreamedTextureId {
+        let path = path.as_ref().to_path_buf();
+        if let Some(&id) = self.by_path.get(&path) {
+            return id;
+        }
+        let id = StreamedTextureId(self.entries.len());
+        self.entries.push(Entry {
+            path: path.clone(),
+            state: State::Decoding,
+            last_used: self.frame,
+        });
+        self.by_path.insert(path.clone(), id);
+        self.send_request(id, path);
+        id
+    }
+
+    fn send_request(&self, id: StreamedTextureId, path: PathBuf) {
+        let decoded = self.decoded_tx.clone();
+        jobs::global().spawn(move || {
+            let mips = decode(&path);
+            // Fails when the streamer is gone, and the texture with it.
+            let _ = decoded.send(Decoded { id, mips });
+        });
+    }
+
+    // The texture with the mip levels uploaded so far, or `None` while none are.
+    // Counts as a use for the LRU eviction, and brings evicted textures back.
+    pub fn texture(&mut self, id: StreamedTextureId) -> Option<Rc<Texture>> {
+        let entry = &mut self.entries[id.0];
+        entry.last_used = self.frame;
+        match &entry.state {
+            State::Resident { current, .. } => current.clone(),
+            State::Evicted => {
+                entry.state = State::Decoding;
+                let path = entry.path.clone();
+                self.send_request(id, path);
+                None
+            }
+            State::Decoding | State::Failed => None,
+        }
+    }
+
+    // Streams the image at `path` in again, after it changed on disk. The old version stays
+    // in use until the new one arrives. Returns false if `path` was never requested.
+    pub fn reload(&mut self, path: &Path) -> bool {
+        let id = match self.by_path.get(path) {
+            Some(&id) => id,
+            None => return false,
+        };
+        let entry = &mut self.entries[id.0];
+        if let State::Resident { bytes, .. } = entry.state {
+            self.resident_bytes -= bytes;
+        }
+        entry.state = State::Decoding;
+        self.send_request(id, path.to_path_buf());
+        true
+    }
+
+    pub fn is_fully_loaded(&self, id: StreamedTextureId) -> bool {
+        matches!(&self.entries[id.0].state, State::Resident { pending, .. } if pending.is_empty())
+    }
+
+    // GPU memory used by streamed textures, in bytes.
+    pub fn resident_bytes(&self) -> u64 {
+        self.resident_bytes
+    }
+
+    // Call once per frame: creates textures for finished decodes, uploads mip levels
+    // and evicts textures over the budget.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.frame += 1;
+
+        while let Ok(decoded) = self.decoded.try_recv() {
+            self.receive(device, decoded);
+        }
+
+        let mut uploaded = 0;
+        while uploaded == 0 || uploaded < self.upload_bytes_per_frame {
+            match self.upload_smallest_pending(device, queue) {
+                Some(bytes) => uploaded += bytes,
+                None => break,
+            }
+        }
+
+        self.evict_over_budget();
+    }
+
+    fn receive(&mut self, device: &wgpu::Device, decoded: Decoded) {
+        let entry = &mut self.entries[decoded.id.0];
+        // The texture may have been evicted and requested again in the meantime.
+        if !matches!(entry.state, State::Decoding) {
+            return;
+        }
+        let mut mips = match decoded.mips {
+            Ok(mips) => mips,
+            Err(e) => {
+                tracing::error!("Failed to load texture {}: {}", entry.path.display(), e);
+                entry.state = State::Failed;
+                return;
+            }
+        };
+
+        // Skip the levels larger than the device allows.
+        let max_size = self.max_texture_size;
+        let too_large = mips.iter().take_while(|mip| mip.width.max(mip.height) > max_size).count();
+        mips.drain(..too_large.min(mips.len() - 1));
+
+        let label = entry.path.to_string_lossy();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&label),
+            size: wgpu::Extent3d {
+                width: mips[0].width,
+                height: mips[0].height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mips.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let bytes = mips.iter().map(|mip| mip.pixels.len() as u64).sum();
+        self.resident_bytes += bytes;
+        entry.state = State::Resident {
+            texture: Rc::new(texture),
+            bytes,
+            pending: mips,
+            current: None,
+        };
+    }
+
+    // Uploads the smallest mip level still pending across all textures.
+    // Returns the number of bytes uploaded, or `None` if nothing is pending.
+    fn upload_smallest_pending(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<u64> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .filter_map(|entry| match &mut entry.state {
+                State::Resident { pending, .. } if !pending.is_empty() => Some(entry),
+                _ => None,
+            })
+            .min_by_key(|entry| match &entry.state {
+                State::Resident { pending, .. } => pending.last().unwrap().pixels.len(),
+                _ => unreachable!(),
+            })?;
+
+        let label = entry.path.to_string_lossy().into_owned();
+        let (texture, pending, current) = match &mut entry.state {
+            State::Resident {
+                texture,
+                pending,
+                current,
+                ..
+            } => (texture, pending, current),
+            _ => unreachable!(),
+        };
+
+        let mip = pending.pop().unwrap();
+        let level = pending.len() as u32; // The levels above it are still pending.
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &mip.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * mip.width),
+                rows_per_image: NonZeroU32::new(mip.height),
+            },
+            wgpu::Extent3d {
+                width: mip.width,
+                height: mip.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        // Only view the levels that hold data, sampling the others would show black.
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(&label),
+            base_mip_level: level,
+            ..Default::default()
+        });
+        let sampler = self.cache.sampler(device, &wgpu::SamplerDescriptor {
+            label: Some("Streamed Texture Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: self.anisotropy,
+            ..Default::default()
+        });
+        *current = Some(Rc::new(Texture {
+            texture: texture.clone(),
+            view,
+            sampler,
+        }));
+
+        Some(mip.pixels.len() as u64)
+    }
+
+    // Evicts the least recently used textures until the budget is met.
+    // Textures used this frame are never evicted.
+    fn evict_over_budget(&mut self) {
+        let resident = self.entries.iter().enumerate().filter_map(|(i, entry)| match entry.state {
+            State::Resident { bytes, .. } => Some((i, bytes, entry.last_used)),
+            _ => None,
+        });
+        for i in least_recently_used(resident, self.resident_bytes, self.vram_budget, self.frame) {
+            let entry = &mut self.entries[i];
+            if let State::Resident { bytes, .. } = entry.state {
+                self.resident_bytes -= bytes;
+            }
+            // Materials may still hold the texture, its memory is freed once they let go of it.
+            entry.state = State::Evicted;
+        }
+    }
+}
+
+// The textures to evict, least recently used first, to bring `resident` bytes within `budget`.
+// `textures` are (index, bytes, frame last used); the ones used in `frame` are kept.
+fn least_recently_used(textures: impl Iterator<Item = (usize, u64, u64)>, mut resident: u64, budget: u64, frame: u64) -> Vec<usize> {
+    let mut textures: Vec<(usize, u64, u64)> = textures.filter(|&(_, _, last_used)| last_used < frame).collect();
+    textures.sort_by_key(|&(_, _, last_used)| last_used);
+    let mut evicted = Vec::new();
+    for (i, bytes, _) in textures {
+        if resident <= budget {
+            break;
+        }
+        resident -= bytes;
+        evicted.push(i);
+    }
+    evicted
+}
+
+// Runs on a job worker: decodes the image and builds its mip chain down to 1x1.
+fn decode(path: &Path) -> Result<Vec<MipLevel>, String> {
+    let mut image = image::open(path).map_err(|e| e.to_string())?.to_rgba8();
+    let mut mips = Vec::new();
+    loop {
+        let (width, height) = image.dimensions();
+        let next = if width > 1 || height > 1 {
+            Some(image::imageops::resize(
+                &image,
+                (width / 2).max(1),
+                (height / 2).max(1),
+                image::imageops::FilterType::Triangle,
+            ))
+        } else {
+            None
+        };
+        mips.push(MipLevel {
+            width,
+            height,
+            pixels: image.into_raw(),
+        });
+        match next {
+            Some(next) => image = next,
+            None => return Ok(mips),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn evicts_least_recently_used_until_within_budget() {
+        // (index, bytes, last used)
+        let textures = [(0, 400, 3), (1, 300, 1), (2, 200, 5), (3, 100, 2)];
+        assert_eq!(least_recently_used(textures.into_iter(), 1000, 1000, 6), Vec::<usize>::new());
+        assert_eq!(least_recently_used(textures.into_iter(), 1000, 800, 6), vec![1]);
+        assert_eq!(least_recently_used(textures.into_iter(), 1000, 500, 6), vec![1, 3, 0]);
+        // Textures used this frame stay, even over the budget.
+        assert_eq!(least_recently_used(textures.into_iter(), 1000, 0, 5), vec![1, 3, 0]);
+    }
+
+    // Streams three textures into a budget for two, through the jobs and a real device.
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn streaming_past_the_budget_evicts_the_least_recently_used() {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default())).expect("no adapter");
+        let (device, queue) = pollster::block_on(adapter.request_device(&Default::default(), None)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("streaming-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = (0..3).map(|i| dir.join(format!("{}.png", i))).collect();
+        for path in &paths {
+            image::RgbaImage::new(64, 64).save(path).unwrap();
+        }
+        // The mip chain of a 64x64 texture, down to 1x1.
+        let bytes: u64 = (0..7).map(|level| 4 * (64u64 >> level).pow(2)).sum();
+
+        let mut streamer = TextureStreamer::new(2 * bytes + bytes / 2, Rc::new(DeviceCache::new()));
+        let stream = |streamer: &mut TextureStreamer, used: &[StreamedTextureId], done: StreamedTextureId| {
+            let start = Instant::now();
+            while !streamer.is_fully_loaded(done) {
+                assert!(start.elapsed() < Duration::from_secs(10), "the texture never arrived");
+                for &id in used {
+                    streamer.texture(id);
+                }
+                streamer.update(&device, &queue);
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        };
+
+        let first = streamer.request(&paths[0]);
+        let second = streamer.request(&paths[1]);
+        stream(&mut streamer, &[first, second], first);
+        stream(&mut streamer, &[first, second], second);
+        assert_eq!(streamer.resident_bytes(), 2 * bytes);
+
+        // Only the second is still in use when the third arrives.
+        let third = streamer.request(&paths[2]);
+        stream(&mut streamer, &[second, third], third);
+        assert_eq!(streamer.resident_bytes(), 2 * bytes);
+        assert!(matches!(streamer.entries[first.0].state, State::Evicted));
+        assert!(streamer.is_fully_loaded(second));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use windows::Win32::Foundation::{LPARAM, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    WM_CHAR, WM_DEADCHAR, WM_KEYDOWN, WM_KEYUP, WM_KILLFOCUS, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MOUSEMOVE, WM_MOUSEWHEEL,
+    WM_RBUTTONDOWN, WM_RBUTTONUP,
+};
+
+use crate::game::{Event, MouseButton};
+
+// Synthetic input
+//======================
+// Fabricated window messages, fed to `Window::inject` instead of coming from the message pump.
+// They run through the same window procedure code as real input, so the state of `Keyboard`
+// and `Mouse` and the queued `Event`s can be checked without a window on screen:
+//
+//     let mut window = Window::new(800, 600, "test");
+//     window.inject(Message::key_down(VK_SPACE.0));
+//     window.inject_event(Event::MouseMoved { x: 10, y: 20 });
+//     assert!(window.input(&Rng::new(0)).keyboard.key_is_pressed(VK_SPACE.0));
+//     assert_eq!(window.next_event(), Some(Event::KeyPressed(VK_SPACE.0)));
+//
+// Messages that depend on the ones after them, like AltGr's left Ctrl, go through
+// `Window::inject_all`, where the rest of the sequence stands in for the message queue.
+
+// Bit 30 of the lparam of WM_KEYDOWN: the key was already down, i.e. this is an auto-repeat.
+const PREVIOUS_KEY_STATE: LPARAM = 1 << 30;
+// Bit 24 of the lparam of key messages: right Ctrl, right Alt and the other extended keys.
+pub const EXTENDED_KEY: LPARAM = 1 << 24;
+
+/// A window message, as the window procedure receives it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub id: u32,
+    pub wparam: WPARAM,
+    pub lparam: LPARAM,
+    // When it was posted, in milliseconds as `GetMessageTime`.
+    pub time: u32,
+}
+
+impl Message {
+    pub fn new(id: u32, wparam: WPARAM, lparam: LPARAM) -> Message {
+        Message { id, wparam, lparam, time: 0 }
+    }
+
+    // Posted at `time`; the messages of one key stroke, like AltGr's Ctrl and Alt, share it.
+    pub fn at(mut self, time: u32) -> Message {
+        self.time = time;
+        self
+    }
+
+    // A key message for the right-hand key of a pair, e.g. right Alt instead of left Alt.
+    pub fn extended(mut self) -> Message {
+        self.lparam |= EXTENDED_KEY;
+        self
+    }
+
+    // `key` is a virtual-key code, as in `Keyboard`.
+    pub fn key_down(key: u16) -> Message {
+        Message::new(WM_KEYDOWN, key as WPARAM, 0)
+    }
+
+    // A key held down long enough to repeat.
+    pub fn key_repeat(key: u16) -> Message {
+        Message::new(WM_KEYDOWN, key as WPARAM, PREVIOUS_KEY_STATE)
+    }
+
+    pub fn key_up(key: u16) -> Message {
+        Message::new(WM_KEYUP, key as WPARAM, 0)
+    }
+
+    // A UTF-16 code unit of typed text.
+    pub fn char(character: u16) -> Message {
+        Message::new(WM_CHAR, character as WPARAM, 0)
+    }
+
+    // The accent of a dead key, which waits to combine with the next character.
+    pub fn dead_char(character: u16) -> Message {
+        Message::new(WM_DEADCHAR, character as WPARAM, 0)
+    }
+
+    // Client area coordinates, packed into the lparam like the real message.
+    pub fn mouse_move(x: i32, y: i32) -> Message {
+        let lparam = (x as u16 as LPARAM) | ((y as u16 as LPARAM) << 16);
+        Message::new(WM_MOUSEMOVE, 0, lparam)
+    }
+
+    pub fn button_down(button: MouseButton) -> Message {
+        match button {
+            MouseButton::Left => Message::new(WM_LBUTTONDOWN, 0, 0),
+            MouseButton::Right => Message::new(WM_RBUTTONDOWN, 0, 0),
+        }
+    }
+
+    pub fn button_up(button: MouseButton) -> Message {
+        match button {
+            MouseButton::Left => Message::new(WM_LBUTTONUP, 0, 0),
+            MouseButton::Right => Message::new(WM_RBUTTONUP, 0, 0),
+        }
+    }
+
+    // In wheel units, `WHEEL_DELTA` per notch, positive away from the user, in the high word of
+    // the wparam like the real message.
+    pub fn wheel(delta: i16) -> Message {
+        Message::new(WM_MOUSEWHEEL, (delta as u16 as WPARAM) << 16, 0)
+    }
+
+    pub fn focus_lost() -> Message {
+        Message::new(WM_KILLFOCUS, 0, 0)
+    }
+
+    // The message the window receives for `event`, `None` for the events `App` raises itself, for
+    // raw motion, whose WM_INPUT only carries a handle to the system's data, and for scrolling,
+    // which may be in pixels or finer than WM_MOUSEWHEEL's units.
+    pub fn from_event(event: &Event) -> Option<Message> {
+        Some(match *event {
+            Event::KeyPressed(key) => Message::key_down(key),
+            Event::KeyReleased(key) => Message::key_up(key),
+            Event::Char(character) => Message::char(character),
+            Event::MouseMoved { x, y } => Message::mouse_move(x, y),
+            Event::MousePressed(button) => Message::button_down(button),
+            Event::MouseReleased(button) => Message::button_up(button),
+            Event::FocusLost => Message::focus_lost(),
+            Event::MouseRawMoved { .. }
+            | Event::MouseScrolled(_)
+            | Event::OcclusionChanged { .. }
+            | Event::PowerChanged { .. } => return None,
+        })
+    }
+}
@@ -0,0 +1,340 @@
+use std::num::NonZeroU32;
+
+// Text overlay
+//======================
+// Screen-space text for debug output, drawn on top of the scene.
+// The font is a built-in 3x5 pixel font covering digits, letters (lower case is drawn as upper case)
+// and some punctuation, rasterized into a small atlas texture at startup.
+// Every glyph is an instance of a quad, the vertex shader places it in pixel coordinates.
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+// Each glyph occupies a cell one pixel larger than the glyph, which keeps sampling from bleeding.
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+
+// Rows from top to bottom, '1' marks a set pixel.
+#[rustfmt::skip]
+const GLYPHS: &[(char, [&str; 5])] = &[
+    ('?', ["110", "001", "010", "000", "010"]),
+    (' ', ["000", "000", "000", "000", "000"]),
+    ('0', ["111", "101", "101", "101", "111"]),
+    ('1', ["010", "110", "010", "010", "111"]),
+    ('2', ["110", "001", "010", "100", "111"]),
+    ('3', ["110", "001", "010", "001", "110"]),
+    ('4', ["101", "101", "111", "001", "001"]),
+    ('5', ["111", "100", "110", "001", "110"]),
+    ('6', ["011", "100", "111", "101", "111"]),
+    ('7', ["111", "001", "010", "010", "010"]),
+    ('8', ["111", "101", "111", "101", "111"]),
+    ('9', ["111", "101", "111", "001", "110"]),
+    ('A', ["010", "101", "111", "101", "101"]),
+    ('B', ["110", "101", "110", "101", "110"]),
+    ('C', ["011", "100", "100", "100", "011"]),
+    ('D', ["110", "101", "101", "101", "110"]),
+    ('E', ["111", "100", "110", "100", "111"]),
+    ('F', ["111", "100", "110", "100", "100"]),
+    ('G', ["011", "100", "101", "101", "011"]),
+    ('H', ["101", "101", "111", "101", "101"]),
+    ('I', ["111", "010", "010", "010", "111"]),
+    ('J', ["001", "001", "001", "101", "010"]),
+    ('K', ["101", "101", "110", "101", "101"]),
+    ('L', ["100", "100", "100", "100", "111"]),
+    ('M', ["101", "111", "111", "101", "101"]),
+    ('N', ["110", "101", "101", "101", "101"]),
+    ('O', ["010", "101", "101", "101", "010"]),
+    ('P', ["110", "101", "110", "100", "100"]),
+    ('Q', ["010", "101", "101", "110", "011"]),
+    ('R', ["110", "101", "110", "101", "101"]),
+    ('S', ["011", "100", "010", "001", "110"]),
+    ('T', ["111", "010", "010", "010", "010"]),
+    ('U', ["101", "101", "101", "101", "111"]),
+    ('V', ["101", "101", "101", "101", "010"]),
+    ('W', ["101", "101", "111", "111", "101"]),
+    ('X', ["101", "101", "010", "101", "101"]),
+    ('Y', ["101", "101", "010", "010", "010"]),
+    ('Z', ["111", "001", "010", "100", "111"]),
+    ('.', ["000", "000", "000", "000", "010"]),
+    (',', ["000", "000", "000", "010", "100"]),
+    (':', ["000", "010", "000", "010", "000"]),
+    ('/', ["001", "001", "010", "100", "100"]),
+    ('%', ["101", "001", "010", "100", "101"]),
+    ('-', ["000", "000", "111", "000", "000"]),
+    ('+', ["000", "010", "111", "010", "000"]),
+    ('=', ["000", "111", "000", "111", "000"]),
+    ('(', ["010", "100", "100", "100", "010"]),
+    (')', ["010", "001", "001", "001", "010"]),
+    ('_', ["000", "000", "000", "000", "111"]),
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    position: [f32; 2],
+    size: [f32; 2],
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    color: [f32; 4],
+}
+
+impl GlyphInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x2, 1 => Float32x2, 2 => Float32x2, 3 => Float32x2, 4 => Float32x4
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+            // One glyph per instance, the 6 vertices of its quad come from the vertex index.
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Queues text every frame and draws it on top of the scene.
+pub struct TextOverlay {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    screen_buffer: wgpu::Buffer,
+    instances: Vec<GlyphInstance>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    instance_count: u32,
+}
+
+impl TextOverlay {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat, samples: u32) -> TextOverlay {
+        let atlas = create_atlas(device, queue);
+        let atlas_view = atlas.create_view(&wgpu::TextureViewDescriptor::default());
+        // Nearest filtering keeps the pixels of the font crisp at any scale.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Font Sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let screen_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Text Screen Buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Text Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Text Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: screen_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Text Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("text.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Text Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[GlyphInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    // Glyph coverage ends up in alpha.
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(), // No culling, quads are never seen from the back anyway.
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: samples,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let instance_capacity = 256;
+        TextOverlay {
+            pipeline,
+            bind_group,
+            screen_buffer,
+            instances: Vec::new(),
+            instance_buffer: create_instance_buffer(device, instance_capacity),
+            instance_capacity,
+            instance_count: 0,
+        }
+    }
+
+    // Queues `text` for the next frame, with its top left corner at (`x`, `y`) pixels.
+    // Every font pixel becomes `scale` x `scale` screen pixels. '\n' starts a new line.
+    pub fn queue_text(&mut self, x: f32, y: f32, scale: f32, color: [f32; 4], text: &str) {
+        let atlas_width = (GLYPHS.len() as u32 * CELL_WIDTH) as f32;
+        let (mut pen_x, mut pen_y) = (x, y);
+        for c in text.chars() {
+            if c == '\n' {
+                pen_x = x;
+                pen_y += CELL_HEIGHT as f32 * scale;
+                continue;
+            }
+            let index = glyph_index(c);
+            if c != ' ' {
+                let u = (index as u32 * CELL_WIDTH) as f32;
+                self.instances.push(GlyphInstance {
+                    position: [pen_x, pen_y],
+                    size: [GLYPH_WIDTH as f32 * scale, GLYPH_HEIGHT as f32 * scale],
+                    uv_min: [u / atlas_width, 0.0],
+                    uv_max: [(u + GLYPH_WIDTH as f32) / atlas_width, GLYPH_HEIGHT as f32 / CELL_HEIGHT as f32],
+                    color,
+                });
+            }
+            pen_x += CELL_WIDTH as f32 * scale;
+        }
+    }
+
+    // Uploads the queued glyphs, to be drawn by `draw` this frame.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        queue.write_buffer(&self.screen_buffer, 0, bytemuck::cast_slice(&[width as f32, height as f32, 0.0, 0.0]));
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.instances));
+        self.instance_count = self.instances.len() as u32;
+        self.instances.clear();
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.instance_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.instance_count);
+    }
+}
+
+// The size `queue_text` draws `text` at, in pixels, without the spacing after the last glyph
+// and line.
+pub fn measure_text(text: &str, scale: f32) -> (f32, f32) {
+    let columns = text.lines().map(|line| line.chars().count()).max().unwrap_or(0) as f32;
+    let rows = text.lines().count().max(1) as f32;
+    (
+        (columns * CELL_WIDTH as f32 - 1.0).max(0.0) * scale,
+        (rows * CELL_HEIGHT as f32 - 1.0) * scale,
+    )
+}
+
+fn glyph_index(c: char) -> usize {
+    let c = c.to_ascii_uppercase();
+    GLYPHS.iter().position(|(glyph, _)| *glyph == c).unwrap_or(0) // Unknown characters become '?'.
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Text Instance Buffer"),
+        size: (capacity * std::mem::size_of::<GlyphInstance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// Rasterizes all glyphs next to each other into a single row atlas.
+fn create_atlas(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+    let width = GLYPHS.len() as u32 * CELL_WIDTH;
+    let mut pixels = vec![0u8; (width * CELL_HEIGHT) as usize];
+    for (i, (_, rows)) in GLYPHS.iter().enumerate() {
+        for (y, row) in rows.iter().enumerate() {
+            for (x, bit) in row.chars().enumerate() {
+                if bit == '1' {
+                    pixels[y * width as usize + i * CELL_WIDTH as usize + x] = 255;
+                }
+            }
+        }
+    }
+
+    let size = wgpu::Extent3d {
+        width,
+        height: CELL_HEIGHT,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Font Atlas"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        &pixels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(width),
+            rows_per_image: NonZeroU32::new(CELL_HEIGHT),
+        },
+        size,
+    );
+    texture
+}
@@ -0,0 +1,50 @@
+// Screen-space text: one instance per glyph, a quad generated from the vertex index.
+
+struct Screen {
+    size: vec2<f32>; // In pixels.
+};
+
+[[group(0), binding(0)]]
+var<uniform> screen: Screen;
+[[group(0), binding(1)]]
+var t_font: texture_2d<f32>;
+[[group(0), binding(2)]]
+var s_font: sampler;
+
+struct GlyphInput {
+    [[location(0)]] position: vec2<f32>; // Top left corner, in pixels.
+    [[location(1)]] size: vec2<f32>;
+    [[location(2)]] uv_min: vec2<f32>;
+    [[location(3)]] uv_max: vec2<f32>;
+    [[location(4)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32, glyph: GlyphInput) -> VertexOutput {
+    // Two triangles: (0, 0) (1, 0) (0, 1) and (0, 1) (1, 0) (1, 1).
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[index];
+    let pixel = glyph.position + corner * glyph.size;
+
+    var out: VertexOutput;
+    // Pixels have y pointing down, clip space has y pointing up.
+    out.clip_position = vec4<f32>(pixel.x / screen.size.x * 2.0 - 1.0, 1.0 - pixel.y / screen.size.y * 2.0, 0.0, 1.0);
+    out.uv = mix(glyph.uv_min, glyph.uv_max, corner);
+    out.color = glyph.color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coverage = textureSample(t_font, s_font, in.uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
@@ -0,0 +1,106 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::device_cache::DeviceCache;
+
+/// A 2D texture together with a view and a sampler to bind it with.
+pub struct Texture {
+    pub texture: Rc<wgpu::Texture>, // Shared by textures that view different mip levels of it.
+    pub view: wgpu::TextureView,
+    pub sampler: Rc<wgpu::Sampler>, // Shared with the other textures sampled the same way, see device_cache.rs.
+}
+
+impl Texture {
+    // The format of the depth buffer the scene is drawn with.
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    // Creates a texture from tightly packed 8-bit RGBA pixels (sRGB encoded).
+    // Images larger than the device allows are scaled down, see capabilities.rs.
+    pub fn from_rgba8(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &DeviceCache,
+        label: &str,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Texture {
+        let max_size = device.limits().max_texture_dimension_2d;
+        if width > max_size || height > max_size {
+            let scale = max_size as f64 / width.max(height) as f64;
+            let (new_width, new_height) = (
+                ((width as f64 * scale) as u32).clamp(1, max_size),
+                ((height as f64 * scale) as u32).clamp(1, max_size),
+            );
+            tracing::warn!(
+                "Texture {} is {}x{}, larger than the device allows, scaling it to {}x{}",
+                label, width, height, new_width, new_height
+            );
+            let image = image::RgbaImage::from_raw(width, height, data.to_vec()).expect("pixels match the size");
+            let image = image::imageops::resize(&image, new_width, new_height, image::imageops::FilterType::Triangle);
+            return Texture::from_rgba8(device, queue, cache, label, new_width, new_height, &image);
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            // TEXTURE_BINDING to use it in shaders, COPY_DST to copy the pixels into it.
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        queue.write_texture(
+            // Where to copy the pixel data to.
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            // The layout of the pixel data.
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = cache.sampler(device, &wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Texture {
+            texture: Rc::new(texture),
+            view,
+            sampler,
+        }
+    }
+
+    // Loads a PNG or JPEG image.
+    pub fn load(device: &wgpu::Device, queue: &wgpu::Queue, cache: &DeviceCache, path: &Path) -> Result<Texture, image::ImageError> {
+        let image = image::open(path)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        let label = path.to_string_lossy();
+        Ok(Texture::from_rgba8(device, queue, cache, &label, width, height, &image))
+    }
+}
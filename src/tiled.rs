@@ -0,0 +1,646 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+// Tiled maps
+//======================
+// Reads maps made with the Tiled editor (https://www.mapeditor.org), as .tmx (XML) or .json
+// files, into what the tilemap renderer needs of them, see tilemap.rs:
+//  - the tilesets, embedded or in their own .tsx or .json files, with one image each and the
+//    animations of their tiles,
+//  - the tile layers, with offsets, parallax factors and opacity. Group layers are flattened
+//    into their tile layers; object and image layers are skipped.
+// Only orthogonal, finite maps are supported. Layer data can be CSV, plain XML tiles, or base64,
+// uncompressed or compressed with zlib or gzip.
+
+// The top bits of a tile's global id flip it, see `TileLayer::tiles`.
+pub const FLIP_HORIZONTAL: u32 = 0x8000_0000;
+pub const FLIP_VERTICAL: u32 = 0x4000_0000;
+pub const FLIP_DIAGONAL: u32 = 0x2000_0000;
+const FLIP_HEXAGONAL: u32 = 0x1000_0000;
+pub const GID_MASK: u32 = !(FLIP_HORIZONTAL | FLIP_VERTICAL | FLIP_DIAGONAL | FLIP_HEXAGONAL);
+
+/// Why a map could not be read.
+#[derive(Debug)]
+pub enum TiledError {
+    Io(std::io::Error),
+    Xml(roxmltree::Error),
+    Json(serde_json::Error),
+    Invalid(String), // Parsed, but not something the renderer can draw.
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TiledError::Io(e) => write!(f, "{}", e),
+            TiledError::Xml(e) => write!(f, "{}", e),
+            TiledError::Json(e) => write!(f, "{}", e),
+            TiledError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+impl From<std::io::Error> for TiledError {
+    fn from(e: std::io::Error) -> Self {
+        TiledError::Io(e)
+    }
+}
+
+impl From<roxmltree::Error> for TiledError {
+    fn from(e: roxmltree::Error) -> Self {
+        TiledError::Xml(e)
+    }
+}
+
+impl From<serde_json::Error> for TiledError {
+    fn from(e: serde_json::Error) -> Self {
+        TiledError::Json(e)
+    }
+}
+
+fn invalid<T>(message: impl Into<String>) -> Result<T, TiledError> {
+    Err(TiledError::Invalid(message.into()))
+}
+
+/// An orthogonal map, its size in tiles and the size of its grid cells in pixels.
+#[derive(Clone, Debug)]
+pub struct TiledMap {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub tilesets: Vec<Tileset>, // By ascending `first_gid`.
+    pub layers: Vec<TileLayer>, // Bottom to top.
+}
+
+/// Tiles cut from a single image.
+#[derive(Clone, Debug)]
+pub struct Tileset {
+    pub first_gid: u32, // The global id of tile 0 in the map's layers.
+    pub name: String,
+    pub image: PathBuf, // Relative to the working directory.
+    pub image_width: u32,
+    pub image_height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub columns: u32,
+    pub tile_count: u32,
+    pub spacing: u32, // Between tiles, in pixels.
+    pub margin: u32,  // Around the tiles, in pixels.
+    pub animations: HashMap<u32, Vec<AnimationFrame>>, // By local tile id.
+}
+
+/// A step of a tile animation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct AnimationFrame {
+    #[serde(rename = "tileid")]
+    pub tile: u32, // Local id in the same tileset.
+    #[serde(rename = "duration")]
+    pub duration_ms: u32,
+}
+
+/// A grid of tiles, drawn above the layers before it.
+#[derive(Clone, Debug)]
+pub struct TileLayer {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    // Global tile ids, row by row from the top left. 0 is empty; the flip bits are kept.
+    pub tiles: Vec<u32>,
+    pub offset: (f32, f32),   // In pixels.
+    pub parallax: (f32, f32), // 1 moves with the camera, less for layers further back.
+    pub opacity: f32,
+    pub visible: bool,
+}
+
+impl TiledMap {
+    // Loads a .tmx or .json map and the tilesets it refers to.
+    pub fn load(path: &Path) -> Result<TiledMap, TiledError> {
+        let text = std::fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let map = if is_json(path) {
+            parse_json_map(&text, dir)?
+        } else {
+            parse_tmx_map(&text, dir)?
+        };
+        for layer in &map.layers {
+            if layer.tiles.len() != (layer.width * layer.height) as usize {
+                return invalid(format!("layer '{}' has {} tiles, not {}x{}", layer.name, layer.tiles.len(), layer.width, layer.height));
+            }
+        }
+        Ok(map)
+    }
+
+    // The tileset of a global tile id (without flip bits) and the tile's id within it.
+    pub fn tileset_of(&self, gid: u32) -> Option<(usize, u32)> {
+        let index = self.tilesets.iter().rposition(|tileset| tileset.first_gid <= gid)?;
+        let local = gid - self.tilesets[index].first_gid;
+        (local < self.tilesets[index].tile_count).then_some((index, local))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("json" | "tmj" | "tsj"))
+}
+
+// Group layers pass their offset, parallax and opacity on to the layers they contain.
+#[derive(Clone, Copy)]
+struct Inherited {
+    offset: (f32, f32),
+    parallax: (f32, f32),
+    opacity: f32,
+    visible: bool,
+}
+
+impl Inherited {
+    const ROOT: Inherited = Inherited {
+        offset: (0.0, 0.0),
+        parallax: (1.0, 1.0),
+        opacity: 1.0,
+        visible: true,
+    };
+
+    fn child(self, offset: (f32, f32), parallax: (f32, f32), opacity: f32, visible: bool) -> Inherited {
+        Inherited {
+            offset: (self.offset.0 + offset.0, self.offset.1 + offset.1),
+            parallax: (self.parallax.0 * parallax.0, self.parallax.1 * parallax.1),
+            opacity: self.opacity * opacity,
+            visible: self.visible && visible,
+        }
+    }
+}
+
+// Decodes base64 layer data, decompressing it first, into little-endian global ids.
+fn decode_base64(data: &str, compression: Option<&str>) -> Result<Vec<u32>, TiledError> {
+    use base64::Engine;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| TiledError::Invalid(format!("invalid base64 layer data: {}", e)))?;
+    let bytes = match compression.unwrap_or("") {
+        "" => bytes,
+        "zlib" => {
+            let mut out = Vec::new();
+            flate2::read::ZlibDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+            out
+        }
+        "gzip" => {
+            let mut out = Vec::new();
+            flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+            out
+        }
+        other => return invalid(format!("unsupported layer compression '{}'", other)),
+    };
+    Ok(bytes.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+}
+
+fn check_orientation(orientation: Option<&str>, infinite: bool) -> Result<(), TiledError> {
+    if !matches!(orientation, None | Some("orthogonal")) {
+        return invalid(format!("{} maps are not supported, only orthogonal ones", orientation.unwrap_or_default()));
+    }
+    if infinite {
+        return invalid("infinite maps are not supported");
+    }
+    Ok(())
+}
+
+// TMX
+//======================
+
+fn parse_tmx_map(text: &str, dir: &Path) -> Result<TiledMap, TiledError> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "map" {
+        return invalid("not a Tiled map");
+    }
+    check_orientation(root.attribute("orientation"), attr(&root, "infinite", 0u32)? != 0)?;
+    let mut map = TiledMap {
+        width: required(&root, "width")?,
+        height: required(&root, "height")?,
+        tile_width: required(&root, "tilewidth")?,
+        tile_height: required(&root, "tileheight")?,
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+    };
+    for node in root.children().filter(|node| node.has_tag_name("tileset")) {
+        let first_gid = required(&node, "firstgid")?;
+        let tileset = match node.attribute("source") {
+            Some(source) => load_tileset(&dir.join(source), first_gid)?,
+            None => parse_tmx_tileset(&node, dir, first_gid)?,
+        };
+        map.tilesets.push(tileset);
+    }
+    map.tilesets.sort_by_key(|tileset| tileset.first_gid);
+    parse_tmx_layers(&root, Inherited::ROOT, &mut map.layers)?;
+    Ok(map)
+}
+
+fn parse_tmx_tileset(node: &roxmltree::Node, dir: &Path, first_gid: u32) -> Result<Tileset, TiledError> {
+    let image = node
+        .children()
+        .find(|child| child.has_tag_name("image"))
+        .ok_or_else(|| TiledError::Invalid("tilesets made of separate images are not supported".into()))?;
+    let mut animations = HashMap::new();
+    for tile in node.children().filter(|child| child.has_tag_name("tile")) {
+        if let Some(animation) = tile.children().find(|child| child.has_tag_name("animation")) {
+            let frames = animation
+                .children()
+                .filter(|child| child.has_tag_name("frame"))
+                .map(|frame| {
+                    Ok(AnimationFrame {
+                        tile: required(&frame, "tileid")?,
+                        duration_ms: required(&frame, "duration")?,
+                    })
+                })
+                .collect::<Result<Vec<_>, TiledError>>()?;
+            animations.insert(required(&tile, "id")?, frames);
+        }
+    }
+    let source: String = required(&image, "source")?;
+    Ok(Tileset {
+        first_gid,
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        image: dir.join(source),
+        image_width: attr(&image, "width", 0)?,
+        image_height: attr(&image, "height", 0)?,
+        tile_width: required(node, "tilewidth")?,
+        tile_height: required(node, "tileheight")?,
+        columns: required(node, "columns")?,
+        tile_count: required(node, "tilecount")?,
+        spacing: attr(node, "spacing", 0)?,
+        margin: attr(node, "margin", 0)?,
+        animations,
+    })
+}
+
+fn parse_tmx_layers(parent: &roxmltree::Node, inherited: Inherited, layers: &mut Vec<TileLayer>) -> Result<(), TiledError> {
+    for node in parent.children().filter(|node| node.is_element()) {
+        let name = node.tag_name().name();
+        if !matches!(name, "layer" | "group") {
+            if matches!(name, "objectgroup" | "imagelayer") {
+                tracing::debug!("Skipping {} '{}'", name, node.attribute("name").unwrap_or_default());
+            }
+            continue;
+        }
+        let inherited = inherited.child(
+            (attr(&node, "offsetx", 0.0)?, attr(&node, "offsety", 0.0)?),
+            (attr(&node, "parallaxx", 1.0)?, attr(&node, "parallaxy", 1.0)?),
+            attr(&node, "opacity", 1.0)?,
+            attr(&node, "visible", 1u32)? != 0,
+        );
+        if name == "group" {
+            parse_tmx_layers(&node, inherited, layers)?;
+            continue;
+        }
+        let data = node
+            .children()
+            .find(|child| child.has_tag_name("data"))
+            .ok_or_else(|| TiledError::Invalid("layer without data".into()))?;
+        let text = data.text().unwrap_or_default();
+        let tiles = match data.attribute("encoding") {
+            Some("csv") => text
+                .split(',')
+                .map(|gid| gid.trim().parse::<u32>().map_err(|e| TiledError::Invalid(format!("invalid tile '{}': {}", gid.trim(), e))))
+                .collect::<Result<Vec<_>, _>>()?,
+            Some("base64") => decode_base64(text, data.attribute("compression"))?,
+            Some(other) => return invalid(format!("unsupported layer encoding '{}'", other)),
+            None => data
+                .children()
+                .filter(|child| child.has_tag_name("tile"))
+                .map(|tile| attr(&tile, "gid", 0))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        layers.push(TileLayer {
+            name: node.attribute("name").unwrap_or_default().to_string(),
+            width: required(&node, "width")?,
+            height: required(&node, "height")?,
+            tiles,
+            offset: inherited.offset,
+            parallax: inherited.parallax,
+            opacity: inherited.opacity,
+            visible: inherited.visible,
+        });
+    }
+    Ok(())
+}
+
+fn attr<T: std::str::FromStr>(node: &roxmltree::Node, name: &str, default: T) -> Result<T, TiledError> {
+    match node.attribute(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| TiledError::Invalid(format!("invalid {} '{}' of <{}>", name, value, node.tag_name().name()))),
+        None => Ok(default),
+    }
+}
+
+fn required<T: std::str::FromStr>(node: &roxmltree::Node, name: &str) -> Result<T, TiledError> {
+    match node.attribute(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| TiledError::Invalid(format!("invalid {} '{}' of <{}>", name, value, node.tag_name().name()))),
+        None => invalid(format!("<{}> has no {}", node.tag_name().name(), name)),
+    }
+}
+
+// JSON
+//======================
+
+#[derive(Deserialize)]
+struct JsonMap {
+    orientation: Option<String>,
+    #[serde(default)]
+    infinite: bool,
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    firstgid: Option<u32>, // Missing in tileset files.
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: u32,
+    #[serde(default)]
+    imageheight: u32,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    tilecount: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    id: u32,
+    #[serde(default)]
+    animation: Vec<AnimationFrame>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonData {
+    Tiles(Vec<u32>),
+    Encoded(String),
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    data: Option<JsonData>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+    parallaxx: Option<f32>,
+    parallaxy: Option<f32>,
+    opacity: Option<f32>,
+    visible: Option<bool>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>, // Of groups.
+}
+
+fn parse_json_map(text: &str, dir: &Path) -> Result<TiledMap, TiledError> {
+    let json: JsonMap = serde_json::from_str(text)?;
+    check_orientation(json.orientation.as_deref(), json.infinite)?;
+    let mut map = TiledMap {
+        width: json.width,
+        height: json.height,
+        tile_width: json.tilewidth,
+        tile_height: json.tileheight,
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+    };
+    for tileset in json.tilesets {
+        let first_gid = tileset.firstgid.ok_or_else(|| TiledError::Invalid("tileset without firstgid".into()))?;
+        let tileset = match &tileset.source {
+            Some(source) => load_tileset(&dir.join(source), first_gid)?,
+            None => json_tileset(tileset, dir, first_gid)?,
+        };
+        map.tilesets.push(tileset);
+    }
+    map.tilesets.sort_by_key(|tileset| tileset.first_gid);
+    json_layers(json.layers, Inherited::ROOT, &mut map.layers)?;
+    Ok(map)
+}
+
+fn json_tileset(json: JsonTileset, dir: &Path, first_gid: u32) -> Result<Tileset, TiledError> {
+    let image = json
+        .image
+        .ok_or_else(|| TiledError::Invalid("tilesets made of separate images are not supported".into()))?;
+    Ok(Tileset {
+        first_gid,
+        name: json.name,
+        image: dir.join(image),
+        image_width: json.imagewidth,
+        image_height: json.imageheight,
+        tile_width: json.tilewidth,
+        tile_height: json.tileheight,
+        columns: json.columns,
+        tile_count: json.tilecount,
+        spacing: json.spacing,
+        margin: json.margin,
+        animations: json
+            .tiles
+            .into_iter()
+            .filter(|tile| !tile.animation.is_empty())
+            .map(|tile| (tile.id, tile.animation))
+            .collect(),
+    })
+}
+
+fn json_layers(json: Vec<JsonLayer>, inherited: Inherited, layers: &mut Vec<TileLayer>) -> Result<(), TiledError> {
+    for layer in json {
+        let inherited = inherited.child(
+            (layer.offsetx, layer.offsety),
+            (layer.parallaxx.unwrap_or(1.0), layer.parallaxy.unwrap_or(1.0)),
+            layer.opacity.unwrap_or(1.0),
+            layer.visible.unwrap_or(true),
+        );
+        match layer.kind.as_str() {
+            "group" => json_layers(layer.layers, inherited, layers)?,
+            "tilelayer" => {
+                let tiles = match (layer.data, layer.encoding.as_deref()) {
+                    (Some(JsonData::Tiles(tiles)), _) => tiles,
+                    (Some(JsonData::Encoded(data)), Some("base64")) => decode_base64(&data, layer.compression.as_deref())?,
+                    (Some(JsonData::Encoded(_)), encoding) => {
+                        return invalid(format!("unsupported layer encoding '{}'", encoding.unwrap_or_default()));
+                    }
+                    (None, _) => return invalid(format!("layer '{}' has no data", layer.name)),
+                };
+                layers.push(TileLayer {
+                    name: layer.name,
+                    width: layer.width,
+                    height: layer.height,
+                    tiles,
+                    offset: inherited.offset,
+                    parallax: inherited.parallax,
+                    opacity: inherited.opacity,
+                    visible: inherited.visible,
+                });
+            }
+            other => tracing::debug!("Skipping {} '{}'", other, layer.name),
+        }
+    }
+    Ok(())
+}
+
+// An external tileset, .tsx or .json, relative to which its image is found.
+fn load_tileset(path: &Path, first_gid: u32) -> Result<Tileset, TiledError> {
+    let text = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    if is_json(path) {
+        json_tileset(serde_json::from_str(&text)?, dir, first_gid)
+    } else {
+        let doc = roxmltree::Document::parse(&text)?;
+        parse_tmx_tileset(&doc.root_element(), dir, first_gid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maps_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("res/maps")
+    }
+
+    fn error(result: Result<TiledMap, TiledError>) -> String {
+        result.expect_err("the map is not supported").to_string()
+    }
+
+    #[test]
+    fn loads_a_tmx_map_with_an_external_tileset() {
+        let dir = maps_dir();
+        let map = TiledMap::load(&dir.join("demo.tmx")).unwrap();
+        assert_eq!((map.width, map.height, map.tile_width, map.tile_height), (20, 12, 16, 16));
+
+        assert_eq!(map.tilesets.len(), 1);
+        let tileset = &map.tilesets[0];
+        assert_eq!((tileset.first_gid, tileset.name.as_str()), (1, "terrain"));
+        assert_eq!(tileset.image, dir.join("terrain.png"));
+        assert_eq!((tileset.image_width, tileset.image_height, tileset.columns, tileset.tile_count), (64, 32, 4, 8));
+        let water = [
+            AnimationFrame { tile: 2, duration_ms: 500 },
+            AnimationFrame { tile: 3, duration_ms: 500 },
+        ];
+        assert_eq!(tileset.animations.get(&2).map(Vec::as_slice), Some(&water[..]));
+
+        // The object layer is skipped, the group flattened.
+        let names: Vec<&str> = map.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["ground", "details"]);
+        let ground = &map.layers[0];
+        assert_eq!(ground.tiles.len(), 20 * 12);
+        assert_eq!((ground.tiles[0], ground.tiles[1], ground.tiles[3 * 20 + 7]), (2, 1, 3));
+        assert_eq!((ground.offset, ground.parallax, ground.opacity, ground.visible), ((0.0, 0.0), (1.0, 1.0), 1.0, true));
+    }
+
+    #[test]
+    fn groups_pass_their_settings_on_and_base64_keeps_flips() {
+        let map = TiledMap::load(&maps_dir().join("demo.tmx")).unwrap();
+        let details = &map.layers[1];
+        assert_eq!((details.offset, details.parallax, details.opacity), ((0.0, -4.0), (0.5, 0.5), 0.8));
+        assert_eq!(details.tiles.len(), 20 * 12);
+        assert_eq!(details.tiles[2 * 20 + 3], 7);
+        let flipped = details.tiles[5 * 20 + 17];
+        assert_eq!(flipped, 8 | FLIP_HORIZONTAL);
+        assert_eq!(map.tileset_of(flipped & GID_MASK), Some((0, 7)));
+        assert_eq!(map.tileset_of(0), None); // Empty.
+        assert_eq!(map.tileset_of(9), None); // Past the last tile.
+    }
+
+    const JSON_MAP: &str = r#"{
+        "orientation": "orthogonal", "width": 2, "height": 2, "tilewidth": 8, "tileheight": 8,
+        "tilesets": [
+            {"firstgid": 1, "name": "a", "image": "a.png", "imagewidth": 16, "imageheight": 8,
+             "tilewidth": 8, "tileheight": 8, "columns": 2, "tilecount": 2,
+             "tiles": [{"id": 0, "animation": [{"tileid": 1, "duration": 100}]}, {"id": 1}]},
+            {"firstgid": 3, "name": "b", "image": "b.png", "tilewidth": 8, "tileheight": 8,
+             "columns": 1, "tilecount": 1}
+        ],
+        "layers": [
+            {"type": "tilelayer", "name": "plain", "width": 2, "height": 2, "data": [1, 0, 0, 2]},
+            {"type": "group", "name": "g", "opacity": 0.5, "visible": false, "parallaxx": 0.5, "layers": [
+                {"type": "tilelayer", "name": "encoded", "width": 2, "height": 2, "encoding": "base64",
+                 "data": "AQAAAAAAAAAAAAAAAgAAAA==", "offsetx": 3},
+                {"type": "tilelayer", "name": "gzipped", "width": 2, "height": 2, "encoding": "base64",
+                 "compression": "gzip", "data": "H4sIAAAAAAACA2NmYGBgBmJGBgYHIMUAAIB1tCYQAAAA"}
+            ]},
+            {"type": "objectgroup", "name": "objects", "objects": []}
+        ]
+    }"#;
+
+    #[test]
+    fn parses_a_json_map() {
+        let map = parse_json_map(JSON_MAP, Path::new("maps")).unwrap();
+        assert_eq!(map.tilesets.len(), 2);
+        assert_eq!(map.tilesets[0].image, Path::new("maps/a.png"));
+        assert_eq!(map.tilesets[0].animations.len(), 1); // Tiles without an animation are left out.
+        assert_eq!(map.tileset_of(2), Some((0, 1)));
+        assert_eq!(map.tileset_of(3), Some((1, 0)));
+
+        let names: Vec<&str> = map.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["plain", "encoded", "gzipped"]);
+        assert_eq!(map.layers[0].tiles, [1, 0, 0, 2]);
+        let encoded = &map.layers[1];
+        assert_eq!(encoded.tiles, [1, 0, 0, 2]);
+        assert_eq!((encoded.offset, encoded.parallax, encoded.opacity, encoded.visible), ((3.0, 0.0), (0.5, 1.0), 0.5, false));
+        assert_eq!(map.layers[2].tiles, [3, 3, 1 | FLIP_VERTICAL, 0]);
+    }
+
+    #[test]
+    fn unsupported_maps_are_errors() {
+        let tmx = |attributes: &str, data: &str| {
+            format!(r#"<map width="1" height="1" tilewidth="8" tileheight="8" {}><layer name="l" width="1" height="1">{}</layer></map>"#, attributes, data)
+        };
+        let dir = Path::new("");
+        assert_eq!(
+            error(parse_tmx_map(&tmx(r#"orientation="isometric""#, ""), dir)),
+            "isometric maps are not supported, only orthogonal ones"
+        );
+        assert_eq!(error(parse_tmx_map(&tmx(r#"infinite="1""#, ""), dir)), "infinite maps are not supported");
+        assert_eq!(
+            error(parse_tmx_map(&tmx("", r#"<data encoding="hex">01</data>"#), dir)),
+            "unsupported layer encoding 'hex'"
+        );
+        assert_eq!(
+            error(parse_tmx_map(&tmx("", r#"<data encoding="base64" compression="zstd">AQAAAA==</data>"#), dir)),
+            "unsupported layer compression 'zstd'"
+        );
+        assert_eq!(error(parse_tmx_map(&tmx("", r#"<data encoding="csv">1,x</data>"#), dir)).split(':').next(), Some("invalid tile 'x'"));
+        assert_eq!(error(parse_tmx_map("<tileset/>", dir)), "not a Tiled map");
+        assert_eq!(error(parse_tmx_map(r#"<map width="1"/>"#, dir)), "<map> has no height");
+        let separate_images = r#"<map width="1" height="1" tilewidth="8" tileheight="8"><tileset firstgid="1" tilewidth="8" tileheight="8" columns="0" tilecount="1"><tile id="0"><image source="a.png"/></tile></tileset></map>"#;
+        assert_eq!(error(parse_tmx_map(separate_images, dir)), "tilesets made of separate images are not supported");
+    }
+}
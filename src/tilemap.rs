@@ -0,0 +1,730 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use cgmath::{Matrix4, Vector2, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::assets::LoadError;
+use crate::device_cache::DeviceCache;
+use crate::game::Event;
+use crate::gfx::GFX;
+use crate::texture::Texture;
+use crate::tiled::{AnimationFrame, TiledMap, FLIP_DIAGONAL, FLIP_HORIZONTAL, FLIP_VERTICAL, GID_MASK};
+
+// Tilemaps
+//======================
+// Draws the tile layers of Tiled maps (see tiled.rs) behind the scene, through a 2D camera shared
+// by all maps:
+//
+//     let map = gfx.load_tilemap("res/maps/demo.tmx")?;
+//     gfx.tilemap_camera_mut().position = Vector2::new(160.0, 90.0);
+//
+// Every layer is cut into chunks of `CHUNK_SIZE` x `CHUNK_SIZE` tiles, each with a static vertex
+// buffer built once at load time and one draw call per tileset it uses. Only the chunks inside the
+// camera's view are drawn. Layers move with the camera by their parallax factors, so layers
+// further back can scroll slower, and are drawn bottom to top with alpha blending.
+//
+// Animated tiles are the exception to static buffers: when one of them changes frames, its four
+// vertices are rewritten in place.
+//
+// Tiles are drawn by the cameras that see `RenderLayers::TILEMAPS`, before the renderables, with
+// the depth test off and without writing depth, so the 3D scene always goes on top.
+
+// In tiles, per side.
+const CHUNK_SIZE: u32 = 32;
+
+// Moves the UVs this far (in texels) into the tile, so neighbouring tiles of the atlas don't bleed
+// in at fractional zoom levels.
+const UV_INSET: f32 = 0.01;
+
+/// The view of the tilemaps: which point of the map is in the middle of the viewport, in map
+/// pixels, and how many screen pixels a map pixel covers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera2d {
+    pub position: Vector2<f32>,
+    pub zoom: f32,
+}
+
+impl Default for Camera2d {
+    fn default() -> Self {
+        Camera2d {
+            position: Vector2::new(0.0, 0.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+/// Identifies a map loaded with `GFX::load_tilemap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TilemapId(usize);
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TileVertex {
+    position: [f32; 2], // In map pixels, y down.
+    uv: [f32; 2],
+}
+
+impl TileVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TileVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerUniform {
+    view_proj: [[f32; 4]; 4],
+    tint: [f32; 4],
+}
+
+// A tileset's image on the GPU, and where its tiles are in it.
+struct TilesetAtlas {
+    bind_group: wgpu::BindGroup,
+    size: (f32, f32), // Of the image, in texels.
+    tile_size: (u32, u32),
+    columns: u32,
+    spacing: u32,
+    margin: u32,
+    animations: HashMap<u32, Vec<AnimationFrame>>, // By local tile id.
+}
+
+impl TilesetAtlas {
+    // The four vertices of `local` tile drawn at (x, y) (its top left corner), flipped as `gid`
+    // says, clockwise from the top left.
+    fn quad(&self, local: u32, gid: u32, x: f32, y: f32) -> [TileVertex; 4] {
+        let (tw, th) = self.tile_size;
+        let columns = self.columns.max(1);
+        let px = (self.margin + (local % columns) * (tw + self.spacing)) as f32;
+        let py = (self.margin + (local / columns) * (th + self.spacing)) as f32;
+        let u = [(px + UV_INSET) / self.size.0, (px + tw as f32 - UV_INSET) / self.size.0];
+        let v = [(py + UV_INSET) / self.size.1, (py + th as f32 - UV_INSET) / self.size.1];
+        let (w, h) = (tw as f32, th as f32);
+        let corners = [(0, 0), (1, 0), (1, 1), (0, 1)];
+        corners.map(|(cx, cy)| {
+            // Tiled flips the tile diagonally first, then horizontally, then vertically; undo
+            // them in reverse to find which texel ends up at this corner.
+            let (mut s, mut t) = (cx, cy);
+            if gid & FLIP_VERTICAL != 0 {
+                t = 1 - t;
+            }
+            if gid & FLIP_HORIZONTAL != 0 {
+                s = 1 - s;
+            }
+            if gid & FLIP_DIAGONAL != 0 {
+                std::mem::swap(&mut s, &mut t);
+            }
+            TileVertex {
+                position: [x + cx as f32 * w, y + cy as f32 * h],
+                uv: [u[s], v[t]],
+            }
+        })
+    }
+
+    // The tile shown in place of `local` `time_ms` into its animation.
+    fn frame(&self, local: u32, time_ms: u64) -> u32 {
+        let frames = match self.animations.get(&local) {
+            Some(frames) if !frames.is_empty() => frames,
+            _ => return local,
+        };
+        let total: u64 = frames.iter().map(|frame| frame.duration_ms as u64).sum();
+        if total == 0 {
+            return frames[0].tile;
+        }
+        let mut t = time_ms % total;
+        for frame in frames {
+            if t < frame.duration_ms as u64 {
+                return frame.tile;
+            }
+            t -= frame.duration_ms as u64;
+        }
+        frames[frames.len() - 1].tile
+    }
+}
+
+struct Chunk {
+    bounds: (Vector2<f32>, Vector2<f32>), // Min and max, in layer pixels.
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    draws: Vec<(usize, Range<u32>)>, // The indices drawn with each tileset.
+}
+
+// A tile whose vertices change with its animation.
+struct AnimatedTile {
+    chunk: usize,
+    first_vertex: u32,
+    tileset: usize,
+    gid: u32,   // With its flip bits.
+    local: u32, // The id in its tileset, whose animation it plays.
+    position: (f32, f32),
+    shown: u32, // The tile whose UVs are in the buffer.
+}
+
+struct Layer {
+    name: String,
+    visible: bool,
+    offset: Vector2<f32>,
+    parallax: Vector2<f32>,
+    opacity: f32,
+    chunks: Vec<Chunk>,
+    animated: Vec<AnimatedTile>,
+}
+
+/// A loaded map: its layers cut into chunks, and the atlases of its tilesets.
+pub struct Tilemap {
+    size: (u32, u32), // In pixels.
+    tilesets: Vec<TilesetAtlas>,
+    layers: Vec<Layer>,
+}
+
+impl Tilemap {
+    // Loads the map, its tilesets and their images, and builds the chunks.
+    fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &DeviceCache,
+        tileset_layout: &wgpu::BindGroupLayout,
+        path: &Path,
+    ) -> Result<Tilemap, LoadError> {
+        let map = TiledMap::load(path)?;
+        let sampler = cache.sampler(device, &wgpu::SamplerDescriptor {
+            label: Some("Tileset Sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let mut tilesets = Vec::with_capacity(map.tilesets.len());
+        for tileset in &map.tilesets {
+            let image = image::open(&tileset.image)?.to_rgba8();
+            let (width, height) = image.dimensions();
+            // Tiled stores the size the image had when the map was saved; 0 if it doesn't.
+            if (tileset.image_width, tileset.image_height) != (0, 0) && (tileset.image_width, tileset.image_height) != (width, height) {
+                tracing::warn!(
+                    "Tileset {}: {} is {}x{}, the map expects {}x{}",
+                    tileset.name,
+                    tileset.image.display(),
+                    width,
+                    height,
+                    tileset.image_width,
+                    tileset.image_height
+                );
+            }
+            let texture = Texture::from_rgba8(device, queue, cache, &tileset.image.to_string_lossy(), width, height, &image);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Tileset Bind Group"),
+                layout: tileset_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            });
+            tilesets.push(TilesetAtlas {
+                bind_group,
+                // The image, not what the map says it was when it was saved.
+                size: (width as f32, height as f32),
+                tile_size: (tileset.tile_width, tileset.tile_height),
+                columns: tileset.columns,
+                spacing: tileset.spacing,
+                margin: tileset.margin,
+                animations: tileset.animations.clone(),
+            });
+        }
+
+        // Tiles taller or wider than the grid stick out above and to the right of their cell.
+        let overhang = Vector2::new(
+            map.tilesets.iter().map(|t| t.tile_width.saturating_sub(map.tile_width)).max().unwrap_or(0) as f32,
+            map.tilesets.iter().map(|t| t.tile_height.saturating_sub(map.tile_height)).max().unwrap_or(0) as f32,
+        );
+        let layers = map
+            .layers
+            .iter()
+            .map(|layer| {
+                let mut chunks = Vec::new();
+                let mut animated = Vec::new();
+                for cy in (0..layer.height).step_by(CHUNK_SIZE as usize) {
+                    for cx in (0..layer.width).step_by(CHUNK_SIZE as usize) {
+                        // (tileset, gid, local id, top left corner) of the tiles in this chunk.
+                        let mut quads = Vec::new();
+                        for y in cy..(cy + CHUNK_SIZE).min(layer.height) {
+                            for x in cx..(cx + CHUNK_SIZE).min(layer.width) {
+                                let gid = layer.tiles[(y * layer.width + x) as usize];
+                                if gid & GID_MASK == 0 {
+                                    continue;
+                                }
+                                let (tileset, local) = match map.tileset_of(gid & GID_MASK) {
+                                    Some(found) => found,
+                                    None => {
+                                        tracing::warn!("Tile {} of layer '{}' is in no tileset", gid & GID_MASK, layer.name);
+                                        continue;
+                                    }
+                                };
+                                // Aligned with the bottom left of its cell.
+                                let th = tilesets[tileset].tile_size.1;
+                                let position = ((x * map.tile_width) as f32, ((y + 1) * map.tile_height) as f32 - th as f32);
+                                quads.push((tileset, gid, local, position));
+                            }
+                        }
+                        if quads.is_empty() {
+                            continue;
+                        }
+                        // One draw per tileset.
+                        quads.sort_by_key(|quad| quad.0);
+
+                        let chunk = chunks.len();
+                        let mut vertices = Vec::with_capacity(quads.len() * 4);
+                        let mut indices: Vec<u16> = Vec::with_capacity(quads.len() * 6);
+                        let mut draws: Vec<(usize, Range<u32>)> = Vec::new();
+                        for &(tileset, gid, local, (x, y)) in &quads {
+                            let atlas = &tilesets[tileset];
+                            let first_vertex = vertices.len() as u32;
+                            if atlas.animations.contains_key(&local) {
+                                animated.push(AnimatedTile {
+                                    chunk,
+                                    first_vertex,
+                                    tileset,
+                                    gid,
+                                    local,
+                                    position: (x, y),
+                                    shown: local,
+                                });
+                            }
+                            vertices.extend_from_slice(&atlas.quad(local, gid, x, y));
+                            let base = first_vertex as u16;
+                            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+                            let end = indices.len() as u32;
+                            match draws.last_mut() {
+                                Some((last, range)) if *last == tileset => range.end = end,
+                                _ => draws.push((tileset, end - 6..end)),
+                            }
+                        }
+
+                        let min = Vector2::new((cx * map.tile_width) as f32, (cy * map.tile_height) as f32);
+                        let max = Vector2::new(
+                            ((cx + CHUNK_SIZE).min(layer.width) * map.tile_width) as f32,
+                            ((cy + CHUNK_SIZE).min(layer.height) * map.tile_height) as f32,
+                        );
+                        chunks.push(Chunk {
+                            bounds: (Vector2::new(min.x, min.y - overhang.y), Vector2::new(max.x + overhang.x, max.y)),
+                            vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                                label: Some("Tile Chunk Vertex Buffer"),
+                                contents: bytemuck::cast_slice(&vertices),
+                                // COPY_DST for the animated tiles.
+                                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                            }),
+                            index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                                label: Some("Tile Chunk Index Buffer"),
+                                contents: bytemuck::cast_slice(&indices),
+                                usage: wgpu::BufferUsages::INDEX,
+                            }),
+                            draws,
+                        });
+                    }
+                }
+                Layer {
+                    name: layer.name.clone(),
+                    visible: layer.visible,
+                    offset: Vector2::new(layer.offset.0, layer.offset.1),
+                    parallax: Vector2::new(layer.parallax.0, layer.parallax.1),
+                    opacity: layer.opacity,
+                    chunks,
+                    animated,
+                }
+            })
+            .collect();
+
+        Ok(Tilemap {
+            size: (map.width * map.tile_width, map.height * map.tile_height),
+            tilesets,
+            layers,
+        })
+    }
+
+    // In pixels.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn layer_names(&self) -> impl Iterator<Item = &str> {
+        self.layers.iter().map(|layer| layer.name.as_str())
+    }
+
+    // Shows or hides every layer called `name`. False if there is none.
+    pub fn set_layer_visible(&mut self, name: &str, visible: bool) -> bool {
+        let mut found = false;
+        for layer in self.layers.iter_mut().filter(|layer| layer.name == name) {
+            layer.visible = visible;
+            found = true;
+        }
+        found
+    }
+
+    // Rewrites the vertices of the animated tiles that changed frames.
+    fn animate(&mut self, queue: &wgpu::Queue, time_ms: u64) {
+        for layer in &mut self.layers {
+            for tile in &mut layer.animated {
+                let atlas = &self.tilesets[tile.tileset];
+                let shown = atlas.frame(tile.local, time_ms);
+                if shown == tile.shown {
+                    continue;
+                }
+                tile.shown = shown;
+                let vertices = atlas.quad(shown, tile.gid, tile.position.0, tile.position.1);
+                let offset = tile.first_vertex as u64 * std::mem::size_of::<TileVertex>() as u64;
+                queue.write_buffer(&layer.chunks[tile.chunk].vertex_buffer, offset, bytemuck::cast_slice(&vertices));
+            }
+        }
+    }
+}
+
+// What a camera draws of a layer this frame.
+struct LayerDraw {
+    map: usize,
+    layer: usize,
+    offset: u32, // Of its uniform.
+    chunks: Vec<usize>,
+}
+
+/// Draws the loaded tilemaps, see above.
+pub struct Tilemaps {
+    maps: Vec<Tilemap>,
+    camera: Camera2d,
+    time_ms: u64,
+    pipeline: wgpu::RenderPipeline,
+    uniform_layout: wgpu::BindGroupLayout,
+    tileset_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    uniform_stride: u32,
+    uniform_capacity: usize, // In layer uniforms.
+    draws: Vec<Vec<LayerDraw>>, // By camera, for this frame.
+}
+
+impl Tilemaps {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) -> Tilemaps {
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tile Layer Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    // Every camera and layer has its own part of the buffer.
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<LayerUniform>() as u64),
+                },
+                count: None,
+            }],
+        });
+        let tileset_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tileset Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline = create_pipeline(device, &uniform_layout, &tileset_layout, format, samples);
+
+        let uniform_stride = device.limits().min_uniform_buffer_offset_alignment.max(std::mem::size_of::<LayerUniform>() as u32);
+        let uniform_capacity = 16;
+        let uniform_buffer = create_uniform_buffer(device, uniform_stride, uniform_capacity);
+        let uniform_bind_group = create_uniform_bind_group(device, &uniform_layout, &uniform_buffer);
+        Tilemaps {
+            maps: Vec::new(),
+            camera: Camera2d::default(),
+            time_ms: 0,
+            pipeline,
+            uniform_layout,
+            tileset_layout,
+            uniform_buffer,
+            uniform_bind_group,
+            uniform_stride,
+            uniform_capacity,
+            draws: Vec::new(),
+        }
+    }
+
+    // Recreates the pipeline for a new sample count; the maps are kept.
+    pub fn set_samples(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) {
+        self.pipeline = create_pipeline(device, &self.uniform_layout, &self.tileset_layout, format, samples);
+    }
+
+    pub fn load(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, cache: &DeviceCache, path: &Path) -> Result<TilemapId, LoadError> {
+        let map = Tilemap::load(device, queue, cache, &self.tileset_layout, path)?;
+        self.maps.push(map);
+        Ok(TilemapId(self.maps.len() - 1))
+    }
+
+    pub fn get(&self, id: TilemapId) -> &Tilemap {
+        &self.maps[id.0]
+    }
+
+    pub fn get_mut(&mut self, id: TilemapId) -> &mut Tilemap {
+        &mut self.maps[id.0]
+    }
+
+    pub fn camera(&self) -> &Camera2d {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera2d {
+        &mut self.camera
+    }
+
+    // The time the animations are at, e.g. `Time::elapsed`.
+    pub fn set_time(&mut self, seconds: f64) {
+        self.time_ms = (seconds.max(0.0) * 1000.0) as u64;
+    }
+
+    // Culls the chunks for every camera that draws tilemaps, given the size of its viewport in
+    // pixels (`None` for the others), and uploads the layer matrices and the animated tiles.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, viewports: &[Option<(u32, u32)>]) {
+        self.draws.clear();
+        if self.maps.is_empty() {
+            return;
+        }
+        for map in &mut self.maps {
+            map.animate(queue, self.time_ms);
+        }
+
+        let mut uniforms = Vec::new();
+        for viewport in viewports {
+            let mut draws = Vec::new();
+            if let Some((width, height)) = *viewport {
+                let zoom = self.camera.zoom.max(f32::EPSILON);
+                let half = Vector2::new(width as f32, height as f32) / (2.0 * zoom);
+                for (m, map) in self.maps.iter().enumerate() {
+                    for (l, layer) in map.layers.iter().enumerate().filter(|(_, layer)| layer.visible) {
+                        // The point of the layer in the middle of the viewport.
+                        let center = Vector2::new(
+                            self.camera.position.x * layer.parallax.x,
+                            self.camera.position.y * layer.parallax.y,
+                        ) - layer.offset;
+                        let (min, max) = (center - half, center + half);
+                        let chunks: Vec<usize> = layer
+                            .chunks
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, chunk)| {
+                                chunk.bounds.0.x < max.x && chunk.bounds.1.x > min.x && chunk.bounds.0.y < max.y && chunk.bounds.1.y > min.y
+                            })
+                            .map(|(i, _)| i)
+                            .collect();
+                        if chunks.is_empty() {
+                            continue;
+                        }
+                        // Pixels to clip space, with y up.
+                        let view_proj = Matrix4::from_nonuniform_scale(1.0 / half.x, -1.0 / half.y, 1.0)
+                            * Matrix4::from_translation(Vector3::new(-center.x, -center.y, 0.0));
+                        draws.push(LayerDraw {
+                            map: m,
+                            layer: l,
+                            offset: uniforms.len() as u32 * self.uniform_stride,
+                            chunks,
+                        });
+                        uniforms.push(LayerUniform {
+                            view_proj: view_proj.into(),
+                            tint: [1.0, 1.0, 1.0, layer.opacity],
+                        });
+                    }
+                }
+            }
+            self.draws.push(draws);
+        }
+
+        if uniforms.len() > self.uniform_capacity {
+            self.uniform_capacity = uniforms.len().next_power_of_two();
+            self.uniform_buffer = create_uniform_buffer(device, self.uniform_stride, self.uniform_capacity);
+            self.uniform_bind_group = create_uniform_bind_group(device, &self.uniform_layout, &self.uniform_buffer);
+        }
+        let mut bytes = vec![0u8; uniforms.len() * self.uniform_stride as usize];
+        for (i, uniform) in uniforms.iter().enumerate() {
+            let start = i * self.uniform_stride as usize;
+            bytes[start..start + std::mem::size_of::<LayerUniform>()].copy_from_slice(bytemuck::bytes_of(uniform));
+        }
+        if !bytes.is_empty() {
+            queue.write_buffer(&self.uniform_buffer, 0, &bytes);
+        }
+    }
+
+    // Draws what `prepare` found visible to camera `index`. Returns the draw calls and triangles.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) -> (u32, u32) {
+        let (mut draw_calls, mut triangles) = (0, 0);
+        let draws = match self.draws.get(index) {
+            Some(draws) if !draws.is_empty() => draws,
+            _ => return (0, 0),
+        };
+        render_pass.push_debug_group("Tilemaps");
+        render_pass.set_pipeline(&self.pipeline);
+        for draw in draws {
+            let map = &self.maps[draw.map];
+            let layer = &map.layers[draw.layer];
+            render_pass.set_bind_group(0, &self.uniform_bind_group, &[draw.offset]);
+            for &c in &draw.chunks {
+                let chunk = &layer.chunks[c];
+                render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(chunk.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                for (tileset, indices) in &chunk.draws {
+                    render_pass.set_bind_group(1, &map.tilesets[*tileset].bind_group, &[]);
+                    render_pass.draw_indexed(indices.clone(), 0, 0..1);
+                    draw_calls += 1;
+                    triangles += indices.len() as u32 / 3;
+                }
+            }
+        }
+        render_pass.pop_debug_group();
+        (draw_calls, triangles)
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    uniform_layout: &wgpu::BindGroupLayout,
+    tileset_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Tilemap Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("tilemap.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Tilemap Pipeline Layout"),
+        bind_group_layouts: &[uniform_layout, tileset_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Tilemap Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[TileVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        // Flipped tiles turn around, so both windings are drawn.
+        primitive: wgpu::PrimitiveState::default(),
+        // Behind the scene, without hiding anything from it.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+fn create_uniform_buffer(device: &wgpu::Device, stride: u32, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Tile Layer Uniform Buffer"),
+        size: stride as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_uniform_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Tile Layer Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(std::mem::size_of::<LayerUniform>() as u64),
+            }),
+        }],
+    })
+}
+
+// Tilemap demo
+//======================
+
+/// A Tiled map behind the scene, filling the window's height, e.g. res/maps/demo.tmx. L shows and
+/// hides the layers above the bottom one.
+pub struct TilemapDemo {
+    map: TilemapId,
+    upper_layers: Vec<String>,
+    upper_visible: bool,
+    toggle_requested: bool,
+}
+
+impl TilemapDemo {
+    pub fn new(gfx: &mut GFX, path: &Path) -> Result<TilemapDemo, LoadError> {
+        let map = gfx.load_tilemap(path)?;
+        let tilemap = gfx.tilemap(map);
+        let (width, height) = tilemap.size();
+        let names: Vec<String> = tilemap.layer_names().map(str::to_string).collect();
+        tracing::info!("Tilemap {}: {}x{} pixels, layers {}", path.display(), width, height, names.join(", "));
+        // Whole screen pixels per map pixel keep the tiles crisp.
+        let zoom = (gfx.render_size().1 as f32 / height.max(1) as f32).floor().max(1.0);
+        *gfx.tilemap_camera_mut() = Camera2d {
+            position: Vector2::new(width as f32 * 0.5, height as f32 * 0.5),
+            zoom,
+        };
+        Ok(TilemapDemo {
+            map,
+            upper_layers: names.into_iter().skip(1).collect(),
+            upper_visible: true,
+            toggle_requested: false,
+        })
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        if *event == Event::KeyPressed(b'L' as u16) {
+            self.toggle_requested = true;
+        }
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        if !std::mem::take(&mut self.toggle_requested) {
+            return;
+        }
+        self.upper_visible = !self.upper_visible;
+        let tilemap = gfx.tilemap_mut(self.map);
+        for name in &self.upper_layers {
+            tilemap.set_layer_visible(name, self.upper_visible);
+        }
+    }
+}
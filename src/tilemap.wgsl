@@ -0,0 +1,32 @@
+// Tile layers, see tilemap.rs.
+
+struct LayerUniform {
+    view_proj: mat4x4<f32>; // From the layer's pixels to clip space, with its offset and parallax.
+    tint: vec4<f32>;        // The layer's opacity, in alpha.
+};
+
+[[group(0), binding(0)]]
+var<uniform> layer: LayerUniform;
+
+[[group(1), binding(0)]]
+var t_tileset: texture_2d<f32>;
+[[group(1), binding(1)]]
+var s_tileset: sampler;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[location(0)]] position: vec2<f32>, [[location(1)]] uv: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = layer.view_proj * vec4<f32>(position, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(t_tileset, s_tileset, in.uv) * layer.tint;
+}
@@ -0,0 +1,176 @@
+use std::time::{Duration, Instant};
+
+use crate::light::DirectionalLight;
+
+// Time
+//======================
+// `Instant` is backed by QueryPerformanceCounter on Windows, so it has sub-microsecond resolution.
+// `Time` keeps two clocks:
+//  - real time, measured once per rendered frame,
+//  - game time, which only advances by whole update ticks, slower or faster with `scale`,
+//    and not at all while paused.
+// Gameplay should use game time; UI and debug overlays may want real time.
+
+pub struct Time {
+    start: Instant,
+    last_frame: Instant,
+    real_delta: f32,
+    delta: f32,
+    elapsed: f64,
+    frame: u64,
+    paused: bool,
+    scale: f32,
+}
+
+impl Time {
+    pub fn new() -> Time {
+        let now = Instant::now();
+        Time {
+            start: now,
+            last_frame: now,
+            real_delta: 0.0,
+            delta: 0.0,
+            elapsed: 0.0,
+            frame: 0,
+            paused: false,
+            scale: 1.0,
+        }
+    }
+
+    // Starts a new frame. Returns the game time to simulate: the real time since the previous
+    // frame (at most `max_delta`) times the scale, or zero while paused.
+    pub(crate) fn begin_frame(&mut self, max_delta: f32) -> f32 {
+        let real_delta = self.last_frame.elapsed().as_secs_f32().min(max_delta);
+        self.begin_frame_with(real_delta)
+    }
+
+    // Starts a new frame that took `real_delta` seconds, as recorded in a replay.
+    pub(crate) fn begin_frame_with(&mut self, real_delta: f32) -> f32 {
+        self.real_delta = real_delta;
+        self.last_frame = Instant::now();
+        self.frame += 1;
+        if self.paused {
+            0.0
+        } else {
+            self.real_delta * self.scale
+        }
+    }
+
+    // Advances game time by one update tick.
+    pub(crate) fn tick(&mut self, dt: f32) {
+        self.delta = dt;
+        self.elapsed += dt as f64;
+    }
+
+    /// Seconds of game time covered by the current update.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    /// Seconds of game time since the start.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// Seconds of real time between the last two frames.
+    pub fn real_delta(&self) -> f32 {
+        self.real_delta
+    }
+
+    /// Real time since the start.
+    pub fn real_elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Number of frames rendered so far, counting the current one.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // 0.5 for slow motion, 2.0 for fast forward.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.max(0.0);
+    }
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The time values shaders see, at group(0) binding(1) next to the camera, and the directional
+// light (light.rs):
+//
+//     struct Globals {
+//         time: f32;
+//         delta_time: f32;
+//         frame: u32;
+//         resolution: vec2<f32>;  // Of the frame the cameras render into, in pixels.
+//         light_direction: vec4<f32>;  // Towards the light, normalized.
+//         light_color: vec4<f32>;
+//         ambient: vec4<f32>;
+//     };
+//     [[group(0), binding(1)]]
+//     var<uniform> globals: Globals;
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GlobalsUniform {
+    time: f32, // Game time, wrapped to keep f32 precision in long sessions.
+    delta_time: f32,
+    frame: u32,
+    _padding: u32,
+    resolution: [f32; 2], // Kept up to date by the GFX when the window is resized.
+    _padding_2: [f32; 2],
+    light_direction: [f32; 4],
+    light_color: [f32; 4],
+    ambient: [f32; 4],
+}
+
+impl GlobalsUniform {
+    pub fn new() -> Self {
+        let mut globals: Self = bytemuck::Zeroable::zeroed();
+        globals.set_light(&DirectionalLight::default());
+        globals
+    }
+
+    pub fn update(&mut self, time: &Time) {
+        self.time = (time.elapsed() % 3600.0) as f32;
+        self.delta_time = time.real_delta();
+        self.frame = time.frame() as u32;
+    }
+
+    // Of the frame being drawn, in real seconds.
+    pub fn delta_time(&self) -> f32 {
+        self.delta_time
+    }
+
+    pub fn set_resolution(&mut self, width: u32, height: u32) {
+        self.resolution = [width as f32, height as f32];
+    }
+
+    pub fn set_light(&mut self, light: &DirectionalLight) {
+        let direction = light.normalized_direction();
+        self.light_direction = [direction.x, direction.y, direction.z, 0.0];
+        self.light_color = [light.color[0], light.color[1], light.color[2], 1.0];
+        self.ambient = [light.ambient[0], light.ambient[1], light.ambient[2], 1.0];
+    }
+}
+
+impl Default for GlobalsUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
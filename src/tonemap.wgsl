@@ -0,0 +1,55 @@
+// Maps the HDR scene into the frame: scaled by the exposure of the average luminance (see
+// luminance.wgsl), clamped to the EV range, then compressed with the ACES curve. See exposure.rs.
+// The bloom (bloom.rs) is added before, and exposed with the scene.
+
+struct TonemapParams {
+    min_ev: f32;
+    max_ev: f32;
+    compensation: f32; // In EV, brighter when positive.
+    bloom_intensity: f32; // 0 without bloom.
+};
+
+struct Average {
+    luminance: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: TonemapParams;
+[[group(0), binding(1)]]
+var scene: texture_2d<f32>;
+[[group(0), binding(2)]]
+var<storage, read> average: Average;
+[[group(0), binding(3)]]
+var bloom: texture_2d<f32>; // Smaller than the scene, black without bloom.
+[[group(0), binding(4)]]
+var bloom_sampler: sampler;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    // A triangle over the whole frame: (-1, -1), (3, -1) and (-1, 3).
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let uv = in.clip_position.xy / vec2<f32>(textureDimensions(scene));
+    let color = textureLoad(scene, vec2<i32>(in.clip_position.xy), 0).rgb
+        + textureSampleLevel(bloom, bloom_sampler, uv, 0.0).rgb * params.bloom_intensity;
+    // EV100 of the average luminance, with the usual light meter constant of 12.5.
+    let ev = clamp(log2(max(average.luminance, 1e-6) * 100.0 / 12.5), params.min_ev, params.max_ev);
+    // Middle gray (0.18) for a scene of the average luminance of `ev`.
+    let exposure = 0.18 / (exp2(ev) * 0.125) * exp2(params.compensation);
+    return vec4<f32>(aces(color * exposure), 1.0);
+}
@@ -0,0 +1,210 @@
+use cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3};
+use serde::{Deserialize, Serialize};
+
+// Transforms
+//======================
+// Every renderable is placed by a translation, rotation and scale, relative to its parent if it
+// has one (`GFX::set_parent`), or to the world otherwise. `Transforms` keeps the world matrices
+// of all renderables and recomputes only those of renderables that changed or whose parents did:
+//
+//     gfx.set_parent(wheel, Some(car));
+//     gfx.set_transform(car, Transform::from_translation(position));  // The wheel moves along.
+//
+// The model matrices of all renderables live in one instance buffer. Each frame only the matrices
+// that changed are uploaded, and the vertex shader reads the matrix of the renderable it draws
+// from locations 5 to 8:
+//
+//     struct InstanceInput {
+//         [[location(5)]] model_0: vec4<f32>;
+//         [[location(6)]] model_1: vec4<f32>;
+//         [[location(7)]] model_2: vec4<f32>;
+//         [[location(8)]] model_3: vec4<f32>;
+//     };
+//
+// Shaders that don't declare these draw their meshes untransformed.
+
+/// Position, orientation and size of a renderable.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: Vector3::new(0.0, 0.0, 0.0),
+        rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    pub fn from_translation(translation: Vector3<f32>) -> Transform {
+        Transform {
+            translation,
+            ..Transform::IDENTITY
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Transform {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vector3<f32>) -> Transform {
+        self.scale = scale;
+        self
+    }
+
+    // Scales first, then rotates, then translates.
+    pub fn to_matrix(self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+// A model matrix as it is laid out in the instance buffer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ModelInstance {
+    model: [[f32; 4]; 4],
+}
+
+impl ModelInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4
+    ];
+
+    pub fn new(world: &Matrix4<f32>) -> ModelInstance {
+        ModelInstance { model: (*world).into() }
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ModelInstance>() as wgpu::BufferAddress,
+            // One matrix per renderable, drawn as a single instance.
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// The transforms of the renderables, by index, with their parents and world matrices.
+#[derive(Default)]
+pub struct Transforms {
+    local: Vec<Transform>,
+    parents: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    world: Vec<Matrix4<f32>>,
+    dirty: Vec<bool>, // The local transform or the parent changed since the last `update`.
+    changed: Vec<usize>, // World matrices recomputed since the last `take_changed`.
+}
+
+impl Transforms {
+    pub fn new() -> Transforms {
+        Transforms::default()
+    }
+
+    // Adds a transform without a parent, returns its index.
+    pub fn push(&mut self, transform: Transform) -> usize {
+        self.local.push(transform);
+        self.parents.push(None);
+        self.children.push(Vec::new());
+        self.world.push(Matrix4::identity());
+        self.dirty.push(true);
+        self.local.len() - 1
+    }
+
+    pub fn local(&self, index: usize) -> &Transform {
+        &self.local[index]
+    }
+
+    pub fn set(&mut self, index: usize, transform: Transform) {
+        // Physics sets the transform of every body every frame, most of them asleep.
+        if self.local[index] != transform {
+            self.local[index] = transform;
+            self.dirty[index] = true;
+        }
+    }
+
+    pub fn parent(&self, index: usize) -> Option<usize> {
+        self.parents[index]
+    }
+
+    // Places `index` relative to `parent`, or to the world with `None`. Returns false, changing
+    // nothing, if `parent` is `index` itself or one of its descendants.
+    pub fn set_parent(&mut self, index: usize, parent: Option<usize>) -> bool {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == index {
+                return false;
+            }
+            ancestor = self.parents[a];
+        }
+        if let Some(old) = self.parents[index] {
+            self.children[old].retain(|&child| child != index);
+        }
+        if let Some(parent) = parent {
+            self.children[parent].push(index);
+        }
+        self.parents[index] = parent;
+        self.dirty[index] = true;
+        true
+    }
+
+    // The world matrix as of the last `update`.
+    pub fn world(&self, index: usize) -> &Matrix4<f32> {
+        &self.world[index]
+    }
+
+    // Recomputes the world matrices of the dirty transforms and their descendants, parents
+    // before children.
+    pub fn update(&mut self) {
+        for index in 0..self.local.len() {
+            // Descendants of a dirty transform are recomputed together with it.
+            if self.dirty[index] && !self.has_dirty_ancestor(index) {
+                self.update_subtree(index);
+            }
+        }
+    }
+
+    // The indices of the world matrices recomputed since the last call, each once.
+    pub fn take_changed(&mut self) -> Vec<usize> {
+        let mut changed = std::mem::take(&mut self.changed);
+        changed.sort_unstable();
+        changed.dedup();
+        changed
+    }
+
+    fn has_dirty_ancestor(&self, index: usize) -> bool {
+        let mut ancestor = self.parents[index];
+        while let Some(a) = ancestor {
+            if self.dirty[a] {
+                return true;
+            }
+            ancestor = self.parents[a];
+        }
+        false
+    }
+
+    fn update_subtree(&mut self, root: usize) {
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            let local = self.local[index].to_matrix();
+            self.world[index] = match self.parents[index] {
+                Some(parent) => self.world[parent] * local,
+                None => local,
+            };
+            self.dirty[index] = false;
+            self.changed.push(index);
+            stack.extend_from_slice(&self.children[index]);
+        }
+    }
+}
@@ -0,0 +1,665 @@
+use crate::game::{Event, MouseButton};
+use crate::gfx::GFX;
+use crate::nine_slice::NineSlice;
+use crate::text::measure_text;
+
+// Retained UI
+//======================
+// A small tree of widgets for menus and settings screens: panels, labels, buttons and sliders.
+// The game builds the tree once, feeds it the window's events and draws it every frame:
+//
+//     let mut ui = Ui::new(UiStyle::default());
+//     let menu = ui.add(ui.root(), Widget::panel(Layout::at([0.5, 0.5], 0.0, 0.0, 200.0, 120.0)));
+//     let play = ui.add(menu, Widget::button(Layout::at([0.5, 0.0], 0.0, 16.0, 160.0, 32.0), "PLAY"));
+//
+//     // Game::on_event                    // Game::render
+//     ui.handle_event(event, gfx);         ui.draw(frame.gfx);
+//     for event in ui.take_events() { if event == UiEvent::Clicked(play) { ... } }
+//
+// Every widget is placed inside its parent's rectangle by its `Layout`: anchors on the parent, as
+// fractions of its size, and offsets from them in UI units. Anchors at the same point pin a widget
+// of a fixed size to it, anchors apart stretch it with the parent. UI units are pixels of the
+// frame divided by `GFX::ui_scale`.
+//
+// Widgets are drawn in tree order, parents before their children, with the nine-slice panels and
+// text of the GFX (see nine_slice.rs and text.rs). The mouse hits the topmost visible widget under
+// it; panels take hits too, so buttons behind a window can't be clicked through it.
+
+/// Identifies a widget added with `Ui::add`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WidgetId(usize);
+
+/// Where a widget goes in its parent's rectangle, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub anchor_min: [f32; 2], // Top left, as fractions of the parent's size.
+    pub anchor_max: [f32; 2], // Bottom right.
+    pub offset_min: [f32; 2], // From `anchor_min`, in UI units.
+    pub offset_max: [f32; 2], // From `anchor_max`.
+}
+
+impl Layout {
+    // Covers the parent, less `margin` on every side.
+    pub fn fill(margin: f32) -> Layout {
+        Layout::stretch([0.0, 0.0], [1.0, 1.0], margin)
+    }
+
+    // Stretches between two anchors, less `margin` on every side.
+    pub fn stretch(anchor_min: [f32; 2], anchor_max: [f32; 2], margin: f32) -> Layout {
+        Layout {
+            anchor_min,
+            anchor_max,
+            offset_min: [margin, margin],
+            offset_max: [-margin, -margin],
+        }
+    }
+
+    // A `width` x `height` rectangle at `anchor`, which is also the point of the rectangle that
+    // sits on the anchor: [0, 0] puts its top left at the parent's top left, [0.5, 0.5] centers it,
+    // [1, 1] puts its bottom right at the parent's bottom right. (`x`, `y`) moves it from there.
+    pub fn at(anchor: [f32; 2], x: f32, y: f32, width: f32, height: f32) -> Layout {
+        let min = [x - anchor[0] * width, y - anchor[1] * height];
+        Layout {
+            anchor_min: anchor,
+            anchor_max: anchor,
+            offset_min: min,
+            offset_max: [min[0] + width, min[1] + height],
+        }
+    }
+
+    // The rectangle in `parent`.
+    fn place(&self, parent: Rect) -> Rect {
+        let point = |anchor: [f32; 2], offset: [f32; 2]| {
+            [
+                parent.x + parent.width * anchor[0] + offset[0],
+                parent.y + parent.height * anchor[1] + offset[1],
+            ]
+        };
+        let min = point(self.anchor_min, self.offset_min);
+        let max = point(self.anchor_max, self.offset_max);
+        Rect {
+            x: min[0],
+            y: min[1],
+            width: (max[0] - min[0]).max(0.0),
+            height: (max[1] - min[1]).max(0.0),
+        }
+    }
+}
+
+/// A laid out rectangle, in UI units from the top left of the frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WidgetKind {
+    Panel,
+    Label { text: String },
+    Button { text: String },
+    Slider { min: f32, max: f32, value: f32 },
+}
+
+/// A node of the UI tree.
+#[derive(Clone, Debug)]
+pub struct Widget {
+    pub kind: WidgetKind,
+    pub layout: Layout,
+    pub visible: bool, // Hidden widgets hide their children too, and take no hits.
+    pub enabled: bool, // Disabled buttons and sliders are drawn faded and ignore the mouse.
+    parent: Option<WidgetId>,
+    children: Vec<WidgetId>,
+    rect: Rect,
+}
+
+impl Widget {
+    fn new(kind: WidgetKind, layout: Layout) -> Widget {
+        Widget {
+            kind,
+            layout,
+            visible: true,
+            enabled: true,
+            parent: None,
+            children: Vec::new(),
+            rect: Rect::default(),
+        }
+    }
+
+    pub fn panel(layout: Layout) -> Widget {
+        Widget::new(WidgetKind::Panel, layout)
+    }
+
+    pub fn label(layout: Layout, text: &str) -> Widget {
+        Widget::new(WidgetKind::Label { text: text.to_string() }, layout)
+    }
+
+    pub fn button(layout: Layout, text: &str) -> Widget {
+        Widget::new(WidgetKind::Button { text: text.to_string() }, layout)
+    }
+
+    pub fn slider(layout: Layout, min: f32, max: f32, value: f32) -> Widget {
+        let value = value.clamp(min.min(max), max.max(min));
+        Widget::new(WidgetKind::Slider { min, max, value }, layout)
+    }
+
+    // Where the last `Ui::layout` put it.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.enabled && matches!(self.kind, WidgetKind::Button { .. } | WidgetKind::Slider { .. })
+    }
+}
+
+/// What the widgets look like. The panels default to flat colors, `NineSlice::solid`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UiStyle {
+    pub panel: NineSlice,
+    pub button: NineSlice,
+    pub slider_track: NineSlice,
+    pub slider_knob: NineSlice,
+    pub panel_color: [f32; 4],
+    // Of buttons and slider knobs, by state.
+    pub normal_color: [f32; 4],
+    pub hovered_color: [f32; 4],
+    pub pressed_color: [f32; 4],
+    pub track_color: [f32; 4],
+    pub text_color: [f32; 4],
+    pub text_scale: f32, // Font pixels per UI unit, see text.rs.
+    pub knob_width: f32, // In UI units.
+}
+
+impl Default for UiStyle {
+    fn default() -> Self {
+        UiStyle {
+            panel: NineSlice::solid(),
+            button: NineSlice::solid(),
+            slider_track: NineSlice::solid(),
+            slider_knob: NineSlice::solid(),
+            panel_color: [0.1, 0.1, 0.12, 0.9],
+            normal_color: [0.25, 0.25, 0.3, 1.0],
+            hovered_color: [0.35, 0.35, 0.42, 1.0],
+            pressed_color: [0.18, 0.18, 0.22, 1.0],
+            track_color: [0.05, 0.05, 0.06, 1.0],
+            text_color: [1.0, 1.0, 1.0, 1.0],
+            text_scale: 2.0,
+            knob_width: 10.0,
+        }
+    }
+}
+
+/// What happened to the widgets, in order, since the last `Ui::take_events`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UiEvent {
+    Clicked(WidgetId),           // Pressed and released over the same button.
+    ValueChanged(WidgetId, f32), // A slider was dragged.
+}
+
+/// The widget tree, its layout and the state of the mouse over it.
+pub struct Ui {
+    widgets: Vec<Widget>, // The root is widget 0.
+    style: UiStyle,
+    size: (f32, f32), // Of the frame at the last layout, in UI units.
+    mouse: Option<(f32, f32)>, // In UI units, `None` outside the frame.
+    hovered: Option<WidgetId>,
+    pressed: Option<WidgetId>, // Where the left button went down, until it goes up.
+    events: Vec<UiEvent>,
+}
+
+impl Ui {
+    pub fn new(style: UiStyle) -> Ui {
+        Ui {
+            widgets: vec![Widget::panel(Layout::fill(0.0))],
+            style,
+            size: (0.0, 0.0),
+            mouse: None,
+            hovered: None,
+            pressed: None,
+            events: Vec::new(),
+        }
+    }
+
+    // Covers the frame and draws nothing itself; the parent of the top level widgets.
+    pub fn root(&self) -> WidgetId {
+        WidgetId(0)
+    }
+
+    pub fn add(&mut self, parent: WidgetId, mut widget: Widget) -> WidgetId {
+        let id = WidgetId(self.widgets.len());
+        widget.parent = Some(parent);
+        self.widgets.push(widget);
+        self.widgets[parent.0].children.push(id);
+        self.layout_subtree(parent);
+        id
+    }
+
+    pub fn get(&self, id: WidgetId) -> &Widget {
+        &self.widgets[id.0]
+    }
+
+    // Layouts changed here take effect with the next `layout` or `draw`.
+    pub fn get_mut(&mut self, id: WidgetId) -> &mut Widget {
+        &mut self.widgets[id.0]
+    }
+
+    pub fn set_text(&mut self, id: WidgetId, new_text: &str) {
+        if let WidgetKind::Label { text } | WidgetKind::Button { text } = &mut self.widgets[id.0].kind {
+            *text = new_text.to_string();
+        }
+    }
+
+    // The value of a slider, `None` for other widgets.
+    pub fn value(&self, id: WidgetId) -> Option<f32> {
+        match self.widgets[id.0].kind {
+            WidgetKind::Slider { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn set_value(&mut self, id: WidgetId, new_value: f32) {
+        if let WidgetKind::Slider { min, max, value } = &mut self.widgets[id.0].kind {
+            *value = new_value.clamp(min.min(*max), max.max(*min));
+        }
+    }
+
+    pub fn is_hovered(&self, id: WidgetId) -> bool {
+        self.hovered == Some(id)
+    }
+
+    pub fn is_pressed(&self, id: WidgetId) -> bool {
+        self.pressed == Some(id) && self.hovered == Some(id)
+    }
+
+    // True while the mouse is over a visible widget other than the root, so the game can ignore
+    // clicks meant for the UI.
+    pub fn wants_mouse(&self) -> bool {
+        self.pressed.is_some() || self.mouse.is_some_and(|(x, y)| self.hit_test(x, y).is_some())
+    }
+
+    // Places every widget in a `width` x `height` frame, in UI units.
+    pub fn layout(&mut self, width: f32, height: f32) {
+        self.size = (width, height);
+        self.layout_subtree(self.root());
+    }
+
+    fn layout_subtree(&mut self, id: WidgetId) {
+        let parent = match self.widgets[id.0].parent {
+            Some(parent) => self.widgets[parent.0].rect,
+            None => Rect {
+                x: 0.0,
+                y: 0.0,
+                width: self.size.0,
+                height: self.size.1,
+            },
+        };
+        let widget = &mut self.widgets[id.0];
+        widget.rect = widget.layout.place(parent);
+        for i in 0..self.widgets[id.0].children.len() {
+            let child = self.widgets[id.0].children[i];
+            self.layout_subtree(child);
+        }
+    }
+
+    // The topmost visible widget at (`x`, `y`), in UI units, not counting the root.
+    pub fn hit_test(&self, x: f32, y: f32) -> Option<WidgetId> {
+        self.hit_subtree(self.root(), x, y).filter(|&id| id != self.root())
+    }
+
+    fn hit_subtree(&self, id: WidgetId, x: f32, y: f32) -> Option<WidgetId> {
+        let widget = &self.widgets[id.0];
+        if !widget.visible {
+            return None;
+        }
+        // Later children are drawn over earlier ones.
+        for &child in widget.children.iter().rev() {
+            if let Some(hit) = self.hit_subtree(child, x, y) {
+                return Some(hit);
+            }
+        }
+        widget.rect.contains(x, y).then_some(id)
+    }
+
+    // Updates the hover and press states from an event of the window. `gfx` converts the mouse
+    // position to UI units.
+    pub fn handle_event(&mut self, event: &Event, gfx: &GFX) {
+        match *event {
+            Event::MouseMoved { x, y } => {
+                let scale = gfx.ui_scale();
+                let mouse = gfx.window_to_virtual(x as f32, y as f32).map(|(x, y)| (x / scale, y / scale));
+                self.move_mouse(mouse);
+            }
+            Event::MousePressed(MouseButton::Left) => self.press(),
+            Event::MouseReleased(MouseButton::Left) => self.release(),
+            Event::FocusLost => {
+                self.pressed = None;
+                self.mouse = None;
+                self.hovered = None;
+            }
+            _ => {}
+        }
+    }
+
+    // `mouse` in UI units, `None` outside the frame.
+    fn move_mouse(&mut self, mouse: Option<(f32, f32)>) {
+        self.mouse = mouse;
+        self.update_hover();
+        self.drag();
+    }
+
+    fn press(&mut self) {
+        self.pressed = self.hovered;
+        self.drag();
+    }
+
+    fn release(&mut self) {
+        if let Some(pressed) = self.pressed.take() {
+            let button = matches!(self.widgets[pressed.0].kind, WidgetKind::Button { .. });
+            if button && self.hovered == Some(pressed) {
+                self.events.push(UiEvent::Clicked(pressed));
+            }
+        }
+    }
+
+    fn update_hover(&mut self) {
+        self.hovered = self
+            .mouse
+            .and_then(|(x, y)| self.hit_test(x, y))
+            .filter(|id| self.widgets[id.0].is_interactive());
+    }
+
+    // Moves the knob of the slider being pressed to the mouse.
+    fn drag(&mut self) {
+        let (id, (x, _)) = match (self.pressed, self.mouse) {
+            (Some(id), Some(mouse)) => (id, mouse),
+            _ => return,
+        };
+        let knob_width = self.style.knob_width;
+        let widget = &mut self.widgets[id.0];
+        let rect = widget.rect;
+        if let WidgetKind::Slider { min, max, value } = &mut widget.kind {
+            let travel = (rect.width - knob_width).max(f32::EPSILON);
+            let t = ((x - rect.x - knob_width / 2.0) / travel).clamp(0.0, 1.0);
+            let new_value = *min + (*max - *min) * t;
+            if new_value != *value {
+                *value = new_value;
+                self.events.push(UiEvent::ValueChanged(id, new_value));
+            }
+        }
+    }
+
+    // What happened since the last call, see `UiEvent`.
+    pub fn take_events(&mut self) -> Vec<UiEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Lays the tree out for the frame and queues it for drawing this frame.
+    pub fn draw(&mut self, gfx: &mut GFX) {
+        let scale = gfx.ui_scale();
+        let (width, height) = gfx.render_size();
+        self.layout(width as f32 / scale, height as f32 / scale);
+        // Hover follows widgets that moved under a still mouse.
+        self.update_hover();
+        self.draw_subtree(gfx, self.root(), scale);
+    }
+
+    fn draw_subtree(&self, gfx: &mut GFX, id: WidgetId, scale: f32) {
+        let widget = &self.widgets[id.0];
+        if !widget.visible {
+            return;
+        }
+        let style = &self.style;
+        let rect = widget.rect;
+        let state_color = if !widget.enabled {
+            fade(style.normal_color)
+        } else if self.is_pressed(id) {
+            style.pressed_color
+        } else if self.is_hovered(id) {
+            style.hovered_color
+        } else {
+            style.normal_color
+        };
+        let text_color = if widget.enabled { style.text_color } else { fade(style.text_color) };
+        match &widget.kind {
+            WidgetKind::Panel if id == self.root() => {}
+            WidgetKind::Panel => gfx.draw_panel(rect.x, rect.y, rect.width, rect.height, &style.panel, style.panel_color),
+            WidgetKind::Label { text } => draw_label(gfx, rect, text, style.text_scale, text_color, scale),
+            WidgetKind::Button { text } => {
+                gfx.draw_panel(rect.x, rect.y, rect.width, rect.height, &style.button, state_color);
+                draw_label(gfx, rect, text, style.text_scale, text_color, scale);
+            }
+            &WidgetKind::Slider { min, max, value } => {
+                let track_height = (rect.height / 4.0).max(2.0);
+                let track_y = rect.y + (rect.height - track_height) / 2.0;
+                gfx.draw_panel(rect.x, track_y, rect.width, track_height, &style.slider_track, style.track_color);
+                let t = if max != min { (value - min) / (max - min) } else { 0.0 };
+                let knob_x = rect.x + (rect.width - style.knob_width).max(0.0) * t;
+                gfx.draw_panel(knob_x, rect.y, style.knob_width, rect.height, &style.slider_knob, state_color);
+            }
+        }
+        for &child in &widget.children {
+            self.draw_subtree(gfx, child, scale);
+        }
+    }
+}
+
+// Centers `text` in `rect`. Text is drawn in pixels, so it is scaled and snapped here.
+fn draw_label(gfx: &mut GFX, rect: Rect, text: &str, text_scale: f32, color: [f32; 4], scale: f32) {
+    let font_scale = (text_scale * scale).round().max(1.0);
+    let (width, height) = measure_text(text, font_scale);
+    let x = ((rect.x + rect.width / 2.0) * scale - width / 2.0).round();
+    let y = ((rect.y + rect.height / 2.0) * scale - height / 2.0).round();
+    gfx.draw_text(x, y, font_scale, color, text);
+}
+
+fn fade([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [r, g, b, a * 0.5]
+}
+
+// UI demo
+//======================
+
+// A menu drawn with the nine-slice frames of res/ui.png: a slider for the UI scale and a button
+// resetting it. U shows and hides the menu.
+pub struct UiDemo {
+    ui: Ui,
+    menu: WidgetId,
+    scale_label: WidgetId,
+    scale: WidgetId,
+    reset: WidgetId,
+    events: Vec<Event>, // Of the window since the last frame; the UI needs the GFX to handle them.
+    toggle_requested: bool,
+}
+
+impl UiDemo {
+    pub fn new(gfx: &mut GFX) -> UiDemo {
+        let mut style = UiStyle::default();
+        match gfx.load_ui_atlas("res/ui.png") {
+            // A plain frame and one with cut corners, both with 4 texel borders.
+            Ok(atlas) => {
+                let frame = NineSlice::new(atlas, [0, 0, 16, 16], [4, 4, 4, 4]).with_border_scale(2.0);
+                let button = NineSlice::new(atlas, [16, 0, 16, 16], [4, 4, 4, 4]);
+                style.panel = frame;
+                style.button = button;
+                style.slider_knob = button;
+            }
+            Err(e) => tracing::warn!("UI demo: {}, drawing flat panels", e),
+        }
+        let mut ui = Ui::new(style);
+        let menu = ui.add(ui.root(), Widget::panel(Layout::at([0.0, 0.0], 16.0, 16.0, 220.0, 140.0)));
+        ui.add(menu, Widget::label(Layout::at([0.5, 0.0], 0.0, 14.0, 200.0, 16.0), "UI DEMO"));
+        let scale_label = ui.add(menu, Widget::label(Layout::at([0.5, 0.0], 0.0, 38.0, 200.0, 16.0), ""));
+        // Stretched across the menu, less a margin, 20 units high under the label.
+        let track = Layout {
+            anchor_min: [0.0, 0.0],
+            anchor_max: [1.0, 0.0],
+            offset_min: [20.0, 60.0],
+            offset_max: [-20.0, 80.0],
+        };
+        let scale = ui.add(menu, Widget::slider(track, 1.0, 3.0, gfx.ui_scale()));
+        let reset = ui.add(menu, Widget::button(Layout::at([0.5, 1.0], 0.0, -16.0, 120.0, 32.0), "RESET"));
+        let mut demo = UiDemo {
+            ui,
+            menu,
+            scale_label,
+            scale,
+            reset,
+            events: Vec::new(),
+            toggle_requested: false,
+        };
+        demo.set_scale(gfx, gfx.ui_scale());
+        demo
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        if *event == Event::KeyPressed(b'U' as u16) {
+            self.toggle_requested = true;
+        }
+        self.events.push(*event);
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        if std::mem::take(&mut self.toggle_requested) {
+            let menu = self.ui.get_mut(self.menu);
+            menu.visible = !menu.visible;
+        }
+        for event in std::mem::take(&mut self.events) {
+            self.ui.handle_event(&event, gfx);
+        }
+        for event in self.ui.take_events() {
+            match event {
+                UiEvent::ValueChanged(id, value) if id == self.scale => self.set_scale(gfx, value),
+                UiEvent::Clicked(id) if id == self.reset => self.set_scale(gfx, 1.0),
+                _ => {}
+            }
+        }
+        self.ui.draw(gfx);
+    }
+
+    fn set_scale(&mut self, gfx: &mut GFX, scale: f32) {
+        // Whole and half steps keep the borders of the frames on whole pixels.
+        let scale = (scale * 2.0).round() / 2.0;
+        gfx.set_ui_scale(scale);
+        self.ui.set_value(self.scale, scale);
+        self.ui.set_text(self.scale_label, &format!("SCALE {:.1}", scale));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    #[test]
+    fn anchored_widgets_keep_their_size() {
+        let mut ui = Ui::new(UiStyle::default());
+        let menu = ui.add(ui.root(), Widget::panel(Layout::at([0.5, 0.5], 0.0, 0.0, 200.0, 100.0)));
+        let corner = ui.add(menu, Widget::label(Layout::at([1.0, 1.0], -5.0, -5.0, 40.0, 10.0), "HI"));
+        let play = ui.add(menu, Widget::button(Layout::at([0.5, 0.0], 0.0, 16.0, 160.0, 32.0), "PLAY"));
+        ui.layout(800.0, 600.0);
+        assert_eq!(ui.get(menu).rect(), rect(300.0, 250.0, 200.0, 100.0));
+        assert_eq!(ui.get(corner).rect(), rect(455.0, 335.0, 40.0, 10.0));
+        assert_eq!(ui.get(play).rect(), rect(320.0, 266.0, 160.0, 32.0));
+        // The menu stays centered, at its size, in another frame.
+        ui.layout(400.0, 300.0);
+        assert_eq!(ui.get(menu).rect(), rect(100.0, 100.0, 200.0, 100.0));
+        assert_eq!(ui.get(play).rect(), rect(120.0, 116.0, 160.0, 32.0));
+    }
+
+    #[test]
+    fn stretched_widgets_follow_their_parent() {
+        let mut ui = Ui::new(UiStyle::default());
+        let window = ui.add(ui.root(), Widget::panel(Layout::fill(10.0)));
+        let bar = ui.add(window, Widget::panel(Layout::stretch([0.0, 1.0], [1.0, 1.0], 0.0)));
+        ui.get_mut(bar).layout.offset_min = [4.0, -20.0];
+        ui.get_mut(bar).layout.offset_max = [-4.0, 0.0];
+        let half = ui.add(window, Widget::panel(Layout::stretch([0.5, 0.0], [1.0, 1.0], 5.0)));
+        ui.layout(200.0, 100.0);
+        assert_eq!(ui.get(window).rect(), rect(10.0, 10.0, 180.0, 80.0));
+        assert_eq!(ui.get(bar).rect(), rect(14.0, 70.0, 172.0, 20.0));
+        assert_eq!(ui.get(half).rect(), rect(105.0, 15.0, 80.0, 70.0));
+        ui.layout(400.0, 200.0);
+        assert_eq!(ui.get(bar).rect(), rect(14.0, 170.0, 372.0, 20.0));
+        assert_eq!(ui.get(half).rect(), rect(205.0, 15.0, 180.0, 170.0));
+        // Margins wider than the parent leave nothing rather than a negative size.
+        ui.layout(15.0, 15.0);
+        assert_eq!((ui.get(window).rect().width, ui.get(half).rect().height), (0.0, 0.0));
+    }
+
+    #[test]
+    fn the_topmost_visible_widget_takes_the_hit() {
+        let mut ui = Ui::new(UiStyle::default());
+        let back = ui.add(ui.root(), Widget::panel(Layout::at([0.0, 0.0], 0.0, 0.0, 100.0, 100.0)));
+        let button = ui.add(back, Widget::button(Layout::at([0.0, 0.0], 10.0, 10.0, 50.0, 20.0), "OK"));
+        let front = ui.add(ui.root(), Widget::panel(Layout::at([0.0, 0.0], 50.0, 0.0, 100.0, 100.0)));
+        ui.layout(200.0, 200.0);
+        assert_eq!(ui.hit_test(20.0, 15.0), Some(button)); // Children over their parent.
+        assert_eq!(ui.hit_test(55.0, 15.0), Some(front)); // Later siblings over earlier ones.
+        assert_eq!(ui.hit_test(5.0, 50.0), Some(back));
+        assert_eq!(ui.hit_test(180.0, 180.0), None); // Not the root.
+
+        ui.get_mut(front).visible = false;
+        assert_eq!(ui.hit_test(55.0, 15.0), Some(button));
+        ui.get_mut(back).visible = false;
+        assert_eq!(ui.hit_test(20.0, 15.0), None); // Hidden with its parent.
+    }
+
+    #[test]
+    fn buttons_click_when_released_over_them() {
+        let mut ui = Ui::new(UiStyle::default());
+        let panel = ui.add(ui.root(), Widget::panel(Layout::at([0.0, 0.0], 0.0, 0.0, 100.0, 100.0)));
+        let button = ui.add(panel, Widget::button(Layout::at([0.0, 0.0], 10.0, 10.0, 50.0, 20.0), "OK"));
+        ui.layout(200.0, 200.0);
+
+        ui.move_mouse(Some((20.0, 15.0)));
+        assert!(ui.is_hovered(button) && ui.wants_mouse());
+        ui.press();
+        assert!(ui.is_pressed(button));
+        ui.release();
+        assert_eq!(ui.take_events(), [UiEvent::Clicked(button)]);
+
+        // Dragged off before the release.
+        ui.press();
+        ui.move_mouse(Some((80.0, 80.0)));
+        assert!(!ui.is_pressed(button) && ui.wants_mouse()); // The panel takes the mouse too.
+        ui.release();
+        assert!(ui.take_events().is_empty());
+
+        ui.get_mut(button).enabled = false;
+        ui.move_mouse(Some((20.0, 15.0)));
+        ui.press();
+        ui.release();
+        assert!(!ui.is_hovered(button) && ui.take_events().is_empty());
+        ui.move_mouse(Some((150.0, 150.0)));
+        assert!(!ui.wants_mouse());
+    }
+
+    #[test]
+    fn sliders_follow_the_mouse_within_their_range() {
+        let mut ui = Ui::new(UiStyle::default());
+        // 110 units wide, so the 10 unit knob travels 100 units from x = 5 to 105.
+        let slider = ui.add(ui.root(), Widget::slider(Layout::at([0.0, 0.0], 0.0, 0.0, 110.0, 20.0), 0.0, 10.0, 20.0));
+        ui.layout(200.0, 200.0);
+        assert_eq!(ui.value(slider), Some(10.0));
+        assert_eq!(ui.value(ui.root()), None);
+
+        ui.move_mouse(Some((30.0, 10.0)));
+        ui.press();
+        ui.move_mouse(Some((180.0, 50.0)));
+        ui.release();
+        assert_eq!(ui.take_events(), [UiEvent::ValueChanged(slider, 2.5), UiEvent::ValueChanged(slider, 10.0)]);
+        assert_eq!(ui.value(slider), Some(10.0));
+    }
+}
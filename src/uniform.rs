@@ -0,0 +1,297 @@
+use cgmath::{Matrix2, Matrix3, Matrix4, Point3, Vector2, Vector3, Vector4};
+
+// WGSL uniform layout
+//======================
+// Uniform buffers follow the WGSL memory layout rules, which do not match `#[repr(C)]`:
+// a vec3 is aligned like a vec4 but only 12 bytes large, matrix columns are padded to vec4s,
+// and structs and arrays inside a uniform buffer start and end at 16 byte boundaries.
+// `UniformLayout` describes the WGSL alignment and size of a type,
+// and writes it into a byte buffer with all padding in place.
+//
+// Structs are declared with the `uniform_struct!` macro, which derives the layout from
+// the fields, and `assert_uniform_size!` checks the resulting size at compile time:
+//
+//     uniform_struct! {
+//         pub struct Light {
+//             pub direction: Vector3<f32>, // offset 0
+//             pub intensity: f32,          // offset 12, fills the vec3 padding
+//             pub color: Vector3<f32>,     // offset 16
+//         }
+//     }
+//     assert_uniform_size!(Light, 32);
+
+/// The layout of a type inside a WGSL uniform buffer.
+pub trait UniformLayout {
+    /// AlignOf(T) in WGSL.
+    const ALIGN: usize;
+    /// SizeOf(T) in WGSL.
+    const SIZE: usize;
+    /// Structs and arrays: aligned and padded to 16 bytes when nested in a uniform buffer.
+    const COMPOSITE: bool = false;
+
+    /// Writes the value into `out`, which is exactly `SIZE` bytes large.
+    fn write_bytes(&self, out: &mut [u8]);
+
+    /// The value as it should be copied into a uniform buffer.
+    fn to_uniform_bytes(&self) -> Vec<u8> {
+        let mut out = vec![0; Self::SIZE];
+        self.write_bytes(&mut out);
+        out
+    }
+}
+
+pub const fn align_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+// Alignment of `T` when it is a member of a struct in a uniform buffer.
+pub const fn member_align<T: UniformLayout>() -> usize {
+    if T::COMPOSITE {
+        align_up(T::ALIGN, 16)
+    } else {
+        T::ALIGN
+    }
+}
+
+// Number of bytes `T` occupies as a member of a struct in a uniform buffer.
+pub const fn member_size<T: UniformLayout>() -> usize {
+    if T::COMPOSITE {
+        align_up(T::SIZE, 16)
+    } else {
+        T::SIZE
+    }
+}
+
+macro_rules! impl_scalar_layout {
+    ($($ty:ty),*) => {$(
+        impl UniformLayout for $ty {
+            const ALIGN: usize = 4;
+            const SIZE: usize = 4;
+            fn write_bytes(&self, out: &mut [u8]) {
+                out.copy_from_slice(&self.to_ne_bytes());
+            }
+        }
+    )*};
+}
+impl_scalar_layout!(f32, i32, u32);
+
+// Writes consecutive scalars starting at the beginning of `out`.
+fn write_scalars<T: UniformLayout>(scalars: &[T], out: &mut [u8]) {
+    for (i, scalar) in scalars.iter().enumerate() {
+        scalar.write_bytes(&mut out[i * T::SIZE..(i + 1) * T::SIZE]);
+    }
+}
+
+macro_rules! impl_vector_layout {
+    ($($vec:ident, $n:expr, $align:expr);*) => {$(
+        impl<T: UniformLayout + Copy> UniformLayout for $vec<T> {
+            const ALIGN: usize = $align * T::ALIGN;
+            const SIZE: usize = $n * T::SIZE;
+            fn write_bytes(&self, out: &mut [u8]) {
+                let scalars: [T; $n] = (*self).into();
+                write_scalars(&scalars, out);
+            }
+        }
+    )*};
+}
+// vec3 is aligned like a vec4.
+impl_vector_layout!(Vector2, 2, 2; Vector3, 3, 4; Vector4, 4, 4);
+
+impl UniformLayout for Point3<f32> {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 12;
+    fn write_bytes(&self, out: &mut [u8]) {
+        write_scalars(&[self.x, self.y, self.z], out);
+    }
+}
+
+// Matrices are arrays of column vectors.
+macro_rules! impl_matrix_layout {
+    ($($mat:ident, $col:ident, $n:expr);*) => {$(
+        impl UniformLayout for $mat<f32> {
+            const ALIGN: usize = <$col<f32>>::ALIGN;
+            const SIZE: usize = $n * align_up(<$col<f32>>::SIZE, <$col<f32>>::ALIGN);
+            fn write_bytes(&self, out: &mut [u8]) {
+                let stride = align_up(<$col<f32>>::SIZE, <$col<f32>>::ALIGN);
+                for i in 0..$n {
+                    self[i].write_bytes(&mut out[i * stride..i * stride + <$col<f32>>::SIZE]);
+                }
+            }
+        }
+    )*};
+}
+impl_matrix_layout!(Matrix2, Vector2, 2; Matrix3, Vector3, 3; Matrix4, Vector4, 4);
+
+// array<T, N>: the element stride is rounded up to 16 bytes in uniform buffers.
+impl<T: UniformLayout, const N: usize> UniformLayout for [T; N] {
+    const ALIGN: usize = T::ALIGN;
+    const SIZE: usize = N * align_up(align_up(T::SIZE, T::ALIGN), 16);
+    const COMPOSITE: bool = true;
+    fn write_bytes(&self, out: &mut [u8]) {
+        let stride = align_up(align_up(T::SIZE, T::ALIGN), 16);
+        for (i, element) in self.iter().enumerate() {
+            element.write_bytes(&mut out[i * stride..i * stride + T::SIZE]);
+        }
+    }
+}
+
+/// Declares a struct whose `UniformLayout` follows the WGSL rules for its fields.
+/// Fields must be declared in the same order as in the WGSL struct.
+macro_rules! uniform_struct {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($field_vis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $($field_vis $field: $ty),*
+        }
+
+        impl crate::uniform::UniformLayout for $name {
+            // A struct is aligned like its most aligned member.
+            const ALIGN: usize = {
+                let mut align = 1;
+                $(
+                    let member = crate::uniform::member_align::<$ty>();
+                    if member > align {
+                        align = member;
+                    }
+                )*
+                align
+            };
+            // The size is rounded up to the struct alignment.
+            const SIZE: usize = {
+                let mut offset = 0;
+                $(
+                    offset = crate::uniform::align_up(offset, crate::uniform::member_align::<$ty>())
+                        + crate::uniform::member_size::<$ty>();
+                )*
+                crate::uniform::align_up(offset, Self::ALIGN)
+            };
+            const COMPOSITE: bool = true;
+
+            fn write_bytes(&self, out: &mut [u8]) {
+                let mut offset = 0;
+                $(
+                    offset = crate::uniform::align_up(offset, crate::uniform::member_align::<$ty>());
+                    crate::uniform::UniformLayout::write_bytes(
+                        &self.$field,
+                        &mut out[offset..offset + <$ty as crate::uniform::UniformLayout>::SIZE],
+                    );
+                    offset += crate::uniform::member_size::<$ty>();
+                )*
+                let _ = offset;
+            }
+        }
+    };
+}
+
+/// Fails to compile unless the WGSL size of `$ty` is `$size` bytes.
+macro_rules! assert_uniform_size {
+    ($ty:ty, $size:expr) => {
+        const _: () = assert!(
+            <$ty as crate::uniform::UniformLayout>::SIZE == $size,
+            "uniform struct size does not match the WGSL layout"
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    uniform_struct! {
+        struct Light {
+            direction: Vector3<f32>,
+            intensity: f32,
+            color: Vector3<f32>,
+        }
+    }
+
+    uniform_struct! {
+        struct Packed {
+            scale: f32,
+            position: Vector3<f32>,
+            offsets: [f32; 3],
+            light: Light,
+            tail: f32,
+        }
+    }
+
+    fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn vec3_shares_its_padding_with_a_following_f32() {
+        assert_eq!(Light::ALIGN, 16);
+        assert_eq!(Light::SIZE, 32);
+        let light = Light {
+            direction: Vector3::new(1.0, 2.0, 3.0),
+            intensity: 4.0,
+            color: Vector3::new(5.0, 6.0, 7.0),
+        };
+        let bytes = light.to_uniform_bytes();
+        assert_eq!(bytes.len(), 32);
+        // direction at 0, intensity at 12, color at 16, then 4 bytes of padding.
+        let values: Vec<f32> = (0..8).map(|i| f32_at(&bytes, i * 4)).collect();
+        assert_eq!(values, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 0.0]);
+    }
+
+    #[test]
+    fn arrays_have_a_16_byte_stride() {
+        assert_eq!(<[f32; 3]>::SIZE, 48);
+        assert_eq!(<[Vector2<f32>; 2]>::SIZE, 32);
+        assert_eq!(<[Vector4<f32>; 2]>::SIZE, 32);
+        assert_eq!(<[Light; 2]>::SIZE, 64);
+        let bytes = [1.0f32, 2.0, 3.0].to_uniform_bytes();
+        assert_eq!((f32_at(&bytes, 0), f32_at(&bytes, 16), f32_at(&bytes, 32)), (1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn matrix_columns_are_padded_to_vec4s() {
+        assert_eq!(Matrix2::<f32>::SIZE, 16);
+        assert_eq!(Matrix3::<f32>::SIZE, 48);
+        assert_eq!(Matrix4::<f32>::SIZE, 64);
+        let bytes = Matrix3::new(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0).to_uniform_bytes();
+        assert_eq!((f32_at(&bytes, 0), f32_at(&bytes, 16), f32_at(&bytes, 32)), (1.0, 4.0, 7.0));
+        assert_eq!(f32_at(&bytes, 12), 0.0);
+    }
+
+    #[test]
+    fn nested_arrays_and_structs_start_at_16_bytes() {
+        // scale at 0, position at 16, offsets at 32 (48 bytes), light at 80 (32 bytes), tail at
+        // 112, rounded up to the struct alignment.
+        assert_eq!(Packed::SIZE, 128);
+        let packed = Packed {
+            scale: 1.0,
+            position: Vector3::new(2.0, 3.0, 4.0),
+            offsets: [5.0, 6.0, 7.0],
+            light: Light {
+                direction: Vector3::new(8.0, 9.0, 10.0),
+                intensity: 11.0,
+                color: Vector3::new(12.0, 13.0, 14.0),
+            },
+            tail: 15.0,
+        };
+        let bytes = packed.to_uniform_bytes();
+        let expected = [
+            (0, 1.0),
+            (16, 2.0),
+            (24, 4.0),
+            (32, 5.0),
+            (48, 6.0),
+            (64, 7.0),
+            (80, 8.0),
+            (92, 11.0),
+            (96, 12.0),
+            (112, 15.0),
+        ];
+        for (offset, value) in expected {
+            assert_eq!(f32_at(&bytes, offset), value, "at offset {}", offset);
+        }
+        assert_eq!(f32_at(&bytes, 4), 0.0);
+    }
+}
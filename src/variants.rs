@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::reflection::ReflectError;
+
+// Shader variants
+//======================
+// One WGSL source often covers several closely related shaders: with and without shadows, with
+// vertex colors or an instance color, one light or four. Instead of keeping copies of the source
+// in sync, material shaders may contain preprocessor lines that are resolved with a set of
+// defines before the source is compiled:
+//
+//     #ifdef SHADOWS            keeps the lines up to the #else or #endif if SHADOWS is defined
+//     #ifndef SHADOWS           ... if it is not
+//     #if INSTANCE_COLOR        ... if it is defined and not 0
+//     #else
+//     #endif
+//     let count = #{LIGHTS}u;   replaced by the value of LIGHTS
+//
+// Directives are on lines of their own and nest. Removed lines are kept as empty lines, so the line
+// numbers in compile errors match the source.
+//
+// The defines a material is drawn with are, from weakest to strongest: the defaults of its shader,
+// the global ones of the GFX (e.g. quality settings), the material's own, and those of the mesh
+// (e.g. `PACKED_VERTICES`). Every combination is compiled once, when it is first drawn, and
+// cached together with its pipelines, see material.rs.
+
+/// Names and values for the shader preprocessor. Flags are defines with the value 1.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ShaderDefines(BTreeMap<String, i64>);
+
+impl ShaderDefines {
+    pub fn new() -> ShaderDefines {
+        ShaderDefines::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: i64) {
+        self.0.insert(name.to_string(), value);
+    }
+
+    // Defines the flag, or removes it when `on` is false, so `#ifdef` and `#if` agree.
+    pub fn set_flag(&mut self, name: &str, on: bool) {
+        if on {
+            self.set(name, 1);
+        } else {
+            self.0.remove(name);
+        }
+    }
+
+    pub fn with(mut self, name: &str, value: i64) -> ShaderDefines {
+        self.set(name, value);
+        self
+    }
+
+    pub fn with_flag(mut self, name: &str, on: bool) -> ShaderDefines {
+        self.set_flag(name, on);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<i64> {
+        self.0.get(name).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // These defines with those of `other` added; `other` wins where both define a name.
+    pub fn merged(&self, other: &ShaderDefines) -> ShaderDefines {
+        let mut merged = self.clone();
+        merged.0.extend(other.0.iter().map(|(name, value)| (name.clone(), *value)));
+        merged
+    }
+}
+
+// E.g. "LIGHTS=4 SHADOWS=1", for labels and logs.
+impl fmt::Display for ShaderDefines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (name, value)) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+// An #ifdef, #ifndef or #if block that is open.
+struct Block {
+    outer: bool,     // The lines around the block are kept.
+    condition: bool, // The lines before #else are kept, if the outer ones are.
+    in_else: bool,
+}
+
+impl Block {
+    fn active(&self) -> bool {
+        self.outer && self.condition != self.in_else
+    }
+}
+
+// Resolves the directives of `source`, see above.
+pub fn preprocess(source: &str, defines: &ShaderDefines) -> Result<String, ReflectError> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut output = String::with_capacity(source.len());
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: String| ReflectError::Preprocess(format!("line {}: {}", number + 1, message));
+        let active = blocks.last().is_none_or(Block::active);
+
+        let trimmed = line.trim();
+        if trimmed.starts_with('#') && !trimmed.starts_with("#{") {
+            let mut words = trimmed[1..].split_whitespace();
+            let (directive, name) = (words.next().unwrap_or(""), words.next());
+            let name = |directive: &str| name.ok_or_else(|| error(format!("#{} needs a name", directive)));
+            match directive {
+                "ifdef" => blocks.push(Block {
+                    outer: active,
+                    condition: defines.get(name("ifdef")?).is_some(),
+                    in_else: false,
+                }),
+                "ifndef" => blocks.push(Block {
+                    outer: active,
+                    condition: defines.get(name("ifndef")?).is_none(),
+                    in_else: false,
+                }),
+                "if" => blocks.push(Block {
+                    outer: active,
+                    condition: defines.get(name("if")?).is_some_and(|value| value != 0),
+                    in_else: false,
+                }),
+                "else" => match blocks.last_mut() {
+                    Some(block) if !block.in_else => block.in_else = true,
+                    Some(_) => return Err(error("second #else in the same block".into())),
+                    None => return Err(error("#else without #if".into())),
+                },
+                "endif" => {
+                    blocks.pop().ok_or_else(|| error("#endif without #if".into()))?;
+                }
+                _ => return Err(error(format!("unknown directive #{}", directive))),
+            }
+            output.push('\n');
+            continue;
+        }
+
+        if active {
+            substitute(line, defines, &mut output).map_err(error)?;
+        }
+        output.push('\n');
+    }
+    if !blocks.is_empty() {
+        return Err(ReflectError::Preprocess(format!("{} #if without #endif", blocks.len())));
+    }
+    Ok(output)
+}
+
+// Appends `line` with every #{NAME} replaced by its value.
+fn substitute(line: &str, defines: &ShaderDefines, output: &mut String) -> Result<(), String> {
+    let mut rest = line;
+    while let Some(start) = rest.find("#{") {
+        output.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or("#{ without }")? + start;
+        let name = rest[start + 2..end].trim();
+        let value = defines.get(name).ok_or_else(|| format!("{} is not defined", name))?;
+        output.push_str(&value.to_string());
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The kept lines, without the empty ones left for removed lines.
+    fn kept(source: &str, defines: &ShaderDefines) -> Vec<String> {
+        let output = preprocess(source, defines).unwrap();
+        output.lines().filter(|line| !line.is_empty()).map(str::to_string).collect()
+    }
+
+    fn error(source: &str) -> String {
+        match preprocess(source, &ShaderDefines::new()) {
+            Err(ReflectError::Preprocess(message)) => message,
+            other => panic!("expected a preprocessor error, got {:?}", other),
+        }
+    }
+
+    const NESTED: &str = "\
+a
+#ifdef OUTER
+b
+#ifndef INNER
+c
+#else
+d
+#endif
+e
+#else
+f
+#if INNER
+g
+#endif
+#endif
+h";
+
+    #[test]
+    fn nested_blocks_keep_the_taken_branches() {
+        let defines = |outer: bool, inner: i64| ShaderDefines::new().with_flag("OUTER", outer).with("INNER", inner);
+        assert_eq!(kept(NESTED, &ShaderDefines::new()), ["a", "f", "h"]);
+        assert_eq!(kept(NESTED, &ShaderDefines::new().with_flag("OUTER", true)), ["a", "b", "c", "e", "h"]);
+        assert_eq!(kept(NESTED, &defines(true, 0)), ["a", "b", "d", "e", "h"]);
+        // `#if` needs a value other than 0, `#ifdef` only the name.
+        assert_eq!(kept(NESTED, &defines(false, 0)), ["a", "f", "h"]);
+        assert_eq!(kept(NESTED, &defines(false, 2)), ["a", "f", "g", "h"]);
+    }
+
+    #[test]
+    fn removed_lines_keep_the_line_numbers() {
+        let output = preprocess(NESTED, &ShaderDefines::new()).unwrap();
+        assert_eq!(output.lines().count(), NESTED.lines().count());
+        assert_eq!(output.lines().nth(10), Some("f"));
+    }
+
+    #[test]
+    fn values_are_substituted() {
+        let defines = ShaderDefines::new().with("LIGHTS", 4).with("SIZE", -8);
+        let source = "let count = #{LIGHTS}u;\nlet size = vec2<i32>(#{ SIZE }, #{SIZE});";
+        assert_eq!(kept(source, &defines), ["let count = 4u;", "let size = vec2<i32>(-8, -8);"]);
+        // Only in kept lines.
+        assert_eq!(kept("#ifdef NONE\n#{NONE}\n#endif\nx", &defines), ["x"]);
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert_eq!(error("a\nlet count = #{LIGHTS}u;"), "line 2: LIGHTS is not defined");
+        assert_eq!(error("#{LIGHTS"), "line 1: #{ without }");
+        assert_eq!(error("#ifdef"), "line 1: #ifdef needs a name");
+        assert_eq!(error("#include \"common.wgsl\""), "line 1: unknown directive #include");
+    }
+
+    #[test]
+    fn unbalanced_directives_are_errors() {
+        assert_eq!(error("a\n#endif"), "line 2: #endif without #if");
+        assert_eq!(error("#else"), "line 1: #else without #if");
+        assert_eq!(error("#ifdef A\n#else\n#else\n#endif"), "line 3: second #else in the same block");
+        assert_eq!(error("#ifdef A\n#ifdef B\n#endif"), "1 #if without #endif");
+    }
+}
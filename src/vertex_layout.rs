@@ -0,0 +1,343 @@
+use crate::packing;
+use crate::variants::ShaderDefines;
+
+// Flexible vertex layouts
+//======================
+// `Vertex` has a position and a color, which is all the built-in shaders need. Meshes for
+// textured or lit materials carry other attributes, and different meshes carry different ones.
+// Instead of a vertex struct per combination, such meshes are built from separate attribute
+// streams (`VertexData`), interleaved in a fixed order when the mesh is created:
+//
+//     attribute   format       location   define
+//     position    Float32x3    0
+//     color       Float32x3    1          VERTEX_COLOR
+//     uv          Float32x2    2          VERTEX_UV
+//     uv2         Float32x2    3          VERTEX_UV2, e.g. for light maps
+//     normal      Float32x3    4          VERTEX_NORMAL
+//     tangent     Float32x4    9          VERTEX_TANGENT, w is the handedness (-1 or 1)
+//
+// Locations 5 to 8 are the model matrix of the instance (transform.rs), so tangents come after it.
+// Every attribute a mesh has is defined for its shader variant (variants.rs), so one shader can
+// read what is there:
+//
+//     struct VertexInput {
+//         [[location(0)]] position: vec3<f32>;
+//     #ifdef VERTEX_UV
+//         [[location(2)]] uv: vec2<f32>;
+//     #endif
+//     };
+//
+// Packed layouts (packing.rs) use the compressed formats instead: half-float positions and uvs,
+// 8-bit colors, and 10-10-10-2 normals and tangents, read as a `u32` with `PACKED_VERTICES`
+// defined. The attributes select the pipeline like the other layouts, see `VertexLayout`.
+
+pub const POSITION_LOCATION: u32 = 0;
+pub const COLOR_LOCATION: u32 = 1;
+pub const UV_LOCATION: u32 = 2;
+pub const UV2_LOCATION: u32 = 3;
+pub const NORMAL_LOCATION: u32 = 4;
+pub const TANGENT_LOCATION: u32 = 9;
+
+/// The attributes of a flexible vertex layout besides the position, see above.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VertexAttributes {
+    pub color: bool,
+    pub uv: bool,
+    pub uv2: bool,
+    pub normal: bool,
+    pub tangent: bool,
+    /// Compressed formats, see above.
+    pub packed: bool,
+}
+
+impl VertexAttributes {
+    pub fn builder() -> VertexLayoutBuilder {
+        VertexLayoutBuilder::default()
+    }
+
+    // In the order they are interleaved, with their formats and shader defines.
+    fn enabled(self) -> impl Iterator<Item = (u32, wgpu::VertexFormat, &'static str)> {
+        use wgpu::VertexFormat::*;
+        let format = |full, packed| if self.packed { packed } else { full };
+        [
+            (self.color, COLOR_LOCATION, format(Float32x3, packing::COLOR_FORMAT), "VERTEX_COLOR"),
+            (self.uv, UV_LOCATION, format(Float32x2, packing::UV_FORMAT), "VERTEX_UV"),
+            (self.uv2, UV2_LOCATION, format(Float32x2, packing::UV_FORMAT), "VERTEX_UV2"),
+            (self.normal, NORMAL_LOCATION, format(Float32x3, packing::NORMAL_FORMAT), "VERTEX_NORMAL"),
+            (self.tangent, TANGENT_LOCATION, format(Float32x4, packing::NORMAL_FORMAT), "VERTEX_TANGENT"),
+        ]
+        .into_iter()
+        .filter(|(on, ..)| *on)
+        .map(|(_, location, format, define)| (location, format, define))
+    }
+
+    // The interleaved attributes, with their offsets.
+    pub fn attributes(self) -> Vec<wgpu::VertexAttribute> {
+        let mut offset = 0;
+        let mut attributes = Vec::new();
+        let position = (POSITION_LOCATION, self.position_format());
+        for (shader_location, format) in std::iter::once(position).chain(self.enabled().map(|(l, f, _)| (l, f))) {
+            attributes.push(wgpu::VertexAttribute {
+                format,
+                offset,
+                shader_location,
+            });
+            offset += format.size();
+        }
+        attributes
+    }
+
+    fn position_format(self) -> wgpu::VertexFormat {
+        if self.packed {
+            packing::POSITION_FORMAT
+        } else {
+            wgpu::VertexFormat::Float32x3
+        }
+    }
+
+    // Bytes per vertex.
+    pub fn stride(self) -> wgpu::BufferAddress {
+        self.position_format().size() + self.enabled().map(|(_, format, _)| format.size()).sum::<u64>()
+    }
+
+    pub fn defines(self) -> ShaderDefines {
+        self.enabled()
+            .fold(ShaderDefines::new(), |defines, (_, _, define)| defines.with_flag(define, true))
+            .with_flag("PACKED_VERTICES", self.packed)
+    }
+}
+
+/// Picks the attributes of a flexible vertex layout:
+///
+/// ```ignore
+/// let layout = VertexAttributes::builder().uv().normal().tangent().build();
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VertexLayoutBuilder {
+    attributes: VertexAttributes,
+}
+
+impl VertexLayoutBuilder {
+    pub fn color(mut self) -> VertexLayoutBuilder {
+        self.attributes.color = true;
+        self
+    }
+
+    pub fn uv(mut self) -> VertexLayoutBuilder {
+        self.attributes.uv = true;
+        self
+    }
+
+    pub fn uv2(mut self) -> VertexLayoutBuilder {
+        self.attributes.uv2 = true;
+        self
+    }
+
+    pub fn normal(mut self) -> VertexLayoutBuilder {
+        self.attributes.normal = true;
+        self
+    }
+
+    pub fn tangent(mut self) -> VertexLayoutBuilder {
+        self.attributes.tangent = true;
+        self
+    }
+
+    pub fn build(self) -> VertexAttributes {
+        self.attributes
+    }
+}
+
+/// The attribute streams of a mesh with a flexible layout, one entry per vertex in each.
+#[derive(Clone, Debug, Default)]
+pub struct VertexData {
+    pub positions: Vec<[f32; 3]>,
+    pub colors: Option<Vec<[f32; 3]>>,
+    pub uvs: Option<Vec<[f32; 2]>>,
+    pub uvs2: Option<Vec<[f32; 2]>>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    pub tangents: Option<Vec<[f32; 4]>>,
+    pub packed: bool,
+}
+
+impl VertexData {
+    pub fn new(positions: Vec<[f32; 3]>) -> VertexData {
+        VertexData {
+            positions,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_colors(mut self, colors: Vec<[f32; 3]>) -> VertexData {
+        self.colors = Some(colors);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<[f32; 2]>) -> VertexData {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn with_uvs2(mut self, uvs: Vec<[f32; 2]>) -> VertexData {
+        self.uvs2 = Some(uvs);
+        self
+    }
+
+    pub fn with_normals(mut self, normals: Vec<[f32; 3]>) -> VertexData {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_tangents(mut self, tangents: Vec<[f32; 4]>) -> VertexData {
+        self.tangents = Some(tangents);
+        self
+    }
+
+    // Compresses the vertices when they are interleaved, see packing.rs.
+    pub fn with_packing(mut self, packed: bool) -> VertexData {
+        self.packed = packed;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    // The attributes present.
+    pub fn attributes(&self) -> VertexAttributes {
+        VertexAttributes {
+            color: self.colors.is_some(),
+            uv: self.uvs.is_some(),
+            uv2: self.uvs2.is_some(),
+            normal: self.normals.is_some(),
+            tangent: self.tangents.is_some(),
+            packed: self.packed,
+        }
+    }
+
+    // The vertices in the layout of `attributes`. Panics if a stream is shorter than the positions.
+    pub fn interleave(&self) -> Vec<u8> {
+        let stride = self.attributes().stride() as usize;
+        let mut bytes = Vec::with_capacity(stride * self.len());
+        for (i, position) in self.positions.iter().enumerate() {
+            if self.packed {
+                self.interleave_packed(i, *position, &mut bytes);
+                continue;
+            }
+            bytes.extend_from_slice(bytemuck::cast_slice(position));
+            if let Some(colors) = &self.colors {
+                bytes.extend_from_slice(bytemuck::cast_slice(&colors[i]));
+            }
+            if let Some(uvs) = &self.uvs {
+                bytes.extend_from_slice(bytemuck::cast_slice(&uvs[i]));
+            }
+            if let Some(uvs) = &self.uvs2 {
+                bytes.extend_from_slice(bytemuck::cast_slice(&uvs[i]));
+            }
+            if let Some(normals) = &self.normals {
+                bytes.extend_from_slice(bytemuck::cast_slice(&normals[i]));
+            }
+            if let Some(tangents) = &self.tangents {
+                bytes.extend_from_slice(bytemuck::cast_slice(&tangents[i]));
+            }
+        }
+        bytes
+    }
+
+    fn interleave_packed(&self, i: usize, position: [f32; 3], bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(bytemuck::cast_slice(&packing::pack_position(position)));
+        if let Some(colors) = &self.colors {
+            bytes.extend_from_slice(&packing::pack_color(colors[i]));
+        }
+        if let Some(uvs) = &self.uvs {
+            bytes.extend_from_slice(bytemuck::cast_slice(&packing::pack_uv(uvs[i])));
+        }
+        if let Some(uvs) = &self.uvs2 {
+            bytes.extend_from_slice(bytemuck::cast_slice(&packing::pack_uv(uvs[i])));
+        }
+        if let Some(normals) = &self.normals {
+            bytes.extend_from_slice(&packing::pack_normal(normals[i]).to_ne_bytes());
+        }
+        if let Some(tangents) = &self.tangents {
+            let [x, y, z, w] = tangents[i];
+            bytes.extend_from_slice(&packing::pack_tangent([x, y, z], w).to_ne_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_attributes() -> VertexData {
+        VertexData::new(vec![[1.0, 2.0, 3.0]])
+            .with_colors(vec![[1.0, 0.5, 0.0]])
+            .with_uvs(vec![[0.25, 0.75]])
+            .with_uvs2(vec![[0.5, 0.5]])
+            .with_normals(vec![[0.0, 1.0, 0.0]])
+            .with_tangents(vec![[1.0, 0.0, 0.0, -1.0]])
+    }
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes.chunks(4).map(|b| f32::from_ne_bytes(b.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn attributes_are_interleaved_in_a_fixed_order() {
+        let vertices = all_attributes();
+        let attributes = vertices.attributes();
+        assert_eq!(attributes, VertexAttributes::builder().color().uv().uv2().normal().tangent().build());
+        let layout: Vec<_> = attributes.attributes().iter().map(|a| (a.shader_location, a.offset)).collect();
+        assert_eq!(layout, [(0, 0), (1, 12), (2, 24), (3, 32), (4, 40), (9, 52)]);
+        assert_eq!(attributes.stride(), 68);
+        #[rustfmt::skip]
+        assert_eq!(floats(&vertices.interleave()), [
+            1.0, 2.0, 3.0, 1.0, 0.5, 0.0, 0.25, 0.75, 0.5, 0.5, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, -1.0,
+        ]);
+        let defines = attributes.defines();
+        for define in ["VERTEX_COLOR", "VERTEX_UV", "VERTEX_UV2", "VERTEX_NORMAL", "VERTEX_TANGENT"] {
+            assert_eq!(defines.get(define), Some(1), "{}", define);
+        }
+        assert_eq!(defines.get("PACKED_VERTICES"), None);
+    }
+
+    #[test]
+    fn missing_attributes_leave_no_gaps() {
+        let vertices = VertexData::new(vec![[0.0; 3], [1.0; 3]]).with_uvs(vec![[0.0, 1.0], [1.0, 0.0]]);
+        let attributes = vertices.attributes();
+        assert_eq!(attributes, VertexAttributes::builder().uv().build());
+        assert_eq!(attributes.stride(), 20);
+        let layout: Vec<_> = attributes.attributes().iter().map(|a| (a.shader_location, a.offset)).collect();
+        assert_eq!(layout, [(0, 0), (2, 12)]);
+        assert_eq!(floats(&vertices.interleave()), [0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0]);
+        assert_eq!(attributes.defines().get("VERTEX_NORMAL"), None);
+    }
+
+    #[test]
+    fn packed_vertices_use_the_compressed_formats() {
+        let vertices = all_attributes().with_packing(true);
+        let attributes = vertices.attributes();
+        let formats: Vec<_> = attributes.attributes().iter().map(|a| (a.format, a.offset)).collect();
+        assert_eq!(
+            formats,
+            [
+                (packing::POSITION_FORMAT, 0),
+                (packing::COLOR_FORMAT, 8),
+                (packing::UV_FORMAT, 12),
+                (packing::UV_FORMAT, 16),
+                (packing::NORMAL_FORMAT, 20),
+                (packing::NORMAL_FORMAT, 24),
+            ]
+        );
+        assert_eq!(attributes.stride(), 28);
+        let bytes = vertices.interleave();
+        assert_eq!(bytes.len(), 28);
+        assert_eq!(bytes[8..12], packing::pack_color([1.0, 0.5, 0.0]));
+        assert_eq!(bytes[20..24], packing::pack_normal([0.0, 1.0, 0.0]).to_ne_bytes());
+        assert_eq!(attributes.defines().get("PACKED_VERTICES"), Some(1));
+    }
+}
@@ -0,0 +1,548 @@
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+
+use crate::game::Event;
+use crate::gfx::GFX;
+use crate::light_cookies::CookieId;
+use crate::local_lights::{LocalLight, LocalLightId};
+use crate::reflection::ShaderReflection;
+use crate::uniform::UniformLayout;
+
+// Volumetric lighting
+//======================
+// Light shafts: the light of the local lights (local_lights.rs) scattered towards the camera by a
+// thin fog filling the scene, so a spot light shows its cone in the air and the shadow casters in
+// it cut dark shafts out of it:
+//
+//     gfx.set_volumetrics(Some(VolumetricSettings::default().with_density(0.08).with_anisotropy(0.5)));
+//     gfx.add_local_light(LocalLight::spot(position, direction, [20.0, 18.0, 15.0], 12.0, 30.0).with_shadows(1.0));
+//
+// Every pixel marches its ray from the camera to the scene's depth (volumetrics.wgsl), at every
+// step adding the light of each local light, attenuated and shaped like on a surface, times the
+// shadow map of the light where it casts shadows, so the shafts are occluded by the same casters
+// as the surfaces. `density` is the extinction of the fog per world unit: how much light it
+// scatters, and how much of the scene behind it it hides. The Henyey-Greenstein phase function
+// spreads the scattered light around the direction it came from by `anisotropy`: 0 scatters it
+// evenly, towards 1 mostly forward, so the shafts glow when looking towards the light. The fog
+// also scatters `ambient` of the ambient light, which keeps it from being black between lights.
+//
+// The directional light has no shadow map, so it casts no shafts. Only the lights' shadow maps are
+// sampled, so lights without shadows light the fog through every wall.
+//
+// Like the clouds (clouds.rs), the march runs at 1/`resolution` of the scene's size along each
+// axis, offset by a different amount of the step every pixel and frame to turn the banding into
+// noise, and is blended over the scene after its renderables, the clouds included, with HDR into
+// the scene target before the tonemapping.
+
+/// The format of the low resolution target: the scattered light, and the transmittance.
+pub const VOLUMETRIC_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// The fog the light shafts scatter in, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VolumetricSettings {
+    pub density: f32, // Extinction per world unit.
+    pub anisotropy: f32, // From -1, scattering back, to 1, scattering forward.
+    pub ambient: f32, // How much of the ambient light the fog scatters.
+    pub max_distance: f32, // How far from the camera the fog reaches, in world units.
+    pub steps: u32, // Of the march.
+    pub resolution: u32, // Scene pixels per volumetric pixel, along each axis.
+}
+
+impl Default for VolumetricSettings {
+    fn default() -> Self {
+        VolumetricSettings {
+            density: 0.05,
+            anisotropy: 0.3,
+            ambient: 0.2,
+            max_distance: 50.0,
+            steps: 32,
+            resolution: 2,
+        }
+    }
+}
+
+impl VolumetricSettings {
+    pub fn with_density(mut self, density: f32) -> VolumetricSettings {
+        self.density = density;
+        self
+    }
+
+    pub fn with_anisotropy(mut self, anisotropy: f32) -> VolumetricSettings {
+        self.anisotropy = anisotropy;
+        self
+    }
+}
+
+/// A camera the light shafts are drawn for this frame.
+#[derive(Clone, Copy, Debug)]
+pub struct VolumetricCamera {
+    pub view_proj: Matrix4<f32>,
+    pub eye: Point3<f32>,
+    pub viewport: (u32, u32, u32, u32), // x, y, width and height in pixels of the scene target.
+}
+
+uniform_struct! {
+    struct VolumetricParams {
+        inverse_view_proj: Matrix4<f32>,
+        eye: Vector4<f32>,
+        viewport: Vector4<f32>,
+        density: f32,
+        anisotropy: f32,
+        ambient: f32,
+        max_distance: f32,
+        steps: f32,
+        resolution: f32,
+    }
+}
+assert_uniform_size!(VolumetricParams, 128);
+
+uniform_struct! {
+    struct CompositeParams {
+        resolution: f32,
+    }
+}
+
+/// Renders the light shafts for every camera, see above.
+pub struct Volumetrics {
+    march_layout: wgpu::BindGroupLayout, // Depends on the samples of the depth.
+    march_pipeline: wgpu::RenderPipeline,
+    composite_layout: wgpu::BindGroupLayout,
+    composite_pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    params_stride: u32,
+    params_capacity: usize, // In cameras.
+    composite_buffer: wgpu::Buffer,
+    target_sampler: wgpu::Sampler,
+    target: Option<(wgpu::Texture, wgpu::TextureView)>,
+    target_size: (u32, u32),
+    march_bind_group: Option<wgpu::BindGroup>, // Made by `prepare`.
+    composite_bind_group: Option<wgpu::BindGroup>,
+    viewports: Vec<(f32, f32, f32, f32)>, // In the volumetric target, by camera.
+    frame: u32,
+}
+
+impl Volumetrics {
+    // `camera_layout` is that of the camera bind groups, whose local lights are marched through.
+    // `format` and `samples` are those of the scene target, and of the depth.
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, samples: u32) -> Volumetrics {
+        let (march_layout, march_pipeline) = create_march_pipeline(device, camera_layout, samples);
+        let (composite_layout, composite_pipeline) = create_composite_pipeline(device, format, samples);
+        let params_stride = device.limits().min_uniform_buffer_offset_alignment.max(VolumetricParams::SIZE as u32);
+        let params_capacity = 4;
+        let composite_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Volumetric Composite Params"),
+            size: CompositeParams::SIZE as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let target_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Volumetric Target Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Volumetrics {
+            march_layout,
+            march_pipeline,
+            composite_layout,
+            composite_pipeline,
+            params_buffer: create_params_buffer(device, params_stride, params_capacity),
+            params_stride,
+            params_capacity,
+            composite_buffer,
+            target_sampler,
+            target: None,
+            target_size: (0, 0),
+            march_bind_group: None,
+            composite_bind_group: None,
+            viewports: Vec::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn set_samples(&mut self, device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat, samples: u32) {
+        (self.march_layout, self.march_pipeline) = create_march_pipeline(device, camera_layout, samples);
+        (self.composite_layout, self.composite_pipeline) = create_composite_pipeline(device, format, samples);
+        self.march_bind_group = None;
+        self.composite_bind_group = None;
+    }
+
+    // Uploads the parameters of every camera and makes this frame's bind groups, to be drawn with
+    // `draw` after the camera's scene pass into `depth`. `size` is that of the scene target.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &VolumetricSettings,
+        cameras: &[VolumetricCamera],
+        depth: &wgpu::TextureView,
+        size: (u32, u32),
+    ) {
+        let resolution = settings.resolution.max(1);
+        let target_size = (size.0.div_ceil(resolution).max(1), size.1.div_ceil(resolution).max(1));
+        if target_size != self.target_size || self.target.is_none() {
+            self.target = Some(create_target(device, target_size));
+            self.target_size = target_size;
+        }
+        if cameras.len() > self.params_capacity {
+            self.params_capacity = cameras.len().next_power_of_two();
+            self.params_buffer = create_params_buffer(device, self.params_stride, self.params_capacity);
+        }
+
+        // The golden ratio spreads the ray offsets of consecutive frames evenly.
+        let jitter = (self.frame as f32 * 0.618034).fract();
+        self.frame = self.frame.wrapping_add(1);
+        self.viewports.clear();
+        for (index, camera) in cameras.iter().enumerate() {
+            let (x, y, w, h) = camera.viewport;
+            let scale = resolution as f32;
+            let viewport = (x as f32 / scale, y as f32 / scale, (w as f32 / scale).ceil(), (h as f32 / scale).ceil());
+            self.viewports.push(viewport);
+            let params = VolumetricParams {
+                inverse_view_proj: camera.view_proj.invert().unwrap_or_else(Matrix4::identity),
+                eye: Vector4::new(camera.eye.x, camera.eye.y, camera.eye.z, jitter),
+                viewport: Vector4::new(viewport.0, viewport.1, viewport.2, viewport.3),
+                density: settings.density.max(0.0),
+                anisotropy: settings.anisotropy.clamp(-0.99, 0.99),
+                ambient: settings.ambient.max(0.0),
+                max_distance: settings.max_distance.max(f32::EPSILON),
+                steps: settings.steps.max(1) as f32,
+                resolution: scale,
+            };
+            let offset = index as u64 * self.params_stride as u64;
+            queue.write_buffer(&self.params_buffer, offset, &params.to_uniform_bytes());
+        }
+        let composite = CompositeParams {
+            resolution: resolution as f32,
+        };
+        queue.write_buffer(&self.composite_buffer, 0, &composite.to_uniform_bytes());
+
+        // The depth view changes with the surface size, so these bind groups are made every frame.
+        let target = &self.target.as_ref().expect("created above").1;
+        self.march_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Volumetric March Bind Group"),
+            layout: &self.march_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.params_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(VolumetricParams::SIZE as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+            ],
+        }));
+        self.composite_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Volumetric Composite Bind Group"),
+            layout: &self.composite_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(target),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.target_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.composite_buffer.as_entire_binding(),
+                },
+            ],
+        }));
+    }
+
+    // Records the passes marching the light shafts of camera `index`, lit by the local lights of
+    // its bind group, and blending them over its part of the scene, within its viewport and
+    // scissor rect (x, y, width, height in pixels).
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        index: usize,
+        camera: &wgpu::BindGroup,
+        viewport: (u32, u32, u32, u32),
+        scissor: (u32, u32, u32, u32),
+    ) {
+        let (march_bind_group, composite_bind_group, volume_viewport, volume_target) =
+            match (&self.march_bind_group, &self.composite_bind_group, self.viewports.get(index), &self.target) {
+                (Some(march), Some(composite), Some(viewport), Some((_, view))) => (march, composite, *viewport, view),
+                _ => return,
+            };
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Volumetric March Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: volume_target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            let (x, y, w, h) = volume_viewport;
+            let (max_w, max_h) = (self.target_size.0 as f32, self.target_size.1 as f32);
+            render_pass.set_viewport(x, y, w.min(max_w - x), h.min(max_h - y), 0.0, 1.0);
+            render_pass.set_pipeline(&self.march_pipeline);
+            render_pass.set_bind_group(0, camera, &[]);
+            render_pass.set_bind_group(1, march_bind_group, &[index as u32 * self.params_stride]);
+            render_pass.draw(0..3, 0..1);
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Volumetric Composite Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        let (x, y, w, h) = viewport;
+        let (sx, sy, sw, sh) = scissor;
+        render_pass.set_viewport(x as f32, y as f32, w as f32, h as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(sx, sy, sw, sh);
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// Light shafts demo
+//======================
+
+const SHAFTS_POSITION: Point3<f32> = Point3::new(0.3, 0.8, -2.5);
+// The size of the window cookie, and of the bars of its frame, in pixels.
+const WINDOW_SIZE: u32 = 64;
+const WINDOW_BAR: u32 = 4;
+
+// A spot light behind the pentagon shining towards the camera through a thin fog, so the pentagon
+// cuts a shaft of shadow out of its cone. It shines through a window (a cookie, see
+// light_cookies.rs) and sways slowly from side to side. L switches the light off and on, O takes
+// the window out and puts it back.
+pub struct LightShafts {
+    light: Option<LocalLightId>, // `None` while switched off.
+    window: Option<CookieId>, // `None` while taken out.
+    time: f32,
+    toggle_light: bool,
+    toggle_window: bool,
+}
+
+impl LightShafts {
+    pub fn new(gfx: &mut GFX) -> LightShafts {
+        gfx.set_volumetrics(Some(VolumetricSettings::default().with_density(0.15).with_anisotropy(0.6)));
+        let mut shafts = LightShafts {
+            light: None,
+            window: gfx.add_light_cookie(WINDOW_SIZE, WINDOW_SIZE, &window_cookie()),
+            time: 0.0,
+            toggle_light: false,
+            toggle_window: false,
+        };
+        shafts.light = gfx.add_local_light(shafts.spot_light());
+        if shafts.light.is_none() {
+            tracing::warn!("Light shafts: no local light left");
+        }
+        shafts
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    pub fn render(&mut self, gfx: &mut GFX) {
+        if std::mem::take(&mut self.toggle_light) {
+            self.light = match self.light.take() {
+                Some(light) => {
+                    gfx.remove_local_light(light);
+                    None
+                }
+                None => gfx.add_local_light(self.spot_light()),
+            };
+        }
+        if std::mem::take(&mut self.toggle_window) {
+            self.window = match self.window.take() {
+                Some(cookie) => {
+                    gfx.remove_light_cookie(cookie);
+                    None
+                }
+                None => gfx.add_light_cookie(WINDOW_SIZE, WINDOW_SIZE, &window_cookie()),
+            };
+        }
+        if let Some(id) = self.light {
+            let sway = self.spot_light();
+            let light = LocalLight {
+                shape: sway.shape,
+                cookie: sway.cookie,
+                ..*gfx.local_light(id)
+            };
+            gfx.set_local_light(id, light);
+        }
+    }
+
+    pub fn on_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyPressed(key) if key == b'L' as u16 => self.toggle_light = true,
+            Event::KeyPressed(key) if key == b'O' as u16 => self.toggle_window = true,
+            _ => {}
+        }
+    }
+
+    // Towards the camera, swaying by up to half a unit at the pentagon.
+    fn spot_light(&self) -> LocalLight {
+        let direction = Vector3::new(-0.3 + 0.5 * (self.time * 0.4).sin(), -0.8, 4.5);
+        let light = LocalLight::spot(SHAFTS_POSITION, direction, [6.0, 5.0, 4.0], 8.0, 25.0).with_shadows(1.0);
+        match self.window {
+            Some(cookie) => light.with_cookie(cookie),
+            None => light,
+        }
+    }
+}
+
+// Four panes of light in a dark frame, as sRGB pixels.
+fn window_cookie() -> Vec<u8> {
+    let bar = |i: u32| {
+        let frame = !(WINDOW_BAR..WINDOW_SIZE - WINDOW_BAR).contains(&i);
+        frame || i.abs_diff(WINDOW_SIZE / 2) < WINDOW_BAR / 2
+    };
+    let mut pixels = Vec::with_capacity((WINDOW_SIZE * WINDOW_SIZE * 4) as usize);
+    for y in 0..WINDOW_SIZE {
+        for x in 0..WINDOW_SIZE {
+            let value = if bar(x) || bar(y) { 0 } else { 255 };
+            pixels.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+    pixels
+}
+
+fn create_target(device: &wgpu::Device, size: (u32, u32)) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Volumetric Target"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: VOLUMETRIC_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
+
+fn create_march_pipeline(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, samples: u32) -> (wgpu::BindGroupLayout, wgpu::RenderPipeline) {
+    let wgsl = include_str!("volumetrics.wgsl");
+    // The same shader reads multisampled depth and plain depth, like clouds.rs.
+    let wgsl = if samples > 1 {
+        wgsl.to_string()
+    } else {
+        wgsl.replace("texture_depth_multisampled_2d", "texture_depth_2d")
+    };
+    let reflection = ShaderReflection::from_wgsl(&wgsl).expect("built-in shader is valid");
+    // Every camera has its own part of the parameters' buffer.
+    let mut entries = reflection.layout_entries(1);
+    for entry in &mut entries {
+        if let wgpu::BindingType::Buffer { has_dynamic_offset, .. } = &mut entry.ty {
+            *has_dynamic_offset = true;
+        }
+    }
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Volumetric March Bind Group Layout"),
+        entries: &entries,
+    });
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Volumetric March Shader"),
+        source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+    });
+    let pipeline = create_pipeline(device, "Volumetric March", &[camera_layout, &layout], &shader, VOLUMETRIC_FORMAT, None, 1);
+    (layout, pipeline)
+}
+
+fn create_composite_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32) -> (wgpu::BindGroupLayout, wgpu::RenderPipeline) {
+    // The same blend of scattered light and transmittance as the clouds.
+    let wgsl = include_str!("clouds_composite.wgsl");
+    let reflection = ShaderReflection::from_wgsl(wgsl).expect("built-in shader is valid");
+    let layout = reflection.create_bind_group_layout(device, Some("Volumetric Composite Bind Group Layout"), 0);
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Volumetric Composite Shader"),
+        source: wgpu::ShaderSource::Wgsl(wgsl.into()),
+    });
+    // The scattered light adds up, the scene shows through by the transmittance, and the alpha of
+    // the scene is left alone.
+    let blend = wgpu::BlendState {
+        color: wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::SrcAlpha,
+            operation: wgpu::BlendOperation::Add,
+        },
+        alpha: wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        },
+    };
+    let pipeline = create_pipeline(device, "Volumetric Composite", &[&layout], &shader, format, Some(blend), samples);
+    (layout, pipeline)
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    samples: u32,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format!("{} Pipeline Layout", label)),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{} Pipeline", label)),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+fn create_params_buffer(device: &wgpu::Device, stride: u32, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Volumetric Params"),
+        size: stride as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,177 @@
+// Light shafts of the local lights through a uniform fog, see volumetrics.rs. A triangle covers the
+// camera's viewport of the low resolution volumetric target, and every pixel marches its ray up to
+// the scene, adding the light every step scatters towards the camera.
+
+// See time.rs.
+struct Globals {
+    time: f32;
+    delta_time: f32;
+    frame: u32;
+    resolution: vec2<f32>;
+    light_direction: vec4<f32>;
+    light_color: vec4<f32>;
+    ambient: vec4<f32>;
+};
+[[group(0), binding(1)]]
+var<uniform> globals: Globals;
+
+// See local_lights.rs and shadow_atlas.rs.
+struct LocalLight {
+    position: vec4<f32>; // w is the range.
+    color: vec4<f32>; // w is the index of the first shadow map, -1 without shadows.
+    direction: vec4<f32>; // Of spot lights.
+    // The cosines of the outer and inner angle; z is 1 for point lights; w is the cookie, -1
+    // without one.
+    cone: vec4<f32>;
+    projection: mat4x4<f32>; // The view projection of spot lights, for the cookie.
+};
+struct ShadowView {
+    view_proj: mat4x4<f32>;
+    rect: vec4<f32>; // In the atlas: the corner in xy, the size in zw.
+};
+struct LocalLights {
+    count: u32;
+    lights: array<LocalLight, 16>;
+    views: array<ShadowView, 32>;
+};
+[[group(0), binding(2)]]
+var<uniform> local_lights: LocalLights;
+[[group(0), binding(3)]]
+var shadow_atlas: texture_depth_2d;
+[[group(0), binding(4)]]
+var shadow_sampler: sampler_comparison;
+// See light_cookies.rs.
+[[group(0), binding(5)]]
+var cookies: texture_2d_array<f32>;
+[[group(0), binding(6)]]
+var cookie_sampler: sampler;
+
+struct VolumetricParams {
+    inverse_view_proj: mat4x4<f32>;
+    eye: vec4<f32>; // w is this frame's offset of the ray starts, in steps.
+    viewport: vec4<f32>; // x, y, width and height in pixels of the volumetric target.
+    density: f32; // Extinction per world unit.
+    anisotropy: f32; // Henyey-Greenstein g, from -1 (back) to 1 (forward scattering).
+    ambient: f32; // How much of the ambient light the fog scatters.
+    max_distance: f32; // Of the march, in world units.
+    steps: f32;
+    resolution: f32; // Scene pixels per volumetric pixel, along each axis.
+};
+[[group(1), binding(0)]]
+var<uniform> params: VolumetricParams;
+[[group(1), binding(1)]]
+var depth: texture_depth_multisampled_2d;
+
+let PI: f32 = 3.14159265;
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = params.inverse_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Henyey-Greenstein: how much light scatters at an angle with cosine `cos_theta` to where it came from.
+fn phase(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+// How much of `light` reaches `position` past the shadow casters, as in shader.wgsl.
+fn local_shadow(light: LocalLight, position: vec3<f32>) -> f32 {
+    if (light.color.w < 0.0) {
+        return 1.0;
+    }
+    var index = u32(light.color.w);
+    if (light.cone.z > 0.0) {
+        // The face of the cube around a point light that the position is on.
+        let d = position - light.position.xyz;
+        let a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            index = index + select(1u, 0u, d.x > 0.0);
+        } else if (a.y >= a.z) {
+            index = index + select(3u, 2u, d.y > 0.0);
+        } else {
+            index = index + select(5u, 4u, d.z > 0.0);
+        }
+    }
+    let view = local_lights.views[index];
+    let clip = view.view_proj * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_atlas));
+    let uv = clamp(ndc.xy * vec2<f32>(0.5, -0.5) + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    let atlas_uv = clamp(view.rect.xy + uv * view.rect.zw, view.rect.xy + texel, view.rect.xy + view.rect.zw - texel);
+    return textureSampleCompareLevel(shadow_atlas, shadow_sampler, atlas_uv, ndc.z);
+}
+
+// The light of the local lights at `position` scattered along `direction` towards the camera.
+fn in_scattered(position: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < local_lights.count; i = i + 1u) {
+        let light = local_lights.lights[i];
+        let to_light = light.position.xyz - position;
+        let distance = length(to_light);
+        let range = light.position.w;
+        if (distance > 0.0 && distance < range) {
+            let l = to_light / distance;
+            let window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+            let falloff = window * window / (distance * distance + 1.0);
+            let cone = smoothStep(light.cone.x, light.cone.y, dot(-l, light.direction.xyz));
+            if (cone > 0.0) {
+                var color = light.color.rgb;
+                if (light.cone.w >= 0.0) {
+                    let clip = light.projection * vec4<f32>(position, 1.0);
+                    let uv = clip.xy / max(clip.w, 0.0001) * vec2<f32>(0.5, -0.5) + 0.5;
+                    color = color * textureSampleLevel(cookies, cookie_sampler, uv, i32(light.cone.w), 0.0).rgb;
+                }
+                // The ray runs towards the camera, so light going along it scatters forward.
+                let scattering = phase(dot(-direction, -l), params.anisotropy);
+                total = total + color * falloff * cone * scattering * local_shadow(light, position);
+            }
+        }
+    }
+    return total;
+}
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let viewport = params.viewport;
+    let local = (position.xy - viewport.xy) / viewport.zw;
+    let ndc = vec2<f32>(local.x * 2.0 - 1.0, 1.0 - local.y * 2.0);
+    let origin = params.eye.xyz;
+    let direction = normalize(unproject(ndc, 1.0) - unproject(ndc, 0.0));
+
+    // The scene ends the ray.
+    let size = textureDimensions(depth);
+    let pixel = min(vec2<i32>(position.xy * params.resolution), size - 1);
+    let scene_depth = textureLoad(depth, pixel, 0);
+    var end = params.max_distance;
+    if (scene_depth < 1.0) {
+        end = min(end, length(unproject(ndc, scene_depth) - origin));
+    }
+
+    let step = end / params.steps;
+    // Interleaved gradient noise (Jimenez) offsets the start of every pixel's march, as in
+    // clouds.wgsl, which turns the banding of few steps into a fine noise.
+    let noise_offset = fract(52.9829189 * fract(dot(position.xy, vec2<f32>(0.06711056, 0.00583715))));
+    var t = step * fract(noise_offset + params.eye.w);
+    let ambient = globals.ambient.rgb * params.ambient / (4.0 * PI);
+    let step_transmittance = exp(-params.density * step);
+    var scattered = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    for (var i = 0.0; i < params.steps; i = i + 1.0) {
+        let light = in_scattered(origin + direction * t, direction) + ambient;
+        // Integrated over the step, as in clouds.wgsl, with all the extinction scattering.
+        scattered = scattered + light * transmittance * (1.0 - step_transmittance);
+        transmittance = transmittance * step_transmittance;
+        t = t + step;
+    }
+    return vec4<f32>(scattered, transmittance);
+}
@@ -1,11 +1,155 @@
+use std::fmt;
+
+use windows::Win32::Foundation::{HWND, PWSTR};
+use windows::Win32::UI::WindowsAndMessaging::{GetWindowTextLengthW, GetWindowTextW, SetWindowTextW};
+
+use crate::error::Win32Error;
+
+// Wide strings
+//======================
+// Win32 takes and returns text as null-terminated UTF-16. A `WideString` owns such a buffer, so a
+// pointer into it stays valid for as long as the value is kept in a variable:
+//
+//     let title = WideString::new("Demo");
+//     SetWindowTextW(hwnd, title.as_pwstr());
+//
+// Taking the pointer of a temporary, as in `PWSTR("Demo".to_wide().as_ptr() as *mut u16)`, is the
+// bug this prevents: the buffer is freed at the end of the statement that made the pointer, and
+// the call reads freed memory.
+
+/// A null-terminated UTF-16 string.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct WideString {
+    units: Vec<u16>, // Always ends with the terminating 0.
+}
+
+impl WideString {
+    // Text with interior nulls is cut at the first one, as Win32 would read it.
+    pub fn new(text: &str) -> WideString {
+        WideString::from_units(&text.encode_utf16().collect::<Vec<_>>())
+    }
+
+    // UTF-16 from Win32, up to the first null or the end of `units`.
+    pub fn from_units(units: &[u16]) -> WideString {
+        let end = units.iter().position(|&unit| unit == 0).unwrap_or(units.len());
+        let mut owned = Vec::with_capacity(end + 1);
+        owned.extend_from_slice(&units[..end]);
+        owned.push(0);
+        WideString { units: owned }
+    }
+
+    // For Win32 parameters. The functions that take text don't write to it, despite the type.
+    pub fn as_pwstr(&self) -> PWSTR {
+        PWSTR(self.units.as_ptr() as *mut u16)
+    }
+
+    pub fn as_ptr(&self) -> *const u16 {
+        self.units.as_ptr()
+    }
+
+    // The text without the terminating null.
+    pub fn as_units(&self) -> &[u16] {
+        &self.units[..self.units.len() - 1]
+    }
+
+    // In UTF-16 code units, without the terminating null.
+    pub fn len(&self) -> usize {
+        self.units.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Unpaired surrogates become U+FFFD.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf16_lossy(self.as_units())
+    }
+}
+
+impl From<&str> for WideString {
+    fn from(text: &str) -> Self {
+        WideString::new(text)
+    }
+}
+
+impl fmt::Display for WideString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_lossy())
+    }
+}
+
+impl fmt::Debug for WideString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string_lossy())
+    }
+}
+
 pub trait ToWide {
-    fn to_wide(&self) -> Vec<u16>;
+    fn to_wide(&self) -> WideString;
+}
+
+impl ToWide for str {
+    fn to_wide(&self) -> WideString {
+        WideString::new(self)
+    }
+}
+
+// Window text
+//======================
+
+// The title of a window, or the text of a control.
+pub fn get_window_text(hwnd: HWND) -> String {
+    unsafe {
+        let length = GetWindowTextLengthW(hwnd).max(0) as usize;
+        let mut buffer = vec![0u16; length + 1];
+        let copied = GetWindowTextW(hwnd, PWSTR(buffer.as_mut_ptr()), buffer.len() as i32).max(0) as usize;
+        WideString::from_units(&buffer[..copied]).to_string_lossy()
+    }
+}
+
+pub fn set_window_text(hwnd: HWND, text: &str) -> Result<(), Win32Error> {
+    let text = WideString::new(text);
+    unsafe { SetWindowTextW(hwnd, text.as_pwstr()).ok().map_err(|e| win_error!(e)) }
 }
 
-impl ToWide for &str {
-    fn to_wide(&self) -> Vec<u16> {
-        let mut result: Vec<u16> = self.encode_utf16().collect();
-        result.push(0);
-        result
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The unit just past the text, where Win32 stops reading.
+    fn terminator(wide: &WideString) -> u16 {
+        unsafe { *wide.as_ptr().add(wide.len()) }
+    }
+
+    #[test]
+    fn non_ascii_text_round_trips() {
+        let wide = "Grüße, 世界".to_wide();
+        assert_eq!(wide.len(), 9);
+        assert!(!wide.is_empty());
+        assert_eq!(wide.to_string_lossy(), "Grüße, 世界");
+        assert_eq!(terminator(&wide), 0);
+        assert_eq!(WideString::from_units(wide.as_units()), wide);
+    }
+
+    #[test]
+    fn surrogate_pairs_round_trip() {
+        let wide = WideString::new("a🎮b");
+        assert_eq!(wide.as_units(), &[0x61, 0xD83C, 0xDFAE, 0x62]);
+        assert_eq!(wide.len(), 4);
+        assert_eq!(wide.to_string_lossy(), "a🎮b");
+        assert_eq!(terminator(&wide), 0);
+
+        // Half a pair, as a buffer cut in the middle would hold.
+        assert_eq!(WideString::from_units(&[0x61, 0xD83C]).to_string_lossy(), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn text_ends_at_the_first_null() {
+        let wide = WideString::from_units(&[0x68, 0x69, 0, 0x78]);
+        assert_eq!(wide.to_string_lossy(), "hi");
+        assert_eq!(terminator(&wide), 0);
+        assert!(WideString::new("").is_empty());
+        assert_eq!(terminator(&WideString::new("")), 0);
     }
 }
@@ -1,31 +1,47 @@
-use crate::win32_common::ToWide;
+use crate::win32_common::{get_window_text, set_window_text, WideString};
 use std::ffi::c_void;
 use std::os::raw;
-use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, PWSTR, RECT, WPARAM};
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED};
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
-use windows::Win32::UI::Input::KeyboardAndMouse::{ReleaseCapture, SetCapture, VK_MENU};
+use windows::Win32::System::SystemInformation::GetTickCount;
+use windows::Win32::UI::Input::KeyboardAndMouse::{ReleaseCapture, SetCapture, VK_CONTROL, VK_MENU};
+use windows::Win32::UI::Input::{
+    GetRawInputData, RegisterRawInputDevices, HRAWINPUT, RAWINPUT, RAWINPUTDEVICE, RAWINPUTHEADER, RID_INPUT,
+    RIM_TYPEMOUSE,
+};
 use windows::Win32::UI::WindowsAndMessaging::{
-    AdjustWindowRect, CreateWindowExW, DefWindowProcW, DestroyWindow,
-    GetWindowLongPtrW, LoadCursorW, MessageBoxW, PostQuitMessage,
+    AdjustWindowRect, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW,
+    GetWindowLongPtrW, LoadCursorW, MsgWaitForMultipleObjects, PeekMessageW, PostQuitMessage, TranslateMessage,
+    MSG, PM_REMOVE, QS_ALLINPUT, WM_QUIT,
     RegisterClassW, SetWindowLongPtrW, CREATESTRUCTW, CS_HREDRAW, CS_VREDRAW,
-    CW_USEDEFAULT, GWLP_USERDATA, IDC_CROSS, MB_OK,
+    CW_USEDEFAULT, GWLP_USERDATA, IDC_CROSS,
     WM_ACTIVATE, WM_CHAR, WM_DESTROY, WM_KEYDOWN, WM_KEYUP, WM_KILLFOCUS, WM_LBUTTONDOWN,
-    WM_LBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_NCCREATE, WM_RBUTTONDOWN, WM_RBUTTONUP,
+    WM_LBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_NCCREATE, WM_RBUTTONDOWN, WM_RBUTTONUP,
     WM_SYSKEYDOWN, WM_SYSKEYUP, WNDCLASSW, WS_CAPTION, WS_MINIMIZEBOX, WS_OVERLAPPEDWINDOW,
-    WS_SYSMENU, WS_VISIBLE, WM_SIZE, GetClientRect, WM_PAINT,
+    WS_SYSMENU, WS_VISIBLE, WM_SIZE, GetClientRect, SIZE_MINIMIZED, WA_INACTIVE,
+    GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN, WS_POPUP, SetWindowPos, GWL_STYLE, HWND_TOP,
+    SWP_FRAMECHANGED, SWP_SHOWWINDOW, WM_INPUT, WM_PAINT, WM_DEADCHAR, WM_SYSDEADCHAR, GetMessageTime,
+    PM_NOREMOVE,
 };
 
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::game::{Event, Input, MouseButton, ScrollDelta};
 use crate::keyboard::Keyboard;
-use crate::mouse::Mouse;
-use crate::gfx::GFX;
+use crate::mouse::{Mouse, MouseDelta, WHEEL_DELTA};
+use crate::rng::Rng;
+use crate::gfx::{GfxOptions, GFX};
+use crate::platform::{apply_event, NativeWindow, Platform};
+use crate::synthetic::{Message, EXTENDED_KEY};
 
 // Dealing with errors
 //======================
-// .map_err(|e| os_error!(e));
-// return Err(os_error!(::windows::core::Error::from_win32()))
+// .map_err(|e| win_error!(e)); or plain `?`, both record the file and line, see error.rs.
+// return Err(Win32Error::last().into())
 // For example: AdjustWindowRect(&mut wr, WS_CAPTION | WS_MINIMIZEBOX | WS_SYSMENU, BOOL(0)).ok().map_err(|e| win_error!(e))?;
-use crate::error::Win32Error;
-pub type Result<T> = core::result::Result<T, Win32Error>;
+use crate::error::{Context, Result};
 
 pub struct Window {
     pub width: i32,
@@ -33,9 +49,85 @@ pub struct Window {
     window_name: String,
     window_handle: HWND,
     pub visible: bool,
+    fullscreen: bool,
+    pub minimized: bool,
+    pub focused: bool,
+    // False while a replay drives the input, see replay.rs. Injected messages always get through.
+    pub accept_os_input: bool,
     kbd: Keyboard,
     mouse: Mouse,
     gfx: Option<GFX>,
+    // Collected by the window procedure, handed to the game by `App`.
+    events: VecDeque<Event>,
+    resized: Option<(u32, u32)>,
+    invalidated: bool, // Since the last `take_invalidated`.
+    input_time: Option<Instant>, // Since the last `take_input_time`.
+    // The injected messages still to handle, and the time of the one being handled, see `inject_all`.
+    injected: VecDeque<Message>,
+    injected_time: Option<u32>,
+}
+
+/// Configures a `Window` before it is created.
+#[derive(Clone)]
+pub struct WindowBuilder {
+    width: i32,
+    height: i32,
+    title: String,
+    fullscreen: bool,
+}
+
+impl WindowBuilder {
+    pub fn new() -> WindowBuilder {
+        WindowBuilder {
+            width: 800,
+            height: 600,
+            title: "-".into(),
+            fullscreen: false,
+        }
+    }
+
+    // Size of the client area, ignored in fullscreen.
+    pub fn size(mut self, width: i32, height: i32) -> WindowBuilder {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> WindowBuilder {
+        self.title = title.into();
+        self
+    }
+
+    pub fn fullscreen(mut self, fullscreen: bool) -> WindowBuilder {
+        self.fullscreen = fullscreen;
+        self
+    }
+
+    // The native window of the platform, see `NativeWindow`.
+    #[cfg(not(target_os = "linux"))]
+    pub fn build(self) -> NativeWindow {
+        let mut window = Window::new(self.width, self.height, &self.title);
+        window.fullscreen = self.fullscreen;
+        window
+    }
+
+    // On X11, see x11_window.rs.
+    #[cfg(target_os = "linux")]
+    pub fn build(self) -> NativeWindow {
+        crate::x11_window::X11Window::new(self.width, self.height, &self.title, self.fullscreen)
+    }
+
+    // The same window, created through winit instead, see winit_window.rs.
+    #[cfg(feature = "winit")]
+    pub fn build_winit(self) -> crate::winit_window::WinitWindow {
+        crate::winit_window::WinitWindow::new(self.width, self.height, &self.title, self.fullscreen)
+    }
+}
+
+impl Default for WindowBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Window {
@@ -47,22 +139,34 @@ impl Window {
             window_name: window_user_name.into(),
             window_handle: 0,
             visible: false, // will need to be set on actual window creation
+            fullscreen: false,
+            minimized: false,
+            focused: false,
+            accept_os_input: true,
             kbd: Keyboard::new(),
             mouse: Mouse::new(),
             gfx: None,
+            events: VecDeque::new(),
+            resized: None,
+            invalidated: true,
+            input_time: None,
+            injected: VecDeque::new(),
+            injected_time: None,
         }
     }
 
-    pub fn initialize(&mut self) -> Result<()> {
+    pub fn initialize(&mut self, gfx_options: &GfxOptions) -> Result<()> {
         unsafe {
             let instance = GetModuleHandleW(None);
-            let window_class_name = "window".to_wide().as_ptr() as *mut u16;
+            // Kept alive until the window exists, the class name is read by both calls.
+            let window_class_name = WideString::new("window");
+            let window_name = WideString::new(&self.window_name);
 
             let wc = {
                 WNDCLASSW {
                     hCursor: LoadCursorW(None, IDC_CROSS),
                     hInstance: instance,
-                    lpszClassName: PWSTR(window_class_name),
+                    lpszClassName: window_class_name.as_pwstr(),
 
                     style: CS_HREDRAW | CS_VREDRAW,
                     lpfnWndProc: Some(Self::wndproc),
@@ -73,7 +177,25 @@ impl Window {
             let atom = RegisterClassW(&wc);
             debug_assert!(atom != 0);
 
-            let window_handle = {
+            let window_handle = if self.fullscreen {
+                // Borderless fullscreen: a popup window covering the primary monitor.
+                self.width = GetSystemMetrics(SM_CXSCREEN);
+                self.height = GetSystemMetrics(SM_CYSCREEN);
+                CreateWindowExW(
+                    Default::default(),
+                    window_class_name.as_pwstr(),
+                    window_name.as_pwstr(),
+                    WS_POPUP | WS_VISIBLE,
+                    0,
+                    0,
+                    self.width,
+                    self.height,
+                    None,
+                    None,
+                    instance,
+                    self as *mut Window as *const c_void,
+                )
+            } else {
                 // calculate window size based on desired client region size
                 let mut wr = RECT::default();
                 wr.left = 100;
@@ -84,11 +206,10 @@ impl Window {
                 AdjustWindowRect(&mut wr, WS_CAPTION | WS_MINIMIZEBOX | WS_SYSMENU, BOOL(0))
                     .ok()
                     .map_err(|e| win_error!(e))?;
-                let window_name: &str = &self.window_name;
                 CreateWindowExW(
                     Default::default(),
-                    PWSTR(window_class_name),
-                    PWSTR(window_name.to_wide().as_ptr() as *mut u16),
+                    window_class_name.as_pwstr(),
+                    window_name.as_pwstr(),
                     WS_OVERLAPPEDWINDOW | WS_VISIBLE,
                     CW_USEDEFAULT,
                     CW_USEDEFAULT,
@@ -102,8 +223,22 @@ impl Window {
             };
 
 
+            // Ask for raw mouse input (usage page 1, usage 2 is the mouse), which WM_INPUT delivers
+            // alongside WM_MOUSEMOVE, see `MouseDelta`.
+            let device = RAWINPUTDEVICE {
+                usUsagePage: 0x01,
+                usUsage: 0x02,
+                dwFlags: 0,
+                hwndTarget: self.window_handle,
+            };
+            let registered = RegisterRawInputDevices(&device, 1, std::mem::size_of::<RAWINPUTDEVICE>() as u32);
+            if !registered.as_bool() {
+                tracing::warn!("Cannot register for raw mouse input, only the cursor position tracks movement");
+            }
+
             // Initialize Graphics
-            let mut gfx = pollster::block_on(GFX::new(&self)); 
+            let gfx = pollster::block_on(GFX::new(&*self, self.width as u32, self.height as u32, gfx_options))
+                .context("cannot initialize graphics")?;
             self.gfx = Some(gfx);
             
             // Check for error
@@ -114,69 +249,190 @@ impl Window {
         }
     }
 
-    fn render(&mut self) -> Result<()> {
-        // TEST KBD CODE
-        if self.kbd.key_is_pressed(VK_MENU) {
-            unsafe {
-                MessageBoxW(
-                    0,
-                    PWSTR("Message Received!".to_wide().as_ptr() as *mut u16),
-                    PWSTR("ALT Key Pressed!".to_wide().as_ptr() as *mut u16),
-                    MB_OK,
-                );
-            }
+    // The text of the title bar.
+    pub fn title(&self) -> String {
+        if self.window_handle == 0 {
+            return self.window_name.clone();
         }
+        get_window_text(self.window_handle)
+    }
 
-        // TEST MOUSE CODE
-        while !self.mouse.is_empty() {
-            if let Some(event) = self.mouse.read() {
-                if event.get_type() == crate::mouse::EventType::Move {
-                    println!(
-                        "Mouse Position: {}, {}",
-                        event.get_pos_x(),
-                        event.get_pos_y()
-                    );
-                }
-            }
+    pub fn set_title(&mut self, title: &str) -> Result<()> {
+        self.window_name = title.into();
+        if self.window_handle != 0 {
+            set_window_text(self.window_handle, title)?;
         }
+        Ok(())
+    }
+
+    pub fn gfx_mut(&mut self) -> Option<&mut GFX> {
+        self.gfx.as_mut()
+    }
 
+    pub fn release_gfx(&mut self) {
+        self.gfx = None;
+    }
+
+    pub fn recreate_gfx(&mut self, gfx_options: &GfxOptions) -> Result<()> {
+        // The old surface has to go before the new one is made for the same window.
+        self.gfx = None;
+        let gfx = pollster::block_on(GFX::new(&*self, self.width as u32, self.height as u32, gfx_options))
+            .context("cannot recreate graphics")?;
+        self.gfx = Some(gfx);
         Ok(())
     }
 
+    pub fn input<'a>(&'a self, rng: &'a Rng) -> Input<'a> {
+        Input {
+            keyboard: &self.kbd,
+            keys: self.kbd.state(),
+            previous_keys: Default::default(),
+            mouse: &self.mouse,
+            mouse_delta: Default::default(),
+            rng,
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    // The new client size, if the window was resized since the last call.
+    pub fn take_resize(&mut self) -> Option<(u32, u32)> {
+        self.resized.take()
+    }
+
+    // Switches between a window of the given client size and borderless fullscreen, by changing the
+    // style of the existing window. WM_SIZE reports the new size.
+    pub fn set_window_mode(&mut self, width: i32, height: i32, fullscreen: bool) -> Result<()> {
+        self.fullscreen = fullscreen;
+        unsafe {
+            let (style, x, y, w, h) = if fullscreen {
+                (WS_POPUP | WS_VISIBLE, 0, 0, GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN))
+            } else {
+                let mut wr = RECT {
+                    left: 100,
+                    top: 100,
+                    right: 100 + width,
+                    bottom: 100 + height,
+                };
+                AdjustWindowRect(&mut wr, WS_CAPTION | WS_MINIMIZEBOX | WS_SYSMENU, BOOL(0))
+                    .ok()
+                    .map_err(|e| win_error!(e))?;
+                (WS_OVERLAPPEDWINDOW | WS_VISIBLE, wr.left, wr.top, wr.right - wr.left, wr.bottom - wr.top)
+            };
+            SetWindowLongPtrW(self.window_handle, GWL_STYLE, style as isize);
+            SetWindowPos(self.window_handle, HWND_TOP, x, y, w, h, SWP_FRAMECHANGED | SWP_SHOWWINDOW)
+                .ok()
+                .map_err(|e| win_error!(e))?;
+        }
+        Ok(())
+    }
+
+    // Handles a fabricated message as if the message pump had delivered it, see synthetic.rs.
+    pub fn inject(&mut self, message: Message) -> LRESULT {
+        self.injected_time = Some(message.time);
+        let result = self.user_message_handler(message.id, message.wparam, message.lparam);
+        self.injected_time = None;
+        result
+    }
+
+    // Handles fabricated messages in order. While one is handled, the ones after it are what a
+    // look at the message queue finds, as for AltGr's Ctrl.
+    pub fn inject_all(&mut self, messages: impl IntoIterator<Item = Message>) {
+        self.injected.extend(messages);
+        while let Some(message) = self.injected.pop_front() {
+            self.inject(message);
+        }
+    }
+
+    // Whether a key message of the left Ctrl key is the one Windows sends ahead of AltGr: a right
+    // Alt message with the same time follows it in the queue, or in the injected messages.
+    fn is_altgr_control(&self, wparam: WPARAM, lparam: LPARAM) -> bool {
+        if wparam != VK_CONTROL as usize || lparam & EXTENDED_KEY != 0 {
+            return false;
+        }
+        let (time, next) = match self.injected_time {
+            Some(time) => (time, self.injected.front().copied()),
+            None => unsafe {
+                let mut next = MSG::default();
+                let found = PeekMessageW(&mut next, None, WM_KEYDOWN, WM_SYSKEYUP, PM_NOREMOVE).as_bool();
+                let next = found.then(|| Message::new(next.message, next.wParam, next.lParam).at(next.time));
+                (GetMessageTime() as u32, next)
+            },
+        };
+        next.is_some_and(|next| {
+            (WM_KEYDOWN..=WM_SYSKEYUP).contains(&next.id)
+                && next.wparam == VK_MENU as usize
+                && next.lparam & EXTENDED_KEY != 0
+                && next.time == time
+        })
+    }
+
+    pub fn inject_event(&mut self, event: Event) {
+        match Message::from_event(&event) {
+            Some(message) => {
+                self.inject(message);
+            }
+            // Raw motion, and events of the app rather than the window.
+            None => apply_event(&mut self.kbd, &mut self.mouse, &mut self.events, event),
+        }
+    }
+
     fn user_message_handler(&mut self, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
         unsafe {
             match message {
                 WM_ACTIVATE => {
                     self.visible = true;
+                    // The low word tells whether the window is activated or deactivated.
+                    self.focused = (wparam & 0xFFFF) as u32 != WA_INACTIVE;
                     0
                 }
 
+                // AltGr is not a key of its own: Windows sends it as a left Ctrl, then a right Alt.
+                // Ignoring the Ctrl keeps AltGr+E from looking like the Ctrl+E shortcut.
+                WM_KEYDOWN | WM_SYSKEYDOWN | WM_KEYUP | WM_SYSKEYUP if self.is_altgr_control(wparam, lparam) => 0,
+
                 WM_KEYDOWN | WM_SYSKEYDOWN => {
                     // filter for autorepeat key messages to decide whether to process a key press or not.
                     if lparam & 0x40000000 == 0 || self.kbd.auto_repeat_is_enabled() {
-                        self.kbd.on_key_pressed(
-                            wparam
-                                .try_into()
-                                .expect("failed to convert keycode to u8"),
-                        );
+                        let code = wparam.try_into().expect("failed to convert keycode to u8");
+                        self.kbd.on_key_pressed(code);
+                        self.events.push_back(Event::KeyPressed(code));
                     }
                     0
                 }
 
                 WM_KEYUP | WM_SYSKEYUP => {
-                    self.kbd
-                        .on_key_released(wparam.try_into().expect("failed to convert keycode"));
+                    let code = wparam.try_into().expect("failed to convert keycode");
+                    self.kbd.on_key_released(code);
+                    self.events.push_back(Event::KeyReleased(code));
                     0
                 }
 
                 WM_CHAR => {
-                    self.kbd
-                        .on_char(wparam.try_into().expect("failed to convert char"));
+                    let character: u16 = wparam.try_into().expect("failed to convert char");
+                    // Ctrl+letter types the control characters 1 to 26, Ctrl+Backspace 127. Those
+                    // are shortcuts, not text; Backspace, Tab, Enter and Escape alone still type.
+                    let control = character < 0x20 || character == 0x7F;
+                    if !(control && self.kbd.key_is_pressed(VK_CONTROL)) {
+                        self.kbd.on_char(character);
+                        self.events.push_back(Event::Char(character));
+                    }
+                    0
+                }
+
+                // The accent of a dead key, e.g. ^ on a French layout. TranslateMessage combines
+                // it with the next key into one WM_CHAR (ê), or sends both if they don't combine.
+                WM_DEADCHAR | WM_SYSDEADCHAR => {
+                    let character = wparam.try_into().expect("failed to convert char");
+                    self.kbd.on_dead_char(character);
                     0
                 }
 
                 WM_KILLFOCUS => {
                     self.kbd.clear_state();
+                    self.events.push_back(Event::FocusLost);
                     0
                 }
 
@@ -189,9 +445,13 @@ impl Window {
                     // Mouse inside client area
                     if x >= 0 && y >= 0 && x < self.width as isize && y < self.height as isize {
                         self.mouse.on_mouse_move(x, y);
+                        self.events.push_back(Event::MouseMoved { x: x as i32, y: y as i32 });
                         if !self.mouse.is_in_window() {
                             // Still receive mouse move events when we leave the window client area
-                            SetCapture(self.window_handle);
+                            // (injected messages may have no window to capture them).
+                            if self.window_handle != 0 {
+                                SetCapture(self.window_handle);
+                            }
                             self.mouse.on_mouse_enter();
                         }
                     }
@@ -200,10 +460,13 @@ impl Window {
                         // track mouse when left or right button is pressed (dragging)
                         if self.mouse.left_is_pressed() || self.mouse.right_is_pressed() {
                             self.mouse.on_mouse_move(x, y);
+                            self.events.push_back(Event::MouseMoved { x: x as i32, y: y as i32 });
                         }
                         // Don't track mouse when leaving the client area
                         else {
-                            ReleaseCapture();
+                            if self.window_handle != 0 {
+                                ReleaseCapture();
+                            }
                             self.mouse.on_mouse_leave();
                         }
                     }
@@ -212,61 +475,82 @@ impl Window {
 
                 WM_LBUTTONDOWN => {
                     self.mouse.on_left_pressed();
+                    self.events.push_back(Event::MousePressed(MouseButton::Left));
                     0
                 }
 
                 WM_RBUTTONDOWN => {
                     self.mouse.on_right_pressed();
+                    self.events.push_back(Event::MousePressed(MouseButton::Right));
                     0
                 }
 
                 WM_LBUTTONUP => {
                     self.mouse.on_left_released();
+                    self.events.push_back(Event::MouseReleased(MouseButton::Left));
                     0
                 }
 
                 WM_RBUTTONUP => {
                     self.mouse.on_right_released();
+                    self.events.push_back(Event::MouseReleased(MouseButton::Right));
                     0
                 }
 
-                WM_MOUSEHWHEEL => {
-                    // First 16-bits of lparam contain mouse x-position
-                    let x = lparam & 0xFFFF;
-                    // Next 16-bits of lparam contain mouse y-position
-                    let y = (lparam >> 16) & 0xFFFF;
-                    self.mouse.on_wheel_delta(x, y, wparam);
+                WM_MOUSEWHEEL | WM_MOUSEHWHEEL => {
+                    // The high word of wparam is the signed distance, in fractions of 120 per notch.
+                    let lines = ((wparam >> 16) & 0xFFFF) as u16 as i16 as f32 / WHEEL_DELTA;
+                    let delta = match message {
+                        WM_MOUSEWHEEL => ScrollDelta::Lines { x: 0.0, y: lines },
+                        _ => ScrollDelta::Lines { x: lines, y: 0.0 },
+                    };
+                    apply_event(&mut self.kbd, &mut self.mouse, &mut self.events, Event::MouseScrolled(delta));
                     0
                 }
 
+                WM_INPUT => {
+                    let mut input = RAWINPUT::default();
+                    let mut size = std::mem::size_of::<RAWINPUT>() as u32;
+                    let read = GetRawInputData(
+                        lparam as HRAWINPUT,
+                        RID_INPUT,
+                        &mut input as *mut RAWINPUT as *mut c_void,
+                        &mut size,
+                        std::mem::size_of::<RAWINPUTHEADER>() as u32,
+                    );
+                    // Tablets and remote desktop report absolute positions, WM_MOUSEMOVE covers those.
+                    if read != u32::MAX && input.header.dwType == RIM_TYPEMOUSE {
+                        let mouse = input.data.mouse;
+                        if mouse.usFlags & MOUSE_MOVE_ABSOLUTE == 0 {
+                            let event = Event::MouseRawMoved {
+                                dx: mouse.lLastX,
+                                dy: mouse.lLastY,
+                            };
+                            apply_event(&mut self.kbd, &mut self.mouse, &mut self.events, event);
+                        }
+                    }
+                    // The system cleans up after WM_INPUT in the default procedure.
+                    DefWindowProcW(self.window_handle, message, wparam, lparam)
+                }
+
                 WM_SIZE => {
-                    println!("WM_SIZE");
+                    tracing::trace!("WM_SIZE");
+                    self.minimized = wparam as u32 == SIZE_MINIMIZED;
                     let mut rc: RECT = RECT::default();
                     GetClientRect(self.window_handle, &mut rc);
-                    let new_width = (rc.right - rc.left) as u32;
-                    let new_height = (rc.bottom - rc.top) as u32;
-                    // Update the GPU 
-                    if let Some(gfx) = self.gfx.as_mut() {
-                        gfx.resize(new_width, new_height);
+                    let new_width = rc.right - rc.left;
+                    let new_height = rc.bottom - rc.top;
+                    // Minimizing shrinks the client area to nothing, keep the last real size.
+                    if new_width > 0 && new_height > 0 {
+                        self.width = new_width;
+                        self.height = new_height;
+                        // The GPU and the game are updated by `App`, outside of the window procedure.
+                        self.resized = Some((new_width as u32, new_height as u32));
                     }
                     0
                 }
 
-                WM_PAINT => {
-                    println!("WM_PAINT");
-                    let gfx =  self.gfx.as_mut().unwrap();
-                    match gfx.render() {
-                        Ok(_) => {}
-                        // Reconfigure the surface if lost
-                        Err(wgpu::SurfaceError::Lost) => gfx.resize(self.width as u32, self.height as u32),
-                        // The system is out of memory, we should probably quit
-                        Err(wgpu::SurfaceError::OutOfMemory) => PostQuitMessage(0),
-                        // All other errors (Outdated, Timeout) should be resolved by the next frame
-                        Err(e) => eprintln!("{:?}", e),
-                    }
-                    0
-                }
-    
+
                 WM_DESTROY => {
                     PostQuitMessage(0);
                     0
@@ -276,6 +560,18 @@ impl Window {
         }
     }
 
+    // Dispatches all pending messages. Returns false once WM_QUIT arrives.
+    unsafe fn pump(message: &mut MSG) -> bool {
+        while PeekMessageW(message, None, 0, 0, PM_REMOVE).into() {
+            if message.message == WM_QUIT {
+                return false;
+            }
+            TranslateMessage(message);
+            DispatchMessageW(message);
+        }
+        true
+    }
+
     extern "system" fn wndproc(
         window_handle: HWND,
         message: u32,
@@ -290,7 +586,18 @@ impl Window {
                 SetWindowLongPtrW(window_handle, GWLP_USERDATA, this as isize);
             } else {
                 let this = GetWindowLongPtrW(window_handle, GWLP_USERDATA) as *mut Self;
+                if !this.is_null() && !(*this).accept_os_input && is_input_message(message) {
+                    return DefWindowProcW(window_handle, message, wparam, lparam);
+                }
                 if !this.is_null() {
+                    // The default procedure validates the window after WM_PAINT, the frame that
+                    // redraws it comes from the game loop.
+                    if message == WM_PAINT || is_input_message(message) {
+                        (*this).invalidated = true;
+                    }
+                    if is_input_message(message) && message != WM_KILLFOCUS {
+                        (*this).input_time.get_or_insert_with(message_time);
+                    }
                     return (*this).user_message_handler(message, wparam, lparam);
                 }
             }
@@ -300,6 +607,157 @@ impl Window {
     }
 }
 
+impl Platform for Window {
+    fn initialize(&mut self, gfx_options: &GfxOptions) -> Result<()> {
+        Window::initialize(self, gfx_options)
+    }
+
+    fn gfx_mut(&mut self) -> Option<&mut GFX> {
+        Window::gfx_mut(self)
+    }
+
+    fn release_gfx(&mut self) {
+        Window::release_gfx(self)
+    }
+
+    fn recreate_gfx(&mut self, gfx_options: &GfxOptions) -> Result<()> {
+        Window::recreate_gfx(self, gfx_options)
+    }
+
+    fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn set_window_mode(&mut self, width: i32, height: i32, fullscreen: bool) {
+        if let Err(e) = Window::set_window_mode(self, width, height, fullscreen) {
+            tracing::error!("Failed to change the window mode: {}", e);
+        }
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    fn is_visible(&self) -> bool {
+        self.visible && !self.minimized
+    }
+
+    fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    // DWM cloaks windows on another virtual desktop, and those of suspended store apps.
+    fn is_occluded(&self) -> bool {
+        let mut cloaked = 0u32;
+        let size = std::mem::size_of::<u32>() as u32;
+        unsafe {
+            DwmGetWindowAttribute(self.window_handle, DWMWA_CLOAKED, &mut cloaked as *mut u32 as *mut _, size).is_ok()
+                && cloaked != 0
+        }
+    }
+
+    fn input<'a>(&'a self, rng: &'a Rng) -> Input<'a> {
+        Window::input(self, rng)
+    }
+
+    fn take_mouse_delta(&mut self) -> MouseDelta {
+        self.mouse.take_frame_delta()
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        Window::next_event(self)
+    }
+
+    fn take_resize(&mut self) -> Option<(u32, u32)> {
+        Window::take_resize(self)
+    }
+
+    fn take_input_time(&mut self) -> Option<Instant> {
+        self.input_time.take()
+    }
+
+    fn take_invalidated(&mut self) -> bool {
+        std::mem::take(&mut self.invalidated) || self.resized.is_some() || !self.events.is_empty()
+    }
+
+    fn inject_event(&mut self, event: Event) {
+        Window::inject_event(self, event)
+    }
+
+    fn set_accept_os_input(&mut self, accept: bool) {
+        self.accept_os_input = accept;
+    }
+
+    fn pump_messages(&mut self) -> bool {
+        let mut message = MSG::default();
+        unsafe { Self::pump(&mut message) }
+    }
+
+    fn wait_messages(&mut self, deadline: Option<Instant>) -> bool {
+        let mut message = MSG::default();
+        unsafe {
+            let deadline = match deadline {
+                Some(deadline) => deadline,
+                None => {
+                    // Blocks until a message arrives.
+                    GetMessageW(&mut message, None, 0, 0);
+                    if message.message == WM_QUIT {
+                        return false;
+                    }
+                    TranslateMessage(&message);
+                    DispatchMessageW(&message);
+                    return true;
+                }
+            };
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    return true;
+                }
+                let timeout = (deadline - now).as_millis() as u32;
+                MsgWaitForMultipleObjects(0, std::ptr::null(), false, timeout, QS_ALLINPUT);
+                if !Self::pump(&mut message) {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+// When the message being handled was posted. Message times are milliseconds since boot, like
+// GetTickCount, and wrap around after 49 days.
+fn message_time() -> Instant {
+    let age = unsafe { GetTickCount().wrapping_sub(GetMessageTime() as u32) };
+    let now = Instant::now();
+    now.checked_sub(Duration::from_millis(age as u64)).unwrap_or(now)
+}
+
+// The messages that change the keyboard and mouse state, or that become `Event`s.
+fn is_input_message(message: u32) -> bool {
+    matches!(
+        message,
+        WM_KEYDOWN
+            | WM_KEYUP
+            | WM_SYSKEYDOWN
+            | WM_SYSKEYUP
+            | WM_CHAR
+            | WM_DEADCHAR
+            | WM_SYSDEADCHAR
+            | WM_KILLFOCUS
+            | WM_MOUSEMOVE
+            | WM_LBUTTONDOWN
+            | WM_LBUTTONUP
+            | WM_RBUTTONDOWN
+            | WM_RBUTTONUP
+            | WM_MOUSEWHEEL
+            | WM_MOUSEHWHEEL
+            | WM_INPUT
+    )
+}
+
+// RAWMOUSE::usFlags bit for absolute positions.
+const MOUSE_MOVE_ABSOLUTE: u16 = 0x01;
+
 unsafe impl raw_window_handle::HasRawWindowHandle for Window {
     fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
         let mut handle = raw_window_handle::Win32Handle::empty();
@@ -315,11 +773,166 @@ impl Drop for Window {
     fn drop(&mut self) {
         unsafe {
             if self.window_handle != 0 {
-                println!("Destroying window.");
+                tracing::debug!("Destroying window");
                 let _ = DestroyWindow(self.window_handle)
                     .ok()
-                    .map_err(|e| println!("{}", win_error!(e))); // TODO: error triggers on exit!?
+                    .map_err(|e| tracing::error!("{}", win_error!(e))); // TODO: error triggers on exit!?
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::ScrollDelta;
+    use crate::mouse::EventType;
+
+    const KEY_A: u16 = 0x41;
+
+    // Never shown: injected messages need no window on screen, see synthetic.rs.
+    fn window() -> Window {
+        Window::new(800, 600, "test")
+    }
+
+    #[test]
+    fn keys_go_down_and_up() {
+        let mut window = window();
+        window.inject(Message::key_down(KEY_A));
+        assert!(window.kbd.state().is_pressed(KEY_A));
+        assert!(window.kbd.read_key().is_some_and(|key| key.is_press() && key.get_code() == KEY_A));
+        assert_eq!(window.next_event(), Some(Event::KeyPressed(KEY_A)));
+
+        window.inject(Message::key_up(KEY_A));
+        assert!(window.kbd.state().is_empty());
+        assert!(window.kbd.read_key().is_some_and(|key| key.is_release() && key.get_code() == KEY_A));
+        assert_eq!(window.next_event(), Some(Event::KeyReleased(KEY_A)));
+        assert!(window.kbd.read_key().is_none() && window.next_event().is_none());
+    }
+
+    #[test]
+    fn auto_repeat_presses_again_only_when_enabled() {
+        let mut window = window();
+        window.inject(Message::key_down(KEY_A));
+        window.inject(Message::key_repeat(KEY_A));
+        window.inject(Message::key_repeat(KEY_A));
+        assert!(window.kbd.read_key().is_some());
+        assert!(window.kbd.read_key().is_none());
+        assert_eq!(window.events.len(), 1);
+
+        window.kbd.enable_auto_repeat();
+        window.inject(Message::key_repeat(KEY_A));
+        window.inject(Message::key_repeat(KEY_A));
+        assert!(window.kbd.read_key().is_some_and(|key| key.is_press()));
+        assert!(window.kbd.read_key().is_some_and(|key| key.is_press()));
+        assert!(window.kbd.read_key().is_none());
+        assert!(window.kbd.state().is_pressed(KEY_A));
+    }
+
+    #[test]
+    fn typed_characters_are_buffered() {
+        let mut window = window();
+        window.inject(Message::char('a' as u16));
+        window.inject(Message::char('é' as u16));
+        assert_eq!(window.kbd.read_char(), Some('a' as u16));
+        assert_eq!(window.kbd.read_char(), Some('é' as u16));
+        assert_eq!(window.kbd.read_char(), None);
+        assert_eq!(window.next_event(), Some(Event::Char('a' as u16)));
+        assert_eq!(window.next_event(), Some(Event::Char('é' as u16)));
+    }
+
+    #[test]
+    fn the_mouse_moves_clicks_and_scrolls() {
+        let mut window = window();
+        window.inject(Message::mouse_move(10, 20));
+        window.inject(Message::mouse_move(15, 18));
+        assert_eq!(window.mouse.get_pos(), (15, 18));
+        assert!(window.mouse.is_in_window());
+        assert_eq!(window.next_event(), Some(Event::MouseMoved { x: 10, y: 20 }));
+        assert_eq!(window.next_event(), Some(Event::MouseMoved { x: 15, y: 18 }));
+
+        window.inject(Message::button_down(MouseButton::Left));
+        assert!(window.mouse.left_is_pressed() && !window.mouse.right_is_pressed());
+        window.inject(Message::button_up(MouseButton::Left));
+        window.inject(Message::button_down(MouseButton::Right));
+        assert!(!window.mouse.left_is_pressed() && window.mouse.right_is_pressed());
+        assert_eq!(window.next_event(), Some(Event::MousePressed(MouseButton::Left)));
+        assert_eq!(window.next_event(), Some(Event::MouseReleased(MouseButton::Left)));
+        assert_eq!(window.next_event(), Some(Event::MousePressed(MouseButton::Right)));
+
+        window.inject(Message::wheel(-2 * WHEEL_DELTA as i16));
+        assert_eq!(window.next_event(), Some(Event::MouseScrolled(ScrollDelta::Lines { x: 0.0, y: -2.0 })));
+        let delta = window.mouse.take_frame_delta();
+        assert_eq!((delta.x, delta.y), (5, -2));
+        assert_eq!(delta.scroll_lines, (0.0, -2.0));
+
+        let types: Vec<_> = std::iter::from_fn(|| window.mouse.read()).map(|event| event.get_type()).collect();
+        assert!(types[types.len() - 2..] == [EventType::WheelDown, EventType::WheelDown]);
+    }
+
+    #[test]
+    fn losing_the_focus_releases_the_keys() {
+        let mut window = window();
+        window.inject(Message::key_down(KEY_A));
+        window.inject(Message::key_down(VK_MENU));
+        assert_eq!(window.kbd.state().count(), 2);
+
+        window.inject(Message::focus_lost());
+        assert!(window.kbd.state().is_empty());
+        assert_eq!(window.events.back(), Some(&Event::FocusLost));
+    }
+
+    #[test]
+    fn ctrl_letters_are_shortcuts_not_text() {
+        let mut window = window();
+        // Ctrl+A, with the control character TranslateMessage makes of it.
+        window.inject_all([
+            Message::key_down(VK_CONTROL).at(10),
+            Message::key_down(KEY_A).at(40),
+            Message::char(0x01).at(40),
+        ]);
+        assert!(window.kbd.state().is_pressed(VK_CONTROL));
+        assert_eq!(window.kbd.read_char(), None);
+        assert!(!window.events.iter().any(|event| matches!(event, Event::Char(_))));
+
+        // Backspace alone still types.
+        window.inject_all([Message::key_up(KEY_A), Message::key_up(VK_CONTROL), Message::char(0x08)]);
+        assert_eq!(window.kbd.read_char(), Some(0x08));
+    }
+
+    #[test]
+    fn altgr_types_without_pressing_ctrl() {
+        let mut window = window();
+        // AltGr+Q on a German layout.
+        window.inject_all([
+            Message::key_down(VK_CONTROL).at(100),
+            Message::key_down(VK_MENU).extended().at(100),
+            Message::key_down(0x51).at(130),
+            Message::char('@' as u16).at(130),
+        ]);
+        assert!(!window.kbd.state().is_pressed(VK_CONTROL));
+        assert!(window.kbd.state().is_pressed(VK_MENU));
+        assert_eq!(window.kbd.read_char(), Some('@' as u16));
+        let events: Vec<_> = window.events.drain(..).collect();
+        assert_eq!(events, [Event::KeyPressed(VK_MENU), Event::KeyPressed(0x51), Event::Char('@' as u16)]);
+
+        // Ctrl, then right Alt a little later, is Ctrl and Alt.
+        let mut ctrl_alt = self::window();
+        ctrl_alt.inject_all([Message::key_down(VK_CONTROL).at(100), Message::key_down(VK_MENU).extended().at(160)]);
+        assert!(ctrl_alt.kbd.state().is_pressed(VK_CONTROL) && ctrl_alt.kbd.state().is_pressed(VK_MENU));
+    }
+
+    #[test]
+    fn a_dead_key_waits_for_the_next_character() {
+        let mut window = window();
+        window.inject(Message::dead_char('^' as u16));
+        assert_eq!(window.kbd.dead_char(), Some('^' as u16));
+        assert_eq!(window.kbd.read_char(), None);
+        assert!(window.events.is_empty());
+
+        window.inject(Message::char('ê' as u16));
+        assert_eq!(window.kbd.dead_char(), None);
+        assert_eq!(window.kbd.read_char(), Some('ê' as u16));
+        assert_eq!(window.next_event(), Some(Event::Char('ê' as u16)));
+    }
+}
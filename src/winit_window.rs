@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use winit::dpi::PhysicalSize;
+use winit::event::{DeviceEvent, ElementState, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::platform::run_return::EventLoopExtRunReturn;
+use winit::window::Fullscreen;
+
+use crate::game::{Event, Input, MouseButton, ScrollDelta};
+use crate::gfx::{GfxOptions, GFX};
+use crate::keyboard::Keyboard;
+use crate::mouse::{Mouse, MouseDelta};
+use crate::error::{Context, Result};
+use crate::platform::{apply_event, Platform};
+use crate::rng::Rng;
+
+// winit windows
+//======================
+// The `Platform` on top of winit, enabled with the `winit` feature and selected with `--winit`.
+// winit's event loop wants to own the program; `run_return` hands control back once the pending
+// events are handled, which fits the game loop of `App`. Keys are translated to Win32 virtual-key
+// codes, so `Keyboard`, key bindings and recorded replays work the same as with `Window`.
+
+/// A window created through winit, see above.
+pub struct WinitWindow {
+    width: i32,
+    height: i32,
+    title: String,
+    fullscreen: bool,
+    // Created by `initialize`, on the thread that runs the game loop.
+    event_loop: Option<EventLoop<()>>,
+    window: Option<winit::window::Window>,
+    visible: bool,
+    minimized: bool,
+    focused: bool,
+    closed: bool,
+    accept_os_input: bool,
+    kbd: Keyboard,
+    mouse: Mouse,
+    gfx: Option<GFX>,
+    events: VecDeque<Event>,
+    resized: Option<(u32, u32)>,
+    invalidated: bool, // Since the last `take_invalidated`.
+    input_time: Option<Instant>, // Since the last `take_input_time`.
+}
+
+impl WinitWindow {
+    pub fn new(width: i32, height: i32, title: &str, fullscreen: bool) -> WinitWindow {
+        WinitWindow {
+            width,
+            height,
+            title: title.into(),
+            fullscreen,
+            event_loop: None,
+            window: None,
+            visible: false,
+            minimized: false,
+            focused: false,
+            closed: false,
+            accept_os_input: true,
+            kbd: Keyboard::new(),
+            mouse: Mouse::new(),
+            gfx: None,
+            events: VecDeque::new(),
+            resized: None,
+            invalidated: true,
+            input_time: None,
+        }
+    }
+
+    fn apply(&mut self, event: Event) {
+        if event.is_input() {
+            self.input_time.get_or_insert_with(Instant::now);
+        }
+        apply_event(&mut self.kbd, &mut self.mouse, &mut self.events, event);
+    }
+
+    fn handle(&mut self, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested | WindowEvent::Destroyed => self.closed = true,
+            WindowEvent::Resized(size) => {
+                // Minimizing shrinks the client area to nothing, keep the last real size.
+                self.minimized = size.width == 0 || size.height == 0;
+                if !self.minimized {
+                    self.width = size.width as i32;
+                    self.height = size.height as i32;
+                    self.resized = Some((size.width, size.height));
+                }
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = focused;
+                if !focused && self.accept_os_input {
+                    self.apply(Event::FocusLost);
+                }
+            }
+            _ if !self.accept_os_input => {}
+            WindowEvent::KeyboardInput { input, .. } => {
+                let code = match input.virtual_keycode.and_then(virtual_key_code) {
+                    Some(code) => code,
+                    None => return,
+                };
+                match input.state {
+                    ElementState::Pressed => {
+                        // winit repeats held keys, like WM_KEYDOWN.
+                        if !self.kbd.key_is_pressed(code) || self.kbd.auto_repeat_is_enabled() {
+                            self.apply(Event::KeyPressed(code));
+                        }
+                    }
+                    ElementState::Released => self.apply(Event::KeyReleased(code)),
+                }
+            }
+            WindowEvent::ReceivedCharacter(character) => {
+                let mut units = [0; 2];
+                for unit in character.encode_utf16(&mut units) {
+                    self.apply(Event::Char(*unit));
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.apply(Event::MouseMoved {
+                    x: position.x as i32,
+                    y: position.y as i32,
+                });
+            }
+            WindowEvent::CursorEntered { .. } => self.mouse.on_mouse_enter(),
+            WindowEvent::CursorLeft { .. } => self.mouse.on_mouse_leave(),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    winit::event::MouseButton::Left => MouseButton::Left,
+                    winit::event::MouseButton::Right => MouseButton::Right,
+                    _ => return,
+                };
+                match state {
+                    ElementState::Pressed => self.apply(Event::MousePressed(button)),
+                    ElementState::Released => self.apply(Event::MouseReleased(button)),
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => ScrollDelta::Lines { x, y },
+                    MouseScrollDelta::PixelDelta(position) => ScrollDelta::Pixels {
+                        x: position.x as f32,
+                        y: position.y as f32,
+                    },
+                };
+                self.apply(Event::MouseScrolled(delta));
+            }
+            _ => {}
+        }
+    }
+
+    // Runs the event loop until the pending events are handled and, with `Wait` or `WaitUntil`,
+    // until at least one event arrived or the deadline passed.
+    fn run_event_loop(&mut self, control_flow: ControlFlow) -> bool {
+        let mut event_loop = self.event_loop.take().expect("window is initialized");
+        let deadline = match control_flow {
+            ControlFlow::WaitUntil(deadline) => Some(deadline),
+            _ => None,
+        };
+        let mut received = matches!(control_flow, ControlFlow::Poll);
+        event_loop.run_return(|event, _, flow| {
+            *flow = control_flow;
+            match event {
+                winit::event::Event::WindowEvent { event, .. } => {
+                    received = true;
+                    self.handle(event);
+                }
+                // Device events arrive even without the focus.
+                winit::event::Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } if self.focused && self.accept_os_input => {
+                    self.apply(Event::MouseRawMoved {
+                        dx: delta.0 as i32,
+                        dy: delta.1 as i32,
+                    });
+                }
+                // The OS asks for a repaint, e.g. after the window was uncovered.
+                winit::event::Event::RedrawRequested(_) => self.invalidated = true,
+                winit::event::Event::RedrawEventsCleared => {
+                    let timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                    if received || timed_out || self.closed {
+                        *flow = ControlFlow::Exit;
+                    }
+                }
+                _ => {}
+            }
+        });
+        self.event_loop = Some(event_loop);
+        !self.closed
+    }
+}
+
+impl Platform for WinitWindow {
+    fn initialize(&mut self, gfx_options: &GfxOptions) -> Result<()> {
+        let event_loop = EventLoop::new();
+        let mut builder = winit::window::WindowBuilder::new().with_title(&self.title);
+        builder = if self.fullscreen {
+            builder.with_fullscreen(Some(Fullscreen::Borderless(None)))
+        } else {
+            builder.with_inner_size(PhysicalSize::new(self.width as u32, self.height as u32))
+        };
+        let window = builder
+            .build(&event_loop)
+            .map_err(|e| platform_error!(e))?;
+        let size = window.inner_size();
+        self.width = size.width as i32;
+        self.height = size.height as i32;
+        self.visible = true;
+        self.focused = true;
+        let gfx = pollster::block_on(GFX::new(&window, size.width, size.height, gfx_options))
+            .context("cannot initialize graphics")?;
+        self.gfx = Some(gfx);
+        self.window = Some(window);
+        self.event_loop = Some(event_loop);
+        Ok(())
+    }
+
+    fn gfx_mut(&mut self) -> Option<&mut GFX> {
+        self.gfx.as_mut()
+    }
+
+    fn release_gfx(&mut self) {
+        self.gfx = None;
+    }
+
+    fn recreate_gfx(&mut self, gfx_options: &GfxOptions) -> Result<()> {
+        let window = self.window.as_ref().expect("window not initialized");
+        let size = window.inner_size();
+        // The old surface has to go before the new one is made for the same window.
+        self.gfx = None;
+        let gfx = pollster::block_on(GFX::new(window, size.width, size.height, gfx_options))
+            .context("cannot recreate graphics")?;
+        self.gfx = Some(gfx);
+        Ok(())
+    }
+
+    fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn set_window_mode(&mut self, width: i32, height: i32, fullscreen: bool) {
+        self.fullscreen = fullscreen;
+        let window = self.window.as_ref().expect("window is initialized");
+        if fullscreen {
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+        } else {
+            window.set_fullscreen(None);
+            window.set_inner_size(PhysicalSize::new(width.max(1) as u32, height.max(1) as u32));
+        }
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    fn is_visible(&self) -> bool {
+        self.visible && !self.minimized
+    }
+
+    fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    // winit 0.26 doesn't tell when the window is covered.
+    fn is_occluded(&self) -> bool {
+        false
+    }
+
+    fn input<'a>(&'a self, rng: &'a Rng) -> Input<'a> {
+        Input {
+            keyboard: &self.kbd,
+            keys: self.kbd.state(),
+            previous_keys: Default::default(),
+            mouse: &self.mouse,
+            mouse_delta: Default::default(),
+            rng,
+        }
+    }
+
+    fn take_mouse_delta(&mut self) -> MouseDelta {
+        self.mouse.take_frame_delta()
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn take_resize(&mut self) -> Option<(u32, u32)> {
+        self.resized.take()
+    }
+
+    fn take_input_time(&mut self) -> Option<Instant> {
+        self.input_time.take()
+    }
+
+    fn take_invalidated(&mut self) -> bool {
+        std::mem::take(&mut self.invalidated) || self.resized.is_some() || !self.events.is_empty()
+    }
+
+    fn inject_event(&mut self, event: Event) {
+        self.apply(event);
+    }
+
+    fn set_accept_os_input(&mut self, accept: bool) {
+        self.accept_os_input = accept;
+    }
+
+    fn pump_messages(&mut self) -> bool {
+        self.run_event_loop(ControlFlow::Poll)
+    }
+
+    fn wait_messages(&mut self, deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(deadline) => self.run_event_loop(ControlFlow::WaitUntil(deadline)),
+            None => self.run_event_loop(ControlFlow::Wait),
+        }
+    }
+}
+
+unsafe impl raw_window_handle::HasRawWindowHandle for WinitWindow {
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
+        self.window.as_ref().expect("window is initialized").raw_window_handle()
+    }
+}
+
+// The Win32 virtual-key code of a key, for the keys `Keyboard` users are likely to bind.
+fn virtual_key_code(key: VirtualKeyCode) -> Option<u16> {
+    use VirtualKeyCode::*;
+    let letters = [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z];
+    let digits = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    let functions = [F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12];
+    if let Some(i) = letters.iter().position(|&k| k == key) {
+        return Some(b'A' as u16 + i as u16);
+    }
+    if let Some(i) = digits.iter().position(|&k| k == key) {
+        return Some(b'0' as u16 + i as u16);
+    }
+    if let Some(i) = functions.iter().position(|&k| k == key) {
+        return Some(0x70 + i as u16);
+    }
+    let code = match key {
+        Back => 0x08,
+        Tab => 0x09,
+        Return => 0x0D,
+        LShift | RShift => 0x10,
+        LControl | RControl => 0x11,
+        LAlt | RAlt => 0x12,
+        Escape => 0x1B,
+        Space => 0x20,
+        Left => 0x25,
+        Up => 0x26,
+        Right => 0x27,
+        Down => 0x28,
+        Delete => 0x2E,
+        _ => return None,
+    };
+    Some(code)
+}
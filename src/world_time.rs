@@ -0,0 +1,266 @@
+use cgmath::Vector3;
+
+use crate::gfx::GFX;
+use crate::light::DirectionalLight;
+use crate::sky::SkySettings;
+
+// World time
+//======================
+// The clock of the game world: the hour of the day, which runs through a whole day in
+// `day_length` seconds of game time, and moves the sun and the moon across the sky (sky.rs), which
+// light the scene (light.rs):
+//
+//     let mut world_time = WorldTime::new(DayCycle::default(), 8.0);  // 8 in the morning.
+//     let lanterns = world_time.at(19.5, "lanterns");
+//     ...
+//     for event in world_time.update(time.delta()) {
+//         if event.alarm == lanterns { ... }  // Every day at half past seven.
+//     }
+//     world_time.apply(gfx);
+//
+// The sun follows its path at `latitude` on a day with the sun at `declination` (0 at the
+// equinoxes, about 23.4 degrees at midsummer, -23.4 at midwinter): rising in the east (+x) around
+// 6, highest at 12, in the south (+z) north of the equator, and setting in the west around 18. The
+// moon is full, opposite the sun. Below the horizon the sky turns to night, and the moon lights the
+// scene instead, see sky.rs.
+//
+// Alarms fire once a day when the clock passes their hour, in the order it passes them, even when
+// an update passes several. Setting the hour jumps there without firing the alarms in between.
+// Scripts schedule alarms with `on_time_of_day`, see scripting.rs.
+
+/// The path of the sun and the sky it lights, see above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DayCycle {
+    pub day_length: f32, // In seconds of game time.
+    pub latitude: f32, // In degrees, positive north of the equator.
+    pub declination: f32, // Of the sun, in degrees, see above.
+    pub sky: SkySettings, // The sun and moon directions are set by the clock.
+}
+
+impl Default for DayCycle {
+    fn default() -> Self {
+        DayCycle {
+            day_length: 600.0,
+            latitude: 40.0,
+            declination: 0.0,
+            sky: SkySettings::default(),
+        }
+    }
+}
+
+impl DayCycle {
+    pub fn with_day_length(mut self, seconds: f32) -> DayCycle {
+        self.day_length = seconds;
+        self
+    }
+
+    pub fn with_latitude(mut self, degrees: f32) -> DayCycle {
+        self.latitude = degrees;
+        self
+    }
+}
+
+/// A scheduled time of day, returned by `WorldTime::at`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AlarmId(usize);
+
+struct Alarm {
+    hour: f32,
+    name: String,
+}
+
+/// An alarm the clock passed, see `WorldTime::update`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeOfDayEvent {
+    pub alarm: AlarmId,
+    pub name: String,
+    pub hour: f32,
+    pub day: u32, // The day the alarm fired on, counted from 0.
+}
+
+/// The clock of the game world, see above.
+pub struct WorldTime {
+    pub cycle: DayCycle,
+    pub paused: bool,
+    hours: f32, // From 0 to 24.
+    day: u32,
+    alarms: Vec<Option<Alarm>>, // By id; `None` where cancelled.
+}
+
+impl WorldTime {
+    pub fn new(cycle: DayCycle, hours: f32) -> WorldTime {
+        WorldTime {
+            cycle,
+            paused: false,
+            hours: hours.rem_euclid(24.0),
+            day: 0,
+            alarms: Vec::new(),
+        }
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.hours
+    }
+
+    pub fn day(&self) -> u32 {
+        self.day
+    }
+
+    // Jumps to `hours` of the same day, without firing the alarms in between.
+    pub fn set_hours(&mut self, hours: f32) {
+        self.hours = hours.rem_euclid(24.0);
+    }
+
+    // Hours and minutes, e.g. for a clock in the UI.
+    pub fn clock(&self) -> (u32, u32) {
+        let minutes = (self.hours * 60.0) as u32;
+        (minutes / 60 % 24, minutes % 60)
+    }
+
+    // Fires every day when the clock passes `hour`.
+    pub fn at(&mut self, hour: f32, name: &str) -> AlarmId {
+        let alarm = Alarm {
+            hour: hour.rem_euclid(24.0),
+            name: name.to_string(),
+        };
+        let index = match self.alarms.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.alarms.push(None);
+                self.alarms.len() - 1
+            }
+        };
+        self.alarms[index] = Some(alarm);
+        AlarmId(index)
+    }
+
+    pub fn cancel(&mut self, id: AlarmId) {
+        if let Some(alarm) = self.alarms.get_mut(id.0) {
+            *alarm = None;
+        }
+    }
+
+    // Advances the clock by `dt` seconds of game time, and returns the alarms it passed, in order.
+    // A single update passes a day at most.
+    pub fn update(&mut self, dt: f32) -> Vec<TimeOfDayEvent> {
+        if self.paused || self.cycle.day_length <= 0.0 || dt <= 0.0 {
+            return Vec::new();
+        }
+        let start = self.hours;
+        let end = start + (dt / self.cycle.day_length * 24.0).min(24.0);
+        // Alarms after the start, today or tomorrow, up to and including the end.
+        let mut passed: Vec<(f32, usize)> = Vec::new();
+        for (index, alarm) in self.alarms.iter().enumerate() {
+            if let Some(alarm) = alarm {
+                for hour in [alarm.hour, alarm.hour + 24.0] {
+                    if hour > start && hour <= end {
+                        passed.push((hour, index));
+                    }
+                }
+            }
+        }
+        passed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let events = passed
+            .into_iter()
+            .map(|(hour, index)| TimeOfDayEvent {
+                alarm: AlarmId(index),
+                name: self.alarms[index].as_ref().map(|alarm| alarm.name.clone()).unwrap_or_default(),
+                hour: hour % 24.0,
+                day: self.day + (hour >= 24.0) as u32,
+            })
+            .collect();
+        self.hours = end;
+        if self.hours >= 24.0 {
+            self.hours -= 24.0;
+            self.day += 1;
+        }
+        events
+    }
+
+    // Towards the sun, see above.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let hour_angle = (self.hours - 12.0) / 24.0 * std::f32::consts::TAU;
+        celestial_direction(hour_angle, self.cycle.declination.to_radians(), self.cycle.latitude.to_radians())
+    }
+
+    // Towards the full moon, opposite the sun.
+    pub fn moon_direction(&self) -> Vector3<f32> {
+        let hour_angle = (self.hours - 12.0) / 24.0 * std::f32::consts::TAU + std::f32::consts::PI;
+        celestial_direction(hour_angle, -self.cycle.declination.to_radians(), self.cycle.latitude.to_radians())
+    }
+
+    pub fn is_day(&self) -> bool {
+        self.sun_direction().y > 0.0
+    }
+
+    // The sky at this time of day.
+    pub fn sky(&self) -> SkySettings {
+        self.cycle.sky.with_sun(self.sun_direction()).with_moon(self.moon_direction())
+    }
+
+    // The light of the sky at this time of day: the sun's, or the moon's at night.
+    pub fn light(&self) -> DirectionalLight {
+        self.sky().light()
+    }
+
+    // Sets the GFX's sky, and with it the light, to this time of day.
+    pub fn apply(&self, gfx: &mut GFX) {
+        gfx.set_sky(Some(self.sky()));
+    }
+}
+
+// The direction towards a body at `hour_angle` west of the meridian and `declination` north of the
+// celestial equator, seen from `latitude`; east is +x, up is +y and north is -z.
+fn celestial_direction(hour_angle: f32, declination: f32, latitude: f32) -> Vector3<f32> {
+    let up = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let east = -declination.cos() * hour_angle.sin();
+    let north = latitude.cos() * declination.sin() - latitude.sin() * declination.cos() * hour_angle.cos();
+    Vector3::new(east, up, -north)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    // Above the horizon, in degrees.
+    fn elevation(direction: Vector3<f32>) -> f32 {
+        direction.normalize().y.asin().to_degrees()
+    }
+
+    #[test]
+    fn the_noon_sun_stands_at_the_latitude_s_height() {
+        for (latitude, declination) in [(40.0, 0.0), (51.5, 23.4), (-33.9, -23.4), (60.0, -23.4)] {
+            let cycle = DayCycle {
+                declination,
+                ..DayCycle::default().with_latitude(latitude)
+            };
+            let noon = WorldTime::new(cycle, 12.0);
+            let sun = noon.sun_direction();
+            assert!((elevation(sun) - (90.0 - (latitude - declination).abs())).abs() < 0.01, "{}", latitude);
+            // Due south north of the sun's path, due north south of it.
+            assert!(sun.x.abs() < 1e-4);
+            assert_eq!(sun.z > 0.0, latitude > declination);
+            assert!((noon.light().direction - sun.normalize()).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn the_light_follows_the_clock() {
+        let mut world_time = WorldTime::new(DayCycle::default().with_latitude(45.0).with_day_length(24.0), 6.0);
+        let mut previous = world_time.light().direction;
+        for _ in 0..5 {
+            world_time.update(1.0);
+            let light = world_time.light();
+            assert!(light.direction.y > previous.y && light.direction.x < previous.x);
+            assert!((light.direction - world_time.sun_direction().normalize()).magnitude() < 1e-4);
+            previous = light.direction;
+        }
+
+        // At midnight the moon lights the scene, from where the sun was at noon.
+        world_time.set_hours(0.0);
+        assert!(!world_time.is_day());
+        let noon = WorldTime::new(world_time.cycle, 12.0);
+        assert!((world_time.light().direction - noon.sun_direction().normalize()).magnitude() < 1e-4);
+    }
+}
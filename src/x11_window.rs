@@ -0,0 +1,466 @@
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::os::raw::{c_char, c_int, c_long, c_uchar, c_ulong};
+use std::ptr;
+use std::time::Instant;
+
+use x11_dl::keysym;
+use x11_dl::xlib;
+
+use crate::game::{Event, Input, MouseButton, ScrollDelta};
+use crate::gfx::{GfxOptions, GFX};
+use crate::keyboard::Keyboard;
+use crate::mouse::{Mouse, MouseDelta};
+use crate::error::{Context, Result};
+use crate::platform::{apply_event, Platform};
+use crate::rng::Rng;
+
+// X11 windows
+//======================
+// The native `Platform` on Linux, on plain Xlib. libX11 is loaded when the window is created,
+// so the program starts (and can fall back to `--winit`) on machines without it.
+// Wayland desktops run it through XWayland; for a native Wayland surface use the winit backend.
+//
+// The message loop mirrors the Win32 one: `XPending`/`XNextEvent` take the place of
+// `PeekMessageW`/`DispatchMessageW`, and waiting polls the connection's file descriptor.
+// Keys are translated from X keysyms to Win32 virtual-key codes, see `virtual_key_code`.
+
+// Mouse buttons 4 to 7 are the scroll wheel, up, down, left and right, one notch per press.
+const WHEEL_UP: u32 = 4;
+const WHEEL_DOWN: u32 = 5;
+const WHEEL_LEFT: u32 = 6;
+const WHEEL_RIGHT: u32 = 7;
+
+/// A window created through Xlib, see above.
+pub struct X11Window {
+    width: i32,
+    height: i32,
+    title: String,
+    fullscreen: bool,
+    // Loaded by `initialize`, with the connection and the window.
+    xlib: Option<xlib::Xlib>,
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    wm_delete_window: xlib::Atom,
+    visible: bool,
+    minimized: bool,
+    focused: bool,
+    closed: bool,
+    accept_os_input: bool,
+    kbd: Keyboard,
+    mouse: Mouse,
+    gfx: Option<GFX>,
+    events: VecDeque<Event>,
+    resized: Option<(u32, u32)>,
+    invalidated: bool, // Since the last `take_invalidated`.
+    input_time: Option<Instant>, // Since the last `take_input_time`.
+    occluded: bool,    // Mapped, but entirely covered by other windows.
+}
+
+impl X11Window {
+    pub fn new(width: i32, height: i32, title: &str, fullscreen: bool) -> X11Window {
+        X11Window {
+            width,
+            height,
+            title: title.into(),
+            fullscreen,
+            xlib: None,
+            display: ptr::null_mut(),
+            window: 0,
+            wm_delete_window: 0,
+            visible: false,
+            minimized: false,
+            focused: false,
+            closed: false,
+            accept_os_input: true,
+            kbd: Keyboard::new(),
+            mouse: Mouse::new(),
+            gfx: None,
+            events: VecDeque::new(),
+            resized: None,
+            invalidated: true,
+            input_time: None,
+            occluded: false,
+        }
+    }
+
+    fn apply(&mut self, event: Event) {
+        if event.is_input() {
+            self.input_time.get_or_insert_with(Instant::now);
+        }
+        apply_event(&mut self.kbd, &mut self.mouse, &mut self.events, event);
+    }
+
+    unsafe fn atom(&self, xlib: &xlib::Xlib, name: &str) -> xlib::Atom {
+        let name = CString::new(name).unwrap();
+        (xlib.XInternAtom)(self.display, name.as_ptr(), xlib::False)
+    }
+
+    // Asks the window manager for a borderless window covering the screen.
+    unsafe fn request_fullscreen(&self, xlib: &xlib::Xlib) {
+        let wm_state = self.atom(xlib, "_NET_WM_STATE");
+        let fullscreen = self.atom(xlib, "_NET_WM_STATE_FULLSCREEN");
+        (xlib.XChangeProperty)(
+            self.display,
+            self.window,
+            wm_state,
+            xlib::XA_ATOM,
+            32,
+            xlib::PropModeReplace,
+            &fullscreen as *const xlib::Atom as *const c_uchar,
+            1,
+        );
+    }
+
+    // Asks the window manager to enter or leave fullscreen, once the window is mapped.
+    unsafe fn send_fullscreen(&self, xlib: &xlib::Xlib, fullscreen: bool) {
+        const NET_WM_STATE_REMOVE: c_long = 0;
+        const NET_WM_STATE_ADD: c_long = 1;
+        let mut message = xlib::XClientMessageEvent {
+            type_: xlib::ClientMessage,
+            serial: 0,
+            send_event: xlib::True,
+            display: self.display,
+            window: self.window,
+            message_type: self.atom(xlib, "_NET_WM_STATE"),
+            format: 32,
+            data: xlib::ClientMessageData::new(),
+        };
+        let action = if fullscreen { NET_WM_STATE_ADD } else { NET_WM_STATE_REMOVE };
+        message.data.set_long(0, action);
+        message.data.set_long(1, self.atom(xlib, "_NET_WM_STATE_FULLSCREEN") as c_long);
+        let mut event = xlib::XEvent { client_message: message };
+        (xlib.XSendEvent)(
+            self.display,
+            (xlib.XDefaultRootWindow)(self.display),
+            xlib::False,
+            xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
+            &mut event,
+        );
+    }
+
+    // Handles all queued X events, like the window procedure does for Win32 messages.
+    fn handle_pending(&mut self) {
+        let xlib = self.xlib.take().expect("window is initialized");
+        unsafe {
+            while (xlib.XPending)(self.display) > 0 {
+                let mut event = MaybeUninit::<xlib::XEvent>::uninit();
+                (xlib.XNextEvent)(self.display, event.as_mut_ptr());
+                self.handle(&xlib, &mut event.assume_init());
+            }
+        }
+        self.xlib = Some(xlib);
+    }
+
+    unsafe fn handle(&mut self, xlib: &xlib::Xlib, event: &mut xlib::XEvent) {
+        match event.get_type() {
+            xlib::ClientMessage if event.client_message.data.get_long(0) as xlib::Atom == self.wm_delete_window => {
+                self.closed = true;
+            }
+            xlib::DestroyNotify => self.closed = true,
+            xlib::MapNotify => {
+                self.visible = true;
+                self.minimized = false;
+                self.invalidated = true;
+            }
+            xlib::Expose => self.invalidated = true,
+            xlib::VisibilityNotify => self.occluded = event.visibility.state == xlib::VisibilityFullyObscured,
+            xlib::UnmapNotify => self.minimized = true,
+            xlib::ConfigureNotify => {
+                let (width, height) = (event.configure.width, event.configure.height);
+                // Moving the window sends the same event, only a new size is a resize.
+                if width > 0 && height > 0 && (width, height) != (self.width, self.height) {
+                    self.width = width;
+                    self.height = height;
+                    self.resized = Some((width as u32, height as u32));
+                }
+            }
+            xlib::FocusIn => self.focused = true,
+            xlib::FocusOut => {
+                self.focused = false;
+                if self.accept_os_input {
+                    self.apply(Event::FocusLost);
+                }
+            }
+            _ if !self.accept_os_input => {}
+            xlib::KeyPress => {
+                if let Some(code) = virtual_key_code((xlib.XLookupKeysym)(&mut event.key, 0)) {
+                    // With detectable auto-repeat, held keys repeat as presses only, like WM_KEYDOWN.
+                    if !self.kbd.key_is_pressed(code) || self.kbd.auto_repeat_is_enabled() {
+                        self.apply(Event::KeyPressed(code));
+                    }
+                }
+                // Typed text comes as Latin-1, enough for the keyboards the demo expects.
+                let mut text = [0 as c_char; 16];
+                let count = (xlib.XLookupString)(
+                    &mut event.key,
+                    text.as_mut_ptr(),
+                    text.len() as c_int,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                );
+                for &byte in &text[..count.max(0) as usize] {
+                    self.apply(Event::Char(byte as u8 as u16));
+                }
+            }
+            xlib::KeyRelease => {
+                if let Some(code) = virtual_key_code((xlib.XLookupKeysym)(&mut event.key, 0)) {
+                    self.apply(Event::KeyReleased(code));
+                }
+            }
+            xlib::MotionNotify => self.apply(Event::MouseMoved {
+                x: event.motion.x,
+                y: event.motion.y,
+            }),
+            xlib::EnterNotify => self.mouse.on_mouse_enter(),
+            xlib::LeaveNotify => self.mouse.on_mouse_leave(),
+            xlib::ButtonPress => match event.button.button {
+                xlib::Button1 => self.apply(Event::MousePressed(MouseButton::Left)),
+                xlib::Button3 => self.apply(Event::MousePressed(MouseButton::Right)),
+                WHEEL_UP => self.apply(Event::MouseScrolled(ScrollDelta::Lines { x: 0.0, y: 1.0 })),
+                WHEEL_DOWN => self.apply(Event::MouseScrolled(ScrollDelta::Lines { x: 0.0, y: -1.0 })),
+                WHEEL_LEFT => self.apply(Event::MouseScrolled(ScrollDelta::Lines { x: -1.0, y: 0.0 })),
+                WHEEL_RIGHT => self.apply(Event::MouseScrolled(ScrollDelta::Lines { x: 1.0, y: 0.0 })),
+                _ => {}
+            },
+            xlib::ButtonRelease => match event.button.button {
+                xlib::Button1 => self.apply(Event::MouseReleased(MouseButton::Left)),
+                xlib::Button3 => self.apply(Event::MouseReleased(MouseButton::Right)),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // Blocks until the X connection has data to read, or until `deadline`.
+    fn wait_for_connection(&self, deadline: Option<Instant>) {
+        let xlib = self.xlib.as_ref().expect("window is initialized");
+        let mut fd = libc::pollfd {
+            fd: unsafe { (xlib.XConnectionNumber)(self.display) },
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()).as_millis() as c_int,
+            None => -1,
+        };
+        unsafe {
+            libc::poll(&mut fd, 1, timeout);
+        }
+    }
+}
+
+impl Platform for X11Window {
+    fn initialize(&mut self, gfx_options: &GfxOptions) -> Result<()> {
+        let xlib = xlib::Xlib::open().map_err(|e| platform_error!(e))?;
+        unsafe {
+            self.display = (xlib.XOpenDisplay)(ptr::null());
+            if self.display.is_null() {
+                return Err(platform_error!("cannot open the X display").into());
+            }
+            let screen = (xlib.XDefaultScreen)(self.display);
+            let root = (xlib.XRootWindow)(self.display, screen);
+            if self.fullscreen {
+                self.width = (xlib.XDisplayWidth)(self.display, screen);
+                self.height = (xlib.XDisplayHeight)(self.display, screen);
+            }
+            self.window = (xlib.XCreateSimpleWindow)(
+                self.display,
+                root,
+                0,
+                0,
+                self.width as u32,
+                self.height as u32,
+                0,
+                0,
+                (xlib.XBlackPixel)(self.display, screen),
+            );
+            let title = CString::new(self.title.as_str()).unwrap_or_default();
+            (xlib.XStoreName)(self.display, self.window, title.as_ptr());
+            let mask: c_long = xlib::KeyPressMask
+                | xlib::KeyReleaseMask
+                | xlib::ButtonPressMask
+                | xlib::ButtonReleaseMask
+                | xlib::PointerMotionMask
+                | xlib::EnterWindowMask
+                | xlib::LeaveWindowMask
+                | xlib::FocusChangeMask
+                | xlib::ExposureMask
+                | xlib::VisibilityChangeMask
+                | xlib::StructureNotifyMask;
+            (xlib.XSelectInput)(self.display, self.window, mask);
+
+            // The close button sends a message instead of destroying the window.
+            self.wm_delete_window = self.atom(&xlib, "WM_DELETE_WINDOW");
+            (xlib.XSetWMProtocols)(self.display, self.window, &mut self.wm_delete_window, 1);
+            // Without this, a held key sends a release before every repeated press.
+            (xlib.XkbSetDetectableAutoRepeat)(self.display, xlib::True, ptr::null_mut());
+
+            if self.fullscreen {
+                self.request_fullscreen(&xlib);
+            }
+            (xlib.XMapWindow)(self.display, self.window);
+            (xlib.XFlush)(self.display);
+        }
+        self.focused = true;
+        self.xlib = Some(xlib);
+        let gfx = pollster::block_on(GFX::new(&*self, self.width as u32, self.height as u32, gfx_options))
+            .context("cannot initialize graphics")?;
+        self.gfx = Some(gfx);
+        Ok(())
+    }
+
+    fn gfx_mut(&mut self) -> Option<&mut GFX> {
+        self.gfx.as_mut()
+    }
+
+    fn release_gfx(&mut self) {
+        self.gfx = None;
+    }
+
+    fn recreate_gfx(&mut self, gfx_options: &GfxOptions) -> Result<()> {
+        // The old surface has to go before the new one is made for the same window.
+        self.gfx = None;
+        let gfx = pollster::block_on(GFX::new(&*self, self.width as u32, self.height as u32, gfx_options))
+            .context("cannot recreate graphics")?;
+        self.gfx = Some(gfx);
+        Ok(())
+    }
+
+    fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn set_window_mode(&mut self, width: i32, height: i32, fullscreen: bool) {
+        let xlib = self.xlib.take().expect("window is initialized");
+        unsafe {
+            if fullscreen != self.fullscreen {
+                self.send_fullscreen(&xlib, fullscreen);
+            }
+            if !fullscreen {
+                (xlib.XResizeWindow)(self.display, self.window, width.max(1) as u32, height.max(1) as u32);
+            }
+            (xlib.XFlush)(self.display);
+        }
+        self.fullscreen = fullscreen;
+        self.xlib = Some(xlib);
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    fn is_visible(&self) -> bool {
+        self.visible && !self.minimized
+    }
+
+    fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    fn is_occluded(&self) -> bool {
+        self.occluded
+    }
+
+    fn input<'a>(&'a self, rng: &'a Rng) -> Input<'a> {
+        Input {
+            keyboard: &self.kbd,
+            keys: self.kbd.state(),
+            previous_keys: Default::default(),
+            mouse: &self.mouse,
+            mouse_delta: Default::default(),
+            rng,
+        }
+    }
+
+    fn take_mouse_delta(&mut self) -> MouseDelta {
+        self.mouse.take_frame_delta()
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn take_resize(&mut self) -> Option<(u32, u32)> {
+        self.resized.take()
+    }
+
+    fn take_input_time(&mut self) -> Option<Instant> {
+        self.input_time.take()
+    }
+
+    fn take_invalidated(&mut self) -> bool {
+        std::mem::take(&mut self.invalidated) || self.resized.is_some() || !self.events.is_empty()
+    }
+
+    fn inject_event(&mut self, event: Event) {
+        self.apply(event);
+    }
+
+    fn set_accept_os_input(&mut self, accept: bool) {
+        self.accept_os_input = accept;
+    }
+
+    fn pump_messages(&mut self) -> bool {
+        self.handle_pending();
+        !self.closed
+    }
+
+    fn wait_messages(&mut self, deadline: Option<Instant>) -> bool {
+        let pending = unsafe { (self.xlib.as_ref().expect("window is initialized").XPending)(self.display) };
+        if pending == 0 {
+            self.wait_for_connection(deadline);
+        }
+        self.handle_pending();
+        !self.closed
+    }
+}
+
+impl Drop for X11Window {
+    fn drop(&mut self) {
+        // The surface must go before the window it presents to.
+        self.gfx = None;
+        if let Some(xlib) = &self.xlib {
+            unsafe {
+                (xlib.XDestroyWindow)(self.display, self.window);
+                (xlib.XCloseDisplay)(self.display);
+            }
+        }
+    }
+}
+
+unsafe impl raw_window_handle::HasRawWindowHandle for X11Window {
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
+        let mut handle = raw_window_handle::XlibHandle::empty();
+        handle.window = self.window;
+        handle.display = self.display as *mut _;
+        raw_window_handle::RawWindowHandle::Xlib(handle)
+    }
+}
+
+// The Win32 virtual-key code of a keysym, for the keys `Keyboard` users are likely to bind.
+// Letters and digits share their codes with ASCII upper case, the keysym is lower case.
+fn virtual_key_code(keysym: c_ulong) -> Option<u16> {
+    let keysym = keysym as u32;
+    let code = match keysym {
+        keysym::XK_a..=keysym::XK_z => keysym - keysym::XK_a + 0x41,
+        keysym::XK_0..=keysym::XK_9 => keysym - keysym::XK_0 + 0x30,
+        keysym::XK_F1..=keysym::XK_F12 => keysym - keysym::XK_F1 + 0x70,
+        keysym::XK_BackSpace => 0x08,
+        keysym::XK_Tab => 0x09,
+        keysym::XK_Return => 0x0D,
+        keysym::XK_Shift_L | keysym::XK_Shift_R => 0x10,
+        keysym::XK_Control_L | keysym::XK_Control_R => 0x11,
+        keysym::XK_Alt_L | keysym::XK_Alt_R => 0x12,
+        keysym::XK_Escape => 0x1B,
+        keysym::XK_space => 0x20,
+        keysym::XK_Left => 0x25,
+        keysym::XK_Up => 0x26,
+        keysym::XK_Right => 0x27,
+        keysym::XK_Down => 0x28,
+        keysym::XK_Delete => 0x2E,
+        _ => return None,
+    };
+    Some(code as u16)
+}